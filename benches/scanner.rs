@@ -0,0 +1,61 @@
+//! Whole-value PII classification with the single `RegexSet` pass.
+//!
+//! ```bash
+//! cargo bench --bench scanner
+//! ```
+
+#![allow(dead_code, unused_imports)]
+
+// The scanner and the config it is built from, without the rest of the proxy
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/metrics.rs"]
+mod metrics;
+#[path = "../src/protocol/mod.rs"]
+mod protocol;
+#[path = "../src/scanner.rs"]
+mod scanner;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use scanner::PiiScanner;
+use std::hint::black_box;
+
+/// Values of a text-heavy result set, mostly not PII
+const CORPUS: &[&str] = &[
+    "user@example.com",
+    "4111 1111 1111 1111",
+    "123-45-6789",
+    "+1-555-123-4567",
+    "192.168.1.1",
+    "1990-01-15",
+    "AB1234567",
+    "GB63 2938 4710 5582 9301 64",
+    "AB 12 34 56 C",
+    "86095742719",
+    "Jane Doe",
+    "42 Wallaby Way, Sydney",
+    "pending",
+    "Lorem ipsum dolor sit amet, consectetur adipiscing elit",
+    "2024-03-01T12:00:00Z",
+    "ORD-2024-000123",
+    "true",
+    "1234.50",
+    "The quick brown fox jumps over the lazy dog",
+    "",
+];
+
+fn classify(c: &mut Criterion) {
+    let scanner = PiiScanner::new();
+    let mut group = c.benchmark_group("classify");
+    group.bench_function("single_pass", |b| {
+        b.iter(|| {
+            for value in CORPUS {
+                black_box(scanner.scan_ranked(black_box(value)));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, classify);
+criterion_main!(benches);
@@ -436,11 +436,48 @@ run_mysql_tests() {
     echo "$result"
     assert_not_contains "$result" "buyer@shop.com" "MySQL Heuristic: Email detected and masked"
     assert_not_contains "$result" "4111-1111-1111-1111" "MySQL Heuristic: Credit card detected and masked"
-    
+
     # Stop proxy
     kill $PROXY_PID 2>/dev/null || true
     PROXY_PID=""
     sleep 1
+
+    # Test 3: TLS between client and proxy
+    log_section "Starting IronVeil proxy (MySQL mode, TLS enabled)..."
+    local tls_config
+    tls_config=$(mktemp --suffix=.yaml)
+    sed -e '/^tls:/,/^upstream_tls:/ s/enabled: false/enabled: true/' \
+        -e "s|certs/server.crt|$(pwd)/tests/fixtures/tls/server.crt|" \
+        -e "s|certs/server.key|$(pwd)/tests/fixtures/tls/server.key|" \
+        proxy.yaml > "$tls_config"
+    ./target/release/iron-veil --port $PROXY_PORT --upstream-host localhost --upstream-port $MYSQL_PORT --api-port $API_PORT --protocol mysql --config "$tls_config" &
+    PROXY_PID=$!
+
+    if ! wait_for_port $PROXY_PORT "IronVeil Proxy (TLS)"; then
+        log_error "Failed to start proxy with TLS"
+        rm -f "$tls_config"
+        return 1
+    fi
+    sleep 2
+
+    log_section "Test: MySQL over TLS (--ssl-mode=REQUIRED)"
+    result=$(docker run --rm mysql:8 \
+        mysql -h $DOCKER_HOST_ADDR -P $PROXY_PORT -uroot -ppassword --ssl-mode=REQUIRED testdb \
+        -e "SHOW STATUS LIKE 'Ssl_cipher'; SELECT email FROM users;" 2>&1)
+
+    echo "$result"
+    if echo "$result" | grep -q "mysql.user@test.com"; then
+        log_error "MySQL TLS: Email was not masked"
+    elif echo "$result" | grep -q "email"; then
+        log_success "MySQL TLS: Client connected with --ssl-mode=REQUIRED and results were masked"
+    else
+        log_error "MySQL TLS: Query over TLS failed"
+    fi
+
+    kill $PROXY_PID 2>/dev/null || true
+    PROXY_PID=""
+    rm -f "$tls_config"
+    sleep 1
 }
 
 #######################################
@@ -1,23 +1,59 @@
-use crate::audit::{AuditEventType, AuditLogger, AuditOutcome, AuthMethod};
-use crate::config::MaskingRule;
-use crate::db_scanner::{DbScanner, ScanConfig};
-use crate::state::AppState;
+use crate::audit::{
+    AuditEntry, AuditEventType, AuditExportFormat, AuditFilter, AuditLogger, AuditOutcome,
+    AuthMethod, ReloadTrigger,
+};
+use crate::config::{
+    API_SCOPES, ApiConfig, ApiKeyConfig, AppConfig, CorsConfig, LimitsConfig, LockoutConfig,
+    MaskingMode, MaskingRule, NamePattern, RuleId, hash_api_key,
+};
+use crate::db_scanner::{DbScanner, ScanConfig, ScanError};
+use crate::interceptor::preview_masking;
+use crate::scan_jobs::ScanJobStatus;
+use crate::state::{AppState, LogEntry, MaskingStats, QueryStats};
+use crate::stats_window::MAX_WINDOW_MINUTES;
+use anyhow::Context;
+use axum::serve::ListenerExt;
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     body::Body,
-    extract::State,
-    http::{Request, StatusCode},
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{HeaderMap, Method, Request, StatusCode, header, request::Parts},
     middleware::{self, Next},
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use ipnet::IpNet;
 use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use tower_http::cors::CorsLayer;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
+use utoipa::openapi::response::ResponseBuilder;
+use utoipa::openapi::schema::{ObjectBuilder, Type};
+use utoipa::openapi::security::{
+    ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme,
+};
+use utoipa::openapi::{ContentBuilder, HeaderBuilder, Ref};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 /// JWT Claims structure
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,6 +65,119 @@ struct Claims {
     /// Issued at (Unix timestamp)
     #[serde(default)]
     iat: usize,
+    /// Space-separated scopes granted to the subject
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    scope: String,
+    /// Scopes as a list, as some identity providers issue them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    roles: Vec<String>,
+}
+
+impl Claims {
+    /// Scopes of the `scope` and `roles` claims; a token with neither gets
+    /// `api.default_scopes`
+    fn scopes(&self, default_scopes: &[String]) -> Vec<String> {
+        let scopes: Vec<String> = self
+            .scope
+            .split_whitespace()
+            .map(String::from)
+            .chain(self.roles.iter().cloned())
+            .collect();
+        if scopes.is_empty() {
+            default_scopes.to_vec()
+        } else {
+            scopes
+        }
+    }
+}
+
+/// Scope a request needs, by the endpoint group its path falls in and
+/// whether it only reads
+fn required_scope(method: &Method, path: &str) -> Option<&'static str> {
+    let reads = method == Method::GET;
+    let group = path.trim_start_matches('/').split('/').next()?;
+    Some(match (group, reads) {
+        ("rules", true) => "rules:read",
+        ("rules", false) => "rules:write",
+        ("config", true) => "config:read",
+        ("config", false) => "config:write",
+        ("scan" | "schema", true) => "scan:read",
+        ("scan" | "schema", false) => "scan:run",
+        ("audit", _) => "audit:read",
+        ("stats" | "connections" | "logs", true) => "stats:read",
+        // Resetting counters, killing connections
+        ("stats" | "connections", false) => "config:write",
+        ("detokenize", _) => DETOKENIZE_SCOPE,
+        ("apikeys", _) => "apikeys:write",
+        _ => return None,
+    })
+}
+
+/// Pass an authenticated request on if its scopes cover the endpoint, or
+/// refuse it with 403 and audit the missing scope
+async fn authorize(
+    state: &AppState,
+    mut request: Request<Body>,
+    next: Next,
+    scopes: &[String],
+    auth_method: AuthMethod,
+    user_id: Option<String>,
+) -> Response {
+    let method = request.method().clone();
+    let endpoint = request.uri().path().to_string();
+    let source = request
+        .extensions()
+        .get::<ClientIp>()
+        .copied()
+        .unwrap_or_default();
+    let Some(scope) = required_scope(&method, &endpoint) else {
+        return next.run(request).await;
+    };
+    if scopes.iter().any(|s| s == scope) {
+        if let Some(user_id) = user_id {
+            request.extensions_mut().insert(AuthUser(user_id));
+        }
+        return next.run(request).await;
+    }
+    state
+        .audit_logger
+        .log(
+            source.tag(
+                AuditLogger::scope_denied(auth_method, user_id, scope)
+                    .with_endpoint(&endpoint)
+                    .with_method(method.as_str()),
+            ),
+        )
+        .await;
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "error": format!("Missing scope '{}'", scope),
+            "required_scope": scope
+        })),
+    )
+        .into_response()
+}
+
+/// Scope a JWT needs to resolve tokens with `POST /detokenize`
+const DETOKENIZE_SCOPE: &str = "detokenize";
+
+/// The JWT subject or API key name of the caller, available to handlers as a
+/// request extension
+#[derive(Debug, Clone)]
+struct AuthUser(String);
+
+/// Caller authenticated by JWT, available to handlers as a request extension
+#[derive(Debug, Clone)]
+struct JwtIdentity {
+    subject: String,
+    scopes: Vec<String>,
+}
+
+impl JwtIdentity {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
 }
 
 /// Validates a JWT token and returns the claims if valid
@@ -41,97 +190,236 @@ fn validate_jwt(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::error
     Ok(token_data.claims)
 }
 
+/// Live log stream, the one endpoint that also takes credentials as a
+/// `token` query parameter, since browsers' `EventSource` can't set headers
+const LOG_STREAM_PATH: &str = "/logs/stream";
+
+/// Pages opened by browsers, which take the API key or JWT as `?token=`
+const QUERY_TOKEN_PATHS: &[&str] = &[LOG_STREAM_PATH, "/docs", "/openapi.json"];
+
+/// The `token` query parameter of a request to one of `QUERY_TOKEN_PATHS`
+fn query_token(request: &Request<Body>) -> Option<String> {
+    if !QUERY_TOKEN_PATHS.contains(&request.uri().path()) {
+        return None;
+    }
+    let axum::extract::Query(mut params) =
+        axum::extract::Query::<HashMap<String, String>>::try_from_uri(request.uri()).ok()?;
+    params.remove("token")
+}
+
+/// Address of the API client, set by `api_auth` for handlers to audit
+#[derive(Debug, Clone, Copy, Default)]
+struct ClientIp(Option<IpAddr>);
+
+impl ClientIp {
+    fn tag(self, entry: AuditEntry) -> AuditEntry {
+        match self.0 {
+            Some(ip) => entry.with_client_ip(ip.to_string()),
+            None => entry,
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(parts
+            .extensions
+            .get::<ClientIp>()
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+/// The client's address: the peer's, or when the peer is a trusted proxy,
+/// the nearest address in `X-Forwarded-For` that isn't also one
+fn client_address(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut client = peer;
+    if !trusted(&client) {
+        return client;
+    }
+    let hops: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in hops.into_iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !trusted(&client) {
+            break;
+        }
+    }
+    client
+}
+
 /// Middleware to validate API key or JWT for protected endpoints
-async fn api_auth(State(state): State<AppState>, request: Request<Body>, next: Next) -> Response {
-    let config = state.config.read().await;
+async fn api_auth(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
     let endpoint = request.uri().path().to_string();
     let method = request.method().to_string();
+    let query_token = query_token(&request);
+    let header_key = request
+        .headers()
+        .get("X-API-Key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
 
-    let api_config = config.api.as_ref();
-    let api_key = api_config.and_then(|c| c.api_key.as_ref());
-    let jwt_secret = api_config.and_then(|c| c.jwt_secret.as_ref());
+    let config = state.config.read().await;
+    let trusted_proxies = config
+        .api
+        .as_ref()
+        .map(|api| api.trusted_proxies.as_slice())
+        .unwrap_or_default();
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| client_address(peer.ip(), request.headers(), trusted_proxies));
+    let source = ClientIp(client_ip);
+    request.extensions_mut().insert(source);
 
-    // If neither API key nor JWT is configured, allow all requests
-    if api_key.is_none() && jwt_secret.is_none() {
+    // If neither API keys nor JWT are configured, allow all requests
+    let Some(api_config) = config.api.as_ref().filter(|api| api.auth_required()) else {
         drop(config);
         return next.run(request).await;
-    }
+    };
+    let jwt_secret = api_config.jwt_secret.clone();
+    let default_scopes = api_config.default_scopes.clone();
+    let has_api_keys = api_config.has_api_keys();
+    let lockout = api_config.lockout.clone();
 
-    // Try API key authentication first
-    if let Some(expected_key) = api_key
-        && let Some(provided_key) = request
-            .headers()
-            .get("X-API-Key")
-            .and_then(|v| v.to_str().ok())
+    // Locked out clients are refused before their credentials are checked
+    let lockout_ip = client_ip.filter(|_| lockout.enabled);
+    if let Some(ip) = lockout_ip
+        && let Some(remaining) = state.auth_lockout.remaining(ip)
     {
-        if provided_key == expected_key {
-            drop(config);
-            // Log successful API key auth
-            state
-                .audit_logger
-                .log(
-                    AuditLogger::auth_success(AuthMethod::ApiKey, None)
+        drop(config);
+        let retry_after = retry_after_secs(remaining);
+        state
+            .audit_logger
+            .log(AuditLogger::auth_locked_out(ip, retry_after))
+            .await;
+        return too_many_attempts(retry_after);
+    }
+
+    // Try API key authentication first: a named key, then the legacy one.
+    // A query token is either an API key or a JWT.
+    let key_match = header_key
+        .as_deref()
+        .or(query_token.as_deref())
+        .filter(|_| has_api_keys)
+        .and_then(|provided| match api_config.find_key(provided) {
+            Some(key) => Some((key.scopes.clone(), Some(key.name.clone()))),
+            None if api_config.is_legacy_key(provided) => Some((default_scopes.clone(), None)),
+            None => None,
+        });
+    drop(config);
+
+    if let Some((scopes, user_id)) = key_match {
+        if let Some(ip) = lockout_ip {
+            state.auth_lockout.record_success(ip);
+        }
+        // Log successful API key auth
+        state
+            .audit_logger
+            .log(
+                source.tag(
+                    AuditLogger::auth_success(AuthMethod::ApiKey, user_id.clone())
                         .with_endpoint(&endpoint)
                         .with_method(&method),
-                )
-                .await;
-            return next.run(request).await;
-        } else {
-            drop(config);
-            // Log failed API key auth
-            state
-                .audit_logger
-                .log(
+                ),
+            )
+            .await;
+        return authorize(&state, request, next, &scopes, AuthMethod::ApiKey, user_id).await;
+    }
+    // A query token can only be a JWT when a JWT secret is set; otherwise it
+    // was a wrong key, and counts towards the lockout like one
+    let key_presented = header_key.is_some() || (query_token.is_some() && jwt_secret.is_none());
+    if has_api_keys && key_presented {
+        auth_failed(&state, lockout_ip, &lockout).await;
+        // Log failed API key auth
+        state
+            .audit_logger
+            .log(
+                source.tag(
                     AuditLogger::auth_failure(AuthMethod::ApiKey, "Invalid API key")
                         .with_endpoint(&endpoint)
                         .with_method(&method),
-                )
-                .await;
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(json!({
-                    "error": "Invalid API key"
-                })),
+                ),
             )
-                .into_response();
-        }
+            .await;
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Invalid API key"
+            })),
+        )
+            .into_response();
     }
 
     // Try JWT authentication
-    if let Some(secret) = jwt_secret
-        && let Some(auth_header) = request
+    if let Some(secret) = &jwt_secret
+        && let Some(token) = request
             .headers()
             .get("Authorization")
             .and_then(|v| v.to_str().ok())
-        && let Some(token) = auth_header.strip_prefix("Bearer ")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .or(query_token.as_deref())
     {
         match validate_jwt(token, secret) {
             Ok(claims) => {
-                drop(config);
+                if let Some(ip) = lockout_ip {
+                    state.auth_lockout.record_success(ip);
+                }
                 // Log successful JWT auth
                 state
                     .audit_logger
                     .log(
-                        AuditLogger::auth_success(AuthMethod::Jwt, Some(claims.sub))
-                            .with_endpoint(&endpoint)
-                            .with_method(&method),
+                        source.tag(
+                            AuditLogger::auth_success(AuthMethod::Jwt, Some(claims.sub.clone()))
+                                .with_endpoint(&endpoint)
+                                .with_method(&method),
+                        ),
                     )
                     .await;
-                return next.run(request).await;
+                let identity = JwtIdentity {
+                    subject: claims.sub.clone(),
+                    scopes: claims.scopes(&default_scopes),
+                };
+                request.extensions_mut().insert(identity.clone());
+                return authorize(
+                    &state,
+                    request,
+                    next,
+                    &identity.scopes,
+                    AuthMethod::Jwt,
+                    Some(identity.subject),
+                )
+                .await;
             }
             Err(e) => {
                 tracing::debug!("JWT validation failed: {}", e);
-                drop(config);
+                auth_failed(&state, lockout_ip, &lockout).await;
                 // Log failed JWT auth
                 state
                     .audit_logger
                     .log(
-                        AuditLogger::auth_failure(
-                            AuthMethod::Jwt,
-                            format!("JWT validation failed: {}", e),
-                        )
-                        .with_endpoint(&endpoint)
-                        .with_method(&method),
+                        source.tag(
+                            AuditLogger::auth_failure(
+                                AuthMethod::Jwt,
+                                format!("JWT validation failed: {}", e),
+                            )
+                            .with_endpoint(&endpoint)
+                            .with_method(&method),
+                        ),
                     )
                     .await;
                 return (
@@ -145,24 +433,21 @@ async fn api_auth(State(state): State<AppState>, request: Request<Body>, next: N
         }
     }
 
-    drop(config);
     // Log denied access (no credentials)
     state
         .audit_logger
         .log(
-            AuditLogger::auth_denied()
-                .with_endpoint(&endpoint)
-                .with_method(&method),
+            source.tag(
+                AuditLogger::auth_denied()
+                    .with_endpoint(&endpoint)
+                    .with_method(&method),
+            ),
         )
         .await;
 
     // No valid authentication provided
-    let config = state.config.read().await;
-    let api_config = config.api.as_ref();
-    let api_key = api_config.and_then(|c| c.api_key.as_ref());
-    let jwt_secret = api_config.and_then(|c| c.jwt_secret.as_ref());
     let auth_methods: Vec<&str> = [
-        api_key.map(|_| "X-API-Key header"),
+        has_api_keys.then_some("X-API-Key header"),
         jwt_secret.map(|_| "Authorization: Bearer <token>"),
     ]
     .into_iter()
@@ -179,51 +464,421 @@ async fn api_auth(State(state): State<AppState>, request: Request<Body>, next: N
         .into_response()
 }
 
-pub async fn start_api_server(port: u16, state: AppState) -> anyhow::Result<()> {
+/// Count a failed authentication against the client, auditing the lockout
+/// it starts if it was the last failure allowed
+async fn auth_failed(state: &AppState, client_ip: Option<IpAddr>, lockout: &LockoutConfig) {
+    let Some(ip) = client_ip else {
+        return;
+    };
+    if let Some(duration) = state.auth_lockout.record_failure(ip, lockout) {
+        tracing::warn!(
+            "Locking {} out of the API for {}s after {} failed authentication attempts",
+            ip,
+            duration.as_secs(),
+            lockout.max_failures
+        );
+        state
+            .audit_logger
+            .log(AuditLogger::auth_lockout(
+                ip,
+                lockout.max_failures,
+                duration.as_secs(),
+            ))
+            .await;
+    }
+}
+
+/// Time left of a lockout in whole seconds, rounded up
+fn retry_after_secs(remaining: Duration) -> u64 {
+    remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
+}
+
+/// 429 for a locked out client, with `Retry-After`
+fn too_many_attempts(retry_after: u64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(json!({
+            "error": "Too many failed authentication attempts",
+            "retry_after_secs": retry_after
+        })),
+    )
+        .into_response()
+}
+
+/// How long an API client has to complete the TLS handshake
+const API_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The management API, bound to `api.bind_address` and with its TLS
+/// certificate loaded, so that a bad address or certificate fails startup
+pub struct ApiServer {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    cors: CorsConfig,
+    state: AppState,
+}
+
+impl ApiServer {
+    pub async fn bind(state: AppState) -> anyhow::Result<Self> {
+        let (bind_address, port, tls, cors) = {
+            let config = state.config.read().await;
+            let server = config.server.with_overrides(&state.server_overrides);
+            let api = config.api.as_ref();
+            (
+                server
+                    .api_bind
+                    .or(api.and_then(|api| api.bind_address))
+                    .unwrap_or(IpAddr::from([0, 0, 0, 0])),
+                server.api_port,
+                api.and_then(|api| api.tls.clone())
+                    .filter(|tls| tls.enabled),
+                api.map(|api| api.cors.clone()).unwrap_or_default(),
+            )
+        };
+        if cors.permissive {
+            tracing::warn!(
+                "api.cors.permissive is set: any website open in a browser can call the management API"
+            );
+        }
+        let tls = match tls {
+            Some(tls) => {
+                let mut server_config = crate::tls::server_config(&tls)
+                    .with_context(|| format!("Failed to load API certificate {}", tls.cert_path))?;
+                server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+                Some(TlsAcceptor::from(Arc::new(server_config)))
+            }
+            None => None,
+        };
+
+        let addr = SocketAddr::new(bind_address, port);
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind API server to {}", addr))?;
+        Ok(Self {
+            listener,
+            tls,
+            cors,
+            state,
+        })
+    }
+
+    pub async fn serve(self) -> anyhow::Result<()> {
+        let app = router(self.state, &self.cors);
+        let addr = self.listener.local_addr()?;
+        match self.tls {
+            Some(acceptor) => {
+                tracing::info!("Management API listening on https://{}", addr);
+                // Tapping the listener makes its peer addresses available
+                // as `ConnectInfo`
+                let listener = TlsListener::new(self.listener, acceptor)?.tap_io(|_| {});
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .await
+            }
+            None => {
+                tracing::info!("Management API listening on http://{}", addr);
+                axum::serve(
+                    self.listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .await
+            }
+        }
+        .map_err(|e| anyhow::anyhow!("API server error: {}", e))
+    }
+}
+
+/// Accepts API connections over TLS. Each handshake runs in its own task, so
+/// a client that stalls mid-handshake doesn't hold up the others.
+struct TlsListener {
+    local_addr: SocketAddr,
+    streams: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    fn new(listener: TcpListener, acceptor: TlsAcceptor) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, streams) = mpsc::channel(64);
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let (socket, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Such as running out of file descriptors
+                        tracing::warn!("Failed to accept API connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(API_TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket))
+                        .await
+                    {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => {
+                            tracing::debug!("API TLS handshake with {} failed: {}", addr, e)
+                        }
+                        Err(_) => tracing::debug!("API TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            streams,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.streams.recv().await {
+            Some(accepted) => accepted,
+            // The accept loop only ends once the server is gone
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// OpenAPI document of the API. Operations come from the `utoipa::path`
+/// attribute of each handler, collected as the routes are registered.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "IronVeil Management API",
+        description = "Masking rules, configuration, scans, statistics and audit log of the IronVeil proxy"
+    ),
+    modifiers(&ApiDocComponents)
+)]
+struct ApiDoc;
+
+/// Security schemes and the error responses shared by protected operations
+struct ApiDocComponents;
+
+impl Modify for ApiDocComponents {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "jwt",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.schemas.insert(
+            "Error".to_string(),
+            ObjectBuilder::new()
+                .property("error", ObjectBuilder::new().schema_type(Type::String))
+                .required("error")
+                .property(
+                    "required_scope",
+                    ObjectBuilder::new()
+                        .schema_type(Type::String)
+                        .description(Some("The scope a 403 was missing")),
+                )
+                .into(),
+        );
+        let error = |description: &str| {
+            ResponseBuilder::new().description(description).content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("Error")))
+                    .build(),
+            )
+        };
+        components.responses.insert(
+            "Unauthorized".to_string(),
+            error("Missing or invalid API key or JWT").build().into(),
+        );
+        components.responses.insert(
+            "Forbidden".to_string(),
+            error("The credentials lack the endpoint's scope")
+                .build()
+                .into(),
+        );
+        components.responses.insert(
+            "TooManyRequests".to_string(),
+            error("Locked out after repeated authentication failures")
+                .header(
+                    "Retry-After",
+                    HeaderBuilder::new()
+                        .schema(ObjectBuilder::new().schema_type(Type::Integer))
+                        .description(Some("Seconds until the lockout ends"))
+                        .build(),
+                )
+                .build()
+                .into(),
+        );
+    }
+}
+
+/// Mark every operation of `openapi` as needing an API key or JWT, with the
+/// error responses of `api_auth`
+fn require_auth(openapi: &mut utoipa::openapi::OpenApi) {
+    for item in openapi.paths.paths.values_mut() {
+        let operations = [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.patch,
+        ];
+        for operation in operations.into_iter().flatten() {
+            operation.security = Some(vec![
+                SecurityRequirement::new("api_key", Vec::<String>::new()),
+                SecurityRequirement::new("jwt", Vec::<String>::new()),
+            ]);
+            for (status, response) in [
+                ("401", "Unauthorized"),
+                ("403", "Forbidden"),
+                ("429", "TooManyRequests"),
+            ] {
+                operation.responses.responses.insert(
+                    status.to_string(),
+                    Ref::new(format!("#/components/responses/{}", response)).into(),
+                );
+            }
+        }
+    }
+}
+
+/// CORS for browser dashboards served from other origins. Without allowed
+/// origins no CORS headers are sent, so browsers allow same-origin requests
+/// only. The config has been validated, so nothing here fails to parse.
+fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    if cors.permissive {
+        return CorsLayer::permissive();
+    }
+    let any = |list: &[String]| list.iter().any(|entry| entry == "*");
+    let origins = if any(&cors.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            cors.allowed_origins
+                .iter()
+                .filter_map(|origin| origin.parse().ok()),
+        )
+    };
+    let headers = if any(&cors.allowed_headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(
+            cors.allowed_headers
+                .iter()
+                .filter_map(|header| header.parse().ok()),
+        )
+    };
+    let methods = if any(&cors.allowed_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(
+            cors.allowed_methods
+                .iter()
+                .filter_map(|method| method.parse().ok()),
+        )
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_headers(headers)
+        .allow_methods(methods)
+        .allow_credentials(cors.allow_credentials)
+}
+
+/// All API routes, with authentication on the protected ones
+fn router(state: AppState, cors: &CorsConfig) -> Router {
     // Public routes (no auth required)
-    let public_routes = Router::new()
-        .route("/health", get(health_check))
-        .route("/metrics", get(get_metrics));
+    let public_routes = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(health_check))
+        .routes(routes!(health_live))
+        .routes(routes!(health_ready))
+        .routes(routes!(get_metrics));
 
     // Protected routes (require API key or JWT if configured)
-    let protected_routes = Router::new()
-        .route("/rules", get(get_rules).post(add_rule))
-        .route("/rules/delete", post(delete_rule))
-        .route("/rules/export", get(export_rules))
-        .route("/rules/import", post(import_rules))
-        .route("/config", get(get_config).post(update_config))
-        .route("/config/reload", post(reload_config))
-        .route("/scan", post(scan_database))
-        .route("/connections", get(get_connections))
-        .route("/stats", get(get_stats))
-        .route("/schema", post(get_schema))
-        .route("/logs", get(get_logs))
-        .route("/audit", get(get_audit_logs))
-        .layer(middleware::from_fn_with_state(state.clone(), api_auth));
+    let (protected_routes, mut protected_api) = OpenApiRouter::new()
+        .routes(routes!(get_rules, add_rule))
+        .routes(routes!(update_rule, delete_rule_by_id))
+        .routes(routes!(delete_rule))
+        .routes(routes!(export_rules))
+        .routes(routes!(import_rules))
+        .routes(routes!(get_config, update_config))
+        .routes(routes!(get_full_config))
+        .routes(routes!(reload_config))
+        .routes(routes!(scan_database))
+        .routes(routes!(scan_value))
+        .routes(routes!(get_scan_history))
+        .routes(routes!(get_scan_history_entry))
+        .routes(routes!(get_scan_job, cancel_scan_job))
+        .routes(routes!(get_scan_result))
+        .routes(routes!(get_connections))
+        .routes(routes!(list_connections))
+        .routes(routes!(kill_connection))
+        .routes(routes!(get_stats))
+        .routes(routes!(reset_stats))
+        .routes(routes!(reset_column_stats))
+        .routes(routes!(get_schema))
+        .routes(routes!(get_logs))
+        .routes(routes!(stream_logs))
+        .routes(routes!(get_audit_logs))
+        .routes(routes!(export_audit_logs))
+        .routes(routes!(detokenize))
+        .routes(routes!(create_api_key))
+        .routes(routes!(revoke_api_key))
+        .routes(routes!(get_openapi))
+        .routes(routes!(get_docs))
+        .layer(middleware::from_fn_with_state(state.clone(), api_auth))
+        .split_for_parts();
+    require_auth(&mut protected_api);
+
+    let (public_routes, mut openapi) = public_routes.split_for_parts();
+    openapi.merge(protected_api);
 
     // Combine routes
-    let app = Router::new()
+    Router::new()
         .merge(public_routes)
         .merge(protected_routes)
+        .layer(Extension(Arc::new(openapi)))
         .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
-        .with_state(state);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    tracing::info!("Management API listening on {}", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to bind API server to {}: {}", addr, e))?;
-    axum::serve(listener, app)
-        .await
-        .map_err(|e| anyhow::anyhow!("API server error: {}", e))?;
-    Ok(())
+        .layer(cors_layer(cors))
+        .with_state(state)
 }
 
+/// Proxy and upstream health
+#[utoipa::path(
+    get, path = "/health", tag = "system",
+    responses(
+        (status = 200, description = "The upstream database is reachable", body = Object,
+            example = json!({"status": "ok", "service": "ironveil", "version": "0.1.0",
+                "upstream": {"healthy": true, "last_check": "2025-01-01T00:00:00Z", "last_error": null,
+                    "latency_ms": 2, "consecutive_failures": 0, "consecutive_successes": 12},
+                "connections": {"active": 3},
+                "config": {"path": "proxy.yaml", "last_reload": "2025-01-01T00:00:00Z",
+                    "last_error": null, "last_error_at": null}})),
+        (status = 503, description = "The upstream database is failing its health checks", body = Object)
+    )
+)]
 async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     let health_status = state.health_status.read().await;
     let active_connections = state.active_connections.load(Ordering::Relaxed);
+    let config_reload = state.config_reload_status.read().await.clone();
 
     let response = json!({
         "status": if health_status.healthy { "ok" } else { "degraded" },
@@ -239,6 +894,16 @@ async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
         },
         "connections": {
             "active": active_connections
+        },
+        "config": {
+            "path": state.config_path.as_str(),
+            "last_reload": config_reload.last_reload,
+            "last_error": config_reload.last_error,
+            "last_error_at": config_reload.last_error_at
+        },
+        "probes": {
+            "liveness": "/health/live",
+            "readiness": "/health/ready"
         }
     });
 
@@ -249,48 +914,422 @@ async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
-async fn get_rules(State(state): State<AppState>) -> Json<Value> {
-    let config = state.config.read().await;
-    Json(json!(*config))
+/// Liveness probe: answers as long as the process is up, whatever the state
+/// of the upstream database
+#[utoipa::path(
+    get, path = "/health/live", tag = "system",
+    responses((status = 200, description = "The process is running", body = Object,
+        example = json!({"status": "alive"})))
+)]
+async fn health_live() -> Json<Value> {
+    Json(json!({ "status": "alive" }))
 }
 
-async fn add_rule(
-    State(state): State<AppState>,
-    Json(rule): Json<MaskingRule>,
-) -> impl IntoResponse {
-    let mut config = state.config.write().await;
-    let rule_json = serde_json::to_value(&rule).unwrap_or_default();
-    config.rules.push(rule);
-    let rules_count = config.rules.len();
+/// Readiness probe: whether the proxy should be sent traffic. Not ready
+/// until the upstream has answered a health check, while it is unhealthy,
+/// and while the proxy is saturated with connections.
+#[utoipa::path(
+    get, path = "/health/ready", tag = "system",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Object,
+            example = json!({"ready": true, "reasons": [], "upstream": {"checked": true, "healthy": true,
+                "last_success": "2025-01-01T00:00:00Z"}, "connections": {"active": 3, "max": 100}})),
+        (status = 503, description = "Not ready; `reasons` says why", body = Object)
+    )
+)]
+async fn health_ready(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    let config = state.config.read().await;
+    let checked = config.health_check.as_ref().is_none_or(|h| h.enabled);
+    let max_connections = config
+        .health_check
+        .as_ref()
+        .and_then(|h| h.ready_max_connections)
+        .or(config.limits.as_ref().and_then(|l| l.max_connections));
     drop(config);
 
-    // Persist to file
-    if let Err(e) = state.save_config().await {
-        tracing::error!("Failed to save config: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "status": "error",
-                "error": format!("Failed to persist rule: {}", e),
-                "rules_count": rules_count
-            })),
-        );
+    let health_status = state.health_status.read().await;
+    let active_connections = state.active_connections.load(Ordering::Relaxed);
+    let mut reasons = Vec::new();
+    // Without health checks the upstream's state is unknown, so it isn't
+    // held against readiness
+    if checked && health_status.last_success.is_none() {
+        reasons.push("Upstream has not answered a health check yet".to_string());
+    } else if checked && !health_status.healthy {
+        reasons.push(format!(
+            "Upstream is unhealthy: {}",
+            health_status
+                .last_error
+                .as_deref()
+                .unwrap_or("unknown error")
+        ));
+    }
+    if let Some(max) = max_connections
+        && active_connections >= max
+    {
+        reasons.push(format!(
+            "Saturated: {} of {} connections in use",
+            active_connections, max
+        ));
     }
 
-    // Log audit event
-    state
-        .audit_logger
-        .log(AuditLogger::rule_added(rule_json))
-        .await;
+    let ready = reasons.is_empty();
+    let response = json!({
+        "ready": ready,
+        "reasons": reasons,
+        "upstream": {
+            "checked": checked,
+            "healthy": health_status.healthy,
+            "last_success": health_status.last_success
+        },
+        "connections": {
+            "active": active_connections,
+            "max": max_connections
+        }
+    });
+    if ready {
+        (StatusCode::OK, Json(response))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(response))
+    }
+}
 
-    (
-        StatusCode::OK,
-        Json(json!({ "status": "success", "rules_count": rules_count })),
-    )
+/// Config fields holding credentials, or where to find them, that no
+/// response may contain
+const SECRET_FIELDS: &[&str] = &[
+    "api_key",
+    "jwt_secret",
+    "hash_salt",
+    "seed_salt",
+    "password",
+    "key",
+    "key_path",
+    "key_hash",
+    "upstream_tls_client_key_path",
+];
+
+/// Replace every secret field that is set, at any depth, with `***`
+fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) {
+                    if !field.is_null() {
+                        *field = json!("***");
+                    }
+                } else {
+                    redact_secrets(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
 }
 
-/// Delete rule request payload
-#[derive(Debug, Deserialize, Serialize)]
+/// `value` as JSON, with its secrets redacted
+fn redacted_json(value: &impl Serialize) -> Value {
+    let mut value = serde_json::to_value(value).unwrap_or_default();
+    redact_secrets(&mut value);
+    value
+}
+
+/// An imported rule whose seed salt was redacted by `/rules/export` takes the
+/// salt back from the current rule with its ID, or else its table and column
+fn restore_redacted_salt(rule: &mut MaskingRule, current: &[MaskingRule]) -> Result<(), String> {
+    let Some(options) = rule.options.as_mut() else {
+        return Ok(());
+    };
+    if options.seed_salt.as_deref() != Some("***") {
+        return Ok(());
+    }
+    let salt = current
+        .iter()
+        .find(|r| r.id == rule.id)
+        .or_else(|| current.iter().find(|r| r.same_target(rule)))
+        .and_then(|r| r.options.as_ref())
+        .and_then(|options| options.seed_salt.clone());
+    match salt {
+        Some(salt) => {
+            rule.options.as_mut().expect("checked above").seed_salt = Some(salt);
+            Ok(())
+        }
+        None => Err(format!(
+            "The seed_salt of the rule for {} is redacted and no current rule has one to keep; send the salt itself",
+            rule.target()
+        )),
+    }
+}
+
+/// The masking rules, with rule secrets such as seed salts redacted
+#[utoipa::path(
+    get, path = "/rules", tag = "rules",
+    responses((status = 200, description = "Whether masking is on, and the rules in order", body = Object,
+        example = json!({"masking_enabled": true, "rules": [{"id": "5f0c...", "table": "users", "column": "email", "strategy": "email"}]})))
+)]
+async fn get_rules(State(state): State<AppState>) -> Json<Value> {
+    let config = state.config.read().await;
+    let mut rules = json!(config.rules);
+    redact_secrets(&mut rules);
+    Json(json!({
+        "masking_enabled": config.masking_enabled,
+        "rules": rules
+    }))
+}
+
+/// The whole configuration, with its secrets redacted
+#[utoipa::path(
+    get, path = "/config/full", tag = "config",
+    responses((status = 200, description = "The configuration, with secrets as `***`", body = Object))
+)]
+async fn get_full_config(State(state): State<AppState>) -> Json<Value> {
+    let mut body = json!(*state.config.read().await);
+    redact_secrets(&mut body);
+    Json(body)
+}
+
+/// Query parameters of the endpoints that add or replace rules
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RuleWriteQuery {
+    /// Accept rules for the same table and column as an existing rule
+    #[serde(default)]
+    allow_duplicates: bool,
+}
+
+/// Parse and check a rule sent to the API
+fn parse_rule(value: Value) -> Result<MaskingRule, String> {
+    let rule: MaskingRule = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    rule.validate().map_err(|e| e.to_string())?;
+    Ok(rule)
+}
+
+fn duplicate_rule(rule: &MaskingRule, existing: &MaskingRule) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({
+            "status": "error",
+            "error": format!(
+                "Rule {} already covers {}; pass allow_duplicates=true to add another",
+                existing.id,
+                rule.target()
+            ),
+            "existing_id": existing.id
+        })),
+    )
+}
+
+/// Add a rule; it always gets a fresh ID, whatever the request says
+#[utoipa::path(
+    post, path = "/rules", tag = "rules", params(RuleWriteQuery), request_body = MaskingRule,
+    responses(
+        (status = 200, description = "Rule added", body = Object,
+            example = json!({"status": "success", "id": "5f0c...", "rules_count": 4})),
+        (status = 400, description = "Invalid rule", body = Object),
+        (status = 409, description = "A rule for the same table and column exists; its ID is `existing_id`", body = Object),
+        (status = 500, description = "The rule could not be saved", body = Object)
+    )
+)]
+async fn add_rule(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    axum::extract::Query(query): axum::extract::Query<RuleWriteQuery>,
+    Json(rule): Json<Value>,
+) -> impl IntoResponse {
+    let mut rule = match parse_rule(rule) {
+        Ok(rule) => rule,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "status": "error", "error": e })),
+            );
+        }
+    };
+
+    rule.id = RuleId::default();
+    let id = rule.id.clone();
+    let mut config = state.config.write().await;
+    if !query.allow_duplicates
+        && let Some(existing) = config.rules.iter().find(|r| r.same_target(&rule))
+    {
+        return duplicate_rule(&rule, existing);
+    }
+    let rule_json = redacted_json(&rule);
+    config.rules.push(rule);
+    let rules_count = config.rules.len();
+    state.config_changed();
+    drop(config);
+
+    // Persist to file
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist rule: {}", e),
+                "rules_count": rules_count
+            })),
+        );
+    }
+
+    // Log audit event
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::rule_added(rule_json)))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({ "status": "success", "id": id, "rules_count": rules_count })),
+    )
+}
+
+/// Replace the rule with the given ID, keeping its ID and its place in the
+/// rule order
+#[utoipa::path(
+    put, path = "/rules/{id}", tag = "rules",
+    params(("id" = String, Path, description = "Rule ID"), RuleWriteQuery), request_body = MaskingRule,
+    responses(
+        (status = 200, description = "Rule replaced", body = Object),
+        (status = 400, description = "Invalid rule", body = Object),
+        (status = 404, description = "No rule has this ID", body = Object),
+        (status = 409, description = "Another rule has the same table and column", body = Object),
+        (status = 500, description = "The rule could not be saved", body = Object)
+    )
+)]
+async fn update_rule(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    Path(id): Path<String>,
+    axum::extract::Query(query): axum::extract::Query<RuleWriteQuery>,
+    Json(rule): Json<Value>,
+) -> impl IntoResponse {
+    let mut rule = match parse_rule(rule) {
+        Ok(rule) => rule,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "status": "error", "error": e })),
+            );
+        }
+    };
+
+    let mut config = state.config.write().await;
+    let Some(index) = config.rules.iter().position(|r| r.id.as_str() == id) else {
+        return rule_not_found(&id);
+    };
+    if !query.allow_duplicates
+        && let Some(existing) = config
+            .rules
+            .iter()
+            .find(|r| r.id.as_str() != id && r.same_target(&rule))
+    {
+        return duplicate_rule(&rule, existing);
+    }
+    let existing = &mut config.rules[index];
+    rule.id = existing.id.clone();
+    let previous = std::mem::replace(existing, rule);
+    let details = json!({
+        "id": id,
+        "previous": redacted_json(&previous),
+        "rule": redacted_json(existing),
+    });
+    let rules_count = config.rules.len();
+    state.config_changed();
+    drop(config);
+
+    // Persist to file
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist rule: {}", e)
+            })),
+        );
+    }
+
+    // Log audit event
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::rule_updated(details)))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({ "status": "success", "id": id, "rules_count": rules_count })),
+    )
+}
+
+/// Delete the rule with the given ID
+#[utoipa::path(
+    delete, path = "/rules/{id}", tag = "rules",
+    params(("id" = String, Path, description = "Rule ID")),
+    responses(
+        (status = 200, description = "Rule deleted", body = Object),
+        (status = 404, description = "No rule has this ID", body = Object),
+        (status = 500, description = "The change could not be saved", body = Object)
+    )
+)]
+async fn delete_rule_by_id(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let Some(index) = config.rules.iter().position(|r| r.id.as_str() == id) else {
+        return rule_not_found(&id);
+    };
+    let rule = config.rules.remove(index);
+    let rules_count = config.rules.len();
+    state.config_changed();
+    drop(config);
+
+    // Persist to file
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist changes: {}", e)
+            })),
+        );
+    }
+
+    // Log audit event
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::rule_deleted(json!({
+            "ids": [&rule.id],
+            "rules": [redacted_json(&rule)],
+            "deleted_count": 1
+        }))))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "status": "success",
+            "deleted": 1,
+            "rules_count": rules_count
+        })),
+    )
+}
+
+fn rule_not_found(id: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "status": "error",
+            "error": format!("No rule with ID '{}'", id)
+        })),
+    )
+}
+
+/// Delete rule request payload
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 struct DeleteRuleRequest {
     /// Index of the rule to delete (0-based)
     index: Option<usize>,
@@ -300,14 +1339,25 @@ struct DeleteRuleRequest {
     table: Option<String>,
 }
 
+#[utoipa::path(
+    post, path = "/rules/delete", tag = "rules", request_body = DeleteRuleRequest,
+    responses(
+        (status = 200, description = "Matching rules deleted", body = Object),
+        (status = 400, description = "Neither an index nor a column was given", body = Object),
+        (status = 404, description = "No rule matches", body = Object),
+        (status = 500, description = "The change could not be saved", body = Object)
+    )
+)]
 async fn delete_rule(
     State(state): State<AppState>,
+    client_ip: ClientIp,
     Json(req): Json<DeleteRuleRequest>,
 ) -> impl IntoResponse {
     let mut config = state.config.write().await;
 
     let original_len = config.rules.len();
     let delete_details = serde_json::to_value(&req).unwrap_or_default();
+    let ids_before: Vec<RuleId> = config.rules.iter().map(|r| r.id.clone()).collect();
 
     if let Some(index) = req.index {
         if index >= config.rules.len() {
@@ -322,11 +1372,11 @@ async fn delete_rule(
         config.rules.remove(index);
     } else if let Some(ref column) = req.column {
         config.rules.retain(|rule| {
-            let column_matches = &rule.column != column;
+            let column_matches = rule.column != **column;
             let table_matches = req
                 .table
                 .as_ref()
-                .map(|t| rule.table.as_ref() != Some(t))
+                .map(|t| rule.table.as_ref().map(NamePattern::as_str) != Some(t.as_str()))
                 .unwrap_or(true);
             column_matches || !table_matches
         });
@@ -341,7 +1391,12 @@ async fn delete_rule(
     }
 
     let deleted_count = original_len - config.rules.len();
+    let deleted_ids: Vec<RuleId> = ids_before
+        .into_iter()
+        .filter(|id| !config.rules.iter().any(|r| r.id == *id))
+        .collect();
     let rules_count = config.rules.len();
+    state.config_changed();
     drop(config);
 
     // Persist to file
@@ -359,10 +1414,11 @@ async fn delete_rule(
     // Log audit event
     state
         .audit_logger
-        .log(AuditLogger::rule_deleted(json!({
+        .log(client_ip.tag(AuditLogger::rule_deleted(json!({
             "request": delete_details,
+            "ids": deleted_ids,
             "deleted_count": deleted_count
-        })))
+        }))))
         .await;
 
     (
@@ -376,10 +1432,15 @@ async fn delete_rule(
 }
 
 /// Export rules as JSON
+#[utoipa::path(
+    get, path = "/rules/export", tag = "rules",
+    responses((status = 200, description = "The rules, as accepted by `/rules/import`. Seed salts are \
+        redacted; importing a redacted salt keeps the current rule's", body = Object))
+)]
 async fn export_rules(State(state): State<AppState>) -> impl IntoResponse {
     let config = state.config.read().await;
-    let rules_json =
-        serde_json::to_string_pretty(&config.rules).unwrap_or_else(|_| "[]".to_string());
+    let rules_json = serde_json::to_string_pretty(&redacted_json(&config.rules))
+        .unwrap_or_else(|_| "[]".to_string());
 
     (
         StatusCode::OK,
@@ -394,15 +1455,144 @@ async fn export_rules(State(state): State<AppState>) -> impl IntoResponse {
     )
 }
 
-/// Import rules from JSON
+/// How `/rules/import` combines the rules sent with the current ones
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ImportMode {
+    /// Add every rule sent, with a fresh ID
+    #[default]
+    Append,
+    /// Add rules for new table and column pairs and update the strategy of
+    /// existing ones; rules already present unchanged are skipped
+    Merge,
+    /// Swap the whole rule set for the rules sent, keeping their IDs
+    Replace,
+}
+
+impl ImportMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ImportMode::Append => "append",
+            ImportMode::Merge => "merge",
+            ImportMode::Replace => "replace",
+        }
+    }
+}
+
+/// Query parameters of `/rules/import`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportQuery {
+    #[serde(default)]
+    #[param(inline)]
+    mode: ImportMode,
+    /// With `append` or `replace`, accept rules for the same table and column
+    /// as another rule
+    #[serde(default)]
+    allow_duplicates: bool,
+}
+
+/// Import rules from JSON. Nothing is imported unless every rule is valid;
+/// the errors of all of them are returned at once. Appended and merged-in
+/// rules get fresh IDs, so that importing an export again doesn't clash
+/// with the rules it came from, while `replace` keeps the IDs sent so that
+/// an export round-trips.
+#[utoipa::path(
+    post, path = "/rules/import", tag = "rules", params(ImportQuery), request_body = Vec<MaskingRule>,
+    responses(
+        (status = 200, description = "Rules imported; `ids` in the order sent, the existing rule's ID for those merged into one", body = Object,
+            example = json!({"status": "success", "mode": "merge", "added": 1, "updated": 1, "skipped": 3,
+                "ids": ["6f1c…", "0b9e…"], "rules_count": 12})),
+        (status = 422, description = "Nothing imported; `errors` lists each invalid rule by `index`", body = Object,
+            example = json!({"status": "error", "errors": [{"index": 1, "error": "Unknown masking strategy 'emial'"}]})),
+        (status = 500, description = "The rules could not be saved", body = Object)
+    )
+)]
 async fn import_rules(
     State(state): State<AppState>,
-    Json(rules): Json<Vec<MaskingRule>>,
+    client_ip: ClientIp,
+    axum::extract::Query(query): axum::extract::Query<ImportQuery>,
+    Json(values): Json<Vec<Value>>,
 ) -> impl IntoResponse {
     let mut config = state.config.write().await;
-    let imported_count = rules.len();
-    config.rules.extend(rules);
+    // Built apart from the config, which is only swapped for it once every
+    // rule has been accepted
+    let mut rules = match query.mode {
+        ImportMode::Replace => Vec::with_capacity(values.len()),
+        ImportMode::Append | ImportMode::Merge => config.rules.clone(),
+    };
+    let current_count = rules.len();
+    let mut ids = Vec::with_capacity(values.len());
+    let (mut added, mut updated, mut skipped) = (0, 0, 0);
+    let mut errors = Vec::new();
+    for (index, value) in values.into_iter().enumerate() {
+        let mut rule = match parse_rule(value) {
+            Ok(rule) => rule,
+            Err(e) => {
+                errors.push(json!({ "index": index, "error": e }));
+                continue;
+            }
+        };
+        if let Err(e) = restore_redacted_salt(&mut rule, &config.rules) {
+            errors.push(json!({ "index": index, "error": e }));
+            continue;
+        }
+        let existing = rules.iter().position(|r| r.same_target(&rule));
+        match (query.mode, existing) {
+            (ImportMode::Merge, Some(position)) => {
+                let existing = &mut rules[position];
+                ids.push(existing.id.clone());
+                if existing.same_masking(&rule) {
+                    skipped += 1;
+                } else {
+                    rule.id = existing.id.clone();
+                    *existing = rule;
+                    updated += 1;
+                }
+                continue;
+            }
+            (ImportMode::Append | ImportMode::Replace, Some(position))
+                if !query.allow_duplicates =>
+            {
+                let error = if position < current_count {
+                    format!(
+                        "Rule {} already covers {}",
+                        rules[position].id,
+                        rule.target()
+                    )
+                } else {
+                    format!("An earlier imported rule already covers {}", rule.target())
+                };
+                errors.push(json!({ "index": index, "error": error }));
+                continue;
+            }
+            _ => {}
+        }
+        if query.mode == ImportMode::Replace {
+            if rules.iter().any(|r| r.id == rule.id) {
+                errors.push(json!({
+                    "index": index,
+                    "error": format!("An earlier imported rule has ID {}", rule.id)
+                }));
+                continue;
+            }
+        } else {
+            rule.id = RuleId::default();
+        }
+        ids.push(rule.id.clone());
+        rules.push(rule);
+        added += 1;
+    }
+    if !errors.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "status": "error", "errors": errors })),
+        );
+    }
+
+    config.rules = rules;
     let total_count = config.rules.len();
+    state.config_changed();
     drop(config);
 
     // Persist to file
@@ -420,64 +1610,229 @@ async fn import_rules(
     // Log audit event
     state
         .audit_logger
-        .log(AuditLogger::rules_imported(imported_count))
+        .log(client_ip.tag(AuditLogger::rules_imported(
+            query.mode.as_str(),
+            &ids,
+            added,
+            updated,
+            skipped,
+        )))
         .await;
 
     (
         StatusCode::OK,
         Json(json!({
             "status": "success",
-            "imported": imported_count,
+            "mode": query.mode,
+            "imported": added + updated,
+            "added": added,
+            "updated": updated,
+            "skipped": skipped,
+            "ids": ids,
             "rules_count": total_count
         })),
     )
 }
 
+/// The runtime settings `POST /config` changes, as they are now
+fn runtime_settings(config: &AppConfig) -> Value {
+    let limits = config.limits.as_ref();
+    json!({
+        "masking_enabled": config.masking_enabled,
+        "masking_mode": config.masking_mode,
+        "upstream_tls": config.upstream_tls,
+        "limits": {
+            "max_connections": limits.and_then(|l| l.max_connections),
+            "connections_per_second": limits.and_then(|l| l.connections_per_second)
+        }
+    })
+}
+
+/// Masking and connection settings, without secrets
+#[utoipa::path(
+    get, path = "/config", tag = "config",
+    responses((status = 200, description = "Masking and connection settings", body = Object))
+)]
 async fn get_config(State(state): State<AppState>) -> Json<Value> {
     let config = state.config.read().await;
-    Json(json!({
-        "masking_enabled": config.masking_enabled,
-        "rules_count": config.rules.len()
-    }))
+    let mut settings = runtime_settings(&config);
+    settings["rules_count"] = json!(config.rules.len());
+    settings["masking"] = json!({
+        "hash_salt_set": config.masking.hash_salt.is_some(),
+        "hash_length": config.masking.hash_length,
+        "seed_salt_set": config.masking.seed_salt.is_some()
+    });
+    Json(settings)
+}
+
+/// Settings `POST /config` may change; any other field is refused
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct ConfigUpdate {
+    masking_enabled: Option<bool>,
+    /// `rules_only`, `heuristic` or `both`
+    #[schema(value_type = Option<String>)]
+    masking_mode: Option<MaskingMode>,
+    /// Negotiate TLS with the upstream, for connections opened from now on
+    upstream_tls: Option<bool>,
+    limits: Option<LimitsUpdate>,
+}
+
+/// Connection limits, applied to new connections; `null` lifts a limit
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct LimitsUpdate {
+    #[serde(default, deserialize_with = "set_or_null")]
+    #[schema(value_type = Option<usize>, minimum = 1)]
+    max_connections: Option<Option<usize>>,
+    #[serde(default, deserialize_with = "set_or_null")]
+    #[schema(value_type = Option<u32>, minimum = 1)]
+    connections_per_second: Option<Option<u32>>,
+}
+
+/// Tell a field sent as `null` (`Some(None)`) from one left out (`None`)
+fn set_or_null<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn invalid_config_update(error: String) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "status": "error", "error": error })),
+    )
 }
 
-async fn update_config(State(state): State<AppState>, Json(payload): Json<Value>) -> Json<Value> {
+/// Change masking and connection settings at runtime. Only the fields sent
+/// change; the result is validated as a whole before any of it applies, and
+/// saved to the config file.
+#[utoipa::path(
+    post, path = "/config", tag = "config", request_body = ConfigUpdate,
+    responses(
+        (status = 200, description = "Settings changed; the settings as they now are", body = Object,
+            example = json!({"status": "success", "masking_enabled": true, "masking_mode": "heuristic",
+                "upstream_tls": false, "limits": {"max_connections": 200, "connections_per_second": null}})),
+        (status = 400, description = "Unknown field, or invalid value; nothing changed", body = Object),
+        (status = 500, description = "The change applies but could not be saved", body = Object)
+    )
+)]
+async fn update_config(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    Json(payload): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    let update: ConfigUpdate = match serde_json::from_value(payload) {
+        Ok(update) => update,
+        Err(e) => return invalid_config_update(format!("Invalid config update: {}", e)),
+    };
+
     let mut config = state.config.write().await;
+    let mut candidate = config.clone();
     let mut changes = serde_json::Map::new();
-
-    if let Some(enabled) = payload.get("masking_enabled").and_then(|v| v.as_bool()) {
-        let old_value = config.masking_enabled;
-        config.masking_enabled = enabled;
-        changes.insert(
-            "masking_enabled".to_string(),
-            json!({
-                "old": old_value,
-                "new": enabled
-            }),
+    let mut change = |field: &str, old: Value, new: Value| {
+        if old != new {
+            changes.insert(field.to_string(), json!({ "old": old, "new": new }));
+        }
+    };
+    if let Some(enabled) = update.masking_enabled {
+        change(
+            "masking_enabled",
+            json!(candidate.masking_enabled),
+            json!(enabled),
+        );
+        candidate.masking_enabled = enabled;
+    }
+    if let Some(mode) = update.masking_mode {
+        change("masking_mode", json!(candidate.masking_mode), json!(mode));
+        candidate.masking_mode = mode;
+    }
+    if let Some(enabled) = update.upstream_tls {
+        change(
+            "upstream_tls",
+            json!(candidate.upstream_tls),
+            json!(enabled),
         );
+        candidate.upstream_tls = enabled;
+    }
+    if let Some(update) = update.limits {
+        let limits = candidate.limits.get_or_insert_with(LimitsConfig::default);
+        if let Some(max) = update.max_connections {
+            change(
+                "limits.max_connections",
+                json!(limits.max_connections),
+                json!(max),
+            );
+            limits.max_connections = max;
+        }
+        if let Some(rate) = update.connections_per_second {
+            change(
+                "limits.connections_per_second",
+                json!(limits.connections_per_second),
+                json!(rate),
+            );
+            limits.connections_per_second = rate;
+        }
+    }
+    if let Err(e) = candidate.validate() {
+        return invalid_config_update(format!("Invalid config update: {:#}", e));
+    }
+    // The TLS files are otherwise only checked at startup
+    if candidate.upstream_tls_enabled()
+        && let Err(e) = crate::tls::upstream_client_config(&candidate)
+    {
+        return invalid_config_update(format!("Upstream TLS cannot be enabled: {:#}", e));
+    }
+    if changes.is_empty() {
+        return (StatusCode::OK, ok_settings(&config));
     }
+    *config = candidate;
+    state.config_changed();
+    let settings = ok_settings(&config);
     drop(config);
 
-    // Log audit event if there were changes
-    if !changes.is_empty() {
-        state
-            .audit_logger
-            .log(AuditLogger::config_change(Value::Object(changes)))
-            .await;
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist config change: {}", e)
+            })),
+        );
     }
 
-    let config = state.config.read().await;
-    Json(json!({ "status": "success", "masking_enabled": config.masking_enabled }))
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::config_change(Value::Object(changes))))
+        .await;
+
+    (StatusCode::OK, settings)
+}
+
+fn ok_settings(config: &AppConfig) -> Json<Value> {
+    let mut settings = runtime_settings(config);
+    settings["status"] = json!("success");
+    Json(settings)
 }
 
 /// Reload configuration from disk
-async fn reload_config(State(state): State<AppState>) -> impl IntoResponse {
+#[utoipa::path(
+    post, path = "/config/reload", tag = "config",
+    responses(
+        (status = 200, description = "Configuration reloaded", body = Object),
+        (status = 500, description = "The file could not be loaded; the running config is kept", body = Object)
+    )
+)]
+async fn reload_config(State(state): State<AppState>, client_ip: ClientIp) -> impl IntoResponse {
     match state.reload_config().await {
         Ok(rules_count) => {
             // Log audit event
             state
                 .audit_logger
-                .log(AuditLogger::config_reload(rules_count))
+                .log(client_ip.tag(AuditLogger::config_reload(rules_count, ReloadTrigger::Api)))
                 .await;
             (
                 StatusCode::OK,
@@ -488,85 +1843,544 @@ async fn reload_config(State(state): State<AppState>) -> impl IntoResponse {
                 })),
             )
         }
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "status": "error",
-                "error": e
-            })),
-        ),
+        Err(e) => {
+            state
+                .audit_logger
+                .log(client_ip.tag(AuditLogger::config_reload_failed(&e, ReloadTrigger::Api)))
+                .await;
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "status": "error",
+                    "error": e
+                })),
+            )
+        }
     }
 }
 
+/// Create a scanner for the upstream database, honouring the scan's `sslmode` and
+/// the upstream TLS settings. Fills in the scan's credentials from the config
+/// when the request has none.
+async fn upstream_scanner(state: &AppState, scan: &mut ScanConfig) -> Result<DbScanner, ScanError> {
+    let (upstream_host, upstream_port) = state.upstream().await;
+    let config = state.config.read().await;
+    scan.filters.validate()?;
+    scan.resolve_credentials(&config.scanner)?;
+    let scanner = DbScanner::new(upstream_host, upstream_port, state.db_protocol)
+        .with_pii_scanner(state.scanner())
+        .with_comment_keywords(config.scanner.comment_keywords.clone());
+
+    Ok(match scan.tls_connector(&config)? {
+        Some((connector, ssl_mode)) => scanner.with_tls(connector, ssl_mode),
+        None => scanner,
+    })
+}
+
+/// Start scanning the upstream database in the background. The response
+/// carries the ID of the job to follow the scan with.
+#[utoipa::path(
+    post, path = "/scan", tag = "scan", request_body = ScanConfig,
+    responses(
+        (status = 202, description = "Scan started", body = Object,
+            example = json!({"job_id": "0b6f...", "status": "running"})),
+        (status = 400, description = "Missing credentials or invalid request", body = Object),
+        (status = 500, description = "The scan could not be started", body = Object)
+    )
+)]
 async fn scan_database(
     State(state): State<AppState>,
     Json(config): Json<ScanConfig>,
 ) -> impl IntoResponse {
-    let scanner = DbScanner::new(
-        state.upstream_host.to_string(),
-        state.upstream_port,
-        state.db_protocol,
-    );
-
-    match scanner.scan(&config).await {
-        Ok(result) => {
-            // Log audit event
-            state
-                .audit_logger
-                .log(AuditLogger::database_scan(
-                    &config.database,
+    match start_scan_job(&state, config, false).await {
+        Ok(job_id) => (
+            StatusCode::ACCEPTED,
+            Json(json!({
+                "job_id": job_id,
+                "status": ScanJobStatus::Running
+            })),
+        ),
+        Err(e) => (
+            scan_error_status(&e),
+            Json(json!({
+                "status": "error",
+                "error": e.to_string()
+            })),
+        ),
+    }
+}
+
+/// Start a scan job, for `POST /scan` or the scan schedule. A completed scan
+/// is audited, recorded in the scan history and reported in the
+/// `ironveil_scan_findings` gauge. Returns the job ID.
+pub(crate) async fn start_scan_job(
+    state: &AppState,
+    mut config: ScanConfig,
+    scheduled: bool,
+) -> Result<String, ScanError> {
+    let scanner = upstream_scanner(state, &mut config).await?;
+
+    let (job_id, progress) = state.scan_jobs.start(&config.database);
+    let scanner = scanner.with_progress(progress);
+    let task = tokio::spawn({
+        let state = state.clone();
+        let job_id = job_id.clone();
+        async move {
+            let outcome = scanner.scan(&config).await;
+            if let Ok(result) = &outcome {
+                state
+                    .audit_logger
+                    .log(AuditLogger::database_scan(
+                        &config.database,
+                        result.findings.len(),
+                        scheduled,
+                    ))
+                    .await;
+                crate::metrics::record_scan_findings(
+                    &result.database,
+                    &result.schema,
                     result.findings.len(),
-                ))
-                .await;
-            (StatusCode::OK, Json(json!(result)))
+                );
+                let record = state.scan_history.record(&job_id, result.clone()).await;
+                if record.previous_id.is_some() && !record.new_findings.is_empty() {
+                    let columns: Vec<String> = record
+                        .new_findings
+                        .iter()
+                        .map(|f| format!("{}.{}", f.table, f.column))
+                        .collect();
+                    tracing::warn!(
+                        "Scan {} of {} found PII in new places: {}",
+                        job_id,
+                        result.database,
+                        columns.join(", ")
+                    );
+                }
+            }
+            state
+                .scan_jobs
+                .finish(&job_id, outcome.map_err(|e| e.to_string()));
         }
+    });
+    state.scan_jobs.set_abort(&job_id, task.abort_handle());
+    Ok(job_id)
+}
+
+/// Missing credentials and bad requests are the client's to fix
+fn scan_error_status(e: &ScanError) -> StatusCode {
+    match e {
+        ScanError::AuthRequired
+        | ScanError::InvalidCredentials(_)
+        | ScanError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn unknown_scan_job(job_id: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Unknown scan job '{}'", job_id) })),
+    )
+}
+
+/// Status and progress of a scan job, with the findings so far
+#[utoipa::path(
+    get, path = "/scan/{job_id}", tag = "scan",
+    params(("job_id" = String, Path, description = "Scan job ID")),
+    responses(
+        (status = 200, description = "Job status and progress", body = Object),
+        (status = 404, description = "Unknown job", body = Object)
+    )
+)]
+async fn get_scan_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match state.scan_jobs.info(&job_id) {
+        Some(info) => (StatusCode::OK, Json(json!(info))),
+        None => unknown_scan_job(&job_id),
+    }
+}
+
+/// Result of a completed scan job
+#[utoipa::path(
+    get, path = "/scan/{job_id}/result", tag = "scan",
+    params(("job_id" = String, Path, description = "Scan job ID")),
+    responses(
+        (status = 200, description = "Findings of the scan", body = Object),
+        (status = 404, description = "Unknown job", body = Object),
+        (status = 409, description = "The job has not completed", body = Object)
+    )
+)]
+async fn get_scan_result(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match state.scan_jobs.result(&job_id) {
+        Some((_, Some(result))) => (StatusCode::OK, Json(json!(result))),
+        Some((status, None)) => (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "job_id": job_id,
+                "status": status,
+                "error": "Scan has no result"
+            })),
+        ),
+        None => unknown_scan_job(&job_id),
+    }
+}
+
+/// Query parameters for paging through the scan history
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScanHistoryQuery {
+    /// Scans to skip, newest first (default: 0)
+    offset: Option<usize>,
+    /// Maximum number of scans to return (default: 20)
+    limit: Option<usize>,
+}
+
+/// Completed scans, newest first, with how their findings changed
+#[utoipa::path(
+    get, path = "/scan/history", tag = "scan", params(ScanHistoryQuery),
+    responses((status = 200, description = "A page of completed scans", body = Object))
+)]
+async fn get_scan_history(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<ScanHistoryQuery>,
+) -> Json<Value> {
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(20);
+    let (total, scans) = state.scan_history.list(offset, limit).await;
+    state
+        .audit_logger
+        .log(AuditLogger::scan_history(None, scans.len()))
+        .await;
+    Json(json!({
+        "total": total,
+        "offset": offset,
+        "limit": limit,
+        "scans": scans
+    }))
+}
+
+/// A completed scan with its findings and the diff against the previous scan
+#[utoipa::path(
+    get, path = "/scan/history/{scan_id}", tag = "scan",
+    params(("scan_id" = String, Path, description = "Scan ID")),
+    responses(
+        (status = 200, description = "The scan and its diff", body = Object),
+        (status = 404, description = "Unknown scan", body = Object)
+    )
+)]
+async fn get_scan_history_entry(
+    State(state): State<AppState>,
+    Path(scan_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let Some(record) = state.scan_history.get(&scan_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "error": format!("No scan '{}' in the history", scan_id)
+            })),
+        );
+    };
+    state
+        .audit_logger
+        .log(AuditLogger::scan_history(Some(&scan_id), 1))
+        .await;
+    (StatusCode::OK, Json(json!(record)))
+}
+
+/// Cancel a running scan job
+#[utoipa::path(
+    delete, path = "/scan/{job_id}", tag = "scan",
+    params(("job_id" = String, Path, description = "Scan job ID")),
+    responses(
+        (status = 200, description = "Job cancelled", body = Object),
+        (status = 404, description = "Unknown job", body = Object),
+        (status = 409, description = "The job has already finished", body = Object)
+    )
+)]
+async fn cancel_scan_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match state.scan_jobs.cancel(&job_id) {
+        Some(ScanJobStatus::Cancelled) => (
+            StatusCode::OK,
+            Json(json!({ "job_id": job_id, "status": ScanJobStatus::Cancelled })),
+        ),
+        Some(status) => (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "job_id": job_id,
+                "status": status,
+                "error": "Scan has already finished"
+            })),
+        ),
+        None => unknown_scan_job(&job_id),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ScanValueRequest {
+    value: String,
+    #[serde(default)]
+    table: Option<String>,
+    column: String,
+}
+
+/// Classify a value and mask it as the proxy would in the given column,
+/// without touching the upstream database
+#[utoipa::path(
+    post, path = "/scan/value", tag = "scan", request_body = ScanValueRequest,
+    responses(
+        (status = 200, description = "Detected PII type and masked value", body = Object),
+        (status = 500, description = "The value could not be masked", body = Object)
+    )
+)]
+async fn scan_value(
+    State(state): State<AppState>,
+    Json(request): Json<ScanValueRequest>,
+) -> (StatusCode, Json<Value>) {
+    let pii_types: Vec<Value> = state
+        .scanner()
+        .scan_ranked(&request.value)
+        .into_iter()
+        .map(|(pii_type, confidence)| {
+            json!({ "pii_type": pii_type.to_string(), "confidence": confidence })
+        })
+        .collect();
+
+    let config = state.config.read().await;
+    match preview_masking(
+        &config,
+        &state,
+        request.table.as_deref(),
+        &request.column,
+        &request.value,
+    ) {
+        Ok(preview) => (
+            StatusCode::OK,
+            Json(json!({
+                "pii_types": pii_types,
+                "rule": redacted_json(&preview.rule),
+                "strategy": preview.strategy,
+                "masked": preview.masked,
+            })),
+        ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({
                 "status": "error",
-                "error": e.to_string()
+                "error": format!("Masking failed: {:#}", e)
             })),
         ),
     }
 }
 
+/// Get active connections with their session details (user, database, application)
+#[utoipa::path(
+    get, path = "/connections", tag = "stats",
+    responses((status = 200, description = "Live connections", body = Object))
+)]
 async fn get_connections(State(state): State<AppState>) -> Json<Value> {
     let count = state.active_connections.load(Ordering::Relaxed);
+    let connections = state.list_connections().await;
+    Json(json!({
+        "active_connections": count,
+        "connections": connections
+    }))
+}
+
+/// Every live connection, oldest first, with its session and what it has
+/// queried and had masked so far
+#[utoipa::path(
+    get, path = "/connections/list", tag = "stats",
+    responses((status = 200, description = "The connection registry", body = Object,
+        example = json!({"count": 1, "connections": [{"connection_id": 42, "protocol": "Postgres",
+            "connected_at": "2025-01-01T00:00:00Z", "user": "alice", "database": "shop",
+            "application_name": "psql", "client_cn": null, "client_addr": "10.0.0.7",
+            "policy": null, "queries": 12, "masked_fields": 30}]})))
+)]
+async fn list_connections(State(state): State<AppState>) -> Json<Value> {
+    let connections = state.list_connections().await;
     Json(json!({
-        "active_connections": count
+        "count": connections.len(),
+        "connections": connections
     }))
 }
 
-/// Get application statistics (queries, masking, connections)
-async fn get_stats(State(state): State<AppState>) -> Json<Value> {
+/// How long a kill waits for the connection to leave the registry
+const KILL_WAIT: Duration = Duration::from_secs(1);
+
+/// Close a proxied connection: both its client and upstream sockets are
+/// dropped, without the grace period a shutdown gives in-flight queries
+#[utoipa::path(
+    post, path = "/connections/{id}/kill", tag = "stats",
+    params(("id" = usize, Path, description = "Connection ID, from `/connections/list`")),
+    responses(
+        (status = 200, description = "Connection closed", body = Object,
+            example = json!({"status": "success", "connection_id": 42, "closed": true})),
+        (status = 202, description = "Signalled, but still closing after a second", body = Object),
+        (status = 404, description = "No live connection has this ID", body = Object)
+    )
+)]
+async fn kill_connection(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    user: Option<Extension<AuthUser>>,
+    Path(id): Path<usize>,
+) -> (StatusCode, Json<Value>) {
+    let Some(connection) = state.kill_connection(id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "error": format!("No live connection with ID {}", id)
+            })),
+        );
+    };
+
+    let user_id = user.map(|Extension(AuthUser(user_id))| user_id);
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::connection_killed(user_id, &connection)))
+        .await;
+
+    let deadline = tokio::time::Instant::now() + KILL_WAIT;
+    let closed = loop {
+        if !state.connections.read().await.contains_key(&id) {
+            break true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            break false;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    let status = if closed {
+        StatusCode::OK
+    } else {
+        StatusCode::ACCEPTED
+    };
+    (
+        status,
+        Json(json!({ "status": "success", "connection_id": id, "closed": closed })),
+    )
+}
+
+/// Query parameters for `GET /stats`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StatsQuery {
+    /// Also count what happened in the last `15m`, `2h`, `1d`..., up to a day
+    window: Option<String>,
+}
+
+/// Minutes in a `/stats` window such as `15m`, `2h` or `1d`
+fn parse_stats_window(window: &str) -> Option<i64> {
+    let minutes = if let Some(count) = window.strip_suffix('m') {
+        count.parse::<i64>().ok()?
+    } else if let Some(count) = window.strip_suffix('h') {
+        count.parse::<i64>().ok()?.checked_mul(60)?
+    } else if let Some(count) = window.strip_suffix('d') {
+        count.parse::<i64>().ok()?.checked_mul(24 * 60)?
+    } else {
+        return None;
+    };
+    (1..=MAX_WINDOW_MINUTES)
+        .contains(&minutes)
+        .then_some(minutes)
+}
+
+fn masking_json(masking: &MaskingStats) -> Value {
+    json!({
+        "email": masking.email,
+        "phone": masking.phone,
+        "address": masking.address,
+        "name": masking.name,
+        "company": masking.company,
+        "credit_card": masking.credit_card,
+        "ssn": masking.ssn,
+        "ip": masking.ip,
+        "dob": masking.dob,
+        "passport": masking.passport,
+        "iban": masking.iban,
+        "nino": masking.nino,
+        "tax_id": masking.tax_id,
+        "hash": masking.hash,
+        "json": masking.json,
+        "null": masking.null,
+        "redact": masking.redact,
+        "tokenize": masking.tokenize,
+        "numeric_noise": masking.numeric_noise,
+        "other": masking.other,
+        "total": masking.total()
+    })
+}
+
+fn queries_json(queries: &QueryStats) -> Value {
+    json!({
+        "total": queries.total_queries,
+        "select": queries.select_count,
+        "insert": queries.insert_count,
+        "update": queries.update_count,
+        "delete": queries.delete_count,
+        "other": queries.other_count
+    })
+}
+
+/// Get application statistics (queries, masking, connections) since start
+/// or the last reset and, with `window`, over the last minutes or hours
+#[utoipa::path(
+    get, path = "/stats", tag = "stats", params(StatsQuery),
+    responses(
+        (status = 200, description = "Counters and connection history", body = Object,
+            example = json!({"active_connections": 3, "total_connections": 120,
+                "masking": {"email": 42, "total": 57}, "by_column": {"entries": [], "other": 0},
+                "queries": {"total": 900, "select": 850, "insert": 20, "update": 20, "delete": 5, "other": 5},
+                "copy_in": {"rows_inspected": 0, "rows_flagged": 0}, "history": [],
+                "window": {"duration": "1h", "since": "2025-01-01T09:01:00Z", "connections": 4,
+                    "masking": {"email": 7, "total": 9}, "queries": {"total": 80, "select": 80},
+                    "copy_in": {"rows_inspected": 0, "rows_flagged": 0}}})),
+        (status = 400, description = "Invalid window", body = Object)
+    )
+)]
+async fn get_stats(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<StatsQuery>,
+) -> (StatusCode, Json<Value>) {
+    let window = match query.window.as_deref().map(|w| (w, parse_stats_window(w))) {
+        None => None,
+        Some((window, Some(minutes))) => Some((window, state.get_windowed_stats(minutes).await)),
+        Some((window, None)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "status": "error",
+                    "error": format!(
+                        "Invalid window '{}': expected minutes, hours or days up to a day, e.g. 15m or 1h",
+                        window
+                    )
+                })),
+            );
+        }
+    };
     let stats = state.get_stats().await;
     let history = state.get_connection_history().await;
     let active_connections = state.active_connections.load(Ordering::Relaxed);
 
-    Json(json!({
+    let mut response = json!({
         "active_connections": active_connections,
         "total_connections": stats.total_connections,
-        "masking": {
-            "email": stats.masking.email,
-            "phone": stats.masking.phone,
-            "address": stats.masking.address,
-            "credit_card": stats.masking.credit_card,
-            "ssn": stats.masking.ssn,
-            "ip": stats.masking.ip,
-            "dob": stats.masking.dob,
-            "passport": stats.masking.passport,
-            "hash": stats.masking.hash,
-            "json": stats.masking.json,
-            "other": stats.masking.other,
-            "total": stats.masking.total()
-        },
-        "queries": {
-            "total": stats.queries.total_queries,
-            "select": stats.queries.select_count,
-            "insert": stats.queries.insert_count,
-            "update": stats.queries.update_count,
-            "delete": stats.queries.delete_count,
-            "other": stats.queries.other_count
+        "masking": masking_json(&stats.masking),
+        "by_column": stats.by_column,
+        "masking_cache": state.masking_cache.as_ref().map(|cache| cache.stats()),
+        "queries": queries_json(&stats.queries),
+        "copy_in": {
+            "rows_inspected": stats.copy_in.rows_inspected,
+            "rows_flagged": stats.copy_in.rows_flagged
         },
         "history": history.iter().map(|p| json!({
             "timestamp": p.timestamp.to_rfc3339(),
@@ -574,18 +2388,103 @@ async fn get_stats(State(state): State<AppState>) -> Json<Value> {
             "total_queries": p.total_queries,
             "total_masked": p.total_masked
         })).collect::<Vec<_>>()
+    });
+    if let Some((duration, bucket)) = window {
+        response["window"] = json!({
+            "duration": duration,
+            "since": bucket.minute.to_rfc3339(),
+            "connections": bucket.connections,
+            "masking": masking_json(&bucket.masking),
+            "queries": queries_json(&bucket.queries),
+            "copy_in": {
+                "rows_inspected": bucket.copy_in.rows_inspected,
+                "rows_flagged": bucket.copy_in.rows_flagged
+            }
+        });
+    }
+    (StatusCode::OK, Json(response))
+}
+
+/// Zero every counter, the per-minute window and the connection history.
+/// Per-connection counts in `/connections/list` are kept.
+#[utoipa::path(
+    post, path = "/stats/reset", tag = "stats",
+    responses((status = 200, description = "Counters reset", body = Object,
+        example = json!({"status": "success", "cleared": {"queries": 900, "masked": 57, "connections": 120}})))
+)]
+async fn reset_stats(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    user: Option<Extension<AuthUser>>,
+) -> Json<Value> {
+    let previous = state.reset_stats().await;
+    let cleared = json!({
+        "queries": previous.queries.total_queries,
+        "masked": previous.masking.total(),
+        "connections": previous.total_connections
+    });
+
+    let user_id = user.map(|Extension(AuthUser(user_id))| user_id);
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::stats_reset(user_id, cleared.clone())))
+        .await;
+
+    Json(json!({
+        "status": "success",
+        "cleared": cleared
+    }))
+}
+
+/// Clear the per-column masking counters
+#[utoipa::path(
+    post, path = "/stats/columns/reset", tag = "stats",
+    responses((status = 200, description = "Counters cleared", body = Object))
+)]
+async fn reset_column_stats(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    user: Option<Extension<AuthUser>>,
+) -> Json<Value> {
+    let cleared = state.reset_column_stats().await;
+
+    let user_id = user.map(|Extension(AuthUser(user_id))| user_id);
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::column_stats_reset(user_id, cleared)))
+        .await;
+
+    Json(json!({
+        "status": "success",
+        "cleared": cleared
     }))
 }
 
+/// Tables and columns of the upstream database
+#[utoipa::path(
+    post, path = "/schema", tag = "scan", request_body = ScanConfig,
+    responses(
+        (status = 200, description = "Tables with their columns", body = Object),
+        (status = 400, description = "Missing credentials or invalid request", body = Object),
+        (status = 500, description = "The schema could not be read", body = Object)
+    )
+)]
 async fn get_schema(
     State(state): State<AppState>,
-    Json(config): Json<ScanConfig>,
+    Json(mut config): Json<ScanConfig>,
 ) -> impl IntoResponse {
-    let scanner = DbScanner::new(
-        state.upstream_host.to_string(),
-        state.upstream_port,
-        state.db_protocol,
-    );
+    let scanner = match upstream_scanner(&state, &mut config).await {
+        Ok(scanner) => scanner,
+        Err(e) => {
+            return (
+                scan_error_status(&e),
+                Json(json!({
+                    "status": "error",
+                    "error": e.to_string()
+                })),
+            );
+        }
+    };
 
     match scanner.get_schema(&config).await {
         Ok(schema) => {
@@ -609,344 +2508,2805 @@ async fn get_schema(
     }
 }
 
-async fn get_logs(State(state): State<AppState>) -> Json<Value> {
+/// Query parameters filtering `/logs` and `/logs/stream`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LogQuery {
+    event_type: Option<String>,
+    connection_id: Option<usize>,
+    user: Option<String>,
+    database: Option<String>,
+}
+
+impl LogQuery {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.event_type
+            .as_ref()
+            .is_none_or(|t| *t == entry.event_type)
+            && self
+                .connection_id
+                .is_none_or(|id| id == entry.connection_id)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|u| entry.user.as_ref() == Some(u))
+            && self
+                .database
+                .as_ref()
+                .is_none_or(|d| entry.database.as_ref() == Some(d))
+    }
+}
+
+/// Recent log entries, newest first
+#[utoipa::path(
+    get, path = "/logs", tag = "logs", params(LogQuery),
+    responses((status = 200, description = "Matching log entries", body = Object))
+)]
+async fn get_logs(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<LogQuery>,
+) -> Json<Value> {
     let logs = state.logs.read().await;
-    Json(json!({
-        "logs": *logs
-    }))
+    let logs: Vec<&LogEntry> = logs.iter().filter(|entry| query.matches(entry)).collect();
+    Json(json!({ "logs": logs }))
+}
+
+/// Log entries as Server-Sent Events, as they are added. A subscriber that
+/// falls more than `LOG_STREAM_CAPACITY` entries behind skips ahead and is
+/// sent a `lagged` event with the number of entries it missed.
+#[utoipa::path(
+    get, path = "/logs/stream", tag = "logs", params(LogQuery),
+    responses((status = 200, description = "`log` events with an entry each, and `lagged` events", body = String, content_type = "text/event-stream"))
+)]
+async fn stream_logs(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<LogQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.log_events.subscribe();
+    let events = futures::stream::unfold((receiver, query), |(mut receiver, query)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(entry) if query.matches(&entry) => Event::default()
+                    .event("log")
+                    .json_data(&entry)
+                    .unwrap_or_default(),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Event::default().event("lagged").data(missed.to_string())
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (receiver, query)));
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
 }
 
 /// Query parameters for audit log retrieval
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct AuditQuery {
     /// Maximum number of entries to return
     limit: Option<usize>,
-    /// Filter by event type
-    event_type: Option<String>,
-    /// Filter by outcome
-    outcome: Option<String>,
+    /// Matching entries to skip, for paging
+    #[serde(default)]
+    offset: usize,
+    event_type: Option<AuditEventType>,
+    outcome: Option<AuditOutcome>,
+    /// RFC 3339 timestamps bounding the entries, `to` exclusive
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    /// Text the endpoint contains
+    endpoint: Option<String>,
+    user_id: Option<String>,
 }
 
-/// Get audit logs with optional filtering
+/// Get audit logs, newest first, matching every filter given
+#[utoipa::path(
+    get, path = "/audit", tag = "audit", params(AuditQuery),
+    responses((status = 200, description = "A page of matching audit entries", body = Object))
+)]
 async fn get_audit_logs(
     State(state): State<AppState>,
     axum::extract::Query(query): axum::extract::Query<AuditQuery>,
 ) -> Json<Value> {
     let limit = query.limit.unwrap_or(100);
-
-    let entries = if let Some(event_type) = query.event_type {
-        // Parse event type
-        let event = match event_type.as_str() {
-            "auth_attempt" => Some(AuditEventType::AuthAttempt),
-            "config_change" => Some(AuditEventType::ConfigChange),
-            "rule_added" => Some(AuditEventType::RuleAdded),
-            "rule_deleted" => Some(AuditEventType::RuleDeleted),
-            "rules_imported" => Some(AuditEventType::RulesImported),
-            "config_reload" => Some(AuditEventType::ConfigReload),
-            "database_scan" => Some(AuditEventType::DatabaseScan),
-            "schema_query" => Some(AuditEventType::SchemaQuery),
-            "api_access" => Some(AuditEventType::ApiAccess),
-            _ => None,
-        };
-        if let Some(e) = event {
-            state.audit_logger.get_entries_by_type(e, Some(limit)).await
-        } else {
-            state.audit_logger.get_entries(Some(limit)).await
-        }
-    } else if let Some(outcome) = query.outcome {
-        // Parse outcome
-        let out = match outcome.as_str() {
-            "success" => Some(AuditOutcome::Success),
-            "failure" => Some(AuditOutcome::Failure),
-            "denied" => Some(AuditOutcome::Denied),
-            _ => None,
-        };
-        if let Some(o) = out {
-            state
-                .audit_logger
-                .get_entries_by_outcome(o, Some(limit))
-                .await
-        } else {
-            state.audit_logger.get_entries(Some(limit)).await
-        }
-    } else {
-        state.audit_logger.get_entries(Some(limit)).await
+    let filter = AuditFilter {
+        event_type: query.event_type,
+        outcome: query.outcome,
+        from: query.from,
+        to: query.to,
+        endpoint: query.endpoint,
+        user_id: query.user_id,
     };
+    let (total, entries) = state.audit_logger.query(&filter, query.offset, limit).await;
 
     Json(json!({
         "count": entries.len(),
+        "total": total,
+        "offset": query.offset,
+        "limit": limit,
+        "filters": filter,
         "entries": entries
     }))
 }
 
-/// Prometheus metrics endpoint
-async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
-    match &state.metrics_handle {
-        Some(handle) => {
-            let metrics = handle.render();
-            (
-                StatusCode::OK,
-                [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
-                metrics,
-            )
-        }
-        None => (
-            StatusCode::SERVICE_UNAVAILABLE,
-            [("content-type", "text/plain; charset=utf-8")],
-            "Metrics not enabled".to_string(),
-        ),
-    }
+/// Query parameters of an audit export
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditExportQuery {
+    #[serde(default)]
+    format: AuditExportFormat,
+    event_type: Option<AuditEventType>,
+    outcome: Option<AuditOutcome>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    endpoint: Option<String>,
+    user_id: Option<String>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::{ApiConfig, AppConfig};
-    use axum::extract::State;
+/// Download the audit entries matching the filters, oldest first, as JSON
+/// lines or CSV. The export itself is audited.
+#[utoipa::path(
+    get, path = "/audit/export", tag = "audit", params(AuditExportQuery),
+    responses((status = 200, description = "Matching audit entries as JSON lines or CSV", 
+        content((String = "application/x-ndjson"), (String = "text/csv"))))
+)]
+async fn export_audit_logs(
+    State(state): State<AppState>,
+    identity: Option<Extension<JwtIdentity>>,
+    axum::extract::Query(query): axum::extract::Query<AuditExportQuery>,
+) -> Response {
+    let filter = AuditFilter {
+        event_type: query.event_type,
+        outcome: query.outcome,
+        from: query.from,
+        to: query.to,
+        endpoint: query.endpoint,
+        user_id: query.user_id,
+    };
+    let user_id = identity.map(|Extension(id)| id.subject);
+    state
+        .audit_logger
+        .log(AuditLogger::audit_export(user_id, query.format, &filter))
+        .await;
+
+    let chunks = state.audit_logger.export(filter, query.format).await;
+    let body = futures::stream::unfold(chunks, |mut chunks| async move {
+        chunks.recv().await.map(|chunk| (chunk, chunks))
+    });
+    let filename = format!(
+        "attachment; filename=\"ironveil-audit-{}.{}\"",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        query.format.extension()
+    );
+    (
+        StatusCode::OK,
+        [
+            ("content-type", query.format.content_type().to_string()),
+            ("content-disposition", filename),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct DetokenizeRequest {
+    token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateApiKeyRequest {
+    name: String,
+    /// Defaults to `api.default_scopes`
+    #[serde(default)]
+    scopes: Option<Vec<String>>,
+}
+
+/// Generate a named API key. The key is returned this once; only its hash is
+/// kept in the config.
+#[utoipa::path(
+    post, path = "/apikeys", tag = "api keys", request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "Key created; `key` is not shown again", body = Object,
+            example = json!({"status": "success", "name": "ci", "key": "ivk_...", "scopes": ["rules:read"]})),
+        (status = 400, description = "Empty name or unknown scope", body = Object),
+        (status = 409, description = "The name is taken", body = Object),
+        (status = 500, description = "The key could not be saved", body = Object)
+    )
+)]
+async fn create_api_key(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    user: Option<Extension<AuthUser>>,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> (StatusCode, Json<Value>) {
+    if request.name.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "status": "error", "error": "API key name must not be empty" })),
+        );
+    }
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .flatten()
+        .find(|scope| !API_SCOPES.contains(&scope.as_str()))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "error": format!("Unknown API scope '{}'", scope)
+            })),
+        );
+    }
+
+    let mut bytes = [0u8; 32];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "status": "error", "error": "Failed to generate API key" })),
+        );
+    }
+    let key = format!("ivk_{}", BASE64_URL.encode(bytes));
+
+    let mut config = state.config.write().await;
+    let api = config.api.get_or_insert_with(ApiConfig::default);
+    if api.keys.iter().any(|k| k.name == request.name) {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "status": "error",
+                "error": format!("An API key named '{}' already exists", request.name)
+            })),
+        );
+    }
+    let scopes = request.scopes.unwrap_or_else(|| api.default_scopes.clone());
+    api.keys.push(ApiKeyConfig {
+        name: request.name.clone(),
+        key_hash: hash_api_key(&key),
+        scopes: scopes.clone(),
+    });
+    state.config_changed();
+    drop(config);
+
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist API key: {}", e)
+            })),
+        );
+    }
+
+    let user_id = user.map(|Extension(AuthUser(user_id))| user_id);
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::api_key_created(
+            user_id,
+            &request.name,
+            &scopes,
+        )))
+        .await;
+
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "status": "success",
+            "name": request.name,
+            "key": key,
+            "scopes": scopes
+        })),
+    )
+}
+
+/// Remove a named API key, so that it is refused from the next request on
+#[utoipa::path(
+    delete, path = "/apikeys/{name}", tag = "api keys",
+    params(("name" = String, Path, description = "Key name")),
+    responses(
+        (status = 200, description = "Key revoked", body = Object),
+        (status = 404, description = "No key has this name", body = Object),
+        (status = 500, description = "The change could not be saved", body = Object)
+    )
+)]
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    user: Option<Extension<AuthUser>>,
+    Path(name): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let mut config = state.config.write().await;
+    let Some(api) = config.api.as_mut() else {
+        return api_key_not_found(&name);
+    };
+    let Some(index) = api.keys.iter().position(|k| k.name == name) else {
+        return api_key_not_found(&name);
+    };
+    api.keys.remove(index);
+    state.config_changed();
+    drop(config);
+
+    if let Err(e) = state.save_config().await {
+        tracing::error!("Failed to save config: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "error": format!("Failed to persist API key removal: {}", e)
+            })),
+        );
+    }
+
+    let user_id = user.map(|Extension(AuthUser(user_id))| user_id);
+    state
+        .audit_logger
+        .log(client_ip.tag(AuditLogger::api_key_revoked(user_id, &name)))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({ "status": "success", "name": name })),
+    )
+}
+
+fn api_key_not_found(name: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "status": "error",
+            "error": format!("No API key named '{}'", name)
+        })),
+    )
+}
+
+/// Resolve a `tokenize` token back to its original value. Requires a JWT
+/// carrying the `detokenize` scope; every attempt is audited.
+#[utoipa::path(
+    post, path = "/detokenize", tag = "tokens", request_body = DetokenizeRequest,
+    responses(
+        (status = 200, description = "The original value", body = Object),
+        (status = 404, description = "Unknown token", body = Object),
+        (status = 503, description = "Tokenization is not configured", body = Object)
+    )
+)]
+async fn detokenize(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    identity: Option<Extension<JwtIdentity>>,
+    Json(request): Json<DetokenizeRequest>,
+) -> (StatusCode, Json<Value>) {
+    let user_id = identity.as_ref().map(|Extension(id)| id.subject.clone());
+
+    if !identity.is_some_and(|Extension(id)| id.has_scope(DETOKENIZE_SCOPE)) {
+        state
+            .audit_logger
+            .log(
+                client_ip.tag(
+                    AuditLogger::detokenize(user_id, &request.token, AuditOutcome::Denied)
+                        .with_error(format!("Missing '{}' scope", DETOKENIZE_SCOPE)),
+                ),
+            )
+            .await;
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": format!("A JWT with the '{}' scope is required", DETOKENIZE_SCOPE)
+            })),
+        );
+    }
+
+    let Some(vault) = &state.token_vault else {
+        state
+            .audit_logger
+            .log(
+                client_ip.tag(
+                    AuditLogger::detokenize(user_id, &request.token, AuditOutcome::Failure)
+                        .with_error("Tokenization not configured"),
+                ),
+            )
+            .await;
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "Tokenization not configured" })),
+        );
+    };
+
+    match vault.detokenize(&request.token) {
+        Some(value) => {
+            state
+                .audit_logger
+                .log(client_ip.tag(AuditLogger::detokenize(
+                    user_id,
+                    &request.token,
+                    AuditOutcome::Success,
+                )))
+                .await;
+            (
+                StatusCode::OK,
+                Json(json!({ "token": request.token, "value": value })),
+            )
+        }
+        None => {
+            state
+                .audit_logger
+                .log(
+                    client_ip.tag(
+                        AuditLogger::detokenize(user_id, &request.token, AuditOutcome::Failure)
+                            .with_error("Unknown token"),
+                    ),
+                )
+                .await;
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "Unknown token" })),
+            )
+        }
+    }
+}
+
+/// This document
+#[utoipa::path(
+    get, path = "/openapi.json", tag = "docs",
+    responses((status = 200, description = "OpenAPI 3 document of the API", body = Object))
+)]
+async fn get_openapi(
+    Extension(openapi): Extension<Arc<utoipa::openapi::OpenApi>>,
+) -> Json<utoipa::openapi::OpenApi> {
+    Json(openapi.as_ref().clone())
+}
+
+/// Swagger UI page for `/openapi.json`. A `?token=` the page was opened with
+/// is passed on to fetch the document.
+#[utoipa::path(
+    get, path = "/docs", tag = "docs",
+    responses((status = 200, description = "Swagger UI", body = String, content_type = "text/html"))
+)]
+async fn get_docs() -> Html<&'static str> {
+    Html(SWAGGER_UI_PAGE)
+}
+
+const SWAGGER_UI_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>IronVeil Management API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({
+      url: "openapi.json" + window.location.search,
+      dom_id: "#swagger-ui",
+    });
+  </script>
+</body>
+</html>
+"##;
+
+/// Prometheus metrics endpoint
+#[utoipa::path(
+    get, path = "/metrics", tag = "system",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain"),
+        (status = 503, description = "Metrics are not enabled", body = Object)
+    )
+)]
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    match &state.metrics_handle {
+        Some(handle) => {
+            let metrics = handle.render();
+            (
+                StatusCode::OK,
+                [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
+                metrics,
+            )
+        }
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [("content-type", "text/plain; charset=utf-8")],
+            "Metrics not enabled".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MaskStrategy;
+    use crate::config::{ApiConfig, AppConfig};
+    use crate::state::{ConnectionInfo, DbProtocol};
+    use axum::extract::Query;
+    use axum::extract::State;
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let config = AppConfig::default();
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let response = health_check(State(state)).await;
+        let (status, _json) = response.into_response().into_parts();
+
+        // For default state (healthy), we should get 200 OK
+        assert_eq!(status.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_health_probes() {
+        let config = AppConfig {
+            health_check: Some(crate::config::HealthCheckConfig {
+                ready_max_connections: Some(2),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        assert_eq!(health_live().await.0["status"], "alive");
+
+        // Not ready before the first successful probe
+        let (status, Json(body)) = health_ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reasons"].as_array().unwrap().len(), 1);
+
+        state.update_health_status(true, Some(1), None).await;
+        let (status, Json(body)) = health_ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["connections"]["max"], 2);
+
+        state.active_connections.store(2, Ordering::Relaxed);
+        let (status, Json(body)) = health_ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(
+            body["reasons"][0]
+                .as_str()
+                .unwrap()
+                .starts_with("Saturated: 2 of 2")
+        );
+        state.active_connections.store(0, Ordering::Relaxed);
+
+        for _ in 0..3 {
+            state
+                .update_health_status(false, None, Some("Connection refused".to_string()))
+                .await;
+        }
+        let (status, Json(body)) = health_ready(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["reasons"][0],
+            "Upstream is unhealthy: Connection refused"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_key_config_parsing() {
+        // Test that API key is correctly parsed from config
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("my-secret-key".to_string()),
+                keys: vec![],
+                jwt_secret: None,
+                stats_history_interval_secs: 10,
+                bind_address: None,
+                tls: None,
+                default_scopes: crate::config::default_api_scopes(),
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let config_guard = state.config.read().await;
+
+        let required_key = config_guard
+            .api
+            .as_ref()
+            .and_then(|api| api.api_key.as_ref());
+
+        assert_eq!(required_key, Some(&"my-secret-key".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_api_key_none_when_not_configured() {
+        // Test that no API key means None
+        let config = AppConfig::default();
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let config_guard = state.config.read().await;
+
+        let required_key = config_guard
+            .api
+            .as_ref()
+            .and_then(|api| api.api_key.as_ref());
+
+        assert_eq!(required_key, None);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_validation_valid_token() {
+        use jsonwebtoken::{EncodingKey, Header, encode};
+
+        let secret = "test-jwt-secret";
+        let claims = Claims {
+            sub: "test-user".to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: chrono::Utc::now().timestamp() as usize,
+            scope: String::new(),
+            roles: vec![],
+        };
+
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+
+        let result = validate_jwt(&token, secret);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().sub, "test-user");
+    }
+
+    #[tokio::test]
+    async fn test_jwt_validation_expired_token() {
+        use jsonwebtoken::{EncodingKey, Header, encode};
+
+        let secret = "test-jwt-secret";
+        let claims = Claims {
+            sub: "test-user".to_string(),
+            exp: (chrono::Utc::now() - chrono::Duration::hours(1)).timestamp() as usize,
+            iat: (chrono::Utc::now() - chrono::Duration::hours(2)).timestamp() as usize,
+            scope: String::new(),
+            roles: vec![],
+        };
+
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+
+        let result = validate_jwt(&token, secret);
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jwt_validation_wrong_secret() {
+        use jsonwebtoken::{EncodingKey, Header, encode};
+
+        let claims = Claims {
+            sub: "test-user".to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: chrono::Utc::now().timestamp() as usize,
+            scope: String::new(),
+            roles: vec![],
+        };
+
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"correct-secret"),
+        )
+        .unwrap();
+
+        let result = validate_jwt(&token, "wrong-secret");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jwt_config_parsing() {
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: None,
+                keys: vec![],
+                jwt_secret: Some("my-jwt-secret".to_string()),
+                stats_history_interval_secs: 10,
+                bind_address: None,
+                tls: None,
+                default_scopes: crate::config::default_api_scopes(),
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let config_guard = state.config.read().await;
+
+        let jwt_secret = config_guard
+            .api
+            .as_ref()
+            .and_then(|api| api.jwt_secret.as_ref());
+
+        assert_eq!(jwt_secret, Some(&"my-jwt-secret".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_config() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: Some("users".parse().unwrap()),
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+
+        let response = get_config(State(state)).await;
+        let json = response.0;
+
+        assert_eq!(json["masking_enabled"], true);
+        assert_eq!(json["rules_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+
+        let payload = json!({ "masking_enabled": false });
+        let (status, Json(json)) =
+            update_config(State(state.clone()), ClientIp::default(), Json(payload)).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["status"], "success");
+        assert_eq!(json["masking_enabled"], false);
+
+        // Verify state was actually updated, and saved
+        let config = state.config.read().await;
+        assert!(!config.masking_enabled);
+        assert!(
+            !AppConfig::load(path.to_str().unwrap())
+                .unwrap()
+                .masking_enabled
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_config_masking_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let state =
+            AppState::new_for_test(AppConfig::default(), path.to_str().unwrap().to_string());
+
+        let payload = json!({ "masking_mode": "rules_only" });
+        let (status, Json(json)) =
+            update_config(State(state.clone()), ClientIp::default(), Json(payload)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["masking_mode"], "rules_only");
+        assert_eq!(
+            state.config.read().await.masking_mode,
+            MaskingMode::RulesOnly
+        );
+
+        let payload = json!({ "masking_mode": "sometimes" });
+        let (status, _) =
+            update_config(State(state.clone()), ClientIp::default(), Json(payload)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            state.config.read().await.masking_mode,
+            MaskingMode::RulesOnly
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_config_limits_and_upstream_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let state =
+            AppState::new_for_test(AppConfig::default(), path.to_str().unwrap().to_string());
+        let update = |payload: Value| {
+            update_config(State(state.clone()), ClientIp::default(), Json(payload))
+        };
+
+        let (status, Json(json)) = update(json!({
+            "limits": { "max_connections": 2, "connections_per_second": 50 },
+            "upstream_tls": true
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["limits"]["max_connections"], 2);
+        assert_eq!(json["upstream_tls"], true);
+        let limits = state.config.read().await.limits.clone().unwrap();
+        assert_eq!(limits.connections_per_second, Some(50));
+        // Fields not sent keep their defaults
+        assert_eq!(limits.idle_timeout_secs, 300);
+
+        // null lifts a limit, leaving the other one alone
+        let (_, Json(json)) = update(json!({ "limits": { "connections_per_second": null } })).await;
+        assert_eq!(json["limits"]["max_connections"], 2);
+        assert_eq!(json["limits"]["connections_per_second"], Value::Null);
+
+        // Each invalid update is refused whole
+        for payload in [
+            json!({ "limits": { "max_connections": 0 } }),
+            json!({ "limits": { "max_connections": -1 } }),
+            json!({ "limits": { "max_connections": "lots" } }),
+            json!({ "limits": { "idle_timeout_secs": 5 } }),
+            json!({ "masking_enabled": false, "upstream_host": "elsewhere" }),
+            json!({ "upstream_tls": "yes" }),
+        ] {
+            let (status, _) = update(payload.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", payload);
+        }
+        let config = state.config.read().await;
+        assert!(config.masking_enabled);
+        assert_eq!(config.limits.as_ref().unwrap().max_connections, Some(2));
+        drop(config);
+
+        let saved = AppConfig::load(path.to_str().unwrap()).unwrap();
+        assert!(saved.upstream_tls);
+        assert_eq!(saved.limits.unwrap().connections_per_second, None);
+
+        let (_, entries) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        let changes: Vec<&Value> = entries
+            .iter()
+            .filter(|e| e.event_type == AuditEventType::ConfigChange)
+            .filter_map(|e| e.details.as_ref())
+            .collect();
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().any(|details| {
+            details["limits.max_connections"] == json!({ "old": null, "new": 2 })
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_add_rule() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "/tmp/test_proxy.yaml".to_string());
+
+        // Create temp file so save works
+        std::fs::write("/tmp/test_proxy.yaml", "rules: []").ok();
+
+        let new_rule = json!({ "table": "users", "column": "phone", "strategy": "phone" });
+
+        // Call add_rule and verify rule was added to state
+        let _ = add_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(RuleWriteQuery::default()),
+            Json(new_rule.clone()),
+        )
+        .await;
+
+        // Verify rule was added
+        {
+            let config = state.config.read().await;
+            assert_eq!(config.rules.len(), 1);
+            assert_eq!(config.rules[0].column, "phone");
+        }
+
+        // The same table and column again is a conflict, unless allowed
+        let response = add_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(RuleWriteQuery::default()),
+            Json(new_rule.clone()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let allow = RuleWriteQuery {
+            allow_duplicates: true,
+        };
+        let response = add_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(allow),
+            Json(new_rule),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.config.read().await.rules.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_add_rule_rejects_unknown_strategy() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        for rule in [
+            json!({ "column": "email", "strategy": "emial" }),
+            json!({ "column": "", "strategy": "email" }),
+            json!({ "table": " ", "column": "email", "strategy": "email" }),
+        ] {
+            let response = add_rule(
+                State(state.clone()),
+                ClientIp::default(),
+                Query(RuleWriteQuery::default()),
+                Json(rule),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(state.config.read().await.rules.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_rules_reports_every_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let config = AppConfig {
+            rules: vec![
+                serde_json::from_value(json!({ "column": "email", "strategy": "email" })).unwrap(),
+            ],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+
+        let rules = vec![
+            json!({ "column": "phone", "strategy": "phone" }),
+            json!({ "column": "ssn", "strategy": "emial" }),
+            json!({ "column": "re:[", "strategy": "redact" }),
+            json!({ "column": "email", "strategy": "hash" }),
+            json!({ "column": "phone", "strategy": "redact" }),
+        ];
+        let response = import_rules(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(ImportQuery::default()),
+            Json(rules.clone()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let indexes: Vec<u64> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(indexes, vec![1, 2, 3, 4]);
+        assert!(
+            body["errors"][0]["error"]
+                .as_str()
+                .unwrap()
+                .contains("emial")
+        );
+        assert_eq!(state.config.read().await.rules.len(), 1);
+
+        // Duplicates may be allowed, but invalid rules never are
+        let allow = || {
+            Query(ImportQuery {
+                allow_duplicates: true,
+                ..Default::default()
+            })
+        };
+        let response = import_rules(
+            State(state.clone()),
+            ClientIp::default(),
+            allow(),
+            Json(rules),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let rules = vec![
+            json!({ "column": "email", "strategy": "hash" }),
+            json!({ "column": "phone", "strategy": "phone" }),
+        ];
+        let response = import_rules(
+            State(state.clone()),
+            ClientIp::default(),
+            allow(),
+            Json(rules),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.config.read().await.rules.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_import_rules_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let config = AppConfig {
+            rules: vec![
+                serde_json::from_value(json!({ "column": "email", "strategy": "email" })).unwrap(),
+                serde_json::from_value(
+                    json!({ "table": "users", "column": "phone", "strategy": "phone" }),
+                )
+                .unwrap(),
+            ],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+        let import = |mode: ImportMode, rules: Vec<Value>| {
+            let state = state.clone();
+            async move {
+                let response = import_rules(
+                    State(state),
+                    ClientIp::default(),
+                    Query(ImportQuery {
+                        mode,
+                        allow_duplicates: false,
+                    }),
+                    Json(rules),
+                )
+                .await
+                .into_response();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+        let exported = serde_json::to_value(&state.config.read().await.rules).unwrap();
+        let email_id = exported[0]["id"].clone();
+
+        // Re-importing an export with merge changes nothing
+        let (status, body) = import(ImportMode::Merge, exported.as_array().unwrap().clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&body["added"], &body["updated"], &body["skipped"]),
+            (&json!(0), &json!(0), &json!(2))
+        );
+        assert_eq!(state.config.read().await.rules.len(), 2);
+
+        let (status, body) = import(
+            ImportMode::Merge,
+            vec![
+                json!({ "column": "email", "strategy": "hash" }),
+                json!({ "column": "ssn", "strategy": "ssn" }),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["mode"], "merge");
+        assert_eq!((&body["added"], &body["updated"]), (&json!(1), &json!(1)));
+        assert_eq!(body["ids"][0], email_id);
+        let rules = state.config.read().await.rules.clone();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].id.as_str(), email_id);
+        assert_eq!(rules[0].strategy.as_str(), "hash");
+
+        // Replacing with the export restores it exactly, IDs included
+        let (status, body) =
+            import(ImportMode::Replace, exported.as_array().unwrap().clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["added"], 2);
+        assert_eq!(
+            serde_json::to_value(&state.config.read().await.rules).unwrap(),
+            exported
+        );
+
+        // A rejected replace leaves the rules alone
+        let (status, body) = import(
+            ImportMode::Replace,
+            vec![
+                json!({ "column": "ssn", "strategy": "ssn" }),
+                json!({ "column": "ssn", "strategy": "redact" }),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["index"], 1);
+        assert_eq!(state.config.read().await.rules.len(), 2);
+
+        // Append still refuses duplicates of existing rules
+        let (status, body) = import(
+            ImportMode::Append,
+            vec![json!({ "column": "email", "strategy": "hash" })],
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            body["errors"][0]["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Rule ")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_rule_rejects_invalid_custom_rule() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+
+        // No pattern to apply
+        let rule = json!({ "column": "order_no", "strategy": "custom" });
+        let response = add_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(RuleWriteQuery::default()),
+            Json(rule),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(state.config.read().await.rules.is_empty());
+
+        // A pattern that doesn't compile never deserializes into a rule
+        let err = serde_json::from_value::<MaskingRule>(json!({
+            "column": "order_no",
+            "strategy": "custom",
+            "options": { "pattern": "ORD-[0-9" }
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid pattern"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_update_and_delete_rule_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let rule = |column: &str| -> MaskingRule {
+            serde_json::from_value(json!({ "column": column, "strategy": "email" })).unwrap()
+        };
+        let config = AppConfig {
+            rules: vec![rule("email"), rule("contact")],
+            ..Default::default()
+        };
+        let id = config.rules[1].id.to_string();
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+
+        // The body's ID, if any, is ignored
+        let edit = json!({ "id": "other", "column": "phone", "strategy": "phone" });
+        let response = update_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Path(id.clone()),
+            Query(RuleWriteQuery::default()),
+            Json(edit),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        {
+            let config = state.config.read().await;
+            assert_eq!(config.rules[1].id.as_str(), id);
+            assert_eq!(config.rules[1].column, "phone");
+            assert_eq!(config.rules[1].strategy, MaskStrategy::Phone);
+        }
+        // Saved with the ID
+        let saved = AppConfig::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(saved.rules[1].id.as_str(), id);
+
+        let response = update_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Path("nope".into()),
+            Query(RuleWriteQuery::default()),
+            Json(json!({ "column": "x", "strategy": "email" })),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // An unknown ID is reported as such even when the body duplicates a rule
+        let response = update_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Path("nope".into()),
+            Query(RuleWriteQuery::default()),
+            Json(json!({ "column": "email", "strategy": "hash" })),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Nor may an edit duplicate another rule
+        let response = update_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Path(id.clone()),
+            Query(RuleWriteQuery::default()),
+            Json(json!({ "column": "email", "strategy": "hash" })),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response =
+            delete_rule_by_id(State(state.clone()), ClientIp::default(), Path(id.clone()))
+                .await
+                .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = delete_rule_by_id(State(state.clone()), ClientIp::default(), Path(id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let config = state.config.read().await;
+        assert_eq!(config.rules.len(), 1);
+        assert_eq!(config.rules[0].column, "email");
+    }
+
+    #[tokio::test]
+    async fn test_get_rules() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+
+        let response = get_rules(State(state)).await;
+        let json = response.0;
+
+        assert!(json["rules"].is_array());
+        assert_eq!(json["rules"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hash_salt_not_exposed() {
+        let mut config = AppConfig::default();
+        config.masking.hash_salt = Some("pepper".to_string());
+        config.masking.seed_salt = Some("paprika".to_string());
+        config.rules.push(MaskingRule {
+            id: Default::default(),
+            table: None,
+            column: "email".parse().unwrap(),
+            strategy: MaskStrategy::Email,
+            options: Some(crate::config::MaskingOptions {
+                seed_salt: Some("sumac".to_string()),
+                ..Default::default()
+            }),
+            json_paths: vec![],
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+
+        let rules = get_rules(State(state.clone())).await.0;
+        assert_eq!(rules["rules"][0]["options"]["seed_salt"], "***");
+        assert!(rules.get("masking").is_none());
+        assert!(!rules.to_string().contains("sumac"));
+
+        let full = get_full_config(State(state.clone())).await.0;
+        assert_eq!(full["masking"]["hash_salt"], "***");
+        assert_eq!(full["masking"]["seed_salt"], "***");
+        assert_eq!(full["rules"][0]["options"]["seed_salt"], "***");
+        for salt in ["pepper", "paprika", "sumac"] {
+            assert!(!full.to_string().contains(salt));
+        }
+
+        let config = get_config(State(state.clone())).await.0;
+        assert_eq!(config["masking"]["hash_salt_set"], true);
+        assert_eq!(config["masking"]["seed_salt_set"], true);
+        assert!(!config.to_string().contains("pepper"));
+
+        let response = export_rules(State(state.clone())).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let exported: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(exported[0]["options"]["seed_salt"], "***");
+        assert!(!exported.to_string().contains("sumac"));
+
+        // Importing the export keeps the salt of the rule it replaces
+        let response = import_rules(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(ImportQuery {
+                mode: ImportMode::Replace,
+                allow_duplicates: false,
+            }),
+            Json(exported.as_array().unwrap().clone()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            state.config.read().await.rules[0]
+                .options
+                .as_ref()
+                .unwrap()
+                .seed_salt
+                .as_deref(),
+            Some("sumac")
+        );
+
+        let (_, scanned) = scan_value(
+            State(state.clone()),
+            Json(ScanValueRequest {
+                value: "jane@example.com".to_string(),
+                table: None,
+                column: "email".to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(scanned["rule"]["options"]["seed_salt"], "***");
+        assert!(!scanned.to_string().contains("sumac"));
+
+        let id = state.config.read().await.rules[0].id.to_string();
+        let salted = |column: &str| {
+            json!({
+                "column": column,
+                "strategy": "email",
+                "options": { "seed_salt": "saffron" }
+            })
+        };
+        let response = add_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Query(RuleWriteQuery::default()),
+            Json(salted("contact")),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = update_rule(
+            State(state.clone()),
+            ClientIp::default(),
+            Path(id.clone()),
+            Query(RuleWriteQuery::default()),
+            Json(salted("email")),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = delete_rule_by_id(State(state.clone()), ClientIp::default(), Path(id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, entries) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        let added = entries
+            .iter()
+            .find(|e| e.event_type == AuditEventType::RuleAdded)
+            .unwrap();
+        assert_eq!(
+            added.details.as_ref().unwrap()["options"]["seed_salt"],
+            "***"
+        );
+        let updated = entries
+            .iter()
+            .find(|e| e.event_type == AuditEventType::RuleUpdated)
+            .unwrap();
+        let details = updated.details.as_ref().unwrap();
+        assert_eq!(details["previous"]["options"]["seed_salt"], "***");
+        assert_eq!(details["rule"]["options"]["seed_salt"], "***");
+        let deleted = entries
+            .iter()
+            .find(|e| e.event_type == AuditEventType::RuleDeleted)
+            .unwrap();
+        assert_eq!(
+            deleted.details.as_ref().unwrap()["rules"][0]["options"]["seed_salt"],
+            "***"
+        );
+        for entry in &entries {
+            let entry = serde_json::to_string(entry).unwrap();
+            assert!(
+                !entry.contains("saffron") && !entry.contains("sumac"),
+                "{}",
+                entry
+            );
+        }
+    }
+
+    #[test]
+    fn test_router_builds() {
+        // Conflicting routes panic when the router is built
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let _ = router(state, &CorsConfig::default());
+    }
+
+    #[tokio::test]
+    async fn test_scan_jobs() {
+        // Nothing listens on port 1, so the scan fails straight away
+        let state = AppState::new(
+            AppConfig::default(),
+            "proxy.yaml".to_string(),
+            "127.0.0.1".to_string(),
+            1,
+            DbProtocol::Postgres,
+        );
+        let config: ScanConfig = serde_json::from_value(json!({
+            "username": "postgres",
+            "password": "postgres",
+            "database": "shop"
+        }))
+        .unwrap();
+
+        let response = scan_database(State(state.clone()), Json(config))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "running");
+        let job_id = body["job_id"].as_str().unwrap().to_string();
+
+        let job = tokio::time::timeout(std::time::Duration::from_secs(20), async {
+            loop {
+                let (status, Json(job)) =
+                    get_scan_job(State(state.clone()), Path(job_id.clone())).await;
+                assert_eq!(status, StatusCode::OK);
+                if job["status"] != "running" {
+                    return job;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["database"], "shop");
+        assert!(
+            job["error"]
+                .as_str()
+                .unwrap()
+                .contains("Database connection failed"),
+            "{}",
+            job
+        );
+
+        let (status, Json(body)) =
+            get_scan_result(State(state.clone()), Path(job_id.clone())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["status"], "failed");
+        let (status, _) = cancel_scan_job(State(state.clone()), Path(job_id)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _) = get_scan_job(State(state.clone()), Path("nope".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = cancel_scan_job(State(state), Path("nope".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_scan_history() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        for id in ["first", "second"] {
+            let result = serde_json::from_value(json!({
+                "status": "completed",
+                "tables_scanned": 1,
+                "columns_scanned": 2,
+                "findings": [],
+                "schema": "public",
+                "database": "shop",
+                "scan_duration_ms": 3,
+                "filters": {}
+            }))
+            .unwrap();
+            state.scan_history.record(id, result).await;
+        }
+
+        let Json(body) = get_scan_history(
+            State(state.clone()),
+            axum::extract::Query(ScanHistoryQuery {
+                offset: None,
+                limit: Some(1),
+            }),
+        )
+        .await;
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["scans"][0]["id"], "second");
+        assert_eq!(body["scans"].as_array().unwrap().len(), 1);
+
+        let (status, Json(body)) =
+            get_scan_history_entry(State(state.clone()), Path("second".to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["previous_id"], "first");
+        assert_eq!(body["result"]["database"], "shop");
+        let (status, _) = get_scan_history_entry(State(state), Path("nope".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_scan_credentials_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let password_file = dir.path().join("scanner-password");
+        std::fs::write(&password_file, "from-file\n").unwrap();
+        let mut config = AppConfig::default();
+        config.scanner.credentials.insert(
+            "default".to_string(),
+            crate::config::ScannerCredentials {
+                username: "scanner".to_string(),
+                password: None,
+                password_file: Some(password_file.to_string_lossy().into_owned()),
+                password_env: None,
+            },
+        );
+        config.scanner.credentials.insert(
+            "reporting".to_string(),
+            crate::config::ScannerCredentials {
+                username: "reporting".to_string(),
+                password: Some("inline-secret".to_string()),
+                password_file: None,
+                password_env: None,
+            },
+        );
+        let state = AppState::new(
+            config,
+            "proxy.yaml".to_string(),
+            "127.0.0.1".to_string(),
+            1,
+            DbProtocol::Postgres,
+        );
+
+        let full = get_full_config(State(state.clone())).await.0;
+        let reporting = &full["scanner"]["credentials"]["reporting"];
+        assert_eq!(reporting["password"], "***");
+        assert!(!full.to_string().contains("inline-secret"));
+
+        let status = |request: Value| {
+            let state = state.clone();
+            async move {
+                let config: ScanConfig = serde_json::from_value(request).unwrap();
+                scan_database(State(state), Json(config))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        assert_eq!(
+            status(json!({"database": "shop"})).await,
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            status(json!({"database": "shop", "credentials_ref": "reporting"})).await,
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            status(json!({"database": "shop", "credentials_ref": "missing"})).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(json!({
+                "database": "shop",
+                "include_tables": ["users"],
+                "exclude_tables": ["*"]
+            }))
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(json!({"database": "shop", "username": "u", "credentials_ref": "reporting"}))
+                .await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_value() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - { table: users, column: email, strategy: hash }
+  - { table: audit.users, column: email, strategy: none }
+  - { column: "*_phone", strategy: redact }
+"#,
+        )
+        .unwrap();
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let scan = |value: &str, table: Option<&str>, column: &str| {
+            let request = Json(ScanValueRequest {
+                value: value.to_string(),
+                table: table.map(str::to_string),
+                column: column.to_string(),
+            });
+            let state = state.clone();
+            async move {
+                let (status, Json(body)) = scan_value(State(state), request).await;
+                assert_eq!(status, StatusCode::OK);
+                body
+            }
+        };
+
+        let body = scan("alice@example.com", Some("public.users"), "email").await;
+        assert_eq!(body["pii_types"][0]["pii_type"], "Email");
+        assert_eq!(body["rule"]["table"], "users");
+        assert_eq!(body["strategy"], "hash");
+        let masked = body["masked"].as_str().unwrap();
+        assert_ne!(masked, "alice@example.com");
+        // The same value always masks the same way
+        let again = scan("alice@example.com", Some("public.users"), "email").await;
+        assert_eq!(again["masked"], masked);
+
+        // Excluded in this table
+        let body = scan("alice@example.com", Some("audit.users"), "email").await;
+        assert_eq!(body["strategy"], "none");
+        assert_eq!(body["masked"], "alice@example.com");
+
+        // No rule without the table, so the heuristics decide
+        let body = scan("alice@example.com", None, "email").await;
+        assert_eq!(body["rule"], Value::Null);
+        assert_eq!(body["strategy"], "email");
+        assert_ne!(body["masked"], "alice@example.com");
+
+        let body = scan("555-0100", None, "home_phone").await;
+        assert_eq!(body["pii_types"], json!([]));
+        assert_eq!(body["rule"]["column"], "*_phone");
+        assert_eq!(body["strategy"], "redact");
+
+        let body = scan("pending", None, "status").await;
+        assert_eq!(body["rule"], Value::Null);
+        assert_eq!(body["strategy"], Value::Null);
+        assert_eq!(body["masked"], "pending");
+    }
+
+    #[tokio::test]
+    async fn test_detokenize_requires_scope_and_is_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let tokenization = crate::config::TokenizationConfig {
+            vault_path: dir.path().join("vault.log").to_string_lossy().into_owned(),
+            key: Some("vault-secret".to_string()),
+            key_env: "IRONVEIL_VAULT_KEY".to_string(),
+        };
+        let vault = crate::vault::TokenVault::open(&tokenization).await.unwrap();
+        let token = vault.tokenize(b"alice@example.com");
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string())
+            .with_token_vault(vault);
+        let request = || {
+            Json(DetokenizeRequest {
+                token: token.clone(),
+            })
+        };
+
+        // Authenticated, but without the scope
+        let reader = JwtIdentity {
+            subject: "reader".to_string(),
+            scopes: vec!["read".to_string()],
+        };
+        let response = detokenize(
+            State(state.clone()),
+            ClientIp::default(),
+            Some(Extension(reader)),
+            request(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // No JWT at all (API key or unauthenticated API)
+        let response = detokenize(State(state.clone()), ClientIp::default(), None, request())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let admin = JwtIdentity {
+            subject: "admin".to_string(),
+            scopes: vec!["read".to_string(), DETOKENIZE_SCOPE.to_string()],
+        };
+        let (status, Json(body)) = detokenize(
+            State(state.clone()),
+            ClientIp::default(),
+            Some(Extension(admin.clone())),
+            request(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["value"], "alice@example.com");
+
+        let (status, _) = detokenize(
+            State(state.clone()),
+            ClientIp::default(),
+            Some(Extension(admin)),
+            Json(DetokenizeRequest {
+                token: "tok_unknown".to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let filter = AuditFilter {
+            event_type: Some(AuditEventType::Detokenize),
+            ..Default::default()
+        };
+        let (_, entries) = state.audit_logger.query(&filter, 0, 100).await;
+        let outcomes: Vec<_> = entries.iter().map(|e| e.outcome.clone()).collect();
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(
+            outcomes
+                .iter()
+                .filter(|o| **o == AuditOutcome::Denied)
+                .count(),
+            2
+        );
+        assert!(
+            entries.iter().any(
+                |e| e.outcome == AuditOutcome::Success && e.user_id.as_deref() == Some("admin")
+            )
+        );
+        // The audit trail names the token, never the value
+        assert!(!serde_json::to_string(&entries).unwrap().contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_scope_claim() {
+        use jsonwebtoken::{EncodingKey, Header, encode};
+
+        let secret = "test-jwt-secret";
+        let claims = Claims {
+            sub: "admin".to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: chrono::Utc::now().timestamp() as usize,
+            scope: "read detokenize".to_string(),
+            roles: vec![],
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+
+        let claims = validate_jwt(&token, secret).unwrap();
+        assert!(
+            claims
+                .scope
+                .split_whitespace()
+                .any(|s| s == DETOKENIZE_SCOPE)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scopes_enforced() {
+        use jsonwebtoken::{EncodingKey, Header, encode};
+
+        let secret = "test-jwt-secret";
+        let token = |scope: &str, roles: Vec<&str>| {
+            let claims = Claims {
+                sub: "dashboard".to_string(),
+                exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+                iat: chrono::Utc::now().timestamp() as usize,
+                scope: scope.to_string(),
+                roles: roles.into_iter().map(String::from).collect(),
+            };
+            encode(
+                &Header::default(),
+                &claims,
+                &EncodingKey::from_secret(secret.as_bytes()),
+            )
+            .unwrap()
+        };
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("reader-key".to_string()),
+                keys: vec![],
+                jwt_secret: Some(secret.to_string()),
+                stats_history_interval_secs: 10,
+                bind_address: None,
+                tls: None,
+                default_scopes: vec!["rules:read".to_string()],
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "/nonexistent/proxy.yaml".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::serve(listener, router(state.clone(), &CorsConfig::default())).into_future(),
+        );
+
+        let client = reqwest::Client::new();
+        let status = |method: reqwest::Method, path: &str, auth: (&str, String)| {
+            client
+                .request(method, format!("http://{}{}", addr, path))
+                .header(auth.0, auth.1)
+                .json(&json!([]))
+                .send()
+        };
+        let bearer = |token: String| ("Authorization", format!("Bearer {}", token));
+        let key = ("X-API-Key", "reader-key".to_string());
+
+        // The API key has the default scopes only
+        let response = status(reqwest::Method::GET, "/rules", key.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = status(reqwest::Method::POST, "/rules/import", key)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["required_scope"], "rules:write");
+
+        // A read-only dashboard token
+        let reader = token("rules:read audit:read", vec![]);
+        let response = status(reqwest::Method::GET, "/audit", bearer(reader.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        for (method, path) in [
+            (reqwest::Method::POST, "/rules/import"),
+            (reqwest::Method::POST, "/config"),
+            (reqwest::Method::POST, "/scan"),
+            (reqwest::Method::GET, "/stats"),
+        ] {
+            let response = status(method, path, bearer(reader.clone())).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+        }
+
+        // Scopes may also come as a list of roles; an empty import is fine
+        let writer = token("", vec!["rules:write"]);
+        let response = status(reqwest::Method::POST, "/rules/import", bearer(writer))
+            .await
+            .unwrap();
+        assert_ne!(response.status(), StatusCode::FORBIDDEN);
+
+        // A token without scopes gets the default ones
+        let response = status(reqwest::Method::GET, "/rules", bearer(token("", vec![])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let filter = AuditFilter {
+            outcome: Some(AuditOutcome::Denied),
+            user_id: Some("dashboard".to_string()),
+            ..Default::default()
+        };
+        let (_, denied) = state.audit_logger.query(&filter, 0, 100).await;
+        assert_eq!(denied.len(), 4);
+        assert!(
+            denied
+                .iter()
+                .any(|e| e.details.as_ref().unwrap()["missing_scope"] == "scan:run")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_named_api_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("legacy-key".to_string()),
+                keys: vec![
+                    ApiKeyConfig {
+                        name: "admin".to_string(),
+                        key_hash: hash_api_key("admin-key"),
+                        scopes: vec!["apikeys:write".to_string(), "audit:read".to_string()],
+                    },
+                    ApiKeyConfig {
+                        name: "ci".to_string(),
+                        key_hash: hash_api_key("ci-key"),
+                        scopes: vec!["rules:read".to_string()],
+                    },
+                ],
+                default_scopes: vec!["stats:read".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::serve(listener, router(state.clone(), &CorsConfig::default())).into_future(),
+        );
+
+        let client = reqwest::Client::new();
+        let get = |path: &str, key: &str| {
+            client
+                .get(format!("http://{}{}", addr, path))
+                .header("X-API-Key", key)
+                .send()
+        };
+
+        // Each key has its own scopes
+        assert_eq!(
+            get("/rules", "ci-key").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            get("/stats", "ci-key").await.unwrap().status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get("/stats", "legacy-key").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            get("/rules", "wrong-key").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // The key's name is the audited user
+        let filter = AuditFilter {
+            event_type: Some(AuditEventType::AuthAttempt),
+            user_id: Some("ci".to_string()),
+            ..Default::default()
+        };
+        let (total, _) = state.audit_logger.query(&filter, 0, 10).await;
+        assert_eq!(total, 3);
+
+        // Generate a key; only its hash is saved
+        let response = client
+            .post(format!("http://{}/apikeys", addr))
+            .header("X-API-Key", "admin-key")
+            .json(&json!({ "name": "exporter", "scopes": ["audit:read"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: Value = response.json().await.unwrap();
+        let key = body["key"].as_str().unwrap().to_string();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains(&key));
+        assert!(saved.contains(&hash_api_key(&key)));
+        assert_eq!(get("/audit", &key).await.unwrap().status(), StatusCode::OK);
+
+        let response = client
+            .post(format!("http://{}/apikeys", addr))
+            .header("X-API-Key", "admin-key")
+            .json(&json!({ "name": "exporter" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = client
+            .post(format!("http://{}/apikeys", addr))
+            .header("X-API-Key", "ci-key")
+            .json(&json!({ "name": "escalate" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Revoking takes effect at once
+        let response = client
+            .delete(format!("http://{}/apikeys/exporter", addr))
+            .header("X-API-Key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            get("/audit", &key).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let response = client
+            .delete(format!("http://{}/apikeys/exporter", addr))
+            .header("X-API-Key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let filter = AuditFilter {
+            user_id: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let (_, entries) = state.audit_logger.query(&filter, 0, 100).await;
+        assert!(
+            entries
+                .iter()
+                .any(|e| e.event_type == AuditEventType::ApiKeyCreated)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auth_lockout() {
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("right-key".to_string()),
+                lockout: LockoutConfig {
+                    enabled: true,
+                    max_failures: 3,
+                    window_secs: 60,
+                    lockout_secs: 120,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "/nonexistent/proxy.yaml".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(state.clone(), &CorsConfig::default())
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let client = reqwest::Client::new();
+        let get = |key: &str| {
+            client
+                .get(format!("http://{}/rules", addr))
+                .header("X-API-Key", key)
+                .send()
+        };
+
+        // A success resets the count
+        for _ in 0..2 {
+            assert_eq!(
+                get("wrong").await.unwrap().status(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(get("right-key").await.unwrap().status(), StatusCode::OK);
+        for _ in 0..3 {
+            assert_eq!(
+                get("wrong").await.unwrap().status(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        // Locked out, even with the right key
+        let response = get("right-key").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 100 && retry_after <= 120, "{}", retry_after);
+
+        let filter = AuditFilter {
+            outcome: Some(AuditOutcome::Denied),
+            ..Default::default()
+        };
+        let (total, entries) = state.audit_logger.query(&filter, 0, 10).await;
+        assert_eq!(total, 2);
+        // Newest first: the refusal, then the start of the lockout
+        assert_eq!(entries[0].client_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(
+            entries[0].details.as_ref().unwrap()["retry_after_secs"],
+            retry_after
+        );
+        assert_eq!(entries[1].client_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(entries[1].details.as_ref().unwrap()["lockout_secs"], 120);
+    }
+
+    #[tokio::test]
+    async fn test_auth_lockout_counts_query_tokens() {
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("right-key".to_string()),
+                lockout: LockoutConfig {
+                    enabled: true,
+                    max_failures: 3,
+                    window_secs: 60,
+                    lockout_secs: 120,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "/nonexistent/proxy.yaml".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(state.clone(), &CorsConfig::default())
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        // Without a JWT secret, a wrong ?token= is a wrong key
+        let client = reqwest::Client::new();
+        let get = |token: &str| {
+            client
+                .get(format!("http://{}/openapi.json?token={}", addr, token))
+                .send()
+        };
+        for guess in ["guess-1", "guess-2", "guess-3"] {
+            assert_eq!(get(guess).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(
+            get("right-key").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
 
-    #[tokio::test]
-    async fn test_health_check() {
-        let config = AppConfig::default();
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let response = health_check(State(state)).await;
-        let (status, _json) = response.into_response().into_parts();
+    #[test]
+    fn test_client_address() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", "198.51.100.9".parse().unwrap());
+        headers.append("X-Forwarded-For", "203.0.113.7, 10.0.0.2".parse().unwrap());
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let direct: IpAddr = "192.0.2.1".parse().unwrap();
 
-        // For default state (healthy), we should get 200 OK
-        assert_eq!(status.status, StatusCode::OK);
+        // The nearest untrusted hop; earlier ones could be forged
+        assert_eq!(
+            client_address(proxy, &headers, &trusted),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+        // Only trusted proxies may forward
+        assert_eq!(client_address(direct, &headers, &trusted), direct);
+        assert_eq!(client_address(proxy, &headers, &[]), proxy);
+        assert_eq!(client_address(proxy, &HeaderMap::new(), &trusted), proxy);
     }
 
     #[tokio::test]
-    async fn test_api_key_config_parsing() {
-        // Test that API key is correctly parsed from config
+    async fn test_client_ip_in_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
         let config = AppConfig {
             api: Some(ApiConfig {
-                api_key: Some("my-secret-key".to_string()),
-                jwt_secret: None,
+                api_key: Some("key".to_string()),
+                trusted_proxies: vec!["127.0.0.1/32".parse().unwrap()],
+                ..Default::default()
             }),
             ..Default::default()
         };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let config_guard = state.config.read().await;
+        let state = AppState::new_for_test(config, path.to_str().unwrap().to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(state.clone(), &CorsConfig::default())
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::serve(listener, app).into_future());
 
-        let required_key = config_guard
-            .api
-            .as_ref()
-            .and_then(|api| api.api_key.as_ref());
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("http://{}/rules", addr))
+            .header("X-API-Key", "key")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .json(&json!({ "column": "email", "strategy": "email" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client
+            .get(format!("http://{}/rules", addr))
+            .header("X-API-Key", "wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        assert_eq!(required_key, Some(&"my-secret-key".to_string()));
+        let audit: Value = client
+            .get(format!("http://{}/audit", addr))
+            .header("X-API-Key", "key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let client_ip = |event_type: &str, outcome: &str| {
+            audit["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|e| e["event_type"] == event_type && e["outcome"] == outcome)
+                .map(|e| e["client_ip"].clone())
+        };
+        assert_eq!(
+            client_ip("rule_added", "success"),
+            Some(json!("203.0.113.7"))
+        );
+        assert_eq!(
+            client_ip("auth_attempt", "failure"),
+            Some(json!("127.0.0.1"))
+        );
+        let auth_successes = audit["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["event_type"] == "auth_attempt" && e["outcome"] == "success")
+            .map(|e| e["client_ip"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(auth_successes, ["127.0.0.1", "203.0.113.7"]);
     }
 
     #[tokio::test]
-    async fn test_api_key_none_when_not_configured() {
-        // Test that no API key means None
-        let config = AppConfig::default();
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let config_guard = state.config.read().await;
+    async fn test_cors_allows_configured_origins_only() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let serve = |cors: CorsConfig| {
+            let state = state.clone();
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                tokio::spawn(axum::serve(listener, router(state, &cors)).into_future());
+                addr
+            }
+        };
+        let client = reqwest::Client::new();
+        let allowed_origin = |addr: SocketAddr, method: reqwest::Method, origin: &'static str| {
+            let request = client
+                .request(method.clone(), format!("http://{}/rules", addr))
+                .header("Origin", origin);
+            let request = if method == reqwest::Method::OPTIONS {
+                request
+                    .header("Access-Control-Request-Method", "POST")
+                    .header("Access-Control-Request-Headers", "x-api-key")
+            } else {
+                request
+            };
+            async move {
+                let response = request.send().await.unwrap();
+                response
+                    .headers()
+                    .get("access-control-allow-origin")
+                    .map(|value| value.to_str().unwrap().to_string())
+            }
+        };
 
-        let required_key = config_guard
-            .api
-            .as_ref()
-            .and_then(|api| api.api_key.as_ref());
+        // Same origin only by default
+        let addr = serve(CorsConfig::default()).await;
+        for method in [reqwest::Method::GET, reqwest::Method::OPTIONS] {
+            assert_eq!(
+                allowed_origin(addr, method, "https://dash.example.com").await,
+                None
+            );
+        }
 
-        assert_eq!(required_key, None);
+        let addr = serve(CorsConfig {
+            allowed_origins: vec!["https://dash.example.com".to_string()],
+            ..Default::default()
+        })
+        .await;
+        for method in [reqwest::Method::GET, reqwest::Method::OPTIONS] {
+            assert_eq!(
+                allowed_origin(addr, method.clone(), "https://dash.example.com")
+                    .await
+                    .as_deref(),
+                Some("https://dash.example.com")
+            );
+            assert_eq!(
+                allowed_origin(addr, method, "https://evil.example").await,
+                None
+            );
+        }
+
+        let addr = serve(CorsConfig {
+            permissive: true,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(
+            allowed_origin(addr, reqwest::Method::GET, "https://evil.example")
+                .await
+                .as_deref(),
+            Some("*")
+        );
     }
 
     #[tokio::test]
-    async fn test_jwt_validation_valid_token() {
-        use jsonwebtoken::{EncodingKey, Header, encode};
-
-        let secret = "test-jwt-secret";
-        let claims = Claims {
-            sub: "test-user".to_string(),
-            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
-            iat: chrono::Utc::now().timestamp() as usize,
+    async fn test_openapi_document() {
+        let config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some("key".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
         };
+        let state = AppState::new_for_test(config, "/nonexistent/proxy.yaml".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state, &CorsConfig::default())).into_future());
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
-        .unwrap();
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{}{}", addr, path);
+        let response = client.get(url("/openapi.json")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client.get(url("/docs?token=key")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.text().await.unwrap().contains("openapi.json"));
 
-        let result = validate_jwt(&token, secret);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap().sub, "test-user");
+        let doc: Value = client
+            .get(url("/openapi.json?token=key"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+
+        // Every registered route, so that a new one has to be added here
+        let routes = [
+            ("get", "/health"),
+            ("get", "/health/live"),
+            ("get", "/health/ready"),
+            ("get", "/metrics"),
+            ("get", "/rules"),
+            ("post", "/rules"),
+            ("put", "/rules/{id}"),
+            ("delete", "/rules/{id}"),
+            ("post", "/rules/delete"),
+            ("get", "/rules/export"),
+            ("post", "/rules/import"),
+            ("get", "/config"),
+            ("post", "/config"),
+            ("get", "/config/full"),
+            ("post", "/config/reload"),
+            ("post", "/scan"),
+            ("post", "/scan/value"),
+            ("get", "/scan/history"),
+            ("get", "/scan/history/{scan_id}"),
+            ("get", "/scan/{job_id}"),
+            ("delete", "/scan/{job_id}"),
+            ("get", "/scan/{job_id}/result"),
+            ("get", "/connections"),
+            ("get", "/connections/list"),
+            ("post", "/connections/{id}/kill"),
+            ("get", "/stats"),
+            ("post", "/stats/reset"),
+            ("post", "/stats/columns/reset"),
+            ("post", "/schema"),
+            ("get", "/logs"),
+            ("get", "/logs/stream"),
+            ("get", "/audit"),
+            ("get", "/audit/export"),
+            ("post", "/detokenize"),
+            ("post", "/apikeys"),
+            ("delete", "/apikeys/{name}"),
+            ("get", "/openapi.json"),
+            ("get", "/docs"),
+        ];
+        let paths = doc["paths"].as_object().unwrap();
+        let documented: usize = paths
+            .values()
+            .map(|item| item.as_object().unwrap().len())
+            .sum();
+        assert_eq!(documented, routes.len());
+        for (method, path) in routes {
+            let operation = &paths[path][method];
+            assert!(
+                operation.is_object(),
+                "{} {} is not documented",
+                method,
+                path
+            );
+            let secured = operation["security"].is_array();
+            let public = path.starts_with("/health") || path == "/metrics";
+            assert_eq!(secured, !public, "{}", path);
+            if secured {
+                assert_eq!(
+                    operation["responses"]["401"]["$ref"],
+                    "#/components/responses/Unauthorized"
+                );
+            }
+        }
+
+        // Request bodies are described, down to the strategy names
+        let schemas = &doc["components"]["schemas"];
+        assert!(
+            schemas["MaskStrategy"]["enum"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("email"))
+        );
+        assert_eq!(
+            schemas["ScanConfig"]["allOf"][1]["required"],
+            json!(["database"])
+        );
+        assert_eq!(
+            doc["components"]["securitySchemes"]["api_key"]["name"],
+            "X-API-Key"
+        );
     }
 
     #[tokio::test]
-    async fn test_jwt_validation_expired_token() {
-        use jsonwebtoken::{EncodingKey, Header, encode};
-
-        let secret = "test-jwt-secret";
-        let claims = Claims {
-            sub: "test-user".to_string(),
-            exp: (chrono::Utc::now() - chrono::Duration::hours(1)).timestamp() as usize,
-            iat: (chrono::Utc::now() - chrono::Duration::hours(2)).timestamp() as usize,
+    async fn test_vault_key_not_exposed() {
+        let config = AppConfig {
+            tokenization: Some(crate::config::TokenizationConfig {
+                vault_path: "vault.log".to_string(),
+                key: Some("vault-secret".to_string()),
+                key_env: "IRONVEIL_VAULT_KEY".to_string(),
+            }),
+            ..Default::default()
         };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
+        let full = get_full_config(State(state)).await.0;
+        assert_eq!(full["tokenization"]["key"], "***");
+        assert_eq!(full["tokenization"]["key_env"], "IRONVEIL_VAULT_KEY");
+        assert!(!full.to_string().contains("vault-secret"));
+    }
+
+    #[tokio::test]
+    async fn test_audit_query_filters() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        for (outcome, endpoint) in [
+            (AuditOutcome::Success, "/rules"),
+            (AuditOutcome::Failure, "/rules/import"),
+            (AuditOutcome::Failure, "/config"),
+        ] {
+            let mut entry = AuditLogger::auth_denied().with_endpoint(endpoint);
+            entry.outcome = outcome;
+            state.audit_logger.log(entry).await;
+        }
+        state
+            .audit_logger
+            .log(AuditLogger::config_reload(1, ReloadTrigger::Api).with_endpoint("/config/reload"))
+            .await;
+
+        let query: AuditQuery = serde_json::from_value(json!({
+            "event_type": "auth_attempt",
+            "outcome": "failure",
+            "from": (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339(),
+            "limit": 1
+        }))
         .unwrap();
+        let body = get_audit_logs(State(state.clone()), axum::extract::Query(query))
+            .await
+            .0;
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["entries"][0]["endpoint"], "/config");
+        assert_eq!(body["filters"]["event_type"], "auth_attempt");
+        assert_eq!(body["filters"]["outcome"], "failure");
+        assert!(body["filters"].get("endpoint").is_none());
 
-        let result = validate_jwt(&token, secret);
-        assert!(result.is_err());
+        let query: AuditQuery =
+            serde_json::from_value(json!({ "endpoint": "rules", "offset": 1 })).unwrap();
+        let body = get_audit_logs(State(state), axum::extract::Query(query))
+            .await
+            .0;
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["entries"][0]["endpoint"], "/rules");
+
+        // As a query string
+        let uri = "/audit?event_type=auth_attempt&outcome=denied&from=2026-01-01T00:00:00Z&limit=5&offset=2"
+            .parse()
+            .unwrap();
+        let axum::extract::Query(query) =
+            axum::extract::Query::<AuditQuery>::try_from_uri(&uri).unwrap();
+        assert_eq!(query.outcome, Some(AuditOutcome::Denied));
+        assert_eq!(query.offset, 2);
+        assert!(query.from.is_some());
+
+        // Unknown event types are an error rather than no filter
+        assert!(serde_json::from_value::<AuditQuery>(json!({ "event_type": "nope" })).is_err());
     }
 
     #[tokio::test]
-    async fn test_jwt_validation_wrong_secret() {
-        use jsonwebtoken::{EncodingKey, Header, encode};
+    async fn test_audit_export() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state
+            .audit_logger
+            .log(AuditLogger::config_reload(3, ReloadTrigger::Api))
+            .await;
+        state.audit_logger.log(AuditLogger::auth_denied()).await;
 
-        let claims = Claims {
-            sub: "test-user".to_string(),
-            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
-            iat: chrono::Utc::now().timestamp() as usize,
+        let query: AuditExportQuery =
+            serde_json::from_value(json!({ "format": "csv", "outcome": "success" })).unwrap();
+        let admin = JwtIdentity {
+            subject: "auditor".to_string(),
+            scopes: vec![],
         };
-
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(b"correct-secret"),
+        let response = export_audit_logs(
+            State(state.clone()),
+            Some(Extension(admin)),
+            axum::extract::Query(query),
         )
-        .unwrap();
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/csv");
+        let disposition = response.headers()["content-disposition"].to_str().unwrap();
+        assert!(disposition.starts_with("attachment; filename=\"ironveil-audit-"));
+        assert!(disposition.ends_with(".csv\""));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        // The header, the reload and the export itself, but not the denial
+        assert_eq!(body.lines().count(), 3, "{}", body);
+        assert!(body.contains(",config_reload,success,"));
+        assert!(body.contains(",audit_export,success,,,auditor,"));
 
-        let result = validate_jwt(&token, "wrong-secret");
-        assert!(result.is_err());
+        let filter = AuditFilter {
+            event_type: Some(AuditEventType::AuditExport),
+            ..Default::default()
+        };
+        let (_, entries) = state.audit_logger.query(&filter, 0, 10).await;
+        assert_eq!(entries[0].details.as_ref().unwrap()["format"], "csv");
     }
 
     #[tokio::test]
-    async fn test_jwt_config_parsing() {
-        let config = AppConfig {
+    async fn test_api_over_tls() {
+        let fixture =
+            |name: &str| format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let tls = |cert: &str| crate::config::TlsConfig {
+            enabled: true,
+            cert_path: fixture(cert),
+            key_path: fixture("server.key"),
+            client_ca_path: None,
+            require_client_auth: false,
+        };
+        let config = |tls| AppConfig {
             api: Some(ApiConfig {
                 api_key: None,
-                jwt_secret: Some("my-jwt-secret".to_string()),
+                keys: vec![],
+                jwt_secret: None,
+                stats_history_interval_secs: 10,
+                bind_address: Some("127.0.0.1".parse().unwrap()),
+                tls: Some(tls),
+                default_scopes: crate::config::default_api_scopes(),
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
             }),
             ..Default::default()
         };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let config_guard = state.config.read().await;
 
-        let jwt_secret = config_guard
-            .api
-            .as_ref()
-            .and_then(|api| api.jwt_secret.as_ref());
+        let any_port = || crate::config::ServerOverrides {
+            api_port: Some(0),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config(tls("missing.crt")), "proxy.yaml".to_string())
+            .with_server_overrides(any_port());
+        let err = ApiServer::bind(state).await.err().unwrap();
+        assert!(err.to_string().contains("missing.crt"), "{}", err);
 
-        assert_eq!(jwt_secret, Some(&"my-jwt-secret".to_string()));
+        let state = AppState::new_for_test(config(tls("server.crt")), "proxy.yaml".to_string())
+            .with_server_overrides(any_port());
+        let server = ApiServer::bind(state).await.unwrap();
+        let addr = server.listener.local_addr().unwrap();
+        assert_eq!(addr.ip().to_string(), "127.0.0.1");
+        tokio::spawn(server.serve());
+
+        // The fixture certificate is only valid for db.internal.example.com
+        let ca = std::fs::read(fixture("ca.crt")).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap())
+            .resolve("db.internal.example.com", addr)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!(
+                "https://db.internal.example.com:{}/health",
+                addr.port()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // No plaintext fallback on a TLS listener
+        let plain = reqwest::get(format!("http://{}/health", addr)).await;
+        assert!(plain.is_err());
     }
 
     #[tokio::test]
-    async fn test_get_config() {
+    async fn test_log_stream() {
         let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![MaskingRule {
-                table: Some("users".to_string()),
-                column: "email".to_string(),
-                strategy: "email".to_string(),
-            }],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            api: Some(ApiConfig {
+                api_key: Some("stream-key".to_string()),
+                keys: vec![],
+                jwt_secret: None,
+                stats_history_interval_secs: 10,
+                bind_address: None,
+                tls: None,
+                default_scopes: crate::config::default_api_scopes(),
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
+            }),
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::serve(listener, router(state.clone(), &CorsConfig::default())).into_future(),
+        );
 
-        let response = get_config(State(state)).await;
-        let json = response.0;
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("http://{}{}", addr, path)).send();
+        // Only the stream takes the token as a query parameter
+        let response = get("/logs/stream?token=wrong").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = get("/logs?token=stream-key").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        assert_eq!(json["masking_enabled"], true);
-        assert_eq!(json["rules_count"], 1);
+        let mut response = get("/logs/stream?token=stream-key&event_type=Query")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"].to_str().unwrap(),
+            "text/event-stream"
+        );
+        let log = |event_type: &str, content: &str| LogEntry {
+            id: content.to_string(),
+            timestamp: chrono::Utc::now(),
+            connection_id: 1,
+            event_type: event_type.to_string(),
+            content: content.to_string(),
+            details: None,
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        };
+        state.add_log(log("Connection", "filtered out")).await;
+        state.add_log(log("Query", "SELECT 1")).await;
+
+        let mut body = String::new();
+        while !body.contains("SELECT 1") {
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), response.chunk())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            body.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        assert!(body.starts_with("event: log\ndata: {"), "{}", body);
+        assert!(!body.contains("filtered out"));
+
+        // The list takes the same filters
+        let logs = client
+            .get(format!("http://{}/logs?event_type=Connection", addr))
+            .header("X-API-Key", "stream-key")
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(logs["logs"].as_array().unwrap().len(), 1);
+        assert_eq!(logs["logs"][0]["content"], "filtered out");
     }
 
     #[tokio::test]
-    async fn test_update_config() {
-        let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+    async fn test_secrets_not_in_get_responses() {
+        let secrets = [
+            "api-key-secret",
+            "jwt-secret-value",
+            "/etc/ironveil/server.key",
+            "/etc/ironveil/upstream.key",
+            "catalog-secret",
+            "salt-secret",
+        ];
+        let mut config = AppConfig {
+            api: Some(ApiConfig {
+                api_key: Some(secrets[0].to_string()),
+                keys: vec![],
+                jwt_secret: Some(secrets[1].to_string()),
+                stats_history_interval_secs: 10,
+                bind_address: None,
+                tls: None,
+                default_scopes: crate::config::default_api_scopes(),
+                lockout: Default::default(),
+                trusted_proxies: vec![],
+                cors: Default::default(),
+            }),
+            tls: Some(crate::config::TlsConfig {
+                enabled: false,
+                cert_path: "/etc/ironveil/server.crt".to_string(),
+                key_path: secrets[2].to_string(),
+                client_ca_path: None,
+                require_client_auth: false,
+            }),
+            upstream_tls_client_key_path: Some(secrets[3].to_string()),
+            catalog_lookup: Some(
+                serde_json::from_value(json!({
+                    "username": "catalog",
+                    "password": secrets[4]
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
         };
+        config.masking.hash_salt = Some(secrets[5].to_string());
+        config.rules.push(
+            serde_json::from_value(json!({ "column": "email", "strategy": "email" })).unwrap(),
+        );
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
-        let payload = json!({ "masking_enabled": false });
-        let response = update_config(State(state.clone()), Json(payload)).await;
-        let json = response.0;
-
-        assert_eq!(json["status"], "success");
-        assert_eq!(json["masking_enabled"], false);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state, &CorsConfig::default())).into_future());
 
-        // Verify state was actually updated
-        let config = state.config.read().await;
-        assert!(!config.masking_enabled);
+        let client = reqwest::Client::new();
+        for path in [
+            "/health",
+            "/rules",
+            "/rules/export",
+            "/config",
+            "/config/full",
+            "/connections",
+            "/stats",
+            "/logs",
+            "/audit",
+            "/scan/history",
+        ] {
+            let response = client
+                .get(format!("http://{}{}", addr, path))
+                .header("X-API-Key", secrets[0])
+                .send()
+                .await
+                .unwrap();
+            assert!(
+                response.status().is_success(),
+                "{}: {}",
+                path,
+                response.status()
+            );
+            let body = response.text().await.unwrap();
+            for secret in secrets {
+                assert!(!body.contains(secret), "{} returned {}", path, secret);
+            }
+        }
     }
 
     #[tokio::test]
-    async fn test_add_rule() {
-        let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
-        };
-        let state = AppState::new_for_test(config, "/tmp/test_proxy.yaml".to_string());
-
-        // Create temp file so save works
-        std::fs::write("/tmp/test_proxy.yaml", "rules: []").ok();
+    async fn test_stats_report_masking_cache() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let stats = get_stats(State(state), axum::extract::Query(StatsQuery::default()))
+            .await
+            .1
+            .0;
+        assert!(stats["masking_cache"].is_null());
 
-        let new_rule = MaskingRule {
-            table: Some("users".to_string()),
-            column: "phone".to_string(),
-            strategy: "phone".to_string(),
-        };
-
-        // Call add_rule and verify rule was added to state
-        let _ = add_rule(State(state.clone()), Json(new_rule)).await;
+        let mut config = AppConfig::default();
+        config.masking.cache_capacity = Some(100);
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let cache = state.masking_cache.clone().unwrap();
+        cache.get_or_insert_with(MaskStrategy::Email, &[0; 16], b"a", || "x".to_string());
+        cache.get_or_insert_with(MaskStrategy::Email, &[0; 16], b"a", || "x".to_string());
 
-        // Verify rule was added
-        let config = state.config.read().await;
-        assert_eq!(config.rules.len(), 1);
-        assert_eq!(config.rules[0].column, "phone");
+        let stats = get_stats(State(state), axum::extract::Query(StatsQuery::default()))
+            .await
+            .1
+            .0;
+        assert_eq!(stats["masking_cache"]["capacity"], 100);
+        assert_eq!(stats["masking_cache"]["size"], 1);
+        assert_eq!(stats["masking_cache"]["hit_rate"], 0.5);
     }
 
     #[tokio::test]
-    async fn test_get_rules() {
-        let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![MaskingRule {
-                table: None,
-                column: "email".to_string(),
-                strategy: "email".to_string(),
-            }],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
-        };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+    async fn test_stats_by_column_and_reset() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state
+            .record_maskings(&[(Some("public.users"), "email", "email")])
+            .await;
 
-        let response = get_rules(State(state)).await;
-        let json = response.0;
+        let stats = get_stats(
+            State(state.clone()),
+            axum::extract::Query(StatsQuery::default()),
+        )
+        .await
+        .1
+        .0;
+        assert_eq!(
+            stats["by_column"]["columns"][0],
+            json!({"table": "public.users", "column": "email", "strategy": "email", "count": 1})
+        );
+        assert_eq!(stats["by_column"]["other"], 0);
 
-        assert!(json["rules"].is_array());
-        assert_eq!(json["rules"].as_array().unwrap().len(), 1);
+        let user = Some(Extension(AuthUser("admin".to_string())));
+        let response = reset_column_stats(State(state.clone()), ClientIp::default(), user)
+            .await
+            .0;
+        assert_eq!(response["cleared"], 1);
+        let (_, entries) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        let entry = &entries[0];
+        assert_eq!(entry.event_type, AuditEventType::StatsReset);
+        assert_eq!(entry.endpoint.as_deref(), Some("/stats/columns/reset"));
+        assert_eq!(entry.user_id.as_deref(), Some("admin"));
+        assert_eq!(entry.details.as_ref().unwrap()["cleared_columns"], 1);
+        let stats = get_stats(State(state), axum::extract::Query(StatsQuery::default()))
+            .await
+            .1
+            .0;
+        assert_eq!(stats["by_column"]["columns"], json!([]));
+        assert_eq!(stats["masking"]["total"], 1);
     }
 
     #[tokio::test]
@@ -954,23 +5314,157 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
         // Simulate some connections
         state.active_connections.fetch_add(3, Ordering::Relaxed);
+        let mut info = ConnectionInfo::new(42, DbProtocol::Postgres);
+        info.user = Some("alice".to_string());
+        info.database = Some("shop".to_string());
+        info.application_name = Some("psql".to_string());
+        info.policy = Some("bi".to_string());
+        state.register_connection(info).await;
 
         let response = get_connections(State(state)).await;
         let json = response.0;
 
         assert_eq!(json["active_connections"], 3);
+        let connections = json["connections"].as_array().unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0]["connection_id"], 42);
+        assert_eq!(connections[0]["user"], "alice");
+        assert_eq!(connections[0]["database"], "shop");
+        assert_eq!(connections[0]["application_name"], "psql");
+        assert_eq!(connections[0]["policy"], "bi");
+    }
+
+    #[tokio::test]
+    async fn test_list_and_kill_connections() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut info = ConnectionInfo::new(7, DbProtocol::MySql);
+        info.client_addr = Some("10.0.0.7".parse().unwrap());
+        info.user = Some("bob".to_string());
+        let kill_switch = state.register_connection(info).await;
+        state.record_connection_query(7).await;
+        state.record_connection_masked(7, 1).await;
+
+        let json = list_connections(State(state.clone())).await.0;
+        assert_eq!(json["count"], 1);
+        assert_eq!(json["connections"][0]["connection_id"], 7);
+        assert_eq!(json["connections"][0]["client_addr"], "10.0.0.7");
+        assert_eq!(json["connections"][0]["protocol"], "MySql");
+        assert_eq!(json["connections"][0]["queries"], 1);
+        assert_eq!(json["connections"][0]["masked_fields"], 1);
+
+        // Stand in for the connection handler closing on the kill switch
+        let handler_state = state.clone();
+        tokio::spawn(async move {
+            kill_switch.cancelled().await;
+            handler_state.unregister_connection(7).await;
+        });
+        let user = Some(Extension(AuthUser("admin".to_string())));
+        let (status, Json(body)) =
+            kill_connection(State(state.clone()), ClientIp::default(), user, Path(7)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["closed"], true);
+        assert!(state.list_connections().await.is_empty());
+
+        let (status, _) =
+            kill_connection(State(state.clone()), ClientIp::default(), None, Path(7)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let filter = AuditFilter {
+            user_id: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let (_, entries) = state.audit_logger.query(&filter, 0, 100).await;
+        let killed = entries
+            .iter()
+            .find(|e| e.event_type == AuditEventType::ConnectionKilled)
+            .unwrap();
+        assert_eq!(killed.details.as_ref().unwrap()["user"], "bob");
+    }
+
+    #[tokio::test]
+    async fn test_stats_window_and_reset() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state.record_connection().await;
+        state.record_query("SELECT").await;
+        state.record_maskings(&[(None, "email", "email")]).await;
+        let stats = |window: &str| {
+            get_stats(
+                State(state.clone()),
+                axum::extract::Query(StatsQuery {
+                    window: Some(window.to_string()),
+                }),
+            )
+        };
+
+        let (status, Json(json)) = stats("1h").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["window"]["duration"], "1h");
+        assert_eq!(json["window"]["connections"], 1);
+        assert_eq!(json["window"]["queries"]["select"], 1);
+        assert_eq!(json["window"]["masking"]["total"], 1);
+        for invalid in ["2x", "0m", "25h", "h"] {
+            assert_eq!(
+                stats(invalid).await.0,
+                StatusCode::BAD_REQUEST,
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(parse_stats_window("90m"), Some(90));
+        assert_eq!(parse_stats_window("1d"), Some(MAX_WINDOW_MINUTES));
+
+        let user = Some(Extension(AuthUser("admin".to_string())));
+        let json = reset_stats(State(state.clone()), ClientIp::default(), user)
+            .await
+            .0;
+        assert_eq!(json["cleared"]["queries"], 1);
+        let (_, Json(json)) = stats("1d").await;
+        assert_eq!(json["queries"]["total"], 0);
+        assert_eq!(json["total_connections"], 0);
+        assert_eq!(json["window"]["masking"]["total"], 0);
+
+        let filter = AuditFilter {
+            user_id: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let (_, entries) = state.audit_logger.query(&filter, 0, 100).await;
+        assert!(
+            entries
+                .iter()
+                .any(|e| e.event_type == AuditEventType::StatsReset)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_history_recorded_periodically() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        tokio::spawn(
+            state
+                .clone()
+                .run_history_recorder(std::time::Duration::from_millis(50)),
+        );
+
+        let json = get_stats(
+            State(state.clone()),
+            axum::extract::Query(StatsQuery::default()),
+        )
+        .await
+        .1
+        .0;
+        assert!(json["history"].as_array().unwrap().is_empty());
+
+        tokio::time::sleep(std::time::Duration::from_millis(120)).await;
+        let json = get_stats(State(state), axum::extract::Query(StatsQuery::default()))
+            .await
+            .1
+            .0;
+        assert!(!json["history"].as_array().unwrap().is_empty());
     }
 
     // Note: scan_database and get_schema tests require a real database connection
@@ -4,18 +4,26 @@
 //! - Authentication attempts (success/failure)
 //! - Configuration changes (rules, config updates)
 //! - Administrative actions
+//! - Detokenization of vault tokens
+//! - Retrieval of past scan results
+//! - Exports of the audit log itself
+//! - Proxied connections killed through the API
+//! - Statistics reset
 //!
 //! Logs can be written to stdout, file, or both with optional rotation.
 
+use crate::config::RuleId;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, mpsc};
 use tracing::{info, warn};
+use utoipa::ToSchema;
 
 /// Maximum number of audit entries to keep in memory
 const MAX_MEMORY_ENTRIES: usize = 1000;
@@ -27,7 +35,7 @@ const MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
 const MAX_ROTATED_FILES: usize = 5;
 
 /// Types of audit events
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AuditEventType {
     /// Authentication attempt
@@ -36,6 +44,8 @@ pub enum AuditEventType {
     ConfigChange,
     /// Rule added
     RuleAdded,
+    /// Rule edited in place
+    RuleUpdated,
     /// Rule deleted
     RuleDeleted,
     /// Rules imported
@@ -48,10 +58,24 @@ pub enum AuditEventType {
     SchemaQuery,
     /// API access (general)
     ApiAccess,
+    /// Token resolved back to its original value
+    Detokenize,
+    /// Scan history retrieved
+    ScanHistory,
+    /// Audit log exported
+    AuditExport,
+    /// Named API key created
+    ApiKeyCreated,
+    /// Named API key revoked
+    ApiKeyRevoked,
+    /// Proxied connection killed
+    ConnectionKilled,
+    /// Statistics counters reset
+    StatsReset,
 }
 
 /// Outcome of an audit event
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AuditOutcome {
     Success,
@@ -59,6 +83,163 @@ pub enum AuditOutcome {
     Denied,
 }
 
+/// Which audit entries a query returns; every field that is set must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<AuditEventType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<AuditOutcome>,
+    /// Entries at or after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<DateTime<Utc>>,
+    /// Entries before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<DateTime<Utc>>,
+    /// Text the endpoint contains
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.event_type
+            .as_ref()
+            .is_none_or(|t| *t == entry.event_type)
+            && self.outcome.as_ref().is_none_or(|o| *o == entry.outcome)
+            && self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp < to)
+            && self.endpoint.as_ref().is_none_or(|text| {
+                entry
+                    .endpoint
+                    .as_ref()
+                    .is_some_and(|endpoint| endpoint.contains(text.as_str()))
+            })
+            && self
+                .user_id
+                .as_ref()
+                .is_none_or(|user| entry.user_id.as_ref() == Some(user))
+    }
+}
+
+/// What started a config reload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadTrigger {
+    /// `POST /config/reload`
+    Api,
+    /// The config file changed on disk
+    FileChange,
+    /// SIGHUP
+    Signal,
+}
+
+/// File format of an audit export
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditExportFormat {
+    /// One JSON entry per line, as in the audit log file
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+/// Columns of a CSV export, in order
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "timestamp",
+    "event_type",
+    "outcome",
+    "client_ip",
+    "auth_method",
+    "user_id",
+    "endpoint",
+    "method",
+    "details",
+    "error",
+];
+
+impl AuditExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jsonl => "application/x-ndjson",
+            Self::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+
+    /// Text the export starts with
+    fn header(self) -> Option<String> {
+        match self {
+            Self::Jsonl => None,
+            Self::Csv => Some(format!("{}\r\n", CSV_COLUMNS.join(","))),
+        }
+    }
+
+    /// An entry as a line of the export
+    fn line(self, entry: &AuditEntry) -> String {
+        match self {
+            Self::Jsonl => {
+                let mut line = serde_json::to_string(entry).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+            Self::Csv => {
+                // Enums as their snake_case names, details as JSON
+                let name = |value: serde_json::Value| match value {
+                    serde_json::Value::String(name) => name,
+                    _ => String::new(),
+                };
+                let fields = [
+                    entry.id.clone(),
+                    entry.timestamp.to_rfc3339(),
+                    name(serde_json::json!(entry.event_type)),
+                    name(serde_json::json!(entry.outcome)),
+                    entry.client_ip.clone().unwrap_or_default(),
+                    entry
+                        .auth_method
+                        .as_ref()
+                        .map(|m| name(serde_json::json!(m)))
+                        .unwrap_or_default(),
+                    entry.user_id.clone().unwrap_or_default(),
+                    entry.endpoint.clone().unwrap_or_default(),
+                    entry.method.clone().unwrap_or_default(),
+                    entry
+                        .details
+                        .as_ref()
+                        .map(|d| d.to_string())
+                        .unwrap_or_default(),
+                    entry.error.clone().unwrap_or_default(),
+                ];
+                let mut line = fields
+                    .iter()
+                    .map(|field| csv_field(field))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                line.push_str("\r\n");
+                line
+            }
+        }
+    }
+}
+
+/// A CSV field, quoted when it holds a comma, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
 /// Authentication method used
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -255,7 +436,6 @@ impl AuditLogger {
     }
 
     /// Update the audit logger configuration
-    #[allow(dead_code)]
     pub async fn update_config(&self, config: AuditConfig) {
         let log_file_path = config.log_file.as_ref().map(PathBuf::from);
         *self.config.write().await = config;
@@ -368,43 +548,87 @@ impl AuditLogger {
         Ok(())
     }
 
-    /// Get recent audit entries
-    pub async fn get_entries(&self, limit: Option<usize>) -> Vec<AuditEntry> {
-        let entries = self.entries.read().await;
-        let limit = limit.unwrap_or(100).min(entries.len());
-        entries.iter().take(limit).cloned().collect()
-    }
-
-    /// Get entries filtered by event type
-    pub async fn get_entries_by_type(
+    /// Every entry matching the filter, oldest first, formatted for export
+    /// and sent a chunk at a time. With a log file, the rotated files and the
+    /// file are read back, followed by any entries only kept in memory;
+    /// without one, the entries in memory are all there is.
+    pub async fn export(
         &self,
-        event_type: AuditEventType,
-        limit: Option<usize>,
-    ) -> Vec<AuditEntry> {
-        let entries = self.entries.read().await;
-        let limit = limit.unwrap_or(100);
-        entries
-            .iter()
-            .filter(|e| e.event_type == event_type)
-            .take(limit)
-            .cloned()
-            .collect()
+        filter: AuditFilter,
+        format: AuditExportFormat,
+    ) -> mpsc::Receiver<std::io::Result<String>> {
+        let (tx, rx) = mpsc::channel(16);
+        let mut memory: Vec<AuditEntry> = self.entries.read().await.iter().rev().cloned().collect();
+        let files: Vec<PathBuf> = match &*self.log_file_path.read().await {
+            Some(path) => {
+                let max_files = self.config.read().await.max_rotated_files;
+                (1..=max_files)
+                    .rev()
+                    .map(|i| PathBuf::from(format!("{}.{}", path.display(), i)))
+                    .chain(std::iter::once(path.clone()))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let send = |chunk: std::io::Result<String>| tx.blocking_send(chunk).is_ok();
+            if let Some(header) = format.header()
+                && !send(Ok(header))
+            {
+                return;
+            }
+            let mut exported = HashSet::new();
+            for path in files {
+                let file = match File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => {
+                        send(Err(e));
+                        return;
+                    }
+                };
+                for line in BufReader::new(file).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            send(Err(e));
+                            return;
+                        }
+                    };
+                    // A line cut short by a crash is skipped
+                    let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                        continue;
+                    };
+                    if filter.matches(&entry) && !send(Ok(format.line(&entry))) {
+                        return;
+                    }
+                    exported.insert(entry.id);
+                }
+            }
+            memory.retain(|entry| !exported.contains(&entry.id) && filter.matches(entry));
+            for entry in memory {
+                if !send(Ok(format.line(&entry))) {
+                    return;
+                }
+            }
+        });
+        rx
     }
 
-    /// Get entries filtered by outcome
-    pub async fn get_entries_by_outcome(
+    /// A page of the entries matching the filter, newest first, and the
+    /// number of entries that match
+    pub async fn query(
         &self,
-        outcome: AuditOutcome,
-        limit: Option<usize>,
-    ) -> Vec<AuditEntry> {
+        filter: &AuditFilter,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<AuditEntry>) {
         let entries = self.entries.read().await;
-        let limit = limit.unwrap_or(100);
-        entries
-            .iter()
-            .filter(|e| e.outcome == outcome)
-            .take(limit)
-            .cloned()
-            .collect()
+        let matching = entries.iter().filter(|entry| filter.matches(entry));
+        let total = matching.clone().count();
+        let page = matching.skip(offset).take(limit).cloned().collect();
+        (total, page)
     }
 
     /// Create an authentication success entry
@@ -424,6 +648,17 @@ impl AuditLogger {
             .with_error(error)
     }
 
+    /// Create an entry for an authenticated caller refused for lacking the
+    /// endpoint's scope
+    pub fn scope_denied(method: AuthMethod, user_id: Option<String>, scope: &str) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::AuthAttempt, AuditOutcome::Denied)
+            .with_auth_method(method)
+            .with_error(format!("Missing scope '{}'", scope))
+            .with_details(serde_json::json!({ "missing_scope": scope }));
+        entry.user_id = user_id;
+        entry
+    }
+
     /// Create an authentication denied entry (no credentials provided)
     pub fn auth_denied() -> AuditEntry {
         AuditEntry::new(AuditEventType::AuthAttempt, AuditOutcome::Denied)
@@ -441,29 +676,58 @@ impl AuditLogger {
         AuditEntry::new(AuditEventType::RuleAdded, AuditOutcome::Success).with_details(rule)
     }
 
+    /// Create a rule updated entry
+    pub fn rule_updated(details: serde_json::Value) -> AuditEntry {
+        AuditEntry::new(AuditEventType::RuleUpdated, AuditOutcome::Success).with_details(details)
+    }
+
     /// Create a rule deleted entry
     pub fn rule_deleted(details: serde_json::Value) -> AuditEntry {
         AuditEntry::new(AuditEventType::RuleDeleted, AuditOutcome::Success).with_details(details)
     }
 
     /// Create a rules imported entry
-    pub fn rules_imported(count: usize) -> AuditEntry {
-        AuditEntry::new(AuditEventType::RulesImported, AuditOutcome::Success)
-            .with_details(serde_json::json!({ "rules_count": count }))
+    pub fn rules_imported(
+        mode: &str,
+        ids: &[RuleId],
+        added: usize,
+        updated: usize,
+        skipped: usize,
+    ) -> AuditEntry {
+        AuditEntry::new(AuditEventType::RulesImported, AuditOutcome::Success).with_details(
+            serde_json::json!({
+                "mode": mode,
+                "rules_count": ids.len(),
+                "ids": ids,
+                "added": added,
+                "updated": updated,
+                "skipped": skipped
+            }),
+        )
     }
 
     /// Create a config reload entry
-    pub fn config_reload(rules_count: usize) -> AuditEntry {
+    pub fn config_reload(rules_count: usize, trigger: ReloadTrigger) -> AuditEntry {
         AuditEntry::new(AuditEventType::ConfigReload, AuditOutcome::Success)
-            .with_details(serde_json::json!({ "rules_count": rules_count }))
+            .with_details(serde_json::json!({ "rules_count": rules_count, "trigger": trigger }))
     }
 
-    /// Create a database scan entry
-    pub fn database_scan(database: &str, findings_count: usize) -> AuditEntry {
+    /// Create an entry for a reload that failed, leaving the running config
+    /// in place
+    pub fn config_reload_failed(error: impl Into<String>, trigger: ReloadTrigger) -> AuditEntry {
+        AuditEntry::new(AuditEventType::ConfigReload, AuditOutcome::Failure)
+            .with_details(serde_json::json!({ "trigger": trigger }))
+            .with_error(error)
+    }
+
+    /// Create a database scan entry, for a scan requested through the API or
+    /// run by the scan schedule. The scan's credentials are never recorded.
+    pub fn database_scan(database: &str, findings_count: usize, scheduled: bool) -> AuditEntry {
         AuditEntry::new(AuditEventType::DatabaseScan, AuditOutcome::Success).with_details(
             serde_json::json!({
                 "database": database,
-                "findings_count": findings_count
+                "findings_count": findings_count,
+                "scheduled": scheduled
             }),
         )
     }
@@ -477,6 +741,122 @@ impl AuditLogger {
             }),
         )
     }
+
+    /// Create an audit export entry, naming the JWT subject that asked for it
+    pub fn audit_export(
+        user_id: Option<String>,
+        format: AuditExportFormat,
+        filter: &AuditFilter,
+    ) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::AuditExport, AuditOutcome::Success)
+            .with_details(serde_json::json!({
+                "format": format,
+                "filters": filter
+            }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create an entry for a client locked out after repeated authentication
+    /// failures
+    pub fn auth_lockout(client_ip: IpAddr, max_failures: u32, lockout_secs: u64) -> AuditEntry {
+        AuditEntry::new(AuditEventType::AuthAttempt, AuditOutcome::Denied)
+            .with_client_ip(client_ip.to_string())
+            .with_error("Too many failed authentication attempts")
+            .with_details(serde_json::json!({
+                "failures": max_failures,
+                "lockout_secs": lockout_secs
+            }))
+    }
+
+    /// Create an entry for a request refused because its client is locked
+    /// out
+    pub fn auth_locked_out(client_ip: IpAddr, retry_after_secs: u64) -> AuditEntry {
+        AuditEntry::new(AuditEventType::AuthAttempt, AuditOutcome::Denied)
+            .with_client_ip(client_ip.to_string())
+            .with_error("Locked out after too many failed authentication attempts")
+            .with_details(serde_json::json!({ "retry_after_secs": retry_after_secs }))
+    }
+
+    /// Create an API key creation entry, with the key's name and scopes but
+    /// never the key
+    pub fn api_key_created(user_id: Option<String>, name: &str, scopes: &[String]) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::ApiKeyCreated, AuditOutcome::Success)
+            .with_details(serde_json::json!({
+                "name": name,
+                "scopes": scopes
+            }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create an API key revocation entry
+    pub fn api_key_revoked(user_id: Option<String>, name: &str) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::ApiKeyRevoked, AuditOutcome::Success)
+            .with_details(serde_json::json!({ "name": name }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create a connection killed entry, with the session that was closed
+    pub fn connection_killed(
+        user_id: Option<String>,
+        connection: &crate::state::LiveConnection,
+    ) -> AuditEntry {
+        let info = &connection.info;
+        let mut entry = AuditEntry::new(AuditEventType::ConnectionKilled, AuditOutcome::Success)
+            .with_details(serde_json::json!({
+                "connection_id": info.connection_id,
+                "client_addr": info.client_addr,
+                "user": info.user,
+                "database": info.database,
+                "protocol": info.protocol
+            }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create a stats reset entry, with the totals that were cleared
+    pub fn stats_reset(user_id: Option<String>, cleared: serde_json::Value) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::StatsReset, AuditOutcome::Success)
+            .with_endpoint("/stats/reset")
+            .with_method("POST")
+            .with_details(serde_json::json!({ "cleared": cleared }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create a column stats reset entry, with the number of columns cleared
+    pub fn column_stats_reset(user_id: Option<String>, cleared: usize) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::StatsReset, AuditOutcome::Success)
+            .with_endpoint("/stats/columns/reset")
+            .with_method("POST")
+            .with_details(serde_json::json!({ "cleared_columns": cleared }));
+        entry.user_id = user_id;
+        entry
+    }
+
+    /// Create a scan history entry, for a page of the history or one scan
+    pub fn scan_history(scan_id: Option<&str>, scans_count: usize) -> AuditEntry {
+        AuditEntry::new(AuditEventType::ScanHistory, AuditOutcome::Success).with_details(
+            serde_json::json!({
+                "scan_id": scan_id,
+                "scans_count": scans_count
+            }),
+        )
+    }
+
+    /// Create a detokenize entry. Only the token is recorded, never the value.
+    pub fn detokenize(user_id: Option<String>, token: &str, outcome: AuditOutcome) -> AuditEntry {
+        let mut entry = AuditEntry::new(AuditEventType::Detokenize, outcome)
+            .with_endpoint("/detokenize")
+            .with_method("POST")
+            .with_details(serde_json::json!({ "token": token }));
+        if let Some(uid) = user_id {
+            entry = entry.with_user_id(uid);
+        }
+        entry
+    }
 }
 
 #[cfg(test)]
@@ -525,7 +905,7 @@ mod tests {
             ))
             .await;
 
-        let entries = logger.get_entries(Some(10)).await;
+        let entries = logger.query(&AuditFilter::default(), 0, 10).await.1;
         assert_eq!(entries.len(), 2);
         // Most recent first
         assert_eq!(entries[0].event_type, AuditEventType::ConfigChange);
@@ -543,7 +923,7 @@ mod tests {
             ))
             .await;
 
-        let entries = logger.get_entries(Some(10)).await;
+        let entries = logger.query(&AuditFilter::default(), 0, 10).await.1;
         assert_eq!(entries.len(), 0);
     }
 
@@ -569,13 +949,13 @@ mod tests {
             ))
             .await;
 
-        let entries = logger.get_entries(Some(10)).await;
+        let entries = logger.query(&AuditFilter::default(), 0, 10).await.1;
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].event_type, AuditEventType::AuthAttempt);
     }
 
     #[tokio::test]
-    async fn test_get_entries_by_type() {
+    async fn test_query_by_type() {
         let logger = AuditLogger::new(AuditConfig::default());
 
         logger
@@ -597,14 +977,21 @@ mod tests {
             ))
             .await;
 
-        let auth_entries = logger
-            .get_entries_by_type(AuditEventType::AuthAttempt, Some(10))
-            .await;
+        let filter = AuditFilter {
+            event_type: Some(AuditEventType::AuthAttempt),
+            ..Default::default()
+        };
+        let (total, auth_entries) = logger.query(&filter, 0, 10).await;
+        assert_eq!(total, 2);
         assert_eq!(auth_entries.len(), 2);
+        let (total, page) = logger.query(&filter, 1, 10).await;
+        assert_eq!(total, 2);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].outcome, AuditOutcome::Success);
     }
 
     #[tokio::test]
-    async fn test_get_entries_by_outcome() {
+    async fn test_query_by_outcome() {
         let logger = AuditLogger::new(AuditConfig::default());
 
         logger
@@ -626,10 +1013,163 @@ mod tests {
             ))
             .await;
 
-        let failures = logger
-            .get_entries_by_outcome(AuditOutcome::Failure, Some(10))
+        let failures = AuditFilter {
+            outcome: Some(AuditOutcome::Failure),
+            ..Default::default()
+        };
+        assert_eq!(logger.query(&failures, 0, 10).await.0, 2);
+
+        // Filters combine
+        let failed_auth = AuditFilter {
+            event_type: Some(AuditEventType::AuthAttempt),
+            ..failures
+        };
+        assert_eq!(logger.query(&failed_auth, 0, 10).await.0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_by_time_endpoint_and_user() {
+        let logger = AuditLogger::new(AuditConfig::default());
+        let now = Utc::now();
+        for (age_mins, endpoint, user) in [
+            (120, "/rules", "alice"),
+            (30, "/rules/import", "bob"),
+            (5, "/config", "alice"),
+        ] {
+            let mut entry = AuditLogger::auth_failure(AuthMethod::Jwt, "expired")
+                .with_endpoint(endpoint)
+                .with_user_id(user);
+            entry.timestamp = now - chrono::Duration::minutes(age_mins);
+            logger.log(entry).await;
+        }
+
+        let last_hour = AuditFilter {
+            from: Some(now - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(logger.query(&last_hour, 0, 10).await.0, 2);
+        let earlier = AuditFilter {
+            to: Some(now - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        let (_, entries) = logger.query(&earlier, 0, 10).await;
+        assert_eq!(entries[0].endpoint.as_deref(), Some("/rules"));
+        assert_eq!(entries.len(), 1);
+
+        let rules = AuditFilter {
+            endpoint: Some("rules".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(logger.query(&rules, 0, 10).await.0, 2);
+        let alices_rules = AuditFilter {
+            user_id: Some("alice".to_string()),
+            ..rules
+        };
+        assert_eq!(logger.query(&alices_rules, 0, 10).await.0, 1);
+    }
+
+    async fn collect(mut chunks: mpsc::Receiver<std::io::Result<String>>) -> String {
+        let mut export = String::new();
+        while let Some(chunk) = chunks.recv().await {
+            export.push_str(&chunk.unwrap());
+        }
+        export
+    }
+
+    #[tokio::test]
+    async fn test_export_reads_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(AuditConfig {
+            log_file: Some(path.to_str().unwrap().to_string()),
+            max_file_size_bytes: 500,
+            max_rotated_files: 20,
+            ..Default::default()
+        });
+        for i in 0..10 {
+            logger
+                .log(
+                    AuditLogger::config_reload(i, ReloadTrigger::Api)
+                        .with_endpoint("/config/reload"),
+                )
+                .await;
+        }
+        assert!(dir.path().join("audit.log.1").exists());
+
+        let export = collect(
+            logger
+                .export(AuditFilter::default(), AuditExportFormat::Jsonl)
+                .await,
+        )
+        .await;
+        let counts: Vec<u64> = export
+            .lines()
+            .map(|line| {
+                let entry: AuditEntry = serde_json::from_str(line).unwrap();
+                entry.details.unwrap()["rules_count"].as_u64().unwrap()
+            })
+            .collect();
+        // Oldest first, each once, although they are in memory too
+        assert_eq!(counts, (0..10).collect::<Vec<_>>());
+
+        // Entries only in memory, like those logged before the file was set
+        let logger = AuditLogger::new(AuditConfig::default());
+        logger
+            .log(AuditLogger::config_reload(1, ReloadTrigger::Api))
+            .await;
+        logger
+            .log(AuditLogger::config_reload(2, ReloadTrigger::Api))
+            .await;
+        let filter = AuditFilter {
+            event_type: Some(AuditEventType::ConfigReload),
+            ..Default::default()
+        };
+        let export = collect(logger.export(filter, AuditExportFormat::Jsonl).await).await;
+        assert_eq!(export.lines().count(), 2);
+        assert!(export.lines().next().unwrap().contains("\"rules_count\":1"));
+    }
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let logger = AuditLogger::new(AuditConfig::default());
+        logger
+            .log(
+                AuditLogger::rule_added(
+                    serde_json::json!({ "column": "e,mail", "note": "say \"hi\"" }),
+                )
+                .with_endpoint("/rules")
+                .with_method("POST"),
+            )
             .await;
-        assert_eq!(failures.len(), 2);
+        logger.log(AuditLogger::auth_denied()).await;
+
+        let export = collect(
+            logger
+                .export(AuditFilter::default(), AuditExportFormat::Csv)
+                .await,
+        )
+        .await;
+        let lines: Vec<&str> = export.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            "id,timestamp,event_type,outcome,client_ip,auth_method,user_id,endpoint,method,details,error"
+        );
+        let fields: Vec<&str> = lines[1].splitn(9, ',').collect();
+        assert_eq!(fields[2], "rule_added");
+        assert_eq!(fields[3], "success");
+        assert_eq!(fields[7], "/rules");
+        assert!(
+            fields[8].ends_with(r#"POST,"{""column"":""e,mail"",""note"":""say \""hi\""""}","#),
+            "{}",
+            fields[8]
+        );
+        assert!(
+            lines[2].contains(",auth_attempt,denied,,none,"),
+            "{}",
+            lines[2]
+        );
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[3], "");
     }
 
     #[test]
@@ -655,17 +1195,24 @@ mod tests {
         let rule_deleted = AuditLogger::rule_deleted(serde_json::json!({"index": 0}));
         assert_eq!(rule_deleted.event_type, AuditEventType::RuleDeleted);
 
-        let rules_imported = AuditLogger::rules_imported(5);
+        let rule_updated = AuditLogger::rule_updated(serde_json::json!({"id": "a"}));
+        assert_eq!(rule_updated.event_type, AuditEventType::RuleUpdated);
+
+        let rules_imported = AuditLogger::rules_imported("append", &[RuleId::default()], 1, 0, 0);
         assert_eq!(rules_imported.event_type, AuditEventType::RulesImported);
 
-        let config_reload = AuditLogger::config_reload(10);
+        let config_reload = AuditLogger::config_reload(10, ReloadTrigger::Api);
         assert_eq!(config_reload.event_type, AuditEventType::ConfigReload);
 
-        let db_scan = AuditLogger::database_scan("testdb", 3);
+        let db_scan = AuditLogger::database_scan("testdb", 3, false);
         assert_eq!(db_scan.event_type, AuditEventType::DatabaseScan);
 
         let schema_query = AuditLogger::schema_query("testdb", 5);
         assert_eq!(schema_query.event_type, AuditEventType::SchemaQuery);
+
+        let key_created = AuditLogger::api_key_created(None, "ci", &["rules:read".to_string()]);
+        assert_eq!(key_created.event_type, AuditEventType::ApiKeyCreated);
+        assert_eq!(key_created.details.unwrap()["name"], "ci");
     }
 
     #[tokio::test]
@@ -679,7 +1226,7 @@ mod tests {
             logger.log(entry).await;
         }
 
-        let entries = logger.get_entries(None).await;
+        let entries = logger.query(&AuditFilter::default(), 0, usize::MAX).await.1;
         assert!(entries.len() <= MAX_MEMORY_ENTRIES);
     }
 }
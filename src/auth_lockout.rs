@@ -0,0 +1,171 @@
+//! Lockout of clients that keep failing management API authentication.
+//!
+//! Failed attempts are counted per source IP over a sliding window. A client
+//! reaching `api.lockout.max_failures` is refused with 429 for
+//! `lockout_secs`, without its credentials being checked, so that guessing
+//! API keys costs the attacker time instead of costing us a comparison each.
+
+use crate::config::LockoutConfig;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Clients tracked before those without recent failures are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct ClientFailures {
+    /// Times of the failures within the window, oldest first
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl ClientFailures {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while self
+            .failures
+            .front()
+            .is_some_and(|failed| now.duration_since(*failed) >= window)
+        {
+            self.failures.pop_front();
+        }
+        if self.locked_until.is_some_and(|until| until <= now) {
+            self.locked_until = None;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.failures.is_empty() && self.locked_until.is_none()
+    }
+}
+
+/// Failed authentication attempts by client IP
+#[derive(Debug, Default)]
+pub struct AuthLockout {
+    clients: Mutex<HashMap<IpAddr, ClientFailures>>,
+}
+
+impl AuthLockout {
+    /// How much longer `ip` is locked out, if it is
+    pub fn remaining(&self, ip: IpAddr) -> Option<Duration> {
+        self.remaining_at(ip, Instant::now())
+    }
+
+    fn remaining_at(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients
+            .get(&ip)?
+            .locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Count a failed attempt from `ip`, returning the lockout it started,
+    /// if this was the failure that reached the limit
+    pub fn record_failure(&self, ip: IpAddr, config: &LockoutConfig) -> Option<Duration> {
+        self.record_failure_at(ip, config, Instant::now())
+    }
+
+    fn record_failure_at(
+        &self,
+        ip: IpAddr,
+        config: &LockoutConfig,
+        now: Instant,
+    ) -> Option<Duration> {
+        let window = Duration::from_secs(config.window_secs);
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&ip) {
+            clients.retain(|_, client| {
+                client.expire(now, window);
+                !client.is_idle()
+            });
+        }
+
+        let client = clients.entry(ip).or_default();
+        client.expire(now, window);
+        client.failures.push_back(now);
+        if client.locked_until.is_none() && client.failures.len() >= config.max_failures as usize {
+            let lockout = Duration::from_secs(config.lockout_secs);
+            client.failures.clear();
+            client.locked_until = Some(now + lockout);
+            return Some(lockout);
+        }
+        None
+    }
+
+    /// Forget the failures of a client that has authenticated
+    pub fn record_success(&self, ip: IpAddr) {
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LockoutConfig {
+        LockoutConfig {
+            enabled: true,
+            max_failures: 3,
+            window_secs: 60,
+            lockout_secs: 300,
+        }
+    }
+
+    #[test]
+    fn test_lockout_after_max_failures() {
+        let lockout = AuthLockout::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(lockout.record_failure_at(ip, &config(), start).is_none());
+        assert!(lockout.record_failure_at(ip, &config(), start).is_none());
+        assert!(lockout.record_failure_at(other, &config(), start).is_none());
+        assert_eq!(
+            lockout.record_failure_at(ip, &config(), start),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            lockout.remaining_at(ip, start + Duration::from_secs(100)),
+            Some(Duration::from_secs(200))
+        );
+        assert!(lockout.remaining_at(other, start).is_none());
+        assert!(
+            lockout
+                .remaining_at(ip, start + Duration::from_secs(300))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_failures_outside_window_expire() {
+        let lockout = AuthLockout::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        lockout.record_failure_at(ip, &config(), start);
+        lockout.record_failure_at(ip, &config(), start + Duration::from_secs(30));
+        // The first failure has left the window
+        let later = start + Duration::from_secs(61);
+        assert!(lockout.record_failure_at(ip, &config(), later).is_none());
+        assert!(lockout.record_failure_at(ip, &config(), later).is_some());
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let lockout = AuthLockout::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        lockout.record_failure_at(ip, &config(), start);
+        lockout.record_failure_at(ip, &config(), start);
+        lockout.record_success(ip);
+        assert!(lockout.record_failure_at(ip, &config(), start).is_none());
+        assert!(lockout.remaining_at(ip, start).is_none());
+    }
+}
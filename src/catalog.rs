@@ -0,0 +1,292 @@
+//! Postgres catalog lookups.
+//!
+//! `RowDescription` only carries the OID of the table a column came from. To
+//! enforce table-scoped masking rules, the proxy resolves those OIDs against
+//! `pg_class`/`pg_namespace` over a side connection and caches the result for
+//! the lifetime of the proxied connection.
+
+use crate::config::{CatalogLookupConfig, NamePattern};
+use crate::tls::MakeRustlsConnect;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio_postgres::Client;
+use tokio_postgres::config::SslMode;
+use tracing::{debug, warn};
+
+/// A schema-qualified table name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableName {
+    pub schema: String,
+    pub name: String,
+}
+
+impl TableName {
+    pub fn new(schema: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            schema: schema.into(),
+            name: name.into(),
+        }
+    }
+
+    /// Check whether a rule's `table` value refers to this table.
+    /// Accepts either a bare table name or a `schema.table` pair; globs and
+    /// regexes may match either form.
+    pub fn matches(&self, pattern: &NamePattern) -> bool {
+        if !pattern.is_exact() {
+            return pattern.matches(&self.name)
+                || pattern.matches(&format!("{}.{}", self.schema, self.name));
+        }
+        match pattern.as_str().split_once('.') {
+            Some((schema, name)) => self.schema == schema && self.name == name,
+            None => self.name == pattern.as_str(),
+        }
+    }
+}
+
+/// Per-connection resolver for table OIDs
+pub struct TableResolver {
+    host: String,
+    port: u16,
+    database: Option<String>,
+    client: Option<Client>,
+    cache: HashMap<u32, TableName>,
+    /// Set after a failed connect so we don't retry on every result set
+    unavailable: bool,
+}
+
+impl TableResolver {
+    pub fn new(host: String, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            database: None,
+            client: None,
+            cache: HashMap::new(),
+            unavailable: false,
+        }
+    }
+
+    /// Set the database the client connected to; OIDs are only meaningful within it
+    pub fn set_database(&mut self, database: String) {
+        if self.database.as_ref() != Some(&database) {
+            self.database = Some(database);
+            self.client = None;
+            self.cache.clear();
+            self.unavailable = false;
+        }
+    }
+
+    /// Whether the next lookup opens the side connection, and so needs the
+    /// upstream TLS settings
+    pub fn needs_connection(&self) -> bool {
+        self.client.is_none() && !self.unavailable
+    }
+
+    /// Seed the cache with a known mapping
+    #[cfg(test)]
+    pub fn insert(&mut self, oid: u32, table: TableName) {
+        self.cache.insert(oid, table);
+    }
+
+    /// Resolve the given OIDs, returning whatever could be determined.
+    /// OIDs missing from the result are unknown (no lookup configured, lookup
+    /// failed, or not a table). `tls` is the connector of the upstream TLS
+    /// settings, needed when the side connection isn't open yet.
+    pub async fn resolve(
+        &mut self,
+        oids: &[u32],
+        config: Option<&CatalogLookupConfig>,
+        tls: Option<anyhow::Result<(MakeRustlsConnect, SslMode)>>,
+    ) -> HashMap<u32, TableName> {
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+        for &oid in oids {
+            if oid == 0 || resolved.contains_key(&oid) {
+                continue;
+            }
+            match self.cache.get(&oid) {
+                Some(table) => {
+                    resolved.insert(oid, table.clone());
+                }
+                None => missing.push(oid),
+            }
+        }
+
+        if missing.is_empty() || self.unavailable {
+            return resolved;
+        }
+        let Some(config) = config else {
+            return resolved;
+        };
+
+        match self.lookup(&missing, config, tls).await {
+            Ok(found) => {
+                if self.cache.len() + found.len() > config.cache_size {
+                    self.cache.clear();
+                }
+                for (oid, table) in found {
+                    self.cache.insert(oid, table.clone());
+                    resolved.insert(oid, table);
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Table OID lookup failed, table-scoped rules will match any table: {}",
+                    e
+                );
+                self.client = None;
+                self.unavailable = true;
+            }
+        }
+
+        resolved
+    }
+
+    async fn lookup(
+        &mut self,
+        oids: &[u32],
+        config: &CatalogLookupConfig,
+        tls: Option<anyhow::Result<(MakeRustlsConnect, SslMode)>>,
+    ) -> anyhow::Result<Vec<(u32, TableName)>> {
+        if self.client.is_none() {
+            self.client = Some(self.connect(config, tls).await?);
+        }
+        let client = self.client.as_ref().expect("client connected above");
+
+        let rows = client
+            .query(
+                "SELECT c.oid, n.nspname::text, c.relname::text \
+                 FROM pg_catalog.pg_class c \
+                 JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE c.oid = ANY($1)",
+                &[&oids],
+            )
+            .await?;
+
+        let found: Vec<(u32, TableName)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get(0),
+                    TableName::new(row.get::<_, String>(1), row.get::<_, String>(2)),
+                )
+            })
+            .collect();
+        debug!("Resolved {} of {} table OIDs", found.len(), oids.len());
+        Ok(found)
+    }
+
+    async fn connect(
+        &self,
+        config: &CatalogLookupConfig,
+        tls: Option<anyhow::Result<(MakeRustlsConnect, SslMode)>>,
+    ) -> anyhow::Result<Client> {
+        let (connector, ssl_mode) = tls
+            .transpose()?
+            .context("No upstream TLS settings for the catalog connection")?;
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&self.host)
+            .port(self.port)
+            .user(&config.username)
+            .password(&config.password)
+            .dbname(self.database.as_deref().unwrap_or(&config.username))
+            .application_name("ironveil-catalog")
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .ssl_mode(ssl_mode);
+
+        let (client, connection) = pg_config.connect(connector).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("Catalog lookup connection closed: {}", e);
+            }
+        });
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_name_matches() {
+        let table = TableName::new("public", "users");
+
+        let matches = |pattern: &str| table.matches(&pattern.parse().unwrap());
+
+        assert!(matches("users"));
+        assert!(matches("public.users"));
+        assert!(!matches("audit"));
+        assert!(!matches("audit.users"));
+
+        assert!(matches("user*"));
+        assert!(matches("public.*"));
+        assert!(!matches("audit.*"));
+        assert!(matches("re:^(public|app)\\.users$"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_uses_cache_without_config() {
+        let mut resolver = TableResolver::new("localhost".to_string(), 5432);
+        resolver.insert(100, TableName::new("public", "users"));
+
+        let resolved = resolver.resolve(&[0, 100, 200], None, None).await;
+
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[&100], TableName::new("public", "users"));
+    }
+
+    #[tokio::test]
+    async fn test_set_database_clears_cache() {
+        let mut resolver = TableResolver::new("localhost".to_string(), 5432);
+        resolver.set_database("app".to_string());
+        resolver.insert(100, TableName::new("public", "users"));
+
+        resolver.set_database("other".to_string());
+
+        assert!(resolver.resolve(&[100], None, None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_connection_follows_upstream_tls_mode() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // An upstream that refuses TLS, reporting what it was sent first
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 8];
+            socket.read_exact(&mut request).await.unwrap();
+            socket.write_all(b"N").await.unwrap();
+            let mut rest = Vec::new();
+            let _ = socket.read_to_end(&mut rest).await;
+            (request, rest)
+        });
+
+        let app = crate::config::AppConfig {
+            upstream_tls_mode: crate::config::UpstreamTlsMode::Require,
+            ..Default::default()
+        };
+        let lookup: CatalogLookupConfig =
+            serde_yaml::from_str("{ username: catalog, password: secret }").unwrap();
+        let mut resolver = TableResolver::new("127.0.0.1".to_string(), port);
+        assert!(resolver.needs_connection());
+        let resolved = resolver
+            .resolve(
+                &[100],
+                Some(&lookup),
+                Some(crate::tls::postgres_connector(&app)),
+            )
+            .await;
+        assert!(resolved.is_empty());
+        assert!(!resolver.needs_connection());
+
+        // An SSLRequest, and nothing in cleartext once it was refused
+        let (request, rest) = upstream.await.unwrap();
+        assert_eq!(request, [0, 0, 0, 8, 4, 210, 22, 47]);
+        assert!(rest.is_empty());
+    }
+}
@@ -1,6 +1,25 @@
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use crate::protocol::mysql::{
+    MYSQL_TYPE_BLOB, MYSQL_TYPE_DATE, MYSQL_TYPE_ENUM, MYSQL_TYPE_JSON, MYSQL_TYPE_LONG_BLOB,
+    MYSQL_TYPE_MEDIUM_BLOB, MYSQL_TYPE_NEWDATE, MYSQL_TYPE_SET, MYSQL_TYPE_STRING,
+    MYSQL_TYPE_TINY_BLOB, MYSQL_TYPE_VAR_STRING, MYSQL_TYPE_VARCHAR,
+};
+use crate::protocol::postgres::type_oid;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Days, NaiveDate, Timelike, Utc};
+use ipnet::IpNet;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use subtle::ConstantTimeEq;
+use utoipa::ToSchema;
+use utoipa::openapi::RefOr;
+use utoipa::openapi::schema::{ObjectBuilder, Schema, Type};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppConfig {
@@ -11,6 +30,21 @@ pub struct AppConfig {
     pub tls: Option<TlsConfig>,
     #[serde(default)]
     pub upstream_tls: bool,
+    /// How strictly upstream TLS is enforced (default: prefer)
+    #[serde(default)]
+    pub upstream_tls_mode: UpstreamTlsMode,
+    /// PEM bundle of CAs trusted for the upstream certificate (default: OS trust store)
+    #[serde(default)]
+    pub upstream_tls_ca_path: Option<String>,
+    /// Client certificate presented to the upstream (mutual TLS)
+    #[serde(default)]
+    pub upstream_tls_client_cert_path: Option<String>,
+    /// Private key for `upstream_tls_client_cert_path`
+    #[serde(default)]
+    pub upstream_tls_client_key_path: Option<String>,
+    /// Name used for SNI and certificate verification instead of the upstream host
+    #[serde(default)]
+    pub upstream_tls_server_name: Option<String>,
     #[serde(default)]
     pub telemetry: Option<TelemetryConfig>,
     #[serde(default)]
@@ -21,6 +55,767 @@ pub struct AppConfig {
     pub health_check: Option<HealthCheckConfig>,
     #[serde(default)]
     pub audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub catalog_lookup: Option<CatalogLookupConfig>,
+    /// What to do when PII is detected in `COPY ... FROM STDIN` data (default: allow)
+    #[serde(default)]
+    pub copy_in_policy: CopyInPolicy,
+    /// What to do when PII is detected in MySQL `LOAD DATA LOCAL INFILE`
+    /// data (default: allow)
+    #[serde(default)]
+    pub local_infile_policy: CopyInPolicy,
+    /// Where the proxy and the API listen, and the upstream; overridden by
+    /// the CLI flags
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Proxy listeners; when empty, a single listener is built from `server`
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// MySQL capabilities removed from both sides of the handshake because
+    /// the proxy cannot parse the traffic they enable
+    #[serde(default = "default_mysql_strip_capabilities")]
+    pub mysql_strip_capabilities: Vec<MySqlCapability>,
+    /// Which MySQL column names rules are matched against (default: original_or_alias)
+    #[serde(default)]
+    pub mysql_column_match: ColumnMatch,
+    /// Whether rules, the PII scanner or both decide what is masked (default: both)
+    #[serde(default)]
+    pub masking_mode: MaskingMode,
+    /// How values flagged by the PII scanner, without a matching rule, are masked
+    #[serde(default)]
+    pub heuristic_masking: HeuristicMaskingConfig,
+    /// Settings shared by the masking strategies
+    #[serde(default)]
+    pub masking: MaskingConfig,
+    /// Token vault for the `tokenize` strategy (optional)
+    #[serde(default)]
+    pub tokenization: Option<TokenizationConfig>,
+    /// Per-client overrides of `masking_enabled` and `rules`; the first
+    /// policy matching a connection applies to it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<MaskingPolicy>,
+    /// Named rule sets that policies can use instead of `rules`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rule_sets: BTreeMap<String, Vec<MaskingRule>>,
+    #[serde(default)]
+    pub logs: LogsConfig,
+    #[serde(default)]
+    pub scanner: ScannerConfig,
+    /// Timestamped copies of the config file kept when changes made through
+    /// the API are saved over it (default: 5, 0 keeps none)
+    #[serde(default = "default_config_backups")]
+    pub config_backups: usize,
+    /// Reloading the config when the file changes on disk
+    #[serde(default)]
+    pub config_watch: ConfigWatchConfig,
+    /// The file as read, before `${VAR}` interpolation, so that saving puts
+    /// the placeholders back instead of the values they resolved to
+    #[serde(skip)]
+    pub template: Option<serde_yaml::Value>,
+}
+
+/// Watching the config file, read at startup
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ConfigWatchConfig {
+    /// Reload when the file changes (default: true)
+    #[serde(default = "default_config_watch_enabled")]
+    pub enabled: bool,
+    /// How long the file must stay unchanged before it is reloaded, so that
+    /// an editor or deploy writing it in several steps triggers one reload
+    /// (default: 500)
+    #[serde(default = "default_config_watch_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+impl Default for ConfigWatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            debounce_ms: default_config_watch_debounce_ms(),
+        }
+    }
+}
+
+fn default_config_watch_enabled() -> bool {
+    true
+}
+
+fn default_config_watch_debounce_ms() -> u64 {
+    500
+}
+
+/// What is kept in the log entries served by `/logs`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct LogsConfig {
+    /// Replace the literals of logged queries that look like PII with `***`
+    /// (default: true)
+    #[serde(default = "default_redact_queries")]
+    pub redact_queries: bool,
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        Self {
+            redact_queries: default_redact_queries(),
+        }
+    }
+}
+
+fn default_redact_queries() -> bool {
+    true
+}
+
+/// How the PII scanner classifies values
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ScannerConfig {
+    /// Only treat numbers passing the Luhn check as card numbers (default: true)
+    #[serde(default = "default_strict_credit_card")]
+    pub strict_credit_card: bool,
+    /// Organisation-specific identifiers, checked before the built-in types
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_patterns: Vec<CustomPattern>,
+    /// Database logins for `/scan` and `/schema`, by name. Requests without
+    /// a username use `default`, or the one their `credentials_ref` names.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub credentials: BTreeMap<String, ScannerCredentials>,
+    /// JSON lines file completed scans are appended to, and the scan history
+    /// is loaded from at startup (default: kept in memory only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<String>,
+    /// Words in a column comment, such as `COMMENT ON COLUMN ... IS 'PII:
+    /// customer email'`, that mark the column as holding PII. Matched
+    /// case-insensitively as whole words (default: pii, gdpr, sensitive).
+    #[serde(default = "default_comment_keywords")]
+    pub comment_keywords: Vec<String>,
+    /// Scan run in the background on a schedule, for continuous discovery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScanScheduleConfig>,
+}
+
+impl Default for ScannerConfig {
+    fn default() -> Self {
+        Self {
+            strict_credit_card: default_strict_credit_card(),
+            custom_patterns: Vec::new(),
+            credentials: BTreeMap::new(),
+            history_file: None,
+            comment_keywords: default_comment_keywords(),
+            schedule: None,
+        }
+    }
+}
+
+/// A scan run every `interval_secs` or whenever `cron` fires. Runs go
+/// through the same jobs and history as `POST /scan`, and a run is skipped
+/// while the previous one is still going.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ScanScheduleConfig {
+    /// Five-field cron expression in UTC, such as `0 3 * * 1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<CronSchedule>,
+    /// Seconds between runs, counted from startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    /// What to scan, as the body of `POST /scan`. The login comes from
+    /// `scanner.credentials`, so no password may be given here.
+    pub scan: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ScanScheduleConfig {
+    /// When the scan is next due, given when it last ran (or the scheduler
+    /// started). Overdue runs are due now.
+    pub fn next_run(&self, last: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match (&self.cron, self.interval_secs) {
+            (Some(cron), _) => cron.next_after(last.max(now)),
+            (None, Some(secs)) => {
+                let interval = chrono::Duration::try_seconds(i64::try_from(secs).ok()?)?;
+                Some(last.checked_add_signed(interval)?.max(now))
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match (&self.cron, self.interval_secs) {
+            (Some(_), Some(_)) => bail!("Scan schedule sets both cron and interval_secs"),
+            (None, None) => bail!("Scan schedule needs cron or interval_secs"),
+            (None, Some(0)) => bail!("Scan schedule interval_secs must be positive"),
+            _ => {}
+        }
+        if self.scan.contains_key("password") {
+            bail!("Scheduled scans take their login from scanner.credentials, not a password");
+        }
+        Ok(())
+    }
+}
+
+/// A five-field cron expression: minute, hour, day of month, month and day
+/// of week (0 or 7 for Sunday). Fields take `*`, numbers, `a-b` ranges,
+/// `/step` and comma-separated lists. As in cron, a day matches if either
+/// day field does when both are restricted.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether each day field is restricted rather than `*`
+    restricted_days: (bool, bool),
+}
+
+impl CronSchedule {
+    pub fn new(source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        let fields: Vec<&str> = source.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            bail!("expected 5 fields, found {}", fields.len());
+        };
+        let mut days_of_week = parse_field(days_of_week, 0, 7).context("day of week")?;
+        // 7 is Sunday too
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59).context("minute")?,
+            hours: parse_field(hours, 0, 23).context("hour")?,
+            days_of_month: parse_field(days_of_month, 1, 31).context("day of month")?,
+            months: parse_field(months, 1, 12).context("month")?,
+            days_of_week,
+            restricted_days: (!fields[2].starts_with('*'), !fields[4].starts_with('*')),
+            source,
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day_of_month = self.days_of_month & (1 << date.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match self.restricted_days {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// The first minute after `time` the expression matches, or `None` if it
+    /// never does (such as `0 0 30 2 *`)
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0).map(|t| t.and_utc());
+        let mut t = time.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        // Every day of the next four years gets a look, leap days included
+        for _ in 0..100_000 {
+            if self.months & (1 << t.month()) == 0 {
+                let (year, month) = match t.month() {
+                    12 => (t.year() + 1, 1),
+                    month => (t.year(), month + 1),
+                };
+                t = midnight(NaiveDate::from_ymd_opt(year, month, 1)?)?;
+            } else if !self.matches_day(t.date_naive()) {
+                t = midnight(t.date_naive().checked_add_days(Days::new(1))?)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + chrono::Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += chrono::Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// The values a cron field matches, as a bit set
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)),
+            None => (item, Some(1)),
+        };
+        let Some(step) = step else {
+            bail!("invalid step in '{}'", item);
+        };
+        let value = |s: &str| match s.parse::<u32>() {
+            Ok(v) if (min..=max).contains(&v) => Ok(v),
+            _ => Err(anyhow::anyhow!(
+                "'{}' is not a number from {} to {}",
+                s,
+                min,
+                max
+            )),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` runs from 5 to the end
+                None if item.contains('/') => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            bail!("range '{}' runs backwards", range);
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl std::str::FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl std::fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for CronSchedule {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for CronSchedule {}
+
+impl Serialize for CronSchedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for CronSchedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        CronSchedule::new(source.clone()).map_err(|e| {
+            serde::de::Error::custom(format!("invalid cron expression '{}': {:#}", source, e))
+        })
+    }
+}
+
+/// A database login for the scanner. The password is given at most one way,
+/// and read each time a scan starts so that rotated secrets are picked up.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ScannerCredentials {
+    pub username: String,
+    /// Never returned by the management API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File holding the password, such as a mounted secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<String>,
+    /// Environment variable holding the password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+}
+
+impl ScannerCredentials {
+    /// The password, empty when none is configured
+    pub fn resolve_password(&self) -> Result<String> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+        if let Some(path) = &self.password_file {
+            let password = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read password file {}", path))?;
+            return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+        }
+        if let Some(var) = &self.password_env {
+            return std::env::var(var)
+                .with_context(|| format!("Password variable {} is not set", var));
+        }
+        Ok(String::new())
+    }
+}
+
+/// A kind of identifier for the PII scanner to recognise, such as employee
+/// IDs or medical record numbers
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct CustomPattern {
+    /// Reported as the PII type of matching values
+    pub name: String,
+    /// Regex a whole value must match
+    pub regex: RulePattern,
+    /// How matching values are masked, whatever `heuristic_masking.strategy` says
+    pub strategy: MaskStrategy,
+    /// Checksum matching values must also pass (default: none)
+    #[serde(default)]
+    pub validate: PatternValidation,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternValidation {
+    #[default]
+    None,
+    /// The digits of the value pass the Luhn check
+    Luhn,
+}
+
+fn default_strict_credit_card() -> bool {
+    true
+}
+
+pub fn default_comment_keywords() -> Vec<String> {
+    ["pii", "gdpr", "sensitive"].map(String::from).to_vec()
+}
+
+/// Masking settings for the connections matching `match`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MaskingPolicy {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub criteria: PolicyMatch,
+    /// Overrides the global `masking_enabled` (default: unchanged)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub masking_enabled: Option<bool>,
+    /// Entry of `rule_sets` used instead of `rules` (default: `rules`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_set: Option<String>,
+}
+
+/// Criteria a connection has to meet, all of the ones set. A policy without
+/// any matches every connection.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PolicyMatch {
+    /// Database user from the startup message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    /// Postgres `application_name` startup parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    /// Network the client connects from, e.g. `10.0.0.0/8`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cidr: Option<IpNet>,
+}
+
+impl PolicyMatch {
+    pub fn matches(
+        &self,
+        user: Option<&str>,
+        database: Option<&str>,
+        application_name: Option<&str>,
+        client_addr: Option<IpAddr>,
+    ) -> bool {
+        let matches = |expected: &Option<String>, actual: Option<&str>| {
+            expected.as_deref().is_none_or(|e| actual == Some(e))
+        };
+        matches(&self.user, user)
+            && matches(&self.database, database)
+            && matches(&self.application_name, application_name)
+            && self
+                .client_cidr
+                .is_none_or(|cidr| client_addr.is_some_and(|addr| cidr.contains(&addr)))
+    }
+}
+
+/// Database wire protocol spoken by a listener and its upstream
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DbProtocol {
+    #[default]
+    Postgres,
+    Mysql,
+}
+
+/// A port the proxy accepts clients on, and the upstream it forwards them to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ListenerConfig {
+    /// Name used to tag logs and metrics (default: "<protocol>-<port>")
+    #[serde(default)]
+    pub name: Option<String>,
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: DbProtocol,
+    #[serde(default = "default_upstream_host")]
+    pub upstream_host: String,
+    pub upstream_port: u16,
+}
+
+fn default_upstream_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl ListenerConfig {
+    /// The configured name, or one derived from protocol and port
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            let protocol = match self.protocol {
+                DbProtocol::Postgres => "postgres",
+                DbProtocol::Mysql => "mysql",
+            };
+            format!("{}-{}", protocol, self.port)
+        })
+    }
+}
+
+/// Where the proxy and the management API listen, and the upstream database.
+/// Each setting can be overridden by the CLI flag of the same name.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Port the proxy listens on, when `listeners` is empty (default: 6543)
+    #[serde(default = "default_listen_port")]
+    pub listen_port: u16,
+    /// Address the proxy listeners bind to (default: 0.0.0.0)
+    #[serde(default = "default_listen_address")]
+    pub listen_address: IpAddr,
+    /// Port of the management API (default: 3001)
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+    /// Address the management API binds to (default: `api.bind_address`, or
+    /// all interfaces)
+    #[serde(default)]
+    pub api_bind: Option<IpAddr>,
+    /// Protocol of the listener built from these settings (default: postgres)
+    #[serde(default)]
+    pub protocol: DbProtocol,
+    /// Upstream database, read again for every new connection
+    #[serde(default = "default_upstream_host")]
+    pub upstream_host: String,
+    #[serde(default = "default_upstream_port")]
+    pub upstream_port: u16,
+    /// Seconds to wait for connections to close on shutdown (default: 30)
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_port: default_listen_port(),
+            listen_address: default_listen_address(),
+            api_port: default_api_port(),
+            api_bind: None,
+            protocol: DbProtocol::Postgres,
+            upstream_host: default_upstream_host(),
+            upstream_port: default_upstream_port(),
+            shutdown_timeout: default_shutdown_timeout(),
+        }
+    }
+}
+
+fn default_listen_port() -> u16 {
+    6543
+}
+
+fn default_listen_address() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_api_port() -> u16 {
+    3001
+}
+
+fn default_upstream_port() -> u16 {
+    5432
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+/// `server` settings given as CLI flags, which win over the config file
+#[derive(Debug, Clone, Default)]
+pub struct ServerOverrides {
+    pub listen_port: Option<u16>,
+    pub listen_address: Option<IpAddr>,
+    pub api_port: Option<u16>,
+    pub api_bind: Option<IpAddr>,
+    pub protocol: Option<DbProtocol>,
+    pub upstream_host: Option<String>,
+    pub upstream_port: Option<u16>,
+    pub shutdown_timeout: Option<u64>,
+}
+
+impl ServerConfig {
+    /// These settings with the CLI flags applied
+    pub fn with_overrides(&self, overrides: &ServerOverrides) -> ServerConfig {
+        ServerConfig {
+            listen_port: overrides.listen_port.unwrap_or(self.listen_port),
+            listen_address: overrides.listen_address.unwrap_or(self.listen_address),
+            api_port: overrides.api_port.unwrap_or(self.api_port),
+            api_bind: overrides.api_bind.or(self.api_bind),
+            protocol: overrides.protocol.unwrap_or(self.protocol),
+            upstream_host: overrides
+                .upstream_host
+                .clone()
+                .unwrap_or_else(|| self.upstream_host.clone()),
+            upstream_port: overrides.upstream_port.unwrap_or(self.upstream_port),
+            shutdown_timeout: overrides.shutdown_timeout.unwrap_or(self.shutdown_timeout),
+        }
+    }
+
+    /// Settings that only take effect on restart and differ in `other`
+    pub fn restart_required(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.listen_port != other.listen_port {
+            changed.push("listen_port");
+        }
+        if self.listen_address != other.listen_address {
+            changed.push("listen_address");
+        }
+        if self.api_port != other.api_port {
+            changed.push("api_port");
+        }
+        if self.api_bind != other.api_bind {
+            changed.push("api_bind");
+        }
+        if self.protocol != other.protocol {
+            changed.push("protocol");
+        }
+        changed
+    }
+}
+
+/// Upstream TLS enforcement, modelled on libpq's `sslmode`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpstreamTlsMode {
+    /// Use TLS when `upstream_tls` is set, fall back to cleartext if the upstream refuses
+    #[default]
+    Prefer,
+    /// Always use TLS. When `upstream_tls_ca_path` is set the certificate
+    /// chain is verified against it, but not the hostname.
+    Require,
+    /// Always use TLS and verify the certificate chain and hostname
+    VerifyFull,
+}
+
+/// How rules are matched against MySQL result columns, which carry both the
+/// name the client sees and the table column it was selected from. Postgres
+/// `RowDescription` only carries the former.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnMatch {
+    /// Match the original table and column, then the alias, so that
+    /// `SELECT email AS e` and computed columns named `email` are both masked
+    #[default]
+    OriginalOrAlias,
+    /// Match only the original table and column; aliases and computed
+    /// columns never match
+    Original,
+}
+
+/// MySQL capability flags that can be stripped from the handshake.
+/// TLS (`CLIENT_SSL`) is governed by the `tls` settings instead.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MySqlCapability {
+    Compress,
+    ZstdCompressionAlgorithm,
+    QueryAttributes,
+    OptionalResultsetMetadata,
+    LocalFiles,
+    MultiStatements,
+    MultiResults,
+    PsMultiResults,
+    ConnectAttrs,
+    SessionTrack,
+    DeprecateEof,
+}
+
+impl MySqlCapability {
+    /// The capability's bit in the handshake flags
+    pub fn flag(self) -> u32 {
+        use crate::protocol::mysql as flags;
+        match self {
+            MySqlCapability::Compress => flags::CLIENT_COMPRESS,
+            MySqlCapability::ZstdCompressionAlgorithm => flags::CLIENT_ZSTD_COMPRESSION_ALGORITHM,
+            MySqlCapability::QueryAttributes => flags::CLIENT_QUERY_ATTRIBUTES,
+            MySqlCapability::OptionalResultsetMetadata => flags::CLIENT_OPTIONAL_RESULTSET_METADATA,
+            MySqlCapability::LocalFiles => flags::CLIENT_LOCAL_FILES,
+            MySqlCapability::MultiStatements => flags::CLIENT_MULTI_STATEMENTS,
+            MySqlCapability::MultiResults => flags::CLIENT_MULTI_RESULTS,
+            MySqlCapability::PsMultiResults => flags::CLIENT_PS_MULTI_RESULTS,
+            MySqlCapability::ConnectAttrs => flags::CLIENT_CONNECT_ATTRS,
+            MySqlCapability::SessionTrack => flags::CLIENT_SESSION_TRACK,
+            MySqlCapability::DeprecateEof => flags::CLIENT_DEPRECATE_EOF,
+        }
+    }
+}
+
+fn default_mysql_strip_capabilities() -> Vec<MySqlCapability> {
+    vec![
+        MySqlCapability::Compress,
+        MySqlCapability::ZstdCompressionAlgorithm,
+        MySqlCapability::QueryAttributes,
+        MySqlCapability::OptionalResultsetMetadata,
+    ]
+}
+
+/// Which sources decide whether a result value is masked
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskingMode {
+    /// Only columns matching a rule are masked; everything else, JSON and
+    /// arrays included, is forwarded untouched
+    RulesOnly,
+    /// Only the PII scanner is used; `none` rules still exempt their columns
+    Heuristic,
+    /// Rules first, then the scanner for columns without a rule
+    #[default]
+    Both,
+}
+
+impl MaskingMode {
+    pub fn uses_rules(self) -> bool {
+        self != MaskingMode::Heuristic
+    }
+
+    pub fn uses_heuristics(self) -> bool {
+        self != MaskingMode::RulesOnly
+    }
+}
+
+/// Policy for PII detected in client-supplied `COPY ... FROM STDIN` or
+/// `LOAD DATA LOCAL INFILE` data
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyInPolicy {
+    /// Forward without inspection
+    #[default]
+    Allow,
+    /// Inspect and record a log entry, but forward the data
+    Log,
+    /// Inspect and abort the COPY (or LOAD DATA) when PII is found
+    Block,
+}
+
+/// Credentials for the side connection used to resolve Postgres table OIDs
+/// to names, so that table-scoped rules can be enforced.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CatalogLookupConfig {
+    /// Database user with read access to `pg_class`/`pg_namespace`
+    pub username: String,
+
+    /// Password for the lookup user
+    #[serde(default)]
+    pub password: String,
+
+    /// Maximum number of cached OID -> table mappings per connection (default: 1024)
+    #[serde(default = "default_catalog_cache_size")]
+    pub cache_size: usize,
+
+    /// Timeout for establishing the lookup connection in seconds (default: 5)
+    #[serde(default = "default_catalog_connect_timeout")]
+    pub connect_timeout_secs: u64,
+}
+
+fn default_catalog_cache_size() -> usize {
+    1024
+}
+
+fn default_catalog_connect_timeout() -> u64 {
+    5
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -33,23 +828,61 @@ pub struct LimitsConfig {
     #[serde(default)]
     pub connections_per_second: Option<u32>,
 
-    /// Timeout for establishing upstream connection in seconds (default: 30)
-    #[serde(default = "default_connect_timeout")]
-    pub connect_timeout_secs: u64,
+    /// Timeout for each attempt to connect to the upstream in seconds (default: 30)
+    #[serde(default = "default_connect_timeout", alias = "connect_timeout_secs")]
+    pub upstream_connect_timeout_secs: u64,
+
+    /// Further attempts after a failed upstream connect (default: 2)
+    #[serde(default = "default_connect_retries")]
+    pub upstream_connect_retries: u32,
 
     /// Idle timeout in seconds - close connection after no activity (default: 300)
     #[serde(default = "default_idle_timeout")]
     pub idle_timeout_secs: u64,
+
+    /// Largest protocol message accepted from client or upstream, in bytes (default: 64 MB)
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+
+    /// On shutdown, how long a connection may take to finish in-flight queries in seconds (default: 10)
+    #[serde(default = "default_shutdown_grace")]
+    pub shutdown_grace_secs: u64,
 }
 
 fn default_connect_timeout() -> u64 {
     30
 }
 
+fn default_connect_retries() -> u32 {
+    2
+}
+
 fn default_idle_timeout() -> u64 {
     300 // 5 minutes
 }
 
+fn default_max_message_size() -> usize {
+    crate::protocol::DEFAULT_MAX_MESSAGE_SIZE
+}
+
+fn default_shutdown_grace() -> u64 {
+    10
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            connections_per_second: None,
+            upstream_connect_timeout_secs: default_connect_timeout(),
+            upstream_connect_retries: default_connect_retries(),
+            idle_timeout_secs: default_idle_timeout(),
+            max_message_size: default_max_message_size(),
+            shutdown_grace_secs: default_shutdown_grace(),
+        }
+    }
+}
+
 /// Health check configuration for upstream database
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct HealthCheckConfig {
@@ -72,6 +905,11 @@ pub struct HealthCheckConfig {
     /// Number of consecutive successes before marking healthy (default: 1)
     #[serde(default = "default_healthy_threshold")]
     pub healthy_threshold: u32,
+
+    /// Active connections at which `/health/ready` reports the proxy as
+    /// saturated (default: `limits.max_connections`, if set)
+    #[serde(default)]
+    pub ready_max_connections: Option<usize>,
 }
 
 impl Default for HealthCheckConfig {
@@ -82,6 +920,7 @@ impl Default for HealthCheckConfig {
             timeout_secs: 5,
             unhealthy_threshold: 3,
             healthy_threshold: 1,
+            ready_max_connections: None,
         }
     }
 }
@@ -110,36 +949,333 @@ fn default_healthy_threshold() -> u32 {
 pub struct ApiConfig {
     /// API key for authenticating management API requests.
     /// If set, all sensitive endpoints require `X-API-Key` header.
+    /// Deprecated in favour of `keys`, which stores only hashes.
     #[serde(default)]
     pub api_key: Option<String>,
 
+    /// Named API keys, each with its own scopes, accepted in the
+    /// `X-API-Key` header alongside `api_key`
+    #[serde(default)]
+    pub keys: Vec<ApiKeyConfig>,
+
     /// JWT secret for token-based authentication.
     /// If set, endpoints also accept `Authorization: Bearer <token>` header.
     #[serde(default)]
     pub jwt_secret: Option<String>,
-}
 
-/// Audit event types to log
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub enum AuditEventType {
-    AuthAttempt,
-    ConfigChange,
-    RuleAdded,
-    RuleDeleted,
-    RulesImported,
-    ConfigReload,
-    DatabaseScan,
-    SchemaQuery,
-    ApiAccess,
-}
+    /// Interval between `/stats` history snapshots in seconds (default: 10)
+    #[serde(default = "default_stats_history_interval")]
+    pub stats_history_interval_secs: u64,
 
-/// Configuration for audit logging
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct AuditConfig {
-    /// Enable audit logging (default: true)
-    #[serde(default = "default_audit_enabled")]
-    pub enabled: bool,
+    /// Address the API listens on, such as 127.0.0.1 or a management
+    /// interface (default: all interfaces)
+    #[serde(default)]
+    pub bind_address: Option<IpAddr>,
+
+    /// Serve the API over TLS; `client_ca_path` and `require_client_auth`
+    /// work as they do for the proxy
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Scopes granted to the API key, and to JWTs that carry neither a
+    /// `scope` nor a `roles` claim (default: every scope but `detokenize`
+    /// and `apikeys:write`)
+    #[serde(default = "default_api_scopes")]
+    pub default_scopes: Vec<String>,
+
+    /// Lockout of clients failing authentication repeatedly
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+
+    /// Reverse proxies in front of the API. Requests from them are
+    /// attributed to the client named in `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Cross-origin access for browser dashboards (default: same origin only)
+    #[serde(default)]
+    pub cors: CorsConfig,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            keys: Vec::new(),
+            jwt_secret: None,
+            stats_history_interval_secs: default_stats_history_interval(),
+            bind_address: None,
+            tls: None,
+            default_scopes: default_api_scopes(),
+            lockout: LockoutConfig::default(),
+            trusted_proxies: Vec::new(),
+            cors: CorsConfig::default(),
+        }
+    }
+}
+
+/// Which other origins browsers may call the management API from
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CorsConfig {
+    /// Origins such as `https://dashboard.example.com`, or `*` for any.
+    /// With none, no CORS headers are sent and browsers keep to same-origin
+    /// requests (default: none)
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    /// Request headers cross-origin callers may send, or `*` (default:
+    /// content-type, authorization, x-api-key)
+    #[serde(default = "default_cors_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+
+    /// Methods cross-origin callers may use, or `*` (default: GET, POST,
+    /// PUT, DELETE)
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Let browsers send cookies and client certificates along; not allowed
+    /// with `*` (default: false)
+    #[serde(default)]
+    pub allow_credentials: bool,
+
+    /// Allow any origin, header and method. For local development only:
+    /// any website could then script the API (default: false)
+    #[serde(default)]
+    pub permissive: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_headers: default_cors_allowed_headers(),
+            allowed_methods: default_cors_allowed_methods(),
+            allow_credentials: false,
+            permissive: false,
+        }
+    }
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    ["content-type", "authorization", "x-api-key"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec()
+}
+
+impl CorsConfig {
+    /// Check every origin, header and method is one the CORS layer accepts
+    pub fn validate(&self) -> Result<()> {
+        use axum::http::{HeaderName, Method};
+
+        for origin in &self.allowed_origins {
+            if origin == "*" {
+                continue;
+            }
+            let (scheme, host) = origin.split_once("://").unwrap_or_default();
+            if !matches!(scheme, "http" | "https")
+                || host.is_empty()
+                || host.contains('/')
+                || !origin.is_ascii()
+            {
+                bail!(
+                    "Invalid api.cors origin '{}'; expected scheme://host[:port], such as https://dashboard.example.com",
+                    origin
+                );
+            }
+        }
+        for header in self.allowed_headers.iter().filter(|h| *h != "*") {
+            if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                bail!("Invalid api.cors header '{}'", header);
+            }
+        }
+        for method in self.allowed_methods.iter().filter(|m| *m != "*") {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                bail!("Invalid api.cors method '{}'", method);
+            }
+        }
+        let wildcard = [
+            &self.allowed_origins,
+            &self.allowed_headers,
+            &self.allowed_methods,
+        ]
+        .iter()
+        .any(|list| list.iter().any(|entry| entry == "*"));
+        if self.allow_credentials && (wildcard || self.permissive) {
+            bail!("api.cors.allow_credentials cannot be combined with '*' or permissive");
+        }
+        Ok(())
+    }
+}
+
+/// Refusing clients that fail management API authentication too often
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LockoutConfig {
+    /// Enable the lockout (default: true)
+    #[serde(default = "default_lockout_enabled")]
+    pub enabled: bool,
+
+    /// Failed attempts from one IP within `window_secs` that lock it out
+    /// (default: 10)
+    #[serde(default = "default_lockout_max_failures")]
+    pub max_failures: u32,
+
+    /// Window over which failures are counted, in seconds (default: 60)
+    #[serde(default = "default_lockout_window")]
+    pub window_secs: u64,
+
+    /// How long a client is refused once locked out, in seconds
+    /// (default: 300)
+    #[serde(default = "default_lockout_duration")]
+    pub lockout_secs: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_lockout_enabled(),
+            max_failures: default_lockout_max_failures(),
+            window_secs: default_lockout_window(),
+            lockout_secs: default_lockout_duration(),
+        }
+    }
+}
+
+fn default_lockout_enabled() -> bool {
+    true
+}
+
+fn default_lockout_max_failures() -> u32 {
+    10
+}
+
+fn default_lockout_window() -> u64 {
+    60
+}
+
+fn default_lockout_duration() -> u64 {
+    300
+}
+
+pub fn default_stats_history_interval() -> u64 {
+    10
+}
+
+/// A named API key. Only the key's hash is kept, so reading the config
+/// file doesn't give access to the API.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKeyConfig {
+    /// Who uses the key; recorded as the user of its audit entries
+    pub name: String,
+
+    /// `sha256:` followed by the hex SHA-256 digest of the key
+    pub key_hash: String,
+
+    /// Scopes the key grants (default: as for `default_scopes`)
+    #[serde(default = "default_api_scopes")]
+    pub scopes: Vec<String>,
+}
+
+const API_KEY_HASH_PREFIX: &str = "sha256:";
+
+/// The `key_hash` stored for an API key
+pub fn hash_api_key(key: &str) -> String {
+    let digest: String = Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}{}", API_KEY_HASH_PREFIX, digest)
+}
+
+impl ApiConfig {
+    /// Whether requests must present an API key or a JWT
+    pub fn auth_required(&self) -> bool {
+        self.api_key.is_some() || !self.keys.is_empty() || self.jwt_secret.is_some()
+    }
+
+    /// Whether any API key, named or legacy, is configured
+    pub fn has_api_keys(&self) -> bool {
+        self.api_key.is_some() || !self.keys.is_empty()
+    }
+
+    /// The named key matching `provided`. Every key is compared, in
+    /// constant time, so that timing tells nothing about which one matched
+    /// or how close a guess came.
+    pub fn find_key(&self, provided: &str) -> Option<&ApiKeyConfig> {
+        let hash = hash_api_key(provided);
+        let mut found = None;
+        for key in &self.keys {
+            let matches: bool = key.key_hash.as_bytes().ct_eq(hash.as_bytes()).into();
+            if matches && found.is_none() {
+                found = Some(key);
+            }
+        }
+        found
+    }
+
+    /// Whether `provided` is the legacy `api_key`, compared in constant time
+    pub fn is_legacy_key(&self, provided: &str) -> bool {
+        self.api_key
+            .as_ref()
+            .is_some_and(|key| key.as_bytes().ct_eq(provided.as_bytes()).into())
+    }
+}
+
+/// Scopes the management API checks, each covering a group of endpoints
+pub const API_SCOPES: &[&str] = &[
+    "rules:read",
+    "rules:write",
+    "config:read",
+    "config:write",
+    "scan:read",
+    "scan:run",
+    "audit:read",
+    "stats:read",
+    "detokenize",
+    "apikeys:write",
+];
+
+/// Every scope but `detokenize` and `apikeys:write`
+pub fn default_api_scopes() -> Vec<String> {
+    API_SCOPES
+        .iter()
+        .filter(|scope| !matches!(**scope, "detokenize" | "apikeys:write"))
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+/// Audit event types to log
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    AuthAttempt,
+    ConfigChange,
+    RuleAdded,
+    RuleUpdated,
+    RuleDeleted,
+    RulesImported,
+    ConfigReload,
+    DatabaseScan,
+    SchemaQuery,
+    ApiAccess,
+    Detokenize,
+    ScanHistory,
+    AuditExport,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    ConnectionKilled,
+    StatsReset,
+}
+
+/// Configuration for audit logging
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuditConfig {
+    /// Enable audit logging (default: true)
+    #[serde(default = "default_audit_enabled")]
+    pub enabled: bool,
 
     /// Log to stdout in addition to file (default: false)
     #[serde(default)]
@@ -201,6 +1337,12 @@ pub struct TlsConfig {
     pub enabled: bool,
     pub cert_path: String,
     pub key_path: String,
+    /// PEM bundle of CAs trusted to sign client certificates
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    /// Reject clients that do not present a certificate signed by `client_ca_path`
+    #[serde(default)]
+    pub require_client_auth: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -225,113 +1367,2313 @@ fn default_masking_enabled() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct MaskingRule {
-    pub table: Option<String>,
-    pub column: String,
-    pub strategy: String,
+fn default_config_backups() -> usize {
+    5
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            masking_enabled: true,
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+/// How a value is masked. Parsed from the strategy names used in rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaskStrategy {
+    Email,
+    EmailPreserveDomain,
+    Phone,
+    PhonePreserve,
+    Address,
+    StreetAddress,
+    Zip,
+    FirstName,
+    LastName,
+    FullName,
+    Company,
+    CreditCard,
+    CreditCardPreserve,
+    Ssn,
+    Ip,
+    Dob,
+    Passport,
+    Iban,
+    /// UK National Insurance number
+    Nino,
+    /// German Steuer-ID
+    TaxId,
+    Json,
+    Hash,
+    Null,
+    Redact,
+    Partial,
+    NumericNoise,
+    DateShift,
+    Custom,
+    Tokenize,
+    /// `none`: exempts the column from rules and heuristics alike
+    Exclude,
+}
+
+impl MaskStrategy {
+    pub const ALL: [MaskStrategy; 30] = [
+        MaskStrategy::Email,
+        MaskStrategy::EmailPreserveDomain,
+        MaskStrategy::Phone,
+        MaskStrategy::PhonePreserve,
+        MaskStrategy::Address,
+        MaskStrategy::StreetAddress,
+        MaskStrategy::Zip,
+        MaskStrategy::FirstName,
+        MaskStrategy::LastName,
+        MaskStrategy::FullName,
+        MaskStrategy::Company,
+        MaskStrategy::CreditCard,
+        MaskStrategy::CreditCardPreserve,
+        MaskStrategy::Ssn,
+        MaskStrategy::Ip,
+        MaskStrategy::Dob,
+        MaskStrategy::Passport,
+        MaskStrategy::Iban,
+        MaskStrategy::Nino,
+        MaskStrategy::TaxId,
+        MaskStrategy::Json,
+        MaskStrategy::Hash,
+        MaskStrategy::Null,
+        MaskStrategy::Redact,
+        MaskStrategy::Partial,
+        MaskStrategy::NumericNoise,
+        MaskStrategy::DateShift,
+        MaskStrategy::Custom,
+        MaskStrategy::Tokenize,
+        MaskStrategy::Exclude,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MaskStrategy::Email => "email",
+            MaskStrategy::EmailPreserveDomain => "email_preserve_domain",
+            MaskStrategy::Phone => "phone",
+            MaskStrategy::PhonePreserve => "phone_preserve",
+            MaskStrategy::Address => "address",
+            MaskStrategy::StreetAddress => "street_address",
+            MaskStrategy::Zip => "zip",
+            MaskStrategy::FirstName => "first_name",
+            MaskStrategy::LastName => "last_name",
+            MaskStrategy::FullName => "full_name",
+            MaskStrategy::Company => "company",
+            MaskStrategy::CreditCard => "credit_card",
+            MaskStrategy::CreditCardPreserve => "credit_card_preserve",
+            MaskStrategy::Ssn => "ssn",
+            MaskStrategy::Ip => "ip",
+            MaskStrategy::Dob => "dob",
+            MaskStrategy::Passport => "passport",
+            MaskStrategy::Iban => "iban",
+            MaskStrategy::Nino => "nino",
+            MaskStrategy::TaxId => "tax_id",
+            MaskStrategy::Json => "json",
+            MaskStrategy::Hash => "hash",
+            MaskStrategy::Null => "null",
+            MaskStrategy::Redact => "redact",
+            MaskStrategy::Partial => "partial",
+            MaskStrategy::NumericNoise => "numeric_noise",
+            MaskStrategy::DateShift => "date_shift",
+            MaskStrategy::Custom => "custom",
+            MaskStrategy::Tokenize => "tokenize",
+            MaskStrategy::Exclude => "none",
         }
     }
 }
 
-impl AppConfig {
-    pub fn load(path: &str) -> Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let config: AppConfig = serde_yaml::from_str(&content)?;
-        Ok(config)
+impl std::str::FromStr for MaskStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        MaskStrategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.as_str() == s)
+            .ok_or_else(|| {
+                let known: Vec<_> = MaskStrategy::ALL.iter().map(|s| s.as_str()).collect();
+                anyhow::anyhow!(
+                    "unknown masking strategy '{}', expected one of: {}",
+                    s,
+                    known.join(", ")
+                )
+            })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl std::fmt::Display for MaskStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-    #[test]
-    fn test_config_load_valid_yaml() {
-        let yaml = r#"
-masking_enabled: true
-upstream_tls: false
-rules:
-  - table: "users"
-    column: "email"
-    strategy: "email"
-  - column: "phone"
-    strategy: "phone"
-"#;
-        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+/// Strategies are documented by name, as rules give them
+impl utoipa::PartialSchema for MaskStrategy {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .enum_values(Some(MaskStrategy::ALL.iter().map(|s| s.as_str())))
+            .into()
+    }
+}
 
-        assert!(config.masking_enabled);
-        assert!(!config.upstream_tls);
-        assert_eq!(config.rules.len(), 2);
-        assert_eq!(config.rules[0].table, Some("users".to_string()));
-        assert_eq!(config.rules[0].column, "email");
-        assert_eq!(config.rules[0].strategy, "email");
-        assert_eq!(config.rules[1].table, None);
+impl ToSchema for MaskStrategy {}
+
+impl Serialize for MaskStrategy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
     }
+}
 
-    #[test]
-    fn test_config_defaults() {
-        let yaml = r#"
-rules: []
-"#;
-        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+impl<'de> Deserialize<'de> for MaskStrategy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
 
-        assert!(config.masking_enabled); // Should default to true
-        assert!(!config.upstream_tls); // Should default to false
-        assert!(config.tls.is_none()); // Should default to None
+/// Stable ID of a masking rule, used to edit or delete it through the API.
+/// Rules loaded without one get a fresh ID, which is written out with the
+/// next config save.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct RuleId(String);
+
+impl Default for RuleId {
+    fn default() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
     }
+}
 
-    #[test]
-    fn test_config_with_tls() {
-        let yaml = r#"
-masking_enabled: true
-upstream_tls: true
-tls:
-  enabled: true
-  cert_path: "certs/server.crt"
-  key_path: "certs/server.key"
-rules: []
-"#;
-        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+impl RuleId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
 
-        assert!(config.upstream_tls);
-        assert!(config.tls.is_some());
+impl std::fmt::Display for RuleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-        let tls = config.tls.unwrap();
-        assert!(tls.enabled);
-        assert_eq!(tls.cert_path, "certs/server.crt");
-        assert_eq!(tls.key_path, "certs/server.key");
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct MaskingRule {
+    /// Assigned by the proxy; ignored when sent, but by `replace` imports
+    #[serde(default)]
+    #[schema(value_type = String, read_only)]
+    pub id: RuleId,
+    /// Table name, glob or `re:` regex; matches every table when omitted
+    #[schema(value_type = Option<String>)]
+    pub table: Option<NamePattern>,
+    /// Column name, glob or `re:` regex
+    #[schema(value_type = String)]
+    pub column: NamePattern,
+    pub strategy: MaskStrategy,
+    /// Strategy options, for `partial`, `redact` and `custom`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<MaskingOptions>,
+    /// For JSON columns: mask only the values at these paths, with the
+    /// rule's strategy, or the PII scanner for `json` rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<String>)]
+    pub json_paths: Vec<JsonPath>,
+}
+
+impl MaskingRule {
+    /// Check the rule names a column and carries what its strategy needs.
+    /// Strategies and patterns are checked when the rule is parsed, so an
+    /// unknown strategy or invalid regex never gets this far.
+    pub fn validate(&self) -> Result<()> {
+        if self.column.as_str().trim().is_empty() {
+            bail!("Rule has an empty column");
+        }
+        if self
+            .table
+            .as_ref()
+            .is_some_and(|table| table.as_str().trim().is_empty())
+        {
+            bail!("Rule for column '{}' has an empty table", self.column);
+        }
+        if self.strategy == MaskStrategy::Custom
+            && self
+                .options
+                .as_ref()
+                .and_then(|o| o.pattern.as_ref())
+                .is_none()
+        {
+            bail!(
+                "Rule for column '{}' uses the custom strategy but sets no options.pattern",
+                self.column
+            );
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_invalid_yaml_fails() {
-        let yaml = r#"
-invalid yaml content {{
-"#;
-        let result: Result<AppConfig, _> = serde_yaml::from_str(yaml);
-        assert!(result.is_err());
+    /// Whether both rules are written for the same table and column
+    pub fn same_target(&self, other: &MaskingRule) -> bool {
+        self.column.as_str() == other.column.as_str()
+            && self.table.as_ref().map(NamePattern::as_str)
+                == other.table.as_ref().map(NamePattern::as_str)
     }
 
-    #[test]
-    fn test_missing_required_fields_fails() {
-        let yaml = r#"
-masking_enabled: true
-"#;
-        let result: Result<AppConfig, _> = serde_yaml::from_str(yaml);
-        assert!(result.is_err()); // Should fail because 'rules' is missing
+    /// Whether both rules mask the same way: same strategy, options and
+    /// JSON paths
+    pub fn same_masking(&self, other: &MaskingRule) -> bool {
+        self.strategy == other.strategy
+            && serde_json::to_value(&self.options).ok() == serde_json::to_value(&other.options).ok()
+            && self.json_paths == other.json_paths
+    }
+
+    /// Where the rule applies, for messages
+    pub fn target(&self) -> String {
+        match &self.table {
+            Some(table) => format!("{}.{}", table, self.column),
+            None => self.column.to_string(),
+        }
+    }
+}
+
+/// Path into a JSON document such as `$.customer.email`, `$.items[*].sku`
+/// or `$['first name']`. `*` selects every element of an array or every
+/// value of an object. Parsed once when the rule is loaded.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<JsonPathSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonPathSegment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn new(source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        let Some(mut rest) = source.strip_prefix('$') else {
+            bail!("must start with '$'");
+        };
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let key = &after[..end];
+                match key {
+                    "" => bail!("empty key"),
+                    "*" => segments.push(JsonPathSegment::Wildcard),
+                    _ => segments.push(JsonPathSegment::Key(key.to_string())),
+                }
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (segment, len) =
+                    if let Some(quote) = after.chars().next().filter(|c| *c == '\'' || *c == '"') {
+                        let Some(end) = after[1..].find(quote) else {
+                            bail!("unterminated quoted key");
+                        };
+                        (JsonPathSegment::Key(after[1..end + 1].to_string()), end + 2)
+                    } else {
+                        let end = after.find(']').unwrap_or(after.len());
+                        let segment = match &after[..end] {
+                            "*" => JsonPathSegment::Wildcard,
+                            index => JsonPathSegment::Index(
+                                index
+                                    .parse()
+                                    .map_err(|_| anyhow::anyhow!("invalid index '{}'", index))?,
+                            ),
+                        };
+                        (segment, end)
+                    };
+                let Some(after) = after[len..].strip_prefix(']') else {
+                    bail!("missing ']'");
+                };
+                segments.push(segment);
+                rest = after;
+            } else {
+                bail!("expected '.' or '[' at '{}'", rest);
+            }
+        }
+        Ok(Self { source, segments })
+    }
+
+    pub fn segments(&self) -> &[JsonPathSegment] {
+        &self.segments
+    }
+}
+
+impl PartialEq for JsonPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for JsonPath {}
+
+impl Serialize for JsonPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        JsonPath::new(source.clone())
+            .map_err(|e| serde::de::Error::custom(format!("invalid JSON path '{}': {}", source, e)))
+    }
+}
+
+/// Table or column name of a rule: an exact name, a glob such as
+/// `*_email`, or a regex prefixed with `re:`. Globs and regexes are compiled
+/// once when the rule is loaded, so that invalid patterns are rejected up front.
+#[derive(Debug, Clone)]
+pub struct NamePattern {
+    source: String,
+    /// `None` for exact names
+    regex: Option<Regex>,
+}
+
+impl NamePattern {
+    pub fn new(source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        let regex = if let Some(pattern) = source.strip_prefix("re:") {
+            Some(Regex::new(pattern)?)
+        } else if source.contains(['*', '?']) {
+            let glob: String = source
+                .split_inclusive(['*', '?'])
+                .map(|part| match part.strip_suffix('*') {
+                    Some(literal) => format!("{}.*", regex::escape(literal)),
+                    None => match part.strip_suffix('?') {
+                        Some(literal) => format!("{}.", regex::escape(literal)),
+                        None => regex::escape(part),
+                    },
+                })
+                .collect();
+            Some(Regex::new(&format!("^{}$", glob))?)
+        } else {
+            None
+        };
+        Ok(Self { source, regex })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether this is a plain name rather than a glob or regex
+    pub fn is_exact(&self) -> bool {
+        self.regex.is_none()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(name),
+            None => self.source == name,
+        }
+    }
+}
+
+impl std::str::FromStr for NamePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl std::fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for NamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for NamePattern {}
+
+impl PartialEq<str> for NamePattern {
+    fn eq(&self, other: &str) -> bool {
+        self.source == other
+    }
+}
+
+impl PartialEq<&str> for NamePattern {
+    fn eq(&self, other: &&str) -> bool {
+        self.source == *other
+    }
+}
+
+impl Serialize for NamePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for NamePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        NamePattern::new(source.clone()).map_err(|e| {
+            serde::de::Error::custom(format!("invalid name pattern '{}': {}", source, e))
+        })
+    }
+}
+
+/// Options for the strategies that transform a value rather than replacing
+/// it with synthetic data
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(default)]
+pub struct MaskingOptions {
+    /// Characters `partial` keeps at the start of the value (default: 0)
+    pub keep_prefix: usize,
+    /// Characters `partial` keeps at the end of the value (default: 4)
+    pub keep_suffix: usize,
+    /// Replacement `partial` uses for each masked character (default: `*`)
+    pub mask_char: char,
+    /// Text `redact` returns in place of the value (default: `[REDACTED]`)
+    pub replacement: String,
+    /// `credit_card_preserve` and `phone_preserve` keep the last four
+    /// digits real (default: false)
+    pub keep_last_four: bool,
+    /// `credit_card_preserve` keeps the six-digit issuer prefix (default: false)
+    pub keep_bin: bool,
+    /// Regex `custom` replaces matches of, using `replacement` as the
+    /// template (`$1` for captures, `{rand:N}` for N seeded random digits)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub pattern: Option<RulePattern>,
+    /// Largest number of days `date_shift` moves a date, either way (default: 365)
+    pub max_shift_days: u32,
+    /// Largest change `numeric_noise` makes to a number, in percent either
+    /// way (default: 10)
+    pub noise_percent: u32,
+    /// Overrides `masking.seed_salt` for this rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_salt: Option<String>,
+    /// Overrides `masking.preserve_length` for this rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_length: Option<bool>,
+}
+
+/// Regex of a `custom` rule, compiled once when the rule is loaded so that
+/// invalid patterns are rejected up front
+#[derive(Debug, Clone)]
+pub struct RulePattern(pub Regex);
+
+impl PartialEq for RulePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RulePattern {}
+
+impl Serialize for RulePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for RulePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(RulePattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid pattern '{}': {}", pattern, e)))
+    }
+}
+
+impl Default for MaskingOptions {
+    fn default() -> Self {
+        Self {
+            keep_prefix: 0,
+            keep_suffix: 4,
+            mask_char: '*',
+            replacement: "[REDACTED]".to_string(),
+            keep_last_four: false,
+            keep_bin: false,
+            pattern: None,
+            max_shift_days: 365,
+            noise_percent: 10,
+            seed_salt: None,
+            preserve_length: None,
+        }
+    }
+}
+
+/// Settings shared by the masking strategies
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct MaskingConfig {
+    /// Secret appended to values before they are hashed by the `hash`
+    /// strategy, so tokens can't be reversed with precomputed tables.
+    /// Never returned by the management API.
+    #[serde(default)]
+    pub hash_salt: Option<String>,
+    /// Hex characters kept from the SHA-256 digest (default: all 64)
+    #[serde(default)]
+    pub hash_length: Option<usize>,
+    /// Key for hashing values into the seeds fake data is generated from.
+    /// Without it the value -> fake mapping can be rebuilt by anyone who
+    /// runs a dictionary through the proxy's algorithm; changing it changes
+    /// every masked value. Never returned by the management API.
+    #[serde(default)]
+    pub seed_salt: Option<String>,
+    /// Entries in the cache of generated fakes shared by all connections;
+    /// unset disables it. Read at startup.
+    #[serde(default)]
+    pub cache_capacity: Option<usize>,
+    /// Have `email_preserve_domain` replace domains not listed in
+    /// `internal_email_domains` with a consistent pseudonym (default: false)
+    #[serde(default)]
+    pub pseudonymize_email_domains: bool,
+    /// Domains, and their subdomains, that `email_preserve_domain` always keeps
+    #[serde(default)]
+    pub internal_email_domains: Vec<String>,
+    /// What is sent in place of a value that fails to be masked (default: fail_closed)
+    #[serde(default)]
+    pub on_error: OnMaskingError,
+    /// Cut or pad replacements to the byte length of the values they
+    /// replace, for rules that don't set `options.preserve_length` and for
+    /// heuristic hits (default: false)
+    #[serde(default)]
+    pub preserve_length: bool,
+    /// Whether bytea values are masked or always forwarded untouched (default: mask)
+    #[serde(default)]
+    pub bytea_mode: ByteaMode,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteaMode {
+    /// Never touch bytea values, whatever the rules
+    Skip,
+    /// Mask the decoded bytes and re-encode the result
+    #[default]
+    Mask,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnMaskingError {
+    /// Forward the original value
+    FailOpen,
+    /// Replace the value with a redaction marker
+    #[default]
+    FailClosed,
+}
+
+/// Vault that maps `tokenize` tokens back to their original values
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TokenizationConfig {
+    /// Encrypted, append-only file holding the token mappings
+    pub vault_path: String,
+    /// Secret the vault encryption and token keys are derived from.
+    /// Never returned by the management API.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Environment variable read for the secret when `key` is not set
+    /// (default: IRONVEIL_VAULT_KEY)
+    #[serde(default = "default_vault_key_env")]
+    pub key_env: String,
+}
+
+fn default_vault_key_env() -> String {
+    "IRONVEIL_VAULT_KEY".to_string()
+}
+
+/// Masking applied to PII found by the scanner rather than by a rule
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct HeuristicMaskingConfig {
+    #[serde(default)]
+    pub strategy: HeuristicStrategy,
+    /// Options for the `partial` and `redact` strategies
+    #[serde(default)]
+    pub options: MaskingOptions,
+    /// With `fake`, emails keep their domain as with `email_preserve_domain`
+    #[serde(default)]
+    pub preserve_email_domain: bool,
+    /// With `fake`, dates of birth are moved with `date_shift` rather than
+    /// replaced by a constant, keeping the age distribution
+    #[serde(default)]
+    pub shift_dates_of_birth: bool,
+    /// Also look for PII inside longer values, such as an email in a
+    /// free-text note, and replace just the spans holding it (default: false)
+    #[serde(default)]
+    pub scan_substrings: bool,
+    /// Postgres type OIDs whose values are scanned. Other built-in types,
+    /// such as integers, booleans and timestamps, are only masked by rules.
+    #[serde(default = "default_scannable_postgres_types")]
+    pub scannable_postgres_types: Vec<u32>,
+    /// MySQL column types (`MYSQL_TYPE_*` codes) whose values are scanned
+    #[serde(default = "default_scannable_mysql_types")]
+    pub scannable_mysql_types: Vec<u8>,
+}
+
+impl Default for HeuristicMaskingConfig {
+    fn default() -> Self {
+        Self {
+            strategy: HeuristicStrategy::default(),
+            options: MaskingOptions::default(),
+            preserve_email_domain: false,
+            shift_dates_of_birth: false,
+            scan_substrings: false,
+            scannable_postgres_types: default_scannable_postgres_types(),
+            scannable_mysql_types: default_scannable_mysql_types(),
+        }
+    }
+}
+
+impl HeuristicMaskingConfig {
+    /// Whether values of a Postgres type are scanned. Types without a fixed
+    /// OID (extensions, enums, domains) always are, as is an unset OID.
+    pub fn scans_postgres_type(&self, oid: u32) -> bool {
+        oid == 0
+            || oid >= type_oid::FIRST_NORMAL_OBJECT_ID
+            || self.scannable_postgres_types.contains(&oid)
+    }
+
+    pub fn scans_mysql_type(&self, column_type: u8) -> bool {
+        self.scannable_mysql_types.contains(&column_type)
+    }
+}
+
+fn default_scannable_postgres_types() -> Vec<u32> {
+    vec![
+        // Scanned when the bytes are valid UTF-8
+        type_oid::BYTEA,
+        type_oid::CHAR,
+        type_oid::NAME,
+        type_oid::TEXT,
+        type_oid::JSON,
+        type_oid::XML,
+        type_oid::JSON_ARRAY,
+        type_oid::UNKNOWN,
+        type_oid::INET,
+        type_oid::TEXT_ARRAY,
+        type_oid::BPCHAR_ARRAY,
+        type_oid::VARCHAR_ARRAY,
+        type_oid::BPCHAR,
+        type_oid::VARCHAR,
+        // Dates are scanned for dates of birth
+        type_oid::DATE,
+        // Anonymous records, from ROW() and whole-row references
+        type_oid::RECORD,
+        type_oid::JSONB,
+        type_oid::JSONB_ARRAY,
+    ]
+}
+
+fn default_scannable_mysql_types() -> Vec<u8> {
+    vec![
+        MYSQL_TYPE_DATE,
+        MYSQL_TYPE_NEWDATE,
+        MYSQL_TYPE_VARCHAR,
+        MYSQL_TYPE_JSON,
+        MYSQL_TYPE_ENUM,
+        MYSQL_TYPE_SET,
+        // TEXT columns are sent as blobs
+        MYSQL_TYPE_TINY_BLOB,
+        MYSQL_TYPE_MEDIUM_BLOB,
+        MYSQL_TYPE_LONG_BLOB,
+        MYSQL_TYPE_BLOB,
+        MYSQL_TYPE_VAR_STRING,
+        MYSQL_TYPE_STRING,
+    ]
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HeuristicStrategy {
+    /// Replace with synthetic data of the detected type
+    #[default]
+    Fake,
+    /// Mask in place with the `partial` strategy
+    Partial,
+    /// Return NULL (JSON `null` inside JSON values)
+    Null,
+    /// Return the `replacement` text of the options
+    Redact,
+}
+
+/// Interpolate the string values of `value` in place. Mapping keys are left
+/// alone. Variables that are unset without a default are added to `unset`.
+fn interpolate_value(
+    value: &mut serde_yaml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unset: &mut Vec<String>,
+) -> Result<()> {
+    match value {
+        serde_yaml::Value::String(text) if text.contains('$') => {
+            *text = interpolate(text, lookup, unset)?;
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                interpolate_value(item, lookup, unset)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                interpolate_value(item, lookup, unset)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => interpolate_value(&mut tagged.value, lookup, unset)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` in `text`. The default applies when
+/// the variable is unset or empty; `$${` stands for a literal `${`, and
+/// `${...}` holding anything but a variable name, such as `${1}`, is kept.
+fn interpolate(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unset: &mut Vec<String>,
+) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(placeholder) = after.strip_prefix("${") else {
+            result.push('$');
+            rest = &after[1..];
+            continue;
+        };
+        let end = placeholder
+            .find('}')
+            .with_context(|| format!("Unterminated ${{ in config value '{}'", text))?;
+        let (name, default) = match placeholder[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&placeholder[..end], None),
+        };
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            // Not a variable, such as `${1}` in a regex replacement
+            result.push_str("${");
+            rest = placeholder;
+            continue;
+        }
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => result.push_str(default),
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => unset.push(name.to_string()),
+        }
+        rest = &placeholder[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Escape the string values of `value` in place, so that interpolating them
+/// gives them back
+fn escape_value(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::String(text) => *text = escape_placeholders(text),
+        serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(escape_value),
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                escape_value(item);
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => escape_value(&mut tagged.value),
+        _ => {}
+    }
+}
+
+fn escape_placeholders(text: &str) -> String {
+    text.replace("${", "$${")
+}
+
+/// Put back the placeholders of `template` wherever `value` still holds what
+/// they resolve to now. Values changed since, and placeholders that no longer
+/// resolve, keep the value.
+fn restore_placeholders(
+    value: &mut serde_yaml::Value,
+    template: &serde_yaml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) {
+    use serde_yaml::Value;
+    match (value, template) {
+        (Value::Mapping(values), Value::Mapping(templates)) => {
+            for (key, template) in templates {
+                if let Some(value) = values.get_mut(key) {
+                    restore_placeholders(value, template, lookup);
+                }
+            }
+        }
+        (Value::Sequence(values), Value::Sequence(templates)) => {
+            for (value, template) in values.iter_mut().zip(templates) {
+                restore_placeholders(value, template, lookup);
+            }
+        }
+        (Value::Tagged(value), Value::Tagged(template)) => {
+            restore_placeholders(&mut value.value, &template.value, lookup)
+        }
+        (value, Value::String(placeholder)) if placeholder.contains("${") => {
+            let mut unset = Vec::new();
+            let resolved = interpolate(placeholder, lookup, &mut unset);
+            if let Ok(resolved) = resolved
+                && unset.is_empty()
+                && *value == Value::String(escape_placeholders(&resolved))
+            {
+                *value = Value::String(placeholder.clone());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace `path` with what `write` puts in a temporary file next to it,
+/// backing up the current file first. On any error `path` is left as it was.
+fn write_atomically(
+    path: &Path,
+    backups: usize,
+    write: impl FnOnce(&mut fs::File) -> Result<()>,
+) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid config path {}", path.display()))?;
+    static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+    let temp_path = dir.join(format!(
+        ".{}.tmp.{}.{}",
+        file_name,
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = (|| {
+        let mut file = fs::File::create(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        // Keep the permissions of the file being replaced, which may hold secrets
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        write(&mut file)?;
+        file.flush()?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {}", temp_path.display()))
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    if backups > 0 && path.exists() {
+        let backup = dir.join(format!(
+            "{}.bak.{}",
+            file_name,
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        ));
+        if let Err(e) = fs::copy(path, &backup) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e).with_context(|| format!("Failed to back up {}", path.display()));
+        }
+        prune_backups(dir, file_name, backups);
+    }
+
+    if let Err(e) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e).with_context(|| format!("Failed to replace {}", path.display()));
+    }
+    // Make the rename itself durable; not every platform can sync a directory
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Delete the oldest `<file_name>.bak.*` files beyond the `keep` newest
+fn prune_backups(dir: &Path, file_name: &str, keep: usize) {
+    let prefix = format!("{}.bak.", file_name);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut backups: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    // The timestamps sort the same way as the names
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        if let Err(e) = fs::remove_file(backup) {
+            tracing::warn!(
+                "Failed to remove old config backup {}: {}",
+                backup.display(),
+                e
+            );
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            masking_enabled: true,
+            rules: vec![],
+            tls: None,
+            upstream_tls: false,
+            upstream_tls_mode: UpstreamTlsMode::Prefer,
+            upstream_tls_ca_path: None,
+            upstream_tls_client_cert_path: None,
+            upstream_tls_client_key_path: None,
+            upstream_tls_server_name: None,
+            telemetry: None,
+            api: None,
+            limits: None,
+            health_check: None,
+            audit: None,
+            catalog_lookup: None,
+            copy_in_policy: CopyInPolicy::Allow,
+            local_infile_policy: CopyInPolicy::Allow,
+            server: ServerConfig::default(),
+            listeners: vec![],
+            mysql_strip_capabilities: default_mysql_strip_capabilities(),
+            mysql_column_match: ColumnMatch::OriginalOrAlias,
+            masking_mode: MaskingMode::Both,
+            heuristic_masking: HeuristicMaskingConfig::default(),
+            masking: MaskingConfig::default(),
+            tokenization: None,
+            policies: vec![],
+            rule_sets: BTreeMap::new(),
+            logs: LogsConfig::default(),
+            scanner: ScannerConfig::default(),
+            config_backups: default_config_backups(),
+            config_watch: ConfigWatchConfig::default(),
+            template: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config = Self::parse(&content)?;
+        config.validate()?;
+        for duplicate in config.duplicate_rules() {
+            tracing::warn!("{}", duplicate);
+        }
+        if config.api.as_ref().is_some_and(|api| {
+            api.auth_required() && api.lockout.enabled && api.trusted_proxies.is_empty()
+        }) {
+            tracing::warn!(
+                "api.lockout is enabled without api.trusted_proxies: behind a reverse proxy every client shares its address, so one client's failures lock them all out"
+            );
+        }
+        if config.api.as_ref().is_some_and(|api| api.api_key.is_some()) {
+            tracing::warn!(
+                "api.api_key is deprecated and kept in plain text; use named, hashed api.keys instead"
+            );
+        }
+        Ok(config)
+    }
+
+    /// Parse the YAML of a config file, replacing `${VAR}` and
+    /// `${VAR:-default}` in string values with the environment variable.
+    /// Fails listing every variable that is unset and has no default.
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::parse_with(content, &|name| std::env::var(name).ok())
+    }
+
+    fn parse_with(content: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        // Parsed directly when there is nothing to replace, for errors that
+        // point at a line
+        if !content.contains("${") {
+            return Ok(serde_yaml::from_str(content)?);
+        }
+        let template: serde_yaml::Value = serde_yaml::from_str(content)?;
+        let mut resolved = template.clone();
+        let mut unset = Vec::new();
+        interpolate_value(&mut resolved, lookup, &mut unset)?;
+        if !unset.is_empty() {
+            unset.sort();
+            unset.dedup();
+            bail!(
+                "Config refers to unset environment variables: {}",
+                unset.join(", ")
+            );
+        }
+        let mut config: AppConfig = serde_yaml::from_value(resolved)?;
+        config.template = Some(template);
+        Ok(config)
+    }
+
+    /// The YAML saved for this config. Values still equal to what a
+    /// placeholder in the loaded file resolved to are written as that
+    /// placeholder, so secrets taken from the environment stay out of the file.
+    pub fn to_yaml(&self) -> Result<String> {
+        self.to_yaml_with(&|name| std::env::var(name).ok())
+    }
+
+    fn to_yaml_with(&self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+        let mut value = serde_yaml::to_value(self).context("Failed to serialize config")?;
+        // A `${` set through the API, such as in a regex replacement, is
+        // saved as `$${` so that loading the file doesn't take it for a variable
+        escape_value(&mut value);
+        if let Some(template) = &self.template {
+            restore_placeholders(&mut value, template, lookup);
+        }
+        serde_yaml::to_string(&value).context("Failed to serialize config")
+    }
+
+    /// The proxy listeners: the `listeners` entries, or a single one built
+    /// from `server`
+    pub fn proxy_listeners(&self, overrides: &ServerOverrides) -> Vec<ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        let server = self.server.with_overrides(overrides);
+        vec![ListenerConfig {
+            name: None,
+            port: server.listen_port,
+            protocol: server.protocol,
+            upstream_host: server.upstream_host,
+            upstream_port: server.upstream_port,
+        }]
+    }
+
+    /// The upstream a new connection to the listener on `port` is forwarded
+    /// to, or `None` when no such listener is configured any more
+    pub fn upstream_for(&self, overrides: &ServerOverrides, port: u16) -> Option<(String, u16)> {
+        if self.listeners.is_empty() {
+            let server = self.server.with_overrides(overrides);
+            return (server.listen_port == port)
+                .then_some((server.upstream_host, server.upstream_port));
+        }
+        self.listeners
+            .iter()
+            .find(|listener| listener.port == port)
+            .map(|listener| (listener.upstream_host.clone(), listener.upstream_port))
+    }
+
+    /// Write the config to `path` without ever leaving a partly written file
+    /// there: the YAML goes to a temporary file in the same directory, which
+    /// is synced and then renamed over the original. The file replaced is
+    /// kept as `<path>.bak.<timestamp>`, up to `config_backups` of them.
+    pub fn save(&self, path: &str) -> Result<()> {
+        write_atomically(Path::new(path), self.config_backups, |file| {
+            Ok(file.write_all(self.to_yaml()?.as_bytes())?)
+        })
+    }
+
+    /// Rules written for the same table and column as an earlier rule of the
+    /// same list, which are flagged when the config is loaded: only one of
+    /// them can decide how the column is masked.
+    pub fn duplicate_rules(&self) -> Vec<String> {
+        let lists = std::iter::once(("rules", &self.rules)).chain(
+            self.rule_sets
+                .iter()
+                .map(|(name, rules)| (name.as_str(), rules)),
+        );
+        let mut duplicates = Vec::new();
+        for (list, rules) in lists {
+            for (i, rule) in rules.iter().enumerate() {
+                if let Some(first) = rules[..i].iter().find(|r| r.same_target(rule)) {
+                    duplicates.push(format!(
+                        "Rule {} in {} duplicates rule {} for {}",
+                        rule.id,
+                        list,
+                        first.id,
+                        rule.target()
+                    ));
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// Check the rules and that their IDs are unique, that policies only
+    /// refer to rule sets that exist, that custom scanner patterns have
+    /// distinct names, that scanner credentials give their password at most
+    /// one way, that the scan schedule is complete and that API scopes exist
+    pub fn validate(&self) -> Result<()> {
+        let mut ids = std::collections::HashSet::new();
+        for rule in self.rules.iter().chain(self.rule_sets.values().flatten()) {
+            rule.validate()?;
+            if !ids.insert(&rule.id) {
+                bail!("Duplicate masking rule ID '{}'", rule.id);
+            }
+        }
+        let patterns = &self.scanner.custom_patterns;
+        for (i, pattern) in patterns.iter().enumerate() {
+            if pattern.name.is_empty() {
+                bail!("Custom scanner pattern {} has no name", i);
+            }
+            if patterns[..i].iter().any(|p| p.name == pattern.name) {
+                bail!("Duplicate custom scanner pattern '{}'", pattern.name);
+            }
+        }
+        for (name, credentials) in &self.scanner.credentials {
+            let sources = [
+                credentials.password.is_some(),
+                credentials.password_file.is_some(),
+                credentials.password_env.is_some(),
+            ];
+            if sources.into_iter().filter(|set| *set).count() > 1 {
+                bail!(
+                    "Scanner credentials '{}' set more than one of password, password_file and password_env",
+                    name
+                );
+            }
+        }
+        if let Some(schedule) = &self.scanner.schedule {
+            schedule.validate()?;
+        }
+        if let Some(limits) = &self.limits
+            && (limits.max_connections == Some(0) || limits.connections_per_second == Some(0))
+        {
+            bail!("limits.max_connections and connections_per_second must be at least 1 when set");
+        }
+        if let Some(scope) = self
+            .api
+            .iter()
+            .flat_map(|api| {
+                api.default_scopes
+                    .iter()
+                    .chain(api.keys.iter().flat_map(|key| &key.scopes))
+            })
+            .find(|scope| !API_SCOPES.contains(&scope.as_str()))
+        {
+            bail!(
+                "Unknown API scope '{}'; valid scopes are {}",
+                scope,
+                API_SCOPES.join(", ")
+            );
+        }
+        if let Some(api) = &self.api {
+            api.cors.validate()?;
+            let lockout = &api.lockout;
+            if lockout.enabled && (lockout.max_failures == 0 || lockout.window_secs == 0) {
+                bail!("api.lockout.max_failures and window_secs must be at least 1");
+            }
+            for (i, key) in api.keys.iter().enumerate() {
+                if key.name.is_empty() {
+                    bail!("API key {} has no name", i);
+                }
+                if api.keys[..i].iter().any(|k| k.name == key.name) {
+                    bail!("Duplicate API key name '{}'", key.name);
+                }
+                let digest = key.key_hash.strip_prefix(API_KEY_HASH_PREFIX);
+                if !digest.is_some_and(|d| {
+                    d.len() == 64 && d.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+                }) {
+                    bail!(
+                        "API key '{}' has an invalid key_hash; expected sha256: and 64 lowercase hex digits",
+                        key.name
+                    );
+                }
+            }
+        }
+        for (i, policy) in self.policies.iter().enumerate() {
+            if self.policies[..i].iter().any(|p| p.name == policy.name) {
+                bail!("Duplicate masking policy '{}'", policy.name);
+            }
+            if let Some(rule_set) = &policy.rule_set
+                && !self.rule_sets.contains_key(rule_set)
+            {
+                bail!(
+                    "Masking policy '{}' uses unknown rule set '{}'",
+                    policy.name,
+                    rule_set
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn policy(&self, name: Option<&str>) -> Option<&MaskingPolicy> {
+        let name = name?;
+        self.policies.iter().find(|policy| policy.name == name)
+    }
+
+    /// Whether masking is enabled for connections under the named policy
+    pub fn masking_enabled_for(&self, policy: Option<&str>) -> bool {
+        self.policy(policy)
+            .and_then(|policy| policy.masking_enabled)
+            .unwrap_or(self.masking_enabled)
+    }
+
+    /// Rules applying to connections under the named policy
+    pub fn rules_for(&self, policy: Option<&str>) -> &[MaskingRule] {
+        self.policy(policy)
+            .and_then(|policy| policy.rule_set.as_ref())
+            .and_then(|rule_set| self.rule_sets.get(rule_set))
+            .unwrap_or(&self.rules)
+    }
+
+    /// Whether upstream connections use TLS; the strict modes imply it
+    pub fn upstream_tls_enabled(&self) -> bool {
+        self.upstream_tls || self.upstream_tls_mode != UpstreamTlsMode::Prefer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        use chrono::TimeZone;
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_config_load_valid_yaml() {
+        let yaml = r#"
+masking_enabled: true
+upstream_tls: false
+rules:
+  - table: "users"
+    column: "email"
+    strategy: "email"
+  - column: "phone"
+    strategy: "phone"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(config.masking_enabled);
+        assert!(!config.upstream_tls);
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.rules[0].table, Some("users".parse().unwrap()));
+        assert_eq!(config.rules[0].column, "email");
+        assert_eq!(config.rules[0].strategy, MaskStrategy::Email);
+        assert_eq!(config.rules[1].table, None);
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        let yaml = r#"
+rules:
+  - id: "email-rule"
+    column: "email"
+    strategy: "email"
+  - column: "phone"
+    strategy: "phone"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.rules[0].id.as_str(), "email-rule");
+        // A rule without an ID gets one, kept when the config is saved
+        let id = config.rules[1].id.clone();
+        assert!(uuid::Uuid::parse_str(id.as_str()).is_ok());
+        let saved: AppConfig =
+            serde_yaml::from_str(&serde_yaml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(saved.rules[1].id, id);
+        assert!(config.validate().is_ok());
+        assert!(config.duplicate_rules().is_empty());
+
+        let yaml = r#"
+rules:
+  - id: "same"
+    column: "email"
+    strategy: "email"
+rule_sets:
+  support:
+    - id: "same"
+      column: "phone"
+      strategy: "phone"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("Duplicate masking rule ID"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_api_scopes() {
+        let config: AppConfig = serde_yaml::from_str("rules: []\napi:\n  api_key: k\n").unwrap();
+        let scopes = config.api.unwrap().default_scopes;
+        assert!(scopes.contains(&"rules:write".to_string()));
+        assert!(!scopes.contains(&"detokenize".to_string()));
+
+        let config: AppConfig = serde_yaml::from_str(
+            "rules: []\napi:\n  default_scopes: [\"rules:read\", \"rules:wirte\"]\n",
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("rules:wirte"), "{}", err);
+    }
+
+    #[test]
+    fn test_cors_config() {
+        let config: AppConfig = serde_yaml::from_str("rules: []\napi:\n  api_key: k\n").unwrap();
+        let cors = config.api.unwrap().cors;
+        assert!(cors.allowed_origins.is_empty());
+        assert!(!cors.permissive);
+        assert!(cors.allowed_headers.contains(&"x-api-key".to_string()));
+
+        let cors = |yaml: &str| {
+            let config: AppConfig =
+                serde_yaml::from_str(&format!("rules: []\napi:\n  cors:\n{}", yaml)).unwrap();
+            config.validate()
+        };
+        assert!(cors("    allowed_origins: [\"https://dash.example.com:8443\", \"http://localhost:3000\"]\n    allow_credentials: true\n").is_ok());
+        assert!(cors("    allowed_origins: [\"*\"]\n").is_ok());
+        for invalid in [
+            "    allowed_origins: [\"dash.example.com\"]\n",
+            "    allowed_origins: [\"https://dash.example.com/\"]\n",
+            "    allowed_methods: [\"GET POST\"]\n",
+            "    allowed_headers: [\"x api key\"]\n",
+            "    allowed_origins: [\"*\"]\n    allow_credentials: true\n",
+            "    permissive: true\n    allow_credentials: true\n",
+        ] {
+            assert!(cors(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let hash = hash_api_key("secret");
+        assert!(hash.starts_with("sha256:2bb80d53"), "{}", hash);
+        let yaml = format!(
+            "rules: []\napi:\n  keys:\n    - name: ci\n      key_hash: \"{}\"\n      scopes: [rules:read]\n    - name: admin\n      key_hash: \"{}\"\n",
+            hash,
+            hash_api_key("other")
+        );
+        let config: AppConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.validate().is_ok());
+        let api = config.api.unwrap();
+        assert!(api.auth_required());
+        assert_eq!(api.find_key("secret").unwrap().name, "ci");
+        assert_eq!(api.find_key("other").unwrap().scopes, default_api_scopes());
+        assert!(api.find_key("guess").is_none());
+        assert!(!api.is_legacy_key("secret"));
+
+        let yaml = "rules: []\napi:\n  keys:\n    - name: ci\n      key_hash: \"secret\"\n";
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("invalid key_hash"), "{}", err);
+    }
+
+    #[test]
+    fn test_rule_validation() {
+        let yaml = r#"
+rules:
+  - column: "email"
+    strategy: "email"
+  - table: "users"
+    column: "email"
+    strategy: "hash"
+  - id: "again"
+    column: "email"
+    strategy: "redact"
+rule_sets:
+  support:
+    - column: "email"
+      strategy: "none"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        // Flagged, not rejected
+        assert!(config.validate().is_ok());
+        let duplicates = config.duplicate_rules();
+        assert_eq!(duplicates.len(), 1);
+        assert!(
+            duplicates[0].starts_with("Rule again in rules"),
+            "{}",
+            duplicates[0]
+        );
+
+        let err =
+            serde_yaml::from_str::<AppConfig>("rules:\n  - column: ssn\n    strategy: emial\n")
+                .unwrap_err();
+        assert!(err.to_string().contains("emial"), "{}", err);
+        let config: AppConfig =
+            serde_yaml::from_str("rules:\n  - column: ''\n    strategy: email\n").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let yaml = r#"
+rules: []
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(config.masking_enabled); // Should default to true
+        assert!(!config.upstream_tls); // Should default to false
+        assert!(config.tls.is_none()); // Should default to None
+    }
+
+    #[test]
+    fn test_config_with_tls() {
+        let yaml = r#"
+masking_enabled: true
+upstream_tls: true
+tls:
+  enabled: true
+  cert_path: "certs/server.crt"
+  key_path: "certs/server.key"
+rules: []
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(config.upstream_tls);
+        assert!(config.tls.is_some());
+
+        let tls = config.tls.unwrap();
+        assert!(tls.enabled);
+        assert_eq!(tls.cert_path, "certs/server.crt");
+        assert_eq!(tls.key_path, "certs/server.key");
+        assert_eq!(tls.client_ca_path, None);
+        assert!(!tls.require_client_auth);
+    }
+
+    #[test]
+    fn test_config_with_client_auth() {
+        let yaml = r#"
+tls:
+  enabled: true
+  cert_path: "certs/server.crt"
+  key_path: "certs/server.key"
+  client_ca_path: "certs/client-ca.pem"
+  require_client_auth: true
+rules: []
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.client_ca_path.as_deref(), Some("certs/client-ca.pem"));
+        assert!(tls.require_client_auth);
+    }
+
+    #[test]
+    fn test_config_with_catalog_lookup() {
+        let yaml = r#"
+rules: []
+catalog_lookup:
+  username: "ironveil"
+  password: "secret"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let lookup = config.catalog_lookup.unwrap();
+        assert_eq!(lookup.username, "ironveil");
+        assert_eq!(lookup.password, "secret");
+        assert_eq!(lookup.cache_size, 1024);
+        assert_eq!(lookup.connect_timeout_secs, 5);
+    }
+
+    #[test]
+    fn test_config_with_listeners() {
+        let yaml = r#"
+rules: []
+listeners:
+  - name: orders
+    port: 6543
+    upstream_host: pg.internal
+    upstream_port: 5432
+  - port: 3307
+    protocol: mysql
+    upstream_port: 3306
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(config.listeners.len(), 2);
+        assert_eq!(config.listeners[0].name(), "orders");
+        assert_eq!(config.listeners[0].protocol, DbProtocol::Postgres);
+        assert_eq!(config.listeners[0].upstream_host, "pg.internal");
+        assert_eq!(config.listeners[1].name(), "mysql-3307");
+        assert_eq!(config.listeners[1].protocol, DbProtocol::Mysql);
+        assert_eq!(config.listeners[1].upstream_host, "127.0.0.1");
+    }
+
+    #[test]
+    fn test_config_upstream_connect_limits() {
+        let yaml = r#"
+rules: []
+limits:
+  connect_timeout_secs: 3
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let limits = config.limits.unwrap();
+        assert_eq!(limits.upstream_connect_timeout_secs, 3);
+        assert_eq!(limits.upstream_connect_retries, 2);
+    }
+
+    #[test]
+    fn test_config_upstream_tls_mode() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.upstream_tls_mode, UpstreamTlsMode::Prefer);
+
+        let config: AppConfig =
+            serde_yaml::from_str("rules: []\nupstream_tls_mode: verify-full").unwrap();
+        assert_eq!(config.upstream_tls_mode, UpstreamTlsMode::VerifyFull);
+    }
+
+    #[test]
+    fn test_config_copy_in_policy() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.copy_in_policy, CopyInPolicy::Allow);
+
+        let config: AppConfig = serde_yaml::from_str("rules: []\ncopy_in_policy: block").unwrap();
+        assert_eq!(config.copy_in_policy, CopyInPolicy::Block);
+        assert_eq!(config.local_infile_policy, CopyInPolicy::Allow);
+
+        let config: AppConfig =
+            serde_yaml::from_str("rules: []\nlocal_infile_policy: log").unwrap();
+        assert_eq!(config.local_infile_policy, CopyInPolicy::Log);
+    }
+
+    #[test]
+    fn test_config_mysql_strip_capabilities() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert!(
+            config
+                .mysql_strip_capabilities
+                .contains(&MySqlCapability::Compress)
+        );
+
+        let config: AppConfig =
+            serde_yaml::from_str("rules: []\nmysql_strip_capabilities: [local_files]").unwrap();
+        assert_eq!(
+            config.mysql_strip_capabilities,
+            vec![MySqlCapability::LocalFiles]
+        );
+        assert_eq!(
+            MySqlCapability::LocalFiles.flag(),
+            crate::protocol::mysql::CLIENT_LOCAL_FILES
+        );
+    }
+
+    #[test]
+    fn test_config_masking_policies() {
+        let yaml = r#"
+masking_enabled: true
+rules:
+  - column: email
+    strategy: email
+rule_sets:
+  bi:
+    - column: salary
+      strategy: redact
+policies:
+  - name: migrations
+    match: { user: migrator, client_cidr: "10.0.0.0/8" }
+    masking_enabled: false
+  - name: bi
+    match: { application_name: metabase }
+    rule_set: bi
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let migrations = &config.policies[0].criteria;
+        let addr = |ip: &str| Some(ip.parse().unwrap());
+        assert!(migrations.matches(Some("migrator"), None, None, addr("10.1.2.3")));
+        assert!(!migrations.matches(Some("migrator"), None, None, addr("192.168.0.1")));
+        assert!(!migrations.matches(Some("migrator"), None, None, None));
+        assert!(!migrations.matches(Some("bi"), None, None, addr("10.1.2.3")));
+
+        assert!(!config.masking_enabled_for(Some("migrations")));
+        assert_eq!(config.rules_for(Some("migrations"))[0].column, "email");
+        assert!(config.masking_enabled_for(Some("bi")));
+        assert_eq!(config.rules_for(Some("bi"))[0].column, "salary");
+        // Unknown or no policy: the global settings
+        assert!(config.masking_enabled_for(Some("gone")));
+        assert_eq!(config.rules_for(None)[0].column, "email");
+
+        let mut config = config;
+        config.policies[1].rule_set = Some("missing".to_string());
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("unknown rule set 'missing'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_json_path_parsing() {
+        use JsonPathSegment::*;
+
+        let path = JsonPath::new("$.orders[*].items[0]['unit price']").unwrap();
+        assert_eq!(
+            path.segments(),
+            [
+                Key("orders".to_string()),
+                Wildcard,
+                Key("items".to_string()),
+                Index(0),
+                Key("unit price".to_string()),
+            ]
+        );
+        assert_eq!(JsonPath::new("$.a.*").unwrap().segments()[1], Wildcard);
+        assert!(JsonPath::new("$").unwrap().segments().is_empty());
+
+        for invalid in ["customer.email", "$.", "$[x]", "$['open", "$[0", "$a"] {
+            assert!(JsonPath::new(invalid).is_err(), "{}", invalid);
+        }
+
+        let err = serde_yaml::from_str::<MaskingRule>(
+            "column: payload\nstrategy: email\njson_paths: [\"$.a[\"]",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("invalid JSON path '$.a['"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_config_rule_name_patterns() {
+        let yaml = r#"
+rules:
+  - column: "*_email"
+    strategy: email
+  - table: "re:^audit_\\d+$"
+    column: "ip?"
+    strategy: redact
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let email = &config.rules[0].column;
+        assert!(!email.is_exact());
+        assert!(email.matches("user_email"));
+        assert!(email.matches("_email"));
+        assert!(!email.matches("email"));
+        assert!(!email.matches("user_email_verified"));
+
+        let table = config.rules[1].table.as_ref().unwrap();
+        assert!(table.matches("audit_2024"));
+        assert!(!table.matches("audit_log"));
+        assert!(config.rules[1].column.matches("ip4"));
+        assert!(!config.rules[1].column.matches("ip"));
+
+        // Regex metacharacters in globs are literal
+        let pattern: NamePattern = "user.*".parse().unwrap();
+        assert!(pattern.matches("user.email"));
+        assert!(!pattern.matches("users_email"));
+
+        // Patterns serialize as written
+        assert_eq!(
+            serde_yaml::to_string(&config.rules[0].column)
+                .unwrap()
+                .trim(),
+            "'*_email'"
+        );
+
+        let err = serde_yaml::from_str::<AppConfig>(
+            "rules:\n  - column: \"re:(unclosed\"\n    strategy: email",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("invalid name pattern 're:(unclosed'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_config_strategy_names() {
+        for strategy in MaskStrategy::ALL {
+            assert_eq!(strategy.as_str().parse::<MaskStrategy>().unwrap(), strategy);
+        }
+        assert_eq!(
+            serde_yaml::to_string(&MaskStrategy::Exclude)
+                .unwrap()
+                .trim(),
+            "none"
+        );
+
+        // A typo fails at load instead of masking with a fallback
+        let err =
+            serde_yaml::from_str::<AppConfig>("rules:\n  - column: email\n    strategy: emial")
+                .unwrap_err()
+                .to_string();
+        assert!(err.contains("unknown masking strategy 'emial'"), "{}", err);
+        assert!(err.contains("email_preserve_domain"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_custom_rule_pattern() {
+        let yaml = r#"
+rules:
+  - column: customer_id
+    strategy: custom
+    options:
+      pattern: "^CUST-(\\d{2})\\d+$"
+      replacement: "CUST-$1{rand:6}"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let options = config.rules[0].options.as_ref().unwrap();
+        assert_eq!(
+            options.pattern.as_ref().unwrap().0.as_str(),
+            r"^CUST-(\d{2})\d+$"
+        );
+        assert!(config.rules[0].validate().is_ok());
+
+        // The pattern round-trips as a plain string
+        let yaml = serde_yaml::to_string(&config.rules[0]).unwrap();
+        let rule: MaskingRule = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(rule.options, config.rules[0].options);
+
+        let err = serde_yaml::from_str::<AppConfig>(
+            "rules:\n  - column: id\n    strategy: custom\n    options: { pattern: \"(unclosed\" }",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("invalid pattern '(unclosed'"),
+            "{}",
+            err
+        );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"rules:\n  - column: id\n    strategy: custom\n")
+            .unwrap();
+        let err = AppConfig::load(file.path().to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("options.pattern"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_custom_scanner_patterns() {
+        let yaml = r#"
+rules: []
+scanner:
+  custom_patterns:
+    - name: employee_id
+      regex: "EMP-\\d{6}"
+      strategy: hash
+    - name: loyalty_card
+      regex: "\\d{16}"
+      strategy: credit_card
+      validate: luhn
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let patterns = &config.scanner.custom_patterns;
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].regex.0.as_str(), r"EMP-\d{6}");
+        assert_eq!(patterns[0].strategy, MaskStrategy::Hash);
+        assert_eq!(patterns[0].validate, PatternValidation::None);
+        assert_eq!(patterns[1].validate, PatternValidation::Luhn);
+        assert!(config.validate().is_ok());
+
+        let err = serde_yaml::from_str::<AppConfig>(
+            "rules: []\nscanner:\n  custom_patterns:\n    - { name: x, regex: \"(unclosed\", strategy: redact }",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("invalid pattern '(unclosed'"),
+            "{}",
+            err
+        );
+
+        let mut config = config;
+        config.scanner.custom_patterns[1].name = "employee_id".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Duplicate custom scanner pattern 'employee_id'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_config_scanner_credentials() {
+        let yaml = r#"
+rules: []
+scanner:
+  credentials:
+    default:
+      username: scanner
+      password_env: SCANNER_PASSWORD
+"#;
+        let mut config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let credentials = config.scanner.credentials.get_mut("default").unwrap();
+        credentials.password = Some("inline".to_string());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("more than one"), "{}", err);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "s3cret\r\n").unwrap();
+        let credentials = ScannerCredentials {
+            username: "scanner".to_string(),
+            password: None,
+            password_file: Some(path.to_string_lossy().into_owned()),
+            password_env: None,
+        };
+        assert_eq!(credentials.resolve_password().unwrap(), "s3cret");
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_server_config() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.server, ServerConfig::default());
+        let listeners = config.proxy_listeners(&ServerOverrides::default());
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].port, 6543);
+        assert_eq!(listeners[0].upstream_port, 5432);
+
+        let yaml = r#"
+rules: []
+server:
+  listen_port: 7000
+  listen_address: 127.0.0.1
+  api_bind: 127.0.0.1
+  protocol: mysql
+  upstream_host: db.internal
+  upstream_port: 3306
+  shutdown_timeout: 5
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.server.api_port, 3001);
+        let overrides = ServerOverrides {
+            listen_port: Some(7001),
+            upstream_host: Some("10.0.0.5".to_string()),
+            ..Default::default()
+        };
+        let server = config.server.with_overrides(&overrides);
+        assert_eq!(server.listen_port, 7001);
+        assert_eq!(server.upstream_host, "10.0.0.5");
+        assert_eq!(server.upstream_port, 3306);
+        assert_eq!(server.protocol, DbProtocol::Mysql);
+        assert_eq!(server.shutdown_timeout, 5);
+
+        let listener = config.proxy_listeners(&overrides).remove(0);
+        assert_eq!(listener.name(), "mysql-7001");
+        assert_eq!(
+            config.upstream_for(&overrides, 7001),
+            Some(("10.0.0.5".to_string(), 3306))
+        );
+        assert_eq!(config.upstream_for(&overrides, 7000), None);
+
+        let mut changed = config.server.clone();
+        changed.upstream_port = 3307;
+        assert!(config.server.restart_required(&changed).is_empty());
+        changed.listen_port = 7002;
+        changed.protocol = DbProtocol::Postgres;
+        assert_eq!(
+            config.server.restart_required(&changed),
+            vec!["listen_port", "protocol"]
+        );
+
+        // With `listeners`, their upstreams are looked up by port
+        let mut config = config;
+        config.listeners = vec![ListenerConfig {
+            name: None,
+            port: 6000,
+            protocol: DbProtocol::Postgres,
+            upstream_host: "pg".to_string(),
+            upstream_port: 5432,
+        }];
+        assert_eq!(config.proxy_listeners(&overrides).len(), 1);
+        assert_eq!(
+            config.upstream_for(&overrides, 6000),
+            Some(("pg".to_string(), 5432))
+        );
+        assert_eq!(config.upstream_for(&overrides, 7001), None);
+    }
+
+    #[test]
+    fn test_config_env_interpolation() {
+        let yaml = r#"
+rules: []
+upstream_tls_server_name: "db.${DOMAIN:-internal}.example.com"
+api:
+  api_key: ${API_KEY}
+  jwt_secret: "${JWT_SECRET:-dev-secret}"
+tls:
+  enabled: false
+  cert_path: "$${NOT_A_VAR}/$HOME"
+  key_path: "${EMPTY:-fallback}"
+"#;
+        let config =
+            AppConfig::parse_with(yaml, &env(&[("API_KEY", "k#1: x"), ("EMPTY", "")])).unwrap();
+        let api = config.api.as_ref().unwrap();
+        // Secrets are taken as they are, whatever YAML they look like
+        assert_eq!(api.api_key.as_deref(), Some("k#1: x"));
+        assert_eq!(api.jwt_secret.as_deref(), Some("dev-secret"));
+        assert_eq!(
+            config.upstream_tls_server_name.as_deref(),
+            Some("db.internal.example.com")
+        );
+        let tls = config.tls.as_ref().unwrap();
+        assert_eq!(tls.cert_path, "${NOT_A_VAR}/$HOME");
+        assert_eq!(tls.key_path, "fallback");
+
+        let err = AppConfig::parse_with(
+            "rules: []\napi:\n  api_key: ${API_KEY}\n  jwt_secret: ${JWT_SECRET}-${API_KEY}\n",
+            &env(&[]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Config refers to unset environment variables: API_KEY, JWT_SECRET"
+        );
+        let err =
+            AppConfig::parse_with("rules: []\nupstream_tls_server_name: ${DOMAIN\n", &env(&[]))
+                .unwrap_err();
+        assert!(err.to_string().contains("Unterminated"), "{}", err);
+        // Anything but a variable name is kept as it is
+        let config = AppConfig::parse_with(
+            "rules: []\nupstream_tls_server_name: ${1DOMAIN}\n",
+            &env(&[]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.upstream_tls_server_name.as_deref(),
+            Some("${1DOMAIN}")
+        );
+    }
+
+    #[test]
+    fn test_config_regex_replacement_not_interpolated() {
+        let yaml = r#"
+rules:
+  - column: customer_id
+    strategy: custom
+    options:
+      pattern: "^CUST-(\\d{2})(?<region>[A-Z]{2})\\d+$"
+      replacement: "CUST-${1}-$${region}-{rand:6}"
+api:
+  api_key: ${API_KEY}
+"#;
+        let lookup = env(&[("API_KEY", "s3cret"), ("region", "eu")]);
+        let mut config = AppConfig::parse_with(yaml, &lookup).unwrap();
+        let options = config.rules[0].options.clone().unwrap();
+        // Numbered groups need no escaping; named ones are written `$${name}`
+        assert_eq!(options.replacement, "CUST-${1}-${region}-{rand:6}");
+        assert!(config.rules[0].validate().is_ok());
+
+        // A replacement set through the API survives a save and a reload
+        let mut rule = config.rules[0].clone();
+        rule.column = "order_id".parse().unwrap();
+        rule.options.as_mut().unwrap().replacement = "ORD-${region}-${2}".to_string();
+        config.rules.push(rule);
+        let saved = config.to_yaml_with(&lookup).unwrap();
+        assert!(saved.contains("api_key: ${API_KEY}"), "{}", saved);
+        let reloaded = AppConfig::parse_with(&saved, &lookup).unwrap();
+        assert_eq!(
+            reloaded.rules[0].options.as_ref().unwrap().replacement,
+            "CUST-${1}-${region}-{rand:6}"
+        );
+        assert_eq!(
+            reloaded.rules[1].options.as_ref().unwrap().replacement,
+            "ORD-${region}-${2}"
+        );
+        assert_eq!(
+            reloaded.api.as_ref().unwrap().api_key.as_deref(),
+            Some("s3cret")
+        );
+    }
+
+    #[test]
+    fn test_config_save_keeps_placeholders() {
+        let yaml = "rules: []\napi:\n  api_key: ${API_KEY}\n  jwt_secret: ${JWT_SECRET:-dev}\n";
+        let lookup = env(&[("API_KEY", "s3cret")]);
+        let mut config = AppConfig::parse_with(yaml, &lookup).unwrap();
+
+        config.masking_enabled = false;
+        let saved = config.to_yaml_with(&lookup).unwrap();
+        assert!(!saved.contains("s3cret"), "{}", saved);
+        assert!(saved.contains("api_key: ${API_KEY}"), "{}", saved);
+        assert!(
+            saved.contains("jwt_secret: ${JWT_SECRET:-dev}"),
+            "{}",
+            saved
+        );
+        assert!(saved.contains("masking_enabled: false"), "{}", saved);
+
+        // A value changed since loading is saved as it is now
+        config.api.as_mut().unwrap().jwt_secret = Some("rotated".to_string());
+        let saved = config.to_yaml_with(&lookup).unwrap();
+        assert!(saved.contains("jwt_secret: rotated"), "{}", saved);
+        assert!(saved.contains("api_key: ${API_KEY}"), "{}", saved);
+
+        let reloaded = AppConfig::parse_with(&saved, &lookup).unwrap();
+        assert_eq!(reloaded.api.unwrap().api_key.as_deref(), Some("s3cret"));
+    }
+
+    #[test]
+    fn test_save_keeps_original_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        fs::write(&path, "rules: []\n").unwrap();
+
+        // Serialization failing after part of the YAML has been written
+        let err = write_atomically(&path, 5, |file| {
+            file.write_all(b"rules:\n  - column: ema")?;
+            bail!("serialization failed")
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("serialization failed"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "rules: []\n");
+        // Neither a temporary file nor a backup is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_save_rotates_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let path_str = path.to_str().unwrap();
+        let backups = || {
+            let mut names: Vec<String> = fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.starts_with("proxy.yaml.bak."))
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Nothing to back up the first time
+        let mut config = AppConfig {
+            config_backups: 2,
+            ..Default::default()
+        };
+        config.save(path_str).unwrap();
+        assert!(backups().is_empty());
+
+        for enabled in [false, true, false] {
+            config.masking_enabled = enabled;
+            config.save(path_str).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let kept = backups();
+        assert_eq!(kept.len(), 2);
+        // The newest backup is the config before the last save
+        let newest = fs::read_to_string(dir.path().join(&kept[1])).unwrap();
+        assert!(newest.contains("masking_enabled: true"));
+        let saved = AppConfig::load(path_str).unwrap();
+        assert!(!saved.masking_enabled);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        config.config_backups = 0;
+        config.save(path_str).unwrap();
+        assert_eq!(backups(), kept);
+    }
+
+    #[test]
+    fn test_config_mysql_column_match() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.mysql_column_match, ColumnMatch::OriginalOrAlias);
+
+        let config: AppConfig =
+            serde_yaml::from_str("rules: []\nmysql_column_match: original").unwrap();
+        assert_eq!(config.mysql_column_match, ColumnMatch::Original);
+    }
+
+    #[test]
+    fn test_config_partial_masking_options() {
+        let yaml = r#"
+rules:
+  - column: card
+    strategy: partial
+    options:
+      keep_suffix: 4
+      mask_char: "x"
+  - column: email
+    strategy: email
+heuristic_masking:
+  strategy: partial
+  options:
+    keep_prefix: 1
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.rules[0].options,
+            Some(MaskingOptions {
+                mask_char: 'x',
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.rules[1].options, None);
+        assert_eq!(
+            config.heuristic_masking.strategy,
+            HeuristicStrategy::Partial
+        );
+        assert_eq!(config.heuristic_masking.options.keep_prefix, 1);
+        assert_eq!(config.heuristic_masking.options.keep_suffix, 4);
+
+        // Rules without options serialize as before
+        let yaml = serde_yaml::to_string(&config.rules[1]).unwrap();
+        assert!(!yaml.contains("options"));
+
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.heuristic_masking.strategy, HeuristicStrategy::Fake);
+    }
+
+    #[test]
+    fn test_config_masking_hash_settings() {
+        let config: AppConfig = serde_yaml::from_str("rules: []").unwrap();
+        assert_eq!(config.masking, MaskingConfig::default());
+
+        let yaml = "rules: []\nmasking:\n  hash_salt: pepper\n  hash_length: 16";
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.masking.hash_salt.as_deref(), Some("pepper"));
+        assert_eq!(config.masking.hash_length, Some(16));
+    }
+
+    #[test]
+    fn test_invalid_yaml_fails() {
+        let yaml = r#"
+invalid yaml content {{
+"#;
+        let result: Result<AppConfig, _> = serde_yaml::from_str(yaml);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_required_fields_fails() {
+        let yaml = r#"
+masking_enabled: true
+"#;
+        let result: Result<AppConfig, _> = serde_yaml::from_str(yaml);
+        assert!(result.is_err()); // Should fail because 'rules' is missing
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        // Mondays at 03:00; 2026-10-17 is a Saturday
+        let weekly = CronSchedule::new("0 3 * * 1").unwrap();
+        let now = at(2026, 10, 17, 12, 30);
+        assert_eq!(weekly.next_after(now), Some(at(2026, 10, 19, 3, 0)));
+        assert_eq!(
+            weekly.next_after(at(2026, 10, 19, 3, 0)),
+            Some(at(2026, 10, 26, 3, 0))
+        );
+
+        let quarter_hourly = CronSchedule::new("*/15 * * * *").unwrap();
+        assert_eq!(
+            quarter_hourly.next_after(now),
+            Some(at(2026, 10, 17, 12, 45))
+        );
+        let offset = CronSchedule::new("5/20 9-17 * * *").unwrap();
+        assert_eq!(offset.next_after(now), Some(at(2026, 10, 17, 12, 45)));
+        assert_eq!(
+            offset.next_after(at(2026, 10, 17, 17, 45)),
+            Some(at(2026, 10, 18, 9, 5))
+        );
+
+        // Year end, and Sunday as 7
+        let new_year = CronSchedule::new("0 0 1 1 *").unwrap();
+        assert_eq!(new_year.next_after(now), Some(at(2027, 1, 1, 0, 0)));
+        let sunday = CronSchedule::new("30 6 * * 7").unwrap();
+        assert_eq!(sunday.next_after(now), Some(at(2026, 10, 18, 6, 30)));
+
+        // Both day fields restricted: either matches
+        let either = CronSchedule::new("0 0 1 * 1").unwrap();
+        assert_eq!(either.next_after(now), Some(at(2026, 10, 19, 0, 0)));
+        assert_eq!(
+            either.next_after(at(2026, 10, 26, 0, 0)),
+            Some(at(2026, 11, 1, 0, 0))
+        );
+
+        let leap_day = CronSchedule::new("0 0 29 2 *").unwrap();
+        assert_eq!(leap_day.next_after(now), Some(at(2028, 2, 29, 0, 0)));
+        assert_eq!(
+            CronSchedule::new("0 0 30 2 *").unwrap().next_after(now),
+            None
+        );
+
+        for invalid in [
+            "0 3 * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(CronSchedule::new(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_schedule_next_run() {
+        let schedule: ScanScheduleConfig = serde_yaml::from_str(
+            r#"
+interval_secs: 3600
+scan:
+  database: shop
+"#,
+        )
+        .unwrap();
+        let last = at(2026, 10, 17, 12, 0);
+        assert_eq!(
+            schedule.next_run(last, at(2026, 10, 17, 12, 30)),
+            Some(at(2026, 10, 17, 13, 0))
+        );
+        // Overdue runs are due straight away
+        let late = at(2026, 10, 17, 15, 10);
+        assert_eq!(schedule.next_run(last, late), Some(late));
+
+        let schedule: ScanScheduleConfig = serde_yaml::from_str(
+            r#"
+cron: "0 3 * * 1"
+scan:
+  database: shop
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.next_run(last, at(2026, 10, 17, 12, 30)),
+            Some(at(2026, 10, 19, 3, 0))
+        );
+        assert_eq!(
+            serde_json::to_value(&schedule).unwrap()["cron"],
+            "0 3 * * 1"
+        );
+    }
+
+    #[test]
+    fn test_config_scan_schedule_validation() {
+        let load = |schedule: &str| {
+            let yaml = format!("rules: []\nscanner:\n  schedule:\n{}", schedule);
+            let config: AppConfig = serde_yaml::from_str(&yaml).unwrap();
+            config.validate()
+        };
+        assert!(load("    interval_secs: 60\n    scan: {database: shop}").is_ok());
+        assert!(load("    scan: {database: shop}").is_err());
+        assert!(load("    interval_secs: 0\n    scan: {database: shop}").is_err());
+        assert!(
+            load("    interval_secs: 60\n    cron: '* * * * *'\n    scan: {database: shop}")
+                .is_err()
+        );
+        assert!(
+            load("    interval_secs: 60\n    scan: {database: shop, password: secret}").is_err()
+        );
+        assert!(
+            serde_yaml::from_str::<ScanScheduleConfig>("cron: '0 3 * *'\nscan: {}")
+                .unwrap_err()
+                .to_string()
+                .contains("invalid cron expression")
+        );
     }
 }
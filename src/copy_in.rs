@@ -0,0 +1,372 @@
+//! Inspection of client-supplied `COPY ... FROM STDIN` data, and of the
+//! file contents streamed for a MySQL `LOAD DATA LOCAL INFILE`.
+//!
+//! CopyData messages (and infile data packets) carry arbitrary chunks of the
+//! input stream, so rows can be split across messages. The inspector buffers partial lines, splits
+//! complete rows into fields and runs each field through the PII scanner.
+
+use crate::scanner::{PiiScanner, PiiType};
+
+/// Upper bound on a buffered partial line; longer lines are scanned as-is
+const MAX_PARTIAL_LINE: usize = 1024 * 1024;
+
+/// Row format of a COPY, as declared in the COPY statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyFormat {
+    pub delimiter: u8,
+    pub csv: bool,
+}
+
+impl Default for CopyFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: b'\t',
+            csv: false,
+        }
+    }
+}
+
+impl CopyFormat {
+    /// Parse the format of a `COPY ... FROM STDIN` statement.
+    /// Returns `None` for any other statement.
+    pub fn from_query(query: &str) -> Option<Self> {
+        let upper = query.trim_start().to_ascii_uppercase();
+        if !upper.starts_with("COPY") {
+            return None;
+        }
+        let options_start = upper.find("FROM STDIN")? + "FROM STDIN".len();
+        let options = &upper[options_start..];
+
+        let csv = options
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == "CSV");
+        let mut format = Self {
+            delimiter: if csv { b',' } else { b'\t' },
+            csv,
+        };
+
+        if let Some(pos) = options.find("DELIMITER") {
+            // Read the delimiter from the original query to preserve its case
+            let rest = query.trim_start()[options_start + pos + "DELIMITER".len()..].trim_start();
+            let rest = rest
+                .strip_prefix("AS ")
+                .or_else(|| rest.strip_prefix("as "))
+                .unwrap_or(rest)
+                .trim_start();
+            if let Some(quoted) = rest.strip_prefix('\'')
+                && let Some(&delimiter) = quoted.as_bytes().first()
+                && delimiter != b'\''
+            {
+                format.delimiter = delimiter;
+            }
+        }
+
+        Some(format)
+    }
+
+    /// Parse the format of a MySQL `LOAD DATA LOCAL INFILE` statement.
+    /// Returns `None` for any other statement.
+    pub fn from_load_data(query: &str) -> Option<Self> {
+        let query = query.trim_start();
+        let upper = query.to_ascii_uppercase();
+        if !upper.starts_with("LOAD DATA") || !upper.contains(" LOCAL ") {
+            return None;
+        }
+
+        // The MySQL defaults match the Postgres text format
+        let mut format = Self::default();
+        let Some(fields_start) = upper.find(" FIELDS ").or_else(|| upper.find(" COLUMNS ")) else {
+            return Some(format);
+        };
+        let fields_end = upper[fields_start..]
+            .find(" LINES ")
+            .map_or(upper.len(), |end| fields_start + end);
+        let clause = &upper[fields_start..fields_end];
+
+        if let Some(pos) = clause.find("TERMINATED BY")
+            && let Some(delimiter) =
+                read_quoted_byte(&query[fields_start + pos + "TERMINATED BY".len()..])
+        {
+            format.delimiter = delimiter;
+        }
+        // Quoted fields are read like CSV
+        format.csv = clause.contains("ENCLOSED BY");
+        Some(format)
+    }
+}
+
+/// First character of a quoted SQL string literal, undoing a backslash escape
+fn read_quoted_byte(s: &str) -> Option<u8> {
+    let s = s.trim_start().as_bytes();
+    let quote = *s.first().filter(|&&q| q == b'\'' || q == b'"')?;
+    match s.get(1..)? {
+        [b'\\', b't', ..] => Some(b'\t'),
+        [b'\\', escaped, ..] => Some(*escaped),
+        [b, ..] if *b != quote => Some(*b),
+        _ => None,
+    }
+}
+
+/// Result of inspecting a chunk of COPY data
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    pub rows: u64,
+    /// 1-based row numbers that contained PII, with the first type detected
+    pub flagged: Vec<(u64, PiiType)>,
+}
+
+/// Incremental scanner for one COPY FROM STDIN stream
+pub struct CopyInInspector {
+    format: CopyFormat,
+    partial: Vec<u8>,
+    rows_seen: u64,
+}
+
+impl CopyInInspector {
+    pub fn new(format: CopyFormat) -> Self {
+        Self {
+            format,
+            partial: Vec::new(),
+            rows_seen: 0,
+        }
+    }
+
+    /// Inspect a CopyData payload, buffering any trailing partial line
+    pub fn feed(&mut self, data: &[u8], scanner: &PiiScanner) -> CopyReport {
+        let mut report = CopyReport::default();
+        let mut start = 0;
+        for (i, &b) in data.iter().enumerate() {
+            if b == b'\n' {
+                if self.partial.is_empty() {
+                    self.scan_line(&data[start..i], scanner, &mut report);
+                } else {
+                    let mut line = std::mem::take(&mut self.partial);
+                    line.extend_from_slice(&data[start..i]);
+                    self.scan_line(&line, scanner, &mut report);
+                }
+                start = i + 1;
+            }
+        }
+
+        self.partial.extend_from_slice(&data[start..]);
+        if self.partial.len() > MAX_PARTIAL_LINE {
+            let line = std::mem::take(&mut self.partial);
+            self.scan_line(&line, scanner, &mut report);
+        }
+        report
+    }
+
+    /// Inspect whatever is left once the client sends CopyDone
+    pub fn finish(&mut self, scanner: &PiiScanner) -> CopyReport {
+        let mut report = CopyReport::default();
+        let line = std::mem::take(&mut self.partial);
+        if !line.is_empty() {
+            self.scan_line(&line, scanner, &mut report);
+        }
+        report
+    }
+
+    fn scan_line(&mut self, line: &[u8], scanner: &PiiScanner, report: &mut CopyReport) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // End-of-data marker
+        if line == b"\\." || line.is_empty() {
+            return;
+        }
+
+        self.rows_seen += 1;
+        report.rows += 1;
+
+        let fields = if self.format.csv {
+            split_csv(line, self.format.delimiter)
+        } else {
+            split_text(line, self.format.delimiter)
+        };
+        let detected = fields
+            .iter()
+            .flatten()
+            .filter_map(|field| std::str::from_utf8(field).ok())
+            .find_map(|field| scanner.scan(field.trim()));
+        if let Some(pii_type) = detected {
+            report.flagged.push((self.rows_seen, pii_type));
+        }
+    }
+}
+
+/// Split a text-format row, undoing backslash escapes. `\N` fields are `None`.
+fn split_text(line: &[u8], delimiter: u8) -> Vec<Option<Vec<u8>>> {
+    let mut fields = Vec::new();
+    let mut raw_fields = Vec::new();
+    let mut field_start = 0;
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] == b'\\' {
+            i += 2;
+            continue;
+        }
+        if line[i] == delimiter {
+            raw_fields.push(&line[field_start..i]);
+            field_start = i + 1;
+        }
+        i += 1;
+    }
+    raw_fields.push(&line[field_start.min(line.len())..]);
+
+    for raw in raw_fields {
+        if raw == b"\\N" {
+            fields.push(None);
+            continue;
+        }
+        let mut field = Vec::with_capacity(raw.len());
+        let mut iter = raw.iter();
+        while let Some(&b) = iter.next() {
+            if b != b'\\' {
+                field.push(b);
+                continue;
+            }
+            match iter.next() {
+                Some(b't') => field.push(b'\t'),
+                Some(b'n') => field.push(b'\n'),
+                Some(b'r') => field.push(b'\r'),
+                Some(&other) => field.push(other),
+                None => {}
+            }
+        }
+        fields.push(Some(field));
+    }
+    fields
+}
+
+/// Split a CSV-format row, honouring double-quoted fields
+fn split_csv(line: &[u8], delimiter: u8) -> Vec<Option<Vec<u8>>> {
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut in_quotes = false;
+    let mut iter = line.iter().peekable();
+    while let Some(&b) = iter.next() {
+        if in_quotes {
+            if b == b'"' {
+                if iter.peek() == Some(&&b'"') {
+                    field.push(b'"');
+                    iter.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(b);
+            }
+        } else if b == b'"' {
+            in_quotes = true;
+        } else if b == delimiter {
+            fields.push(Some(std::mem::take(&mut field)));
+        } else {
+            field.push(b);
+        }
+    }
+    fields.push(Some(field));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_format_from_query() {
+        assert_eq!(
+            CopyFormat::from_query("COPY users FROM STDIN"),
+            Some(CopyFormat::default())
+        );
+        assert_eq!(
+            CopyFormat::from_query("copy users (email) from stdin with (format csv)"),
+            Some(CopyFormat {
+                delimiter: b',',
+                csv: true
+            })
+        );
+        assert_eq!(
+            CopyFormat::from_query("COPY users FROM STDIN WITH (DELIMITER '|')"),
+            Some(CopyFormat {
+                delimiter: b'|',
+                csv: false
+            })
+        );
+        assert_eq!(CopyFormat::from_query("COPY users TO STDOUT"), None);
+        assert_eq!(CopyFormat::from_query("SELECT 1"), None);
+    }
+
+    #[test]
+    fn test_copy_format_from_load_data() {
+        assert_eq!(
+            CopyFormat::from_load_data("LOAD DATA LOCAL INFILE 'users.tsv' INTO TABLE users"),
+            Some(CopyFormat::default())
+        );
+        assert_eq!(
+            CopyFormat::from_load_data(
+                "load data local infile '/tmp/users.csv' into table users \
+                 fields terminated by ',' optionally enclosed by '\"' lines terminated by '\\n'"
+            ),
+            Some(CopyFormat {
+                delimiter: b',',
+                csv: true
+            })
+        );
+        assert_eq!(
+            CopyFormat::from_load_data(
+                "LOAD DATA LOCAL INFILE 'u.txt' INTO TABLE users COLUMNS TERMINATED BY '\\t'"
+            ),
+            Some(CopyFormat::default())
+        );
+        assert_eq!(
+            CopyFormat::from_load_data(
+                "LOAD DATA INFILE '/var/lib/mysql-files/u.txt' INTO TABLE u"
+            ),
+            None
+        );
+        assert_eq!(CopyFormat::from_load_data("SELECT 1"), None);
+    }
+
+    #[test]
+    fn test_rows_split_across_chunks() {
+        let scanner = PiiScanner::new();
+        let mut inspector = CopyInInspector::new(CopyFormat::default());
+
+        let report = inspector.feed(b"1\tplain\n2\tali", &scanner);
+        assert_eq!(report.rows, 1);
+        assert!(report.flagged.is_empty());
+
+        let report = inspector.feed(b"ce@example.com\n3\t\\N\n", &scanner);
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.flagged, vec![(2, PiiType::Email)]);
+
+        let report = inspector.feed(b"4\t123-45-6789", &scanner);
+        assert_eq!(report.rows, 0);
+        let report = inspector.finish(&scanner);
+        assert_eq!(report.rows, 1);
+        assert_eq!(report.flagged, vec![(4, PiiType::Ssn)]);
+    }
+
+    #[test]
+    fn test_csv_quoted_fields() {
+        let scanner = PiiScanner::new();
+        let mut inspector = CopyInInspector::new(CopyFormat {
+            delimiter: b',',
+            csv: true,
+        });
+
+        let report = inspector.feed(
+            b"1,\"hello, world\"\r\n2,\"bob@example.com\"\r\n\\.\n",
+            &scanner,
+        );
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.flagged, vec![(2, PiiType::Email)]);
+    }
+
+    #[test]
+    fn test_split_text_unescapes() {
+        let fields = split_text(b"a\\tb\t\\N\tc", b'\t');
+        assert_eq!(
+            fields,
+            vec![Some(b"a\tb".to_vec()), None, Some(b"c".to_vec())]
+        );
+    }
+}
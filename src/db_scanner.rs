@@ -3,13 +3,22 @@
 //! Provides real database introspection capabilities for PII detection.
 //! Queries `information_schema` for column metadata and samples actual data.
 
+use crate::config::{AppConfig, NamePattern, ScannerConfig};
+use crate::interceptor::pii_type_to_strategy;
+use crate::scan_jobs::ScanProgress;
 use crate::scanner::{PiiScanner, PiiType};
 use crate::state::DbProtocol;
+use crate::tls::MakeRustlsConnect;
+use mysql_async::prelude::Queryable;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use thiserror::Error;
-use tokio_postgres::{Client, NoTls};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
+use tokio_postgres::{Client, NoTls, Socket};
 use tracing::{debug, info, instrument, warn};
+use utoipa::ToSchema;
 
 /// Error types for database scanning operations
 #[derive(Error, Debug)]
@@ -18,48 +27,471 @@ pub enum ScanError {
     ConnectionFailed(String),
     #[error("Query execution failed: {0}")]
     QueryFailed(String),
-    #[error("Unsupported database protocol: {0:?}")]
-    UnsupportedProtocol(DbProtocol),
-    #[allow(dead_code)]
     #[error("Authentication required: please provide database credentials")]
     AuthRequired,
+    #[error("Invalid scanner credentials: {0}")]
+    InvalidCredentials(String),
+    #[error("Invalid scan request: {0}")]
+    InvalidRequest(String),
 }
 
 /// Configuration for database scanning
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Deserialize, ToSchema)]
 pub struct ScanConfig {
-    /// Database username
-    pub username: String,
-    /// Database password
-    pub password: String,
+    /// Database username; omit to use the configured scanner credentials
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Database password, never logged or audited
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Name of the `scanner.credentials` entry to connect with, instead of
+    /// a username and password
+    #[serde(default)]
+    pub credentials_ref: Option<String>,
     /// Database name to scan
     pub database: String,
     /// Maximum number of rows to sample per table (default: 100)
     #[serde(default = "default_sample_size")]
     pub sample_size: usize,
-    /// Schema to scan (default: "public" for Postgres)
-    #[serde(default = "default_schema")]
-    pub schema: String,
-    /// Tables to exclude from scanning
+    /// How Postgres tables are sampled (default: system)
+    #[serde(default)]
+    pub sample_method: SampleMethod,
+    /// Maximum number of values sampled per table, across its scannable
+    /// columns; wide tables get fewer rows (default: 10000)
+    #[serde(default = "default_max_sample_cells")]
+    pub max_sample_cells: usize,
+    /// Schema to scan (default: "public" for Postgres, the database for MySQL)
     #[serde(default)]
-    pub exclude_tables: Vec<String>,
+    pub schema: Option<String>,
+    /// Tables and columns to scan
+    #[serde(flatten)]
+    pub filters: ScanFilters,
     /// Minimum confidence threshold (0.0 - 1.0)
     #[serde(default = "default_confidence_threshold")]
     pub confidence_threshold: f64,
+    /// TLS for this scan, overriding the upstream TLS settings (Postgres only)
+    #[serde(default)]
+    pub sslmode: Option<ScanSslMode>,
+    /// CA certificate (PEM) to verify the server against with `verify-full`.
+    /// Setting it without `sslmode` implies `verify-full`.
+    #[serde(default)]
+    pub ca_cert: Option<String>,
 }
 
-fn default_sample_size() -> usize {
-    100
+/// Which tables and columns a scan looks at, applied before sampling. Names
+/// may be globs such as `audit_*` or `re:` regexes. Column patterns match the
+/// column name or `table.column`, so `*.description` skips every
+/// `description` column.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct ScanFilters {
+    /// Only scan these tables (default: all)
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub include_tables: Vec<NamePattern>,
+    /// Tables to exclude from scanning
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub exclude_tables: Vec<NamePattern>,
+    /// Only scan these columns (default: all)
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub include_columns: Vec<NamePattern>,
+    /// Columns to exclude from scanning
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub exclude_columns: Vec<NamePattern>,
 }
 
-fn default_schema() -> String {
-    "public".to_string()
+impl ScanFilters {
+    pub fn includes_table(&self, table: &str) -> bool {
+        (self.include_tables.is_empty() || self.include_tables.iter().any(|p| p.matches(table)))
+            && !self.exclude_tables.iter().any(|p| p.matches(table))
+    }
+
+    pub fn includes_column(&self, table: &str, column: &str) -> bool {
+        let qualified = format!("{}.{}", table, column);
+        let matches = |p: &NamePattern| p.matches(column) || p.matches(&qualified);
+        (self.include_columns.is_empty() || self.include_columns.iter().any(matches))
+            && !self.exclude_columns.iter().any(matches)
+    }
+
+    /// Reject filters excluding everything they include, which would leave
+    /// nothing to scan
+    pub fn validate(&self) -> Result<(), ScanError> {
+        for (kind, include, exclude) in [
+            ("table", &self.include_tables, &self.exclude_tables),
+            ("column", &self.include_columns, &self.exclude_columns),
+        ] {
+            if !include.is_empty()
+                && include
+                    .iter()
+                    .all(|i| exclude.iter().any(|e| e.matches(i.as_str())))
+            {
+                return Err(ScanError::InvalidRequest(format!(
+                    "every included {} is also excluded",
+                    kind
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How rows are picked from a Postgres table. Tables estimated to hold no
+/// more rows than are sampled are read with `LIMIT` whatever the method, as
+/// are MySQL tables.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleMethod {
+    /// `TABLESAMPLE SYSTEM`: random pages, cheap on large tables
+    #[default]
+    System,
+    /// `TABLESAMPLE BERNOULLI`: random rows, reads the whole table
+    Bernoulli,
+    /// The first rows the table returns, usually the oldest
+    Limit,
+}
+
+/// TLS for a scan connection, as libpq's `sslmode`
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanSslMode {
+    /// Cleartext only
+    Disable,
+    /// TLS without verification when the server offers it, cleartext otherwise
+    Prefer,
+    /// TLS without verifying the server certificate
+    Require,
+    /// TLS, verifying the certificate chain and hostname
+    VerifyFull,
+}
+
+impl std::fmt::Debug for ScanConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScanConfig")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("credentials_ref", &self.credentials_ref)
+            .field("database", &self.database)
+            .field("sample_size", &self.sample_size)
+            .field("sample_method", &self.sample_method)
+            .field("max_sample_cells", &self.max_sample_cells)
+            .field("schema", &self.schema)
+            .field("filters", &self.filters)
+            .field("confidence_threshold", &self.confidence_threshold)
+            .field("sslmode", &self.sslmode)
+            .field("ca_cert", &self.ca_cert)
+            .finish()
+    }
+}
+
+fn default_sample_size() -> usize {
+    100
 }
 
 fn default_confidence_threshold() -> f64 {
     0.5
 }
 
+fn default_max_sample_cells() -> usize {
+    10_000
+}
+
+impl ScanConfig {
+    /// The schema to scan on a database of the given protocol
+    fn schema_for(&self, protocol: DbProtocol) -> String {
+        match (&self.schema, protocol) {
+            (Some(schema), _) => schema.clone(),
+            (None, DbProtocol::Postgres) => "public".to_string(),
+            (None, DbProtocol::MySql) => self.database.clone(),
+        }
+    }
+
+    /// Fill in the username and password from the `scanner.credentials`
+    /// entry named by `credentials_ref`, or `default` when the request has
+    /// no username
+    pub fn resolve_credentials(&mut self, scanner: &ScannerConfig) -> Result<(), ScanError> {
+        let name = match (&self.username, &self.credentials_ref) {
+            (Some(_), Some(_)) => {
+                return Err(ScanError::InvalidCredentials(
+                    "set either username or credentials_ref".to_string(),
+                ));
+            }
+            (Some(_), None) => return Ok(()),
+            (None, Some(name)) => name.as_str(),
+            (None, None) => "default",
+        };
+        let Some(credentials) = scanner.credentials.get(name) else {
+            return Err(match self.credentials_ref {
+                Some(_) => {
+                    ScanError::InvalidCredentials(format!("no scanner credentials '{}'", name))
+                }
+                None => ScanError::AuthRequired,
+            });
+        };
+        let password = credentials
+            .resolve_password()
+            .map_err(|e| ScanError::InvalidCredentials(format!("'{}': {:#}", name, e)))?;
+        self.username = Some(credentials.username.clone());
+        self.password = Some(password);
+        self.credentials_ref = None;
+        Ok(())
+    }
+
+    /// The username and password to connect with
+    fn login(&self) -> Result<(&str, &str), ScanError> {
+        let username = self.username.as_deref().ok_or(ScanError::AuthRequired)?;
+        Ok((username, self.password.as_deref().unwrap_or_default()))
+    }
+
+    /// Rows to sample from a table with `columns` scannable columns
+    fn sample_limit(&self, columns: usize) -> usize {
+        self.sample_size
+            .min(self.max_sample_cells / columns.max(1))
+            .max(1)
+    }
+
+    /// The TLS connector and SSL mode to scan Postgres with, or `None` for
+    /// cleartext. Without `sslmode` or `ca_cert` the upstream TLS settings
+    /// apply, and when those are off TLS is still tried first, as libpq's
+    /// default `sslmode=prefer` does.
+    pub fn tls_connector(
+        &self,
+        config: &AppConfig,
+    ) -> Result<Option<(MakeRustlsConnect, SslMode)>, ScanError> {
+        let tls_error = |e: anyhow::Error| ScanError::ConnectionFailed(format!("{:#}", e));
+        let mode = match (self.sslmode, &self.ca_cert) {
+            (None, None) if config.upstream_tls_enabled() => {
+                return crate::tls::postgres_connector(config)
+                    .map(Some)
+                    .map_err(tls_error);
+            }
+            (None, None) => ScanSslMode::Prefer,
+            (None, Some(_)) => ScanSslMode::VerifyFull,
+            (Some(mode), _) => mode,
+        };
+        let ssl_mode = match mode {
+            ScanSslMode::Disable => return Ok(None),
+            ScanSslMode::Prefer => SslMode::Prefer,
+            ScanSslMode::Require | ScanSslMode::VerifyFull => SslMode::Require,
+        };
+        let connector = crate::tls::scan_connector(
+            mode == ScanSslMode::VerifyFull,
+            self.ca_cert.as_deref(),
+            config.upstream_tls_server_name.clone(),
+        )
+        .map_err(tls_error)?;
+        Ok(Some((connector, ssl_mode)))
+    }
+}
+
+/// Describe a failed Postgres connection. `tokio-postgres` leaves the cause
+/// out of its own message, and a rejected certificate needs a pointer to the
+/// settings that fix it.
+fn connection_error(e: tokio_postgres::Error) -> ScanError {
+    let mut message = format!("{:#}", anyhow::Error::new(e));
+    if message.contains("invalid peer certificate") {
+        message.push_str(
+            " (check `ca_cert` or `upstream_tls_ca_path` and `upstream_tls_server_name`, \
+             or use `sslmode: require` to skip verification)",
+        );
+    }
+    ScanError::ConnectionFailed(message)
+}
+
+/// Open a Postgres connection and drive it on a background task
+async fn spawn_connection<T>(pg_config: tokio_postgres::Config, tls: T) -> Result<Client, ScanError>
+where
+    T: MakeTlsConnect<Socket>,
+    T::Stream: Send + 'static,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let (client, connection) = pg_config.connect(tls).await.map_err(|e| {
+        let error = connection_error(e);
+        warn!("PostgreSQL connection failed: {}", error);
+        error
+    })?;
+
+    // Spawn connection handler
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!("PostgreSQL connection error: {}", e);
+        }
+    });
+
+    Ok(client)
+}
+
+/// An open connection to the database being scanned
+enum ScanConnection {
+    Postgres(Client),
+    MySql(mysql_async::Conn),
+}
+
+impl ScanConnection {
+    /// Close the connection, telling the server where the protocol allows
+    async fn close(self) {
+        if let ScanConnection::MySql(conn) = self
+            && let Err(e) = conn.disconnect().await
+        {
+            debug!("MySQL disconnect failed: {}", e);
+        }
+    }
+}
+
+/// Text of a MySQL value, as it would be sent with the text protocol
+fn mysql_value_string(value: &mysql_async::Value) -> Option<String> {
+    use mysql_async::Value;
+    match value {
+        Value::NULL => None,
+        Value::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        Value::Int(v) => Some(v.to_string()),
+        Value::UInt(v) => Some(v.to_string()),
+        Value::Float(v) => Some(v.to_string()),
+        Value::Double(v) => Some(v.to_string()),
+        // Dates and times, quoted as SQL literals
+        other => Some(other.as_sql(true).trim_matches('\'').to_string()),
+    }
+}
+
+/// Text of a Postgres value, for the types `tokio_postgres` can decode.
+/// Other types are cast to text by the sample query.
+fn pg_value_string(row: &tokio_postgres::Row, idx: usize) -> Option<String> {
+    use std::net::IpAddr;
+    use tokio_postgres::types::{FromSql, Type};
+
+    fn get<'a, T: FromSql<'a> + ToString>(
+        row: &'a tokio_postgres::Row,
+        idx: usize,
+    ) -> Option<String> {
+        row.try_get::<_, Option<T>>(idx)
+            .ok()
+            .flatten()
+            .map(|v| v.to_string())
+    }
+    match *row.columns()[idx].type_() {
+        Type::INT2 => get::<i16>(row, idx),
+        Type::INT4 => get::<i32>(row, idx),
+        Type::INT8 => get::<i64>(row, idx),
+        Type::FLOAT4 => get::<f32>(row, idx),
+        Type::FLOAT8 => get::<f64>(row, idx),
+        Type::BOOL => get::<bool>(row, idx),
+        Type::UUID => get::<uuid::Uuid>(row, idx),
+        Type::DATE => get::<chrono::NaiveDate>(row, idx),
+        Type::TIMESTAMP => get::<chrono::NaiveDateTime>(row, idx),
+        Type::TIMESTAMPTZ => get::<chrono::DateTime<chrono::Utc>>(row, idx),
+        // Host addresses without the `/32` that a text cast would add
+        Type::INET => get::<IpAddr>(row, idx),
+        // varchar, text, char, name and whatever was cast to text
+        _ => get::<String>(row, idx),
+    }
+}
+
+/// Whether a Postgres column is sampled as text: JSON, `numeric`, `cidr`,
+/// and `USER-DEFINED` types such as enums and `citext`, which
+/// `tokio_postgres` has no conversion for
+fn is_pg_text_cast(data_type: &str) -> bool {
+    is_json_type(data_type)
+        || ["numeric", "cidr", "USER-DEFINED"]
+            .iter()
+            .any(|t| data_type.eq_ignore_ascii_case(t))
+}
+
+/// Quote a Postgres identifier
+fn pg_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Whether values of a column are JSON documents, scanned path by path
+fn is_json_type(data_type: &str) -> bool {
+    data_type.eq_ignore_ascii_case("json") || data_type.eq_ignore_ascii_case("jsonb")
+}
+
+/// Query sampling up to `limit` rows of `columns` from a Postgres table
+/// estimated to hold `row_estimate` rows. `TABLESAMPLE` asks for twice the
+/// rows needed, so that an unlucky draw still fills the sample. JSON, enum
+/// and `numeric` columns are fetched as text.
+fn postgres_sample_query(
+    schema: &str,
+    table: &str,
+    columns: &[&ColumnInfo],
+    limit: usize,
+    method: SampleMethod,
+    row_estimate: i64,
+) -> String {
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|col| {
+            let name = pg_ident(&col.column_name);
+            if is_pg_text_cast(&col.data_type) {
+                format!("{}::text AS {}", name, name)
+            } else {
+                name
+            }
+        })
+        .collect();
+    let percent = (limit * 2) as f64 * 100.0 / row_estimate.max(1) as f64;
+    let sample = match method {
+        _ if percent >= 100.0 => String::new(),
+        SampleMethod::System => format!(" TABLESAMPLE SYSTEM ({})", percent),
+        SampleMethod::Bernoulli => format!(" TABLESAMPLE BERNOULLI ({})", percent),
+        SampleMethod::Limit => String::new(),
+    };
+    format!(
+        "SELECT {} FROM {}.{}{} LIMIT {}",
+        columns.join(", "),
+        pg_ident(schema),
+        pg_ident(table),
+        sample,
+        limit
+    )
+}
+
+/// Collect the string values of a JSON document with their paths, as
+/// `mask_json_recursively` visits them. Array elements share the path `[*]`.
+fn json_string_leaves(
+    val: &serde_json::Value,
+    path: &str,
+    leaves: &mut Vec<(String, Vec<String>)>,
+) {
+    match val {
+        serde_json::Value::String(s) => match leaves.iter_mut().find(|(p, _)| p == path) {
+            Some((_, values)) => values.push(s.clone()),
+            None => leaves.push((path.to_string(), vec![s.clone()])),
+        },
+        serde_json::Value::Array(arr) => {
+            let path = format!("{}[*]", path);
+            for v in arr {
+                json_string_leaves(v, &path, leaves);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, v) in map {
+                json_string_leaves(v, &json_path_key(path, key), leaves);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Extend a JSON path by an object key, quoting keys `JsonPath` can't take
+/// after a dot
+fn json_path_key(path: &str, key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}.{}", path, key)
+    } else if key.contains('\'') {
+        format!("{}[\"{}\"]", path, key)
+    } else {
+        format!("{}['{}']", path, key)
+    }
+}
+
+/// Quote a MySQL identifier
+fn mysql_ident(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
 /// Represents column metadata from information_schema
 #[derive(Debug, Clone, Serialize)]
 pub struct ColumnInfo {
@@ -68,10 +500,12 @@ pub struct ColumnInfo {
     pub data_type: String,
     pub is_nullable: bool,
     pub character_maximum_length: Option<i32>,
+    /// `COMMENT ON COLUMN` text, or the MySQL column comment
+    pub comment: Option<String>,
 }
 
 /// Represents a PII finding in the database
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PiiFinding {
     pub table: String,
     pub column: String,
@@ -81,10 +515,16 @@ pub struct PiiFinding {
     pub row_count: usize,
     pub match_count: usize,
     pub data_type: String,
+    /// Path of the values inside a JSON column, such as `$.contact.email`;
+    /// array elements are `[*]`
+    pub json_path: Option<String>,
+    /// Masking strategy to use in a rule for this column, with `json_paths`
+    /// set to `json_path` for JSON findings
+    pub suggested_strategy: String,
 }
 
 /// Represents the complete scan result
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanResult {
     pub status: String,
     pub tables_scanned: usize,
@@ -93,6 +533,8 @@ pub struct ScanResult {
     pub schema: String,
     pub database: String,
     pub scan_duration_ms: u64,
+    /// The tables and columns the scan was limited to
+    pub filters: ScanFilters,
 }
 
 /// Represents schema information
@@ -116,7 +558,13 @@ pub struct DbScanner {
     host: String,
     port: u16,
     protocol: DbProtocol,
-    pii_scanner: PiiScanner,
+    pii_scanner: Arc<PiiScanner>,
+    /// TLS connector and mode for Postgres; `None` connects in cleartext
+    tls: Option<(MakeRustlsConnect, SslMode)>,
+    /// Where to report the tables scanned so far
+    progress: Option<Arc<ScanProgress>>,
+    /// Words in a column comment that mark the column as PII
+    comment_keywords: Vec<String>,
 }
 
 impl DbScanner {
@@ -126,88 +574,126 @@ impl DbScanner {
             host,
             port,
             protocol,
-            pii_scanner: PiiScanner::new(),
+            pii_scanner: Arc::new(PiiScanner::new()),
+            tls: None,
+            progress: None,
+            comment_keywords: crate::config::default_comment_keywords(),
         }
     }
 
+    /// Scan values with the given scanner, e.g. the one built from the `scanner` config
+    pub fn with_pii_scanner(mut self, pii_scanner: Arc<PiiScanner>) -> Self {
+        self.pii_scanner = pii_scanner;
+        self
+    }
+
+    /// Treat columns whose comment has one of these words as PII
+    pub fn with_comment_keywords(mut self, keywords: Vec<String>) -> Self {
+        self.comment_keywords = keywords;
+        self
+    }
+
+    /// Report progress table by table
+    pub fn with_progress(mut self, progress: Arc<ScanProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Connect to Postgres over TLS with the given connector and SSL mode
+    pub fn with_tls(mut self, connector: MakeRustlsConnect, ssl_mode: SslMode) -> Self {
+        self.tls = Some((connector, ssl_mode));
+        self
+    }
+
     /// Scan the database for PII
     #[instrument(skip(self, config), fields(host = %self.host, port = %self.port, db = %config.database))]
     pub async fn scan(&self, config: &ScanConfig) -> Result<ScanResult, ScanError> {
         let start = std::time::Instant::now();
-
-        match self.protocol {
-            DbProtocol::Postgres => self.scan_postgres(config, start).await,
-            DbProtocol::MySql => {
-                // MySQL support coming in future
-                Err(ScanError::UnsupportedProtocol(DbProtocol::MySql))
-            }
-        }
+        let mut conn = self.connect(config).await?;
+        let result = self.scan_tables(&mut conn, config, start).await;
+        conn.close().await;
+        result
     }
 
     /// Get schema information from the database
     #[instrument(skip(self, config), fields(host = %self.host, port = %self.port, db = %config.database))]
     pub async fn get_schema(&self, config: &ScanConfig) -> Result<SchemaInfo, ScanError> {
-        match self.protocol {
-            DbProtocol::Postgres => self.get_postgres_schema(config).await,
-            DbProtocol::MySql => Err(ScanError::UnsupportedProtocol(DbProtocol::MySql)),
-        }
+        let mut conn = self.connect(config).await?;
+        let result = self.read_schema(&mut conn, config).await;
+        conn.close().await;
+        result
     }
 
-    /// Scan PostgreSQL database for PII
-    async fn scan_postgres(
+    /// Scan the tables of the configured schema for PII
+    async fn scan_tables(
         &self,
+        conn: &mut ScanConnection,
         config: &ScanConfig,
         start: std::time::Instant,
     ) -> Result<ScanResult, ScanError> {
-        let client = self.connect_postgres(config).await?;
+        let schema = config.schema_for(self.protocol);
 
         // Get all columns from information_schema
-        let columns = self.get_postgres_columns(&client, &config.schema).await?;
-        info!(
-            "Found {} columns in schema '{}'",
-            columns.len(),
-            config.schema
-        );
+        let columns = self.get_columns(conn, &schema).await?;
+        info!("Found {} columns in schema '{}'", columns.len(), schema);
 
-        // Group columns by table
+        // Group the columns passing the filters by table, leaving out
+        // tables without any
+        let filters = &config.filters;
         let mut tables: HashMap<String, Vec<ColumnInfo>> = HashMap::new();
-        for col in &columns {
-            tables
-                .entry(col.table_name.clone())
-                .or_default()
-                .push(col.clone());
+        for col in columns {
+            if filters.includes_table(&col.table_name)
+                && filters.includes_column(&col.table_name, &col.column_name)
+            {
+                tables.entry(col.table_name.clone()).or_default().push(col);
+            }
         }
 
-        // Filter out excluded tables
-        let tables: HashMap<String, Vec<ColumnInfo>> = tables
-            .into_iter()
-            .filter(|(name, _)| !config.exclude_tables.contains(name))
-            .collect();
-
-        info!(
-            "Scanning {} tables (excluding {:?})",
-            tables.len(),
-            config.exclude_tables
-        );
+        info!("Scanning {} tables (filters: {:?})", tables.len(), filters);
 
         let mut findings = Vec::new();
         let mut columns_scanned = 0;
+        if let Some(progress) = &self.progress {
+            progress.set_tables_total(tables.len());
+        }
 
         for (table_name, table_columns) in &tables {
-            // Sample data from this table
-            let sample_data = self
-                .sample_postgres_table(&client, &config.schema, table_name, config.sample_size)
-                .await?;
+            let table_findings = findings.len();
+            // Sample the scannable columns of this table, leaving blobs behind
+            let scannable: Vec<&ColumnInfo> = table_columns
+                .iter()
+                .filter(|col| self.is_scannable_type(&col.data_type))
+                .collect();
+            let sample_data = if scannable.is_empty() {
+                Vec::new()
+            } else {
+                self.sample_table(conn, &schema, table_name, &scannable, config)
+                    .await?
+            };
 
             for col in table_columns {
                 columns_scanned += 1;
 
+                let documented = col
+                    .comment
+                    .as_deref()
+                    .is_some_and(|c| comment_marks_pii(c, &self.comment_keywords));
+
                 // Skip non-string columns (unlikely to contain PII patterns)
                 if !self.is_scannable_type(&col.data_type) {
                     debug!(
                         "Skipping column {}.{} (type: {})",
                         table_name, col.column_name, col.data_type
                     );
+                    if documented && DOCUMENTED_CONFIDENCE >= config.confidence_threshold {
+                        findings.push(self.documented_finding(table_name, col, 0));
+                    }
+                    continue;
+                }
+
+                // JSON documents are scanned path by path
+                if is_json_type(&col.data_type) {
+                    findings.extend(self.scan_json_column(&sample_data, table_name, col, config));
                     continue;
                 }
 
@@ -225,42 +711,59 @@ impl DbScanner {
                     0.0
                 };
 
-                // Combine column name heuristics with data scanning
-                let (final_type, final_confidence) = if let Some(name_type) = name_pii_type {
-                    // Boost confidence if column name suggests PII
-                    if let Some(data_type) = detected_type {
-                        if name_type == data_type {
-                            // Both agree - high confidence
-                            (Some(data_type), (confidence + 0.3).min(1.0))
-                        } else {
-                            // Conflict - trust data over name but lower confidence
-                            (Some(data_type), confidence * 0.8)
-                        }
-                    } else if confidence < config.confidence_threshold {
-                        // Name suggests PII but no data matches - medium confidence
-                        (Some(name_type), 0.6)
-                    } else {
-                        (detected_type, confidence)
-                    }
-                } else {
-                    (detected_type, confidence)
-                };
+                let (final_type, final_confidence) = column_confidence(
+                    detected_type,
+                    confidence,
+                    name_pii_type,
+                    documented,
+                    config.confidence_threshold,
+                );
+                let name_only_confidence = with_comment_boost(0.6, documented);
 
                 if let Some(pii_type) = final_type
                     && final_confidence >= config.confidence_threshold
                 {
+                    let suggested_strategy = self
+                        .pii_scanner
+                        .custom_strategy(&pii_type)
+                        .unwrap_or_else(|| pii_type_to_strategy(pii_type.clone()));
                     findings.push(PiiFinding {
                         table: table_name.clone(),
                         column: col.column_name.clone(),
-                        pii_type: format!("{:?}", pii_type),
+                        pii_type: pii_type.to_string(),
                         confidence: (final_confidence * 100.0).round() / 100.0,
                         sample: sample_value.map(|s| self.mask_sample(&s)),
                         row_count,
                         match_count,
                         data_type: col.data_type.clone(),
+                        json_path: None,
+                        suggested_strategy: suggested_strategy.to_string(),
+                    });
+                } else if let Some(strategy) = self.check_column_name_strategy(&col.column_name)
+                    && name_only_confidence >= config.confidence_threshold
+                {
+                    // Names and addresses have no pattern to match values
+                    // against, so only the column name can flag them
+                    findings.push(PiiFinding {
+                        table: table_name.clone(),
+                        column: col.column_name.clone(),
+                        pii_type: name_strategy_pii_type(strategy).to_string(),
+                        confidence: name_only_confidence,
+                        sample: None,
+                        row_count,
+                        match_count: 0,
+                        data_type: col.data_type.clone(),
+                        json_path: None,
+                        suggested_strategy: strategy.to_string(),
                     });
+                } else if documented && DOCUMENTED_CONFIDENCE >= config.confidence_threshold {
+                    findings.push(self.documented_finding(table_name, col, row_count));
                 }
             }
+
+            if let Some(progress) = &self.progress {
+                progress.table_done(&findings[table_findings..]);
+            }
         }
 
         let duration = start.elapsed();
@@ -270,38 +773,50 @@ impl DbScanner {
             tables_scanned: tables.len(),
             columns_scanned,
             findings,
-            schema: config.schema.clone(),
+            schema,
             database: config.database.clone(),
             scan_duration_ms: duration.as_millis() as u64,
+            filters: config.filters.clone(),
         })
     }
 
+    /// Connect to the database to scan
+    async fn connect(&self, config: &ScanConfig) -> Result<ScanConnection, ScanError> {
+        match self.protocol {
+            DbProtocol::Postgres => Ok(ScanConnection::Postgres(
+                self.connect_postgres(config).await?,
+            )),
+            DbProtocol::MySql => Ok(ScanConnection::MySql(self.connect_mysql(config).await?)),
+        }
+    }
+
     /// Connect to PostgreSQL database
     async fn connect_postgres(&self, config: &ScanConfig) -> Result<Client, ScanError> {
-        let conn_str = format!(
-            "host={} port={} user={} password={} dbname={} sslmode=prefer connect_timeout=10",
-            self.host, self.port, config.username, config.password, config.database
-        );
+        let (username, password) = config.login()?;
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&self.host)
+            .port(self.port)
+            .user(username)
+            .password(password)
+            .dbname(&config.database)
+            .connect_timeout(std::time::Duration::from_secs(10));
 
         debug!(
             "Connecting to PostgreSQL: host={}, port={}, db={}",
             self.host, self.port, config.database
         );
 
-        let (client, connection) =
-            tokio_postgres::connect(&conn_str, NoTls)
-                .await
-                .map_err(|e| {
-                    warn!("PostgreSQL connection failed: {}", e);
-                    ScanError::ConnectionFailed(format!("{}", e))
-                })?;
-
-        // Spawn connection handler
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                warn!("PostgreSQL connection error: {}", e);
+        let client = match &self.tls {
+            Some((connector, ssl_mode)) => {
+                pg_config.ssl_mode(*ssl_mode);
+                spawn_connection(pg_config, connector.clone()).await?
             }
-        });
+            None => {
+                pg_config.ssl_mode(SslMode::Disable);
+                spawn_connection(pg_config, NoTls).await?
+            }
+        };
 
         info!(
             "Connected to PostgreSQL at {}:{}/{}",
@@ -310,6 +825,59 @@ impl DbScanner {
         Ok(client)
     }
 
+    /// Connect to MySQL database
+    async fn connect_mysql(&self, config: &ScanConfig) -> Result<mysql_async::Conn, ScanError> {
+        debug!(
+            "Connecting to MySQL: host={}, port={}, db={}",
+            self.host, self.port, config.database
+        );
+        if matches!(
+            config.sslmode,
+            Some(ScanSslMode::Require | ScanSslMode::VerifyFull)
+        ) || config.ca_cert.is_some()
+        {
+            return Err(ScanError::ConnectionFailed(
+                "TLS is not supported for MySQL scans".to_string(),
+            ));
+        }
+        let (username, password) = config.login()?;
+
+        let opts = mysql_async::OptsBuilder::default()
+            .ip_or_hostname(self.host.clone())
+            .tcp_port(self.port)
+            .user(Some(username))
+            .pass(Some(password))
+            .db_name(Some(config.database.clone()));
+        let conn = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            mysql_async::Conn::new(opts),
+        )
+        .await
+        .map_err(|_| ScanError::ConnectionFailed("connection timed out".to_string()))?
+        .map_err(|e| {
+            warn!("MySQL connection failed: {}", e);
+            ScanError::ConnectionFailed(format!("{}", e))
+        })?;
+
+        info!(
+            "Connected to MySQL at {}:{}/{}",
+            self.host, self.port, config.database
+        );
+        Ok(conn)
+    }
+
+    /// Get column information from information_schema
+    async fn get_columns(
+        &self,
+        conn: &mut ScanConnection,
+        schema: &str,
+    ) -> Result<Vec<ColumnInfo>, ScanError> {
+        match conn {
+            ScanConnection::Postgres(client) => self.get_postgres_columns(client, schema).await,
+            ScanConnection::MySql(conn) => self.get_mysql_columns(conn, schema).await,
+        }
+    }
+
     /// Get column information from PostgreSQL information_schema
     async fn get_postgres_columns(
         &self,
@@ -318,16 +886,24 @@ impl DbScanner {
     ) -> Result<Vec<ColumnInfo>, ScanError> {
         let query = r#"
             SELECT 
-                table_name,
-                column_name,
-                data_type,
-                is_nullable,
-                character_maximum_length
-            FROM information_schema.columns
-            WHERE table_schema = $1
-            AND table_name NOT LIKE 'pg_%'
-            AND table_name NOT LIKE 'sql_%'
-            ORDER BY table_name, ordinal_position
+                c.table_name,
+                c.column_name,
+                c.data_type,
+                c.is_nullable,
+                c.character_maximum_length,
+                d.description AS comment
+            FROM information_schema.columns c
+            LEFT JOIN pg_catalog.pg_namespace n ON n.nspname = c.table_schema
+            LEFT JOIN pg_catalog.pg_class t
+                ON t.relnamespace = n.oid AND t.relname = c.table_name
+            LEFT JOIN pg_catalog.pg_description d
+                ON d.classoid = 'pg_catalog.pg_class'::regclass
+                AND d.objoid = t.oid
+                AND d.objsubid = c.ordinal_position
+            WHERE c.table_schema = $1
+            AND c.table_name NOT LIKE 'pg_%'
+            AND c.table_name NOT LIKE 'sql_%'
+            ORDER BY c.table_name, c.ordinal_position
         "#;
 
         let rows = client
@@ -343,16 +919,72 @@ impl DbScanner {
                 data_type: row.get("data_type"),
                 is_nullable: row.get::<_, String>("is_nullable") == "YES",
                 character_maximum_length: row.get("character_maximum_length"),
+                comment: row.get("comment"),
             })
             .collect();
 
         Ok(columns)
     }
 
-    /// Get PostgreSQL schema information
-    async fn get_postgres_schema(&self, config: &ScanConfig) -> Result<SchemaInfo, ScanError> {
-        let client = self.connect_postgres(config).await?;
-        let columns = self.get_postgres_columns(&client, &config.schema).await?;
+    /// Get column information from MySQL information_schema
+    async fn get_mysql_columns(
+        &self,
+        conn: &mut mysql_async::Conn,
+        schema: &str,
+    ) -> Result<Vec<ColumnInfo>, ScanError> {
+        let query = r#"
+            SELECT
+                TABLE_NAME AS table_name,
+                COLUMN_NAME AS column_name,
+                DATA_TYPE AS data_type,
+                IS_NULLABLE AS is_nullable,
+                CHARACTER_MAXIMUM_LENGTH AS character_maximum_length,
+                COLUMN_COMMENT AS comment
+            FROM information_schema.columns
+            WHERE TABLE_SCHEMA = ?
+            ORDER BY TABLE_NAME, ORDINAL_POSITION
+        "#;
+
+        let rows: Vec<mysql_async::Row> = conn
+            .exec(query, (schema,))
+            .await
+            .map_err(|e| ScanError::QueryFailed(e.to_string()))?;
+
+        let columns = rows
+            .iter()
+            .map(|row| {
+                let text = |name: &str| -> String {
+                    row.get::<Option<String>, _>(name)
+                        .flatten()
+                        .unwrap_or_default()
+                };
+                ColumnInfo {
+                    table_name: text("table_name"),
+                    column_name: text("column_name"),
+                    data_type: text("data_type"),
+                    is_nullable: text("is_nullable") == "YES",
+                    // LONGTEXT and friends are longer than Postgres reports
+                    character_maximum_length: row
+                        .get::<Option<u64>, _>("character_maximum_length")
+                        .flatten()
+                        .and_then(|len| i32::try_from(len).ok()),
+                    // Empty when the column has none
+                    comment: Some(text("comment")).filter(|c| !c.is_empty()),
+                }
+            })
+            .collect();
+
+        Ok(columns)
+    }
+
+    /// Get schema information
+    async fn read_schema(
+        &self,
+        conn: &mut ScanConnection,
+        config: &ScanConfig,
+    ) -> Result<SchemaInfo, ScanError> {
+        let schema = config.schema_for(self.protocol);
+        let columns = self.get_columns(conn, &schema).await?;
 
         // Group by table
         let mut table_map: HashMap<String, Vec<ColumnInfo>> = HashMap::new();
@@ -366,12 +998,12 @@ impl DbScanner {
         // Get row counts for each table
         let mut tables = Vec::new();
         for (table_name, cols) in table_map {
-            if config.exclude_tables.contains(&table_name) {
+            if !config.filters.includes_table(&table_name) {
                 continue;
             }
 
             let row_count = self
-                .get_table_row_count(&client, &config.schema, &table_name)
+                .get_table_row_count(conn, &schema, &table_name)
                 .await
                 .ok();
 
@@ -387,13 +1019,28 @@ impl DbScanner {
 
         Ok(SchemaInfo {
             database: config.database.clone(),
-            schema: config.schema.clone(),
+            schema,
             tables,
         })
     }
 
-    /// Get row count for a table
+    /// Get the approximate row count of a table
     async fn get_table_row_count(
+        &self,
+        conn: &mut ScanConnection,
+        schema: &str,
+        table: &str,
+    ) -> Result<i64, ScanError> {
+        match conn {
+            ScanConnection::Postgres(client) => {
+                self.get_postgres_row_count(client, schema, table).await
+            }
+            ScanConnection::MySql(conn) => self.get_mysql_row_count(conn, schema, table).await,
+        }
+    }
+
+    /// Get row count for a PostgreSQL table
+    async fn get_postgres_row_count(
         &self,
         client: &Client,
         schema: &str,
@@ -418,18 +1065,73 @@ impl DbScanner {
         }
     }
 
-    /// Sample data from a PostgreSQL table
+    /// Get row count for a MySQL table
+    async fn get_mysql_row_count(
+        &self,
+        conn: &mut mysql_async::Conn,
+        schema: &str,
+        table: &str,
+    ) -> Result<i64, ScanError> {
+        // TABLE_ROWS is InnoDB's estimate, which is much cheaper than COUNT(*)
+        let query = r#"
+            SELECT TABLE_ROWS AS count
+            FROM information_schema.tables
+            WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ?
+        "#;
+
+        let count: Option<Option<u64>> = conn
+            .exec_first(query, (schema, table))
+            .await
+            .map_err(|e| ScanError::QueryFailed(e.to_string()))?;
+
+        Ok(count.flatten().map_or(0, |count| count as i64))
+    }
+
+    /// Sample rows of the given columns of a table, as text
+    async fn sample_table(
+        &self,
+        conn: &mut ScanConnection,
+        schema: &str,
+        table: &str,
+        columns: &[&ColumnInfo],
+        config: &ScanConfig,
+    ) -> Result<Vec<HashMap<String, Option<String>>>, ScanError> {
+        let limit = config.sample_limit(columns.len());
+        match conn {
+            ScanConnection::Postgres(client) => {
+                let row_estimate = match config.sample_method {
+                    SampleMethod::Limit => 0,
+                    _ => self.get_postgres_row_count(client, schema, table).await?,
+                };
+                let query = postgres_sample_query(
+                    schema,
+                    table,
+                    columns,
+                    limit,
+                    config.sample_method,
+                    row_estimate,
+                );
+                self.sample_postgres_table(client, schema, table, &query)
+                    .await
+            }
+            ScanConnection::MySql(conn) => {
+                self.sample_mysql_table(conn, schema, table, columns, limit)
+                    .await
+            }
+        }
+    }
+
+    /// Sample data from a PostgreSQL table with the given query
     async fn sample_postgres_table(
         &self,
         client: &Client,
         schema: &str,
         table: &str,
-        limit: usize,
+        query: &str,
     ) -> Result<Vec<HashMap<String, Option<String>>>, ScanError> {
-        // Use TABLESAMPLE for large tables, or LIMIT for smaller ones
-        let query = format!(r#"SELECT * FROM "{}"."{}" LIMIT {}"#, schema, table, limit);
+        debug!("Sampling {}.{}: {}", schema, table, query);
 
-        let rows = client.query(&query, &[]).await.map_err(|e| {
+        let rows = client.query(query, &[]).await.map_err(|e| {
             ScanError::QueryFailed(format!("Failed to sample {}.{}: {}", schema, table, e))
         })?;
 
@@ -438,31 +1140,7 @@ impl DbScanner {
             .map(|row| {
                 let mut map = HashMap::new();
                 for (idx, col) in row.columns().iter().enumerate() {
-                    // Try to get value as string
-                    let value: Option<String> = match col.type_().name() {
-                        "int2" | "int4" | "int8" | "float4" | "float8" | "numeric" => {
-                            // Handle numeric types
-                            row.try_get::<_, Option<i64>>(idx)
-                                .ok()
-                                .flatten()
-                                .map(|v| v.to_string())
-                                .or_else(|| {
-                                    row.try_get::<_, Option<f64>>(idx)
-                                        .ok()
-                                        .flatten()
-                                        .map(|v| v.to_string())
-                                })
-                        }
-                        "bool" => row
-                            .try_get::<_, Option<bool>>(idx)
-                            .ok()
-                            .flatten()
-                            .map(|v| v.to_string()),
-                        _ => {
-                            // Try as string (covers varchar, text, char, etc.)
-                            row.try_get::<_, Option<String>>(idx).ok().flatten()
-                        }
-                    };
+                    let value = pg_value_string(row, idx);
                     map.insert(col.name().to_string(), value);
                 }
                 map
@@ -473,19 +1151,149 @@ impl DbScanner {
         Ok(result)
     }
 
+    /// Sample data from a MySQL table
+    async fn sample_mysql_table(
+        &self,
+        conn: &mut mysql_async::Conn,
+        schema: &str,
+        table: &str,
+        columns: &[&ColumnInfo],
+        limit: usize,
+    ) -> Result<Vec<HashMap<String, Option<String>>>, ScanError> {
+        let columns: Vec<String> = columns
+            .iter()
+            .map(|col| mysql_ident(&col.column_name))
+            .collect();
+        let query = format!(
+            "SELECT {} FROM {}.{} LIMIT {}",
+            columns.join(", "),
+            mysql_ident(schema),
+            mysql_ident(table),
+            limit
+        );
+
+        let rows: Vec<mysql_async::Row> = conn.query(&query).await.map_err(|e| {
+            ScanError::QueryFailed(format!("Failed to sample {}.{}: {}", schema, table, e))
+        })?;
+
+        let result: Vec<HashMap<String, Option<String>>> = rows
+            .iter()
+            .map(|row| {
+                row.columns_ref()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, col)| {
+                        let value = row.as_ref(idx).and_then(mysql_value_string);
+                        (col.name_str().into_owned(), value)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        debug!("Sampled {} rows from {}.{}", result.len(), schema, table);
+        Ok(result)
+    }
+
+    /// Finding for a column that only its comment marks as PII
+    fn documented_finding(
+        &self,
+        table_name: &str,
+        col: &ColumnInfo,
+        row_count: usize,
+    ) -> PiiFinding {
+        PiiFinding {
+            table: table_name.to_string(),
+            column: col.column_name.clone(),
+            pii_type: "Documented".to_string(),
+            confidence: DOCUMENTED_CONFIDENCE,
+            sample: None,
+            row_count,
+            match_count: 0,
+            data_type: col.data_type.clone(),
+            json_path: None,
+            suggested_strategy: "redact".to_string(),
+        }
+    }
+
+    /// Findings inside a JSON column, one per path whose string values look
+    /// like PII. Confidence is the share of the values at the path that match.
+    fn scan_json_column(
+        &self,
+        sample_data: &[HashMap<String, Option<String>>],
+        table_name: &str,
+        col: &ColumnInfo,
+        config: &ScanConfig,
+    ) -> Vec<PiiFinding> {
+        let mut leaves = Vec::new();
+        for row in sample_data {
+            if let Some(Some(value)) = row.get(&col.column_name)
+                && let Ok(doc) = serde_json::from_str::<serde_json::Value>(value)
+            {
+                json_string_leaves(&doc, "$", &mut leaves);
+            }
+        }
+
+        let mut findings = Vec::new();
+        for (path, values) in leaves {
+            let (match_count, detected_type, sample_value) =
+                self.classify_values(values.iter().map(String::as_str));
+            let Some(pii_type) = detected_type else {
+                continue;
+            };
+            let confidence = match_count as f64 / values.len() as f64;
+            if confidence < config.confidence_threshold {
+                continue;
+            }
+            let suggested_strategy = self
+                .pii_scanner
+                .custom_strategy(&pii_type)
+                .unwrap_or_else(|| pii_type_to_strategy(pii_type.clone()));
+            findings.push(PiiFinding {
+                table: table_name.to_string(),
+                column: col.column_name.clone(),
+                pii_type: pii_type.to_string(),
+                confidence: (confidence * 100.0).round() / 100.0,
+                sample: sample_value.map(|s| self.mask_sample(&s)),
+                row_count: sample_data.len(),
+                match_count,
+                data_type: col.data_type.clone(),
+                json_path: Some(path),
+                suggested_strategy: suggested_strategy.to_string(),
+            });
+        }
+        findings
+    }
+
     /// Check if a data type is scannable for PII
     fn is_scannable_type(&self, data_type: &str) -> bool {
-        matches!(
-            data_type.to_lowercase().as_str(),
-            "character varying"
-                | "varchar"
-                | "text"
-                | "character"
-                | "char"
-                | "name"
-                | "citext"
-                | "bpchar"
-        )
+        let data_type = data_type.to_lowercase();
+        match self.protocol {
+            DbProtocol::Postgres => matches!(
+                data_type.as_str(),
+                "character varying"
+                    | "varchar"
+                    | "text"
+                    | "character"
+                    | "char"
+                    | "name"
+                    | "citext"
+                    | "bpchar"
+                    | "json"
+                    | "jsonb"
+                    | "uuid"
+                    | "date"
+                    | "timestamp without time zone"
+                    | "timestamp with time zone"
+                    | "numeric"
+                    | "inet"
+                    | "cidr"
+                    | "user-defined"
+            ),
+            DbProtocol::MySql => matches!(
+                data_type.as_str(),
+                "varchar" | "char" | "text" | "tinytext" | "mediumtext" | "longtext" | "json"
+            ),
+        }
     }
 
     /// Check column name for PII heuristics
@@ -553,6 +1361,73 @@ impl DbScanner {
             return Some(PiiType::Passport);
         }
 
+        // Bank account patterns
+        if name_lower.contains("iban") {
+            return Some(PiiType::Iban);
+        }
+
+        // UK National Insurance patterns
+        if name_lower.contains("nino") || name_lower.contains("national_insurance") {
+            return Some(PiiType::UkNino);
+        }
+
+        // Tax ID patterns
+        if name_lower.contains("tax_id")
+            || name_lower.contains("taxid")
+            || name_lower.contains("steuer_id")
+            || name_lower.contains("steuerid")
+            || name_lower == "tin"
+        {
+            return Some(PiiType::DeTaxId);
+        }
+
+        None
+    }
+
+    /// Fake data strategy for name, address and company columns, which the
+    /// PII scanner can't recognize from their values
+    fn check_column_name_strategy(&self, column_name: &str) -> Option<&'static str> {
+        let name_lower = column_name.to_lowercase();
+
+        if matches!(
+            name_lower.as_str(),
+            "first_name" | "firstname" | "given_name" | "fname"
+        ) {
+            return Some("first_name");
+        }
+        if matches!(
+            name_lower.as_str(),
+            "last_name" | "lastname" | "surname" | "family_name" | "lname"
+        ) {
+            return Some("last_name");
+        }
+        if matches!(
+            name_lower.as_str(),
+            "full_name" | "fullname" | "customer_name" | "contact_name"
+        ) {
+            return Some("full_name");
+        }
+
+        if name_lower.contains("street")
+            || name_lower.starts_with("address_line")
+            || matches!(name_lower.as_str(), "address" | "address1" | "address_1")
+        {
+            return Some("street_address");
+        }
+        if name_lower.contains("zip")
+            || name_lower.contains("postal_code")
+            || name_lower.contains("postcode")
+        {
+            return Some("zip");
+        }
+        if name_lower == "city" {
+            return Some("address");
+        }
+
+        if name_lower.contains("company") || name_lower == "employer" {
+            return Some("company");
+        }
+
         None
     }
 
@@ -562,36 +1437,50 @@ impl DbScanner {
         sample_data: &[HashMap<String, Option<String>>],
         column_name: &str,
     ) -> (usize, Option<PiiType>, Option<String>) {
-        let mut match_count = 0;
-        let mut detected_type: Option<PiiType> = None;
-        let mut sample_value: Option<String> = None;
-        let mut type_counts: HashMap<PiiType, usize> = HashMap::new();
+        self.classify_values(
+            sample_data
+                .iter()
+                .filter_map(|row| row.get(column_name)?.as_deref()),
+        )
+    }
 
-        for row in sample_data {
-            if let Some(Some(value)) = row.get(column_name) {
-                let trimmed = value.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
+    /// Classify a set of values: the number matching the most likely PII
+    /// type, that type, and the first value of it
+    fn classify_values<'a>(
+        &self,
+        values: impl Iterator<Item = &'a str>,
+    ) -> (usize, Option<PiiType>, Option<String>) {
+        // Each value counts towards every type it could be, weighted by the
+        // confidence of the hit
+        let mut scores: Vec<(PiiType, f32, usize, &str)> = Vec::new();
 
-                if let Some(pii_type) = self.pii_scanner.scan(trimmed) {
-                    match_count += 1;
-                    *type_counts.entry(pii_type.clone()).or_insert(0) += 1;
+        for value in values {
+            let trimmed = value.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
 
-                    if sample_value.is_none() {
-                        sample_value = Some(value.clone());
+            for (pii_type, confidence) in self.pii_scanner.scan_ranked(trimmed) {
+                match scores.iter_mut().find(|(t, ..)| *t == pii_type) {
+                    Some((_, score, count, _)) => {
+                        *score += confidence;
+                        *count += 1;
                     }
+                    None => scores.push((pii_type, confidence, 1, value)),
                 }
             }
         }
 
-        // Determine the most common PII type detected
-        if let Some((most_common_type, _)) = type_counts.into_iter().max_by_key(|(_, count)| *count)
-        {
-            detected_type = Some(most_common_type);
+        // The type with the highest total; ties go to the one seen first
+        let best = scores
+            .into_iter()
+            .reduce(|best, next| if next.1 > best.1 { next } else { best });
+        match best {
+            Some((pii_type, _, match_count, sample_value)) => {
+                (match_count, Some(pii_type), Some(sample_value.to_string()))
+            }
+            None => (0, None, None),
         }
-
-        (match_count, detected_type, sample_value)
     }
 
     /// Mask a sample value for display (don't expose full PII)
@@ -607,6 +1496,75 @@ impl DbScanner {
     }
 }
 
+/// Finding type reported for a column flagged by `check_column_name_strategy`
+/// Confidence of a finding for a column that only its comment marks as PII,
+/// as for one that only its name does
+const DOCUMENTED_CONFIDENCE: f64 = 0.6;
+
+/// Confidence added to a finding when the column's comment marks it as PII
+const COMMENT_BOOST: f64 = 0.2;
+
+/// Whether a column comment has one of `keywords` as a whole word, ignoring
+/// case, as in "PII: customer email"
+fn comment_marks_pii(comment: &str, keywords: &[String]) -> bool {
+    let comment = comment.to_lowercase();
+    keywords.iter().any(|keyword| {
+        let keyword = keyword.to_lowercase();
+        !keyword.is_empty()
+            && comment.match_indices(&keyword).any(|(i, _)| {
+                let before = comment[..i].chars().next_back();
+                let after = comment[i + keyword.len()..].chars().next();
+                !before.is_some_and(char::is_alphanumeric)
+                    && !after.is_some_and(char::is_alphanumeric)
+            })
+    })
+}
+
+fn with_comment_boost(confidence: f64, documented: bool) -> f64 {
+    if documented {
+        ((confidence + COMMENT_BOOST).min(1.0) * 100.0).round() / 100.0
+    } else {
+        confidence
+    }
+}
+
+/// PII type and confidence of a column from what its sampled values matched
+/// (the PII type and the share of values), what its name suggests and
+/// whether its comment marks it as PII. Values and name agreeing raise the
+/// confidence, disagreeing lower it, and a name alone gives 0.6 when too few
+/// values match.
+fn column_confidence(
+    detected_type: Option<PiiType>,
+    confidence: f64,
+    name_type: Option<PiiType>,
+    documented: bool,
+    threshold: f64,
+) -> (Option<PiiType>, f64) {
+    let (pii_type, confidence) = match (name_type, detected_type) {
+        // Both agree - high confidence
+        (Some(name_type), Some(data_type)) if name_type == data_type => {
+            (Some(data_type), (confidence + 0.3).min(1.0))
+        }
+        // Conflict - trust data over name but lower confidence
+        (Some(_), Some(data_type)) => (Some(data_type), confidence * 0.8),
+        // Name suggests PII but no data matches - medium confidence
+        (Some(name_type), None) if confidence < threshold => (Some(name_type), 0.6),
+        (_, detected_type) => (detected_type, confidence),
+    };
+    match pii_type {
+        Some(_) => (pii_type, with_comment_boost(confidence, documented)),
+        None => (None, confidence),
+    }
+}
+
+fn name_strategy_pii_type(strategy: &str) -> &'static str {
+    match strategy {
+        "first_name" | "last_name" | "full_name" => "Name",
+        "company" => "Company",
+        _ => "Address",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -655,10 +1613,56 @@ mod tests {
             scanner.check_column_name_heuristics("passport_number"),
             Some(PiiType::Passport)
         );
+        assert_eq!(
+            scanner.check_column_name_heuristics("iban"),
+            Some(PiiType::Iban)
+        );
+        assert_eq!(
+            scanner.check_column_name_heuristics("employee_nino"),
+            Some(PiiType::UkNino)
+        );
+        assert_eq!(
+            scanner.check_column_name_heuristics("tax_id"),
+            Some(PiiType::DeTaxId)
+        );
+        assert_eq!(
+            scanner.check_column_name_heuristics("SteuerID"),
+            Some(PiiType::DeTaxId)
+        );
         assert_eq!(scanner.check_column_name_heuristics("username"), None);
         assert_eq!(scanner.check_column_name_heuristics("created_at"), None);
     }
 
+    #[test]
+    fn test_column_name_strategy() {
+        let scanner = DbScanner::new("localhost".to_string(), 5432, DbProtocol::Postgres);
+
+        let cases = [
+            ("first_name", Some("first_name")),
+            ("Surname", Some("last_name")),
+            ("full_name", Some("full_name")),
+            ("street_address", Some("street_address")),
+            ("address_line2", Some("street_address")),
+            ("zip_code", Some("zip")),
+            ("postal_code", Some("zip")),
+            ("city", Some("address")),
+            ("company_name", Some("company")),
+            ("username", None),
+            ("ip_address", None),
+            ("product_name", None),
+        ];
+        for (column, expected) in cases {
+            assert_eq!(
+                scanner.check_column_name_strategy(column),
+                expected,
+                "{}",
+                column
+            );
+        }
+        assert_eq!(name_strategy_pii_type("full_name"), "Name");
+        assert_eq!(name_strategy_pii_type("zip"), "Address");
+    }
+
     #[test]
     fn test_is_scannable_type() {
         let scanner = DbScanner::new("localhost".to_string(), 5432, DbProtocol::Postgres);
@@ -670,6 +1674,567 @@ mod tests {
         assert!(!scanner.is_scannable_type("integer"));
         assert!(!scanner.is_scannable_type("boolean"));
         assert!(!scanner.is_scannable_type("timestamp"));
+        assert!(scanner.is_scannable_type("jsonb"));
+        assert!(!scanner.is_scannable_type("bytea"));
+        for data_type in [
+            "uuid",
+            "date",
+            "timestamp with time zone",
+            "numeric",
+            "inet",
+            "USER-DEFINED",
+        ] {
+            assert!(scanner.is_scannable_type(data_type), "{}", data_type);
+        }
+    }
+
+    #[test]
+    fn test_comment_marks_pii() {
+        let keywords = crate::config::default_comment_keywords();
+        assert!(comment_marks_pii("PII: customer email", &keywords));
+        assert!(comment_marks_pii("Erased on request (gdpr)", &keywords));
+        assert!(comment_marks_pii("SENSITIVE", &keywords));
+        // Whole words only
+        assert!(!comment_marks_pii("Spiilled over from legacy", &keywords));
+        assert!(!comment_marks_pii("Insensitive to case", &keywords));
+        assert!(!comment_marks_pii("Order total", &keywords));
+        assert!(!comment_marks_pii("PII", &[]));
+        assert!(comment_marks_pii(
+            "Personal data, see DPA",
+            &["personal data".to_string()]
+        ));
+    }
+
+    #[test]
+    fn test_column_confidence() {
+        use PiiType::{Email, Phone};
+        // Values alone, with the comment adding 0.2
+        assert_eq!(
+            column_confidence(Some(Email), 0.4, None, false, 0.5),
+            (Some(Email), 0.4)
+        );
+        assert_eq!(
+            column_confidence(Some(Email), 0.4, None, true, 0.5),
+            (Some(Email), 0.6)
+        );
+        // Name and values agreeing, capped at 1
+        assert_eq!(
+            column_confidence(Some(Email), 0.5, Some(Email), false, 0.5),
+            (Some(Email), 0.8)
+        );
+        assert_eq!(
+            column_confidence(Some(Email), 0.9, Some(Email), true, 0.5),
+            (Some(Email), 1.0)
+        );
+        // Disagreeing, the values win at a discount
+        assert_eq!(
+            column_confidence(Some(Phone), 0.5, Some(Email), false, 0.5),
+            (Some(Phone), 0.4)
+        );
+        assert_eq!(
+            column_confidence(Some(Phone), 0.5, Some(Email), true, 0.5),
+            (Some(Phone), 0.6)
+        );
+        // The name alone
+        assert_eq!(
+            column_confidence(None, 0.0, Some(Email), false, 0.5),
+            (Some(Email), 0.6)
+        );
+        assert_eq!(
+            column_confidence(None, 0.0, Some(Email), true, 0.5),
+            (Some(Email), 0.8)
+        );
+        // The comment alone leaves the type to `documented_finding`
+        assert_eq!(column_confidence(None, 0.0, None, true, 0.5), (None, 0.0));
+
+        let scanner = DbScanner::new("localhost".to_string(), 5432, DbProtocol::Postgres);
+        let mut col = column("loyalty_id", "integer");
+        col.comment = Some("Sensitive: ties orders to a person".to_string());
+        let finding = scanner.documented_finding("users", &col, 0);
+        assert_eq!(finding.pii_type, "Documented");
+        assert_eq!(finding.confidence, DOCUMENTED_CONFIDENCE);
+        assert_eq!(finding.suggested_strategy, "redact");
+    }
+
+    #[test]
+    fn test_scan_json_column() {
+        let scanner = DbScanner::new("localhost".to_string(), 5432, DbProtocol::Postgres);
+        let config: ScanConfig = serde_json::from_value(serde_json::json!({
+            "username": "postgres",
+            "password": "secret",
+            "database": "shop"
+        }))
+        .unwrap();
+        let documents = [
+            r#"{"contact": {"email": "ann@example.com", "note": "call later"}, "plan": "pro"}"#,
+            r#"{"contact": {"email": "bob@example.com"}, "ips": ["10.0.0.1", "10.0.0.2"]}"#,
+            r#"{"first name": "Carol", "cards": [{"pan": "4111 1111 1111 1111"}]}"#,
+            "not json",
+        ];
+        let sample_data: Vec<HashMap<String, Option<String>>> = documents
+            .iter()
+            .map(|doc| HashMap::from([("properties".to_string(), Some(doc.to_string()))]))
+            .collect();
+
+        let findings = scanner.scan_json_column(
+            &sample_data,
+            "users",
+            &column("properties", "jsonb"),
+            &config,
+        );
+        let found: Vec<(&str, &str, usize)> = findings
+            .iter()
+            .map(|f| {
+                (
+                    f.json_path.as_deref().unwrap(),
+                    f.pii_type.as_str(),
+                    f.match_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("$.contact.email", "Email", 2),
+                ("$.ips[*]", "IpAddress", 2),
+                ("$.cards[*].pan", "CreditCard", 1),
+            ]
+        );
+        assert_eq!(findings[0].suggested_strategy, "email");
+        assert_eq!(findings[0].row_count, 4);
+        assert_eq!(findings[0].confidence, 1.0);
+
+        // Every path is one `JsonPath` accepts
+        let mut leaves = Vec::new();
+        let doc: serde_json::Value =
+            serde_json::from_str(r#"{"first name": 1, "it's": "x", "a-b": {"c_d": ["y"]}}"#)
+                .unwrap();
+        json_string_leaves(&doc, "$", &mut leaves);
+        let paths: Vec<&str> = leaves.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["$['a-b'].c_d[*]", "$[\"it's\"]"]);
+        for path in paths {
+            crate::config::JsonPath::new(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_scan_column_values() {
+        let rows = |values: &[&str]| -> Vec<HashMap<String, Option<String>>> {
+            values
+                .iter()
+                .map(|v| HashMap::from([("col".to_string(), Some(v.to_string()))]))
+                .collect()
+        };
+        let scanner = DbScanner::new("localhost".to_string(), 5432, DbProtocol::Postgres);
+
+        let (count, pii_type, sample) =
+            scanner.scan_column_values(&rows(&["a@b.com", "", "n/a", "c@d.org"]), "col");
+        assert_eq!(count, 2);
+        assert_eq!(pii_type, Some(PiiType::Email));
+        assert_eq!(sample.as_deref(), Some("a@b.com"));
+
+        assert_eq!(
+            scanner.scan_column_values(&rows(&["n/a"]), "col"),
+            (0, None, None)
+        );
+
+        // Card numbers that are also order references count towards both
+        let config: crate::config::ScannerConfig = serde_yaml::from_str(
+            "custom_patterns:\n  - { name: order_ref, regex: \"\\\\d{16}\", strategy: hash }",
+        )
+        .unwrap();
+        let scanner = scanner.with_pii_scanner(Arc::new(PiiScanner::from_config(&config)));
+        let (count, pii_type, _) = scanner.scan_column_values(
+            &rows(&["4111111111111111", "5555555555554444", "a@b.com"]),
+            "col",
+        );
+        assert_eq!(count, 2);
+        assert_eq!(pii_type, Some(PiiType::Custom("order_ref".to_string())));
+    }
+
+    #[test]
+    fn test_mysql_support() {
+        let scanner = DbScanner::new("localhost".to_string(), 3306, DbProtocol::MySql);
+        assert!(scanner.is_scannable_type("varchar"));
+        assert!(scanner.is_scannable_type("LONGTEXT"));
+        assert!(scanner.is_scannable_type("json"));
+        assert!(!scanner.is_scannable_type("int"));
+        assert!(!scanner.is_scannable_type("blob"));
+
+        let config: ScanConfig = serde_json::from_value(serde_json::json!({
+            "username": "root",
+            "password": "secret",
+            "database": "shop"
+        }))
+        .unwrap();
+        // A MySQL schema is a database
+        assert_eq!(config.schema_for(DbProtocol::MySql), "shop");
+        assert_eq!(config.schema_for(DbProtocol::Postgres), "public");
+
+        assert_eq!(mysql_ident("my`table"), "`my``table`");
+
+        use mysql_async::Value;
+        assert_eq!(mysql_value_string(&Value::NULL), None);
+        assert_eq!(
+            mysql_value_string(&Value::Bytes(b"a@b.com".to_vec())).as_deref(),
+            Some("a@b.com")
+        );
+        assert_eq!(mysql_value_string(&Value::Int(-3)).as_deref(), Some("-3"));
+        assert_eq!(
+            mysql_value_string(&Value::Date(1990, 1, 15, 0, 0, 0, 0)).as_deref(),
+            Some("1990-01-15")
+        );
+    }
+
+    fn column(name: &str, data_type: &str) -> ColumnInfo {
+        ColumnInfo {
+            table_name: "users".to_string(),
+            column_name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable: true,
+            character_maximum_length: None,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn test_sample_query() {
+        let (email, notes) = (column("email", "text"), column("note\"s", "text"));
+        let columns = [&email, &notes];
+        // Tiny or never analyzed tables are read with LIMIT alone
+        assert_eq!(
+            postgres_sample_query("public", "users", &columns, 100, SampleMethod::System, 150),
+            r#"SELECT "email", "note""s" FROM "public"."users" LIMIT 100"#
+        );
+        assert_eq!(
+            postgres_sample_query("public", "users", &columns, 100, SampleMethod::System, 0),
+            r#"SELECT "email", "note""s" FROM "public"."users" LIMIT 100"#
+        );
+        // Twice the rows needed
+        assert_eq!(
+            postgres_sample_query(
+                "public",
+                "users",
+                &columns,
+                100,
+                SampleMethod::System,
+                1_000_000
+            ),
+            r#"SELECT "email", "note""s" FROM "public"."users" TABLESAMPLE SYSTEM (0.02) LIMIT 100"#
+        );
+        assert_eq!(
+            postgres_sample_query(
+                "app",
+                "events",
+                &[&column("ip", "text")],
+                50,
+                SampleMethod::Bernoulli,
+                10_000
+            ),
+            r#"SELECT "ip" FROM "app"."events" TABLESAMPLE BERNOULLI (1) LIMIT 50"#
+        );
+        assert_eq!(
+            postgres_sample_query(
+                "app",
+                "events",
+                &[&column("ip", "text")],
+                50,
+                SampleMethod::Limit,
+                10_000
+            ),
+            r#"SELECT "ip" FROM "app"."events" LIMIT 50"#
+        );
+        // JSON is fetched as text
+        assert_eq!(
+            postgres_sample_query(
+                "public",
+                "users",
+                &[&column("properties", "jsonb")],
+                10,
+                SampleMethod::Limit,
+                0
+            ),
+            r#"SELECT "properties"::text AS "properties" FROM "public"."users" LIMIT 10"#
+        );
+        // So are types tokio_postgres can't decode, while dates, UUIDs and
+        // addresses are decoded and formatted
+        let (tier, total, ip, born) = (
+            column("tier", "USER-DEFINED"),
+            column("total", "numeric"),
+            column("ip", "inet"),
+            column("born", "date"),
+        );
+        assert_eq!(
+            postgres_sample_query(
+                "public",
+                "users",
+                &[&tier, &total, &ip, &born],
+                10,
+                SampleMethod::Limit,
+                0
+            ),
+            r#"SELECT "tier"::text AS "tier", "total"::text AS "total", "ip", "born" FROM "public"."users" LIMIT 10"#
+        );
+
+        let config: ScanConfig = serde_json::from_value(serde_json::json!({
+            "username": "postgres",
+            "password": "secret",
+            "database": "shop",
+            "sample_size": 500,
+            "max_sample_cells": 1000
+        }))
+        .unwrap();
+        assert_eq!(config.sample_method, SampleMethod::System);
+        assert_eq!(config.sample_limit(1), 500);
+        // Wide tables get fewer rows
+        assert_eq!(config.sample_limit(4), 250);
+        assert_eq!(config.sample_limit(5000), 1);
+    }
+
+    #[test]
+    fn test_scan_filters() {
+        let config: ScanConfig = serde_json::from_value(serde_json::json!({
+            "database": "shop",
+            "include_tables": ["users", "orders_*"],
+            "exclude_tables": ["orders_archive"],
+            "exclude_columns": ["*.description", "notes"],
+            "include_columns": []
+        }))
+        .unwrap();
+        let filters = &config.filters;
+        assert!(filters.includes_table("users"));
+        assert!(filters.includes_table("orders_2024"));
+        assert!(!filters.includes_table("orders_archive"));
+        assert!(!filters.includes_table("sessions"));
+
+        assert!(filters.includes_column("users", "email"));
+        assert!(!filters.includes_column("users", "description"));
+        assert!(!filters.includes_column("orders_2024", "notes"));
+        assert!(filters.validate().is_ok());
+
+        // Only the given columns
+        let filters = ScanFilters {
+            include_columns: vec!["users.email".parse().unwrap(), "phone".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(filters.includes_column("users", "email"));
+        assert!(!filters.includes_column("orders", "email"));
+        assert!(filters.includes_column("orders", "phone"));
+
+        // Nothing left to scan
+        let filters = ScanFilters {
+            include_tables: vec!["users".parse().unwrap(), "orders".parse().unwrap()],
+            exclude_tables: vec!["users".parse().unwrap(), "ord*".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            filters.validate(),
+            Err(ScanError::InvalidRequest(_))
+        ));
+        let filters = ScanFilters {
+            include_columns: vec!["users.email".parse().unwrap()],
+            exclude_columns: vec!["*.email".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(filters.validate().is_err());
+
+        let bad = serde_json::from_value::<ScanConfig>(serde_json::json!({
+            "database": "shop",
+            "exclude_columns": ["re:("]
+        }));
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_resolve_credentials() {
+        let mut scanner = ScannerConfig::default();
+        let scan =
+            |request: serde_json::Value| -> ScanConfig { serde_json::from_value(request).unwrap() };
+
+        // Inline credentials are kept, but never printed
+        let mut config = scan(serde_json::json!({
+            "username": "admin",
+            "password": "hunter2",
+            "database": "shop"
+        }));
+        config.resolve_credentials(&scanner).unwrap();
+        assert_eq!(config.login().unwrap(), ("admin", "hunter2"));
+        assert!(!format!("{:?}", config).contains("hunter2"));
+
+        let mut config = scan(serde_json::json!({"database": "shop"}));
+        assert!(matches!(
+            config.resolve_credentials(&scanner),
+            Err(ScanError::AuthRequired)
+        ));
+
+        scanner.credentials.insert(
+            "default".to_string(),
+            crate::config::ScannerCredentials {
+                username: "scanner".to_string(),
+                password: None,
+                password_file: None,
+                password_env: Some("IRONVEIL_TEST_UNSET_SCANNER_PASSWORD".to_string()),
+            },
+        );
+        let Err(ScanError::InvalidCredentials(message)) = config.resolve_credentials(&scanner)
+        else {
+            panic!("expected the unset variable to be reported");
+        };
+        assert!(
+            message.contains("IRONVEIL_TEST_UNSET_SCANNER_PASSWORD"),
+            "{}",
+            message
+        );
+
+        scanner.credentials.get_mut("default").unwrap().password_env = None;
+        config.resolve_credentials(&scanner).unwrap();
+        assert_eq!(config.login().unwrap(), ("scanner", ""));
+
+        let mut config = scan(serde_json::json!({
+            "database": "shop",
+            "credentials_ref": "reporting"
+        }));
+        assert!(matches!(
+            config.resolve_credentials(&scanner),
+            Err(ScanError::InvalidCredentials(_))
+        ));
+    }
+
+    fn tls_fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn scan_config(tls: serde_json::Value) -> ScanConfig {
+        let mut config = serde_json::json!({
+            "username": "postgres",
+            "password": "secret",
+            "database": "shop"
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(tls.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn ssl_mode(config: &ScanConfig, app: &AppConfig) -> Option<SslMode> {
+        config
+            .tls_connector(app)
+            .unwrap()
+            .map(|(_, ssl_mode)| ssl_mode)
+    }
+
+    #[test]
+    fn test_scan_tls_connector() {
+        let app = AppConfig::default();
+        // TLS is tried first, as libpq does by default
+        assert_eq!(
+            ssl_mode(&scan_config(serde_json::json!({})), &app),
+            Some(SslMode::Prefer)
+        );
+        assert_eq!(
+            ssl_mode(
+                &scan_config(serde_json::json!({"sslmode": "disable"})),
+                &app
+            ),
+            None
+        );
+        assert_eq!(
+            ssl_mode(
+                &scan_config(serde_json::json!({"sslmode": "require"})),
+                &app
+            ),
+            Some(SslMode::Require)
+        );
+        // A CA certificate implies verify-full
+        let with_ca = scan_config(serde_json::json!({"ca_cert": tls_fixture("ca.crt")}));
+        assert_eq!(with_ca.sslmode, None);
+        assert_eq!(ssl_mode(&with_ca, &app), Some(SslMode::Require));
+
+        // The upstream TLS settings apply unless the scan overrides them
+        let upstream_tls = AppConfig {
+            upstream_tls_mode: crate::config::UpstreamTlsMode::Require,
+            ..Default::default()
+        };
+        assert_eq!(
+            ssl_mode(&scan_config(serde_json::json!({})), &upstream_tls),
+            Some(SslMode::Require)
+        );
+        assert_eq!(
+            ssl_mode(
+                &scan_config(serde_json::json!({"sslmode": "disable"})),
+                &upstream_tls
+            ),
+            None
+        );
+
+        let missing_ca = scan_config(serde_json::json!({"ca_cert": "/nonexistent/ca.crt"}));
+        assert!(matches!(
+            missing_ca.tls_connector(&app),
+            Err(ScanError::ConnectionFailed(_))
+        ));
+    }
+
+    /// Postgres that accepts `SSLRequest` and presents the fixture certificate
+    /// for `db.internal.example.com`, then hangs up
+    async fn spawn_tls_postgres() -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let tls_config = crate::config::TlsConfig {
+            enabled: true,
+            cert_path: tls_fixture("server.crt"),
+            key_path: tls_fixture("server.key"),
+            client_ca_path: None,
+            require_client_auth: false,
+        };
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(
+            crate::tls::server_config(&tls_config).unwrap(),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut ssl_request = [0u8; 8];
+                if socket.read_exact(&mut ssl_request).await.is_err() {
+                    continue;
+                }
+                socket.write_all(b"S").await.unwrap();
+                let _ = acceptor.accept(socket).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_scan_certificate_error() {
+        let port = spawn_tls_postgres().await;
+        let app = AppConfig::default();
+
+        // Trusting the CA isn't enough, the certificate doesn't name 127.0.0.1
+        let config = scan_config(serde_json::json!({
+            "sslmode": "verify-full",
+            "ca_cert": tls_fixture("ca.crt")
+        }));
+        let (connector, ssl_mode) = config.tls_connector(&app).unwrap().unwrap();
+        let scanner = DbScanner::new("127.0.0.1".to_string(), port, DbProtocol::Postgres)
+            .with_tls(connector, ssl_mode);
+        let Err(ScanError::ConnectionFailed(message)) = scanner.connect_postgres(&config).await
+        else {
+            panic!("expected a connection failure");
+        };
+        assert!(message.contains("invalid peer certificate"), "{}", message);
+        assert!(message.contains("`ca_cert`"), "{}", message);
+
+        // Without verification the handshake succeeds and the failure is elsewhere
+        let config = scan_config(serde_json::json!({"sslmode": "require"}));
+        let (connector, ssl_mode) = config.tls_connector(&app).unwrap().unwrap();
+        let scanner = DbScanner::new("127.0.0.1".to_string(), port, DbProtocol::Postgres)
+            .with_tls(connector, ssl_mode);
+        let Err(ScanError::ConnectionFailed(message)) = scanner.connect_postgres(&config).await
+        else {
+            panic!("expected a connection failure");
+        };
+        assert!(!message.contains("certificate"), "{}", message);
     }
 
     #[test]
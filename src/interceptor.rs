@@ -1,76 +1,867 @@
-use crate::protocol::mysql::{ColumnDefinition, ResultRow};
-use crate::protocol::postgres::{DataRow, RowDescription};
-use crate::scanner::{PiiScanner, PiiType};
+use crate::catalog::{TableName, TableResolver};
+use crate::config::{
+    AppConfig, ByteaMode, ColumnMatch, CopyInPolicy, HeuristicMaskingConfig, HeuristicStrategy,
+    JsonPath, JsonPathSegment, MaskStrategy, MaskingConfig, MaskingMode, MaskingOptions,
+    MaskingRule, OnMaskingError,
+};
+use crate::copy_in::{CopyFormat, CopyInInspector, CopyReport};
+use crate::masking_cache::MaskingCache;
+use crate::protocol::mysql::{
+    BinaryResultRow, BinaryValue, ColumnDefinition, ResultRow, ResultSetHeader,
+};
+use crate::protocol::postgres::{
+    BindMessage, DataRow, FORMAT_BINARY, FORMAT_TEXT, RawMessage, RowDescription, type_oid,
+};
+use crate::scanner::{PiiScanner, PiiType, iban_remainder, luhn_valid, tax_id_check_digit};
+use crate::vault::{TokenVault, token_for};
 use anyhow::Result;
+use chrono::NaiveDate;
 use fake::Fake;
-use fake::faker::address::en::CityName;
+use fake::faker::address::en::{BuildingNumber, CityName, StreetName, ZipCode};
+use fake::faker::company::en::CompanyName;
 use fake::faker::creditcard::en::CreditCardNumber;
 use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::{FirstName, LastName, Name};
 use fake::faker::phone_number::en::PhoneNumber;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+use siphasher::sip::SipHasher13;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hasher;
+use std::sync::Arc;
 
-fn generate_fake_data(strategy: &str, seed: u64) -> String {
+fn generate_fake_data(strategy: MaskStrategy, seed: u64) -> String {
     let mut rng = ChaCha8Rng::seed_from_u64(seed);
     match strategy {
-        "email" => SafeEmail().fake_with_rng(&mut rng),
-        "phone" => PhoneNumber().fake_with_rng(&mut rng),
-        "address" => CityName().fake_with_rng(&mut rng),
-        "credit_card" => CreditCardNumber().fake_with_rng(&mut rng),
-        "ssn" => format!("XXX-XX-{:04}", (seed % 10000)),
-        "ip" => "0.0.0.0".to_string(),
-        "dob" => "1900-01-01".to_string(),
-        "passport" => "XXXXXXXX".to_string(),
+        MaskStrategy::Email => SafeEmail().fake_with_rng(&mut rng),
+        MaskStrategy::Phone => PhoneNumber().fake_with_rng(&mut rng),
+        MaskStrategy::Address => CityName().fake_with_rng(&mut rng),
+        MaskStrategy::StreetAddress => {
+            let number: String = BuildingNumber().fake_with_rng(&mut rng);
+            let street: String = StreetName().fake_with_rng(&mut rng);
+            format!("{} {}", number, street)
+        }
+        MaskStrategy::Zip => ZipCode().fake_with_rng(&mut rng),
+        MaskStrategy::FirstName => FirstName().fake_with_rng(&mut rng),
+        MaskStrategy::LastName => LastName().fake_with_rng(&mut rng),
+        MaskStrategy::FullName => Name().fake_with_rng(&mut rng),
+        MaskStrategy::Company => CompanyName().fake_with_rng(&mut rng),
+        MaskStrategy::CreditCard => CreditCardNumber().fake_with_rng(&mut rng),
+        MaskStrategy::Ssn => format!("XXX-XX-{:04}", (seed % 10000)),
+        MaskStrategy::Ip => "0.0.0.0".to_string(),
+        MaskStrategy::Dob => "1900-01-01".to_string(),
+        MaskStrategy::Passport => "XXXXXXXX".to_string(),
+        MaskStrategy::Iban => fake_iban("", seed),
+        MaskStrategy::Nino => fake_nino(&mut rng),
+        MaskStrategy::TaxId => fake_tax_id(&mut rng),
         _ => "MASKED".to_string(),
     }
 }
 
-/// Convert PiiType to masking strategy string
-fn pii_type_to_strategy(pii_type: PiiType) -> &'static str {
+/// Checksum-valid IBAN with random account digits. Keeps the country
+/// code, length and grouping of `value` when it looks like an IBAN, and
+/// is German-shaped otherwise.
+fn fake_iban(value: &str, seed: u64) -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let compact: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    let (country, bban_len) = match compact.get(..2) {
+        Some(country)
+            if (15..=34).contains(&compact.len())
+                && country.chars().all(|c| c.is_ascii_uppercase()) =>
+        {
+            (country, compact.len() - 4)
+        }
+        _ => ("DE", 18),
+    };
+    let bban: String = (0..bban_len)
+        .map(|_| char::from(b'0' + rng.random_range(0..10)))
+        .collect();
+    let check = 98 - iban_remainder(&format!("{}00{}", country, bban));
+    let iban = format!("{}{:02}{}", country, check, bban);
+    if !value.contains(' ') {
+        return iban;
+    }
+    // Printed IBANs are grouped in fours
+    iban.as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// UK National Insurance number with an allocatable prefix
+fn fake_nino(rng: &mut ChaCha8Rng) -> String {
+    const FIRST: &[u8] = b"ABCEGHJKLMNOPRSTWXYZ";
+    const SECOND: &[u8] = b"ABCEGHJKLMNPRSTWXYZ";
+    let prefix = loop {
+        let prefix = [
+            FIRST[rng.random_range(0..FIRST.len())],
+            SECOND[rng.random_range(0..SECOND.len())],
+        ];
+        if !matches!(
+            &prefix,
+            b"BG" | b"GB" | b"KN" | b"NK" | b"NT" | b"TN" | b"ZZ"
+        ) {
+            break prefix;
+        }
+    };
+    format!(
+        "{}{:06}{}",
+        std::str::from_utf8(&prefix).unwrap_or_default(),
+        rng.random_range(0..1_000_000),
+        char::from(b'A' + rng.random_range(0..4))
+    )
+}
+
+/// German Steuer-ID: ten digits with exactly one repeated, then the
+/// MOD 11,10 check digit
+fn fake_tax_id(rng: &mut ChaCha8Rng) -> String {
+    let mut digits: Vec<u8> = (0..10).collect();
+    for i in (1..digits.len()).rev() {
+        digits.swap(i, rng.random_range(0..=i));
+    }
+    // Drop one digit and repeat another in its place
+    let repeated = digits[rng.random_range(0..9)];
+    digits[9] = repeated;
+    for i in (1..digits.len()).rev() {
+        digits.swap(i, rng.random_range(0..=i));
+    }
+    // The first digit can't be zero
+    if digits[0] == 0 {
+        let swap = digits.iter().position(|&d| d != 0).unwrap_or(0);
+        digits.swap(0, swap);
+    }
+    let check = tax_id_check_digit(&digits);
+    digits
+        .iter()
+        .chain(std::iter::once(&check))
+        .map(|d| char::from(b'0' + d))
+        .collect()
+}
+
+/// Mask all but the first `keep_prefix` and last `keep_suffix` characters
+/// of a value. Punctuation and whitespace are kept, so `4111-1111-1111-9012`
+/// becomes `****-****-****-9012`. A value too short to hide anything is
+/// masked in full.
+fn mask_partial(value: &str, options: &MaskingOptions) -> String {
+    let len = value.chars().count();
+    let (keep_prefix, keep_suffix) = if len > options.keep_prefix + options.keep_suffix {
+        (options.keep_prefix, options.keep_suffix)
+    } else {
+        (0, 0)
+    };
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i < keep_prefix || i >= len - keep_suffix || !c.is_alphanumeric() {
+                c
+            } else {
+                options.mask_char
+            }
+        })
+        .collect()
+}
+
+/// Replace the digits of a value with ones drawn from `seed`, keeping
+/// `keep_leading` and `keep_trailing` digits and every non-digit as they are
+fn preserve_digits(value: &str, seed: u64, keep_leading: usize, keep_trailing: usize) -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let digits = value.chars().filter(char::is_ascii_digit).count();
+    let mut index = 0;
+    value
+        .chars()
+        .map(|c| {
+            if !c.is_ascii_digit() {
+                return c;
+            }
+            let keep = index < keep_leading || index + keep_trailing >= digits;
+            index += 1;
+            if keep {
+                c
+            } else {
+                char::from(b'0' + rng.random_range(0..10u8))
+            }
+        })
+        .collect()
+}
+
+/// Synthetic card number with the original's length and formatting, fixed
+/// up to pass the Luhn check. The issuer prefix and last four digits can be
+/// kept real; the rightmost generated digit absorbs the check.
+fn preserve_credit_card(value: &str, seed: u64, options: &MaskingOptions) -> String {
+    let digits = value.chars().filter(char::is_ascii_digit).count();
+    if digits == 0 {
+        return value.to_string();
+    }
+    let keep_leading = if options.keep_bin { 6 } else { 0 };
+    let keep_trailing = if options.keep_last_four { 4 } else { 0 };
+    // Too short to keep anything real
+    let (keep_leading, keep_trailing) = if digits > keep_leading + keep_trailing {
+        (keep_leading, keep_trailing)
+    } else {
+        (0, 0)
+    };
+    let masked = preserve_digits(value, seed, keep_leading, keep_trailing);
+
+    let mut number: Vec<u8> = masked
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|b| b - b'0')
+        .collect();
+    // Exactly one value of the check position satisfies Luhn
+    let check = digits - keep_trailing - 1;
+    for d in 0..10 {
+        number[check] = d;
+        if luhn_valid(&number) {
+            break;
+        }
+    }
+
+    let mut generated = number.into_iter();
+    masked
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                char::from(b'0' + generated.next().unwrap_or_default())
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Synthetic phone number with the original's formatting and country code
+fn preserve_phone(value: &str, seed: u64, options: &MaskingOptions) -> String {
+    // A leading `+` is followed by the country code, up to the first separator
+    let country_code = value
+        .trim_start()
+        .strip_prefix('+')
+        .map(|rest| rest.chars().take_while(char::is_ascii_digit).count())
+        .filter(|len| *len <= 3)
+        .unwrap_or(0);
+    let keep_trailing = if options.keep_last_four { 4 } else { 0 };
+    preserve_digits(value, seed, country_code, keep_trailing)
+}
+
+/// Fake email that keeps the original domain, or a consistent pseudonym of
+/// it for external domains when configured
+fn preserve_email_domain(
+    value: &str,
+    seed: u64,
+    seed_key: &[u8; 16],
+    masking: &MaskingConfig,
+) -> String {
+    let Some((_, domain)) = value.rsplit_once('@') else {
+        return generate_fake_data(MaskStrategy::Email, seed);
+    };
+    let fake = generate_fake_data(MaskStrategy::Email, seed);
+    let local = fake.split('@').next().unwrap_or_default();
+
+    let internal = masking.internal_email_domains.iter().any(|internal| {
+        domain.eq_ignore_ascii_case(internal)
+            || domain
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", internal.to_ascii_lowercase()))
+    });
+    if !masking.pseudonymize_email_domains || internal {
+        return format!("{}@{}", local, domain);
+    }
+
+    // Same domain, same pseudonym, whichever value it came from
+    let pseudonym = hash_seed(domain.to_ascii_lowercase().as_bytes(), seed_key);
+    format!("{}@domain-{:08x}.example", local, pseudonym as u32)
+}
+
+/// Seed for the strategies that derive their output from the value, keyed
+/// so that the mapping can't be rebuilt without the salt
+fn hash_seed(value: &[u8], seed_key: &[u8; 16]) -> u64 {
+    let mut hasher = SipHasher13::new_with_key(seed_key);
+    hasher.write(value);
+    hasher.finish()
+}
+
+/// SipHash key for a seed salt. Without a salt the key is fixed, which keeps
+/// masking deterministic but lets the mapping be precomputed.
+fn seed_key(salt: Option<&str>) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    if let Some(salt) = salt {
+        key.copy_from_slice(&Sha256::digest(salt.as_bytes())[..16]);
+    }
+    key
+}
+
+/// Scale a number by a seeded factor within `noise_percent` percent of 1,
+/// keeping its sign, scale and zero padding, so `-0042.50` stays a
+/// negative, zero-padded number with two decimals. The length only changes
+/// when the noise carries the number across a power of ten. Values that
+/// aren't plain integers or decimals are returned unchanged.
+fn add_numeric_noise(value: &str, seed: u64, noise_percent: u32) -> String {
+    let (sign, unsigned) = match value.strip_prefix(['-', '+']) {
+        Some(unsigned) => value.split_at(value.len() - unsigned.len()),
+        None => ("", value),
+    };
+    let (int_part, frac_part) = match unsigned.split_once('.') {
+        Some((_, "")) => return value.to_string(),
+        Some(parts) => parts,
+        None => (unsigned, ""),
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if int_part.is_empty() || !is_digits(int_part) || !is_digits(frac_part) {
+        return value.to_string();
+    }
+    // Digits beyond what fits in the arithmetic below aren't worth noising
+    let Ok(units) = format!("{}{}", int_part, frac_part).parse::<u64>() else {
+        return value.to_string();
+    };
+
+    // Factor in basis points, so the arithmetic stays exact
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let band = i128::from(noise_percent.min(100)) * 100;
+    let factor = 10_000 + rng.random_range(-band..=band);
+    let noised = (i128::from(units) * factor + 5_000) / 10_000;
+
+    let scale = frac_part.len();
+    let digits = format!("{:0>width$}", noised, width = scale + 1);
+    let (int_digits, frac_digits) = digits.split_at(digits.len() - scale);
+    // Keep zero padding the value was written with
+    let int_digits = if int_part.len() > 1 && int_part.starts_with('0') {
+        format!("{:0>width$}", int_digits, width = int_part.len())
+    } else {
+        int_digits.to_string()
+    };
+
+    if scale == 0 {
+        format!("{}{}", sign, int_digits)
+    } else {
+        format!("{}{}.{}", sign, int_digits, frac_digits)
+    }
+}
+
+/// Move a date by a seeded offset of 1 to `max_shift_days` days either
+/// way, keeping its format. ISO dates, ISO timestamps (the time part is kept
+/// as is) and MM/DD/YYYY are understood; anything else gets the `dob`
+/// placeholder.
+fn shift_date(value: &str, seed: u64, max_shift_days: u32) -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let days = if max_shift_days == 0 {
+        0
+    } else {
+        let days = i64::from(rng.random_range(1..=max_shift_days));
+        if rng.random_bool(0.5) { days } else { -days }
+    };
+    let shift = |date: NaiveDate| date.checked_add_signed(chrono::Duration::days(days));
+
+    let split = value.len().min(10);
+    let (date_part, rest) = if value.is_char_boundary(split) {
+        value.split_at(split)
+    } else {
+        (value, "")
+    };
+    let iso_rest = rest.is_empty() || rest.starts_with(['T', ' ']);
+    if iso_rest
+        && let Ok(date) = NaiveDate::parse_from_str(date_part, "%Y-%m-%d")
+        && let Some(shifted) = shift(date)
+    {
+        return format!("{}{}", shifted.format("%Y-%m-%d"), rest);
+    }
+    if value.len() == 10
+        && let Ok(date) = NaiveDate::parse_from_str(value, "%m/%d/%Y")
+        && let Some(shifted) = shift(date)
+    {
+        return shifted.format("%m/%d/%Y").to_string();
+    }
+    generate_fake_data(MaskStrategy::Dob, seed)
+}
+
+/// Replace every match of `pattern` with `template`, expanding `$1`-style
+/// captures and `{rand:N}` into N digits drawn from the seed. Values the
+/// pattern doesn't match are returned unchanged.
+fn replace_custom(value: &str, pattern: &Regex, template: &str, seed: u64) -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    pattern
+        .replace_all(value, |caps: &Captures| {
+            // Captures first, so digits can't run on into a `$1` group name
+            let mut replaced = String::new();
+            caps.expand(template, &mut replaced);
+            expand_random_digits(&replaced, &mut rng)
+        })
+        .into_owned()
+}
+
+/// Substitute each `{rand:N}` in a template with N random digits
+fn expand_random_digits(template: &str, rng: &mut ChaCha8Rng) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{rand:") {
+        let after = &rest[start + "{rand:".len()..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        let Ok(count) = after[..end].parse::<usize>() else {
+            expanded.push_str(&rest[..start + "{rand:".len()]);
+            rest = after;
+            continue;
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.extend((0..count).map(|_| char::from(b'0' + rng.random_range(0..10u8))));
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Salted SHA-256 of a value as lowercase hex, stable across connections
+/// and restarts for the same salt
+fn hash_value(value: &[u8], masking: &MaskingConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value);
+    hasher.update(masking.hash_salt.as_deref().unwrap_or_default().as_bytes());
+    let mut hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if let Some(length) = masking.hash_length {
+        hex.truncate(length);
+    }
+    hex
+}
+
+/// Masking settings, taken from the config once per result set
+struct MaskingContext {
+    mode: MaskingMode,
+    /// Scanner for the `scanner` config
+    scanner: Arc<PiiScanner>,
+    heuristic: HeuristicMaskingConfig,
+    masking: MaskingConfig,
+    /// Key derived from `masking.seed_salt`
+    seed_key: [u8; 16],
+    vault: Option<Arc<TokenVault>>,
+    cache: Option<Arc<MaskingCache>>,
+    /// Strategy made to fail, to exercise `masking.on_error`
+    #[cfg(test)]
+    failing_strategy: Option<MaskStrategy>,
+}
+
+impl MaskingContext {
+    #[cfg(test)]
+    fn new(config: &AppConfig) -> Self {
+        Self::with_scanner(config, Arc::new(PiiScanner::from_config(&config.scanner)))
+    }
+
+    fn with_scanner(config: &AppConfig, scanner: Arc<PiiScanner>) -> Self {
+        Self {
+            mode: config.masking_mode,
+            scanner,
+            heuristic: config.heuristic_masking.clone(),
+            masking: config.masking.clone(),
+            seed_key: seed_key(config.masking.seed_salt.as_deref()),
+            vault: None,
+            cache: None,
+            #[cfg(test)]
+            failing_strategy: None,
+        }
+    }
+
+    /// Context for a connection, using the state's vault and cache
+    fn for_state(config: &AppConfig, state: &AppState) -> Self {
+        Self {
+            vault: state.token_vault.clone(),
+            cache: state.masking_cache.clone(),
+            ..Self::with_scanner(config, state.scanner())
+        }
+    }
+}
+
+/// Settings a result set is masked with, taken when its columns arrive so
+/// that all of its rows are masked alike and don't have to lock the config
+struct MaskingSnapshot {
+    enabled: bool,
+    ctx: Arc<MaskingContext>,
+}
+
+impl MaskingSnapshot {
+    fn take(config: &AppConfig, state: &AppState, policy: Option<&str>) -> Self {
+        Self {
+            enabled: config.masking_enabled_for(policy),
+            ctx: Arc::new(MaskingContext::for_state(config, state)),
+        }
+    }
+
+    /// The snapshot in `slot`, taken now if the result set's rows came
+    /// without one (e.g. an Execute with no Describe before it)
+    async fn current<'a>(
+        slot: &'a mut Option<Self>,
+        state: &AppState,
+        policy: Option<&str>,
+    ) -> &'a Self {
+        if slot.is_none() {
+            let config = state.config.read().await;
+            *slot = Some(Self::take(&config, state, policy));
+        }
+        slot.as_ref().expect("snapshot just taken")
+    }
+}
+
+/// Produce the replacement for a value under the given strategy, or `None`
+/// when the value is to become NULL
+fn mask_with_strategy(
+    strategy: MaskStrategy,
+    options: Option<&MaskingOptions>,
+    value: &[u8],
+    ctx: &MaskingContext,
+) -> Result<Option<String>> {
+    #[cfg(test)]
+    if ctx.failing_strategy == Some(strategy) {
+        anyhow::bail!("{} failed", strategy);
+    }
+
+    let seed_key = match options.and_then(|o| o.seed_salt.as_deref()) {
+        Some(salt) => seed_key(Some(salt)),
+        None => ctx.seed_key,
+    };
+    let masked = match strategy {
+        MaskStrategy::Null => return Ok(None),
+        MaskStrategy::Redact => options.cloned().unwrap_or_default().replacement,
+        MaskStrategy::Partial => {
+            let options = options.cloned().unwrap_or_default();
+            mask_partial(&String::from_utf8_lossy(value), &options)
+        }
+        MaskStrategy::Hash => hash_value(value, &ctx.masking),
+        // Without a vault the token can't be resolved, so it is only as
+        // reversible as a salted hash
+        MaskStrategy::Tokenize => match &ctx.vault {
+            Some(vault) => vault.tokenize(value),
+            None => token_for(
+                ctx.masking
+                    .hash_salt
+                    .as_deref()
+                    .unwrap_or_default()
+                    .as_bytes(),
+                value,
+            ),
+        },
+        _ => {
+            let generate = || seeded_mask(strategy, options, value, &seed_key, ctx);
+            match &ctx.cache {
+                // Options change the output, so only rules without them
+                // share cache entries
+                Some(cache) if options.is_none() => {
+                    cache.get_or_insert_with(strategy, &seed_key, value, generate)
+                }
+                _ => generate(),
+            }
+        }
+    };
+    // Tokens must stay whole to be resolved
+    let preserve_length = options
+        .and_then(|o| o.preserve_length)
+        .unwrap_or(ctx.masking.preserve_length);
+    if preserve_length && strategy != MaskStrategy::Tokenize {
+        return Ok(Some(fit_length(
+            masked,
+            value.len(),
+            hash_seed(value, &seed_key),
+        )));
+    }
+    Ok(Some(masked))
+}
+
+/// Cut or pad a replacement to exactly `len` bytes. Cuts fall on character
+/// boundaries; the bytes still missing are filled with letters and digits
+/// drawn from `seed`, so a value is always padded the same way.
+fn fit_length(mut masked: String, len: usize, seed: u64) -> String {
+    if masked.len() > len {
+        let mut end = len;
+        while !masked.is_char_boundary(end) {
+            end -= 1;
+        }
+        masked.truncate(end);
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    while masked.len() < len {
+        masked.push(rng.sample(rand::distr::Alphanumeric) as char);
+    }
+    masked
+}
+
+/// Replacement from the strategies that derive their output from a seed
+/// hashed from the value
+fn seeded_mask(
+    strategy: MaskStrategy,
+    options: Option<&MaskingOptions>,
+    value: &[u8],
+    seed_key: &[u8; 16],
+    ctx: &MaskingContext,
+) -> String {
+    let options = options.cloned().unwrap_or_default();
+    let seed = hash_seed(value, seed_key);
+    match strategy {
+        MaskStrategy::CreditCardPreserve => {
+            preserve_credit_card(&String::from_utf8_lossy(value), seed, &options)
+        }
+        MaskStrategy::PhonePreserve => {
+            preserve_phone(&String::from_utf8_lossy(value), seed, &options)
+        }
+        MaskStrategy::EmailPreserveDomain => preserve_email_domain(
+            &String::from_utf8_lossy(value),
+            seed,
+            seed_key,
+            &ctx.masking,
+        ),
+        MaskStrategy::Iban => fake_iban(&String::from_utf8_lossy(value), seed),
+        MaskStrategy::NumericNoise => {
+            add_numeric_noise(&String::from_utf8_lossy(value), seed, options.noise_percent)
+        }
+        MaskStrategy::DateShift => shift_date(
+            &String::from_utf8_lossy(value),
+            seed,
+            options.max_shift_days,
+        ),
+        MaskStrategy::Custom => {
+            if let Some(pattern) = &options.pattern {
+                replace_custom(
+                    &String::from_utf8_lossy(value),
+                    &pattern.0,
+                    &options.replacement,
+                    seed,
+                )
+            } else {
+                generate_fake_data(strategy, seed)
+            }
+        }
+        _ => generate_fake_data(strategy, seed),
+    }
+}
+
+/// The parts of a rule needed to mask the column it matched
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnRule {
+    strategy: MaskStrategy,
+    options: Option<MaskingOptions>,
+    json_paths: Vec<JsonPath>,
+}
+
+impl From<&MaskingRule> for ColumnRule {
+    fn from(rule: &MaskingRule) -> Self {
+        Self {
+            strategy: rule.strategy,
+            options: rule.options.clone(),
+            json_paths: rule.json_paths.clone(),
+        }
+    }
+}
+
+/// First rule that applies, preferring rules naming the column exactly over
+/// globs and regexes. An exclusion rule wins over any other, so a column can
+/// be opted out whatever else matches it.
+fn select_rule(
+    rules: &[MaskingRule],
+    applies: impl Fn(&MaskingRule) -> bool,
+) -> Option<&MaskingRule> {
+    let mut exact = None;
+    let mut pattern = None;
+    for rule in rules.iter().filter(|rule| applies(rule)) {
+        if rule.strategy == MaskStrategy::Exclude {
+            return Some(rule);
+        }
+        if rule.column.is_exact() {
+            exact.get_or_insert(rule);
+        } else {
+            pattern.get_or_insert(rule);
+        }
+    }
+    exact.or(pattern)
+}
+
+/// Strategy for a value the scanner flagged as `pii_type`
+fn heuristic_strategy(
+    pii_type: PiiType,
+    ctx: &MaskingContext,
+) -> (MaskStrategy, Option<&MaskingOptions>) {
+    let heuristic = &ctx.heuristic;
+    // Custom patterns name their own strategy
+    if let Some(strategy) = ctx.scanner.custom_strategy(&pii_type) {
+        return (strategy, Some(&heuristic.options));
+    }
+    match heuristic.strategy {
+        HeuristicStrategy::Fake
+            if pii_type == PiiType::Email && heuristic.preserve_email_domain =>
+        {
+            (MaskStrategy::EmailPreserveDomain, None)
+        }
+        HeuristicStrategy::Fake
+            if pii_type == PiiType::DateOfBirth && heuristic.shift_dates_of_birth =>
+        {
+            (MaskStrategy::DateShift, Some(&heuristic.options))
+        }
+        HeuristicStrategy::Fake => (pii_type_to_strategy(pii_type), None),
+        HeuristicStrategy::Partial => (MaskStrategy::Partial, Some(&heuristic.options)),
+        HeuristicStrategy::Null => (MaskStrategy::Null, None),
+        HeuristicStrategy::Redact => (MaskStrategy::Redact, Some(&heuristic.options)),
+    }
+}
+
+/// Masking strategy for a type of PII
+pub fn pii_type_to_strategy(pii_type: PiiType) -> MaskStrategy {
     match pii_type {
-        PiiType::Email => "email",
-        PiiType::CreditCard => "credit_card",
-        PiiType::Ssn => "ssn",
-        PiiType::Phone => "phone",
-        PiiType::IpAddress => "ip",
-        PiiType::DateOfBirth => "dob",
-        PiiType::Passport => "passport",
+        PiiType::Email => MaskStrategy::Email,
+        PiiType::CreditCard => MaskStrategy::CreditCard,
+        PiiType::Ssn => MaskStrategy::Ssn,
+        PiiType::Phone => MaskStrategy::Phone,
+        PiiType::IpAddress => MaskStrategy::Ip,
+        PiiType::DateOfBirth => MaskStrategy::Dob,
+        PiiType::Passport => MaskStrategy::Passport,
+        PiiType::Iban => MaskStrategy::Iban,
+        PiiType::UkNino => MaskStrategy::Nino,
+        PiiType::DeTaxId => MaskStrategy::TaxId,
+        PiiType::Custom(_) => MaskStrategy::Redact,
     }
 }
 
-fn mask_json_recursively(val: &mut serde_json::Value, scanner: &PiiScanner) {
+fn mask_json_recursively(
+    val: &mut serde_json::Value,
+    scanner: &PiiScanner,
+    ctx: &MaskingContext,
+) -> Result<()> {
     match val {
         serde_json::Value::String(s) => {
             if let Some(pii_type) = scanner.scan(s) {
-                let strategy = pii_type_to_strategy(pii_type);
-
-                // Deterministic seed based on the string value
-                let mut hasher = DefaultHasher::new();
-                s.hash(&mut hasher);
-                let seed = hasher.finish();
-
-                *s = generate_fake_data(strategy, seed);
+                let (strategy, options) = heuristic_strategy(pii_type, ctx);
+                *val = match mask_with_strategy(strategy, options, s.as_bytes(), ctx)? {
+                    Some(masked) => serde_json::Value::String(masked),
+                    None => serde_json::Value::Null,
+                };
             }
         }
         serde_json::Value::Array(arr) => {
             for v in arr {
-                mask_json_recursively(v, scanner);
+                mask_json_recursively(v, scanner, ctx)?;
             }
         }
         serde_json::Value::Object(map) => {
             for (_, v) in map {
-                mask_json_recursively(v, scanner);
+                mask_json_recursively(v, scanner, ctx)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Mask the values a rule's `json_paths` select in a JSON document, with the
+/// rule's strategy or, for `json` rules, the scanner. Returns the new
+/// document, or `None` when the value is not JSON or no path exists in it.
+fn mask_json_paths(
+    raw: &[u8],
+    paths: &[JsonPath],
+    strategy: MaskStrategy,
+    options: Option<&MaskingOptions>,
+    scanner: &PiiScanner,
+    ctx: &MaskingContext,
+) -> Result<Option<String>> {
+    let Ok(mut doc) = serde_json::from_slice::<serde_json::Value>(raw) else {
+        return Ok(None);
+    };
+    let mut found = false;
+    for path in paths {
+        visit_json_path(&mut doc, path.segments(), &mut |val| {
+            found = true;
+            if strategy == MaskStrategy::Json {
+                mask_json_recursively(val, scanner, ctx)
+            } else {
+                mask_json_leaf(val, strategy, options, ctx)
+            }
+        })?;
+    }
+    if !found {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(&doc)?))
+}
+
+fn visit_json_path(
+    val: &mut serde_json::Value,
+    segments: &[JsonPathSegment],
+    f: &mut dyn FnMut(&mut serde_json::Value) -> Result<()>,
+) -> Result<()> {
+    let Some((segment, rest)) = segments.split_first() else {
+        return f(val);
+    };
+    match (segment, val) {
+        (JsonPathSegment::Key(key), serde_json::Value::Object(map)) => {
+            if let Some(v) = map.get_mut(key) {
+                visit_json_path(v, rest, f)?;
+            }
+        }
+        (JsonPathSegment::Index(index), serde_json::Value::Array(arr)) => {
+            if let Some(v) = arr.get_mut(*index) {
+                visit_json_path(v, rest, f)?;
+            }
+        }
+        (JsonPathSegment::Wildcard, serde_json::Value::Array(arr)) => {
+            for v in arr {
+                visit_json_path(v, rest, f)?;
+            }
+        }
+        (JsonPathSegment::Wildcard, serde_json::Value::Object(map)) => {
+            for v in map.values_mut() {
+                visit_json_path(v, rest, f)?;
             }
         }
         _ => {}
     }
+    Ok(())
+}
+
+/// Mask a value selected by a JSON path. Numbers stay numbers when their
+/// replacement is one; arrays and objects have each of their values masked.
+fn mask_json_leaf(
+    val: &mut serde_json::Value,
+    strategy: MaskStrategy,
+    options: Option<&MaskingOptions>,
+    ctx: &MaskingContext,
+) -> Result<()> {
+    match val {
+        serde_json::Value::String(s) => {
+            *val = match mask_with_strategy(strategy, options, s.as_bytes(), ctx)? {
+                Some(masked) => serde_json::Value::String(masked),
+                None => serde_json::Value::Null,
+            };
+        }
+        serde_json::Value::Number(n) => {
+            *val = match mask_with_strategy(strategy, options, n.to_string().as_bytes(), ctx)? {
+                Some(masked) => serde_json::from_str::<serde_json::Number>(&masked)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or(serde_json::Value::String(masked)),
+                None => serde_json::Value::Null,
+            };
+        }
+        serde_json::Value::Array(arr) => {
+            for v in arr {
+                mask_json_leaf(v, strategy, options, ctx)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for v in map.values_mut() {
+                mask_json_leaf(v, strategy, options, ctx)?;
+            }
+        }
+        serde_json::Value::Bool(_) | serde_json::Value::Null => {}
+    }
+    Ok(())
 }
 
-fn mask_postgres_array(raw: &str, scanner: &PiiScanner) -> Option<String> {
+fn mask_postgres_array(
+    raw: &str,
+    scanner: &PiiScanner,
+    ctx: &MaskingContext,
+) -> Result<Option<String>> {
     if !raw.starts_with('{') || !raw.ends_with('}') {
-        return None;
+        return Ok(None);
     }
 
     let content = &raw[1..raw.len() - 1];
@@ -115,16 +906,19 @@ fn mask_postgres_array(raw: &str, scanner: &PiiScanner) -> Option<String> {
         // Unescape if needed (simplified)
         let clean_val = val.replace("\\\"", "\"").replace("\\\\", "\\");
 
-        if let Some(pii_type) = scanner.scan(&clean_val) {
-            let strategy = pii_type_to_strategy(pii_type);
-
-            let mut hasher = DefaultHasher::new();
-            clean_val.hash(&mut hasher);
-            let seed = hasher.finish();
-
-            let fake = generate_fake_data(strategy, seed);
-            // Always quote masked values to be safe
-            new_elements.push(format!("\"{}\"", fake));
+        // Arrays of composites hold record literals
+        if clean_val.starts_with('(')
+            && let Some(masked) = mask_postgres_composite(&clean_val, scanner, ctx)?
+        {
+            new_elements.push(quote_array_element(&masked));
+            changed = true;
+        } else if let Some(pii_type) = scanner.scan(&clean_val) {
+            let (strategy, options) = heuristic_strategy(pii_type, ctx);
+            match mask_with_strategy(strategy, options, clean_val.as_bytes(), ctx)? {
+                // Always quote masked values to be safe
+                Some(fake) => new_elements.push(quote_array_element(&fake)),
+                None => new_elements.push("NULL".to_string()),
+            }
             changed = true;
         } else {
             new_elements.push(elem);
@@ -132,17 +926,374 @@ fn mask_postgres_array(raw: &str, scanner: &PiiScanner) -> Option<String> {
     }
 
     if changed {
-        Some(format!("{{{}}}", new_elements.join(",")))
+        Ok(Some(format!("{{{}}}", new_elements.join(","))))
     } else {
-        None
+        Ok(None)
+    }
+}
+
+/// Quote an array element, escaping quotes and backslashes
+fn quote_array_element(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+/// Parse a composite (record) literal in Postgres output format, such as
+/// `(John,"Doe, Jr.",)`. Fields are `None` when NULL. Returns `None` when
+/// `raw` isn't a well-formed literal.
+fn parse_postgres_composite(raw: &str) -> Option<Vec<Option<String>>> {
+    let content = raw.strip_prefix('(')?.strip_suffix(')')?;
+    let mut fields = Vec::new();
+    let mut chars = content.chars().peekable();
+    loop {
+        let mut field = String::new();
+        // An empty field is NULL; an empty string is written as ""
+        let mut present = false;
+        let mut in_quotes = false;
+        let last = loop {
+            match chars.next() {
+                None if in_quotes => return None,
+                None => break true,
+                Some(',') if !in_quotes => break false,
+                Some('\\') => field.push(chars.next()?),
+                Some('"') if in_quotes && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                Some('"') => in_quotes = !in_quotes,
+                Some(c) => field.push(c),
+            }
+            present = true;
+        };
+        fields.push(present.then_some(field));
+        if last {
+            return Some(fields);
+        }
+    }
+}
+
+/// Write fields back as a composite literal, quoting them as Postgres does
+fn format_postgres_composite(fields: &[Option<String>]) -> String {
+    let mut out = String::from("(");
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let Some(field) = field else {
+            continue;
+        };
+        let needs_quotes = field.is_empty()
+            || field
+                .chars()
+                .any(|c| matches!(c, '(' | ')' | ',' | '"' | '\\') || c.is_whitespace());
+        if !needs_quotes {
+            out.push_str(field);
+            continue;
+        }
+        out.push('"');
+        for c in field.chars() {
+            // Quotes and backslashes are doubled
+            if c == '"' || c == '\\' {
+                out.push(c);
+            }
+            out.push(c);
+        }
+        out.push('"');
+    }
+    out.push(')');
+    out
+}
+
+/// Scan the fields of a composite literal for PII, masking fields that hold
+/// it and nested arrays and composites. Returns the re-serialized literal
+/// if anything was masked.
+fn mask_postgres_composite(
+    raw: &str,
+    scanner: &PiiScanner,
+    ctx: &MaskingContext,
+) -> Result<Option<String>> {
+    let Some(mut fields) = parse_postgres_composite(raw) else {
+        return Ok(None);
+    };
+
+    let mut changed = false;
+    for field in &mut fields {
+        let Some(value) = field else {
+            continue;
+        };
+        let masked = if value.starts_with('(') && value.ends_with(')') {
+            mask_postgres_composite(value, scanner, ctx)?.map(Some)
+        } else if value.starts_with('{') && value.ends_with('}') {
+            mask_postgres_array(value, scanner, ctx)?.map(Some)
+        } else if let Some(pii_type) = scanner.scan(value) {
+            let (strategy, options) = heuristic_strategy(pii_type, ctx);
+            Some(mask_with_strategy(
+                strategy,
+                options,
+                value.as_bytes(),
+                ctx,
+            )?)
+        } else {
+            None
+        };
+        if let Some(masked) = masked {
+            *field = masked;
+            changed = true;
+        }
+    }
+
+    Ok(changed.then(|| format_postgres_composite(&fields)))
+}
+
+/// Replace each span of `text` holding PII, for
+/// `heuristic_masking.scan_substrings`. Returns the masked text, or `None`
+/// when a span's strategy turns the value NULL, with the strategies used;
+/// `None` when no PII was found.
+fn mask_embedded_pii(
+    text: &str,
+    scanner: &PiiScanner,
+    ctx: &MaskingContext,
+) -> Result<Option<(Option<String>, Vec<MaskStrategy>)>> {
+    let hits = scanner.scan_all(text);
+    if hits.is_empty() {
+        return Ok(None);
+    }
+
+    let mut masked = String::with_capacity(text.len());
+    let mut strategies = Vec::with_capacity(hits.len());
+    let mut copied = 0;
+    for (range, pii_type) in hits {
+        let (strategy, options) = heuristic_strategy(pii_type, ctx);
+        strategies.push(strategy);
+        let Some(fake) =
+            mask_with_strategy(strategy, options, text[range.clone()].as_bytes(), ctx)?
+        else {
+            return Ok(Some((None, strategies)));
+        };
+        masked.push_str(&text[copied..range.start]);
+        masked.push_str(&fake);
+        copied = range.end;
+    }
+    masked.push_str(&text[copied..]);
+    Ok(Some((Some(masked), strategies)))
+}
+
+/// What the proxy would do to a value of a column
+#[derive(Debug, Serialize)]
+pub struct MaskingPreview {
+    /// The rule the column matches, if any
+    pub rule: Option<MaskingRule>,
+    /// The strategy the value is masked with; `None` leaves it as it is
+    pub strategy: Option<MaskStrategy>,
+    /// The value sent to clients; `None` for NULL
+    pub masked: Option<String>,
+}
+
+/// Mask `value` as a text value of `column` in `table` (`name` or
+/// `schema.name`), with the default rules. Without a table only rules that
+/// name no table apply. Heuristics don't look into arrays or records.
+pub fn preview_masking(
+    config: &AppConfig,
+    state: &AppState,
+    table: Option<&str>,
+    column: &str,
+    value: &str,
+) -> Result<MaskingPreview> {
+    let unchanged = |rule: Option<MaskingRule>, strategy| MaskingPreview {
+        rule,
+        strategy,
+        masked: Some(value.to_string()),
+    };
+    let table = table.map(|table| match table.split_once('.') {
+        Some((schema, name)) => TableName::new(schema, name),
+        None => TableName::new("", table),
+    });
+    let rule = select_rule(config.rules_for(None), |rule| {
+        let table_match = match (&rule.table, &table) {
+            (None, _) => true,
+            (Some(t), Some(table)) => table.matches(t),
+            (Some(_), None) => false,
+        };
+        table_match && rule.column.matches(column)
+    })
+    .cloned();
+    if !config.masking_enabled_for(None) {
+        return Ok(unchanged(rule, None));
+    }
+    let ctx = MaskingContext::for_state(config, state);
+
+    if let Some(rule) = rule.as_ref().filter(|_| ctx.mode.uses_rules()) {
+        let strategy = rule.strategy;
+        let masked = match strategy {
+            MaskStrategy::Exclude => return Ok(unchanged(Some(rule.clone()), Some(strategy))),
+            _ if !rule.json_paths.is_empty() => mask_json_paths(
+                value.as_bytes(),
+                &rule.json_paths,
+                strategy,
+                rule.options.as_ref(),
+                &ctx.scanner,
+                &ctx,
+            )?
+            .or_else(|| Some(value.to_string())),
+            MaskStrategy::Json => match serde_json::from_str::<serde_json::Value>(value) {
+                Ok(mut json_val) => {
+                    mask_json_recursively(&mut json_val, &ctx.scanner, &ctx)?;
+                    Some(serde_json::to_string(&json_val)?)
+                }
+                Err(_) => {
+                    mask_with_strategy(strategy, rule.options.as_ref(), value.as_bytes(), &ctx)?
+                }
+            },
+            _ => mask_with_strategy(strategy, rule.options.as_ref(), value.as_bytes(), &ctx)?,
+        };
+        return Ok(MaskingPreview {
+            rule: Some(rule.clone()),
+            strategy: Some(strategy),
+            masked,
+        });
+    }
+    if rule
+        .as_ref()
+        .is_some_and(|rule| rule.strategy == MaskStrategy::Exclude)
+        || !ctx.mode.uses_heuristics()
+    {
+        return Ok(unchanged(rule, None));
+    }
+
+    let trimmed = value.trim();
+    if ((trimmed.starts_with('{') && trimmed.ends_with('}'))
+        || (trimmed.starts_with('[') && trimmed.ends_with(']')))
+        && let Ok(mut json_val) = serde_json::from_str::<serde_json::Value>(value)
+    {
+        mask_json_recursively(&mut json_val, &ctx.scanner, &ctx)?;
+        let masked = serde_json::to_string(&json_val)?;
+        if masked == value {
+            return Ok(unchanged(rule, None));
+        }
+        return Ok(MaskingPreview {
+            rule,
+            strategy: Some(MaskStrategy::Json),
+            masked: Some(masked),
+        });
+    }
+    if let Some(pii_type) = ctx.scanner.scan(value) {
+        let (strategy, options) = heuristic_strategy(pii_type, &ctx);
+        return Ok(MaskingPreview {
+            masked: mask_with_strategy(strategy, options, value.as_bytes(), &ctx)?,
+            rule,
+            strategy: Some(strategy),
+        });
+    }
+    if ctx.heuristic.scan_substrings
+        && let Some((masked, strategies)) = mask_embedded_pii(value, &ctx.scanner, &ctx)?
+    {
+        // Several strategies may have been used; report the first
+        return Ok(MaskingPreview {
+            rule,
+            strategy: strategies.first().copied(),
+            masked,
+        });
     }
+    Ok(unchanged(rule, None))
 }
 
 use crate::state::{AppState, LogEntry};
+use bytes::BytesMut;
 use chrono::Utc;
+use serde::Serialize;
 use serde_json::json;
 use tracing::instrument;
 
+/// Sent instead of a value that failed to be masked, with `fail_closed`
+const FAIL_CLOSED_REPLACEMENT: &str = "[REDACTED]";
+
+/// Record the values masked in a row, and clear them, with one update of
+/// the shared stats rather than one per value
+async fn record_row_masking(
+    state: &AppState,
+    connection_id: usize,
+    masked: &mut Vec<(usize, &'static str)>,
+    column_tables: &[Option<String>],
+    column_names: &[String],
+) {
+    if masked.is_empty() {
+        return;
+    }
+    let values: Vec<_> = masked
+        .iter()
+        .map(|&(i, strategy)| {
+            (
+                column_tables.get(i).and_then(Option::as_deref),
+                column_names.get(i).map_or("?", String::as_str),
+                strategy,
+            )
+        })
+        .collect();
+    state.record_maskings(&values).await;
+    state
+        .record_connection_masked(connection_id, masked.len() as u64)
+        .await;
+    masked.clear();
+}
+
+/// Deal with a value whose masking failed: forward it as it is or redact it,
+/// as `masking.on_error` says. Either way the row, and the connection, carry on.
+async fn on_masking_error(
+    state: &AppState,
+    connection_id: usize,
+    column_idx: usize,
+    column_name: Option<&str>,
+    val: &mut BytesMut,
+    ctx: &MaskingContext,
+    error: &anyhow::Error,
+) {
+    crate::metrics::record_masking_error();
+    let on_error = ctx.masking.on_error;
+    tracing::warn!(
+        connection_id,
+        column_idx,
+        ?on_error,
+        "Masking failed: {:#}",
+        error
+    );
+    let outcome = match on_error {
+        OnMaskingError::FailOpen => "original value forwarded",
+        OnMaskingError::FailClosed => {
+            val.clear();
+            val.extend_from_slice(FAIL_CLOSED_REPLACEMENT.as_bytes());
+            "value redacted"
+        }
+    };
+
+    state
+        .add_log(LogEntry {
+            id: format!("{:x}", rand::random::<u128>()),
+            timestamp: Utc::now(),
+            connection_id,
+            event_type: "MaskingError".to_string(),
+            content: format!("Masking failed for column {}, {}", column_idx, outcome),
+            details: Some(json!({
+                "column_idx": column_idx,
+                "column_name": column_name,
+                "error": format!("{:#}", error),
+                "on_error": on_error,
+            })),
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        })
+        .await;
+}
+
 pub trait PacketInterceptor {
     fn on_row_description(
         &mut self,
@@ -156,189 +1307,832 @@ pub trait PacketInterceptor {
 
 pub struct Anonymizer {
     state: AppState,
-    scanner: PiiScanner,
-    target_cols: Vec<(usize, String)>,
+    scanner: Arc<PiiScanner>,
+    /// Explicit rule of each column of the current result set
+    target_cols: Vec<Option<ColumnRule>>,
     connection_id: usize,
+    /// Format codes announced by the last RowDescription
+    column_formats: Vec<i16>,
+    /// Type OIDs announced by the last RowDescription
+    column_types: Vec<u32>,
+    /// Column names announced by the last RowDescription
+    column_names: Vec<String>,
+    /// `schema.table` of each column, where it has been resolved
+    column_tables: Vec<Option<String>>,
+    /// Result formats of Binds (and Sync markers) not yet acknowledged upstream
+    pending_binds: VecDeque<PendingBind>,
+    /// Result formats of the portal currently returning rows, if any
+    active_result_formats: Option<Vec<i16>>,
+    /// Resolves `table_oid`s so table-scoped rules can be enforced
+    tables: TableResolver,
+    /// Masking policy of the connection, resolved from its startup message
+    policy: Option<String>,
+    /// Settings of the current result set
+    snapshot: Option<MaskingSnapshot>,
+    /// Columns and strategies of the values masked in the current row
+    masked: std::sync::Mutex<Vec<(usize, &'static str)>>,
+}
+
+/// Inspects client data sent during `COPY ... FROM STDIN`.
+///
+/// Lives on the client -> upstream path; the upstream side reports
+/// CopyInResponse and ReadyForQuery messages to it.
+pub struct CopyInGuard {
+    state: AppState,
+    scanner: Arc<PiiScanner>,
+    connection_id: usize,
+    /// Format of the last `COPY ... FROM STDIN` statement sent by the client
+    copy_format: Option<CopyFormat>,
+    /// In-progress COPY FROM STDIN inspection
+    copy_in: Option<CopyInState>,
+}
+
+enum CopyInState {
+    Inspecting {
+        inspector: CopyInInspector,
+        policy: CopyInPolicy,
+    },
+    /// The COPY was aborted; drop the rest of the client's data stream
+    Blocked,
+}
+
+/// What to do with a client COPY message or local infile data packet
+#[derive(Debug, PartialEq, Eq)]
+pub enum CopyVerdict {
+    Forward,
+    Drop,
+    /// Replace the message with a CopyFail (or, for MySQL, an ERR to the
+    /// client) carrying this reason
+    Abort(String),
+}
+
+/// Inspects the file a MySQL client streams for `LOAD DATA LOCAL INFILE`.
+///
+/// The protocol has no way to cancel the transfer, so a blocked load swallows
+/// the rest of the file and is then aborted by dropping the upstream
+/// connection, which makes the server abandon the statement.
+pub struct LocalInfileGuard {
+    state: AppState,
+    scanner: Arc<PiiScanner>,
+    connection_id: usize,
+    /// Format of the last `LOAD DATA LOCAL INFILE` statement sent by the client
+    format: Option<CopyFormat>,
+    /// In-progress file inspection
+    infile: Option<CopyInState>,
+    /// Why a blocked load is rejected, reported once the file has been read
+    blocked_reason: Option<String>,
+}
+
+/// Client messages that the upstream acknowledges in order
+#[derive(Debug)]
+enum PendingBind {
+    Bind(Vec<i16>),
+    Sync,
+}
+
+/// How many leading bytes to skip so a binary-format value can be masked as text.
+/// Returns `None` for types whose binary encoding isn't text (ints, timestamps, ...),
+/// which must be forwarded untouched.
+fn binary_text_offset(type_oid: u32) -> Option<usize> {
+    match type_oid {
+        type_oid::TEXT
+        | type_oid::VARCHAR
+        | type_oid::BPCHAR
+        | type_oid::NAME
+        | type_oid::UNKNOWN
+        | type_oid::JSON => Some(0),
+        // jsonb binary format is a 1-byte version header followed by the JSON text
+        type_oid::JSONB => Some(1),
+        // bytea binary format is the raw bytes
+        type_oid::BYTEA => Some(0),
+        _ => None,
+    }
+}
+
+/// Decode a text-format bytea value in the hex format (`\x4869`).
+/// Returns `None` for the legacy escape format or malformed input.
+fn decode_bytea_hex(val: &[u8]) -> Option<BytesMut> {
+    let hex = val.strip_prefix(b"\\x")?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let nibble = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    hex.chunks_exact(2)
+        .map(|pair| Some(nibble(pair[0])? << 4 | nibble(pair[1])?))
+        .collect()
+}
+
+/// Encode bytes as a text-format bytea value in the hex format
+fn encode_bytea_hex(bytes: &[u8]) -> BytesMut {
+    let mut out = BytesMut::with_capacity(2 + bytes.len() * 2);
+    out.extend_from_slice(b"\\x");
+    for b in bytes {
+        out.extend_from_slice(format!("{:02x}", b).as_bytes());
+    }
+    out
 }
 
 impl Anonymizer {
-    pub fn new(state: AppState, connection_id: usize) -> Self {
+    /// Anonymizer of a connection forwarded to `upstream_host`, where table
+    /// OIDs are resolved
+    pub fn new(
+        state: AppState,
+        connection_id: usize,
+        upstream_host: String,
+        upstream_port: u16,
+    ) -> Self {
+        let tables = TableResolver::new(upstream_host, upstream_port);
         Self {
+            scanner: state.scanner(),
             state,
-            scanner: PiiScanner::new(),
             target_cols: Vec::new(),
             connection_id,
+            tables,
+            column_formats: Vec::new(),
+            column_types: Vec::new(),
+            column_names: Vec::new(),
+            column_tables: Vec::new(),
+            pending_binds: VecDeque::new(),
+            active_result_formats: None,
+            policy: None,
+            snapshot: None,
+            masked: std::sync::Mutex::new(Vec::new()),
         }
     }
-}
 
-impl PacketInterceptor for Anonymizer {
-    #[instrument(skip(self, msg), fields(num_fields = msg.fields.len()))]
-    async fn on_row_description(&mut self, msg: &RowDescription) {
-        self.target_cols.clear();
+    /// Record the database the client connected to, for table OID lookups
+    pub fn set_database(&mut self, database: String) {
+        self.tables.set_database(database);
+    }
 
-        let config = self.state.config.read().await;
-        for (i, field) in msg.fields.iter().enumerate() {
-            for rule in &config.rules {
-                // Check if rule applies to this column
-                let table_match = rule.table.as_ref().is_none_or(|_t| {
-                    // TODO: In a real app, we'd need to resolve table OID to name.
-                    // For now, we assume the rule matches if table is None (global)
-                    // or if we could somehow know the table name (which we don't easily from RowDescription alone without a cache).
-                    // So for MVP, we'll ignore table name matching in RowDescription and just match on column name.
-                    // A proper implementation would query pg_class to map OID -> Name.
-                    true
-                });
-
-                // Convert Bytes field name to str for comparison
-                let field_name = std::str::from_utf8(&field.name).unwrap_or("");
-                if table_match && rule.column == field_name {
-                    self.target_cols.push((i, rule.strategy.clone()));
-                    break; // Apply first matching rule
+    /// Apply the named masking policy instead of the global settings
+    pub fn set_policy(&mut self, policy: Option<String>) {
+        self.policy = policy;
+        self.snapshot = None;
+    }
+
+    /// Track the result formats requested by a client Bind
+    pub fn on_bind(&mut self, msg: &BindMessage) {
+        self.pending_binds
+            .push_back(PendingBind::Bind(msg.result_formats.clone()));
+    }
+
+    /// Track a client Sync, which ends an extended-query batch
+    pub fn on_sync(&mut self) {
+        self.pending_binds.push_back(PendingBind::Sync);
+    }
+
+    /// Track backend status messages that affect which result formats are active
+    pub fn on_backend_message(&mut self, message_type: u8) {
+        match message_type {
+            // BindComplete: the oldest pending Bind is now the active portal
+            b'2' => {
+                if let Some(PendingBind::Bind(formats)) = self.pending_binds.pop_front() {
+                    self.active_result_formats = Some(formats);
+                }
+            }
+            // ErrorResponse: the server skips remaining Binds until the next Sync
+            b'E' => {
+                while let Some(PendingBind::Bind(_)) = self.pending_binds.front() {
+                    self.pending_binds.pop_front();
                 }
             }
+            _ => {}
         }
     }
 
-    #[instrument(skip(self, msg), fields(num_values = msg.values.len(), connection_id = self.connection_id))]
-    async fn on_data_row(&mut self, mut msg: DataRow) -> Result<DataRow> {
-        // Check if masking is globally enabled
+    /// CommandComplete ends a result set; its columns must not carry over to the next one
+    pub fn on_command_complete(&mut self) {
+        self.snapshot = None;
+        self.target_cols.clear();
+        self.column_formats.clear();
+        self.column_types.clear();
+        self.column_names.clear();
+        self.column_tables.clear();
+    }
+
+    /// ReadyForQuery: the query or extended-query batch is done
+    pub fn on_ready_for_query(&mut self) {
+        self.on_command_complete();
+        if let Some(PendingBind::Sync) = self.pending_binds.front() {
+            self.pending_binds.pop_front();
+        }
+        self.active_result_formats = None;
+    }
+
+    /// Resolve the wire format of a result column
+    fn column_format(&self, idx: usize) -> i16 {
+        match &self.active_result_formats {
+            Some(formats) => match formats.len() {
+                0 => FORMAT_TEXT,
+                1 => formats[0],
+                _ => formats.get(idx).copied().unwrap_or(FORMAT_TEXT),
+            },
+            None => self.column_formats.get(idx).copied().unwrap_or(FORMAT_TEXT),
+        }
+    }
+
+    /// Count a value masked in column `i`; recorded once the row is done
+    fn record_masking(&self, i: usize, strategy: &'static str) {
+        self.masked.lock().unwrap().push((i, strategy));
+    }
+
+    /// `mask_value`, applying `masking.on_error` when masking fails
+    async fn mask_value_or_recover(
+        &self,
+        i: usize,
+        val: &mut BytesMut,
+        ctx: &MaskingContext,
+        changes_log: &mut Vec<serde_json::Value>,
+    ) -> bool {
+        match self.mask_value(i, val, ctx, changes_log).await {
+            Ok(nullify) => nullify,
+            Err(e) => {
+                let column = self.column_names.get(i).map(String::as_str);
+                on_masking_error(&self.state, self.connection_id, i, column, val, ctx, &e).await;
+                false
+            }
+        }
+    }
+
+    /// Mask a text-format bytea value through its decoded bytes, writing
+    /// the result back in the hex format. Values in the escape format
+    /// (`bytea_output = escape`) are forwarded untouched.
+    async fn mask_bytea_hex(
+        &self,
+        i: usize,
+        val: &mut BytesMut,
+        ctx: &MaskingContext,
+        changes_log: &mut Vec<serde_json::Value>,
+    ) -> bool {
+        let Some(mut bytes) = decode_bytea_hex(val) else {
+            return false;
+        };
+        let original = bytes.clone();
+        let nullify = self
+            .mask_value_or_recover(i, &mut bytes, ctx, changes_log)
+            .await;
+        if bytes != original {
+            *val = encode_bytea_hex(&bytes);
+        }
+        nullify
+    }
+
+    /// Mask a single text value in place, recording any change in
+    /// `changes_log`. Returns whether the value is to become NULL instead.
+    async fn mask_value(
+        &self,
+        i: usize,
+        val: &mut BytesMut,
+        ctx: &MaskingContext,
+        changes_log: &mut Vec<serde_json::Value>,
+    ) -> Result<bool> {
+        let original_val_preview = if val.len() > 50 {
+            format!("{}...", String::from_utf8_lossy(&val[..50]))
+        } else {
+            String::from_utf8_lossy(val).to_string()
+        };
+
+        // 1. Check for explicit rule
+        let explicit = self.target_cols.get(i).and_then(Option::as_ref);
+        let explicit_strategy = explicit.map(|rule| rule.strategy);
+
+        // Excluded columns skip the heuristics as well
+        if explicit_strategy == Some(MaskStrategy::Exclude) {
+            return Ok(false);
+        }
+        let explicit = explicit.filter(|_| ctx.mode.uses_rules());
+        let explicit_strategy = explicit_strategy.filter(|_| ctx.mode.uses_rules());
+
+        // Rules with json_paths only touch the values at those paths
+        if let Some(rule) = explicit
+            && !rule.json_paths.is_empty()
         {
-            let config = self.state.config.read().await;
-            if !config.masking_enabled {
-                return Ok(msg);
+            if let Some(masked) = mask_json_paths(
+                val,
+                &rule.json_paths,
+                rule.strategy,
+                rule.options.as_ref(),
+                &self.scanner,
+                ctx,
+            )? {
+                val.clear();
+                val.extend_from_slice(masked.as_bytes());
+                self.record_masking(i, rule.strategy.as_str());
+                changes_log.push(json!({
+                    "column_idx": i,
+                    "strategy": rule.strategy,
+                    "original": original_val_preview,
+                    "masked": "(JSON paths masked)"
+                }));
             }
+            return Ok(false);
         }
 
-        let mut changes_log = Vec::new();
-        let mut changed_any = false;
+        // Handle explicit JSON strategy
+        if let Some(MaskStrategy::Json) = explicit_strategy
+            && let Ok(s) = std::str::from_utf8(val)
+            && let Ok(mut json_val) = serde_json::from_str::<serde_json::Value>(s)
+        {
+            mask_json_recursively(&mut json_val, &self.scanner, ctx)?;
+            let new_json = serde_json::to_string(&json_val)?;
 
-        for (i, val_opt) in msg.values.iter_mut().enumerate() {
-            if let Some(val) = val_opt {
-                let original_val_preview = if val.len() > 50 {
-                    format!("{}...", String::from_utf8_lossy(&val[..50]))
-                } else {
-                    String::from_utf8_lossy(val).to_string()
-                };
+            if new_json.as_bytes() != &val[..] {
+                val.clear();
+                val.extend_from_slice(new_json.as_bytes());
+                // Record masking stats for JSON
+                self.record_masking(i, "json");
+                changes_log.push(json!({
+                    "column_idx": i,
+                    "strategy": "json",
+                    "original": original_val_preview,
+                    "masked": "(JSON Masked)"
+                }));
+            }
+            return Ok(false);
+        }
 
-                // 1. Check for explicit rule
-                let explicit_strategy = self
-                    .target_cols
-                    .iter()
-                    .find(|(col_idx, _)| *col_idx == i)
-                    .map(|(_, strategy)| strategy.as_str());
-
-                // Handle explicit JSON strategy
-                if let Some("json") = explicit_strategy
-                    && let Ok(s) = std::str::from_utf8(val)
-                    && let Ok(mut json_val) = serde_json::from_str::<serde_json::Value>(s)
+        let strategy = if let Some(rule) = explicit {
+            Some((rule.strategy, rule.options.as_ref()))
+        } else if !ctx.mode.uses_heuristics() {
+            None
+        } else {
+            // 2. Heuristic scan
+            if let Ok(s) = std::str::from_utf8(val) {
+                // Composite-type columns and ROW() values hold record literals
+                let trimmed = s.trim();
+                if trimmed.starts_with('(')
+                    && trimmed.ends_with(')')
+                    && let Some(masked_record) = mask_postgres_composite(s, &self.scanner, ctx)?
                 {
-                    mask_json_recursively(&mut json_val, &self.scanner);
-                    let new_json = serde_json::to_string(&json_val)?;
-
-                    if new_json.as_bytes() != &val[..] {
-                        val.clear();
-                        val.extend_from_slice(new_json.as_bytes());
-                        changed_any = true;
-                        // Record masking stats for JSON
-                        self.state.record_masking("json").await;
-                        changes_log.push(json!({
-                            "column_idx": i,
-                            "strategy": "json",
-                            "original": original_val_preview,
-                            "masked": "(JSON Masked)"
-                        }));
-                    }
-                    continue;
+                    val.clear();
+                    val.extend_from_slice(masked_record.as_bytes());
+                    self.record_masking(i, "other");
+                    changes_log.push(json!({
+                        "column_idx": i,
+                        "strategy": "composite (heuristic)",
+                        "original": original_val_preview,
+                        "masked": masked_record
+                    }));
+                    return Ok(false);
                 }
 
-                let strategy = if let Some(s) = explicit_strategy {
-                    Some(s)
-                } else {
-                    // 2. Heuristic scan
-                    if let Ok(s) = std::str::from_utf8(val) {
-                        // Try JSON heuristic first if it looks like JSON
-                        let trimmed = s.trim();
-                        if (trimmed.starts_with('{') && trimmed.ends_with('}'))
-                            || (trimmed.starts_with('[') && trimmed.ends_with(']'))
-                        {
-                            // Attempt JSON parsing
-                            match serde_json::from_str::<serde_json::Value>(s) {
-                                Ok(mut json_val) => {
-                                    mask_json_recursively(&mut json_val, &self.scanner);
-                                    if let Ok(new_json) = serde_json::to_string(&json_val) {
-                                        if new_json.as_bytes() != &val[..] {
-                                            val.clear();
-                                            val.extend_from_slice(new_json.as_bytes());
-                                            changed_any = true;
-                                            // Record masking stats for heuristic JSON
-                                            self.state.record_masking("json").await;
-                                            changes_log.push(json!({
-                                                "column_idx": i,
-                                                "strategy": "json (heuristic)",
-                                                "original": original_val_preview,
-                                                "masked": "(JSON Masked)"
-                                            }));
-                                        }
-                                        continue;
-                                    }
-                                }
-                                Err(_) => {
-                                    // Not valid JSON, maybe Postgres Array?
-                                    if trimmed.starts_with('{')
-                                        && trimmed.ends_with('}')
-                                        && let Some(masked_array) =
-                                            mask_postgres_array(s, &self.scanner)
-                                    {
-                                        val.clear();
-                                        val.extend_from_slice(masked_array.as_bytes());
-                                        changed_any = true;
-                                        // Record masking stats for array (count as other)
-                                        self.state.record_masking("other").await;
-                                        changes_log.push(json!({
-                                            "column_idx": i,
-                                            "strategy": "array (heuristic)",
-                                            "original": original_val_preview,
-                                            "masked": masked_array
-                                        }));
-                                        continue;
-                                    }
+                // Try JSON heuristic first if it looks like JSON
+                if (trimmed.starts_with('{') && trimmed.ends_with('}'))
+                    || (trimmed.starts_with('[') && trimmed.ends_with(']'))
+                {
+                    // Attempt JSON parsing
+                    match serde_json::from_str::<serde_json::Value>(s) {
+                        Ok(mut json_val) => {
+                            mask_json_recursively(&mut json_val, &self.scanner, ctx)?;
+                            if let Ok(new_json) = serde_json::to_string(&json_val) {
+                                if new_json.as_bytes() != &val[..] {
+                                    val.clear();
+                                    val.extend_from_slice(new_json.as_bytes());
+                                    // Record masking stats for heuristic JSON
+                                    self.record_masking(i, "json");
+                                    changes_log.push(json!({
+                                        "column_idx": i,
+                                        "strategy": "json (heuristic)",
+                                        "original": original_val_preview,
+                                        "masked": "(JSON Masked)"
+                                    }));
                                 }
+                                return Ok(false);
+                            }
+                        }
+                        Err(_) => {
+                            // Not valid JSON, maybe Postgres Array?
+                            if trimmed.starts_with('{')
+                                && trimmed.ends_with('}')
+                                && let Some(masked_array) =
+                                    mask_postgres_array(s, &self.scanner, ctx)?
+                            {
+                                val.clear();
+                                val.extend_from_slice(masked_array.as_bytes());
+                                // Record masking stats for array (count as other)
+                                self.record_masking(i, "other");
+                                changes_log.push(json!({
+                                    "column_idx": i,
+                                    "strategy": "array (heuristic)",
+                                    "original": original_val_preview,
+                                    "masked": masked_array
+                                }));
+                                return Ok(false);
                             }
                         }
+                    }
+                }
 
-                        self.scanner.scan(s).map(pii_type_to_strategy)
-                    } else {
-                        None
+                let pii_type = self.scanner.scan(s);
+                if pii_type.is_none()
+                    && ctx.heuristic.scan_substrings
+                    && let Some((masked, strategies)) = mask_embedded_pii(s, &self.scanner, ctx)?
+                {
+                    if let Some(masked) = &masked {
+                        val.clear();
+                        val.extend_from_slice(masked.as_bytes());
+                    }
+                    for strategy in strategies {
+                        self.record_masking(i, strategy.as_str());
                     }
+                    changes_log.push(json!({
+                        "column_idx": i,
+                        "strategy": "embedded (heuristic)",
+                        "original": original_val_preview,
+                        "masked": masked
+                    }));
+                    return Ok(masked.is_none());
+                }
+                pii_type.map(|pii_type| heuristic_strategy(pii_type, ctx))
+            } else {
+                None
+            }
+        };
+
+        let Some((strat, options)) = strategy else {
+            return Ok(false);
+        };
+        let fake_val = mask_with_strategy(strat, options, val, ctx)?;
+        if let Some(fake_val) = &fake_val {
+            val.clear();
+            val.extend_from_slice(fake_val.as_bytes());
+        }
+
+        // Record masking stats
+        self.record_masking(i, strat.as_str());
+
+        changes_log.push(json!({
+            "column_idx": i,
+            "strategy": strat,
+            "original": original_val_preview,
+            "masked": fake_val
+        }));
+
+        Ok(fake_val.is_none())
+    }
+}
+
+impl CopyInGuard {
+    pub fn new(state: AppState, connection_id: usize) -> Self {
+        Self {
+            scanner: state.scanner(),
+            state,
+            connection_id,
+            copy_format: None,
+            copy_in: None,
+        }
+    }
+
+    /// Remember the format of a `COPY ... FROM STDIN` statement for the CopyInResponse
+    pub fn on_query(&mut self, query: &str) {
+        self.copy_format = CopyFormat::from_query(query);
+    }
+
+    /// Start inspecting client data after a CopyInResponse, if the policy asks for it
+    pub async fn on_copy_in_response(&mut self, msg: &RawMessage) {
+        let policy = self.state.config.read().await.copy_in_policy;
+        self.scanner = self.state.scanner();
+        let format = self.copy_format.take().unwrap_or_default();
+        // Overall format 1 is binary COPY, which can't be scanned as text
+        if policy == CopyInPolicy::Allow || msg.payload().first() != Some(&0) {
+            self.copy_in = None;
+            return;
+        }
+        self.copy_in = Some(CopyInState::Inspecting {
+            inspector: CopyInInspector::new(format),
+            policy,
+        });
+    }
+
+    /// Inspect a client CopyData, CopyDone or CopyFail message
+    pub async fn on_copy_message(&mut self, msg: &RawMessage) -> CopyVerdict {
+        let done = matches!(msg.message_type, b'c' | b'f');
+        let (report, policy) = match &mut self.copy_in {
+            None => return CopyVerdict::Forward,
+            Some(CopyInState::Blocked) => {
+                if done {
+                    self.copy_in = None;
+                }
+                return CopyVerdict::Drop;
+            }
+            Some(CopyInState::Inspecting { inspector, policy }) => match msg.message_type {
+                b'd' => (inspector.feed(msg.payload(), &self.scanner), *policy),
+                b'c' => (inspector.finish(&self.scanner), *policy),
+                _ => (CopyReport::default(), *policy),
+            },
+        };
+        if done {
+            self.copy_in = None;
+        }
+
+        let blocked = report_bulk_load(
+            &self.state,
+            self.connection_id,
+            &report,
+            policy,
+            "COPY FROM STDIN",
+            ("CopyPiiDetected", "CopyInBlocked"),
+        )
+        .await;
+        if !blocked {
+            return CopyVerdict::Forward;
+        }
+        if !done {
+            self.copy_in = Some(CopyInState::Blocked);
+        }
+        let (row, pii_type) = &report.flagged[0];
+        CopyVerdict::Abort(format!(
+            "IronVeil: COPY rejected, PII ({:?}) detected in row {}",
+            pii_type, row
+        ))
+    }
+
+    /// ReadyForQuery: the server may end a COPY early (e.g. on a constraint error)
+    pub fn on_ready_for_query(&mut self) {
+        if let Some(CopyInState::Inspecting { .. }) = self.copy_in {
+            self.copy_in = None;
+        }
+    }
+}
+
+impl LocalInfileGuard {
+    pub fn new(state: AppState, connection_id: usize) -> Self {
+        Self {
+            scanner: state.scanner(),
+            state,
+            connection_id,
+            format: None,
+            infile: None,
+            blocked_reason: None,
+        }
+    }
+
+    /// Remember the format of a `LOAD DATA LOCAL INFILE` statement for the file request
+    pub fn on_query(&mut self, query: &str) {
+        self.format = CopyFormat::from_load_data(query);
+    }
+
+    /// Start inspecting the file after a local infile request, if the policy asks for it
+    pub async fn on_request(&mut self) {
+        let policy = self.state.config.read().await.local_infile_policy;
+        self.scanner = self.state.scanner();
+        let format = self.format.take().unwrap_or_default();
+        self.blocked_reason = None;
+        self.infile = (policy != CopyInPolicy::Allow).then(|| CopyInState::Inspecting {
+            inspector: CopyInInspector::new(format),
+            policy,
+        });
+    }
+
+    /// Inspect a chunk of the file; an empty chunk ends it.
+    ///
+    /// A blocked load yields `Drop` for the rest of the file and `Abort` for
+    /// its end, when the client is ready to read an error.
+    pub async fn on_data(&mut self, data: &[u8]) -> CopyVerdict {
+        let done = data.is_empty();
+        let (report, policy) = match &mut self.infile {
+            None => return CopyVerdict::Forward,
+            Some(CopyInState::Blocked) => {
+                if !done {
+                    return CopyVerdict::Drop;
+                }
+                self.infile = None;
+                return CopyVerdict::Abort(self.blocked_reason.take().unwrap_or_default());
+            }
+            Some(CopyInState::Inspecting { inspector, policy }) => {
+                let report = if done {
+                    inspector.finish(&self.scanner)
+                } else {
+                    inspector.feed(data, &self.scanner)
                 };
+                (report, *policy)
+            }
+        };
+        if done {
+            self.infile = None;
+        }
+
+        let blocked = report_bulk_load(
+            &self.state,
+            self.connection_id,
+            &report,
+            policy,
+            "LOAD DATA LOCAL INFILE",
+            ("LocalInfilePiiDetected", "LocalInfileBlocked"),
+        )
+        .await;
+        if !blocked {
+            return CopyVerdict::Forward;
+        }
 
-                if let Some(strat) = strategy {
-                    // Apply masking
-                    let mut hasher = DefaultHasher::new();
-                    val.hash(&mut hasher);
-                    let seed = hasher.finish();
+        let (row, pii_type) = &report.flagged[0];
+        let reason = format!(
+            "IronVeil: LOAD DATA rejected, PII ({:?}) detected in row {}",
+            pii_type, row
+        );
+        if done {
+            return CopyVerdict::Abort(reason);
+        }
+        self.infile = Some(CopyInState::Blocked);
+        self.blocked_reason = Some(reason);
+        CopyVerdict::Drop
+    }
+}
 
-                    let fake_val = generate_fake_data(strat, seed);
+/// Record the rows inspected in a chunk of bulk-load data and log any PII
+/// found; returns whether the policy blocks the load
+async fn report_bulk_load(
+    state: &AppState,
+    connection_id: usize,
+    report: &CopyReport,
+    policy: CopyInPolicy,
+    source: &str,
+    (detected_event, blocked_event): (&str, &str),
+) -> bool {
+    if report.rows > 0 {
+        state
+            .record_copy_in(report.rows, report.flagged.len() as u64)
+            .await;
+    }
+    if report.flagged.is_empty() {
+        return false;
+    }
 
-                    val.clear();
-                    val.extend_from_slice(fake_val.as_bytes());
-                    changed_any = true;
+    let rows: Vec<serde_json::Value> = report
+        .flagged
+        .iter()
+        .take(20)
+        .map(|(row, pii_type)| json!({ "row": row, "pii_type": format!("{:?}", pii_type) }))
+        .collect();
+    let blocked = policy == CopyInPolicy::Block;
+    let id = format!("{:x}", rand::random::<u128>());
+    state
+        .add_log(LogEntry {
+            id,
+            timestamp: Utc::now(),
+            connection_id,
+            event_type: if blocked {
+                blocked_event
+            } else {
+                detected_event
+            }
+            .to_string(),
+            content: format!(
+                "Detected PII in {} rows of {}",
+                report.flagged.len(),
+                source
+            ),
+            details: Some(json!(rows)),
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        })
+        .await;
+    blocked
+}
+
+impl PacketInterceptor for Anonymizer {
+    #[instrument(skip(self, msg), fields(num_fields = msg.fields.len()))]
+    async fn on_row_description(&mut self, msg: &RowDescription) {
+        self.target_cols.clear();
+        self.column_formats = msg.fields.iter().map(|f| f.format_code).collect();
+        self.column_types = msg.fields.iter().map(|f| f.type_oid).collect();
+        self.column_names = msg
+            .fields
+            .iter()
+            .map(|f| String::from_utf8_lossy(&f.name).into_owned())
+            .collect();
 
-                    // Record masking stats
-                    self.state.record_masking(strat).await;
+        // Only look up table names when a table-scoped rule could apply
+        let (needs_lookup, lookup_config, tls) = {
+            let config = self.state.config.read().await;
+            let needs_lookup = msg.fields.iter().any(|field| {
+                field.table_oid != 0
+                    && config.rules_for(self.policy.as_deref()).iter().any(|rule| {
+                        rule.table.is_some()
+                            && std::str::from_utf8(&field.name)
+                                .is_ok_and(|name| rule.column.matches(name))
+                    })
+            });
+            // The side connection follows the upstream TLS settings
+            let tls =
+                (needs_lookup && config.catalog_lookup.is_some() && self.tables.needs_connection())
+                    .then(|| crate::tls::postgres_connector(&config));
+            (needs_lookup, config.catalog_lookup.clone(), tls)
+        };
+        let tables = if needs_lookup {
+            let oids: Vec<u32> = msg.fields.iter().map(|f| f.table_oid).collect();
+            self.tables
+                .resolve(&oids, lookup_config.as_ref(), tls)
+                .await
+        } else {
+            HashMap::new()
+        };
+        self.column_tables = msg
+            .fields
+            .iter()
+            .map(|f| {
+                tables
+                    .get(&f.table_oid)
+                    .map(|t| format!("{}.{}", t.schema, t.name))
+            })
+            .collect();
 
-                    changes_log.push(json!({
-                        "column_idx": i,
-                        "strategy": strat,
-                        "original": original_val_preview,
-                        "masked": fake_val
-                    }));
+        let config = self.state.config.read().await;
+        self.snapshot = Some(MaskingSnapshot::take(
+            &config,
+            &self.state,
+            self.policy.as_deref(),
+        ));
+        for field in &msg.fields {
+            // Convert Bytes field name to str for comparison
+            let field_name = std::str::from_utf8(&field.name).unwrap_or("");
+            let rule = select_rule(config.rules_for(self.policy.as_deref()), |rule| {
+                // If the table can't be resolved we err on the side of
+                // masking: masking rules apply, exclusions don't
+                let table_match = match (&rule.table, tables.get(&field.table_oid)) {
+                    (None, _) => true,
+                    (Some(t), Some(table)) => table.matches(t),
+                    (Some(_), None) => rule.strategy != MaskStrategy::Exclude,
+                };
+                table_match && rule.column.matches(field_name)
+            });
+            self.target_cols.push(rule.map(ColumnRule::from));
+        }
+    }
+
+    #[instrument(skip(self, msg), fields(num_values = msg.values.len(), connection_id = self.connection_id))]
+    async fn on_data_row(&mut self, mut msg: DataRow) -> Result<DataRow> {
+        let snapshot =
+            MaskingSnapshot::current(&mut self.snapshot, &self.state, self.policy.as_deref()).await;
+        self.scanner = snapshot.ctx.scanner.clone();
+        if !snapshot.enabled {
+            return Ok(msg);
+        }
+        let ctx = snapshot.ctx.clone();
+        // Nothing to scan for in rules_only mode
+        if !ctx.mode.uses_heuristics() && self.target_cols.iter().all(Option::is_none) {
+            return Ok(msg);
+        }
+
+        let mut changes_log = Vec::new();
+
+        for (i, val_opt) in msg.values.iter_mut().enumerate() {
+            let Some(val) = val_opt else {
+                continue;
+            };
+            let type_oid = self.column_types.get(i).copied().unwrap_or(0);
+            let is_bytea = type_oid == type_oid::BYTEA;
+            if is_bytea && ctx.masking.bytea_mode == ByteaMode::Skip {
+                continue;
+            }
+            // Without a rule, values of types that can't hold PII aren't scanned
+            if self.target_cols.get(i).is_none_or(Option::is_none)
+                && !ctx.heuristic.scans_postgres_type(type_oid)
+            {
+                continue;
+            }
+            let nullify = if self.column_format(i) != FORMAT_BINARY {
+                if is_bytea {
+                    self.mask_bytea_hex(i, val, &ctx, &mut changes_log).await
+                } else {
+                    self.mask_value_or_recover(i, val, &ctx, &mut changes_log)
+                        .await
+                }
+            } else {
+                // Binary values can only be masked when their encoding is text-like;
+                // anything else is forwarded untouched rather than corrupted.
+                let Some(offset) = binary_text_offset(type_oid) else {
+                    continue;
+                };
+                if val.len() < offset {
+                    continue;
                 }
+
+                let mut text = val.split_off(offset);
+                let nullify = self
+                    .mask_value_or_recover(i, &mut text, &ctx, &mut changes_log)
+                    .await;
+                val.unsplit(text);
+                nullify
+            };
+            if nullify {
+                *val_opt = None;
             }
         }
 
-        if changed_any {
+        record_row_masking(
+            &self.state,
+            self.connection_id,
+            self.masked.get_mut().unwrap(),
+            &self.column_tables,
+            &self.column_names,
+        )
+        .await;
+
+        if !changes_log.is_empty() {
             // Log the change
             let id = format!("{:x}", rand::random::<u128>());
             self.state
@@ -349,6 +2143,10 @@ impl PacketInterceptor for Anonymizer {
                     event_type: "DataMasked".to_string(),
                     content: format!("Masked {} fields in DataRow", changes_log.len()),
                     details: Some(json!(changes_log)),
+                    client_cn: None,
+                    user: None,
+                    database: None,
+                    application_name: None,
                 })
                 .await;
         }
@@ -363,157 +2161,280 @@ impl PacketInterceptor for Anonymizer {
 
 /// Trait for intercepting MySQL packets
 pub trait MySqlPacketInterceptor {
+    fn on_result_set_header(&mut self, header: &ResultSetHeader);
     fn on_column_definition(
         &mut self,
         col: &ColumnDefinition,
     ) -> impl std::future::Future<Output = ()> + Send;
+    fn on_field_list_column(
+        &mut self,
+        col: ColumnDefinition,
+    ) -> impl std::future::Future<Output = Result<ColumnDefinition>> + Send;
     fn on_result_row(
         &mut self,
         row: ResultRow,
     ) -> impl std::future::Future<Output = Result<ResultRow>> + Send;
+    fn on_binary_result_row(
+        &mut self,
+        row: BinaryResultRow,
+    ) -> impl std::future::Future<Output = Result<BinaryResultRow>> + Send;
 }
 
+/// Most columns a result set header may reserve space for up front
+const MAX_PRESIZED_COLUMNS: usize = 4096;
+
 /// MySQL-specific anonymizer that reuses the core masking logic
 pub struct MySqlAnonymizer {
     state: AppState,
-    scanner: PiiScanner,
-    target_cols: Vec<(usize, String)>,
+    scanner: Arc<PiiScanner>,
+    /// Explicit rule of each column of the current result set
+    target_cols: Vec<Option<ColumnRule>>,
     column_names: Vec<String>,
+    /// `MYSQL_TYPE_*` of each column of the current result set
+    column_types: Vec<u8>,
+    /// `schema.table` each column of the current result set was selected from
+    column_tables: Vec<Option<String>>,
     connection_id: usize,
+    /// Default database of the session, for columns without a schema
+    database: Option<String>,
+    /// Masking policy of the connection, resolved from its handshake
+    policy: Option<String>,
+    /// Settings of the current result set
+    snapshot: Option<MaskingSnapshot>,
+    /// Columns and strategies of the values masked in the current row
+    masked: std::sync::Mutex<Vec<(usize, &'static str)>>,
 }
 
 impl MySqlAnonymizer {
     pub fn new(state: AppState, connection_id: usize) -> Self {
         Self {
+            scanner: state.scanner(),
             state,
-            scanner: PiiScanner::new(),
             target_cols: Vec::new(),
             column_names: Vec::new(),
+            column_types: Vec::new(),
+            column_tables: Vec::new(),
             connection_id,
+            database: None,
+            policy: None,
+            snapshot: None,
+            masked: std::sync::Mutex::new(Vec::new()),
         }
     }
 
+    /// Record the session's default database, after the handshake or a
+    /// COM_INIT_DB / COM_CHANGE_USER
+    pub fn set_database(&mut self, database: Option<String>) {
+        self.database = database;
+    }
+
+    /// Apply the named masking policy instead of the global settings
+    pub fn set_policy(&mut self, policy: Option<String>) {
+        self.policy = policy;
+        self.snapshot = None;
+    }
+
     /// Reset column tracking for a new result set
     pub fn reset_columns(&mut self) {
         self.target_cols.clear();
         self.column_names.clear();
+        self.column_types.clear();
+        self.column_tables.clear();
+        self.snapshot = None;
     }
-}
 
-impl MySqlPacketInterceptor for MySqlAnonymizer {
-    #[instrument(skip(self, col), fields(column_name = %String::from_utf8_lossy(&col.name)))]
-    async fn on_column_definition(&mut self, col: &ColumnDefinition) {
-        let col_name = String::from_utf8_lossy(&col.name).to_string();
-        let col_idx = self.column_names.len();
-        self.column_names.push(col_name.clone());
+    /// Count a value masked in column `i`; recorded once the row is done
+    fn record_masking(&self, i: usize, strategy: &'static str) {
+        self.masked.lock().unwrap().push((i, strategy));
+    }
 
-        let config = self.state.config.read().await;
-        for rule in &config.rules {
-            // Table match (MySQL provides table name in column def)
-            let table_name = String::from_utf8_lossy(&col.table);
-            let table_match = rule.table.as_ref().is_none_or(|t| t == &*table_name);
-
-            if table_match && rule.column == col_name {
-                self.target_cols.push((col_idx, rule.strategy.clone()));
-                tracing::debug!(column = %col_name, strategy = %rule.strategy, "MySQL column matched rule");
-                break;
-            }
+    /// Mask a single value in place, recording any change in `changes_log`.
+    /// Returns whether the value is to become NULL instead.
+    async fn mask_value(
+        &self,
+        i: usize,
+        val: &mut BytesMut,
+        ctx: &MaskingContext,
+        changes_log: &mut Vec<serde_json::Value>,
+    ) -> Result<bool> {
+        let original_val_preview = if val.len() > 50 {
+            format!("{}...", String::from_utf8_lossy(&val[..50]))
+        } else {
+            String::from_utf8_lossy(val).to_string()
+        };
+
+        // Check for explicit rule
+        let explicit = self.target_cols.get(i).and_then(Option::as_ref);
+        let explicit_strategy = explicit.map(|rule| rule.strategy);
+
+        // Excluded columns skip the heuristics as well
+        if explicit_strategy == Some(MaskStrategy::Exclude) {
+            return Ok(false);
         }
-    }
+        let explicit = explicit.filter(|_| ctx.mode.uses_rules());
+        let explicit_strategy = explicit_strategy.filter(|_| ctx.mode.uses_rules());
 
-    #[instrument(skip(self, row), fields(num_values = row.values.len(), connection_id = self.connection_id))]
-    async fn on_result_row(&mut self, mut row: ResultRow) -> Result<ResultRow> {
-        // Check if masking is globally enabled
+        // Rules with json_paths only touch the values at those paths
+        if let Some(rule) = explicit
+            && !rule.json_paths.is_empty()
         {
-            let config = self.state.config.read().await;
-            if !config.masking_enabled {
-                return Ok(row);
+            if let Some(masked) = mask_json_paths(
+                val,
+                &rule.json_paths,
+                rule.strategy,
+                rule.options.as_ref(),
+                &self.scanner,
+                ctx,
+            )? {
+                val.clear();
+                val.extend_from_slice(masked.as_bytes());
+                self.record_masking(i, rule.strategy.as_str());
+                changes_log.push(json!({
+                    "column_idx": i,
+                    "column_name": self.column_names.get(i).unwrap_or(&"?".to_string()),
+                    "strategy": rule.strategy,
+                    "original": original_val_preview,
+                    "masked": "(JSON paths masked)"
+                }));
             }
+            return Ok(false);
         }
 
-        let mut changes_log = Vec::new();
-        let mut changed_any = false;
-
-        for (i, val_opt) in row.values.iter_mut().enumerate() {
-            if let Some(val) = val_opt {
-                let original_val_preview = if val.len() > 50 {
-                    format!("{}...", String::from_utf8_lossy(&val[..50]))
-                } else {
-                    String::from_utf8_lossy(val).to_string()
-                };
+        // Handle explicit JSON strategy
+        if let Some(MaskStrategy::Json) = explicit_strategy
+            && let Ok(s) = std::str::from_utf8(val)
+            && let Ok(mut json_val) = serde_json::from_str::<serde_json::Value>(s)
+        {
+            mask_json_recursively(&mut json_val, &self.scanner, ctx)?;
+            if let Ok(new_json) = serde_json::to_string(&json_val)
+                && new_json.as_bytes() != &val[..]
+            {
+                val.clear();
+                val.extend_from_slice(new_json.as_bytes());
+                // Record masking stats for JSON
+                self.record_masking(i, "json");
+                changes_log.push(json!({
+                    "column_idx": i,
+                    "column_name": self.column_names.get(i).unwrap_or(&"?".to_string()),
+                    "strategy": "json",
+                    "original": original_val_preview,
+                    "masked": "(JSON Masked)"
+                }));
+            }
+            return Ok(false);
+        }
 
-                // Check for explicit rule
-                let explicit_strategy = self
-                    .target_cols
-                    .iter()
-                    .find(|(col_idx, _)| *col_idx == i)
-                    .map(|(_, strategy)| strategy.as_str());
-
-                // Handle explicit JSON strategy
-                if let Some("json") = explicit_strategy
-                    && let Ok(s) = std::str::from_utf8(val)
-                    && let Ok(mut json_val) = serde_json::from_str::<serde_json::Value>(s)
+        let strategy = if let Some(rule) = explicit {
+            Some((rule.strategy, rule.options.as_ref()))
+        } else if !ctx.mode.uses_heuristics() {
+            None
+        } else {
+            // Heuristic scan
+            if let Ok(s) = std::str::from_utf8(val) {
+                let pii_type = self.scanner.scan(s);
+                if pii_type.is_none()
+                    && ctx.heuristic.scan_substrings
+                    && let Some((masked, strategies)) = mask_embedded_pii(s, &self.scanner, ctx)?
                 {
-                    mask_json_recursively(&mut json_val, &self.scanner);
-                    if let Ok(new_json) = serde_json::to_string(&json_val)
-                        && new_json.as_bytes() != &val[..]
-                    {
+                    if let Some(masked) = &masked {
                         val.clear();
-                        val.extend_from_slice(new_json.as_bytes());
-                        changed_any = true;
-                        // Record masking stats for JSON
-                        self.state.record_masking("json").await;
-                        changes_log.push(json!({
-                            "column_idx": i,
-                            "column_name": self.column_names.get(i).unwrap_or(&"?".to_string()),
-                            "strategy": "json",
-                            "original": original_val_preview,
-                            "masked": "(JSON Masked)"
-                        }));
+                        val.extend_from_slice(masked.as_bytes());
                     }
-                    continue;
-                }
-
-                let strategy = if let Some(s) = explicit_strategy {
-                    Some(s)
-                } else {
-                    // Heuristic scan
-                    if let Ok(s) = std::str::from_utf8(val) {
-                        self.scanner.scan(s).map(pii_type_to_strategy)
-                    } else {
-                        None
+                    for strategy in strategies {
+                        self.record_masking(i, strategy.as_str());
                     }
-                };
-
-                if let Some(strat) = strategy {
-                    use std::collections::hash_map::DefaultHasher;
-                    use std::hash::{Hash, Hasher};
-
-                    let mut hasher = DefaultHasher::new();
-                    val.hash(&mut hasher);
-                    let seed = hasher.finish();
-
-                    let fake_val = generate_fake_data(strat, seed);
-
-                    val.clear();
-                    val.extend_from_slice(fake_val.as_bytes());
-                    changed_any = true;
-
-                    // Record masking stats
-                    self.state.record_masking(strat).await;
-
                     changes_log.push(json!({
                         "column_idx": i,
                         "column_name": self.column_names.get(i).unwrap_or(&"?".to_string()),
-                        "strategy": strat,
+                        "strategy": "embedded (heuristic)",
                         "original": original_val_preview,
-                        "masked": fake_val
+                        "masked": masked
                     }));
+                    return Ok(masked.is_none());
+                }
+                pii_type.map(|pii_type| heuristic_strategy(pii_type, ctx))
+            } else {
+                None
+            }
+        };
+
+        let Some((strat, options)) = strategy else {
+            return Ok(false);
+        };
+        let fake_val = mask_with_strategy(strat, options, val, ctx)?;
+        if let Some(fake_val) = &fake_val {
+            val.clear();
+            val.extend_from_slice(fake_val.as_bytes());
+        }
+
+        // Record masking stats
+        self.record_masking(i, strat.as_str());
+
+        changes_log.push(json!({
+            "column_idx": i,
+            "column_name": self.column_names.get(i).unwrap_or(&"?".to_string()),
+            "strategy": strat,
+            "original": original_val_preview,
+            "masked": fake_val
+        }));
+
+        Ok(fake_val.is_none())
+    }
+
+    /// Mask the non-NULL values of a row in place, given with their column
+    /// index. Returns the columns whose values are to become NULL instead.
+    async fn mask_values(
+        &mut self,
+        values: Vec<(usize, &mut BytesMut)>,
+        row_kind: &str,
+    ) -> Vec<usize> {
+        let snapshot =
+            MaskingSnapshot::current(&mut self.snapshot, &self.state, self.policy.as_deref()).await;
+        self.scanner = snapshot.ctx.scanner.clone();
+        if !snapshot.enabled {
+            return Vec::new();
+        }
+        let ctx = snapshot.ctx.clone();
+        // Nothing to scan for in rules_only mode
+        if !ctx.mode.uses_heuristics() && self.target_cols.iter().all(Option::is_none) {
+            return Vec::new();
+        }
+
+        let mut changes_log = Vec::new();
+        let mut nullified = Vec::new();
+
+        for (i, val) in values {
+            // Without a rule, values of types that can't hold PII aren't scanned
+            if self.target_cols.get(i).is_none_or(Option::is_none)
+                && !self
+                    .column_types
+                    .get(i)
+                    .is_none_or(|t| ctx.heuristic.scans_mysql_type(*t))
+            {
+                continue;
+            }
+
+            match self.mask_value(i, val, &ctx, &mut changes_log).await {
+                Ok(true) => nullified.push(i),
+                Ok(false) => {}
+                Err(e) => {
+                    let column = self.column_names.get(i).map(String::as_str);
+                    on_masking_error(&self.state, self.connection_id, i, column, val, &ctx, &e)
+                        .await
                 }
             }
         }
 
-        if changed_any {
+        record_row_masking(
+            &self.state,
+            self.connection_id,
+            self.masked.get_mut().unwrap(),
+            &self.column_tables,
+            &self.column_names,
+        )
+        .await;
+
+        if !changes_log.is_empty() {
             let id = format!("{:x}", rand::random::<u128>());
             self.state
                 .add_log(LogEntry {
@@ -521,12 +2442,130 @@ impl MySqlPacketInterceptor for MySqlAnonymizer {
                     timestamp: Utc::now(),
                     connection_id: self.connection_id,
                     event_type: "MySqlDataMasked".to_string(),
-                    content: format!("Masked {} fields in MySQL ResultRow", changes_log.len()),
+                    content: format!("Masked {} fields in MySQL {}", changes_log.len(), row_kind),
                     details: Some(json!(changes_log)),
+                    client_cn: None,
+                    user: None,
+                    database: None,
+                    application_name: None,
                 })
                 .await;
         }
 
+        nullified
+    }
+}
+
+impl MySqlPacketInterceptor for MySqlAnonymizer {
+    fn on_result_set_header(&mut self, header: &ResultSetHeader) {
+        self.reset_columns();
+        // Bounded, as the count comes straight off the wire
+        let columns = header.column_count.min(MAX_PRESIZED_COLUMNS as u64) as usize;
+        self.column_names.reserve(columns);
+        self.column_types.reserve(columns);
+        self.column_tables.reserve(columns);
+    }
+
+    #[instrument(skip(self, col), fields(column_name = %String::from_utf8_lossy(&col.name)))]
+    async fn on_column_definition(&mut self, col: &ColumnDefinition) {
+        let col_name = String::from_utf8_lossy(&col.name).to_string();
+        self.column_names.push(col_name.clone());
+        self.column_types.push(col.column_type);
+
+        // MySQL provides the table name in the column definition
+        let schema = if col.schema.is_empty() {
+            self.database.clone().unwrap_or_default()
+        } else {
+            String::from_utf8_lossy(&col.schema).into_owned()
+        };
+        self.column_tables
+            .push(match (schema.as_str(), &col.org_table[..]) {
+                (_, []) => None,
+                ("", table) => Some(String::from_utf8_lossy(table).into_owned()),
+                (schema, table) => Some(format!("{}.{}", schema, String::from_utf8_lossy(table))),
+            });
+        let table_column = |table: &[u8], name: &[u8]| {
+            (
+                TableName::new(schema.clone(), String::from_utf8_lossy(table)),
+                String::from_utf8_lossy(name).into_owned(),
+            )
+        };
+
+        let config = self.state.config.read().await;
+        // Computed columns have no original name
+        let mut candidates = Vec::with_capacity(2);
+        if !col.org_name.is_empty() {
+            candidates.push(table_column(&col.org_table, &col.org_name));
+        }
+        if config.mysql_column_match == ColumnMatch::OriginalOrAlias {
+            candidates.push(table_column(&col.table, &col.name));
+        }
+
+        // One entry per column, matched or not, so it lines up with the row
+        let mut matched = None;
+        for (table, column) in &candidates {
+            let rule = select_rule(config.rules_for(self.policy.as_deref()), |rule| {
+                rule.column.matches(column) && rule.table.as_ref().is_none_or(|t| table.matches(t))
+            });
+            if let Some(rule) = rule {
+                tracing::debug!(column = %col_name, original = %column, strategy = %rule.strategy, "MySQL column matched rule");
+                matched = Some(ColumnRule::from(rule));
+                break;
+            }
+        }
+        self.target_cols.push(matched);
+    }
+
+    #[instrument(skip(self, col), fields(column_name = %String::from_utf8_lossy(&col.name)))]
+    async fn on_field_list_column(
+        &mut self,
+        mut col: ColumnDefinition,
+    ) -> Result<ColumnDefinition> {
+        // Each column of a COM_FIELD_LIST response is masked on its own, its
+        // default value standing in for a one-column row
+        self.reset_columns();
+        self.on_column_definition(&col).await;
+        if let Some(Some(default)) = col.default_value.as_mut()
+            && !self
+                .mask_values(vec![(0, default)], "FieldList")
+                .await
+                .is_empty()
+        {
+            col.default_value = Some(None);
+        }
+        self.reset_columns();
+        Ok(col)
+    }
+
+    #[instrument(skip(self, row), fields(num_values = row.values.len(), connection_id = self.connection_id))]
+    async fn on_result_row(&mut self, mut row: ResultRow) -> Result<ResultRow> {
+        let values = row
+            .values
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, val)| val.as_mut().map(|val| (i, val)))
+            .collect();
+        for i in self.mask_values(values, "ResultRow").await {
+            row.values[i] = None;
+        }
+        Ok(row)
+    }
+
+    #[instrument(skip(self, row), fields(num_values = row.values.len(), connection_id = self.connection_id))]
+    async fn on_binary_result_row(&mut self, mut row: BinaryResultRow) -> Result<BinaryResultRow> {
+        // Numeric and temporal values are left as they are
+        let values = row
+            .values
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, val)| match val {
+                Some(BinaryValue::String(val)) => Some((i, val)),
+                _ => None,
+            })
+            .collect();
+        for i in self.mask_values(values, "BinaryResultRow").await {
+            row.values[i] = None;
+        }
         Ok(row)
     }
 }
@@ -537,23 +2576,17 @@ mod tests {
     use crate::config::{AppConfig, MaskingRule};
     use crate::protocol::postgres::{FieldDescription, RowDescription};
     use crate::state::AppState;
-    use bytes::BytesMut;
+    use bytes::BufMut;
 
     #[tokio::test]
     async fn test_heuristic_detection() {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
 
         // Create a DataRow with an email
         let email = "test@example.com";
@@ -582,20 +2615,17 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![MaskingRule {
+                id: Default::default(),
                 table: None,
-                column: "email_col".to_string(),
-                strategy: "address".to_string(), // Intentionally wrong strategy to prove override
+                column: "email_col".parse().unwrap(),
+                strategy: MaskStrategy::Address, // Intentionally wrong strategy to prove override
+                options: None,
+                json_paths: vec![],
             }],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
 
         let desc = RowDescription {
             fields: vec![FieldDescription {
@@ -626,21 +2656,507 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mask_partial() {
+        let card = MaskingOptions::default();
+        assert_eq!(
+            mask_partial("4111-1111-1111-9012", &card),
+            "****-****-****-9012"
+        );
+
+        let email = MaskingOptions {
+            keep_prefix: 1,
+            keep_suffix: 11,
+            ..Default::default()
+        };
+        assert_eq!(mask_partial("john@example.com", &email), "j***@example.com");
+
+        // Counted in characters, never split inside a multi-byte one
+        let options = MaskingOptions {
+            keep_prefix: 1,
+            keep_suffix: 1,
+            mask_char: '•',
+            ..Default::default()
+        };
+        assert_eq!(mask_partial("Zoë Ångström", &options), "Z•• •••••••m");
+        assert_eq!(mask_partial("東京都新宿区", &options), "東••••区");
+        assert_eq!(mask_partial("a😀b😀c", &options), "a😀•😀c");
+
+        // Too short to keep anything
+        assert_eq!(mask_partial("Bob", &MaskingOptions::default()), "***");
+    }
+
+    #[test]
+    fn test_format_preserving_credit_card() {
+        let digits = |s: &str| -> Vec<u8> {
+            s.bytes()
+                .filter(u8::is_ascii_digit)
+                .map(|b| b - b'0')
+                .collect()
+        };
+        let same_format = |a: &str, b: &str| {
+            a.len() == b.len()
+                && a.chars().zip(b.chars()).all(|(x, y)| {
+                    x.is_ascii_digit() == y.is_ascii_digit() && (x.is_ascii_digit() || x == y)
+                })
+        };
+
+        let ctx = MaskingContext::new(&AppConfig::default());
+        for card in [
+            "4111 1111 1111 1111",
+            "5500-0000-0000-0004",
+            "378282246310005",
+            "6011000990139424",
+        ] {
+            let masked = mask_with_strategy(
+                MaskStrategy::CreditCardPreserve,
+                None,
+                card.as_bytes(),
+                &ctx,
+            )
+            .unwrap()
+            .unwrap();
+            assert!(same_format(card, &masked), "{card} -> {masked}");
+            assert!(luhn_valid(&digits(&masked)), "{masked} fails Luhn");
+            assert_ne!(masked, card);
+            // Deterministic for the same input
+            assert_eq!(
+                Some(masked),
+                mask_with_strategy(
+                    MaskStrategy::CreditCardPreserve,
+                    None,
+                    card.as_bytes(),
+                    &ctx
+                )
+                .unwrap()
+            );
+        }
+
+        let options = MaskingOptions {
+            keep_last_four: true,
+            keep_bin: true,
+            ..Default::default()
+        };
+        let card = "4111-1111-1111-9012";
+        let masked = preserve_credit_card(card, 7, &options);
+        assert!(same_format(card, &masked));
+        assert!(luhn_valid(&digits(&masked)));
+        assert!(masked.starts_with("4111-11"));
+        assert!(masked.ends_with("-9012"));
+    }
+
+    #[test]
+    fn test_format_preserving_phone() {
+        let options = MaskingOptions::default();
+        let masked = preserve_phone("+44 20 7946 0958", 3, &options);
+        assert!(masked.starts_with("+44 "));
+        assert_eq!(masked.len(), "+44 20 7946 0958".len());
+        assert_eq!(
+            masked
+                .chars()
+                .filter(|c| !c.is_ascii_digit())
+                .collect::<String>(),
+            "+   "
+        );
+
+        let options = MaskingOptions {
+            keep_last_four: true,
+            ..Default::default()
+        };
+        let masked = preserve_phone("(555) 123-4567", 3, &options);
+        assert!(masked.starts_with('(') && masked.ends_with("-4567"));
+        assert_eq!(&masked[4..6], ") ");
+    }
+
+    #[test]
+    fn test_fit_length() {
+        assert_eq!(fit_length("Zoë Ölund".to_string(), 4, 1), "Zoë");
+        // Cuts never split a character
+        let cut = fit_length("Zoë".to_string(), 3, 1);
+        assert_eq!(cut.len(), 3);
+        assert!(cut.starts_with("Zo"));
+        let padded = fit_length("Bo".to_string(), 6, 1);
+        assert_eq!(padded.len(), 6);
+        assert!(padded.starts_with("Bo"));
+        assert!(padded.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(padded, fit_length("Bo".to_string(), 6, 1));
+        assert_eq!(fit_length("Bob".to_string(), 0, 1), "");
+    }
+
+    #[tokio::test]
+    async fn test_preserve_length() {
+        let preserve = |on| MaskingOptions {
+            preserve_length: Some(on),
+            ..Default::default()
+        };
+        let ctx = MaskingContext::new(&AppConfig::default());
+        let mask = |strategy, options: Option<&MaskingOptions>, value: &str, ctx| {
+            mask_with_strategy(strategy, options, value.as_bytes(), ctx)
+                .unwrap()
+                .unwrap()
+        };
+
+        // Multibyte originals keep their byte length
+        for value in ["Renée Müller", "Zoë", "李小龍", "a", "ab", ""] {
+            let masked = mask(MaskStrategy::FullName, Some(&preserve(true)), value, &ctx);
+            assert_eq!(masked.len(), value.len(), "{} -> {}", value, masked);
+        }
+        let masked = mask(MaskStrategy::Hash, Some(&preserve(true)), "al@x.io", &ctx);
+        assert_eq!(masked.len(), 7);
+        assert_ne!(
+            mask(MaskStrategy::FullName, None, "ab", &ctx).len(),
+            2,
+            "off by default"
+        );
+
+        // masking.preserve_length is the default for rules and heuristic hits
+        let mut config = AppConfig::default();
+        config.masking.preserve_length = true;
+        let ctx = MaskingContext::new(&config);
+        assert_eq!(mask(MaskStrategy::FullName, None, "ab", &ctx).len(), 2);
+        assert_ne!(
+            mask(MaskStrategy::FullName, Some(&preserve(false)), "ab", &ctx).len(),
+            2
+        );
+        // Tokens stay whole
+        assert_ne!(mask(MaskStrategy::Tokenize, None, "ab", &ctx).len(), 2);
+
+        // Padding follows the rule's seed salt
+        let redact = |salt: &str| MaskingOptions {
+            replacement: "x".to_string(),
+            seed_salt: Some(salt.to_string()),
+            ..Default::default()
+        };
+        let padded = |salt| {
+            mask(
+                MaskStrategy::Redact,
+                Some(&redact(salt)),
+                "secret-value",
+                &ctx,
+            )
+        };
+        assert_eq!(padded("sumac").len(), 12);
+        assert_eq!(padded("sumac"), padded("sumac"));
+        assert_ne!(padded("sumac"), padded("saffron"));
+
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![text_field(b"contact")],
+            })
+            .await;
+        let email = "jo@x.io";
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from(email))],
+            })
+            .await
+            .unwrap();
+        let masked = row.values[0].as_ref().unwrap();
+        assert_ne!(&masked[..], email.as_bytes());
+        assert_eq!(masked.len(), email.len());
+    }
+
+    #[test]
+    fn test_email_preserve_domain() {
+        let mut config = AppConfig::default();
+        let ctx = MaskingContext::new(&config);
+        let masked = mask_with_strategy(
+            MaskStrategy::EmailPreserveDomain,
+            None,
+            b"jane.doe@acme.io",
+            &ctx,
+        )
+        .unwrap()
+        .unwrap();
+        let (local, domain) = masked.split_once('@').unwrap();
+        assert_eq!(domain, "acme.io");
+        assert!(!local.is_empty());
+        assert_ne!(local, "jane.doe");
+        assert_eq!(
+            Some(masked.clone()),
+            mask_with_strategy(
+                MaskStrategy::EmailPreserveDomain,
+                None,
+                b"jane.doe@acme.io",
+                &ctx
+            )
+            .unwrap()
+        );
+
+        config.masking.pseudonymize_email_domains = true;
+        config.masking.internal_email_domains = vec!["acme.io".to_string()];
+        let ctx = MaskingContext::new(&config);
+        let mask = |v: &str| preserve_email_domain(v, 1, &ctx.seed_key, &ctx.masking);
+
+        assert!(mask("jane@acme.io").ends_with("@acme.io"));
+        assert!(mask("jane@Mail.ACME.io").ends_with("@Mail.ACME.io"));
+        let external = mask("jane@gmail.com");
+        assert!(external.ends_with(".example"));
+        assert!(!external.contains("gmail"));
+        // One pseudonym per domain
+        let domain = |v: String| v.split_once('@').unwrap().1.to_string();
+        assert_eq!(domain(external), domain(mask("bob@GMAIL.com")));
+        assert_ne!(domain(mask("bob@gmail.com")), domain(mask("bob@yahoo.com")));
+    }
+
+    #[tokio::test]
+    async fn test_heuristic_email_keeps_domain() {
+        let mut config = AppConfig::default();
+        config.heuristic_masking.preserve_email_domain = true;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let row = DataRow {
+            values: vec![Some(BytesMut::from(&b"alice@corp.example.org"[..]))],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        let masked = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+        assert!(masked.ends_with("@corp.example.org"));
+        assert!(!masked.starts_with("alice@"));
+    }
+
+    #[test]
+    fn test_hash_strategy_is_salted_and_deterministic() {
+        let mut config = AppConfig::default();
+        config.masking.hash_salt = Some("pepper".to_string());
+        let ctx = MaskingContext::new(&config);
+
+        let token = mask_with_strategy(MaskStrategy::Hash, None, b"alice@example.com", &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            token,
+            mask_with_strategy(MaskStrategy::Hash, None, b"alice@example.com", &ctx)
+                .unwrap()
+                .unwrap()
+        );
+        assert_ne!(
+            token,
+            mask_with_strategy(MaskStrategy::Hash, None, b"bob@example.com", &ctx)
+                .unwrap()
+                .unwrap()
+        );
+
+        // Another salt, another token
+        config.masking.hash_salt = Some("paprika".to_string());
+        let other = mask_with_strategy(
+            MaskStrategy::Hash,
+            None,
+            b"alice@example.com",
+            &MaskingContext::new(&config),
+        )
+        .unwrap()
+        .unwrap();
+        assert_ne!(token, other);
+
+        config.masking.hash_salt = Some("pepper".to_string());
+        config.masking.hash_length = Some(12);
+        let short = mask_with_strategy(
+            MaskStrategy::Hash,
+            None,
+            b"alice@example.com",
+            &MaskingContext::new(&config),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(short, token[..12]);
+    }
+
+    #[tokio::test]
+    async fn test_null_and_redact_rules() {
+        let rule = |column: &str, strategy: &str, options| MaskingRule {
+            id: Default::default(),
+            table: None,
+            column: column.parse().unwrap(),
+            strategy: strategy.parse().unwrap(),
+            options,
+            json_paths: vec![],
+        };
+        let config = AppConfig {
+            rules: vec![
+                rule("ssn", "null", None),
+                rule("notes", "redact", None),
+                rule(
+                    "iban",
+                    "redact",
+                    Some(MaskingOptions {
+                        replacement: "<iban>".to_string(),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+
+        let field = |name: &'static [u8]| FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: 0,
+            type_len: 0,
+            type_modifier: 0,
+            format_code: 0,
+        };
+        let desc = RowDescription {
+            fields: vec![field(b"ssn"), field(b"notes"), field(b"iban")],
+        };
+        anonymizer.on_row_description(&desc).await;
+
+        let row = DataRow {
+            values: vec![
+                Some(BytesMut::from(&b"123-45-6789"[..])),
+                Some(BytesMut::from(&b"call after 5"[..])),
+                Some(BytesMut::from(&b"DE89370400440532013000"[..])),
+            ],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+
+        assert!(row.values[0].is_none());
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"[REDACTED]");
+        assert_eq!(&row.values[2].as_ref().unwrap()[..], b"<iban>");
+
+        let stats = state.stats.read().await;
+        assert_eq!(stats.masking.null, 1);
+        assert_eq!(stats.masking.redact, 2);
+    }
+
+    #[tokio::test]
+    async fn test_tokenize_rule_uses_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let tokenization = crate::config::TokenizationConfig {
+            vault_path: dir.path().join("vault.log").to_string_lossy().into_owned(),
+            key: Some("vault-secret".to_string()),
+            key_env: "IRONVEIL_VAULT_KEY".to_string(),
+        };
+        let vault = TokenVault::open(&tokenization).await.unwrap();
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Tokenize,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state =
+            AppState::new_for_test(config, "proxy.yaml".to_string()).with_token_vault(vault);
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+
+        let desc = RowDescription {
+            fields: vec![FieldDescription {
+                name: bytes::Bytes::from_static(b"email"),
+                table_oid: 0,
+                column_index: 0,
+                type_oid: 0,
+                type_len: 0,
+                type_modifier: 0,
+                format_code: 0,
+            }],
+        };
+        anonymizer.on_row_description(&desc).await;
+
+        let row = DataRow {
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        let token = String::from_utf8(row.values[0].as_ref().unwrap().to_vec()).unwrap();
+
+        assert!(token.starts_with("tok_"));
+        let vault = state.token_vault.as_ref().unwrap();
+        assert_eq!(
+            vault.detokenize(&token).as_deref(),
+            Some("alice@example.com")
+        );
+        assert_eq!(state.stats.read().await.masking.tokenize, 1);
+    }
+
+    #[tokio::test]
+    async fn test_heuristic_null_maps_to_json_null() {
+        let mut config = AppConfig::default();
+        config.heuristic_masking.strategy = HeuristicStrategy::Null;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let row = DataRow {
+            values: vec![
+                Some(BytesMut::from(
+                    &br#"{"email":"alice@example.com","plan":"pro"}"#[..],
+                )),
+                Some(BytesMut::from(&b"bob@example.com"[..])),
+            ],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_slice(row.values[0].as_ref().unwrap()).unwrap();
+        assert_eq!(json, json!({"email": null, "plan": "pro"}));
+        assert!(row.values[1].is_none());
+    }
+
+    #[tokio::test]
+    async fn test_partial_rule_and_heuristic_default() {
+        let mut config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "card".parse().unwrap(),
+                strategy: MaskStrategy::Partial,
+                options: Some(MaskingOptions {
+                    mask_char: 'X',
+                    ..Default::default()
+                }),
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        config.heuristic_masking.strategy = HeuristicStrategy::Partial;
+        config.heuristic_masking.options.keep_prefix = 1;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let field = |name: &'static [u8]| FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: 0,
+            type_len: 0,
+            type_modifier: 0,
+            format_code: 0,
+        };
+        let desc = RowDescription {
+            fields: vec![field(b"card"), field(b"contact")],
+        };
+        anonymizer.on_row_description(&desc).await;
+
+        let row = DataRow {
+            values: vec![
+                Some(BytesMut::from(&b"4111 1111 1111 9012"[..])),
+                Some(BytesMut::from(&b"jane@example.com"[..])),
+            ],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], b"XXXX XXXX XXXX 9012");
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"j***@*******.com");
+    }
+
     #[tokio::test]
     async fn test_json_masking() {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
 
         let json_data = r#"
         {
@@ -649,7 +3165,7 @@ mod tests {
                 "name": "John Doe"
             },
             "payment": {
-                "cc": "4532-1234-5678-9012"
+                "cc": "4532-1234-5678-9014"
             },
             "tags": ["valid@email.com", "not-pii"]
         }
@@ -673,7 +3189,7 @@ mod tests {
         assert_ne!(email, "test@example.com");
         assert!(email.contains("@")); // Still an email
 
-        assert_ne!(cc, "4532-1234-5678-9012");
+        assert_ne!(cc, "4532-1234-5678-9014");
 
         assert_ne!(tag_email, "valid@email.com");
         assert!(tag_email.contains("@"));
@@ -686,19 +3202,13 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
 
         // Postgres array format: {val1,val2}
-        let array_data = r#"{"test@example.com","normal_val","1234-5678-9012-3456"}"#;
+        let array_data = r#"{"test@example.com","normal_val","4111-1111-1111-1111"}"#;
 
         let mut row = DataRow {
             values: vec![Some(BytesMut::from(array_data.as_bytes()))],
@@ -726,101 +3236,2148 @@ mod tests {
 
         assert_eq!(normal, "\"normal_val\""); // Should be unchanged and still quoted
 
-        assert_ne!(cc, "\"1234-5678-9012-3456\"");
+        assert_ne!(cc, "\"4111-1111-1111-1111\"");
     }
 
     #[tokio::test]
-    async fn test_deterministic_masking() {
-        let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+    async fn test_strict_credit_card_setting() {
+        let order_no = "1234-5678-9012-3456";
+        let mask = |config: AppConfig| async move {
+            let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+            let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+            let row = anonymizer
+                .on_data_row(DataRow {
+                    values: vec![Some(BytesMut::from(order_no))],
+                })
+                .await
+                .unwrap();
+            row.values[0].clone().unwrap()
         };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
 
-        let email = "test@example.com";
+        // Fails the Luhn check, so it isn't a card number
+        assert_eq!(&mask(AppConfig::default()).await[..], order_no.as_bytes());
 
-        // Process same email twice
-        let mut row1 = DataRow {
-            values: vec![Some(BytesMut::from(email.as_bytes()))],
-        };
-        let mut row2 = DataRow {
-            values: vec![Some(BytesMut::from(email.as_bytes()))],
-        };
+        let mut config = AppConfig::default();
+        config.scanner.strict_credit_card = false;
+        assert_ne!(&mask(config).await[..], order_no.as_bytes());
+    }
 
-        row1 = anonymizer.on_data_row(row1).await.unwrap();
-        row2 = anonymizer.on_data_row(row2).await.unwrap();
+    #[tokio::test]
+    async fn test_custom_scanner_pattern_masking() {
+        let mut config = AppConfig {
+            scanner: serde_yaml::from_str(
+                "custom_patterns:\n  - { name: employee_id, regex: \"EMP-\\\\d{6}\", strategy: \"null\" }",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        // The pattern's strategy applies whatever the heuristic strategy is
+        config.heuristic_masking.strategy = HeuristicStrategy::Redact;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![
+                    Some(BytesMut::from("EMP-123456")),
+                    Some(BytesMut::from("EMP-12")),
+                ],
+            })
+            .await
+            .unwrap();
+        assert_eq!(row.values[0], None);
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"EMP-12");
+        assert_eq!(state.get_stats().await.masking.null, 1);
+    }
 
-        let val1 = std::str::from_utf8(row1.values[0].as_ref().unwrap()).unwrap();
-        let val2 = std::str::from_utf8(row2.values[0].as_ref().unwrap()).unwrap();
+    #[tokio::test]
+    async fn test_row_masking_recorded_once_per_row() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state
+            .register_connection(crate::state::ConnectionInfo::new(
+                1,
+                crate::state::DbProtocol::Postgres,
+            ))
+            .await;
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        for _ in 0..2 {
+            anonymizer
+                .on_data_row(DataRow {
+                    values: vec![
+                        Some(BytesMut::from("john@acme.com")),
+                        Some(BytesMut::from("plain text")),
+                        Some(BytesMut::from("jane@acme.com")),
+                    ],
+                })
+                .await
+                .unwrap();
+        }
 
-        // Same input should produce same output (deterministic)
-        assert_eq!(val1, val2, "Same input should produce same masked output");
-        assert_ne!(val1, email, "Output should be different from input");
+        // Every masked value is counted, and the buffer is emptied per row
+        assert_eq!(state.get_stats().await.masking.email, 4);
+        assert_eq!(state.list_connections().await[0].masked_fields, 4);
+        assert!(anonymizer.masked.get_mut().unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_masking_can_be_disabled() {
-        let config = AppConfig {
-            masking_enabled: false, // Disabled
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+    async fn test_embedded_pii_masking() {
+        let note = "call john@acme.com tomorrow, or jane@acme.com if busy";
+        let mask = |config: AppConfig| async move {
+            let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+            let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+            let row = anonymizer
+                .on_data_row(DataRow {
+                    values: vec![Some(BytesMut::from(note))],
+                })
+                .await
+                .unwrap();
+            (row.values[0].clone(), state.get_stats().await.masking.email)
         };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
 
-        let email = "test@example.com";
-        let mut row = DataRow {
-            values: vec![Some(BytesMut::from(email.as_bytes()))],
-        };
+        // Off by default
+        let (masked, emails) = mask(AppConfig::default()).await;
+        assert_eq!(&masked.unwrap()[..], note.as_bytes());
+        assert_eq!(emails, 0);
 
-        row = anonymizer.on_data_row(row).await.unwrap();
-        let val = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+        let mut config = AppConfig::default();
+        config.heuristic_masking.scan_substrings = true;
+        let (masked, emails) = mask(config.clone()).await;
+        let masked = String::from_utf8(masked.unwrap().to_vec()).unwrap();
+        // Only the spans are replaced, each counted
+        assert!(masked.starts_with("call "), "{}", masked);
+        assert!(masked.ends_with(" if busy"), "{}", masked);
+        assert!(masked.contains(" tomorrow, or "), "{}", masked);
+        assert!(!masked.contains("john@acme.com") && !masked.contains("jane@acme.com"));
+        assert_eq!(masked.matches('@').count(), 2);
+        assert_eq!(emails, 2);
+
+        // Whole values are still masked as a whole
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from("john@acme.com"))],
+            })
+            .await
+            .unwrap();
+        assert_ne!(&row.values[0].as_ref().unwrap()[..], b"john@acme.com");
+
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        let row = anonymizer
+            .on_result_row(ResultRow {
+                sequence_id: 4,
+                values: vec![Some(BytesMut::from(note))],
+            })
+            .await
+            .unwrap();
+        let masked = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+        assert!(masked.starts_with("call ") && masked.ends_with(" if busy"));
+        assert!(!masked.contains("john@acme.com"));
+
+        // A null strategy can't blank part of a value, so the value goes
+        config.heuristic_masking.strategy = HeuristicStrategy::Null;
+        let (masked, _) = mask(config).await;
+        assert_eq!(masked, None);
+    }
+
+    #[test]
+    fn test_composite_literal_round_trip() {
+        let literals = [
+            r#"(John,john@x.com,555-123-4567)"#,
+            r#"("Doe, Jr.",,"")"#,
+            r#"("say ""hi""","back\\slash","(nested)")"#,
+            r#"(" padded ",x)"#,
+            r#"(,)"#,
+            r#"(1,"(2,""b c"")")"#,
+        ];
+        for literal in literals {
+            let fields = parse_postgres_composite(literal).unwrap();
+            assert_eq!(format_postgres_composite(&fields), literal);
+        }
 
-        // Should NOT be masked when disabled
         assert_eq!(
-            val, email,
-            "Data should not be masked when masking is disabled"
+            parse_postgres_composite(r#"("Doe, Jr.",,"","say ""hi""",a\,b)"#).unwrap(),
+            vec![
+                Some("Doe, Jr.".to_string()),
+                None,
+                Some(String::new()),
+                Some(r#"say "hi""#.to_string()),
+                Some("a,b".to_string()),
+            ]
         );
+        // Unbalanced quotes, dangling escapes and missing parentheses
+        assert!(parse_postgres_composite(r#"("open,x)"#).is_none());
+        assert!(parse_postgres_composite(r#"(x\)"#).is_none());
+        assert!(parse_postgres_composite("John,x").is_none());
     }
 
     #[tokio::test]
-    async fn test_null_values_handled() {
-        let config = AppConfig {
-            masking_enabled: true,
-            rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+    async fn test_composite_masking() {
+        let desc = RowDescription {
+            fields: vec![
+                FieldDescription {
+                    type_oid: type_oid::RECORD,
+                    ..text_field(b"row")
+                },
+                text_field(b"contacts"),
+            ],
         };
-        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
-        let mut anonymizer = Anonymizer::new(state, 1);
+        let record = r#"(John,john@x.com,,"a ""quoted"", value")"#;
+        let array = r#"{"(1,jane@y.org)","(2,none)"}"#;
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer.on_row_description(&desc).await;
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from(record)), Some(BytesMut::from(array))],
+            })
+            .await
+            .unwrap();
 
-        let mut row = DataRow {
-            values: vec![None, Some(BytesMut::from("data".as_bytes())), None],
+        let masked = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+        let fields = parse_postgres_composite(masked).unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].as_deref(), Some("John"));
+        let email = fields[1].as_deref().unwrap();
+        assert_ne!(email, "john@x.com");
+        assert!(email.contains('@'));
+        // NULLs and untouched fields keep their form
+        assert_eq!(fields[2], None);
+        assert_eq!(fields[3].as_deref(), Some(r#"a "quoted", value"#));
+
+        // Records inside arrays are masked, and the array stays parseable
+        let masked = std::str::from_utf8(row.values[1].as_ref().unwrap()).unwrap();
+        assert_ne!(masked, array);
+        assert!(masked.ends_with(r#""(2,none)"}"#), "{}", masked);
+        let first = masked
+            .strip_prefix("{\"")
+            .and_then(|rest| rest.split_once("\","))
+            .map(|(element, _)| element.replace("\\\"", "\"").replace("\\\\", "\\"))
+            .unwrap();
+        let fields = parse_postgres_composite(&first).unwrap();
+        assert_eq!(fields[0].as_deref(), Some("1"));
+        assert!(fields[1].as_deref().unwrap().contains('@'));
+        assert_ne!(fields[1].as_deref(), Some("jane@y.org"));
+    }
+
+    #[test]
+    fn test_iban_and_national_id_fakes_are_valid() {
+        let scanner = PiiScanner::new();
+        let ctx = MaskingContext::new(&AppConfig::default());
+        let mask = |strategy, value: &str| {
+            mask_with_strategy(strategy, None, value.as_bytes(), &ctx)
+                .unwrap()
+                .unwrap()
         };
 
-        row = anonymizer.on_data_row(row).await.unwrap();
+        for iban in ["GB82 WEST 1234 5698 7654 32", "FR1420041010050500013M02606"] {
+            let fake = mask(MaskStrategy::Iban, iban);
+            assert_ne!(fake, iban);
+            assert_eq!(scanner.scan(&fake), Some(PiiType::Iban), "{}", fake);
+            // Country, length and grouping are kept
+            assert_eq!(&fake[..2], &iban[..2]);
+            assert_eq!(fake.len(), iban.len());
+            assert_eq!(fake.contains(' '), iban.contains(' '));
+        }
+        // Values that aren't IBANs still get one
+        let fake = mask(MaskStrategy::Iban, "n/a");
+        assert!(fake.starts_with("DE"));
+        assert_eq!(scanner.scan(&fake), Some(PiiType::Iban));
+
+        for seed in 0..200 {
+            let nino = generate_fake_data(MaskStrategy::Nino, seed);
+            assert_eq!(scanner.scan(&nino), Some(PiiType::UkNino), "{}", nino);
+            let tax_id = generate_fake_data(MaskStrategy::TaxId, seed);
+            assert_eq!(scanner.scan(&tax_id), Some(PiiType::DeTaxId), "{}", tax_id);
+        }
+
+        assert_eq!(pii_type_to_strategy(PiiType::Iban), MaskStrategy::Iban);
+        assert_eq!(pii_type_to_strategy(PiiType::UkNino), MaskStrategy::Nino);
+        assert_eq!(pii_type_to_strategy(PiiType::DeTaxId), MaskStrategy::TaxId);
+    }
+
+    #[test]
+    fn test_name_and_address_strategies() {
+        for strategy in [
+            MaskStrategy::FirstName,
+            MaskStrategy::LastName,
+            MaskStrategy::FullName,
+            MaskStrategy::StreetAddress,
+            MaskStrategy::Zip,
+            MaskStrategy::Company,
+        ] {
+            let first = generate_fake_data(strategy, 42);
+            assert_eq!(first, generate_fake_data(strategy, 42), "{}", strategy);
+            assert_ne!(first, "MASKED", "{}", strategy);
+            assert!(!first.is_empty(), "{}", strategy);
+        }
+
+        let full_name = generate_fake_data(MaskStrategy::FullName, 7);
+        assert!(full_name.contains(' '), "{}", full_name);
+        let street = generate_fake_data(MaskStrategy::StreetAddress, 7);
+        assert!(
+            street.starts_with(|c: char| c.is_ascii_digit()),
+            "{}",
+            street
+        );
+        let zip = generate_fake_data(MaskStrategy::Zip, 7);
+        assert!(
+            zip.chars().all(|c| c.is_ascii_digit() || c == '-'),
+            "{}",
+            zip
+        );
+    }
+
+    #[test]
+    fn test_custom_strategy() {
+        let options = MaskingOptions {
+            pattern: Some(crate::config::RulePattern(
+                Regex::new(r"^(CUST)-(\d{2})\d+$").unwrap(),
+            )),
+            replacement: "$1-$2{rand:6}".to_string(),
+            ..Default::default()
+        };
+        let ctx = MaskingContext::new(&AppConfig::default());
+        let mask = |value: &str| {
+            mask_with_strategy(MaskStrategy::Custom, Some(&options), value.as_bytes(), &ctx)
+                .unwrap()
+                .unwrap()
+        };
+
+        // Captures are kept, the rest is seeded random digits
+        let masked = mask("CUST-42981734");
+        assert!(masked.starts_with("CUST-42"), "{}", masked);
+        assert_eq!(masked.len(), "CUST-42".len() + 6);
+        assert!(masked[7..].chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(masked, mask("CUST-42981734"));
+        assert_ne!(masked, mask("CUST-42981735"));
+
+        // Unmatched values pass through
+        assert_eq!(mask("ORDER-1"), "ORDER-1");
+
+        // Every match is replaced; a malformed token is left as written
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert_eq!(
+            replace_custom("a1 b2", &Regex::new(r"[a-z](\d)").unwrap(), "x$1", 0),
+            "x1 x2"
+        );
+        assert_eq!(
+            expand_random_digits("{rand:x}{rand:0}", &mut rng),
+            "{rand:x}"
+        );
+    }
+
+    #[test]
+    fn test_numeric_noise_keeps_shape() {
+        assert_eq!(
+            add_numeric_noise("85000", 1, 10),
+            add_numeric_noise("85000", 1, 10)
+        );
+        let noised = add_numeric_noise("-0042.50", 1, 10);
+        assert!(noised.starts_with("-00"), "{}", noised);
+        assert_eq!(noised.len(), "-0042.50".len());
+        assert!(add_numeric_noise("+12", 1, 10).starts_with('+'));
+        assert_eq!(add_numeric_noise("85000", 1, 0), "85000");
+
+        for value in [
+            "n/a",
+            "",
+            "-",
+            "1.",
+            ".5",
+            "1e5",
+            "12 000",
+            "1.2.3",
+            "99999999999999999999",
+        ] {
+            assert_eq!(add_numeric_noise(value, 1, 10), value);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_numeric_noise_within_band(
+            units in 0u64..1_000_000_000_000,
+            scale in 0usize..5,
+            negative: bool,
+            noise_percent in 0u32..=50,
+            seed: u64,
+        ) {
+            let digits = format!("{:0>width$}", units, width = scale + 1);
+            let (int_digits, frac_digits) = digits.split_at(digits.len() - scale);
+            let value = match (negative, scale) {
+                (false, 0) => int_digits.to_string(),
+                (true, 0) => format!("-{}", int_digits),
+                (false, _) => format!("{}.{}", int_digits, frac_digits),
+                (true, _) => format!("-{}.{}", int_digits, frac_digits),
+            };
+
+            let noised = add_numeric_noise(&value, seed, noise_percent);
+            proptest::prop_assert_eq!(&noised, &add_numeric_noise(&value, seed, noise_percent));
+            proptest::prop_assert_eq!(noised.starts_with('-'), negative);
+            proptest::prop_assert!(noised.len().abs_diff(value.len()) <= 1);
+
+            let (int_out, frac_out) = noised
+                .trim_start_matches('-')
+                .split_once('.')
+                .unwrap_or((noised.trim_start_matches('-'), ""));
+            proptest::prop_assert_eq!(frac_out.len(), scale);
+            let out_units: u64 = format!("{}{}", int_out, frac_out).parse().unwrap();
+            // Within the band, give or take rounding to the last digit
+            let diff = u128::from(out_units.abs_diff(units)) * 100;
+            proptest::prop_assert!(
+                diff <= u128::from(units) * u128::from(noise_percent) + 50,
+                "{} -> {}", value, noised
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_numeric_noise_rule_on_postgres_and_mysql_rows() {
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "salary".parse().unwrap(),
+                strategy: MaskStrategy::NumericNoise,
+                options: Some(MaskingOptions {
+                    noise_percent: 5,
+                    ..Default::default()
+                }),
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let expected = add_numeric_noise("85000.00", hash_seed(b"85000.00", &[0; 16]), 5);
+        assert_ne!(expected, "85000.00");
+
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        let desc = RowDescription {
+            fields: vec![FieldDescription {
+                name: bytes::Bytes::from_static(b"salary"),
+                table_oid: 0,
+                column_index: 0,
+                type_oid: 1700,
+                type_len: -1,
+                type_modifier: 0,
+                format_code: 0,
+            }],
+        };
+        anonymizer.on_row_description(&desc).await;
+        let row = DataRow {
+            values: vec![Some(BytesMut::from(&b"85000.00"[..]))],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], expected.as_bytes());
+
+        let mut anonymizer = MySqlAnonymizer::new(state.clone(), 1);
+        let col = ColumnDefinition {
+            sequence_id: 2,
+            catalog: bytes::Bytes::from_static(b"def"),
+            schema: bytes::Bytes::from_static(b"hr"),
+            table: bytes::Bytes::from_static(b"employees"),
+            org_table: bytes::Bytes::from_static(b"employees"),
+            name: bytes::Bytes::from_static(b"salary"),
+            org_name: bytes::Bytes::from_static(b"salary"),
+            character_set: 63,
+            column_length: 12,
+            column_type: 0xf6,
+            flags: 0,
+            decimals: 2,
+            default_value: None,
+        };
+        anonymizer.on_column_definition(&col).await;
+        let row = ResultRow {
+            sequence_id: 3,
+            values: vec![Some(BytesMut::from(&b"85000.00"[..]))],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], expected.as_bytes());
+
+        assert_eq!(state.stats.read().await.masking.numeric_noise, 2);
+    }
+
+    #[test]
+    fn test_date_shift_keeps_format() {
+        let days_between = |a: &str, b: &str, format: &str| {
+            let a = NaiveDate::parse_from_str(a, format).unwrap();
+            let b = NaiveDate::parse_from_str(b, format).unwrap();
+            (a - b).num_days()
+        };
+
+        for seed in 0..50 {
+            let shifted = shift_date("1985-06-15", seed, 30);
+            let days = days_between(&shifted, "1985-06-15", "%Y-%m-%d");
+            assert!(days != 0 && days.abs() <= 30, "{} -> {}", seed, shifted);
+        }
+        assert_eq!(
+            shift_date("1985-06-15", 9, 30),
+            shift_date("1985-06-15", 9, 30)
+        );
+
+        let shifted = shift_date("06/15/1985", 9, 30);
+        assert!(days_between(&shifted, "06/15/1985", "%m/%d/%Y").abs() <= 30);
+
+        // The time and zone of a timestamp are untouched
+        let shifted = shift_date("2024-02-29T13:45:00+02:00", 3, 400);
+        assert!(shifted.ends_with("T13:45:00+02:00"), "{}", shifted);
+        let shifted = shift_date("2024-02-29 13:45:00.123", 3, 400);
+        assert!(shifted.ends_with(" 13:45:00.123"), "{}", shifted);
+
+        assert_eq!(shift_date("not a date", 3, 30), "1900-01-01");
+        assert_eq!(shift_date("1985-06-15xyz", 3, 30), "1900-01-01");
+        assert_eq!(shift_date("1985-06-1é", 3, 30), "1900-01-01");
+    }
+
+    #[test]
+    fn test_heuristic_date_of_birth_shift() {
+        let mut config = AppConfig::default();
+        let ctx = MaskingContext::new(&config);
+        assert_eq!(
+            heuristic_strategy(PiiType::DateOfBirth, &ctx).0,
+            MaskStrategy::Dob
+        );
+
+        config.heuristic_masking.shift_dates_of_birth = true;
+        config.heuristic_masking.options.max_shift_days = 10;
+        let ctx = MaskingContext::new(&config);
+        let (strategy, options) = heuristic_strategy(PiiType::DateOfBirth, &ctx);
+        assert_eq!(strategy, MaskStrategy::DateShift);
+        assert_eq!(options.unwrap().max_shift_days, 10);
+        assert_eq!(
+            heuristic_strategy(PiiType::Email, &ctx).0,
+            MaskStrategy::Email
+        );
+    }
+
+    #[tokio::test]
+    async fn test_seed_salt_changes_masked_values() {
+        async fn mask_email(seed_salt: Option<&str>, rule_salt: Option<&str>) -> Vec<u8> {
+            let mut config = AppConfig {
+                rules: vec![MaskingRule {
+                    id: Default::default(),
+                    table: None,
+                    column: "email".parse().unwrap(),
+                    strategy: MaskStrategy::Email,
+                    options: rule_salt.map(|salt| MaskingOptions {
+                        seed_salt: Some(salt.to_string()),
+                        ..Default::default()
+                    }),
+                    json_paths: vec![],
+                }],
+                ..Default::default()
+            };
+            config.masking.seed_salt = seed_salt.map(String::from);
+            let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+            let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+            anonymizer
+                .on_row_description(&RowDescription {
+                    fields: vec![FieldDescription {
+                        name: bytes::Bytes::from_static(b"email"),
+                        table_oid: 0,
+                        column_index: 0,
+                        type_oid: 0,
+                        type_len: 0,
+                        type_modifier: 0,
+                        format_code: 0,
+                    }],
+                })
+                .await;
+            let row = DataRow {
+                values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+            };
+            let row = anonymizer.on_data_row(row).await.unwrap();
+            row.values[0].as_ref().unwrap().to_vec()
+        }
+
+        // Deterministic within a deployment, across states
+        let salted = mask_email(Some("pepper"), None).await;
+        assert_eq!(salted, mask_email(Some("pepper"), None).await);
+
+        assert_ne!(salted, mask_email(Some("paprika"), None).await);
+        assert_ne!(salted, mask_email(None, None).await);
+
+        // A rule's salt takes precedence over the global one
+        let rule_salted = mask_email(Some("pepper"), Some("sumac")).await;
+        assert_ne!(rule_salted, salted);
+        assert_eq!(rule_salted, mask_email(None, Some("sumac")).await);
+    }
+
+    #[tokio::test]
+    async fn test_masking_cache_shared_by_postgres_and_mysql() {
+        let mut config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        config.masking.cache_capacity = Some(16);
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+
+        let mut pg = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        pg.on_row_description(&RowDescription {
+            fields: vec![FieldDescription {
+                name: bytes::Bytes::from_static(b"email"),
+                table_oid: 0,
+                column_index: 0,
+                type_oid: 0,
+                type_len: 0,
+                type_modifier: 0,
+                format_code: 0,
+            }],
+        })
+        .await;
+        let row = DataRow {
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let pg_masked = pg.on_data_row(row).await.unwrap().values[0].clone();
+
+        let mut mysql = MySqlAnonymizer::new(state.clone(), 2);
+        mysql
+            .on_column_definition(&ColumnDefinition {
+                sequence_id: 2,
+                catalog: bytes::Bytes::from_static(b"def"),
+                schema: bytes::Bytes::from_static(b"shop"),
+                table: bytes::Bytes::from_static(b"orders"),
+                org_table: bytes::Bytes::from_static(b"orders"),
+                name: bytes::Bytes::from_static(b"email"),
+                org_name: bytes::Bytes::from_static(b"email"),
+                character_set: 45,
+                column_length: 255,
+                column_type: 0xfd,
+                flags: 0,
+                decimals: 0,
+                default_value: None,
+            })
+            .await;
+        let row = ResultRow {
+            sequence_id: 3,
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let mysql_masked = mysql.on_result_row(row).await.unwrap().values[0].clone();
+
+        assert_eq!(pg_masked, mysql_masked);
+        let stats = state.masking_cache.as_ref().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_rules_and_precedence() {
+        let rule = |column: &str, strategy: &str| MaskingRule {
+            id: Default::default(),
+            table: None,
+            column: column.parse().unwrap(),
+            strategy: strategy.parse().unwrap(),
+            options: None,
+            json_paths: vec![],
+        };
+        let config = AppConfig {
+            rules: vec![
+                // Listed first, but the exact rule below wins for work_email
+                rule("*_email", "redact"),
+                rule("re:^ssn_(old|new)$", "null"),
+                rule("work_email", "hash"),
+            ],
+            masking_mode: MaskingMode::RulesOnly,
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+
+        let field = |name: &'static [u8]| FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: 0,
+            type_len: 0,
+            type_modifier: 0,
+            format_code: 0,
+        };
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![
+                    field(b"contact_email"),
+                    field(b"work_email"),
+                    field(b"ssn_old"),
+                    field(b"ssn_older"),
+                ],
+            })
+            .await;
+        let strategies: Vec<_> = anonymizer
+            .target_cols
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rule)| Some((i, rule.as_ref()?.strategy.as_str())))
+            .collect();
+        assert_eq!(strategies, [(0, "redact"), (1, "hash"), (2, "null")]);
+
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        for (i, name) in [&b"contact_email"[..], b"work_email"]
+            .into_iter()
+            .enumerate()
+        {
+            anonymizer
+                .on_column_definition(&ColumnDefinition {
+                    sequence_id: 2 + i as u8,
+                    catalog: bytes::Bytes::from_static(b"def"),
+                    schema: bytes::Bytes::from_static(b"app"),
+                    table: bytes::Bytes::from_static(b"users"),
+                    org_table: bytes::Bytes::from_static(b"users"),
+                    name: bytes::Bytes::copy_from_slice(name),
+                    org_name: bytes::Bytes::copy_from_slice(name),
+                    character_set: 45,
+                    column_length: 255,
+                    column_type: 0xfd,
+                    flags: 0,
+                    decimals: 0,
+                    default_value: None,
+                })
+                .await;
+        }
+        let strategies: Vec<_> = anonymizer
+            .target_cols
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rule)| Some((i, rule.as_ref()?.strategy.as_str())))
+            .collect();
+        assert_eq!(strategies, [(0, "redact"), (1, "hash")]);
+    }
+
+    #[test]
+    fn test_mask_json_paths() {
+        let ctx = MaskingContext::new(&AppConfig::default());
+        let scanner = PiiScanner::new();
+        let paths = |paths: &[&str]| -> Vec<JsonPath> {
+            paths.iter().map(|p| JsonPath::new(*p).unwrap()).collect()
+        };
+        let mask = |doc: &serde_json::Value, paths: &[JsonPath], strategy: MaskStrategy| {
+            let raw = serde_json::to_vec(doc).unwrap();
+            mask_json_paths(&raw, paths, strategy, None, &scanner, &ctx)
+                .unwrap()
+                .map(|masked| serde_json::from_str::<serde_json::Value>(&masked).unwrap())
+        };
+
+        let doc = json!({
+            "customer": { "email": "alice@example.com", "name": "Alice" },
+            "orders": [
+                { "items": [{ "contact": "bob@example.com" }, { "contact": "carol@example.com" }] },
+                { "items": [] },
+                { "items": [{ "contact": "dave@example.com" }] }
+            ],
+            "metadata": { "trace_id": "alice@example.com", "total": 1250 }
+        });
+
+        let masked = mask(
+            &doc,
+            &paths(&["$.customer.email", "$.orders[*].items[*].contact"]),
+            MaskStrategy::Redact,
+        )
+        .unwrap();
+        assert_eq!(masked["customer"]["email"], "[REDACTED]");
+        assert_eq!(masked["customer"]["name"], "Alice");
+        assert_eq!(masked["orders"][0]["items"][1]["contact"], "[REDACTED]");
+        assert_eq!(masked["orders"][2]["items"][0]["contact"], "[REDACTED]");
+        assert_eq!(masked["metadata"], doc["metadata"]);
+
+        // json rules scan the selected subtree only
+        let masked = mask(&doc, &paths(&["$.orders[0]"]), MaskStrategy::Json).unwrap();
+        assert_ne!(masked["orders"][0], doc["orders"][0]);
+        assert_eq!(masked["orders"][2], doc["orders"][2]);
+        assert_eq!(masked["customer"], doc["customer"]);
+
+        // Numbers stay numbers
+        let masked = mask(
+            &doc,
+            &paths(&["$.metadata.total"]),
+            MaskStrategy::NumericNoise,
+        )
+        .unwrap();
+        assert!(masked["metadata"]["total"].is_number());
+
+        // Paths that don't exist, or that expect another shape, leave the
+        // document alone
+        let missing = paths(&["$.customer.phone", "$.orders[7].items", "$.customer[0]"]);
+        assert_eq!(mask(&doc, &missing, MaskStrategy::Redact), None);
+        assert_eq!(
+            mask_json_paths(
+                b"not json",
+                &missing,
+                MaskStrategy::Redact,
+                None,
+                &scanner,
+                &ctx
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_paths_rule_leaves_rest_of_document() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - column: payload
+    strategy: email
+    json_paths: ["$.customer.email"]
+"#,
+        )
+        .unwrap();
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![FieldDescription {
+                    name: bytes::Bytes::from_static(b"payload"),
+                    table_oid: 0,
+                    column_index: 0,
+                    type_oid: 0,
+                    type_len: 0,
+                    type_modifier: 0,
+                    format_code: 0,
+                }],
+            })
+            .await;
+        let payload =
+            br#"{"customer":{"email":"alice@example.com"},"metadata":{"trace_id":"bob@example.com"}}"#;
+        let row = DataRow {
+            values: vec![Some(BytesMut::from(&payload[..]))],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        let masked: serde_json::Value =
+            serde_json::from_slice(row.values[0].as_ref().unwrap()).unwrap();
+
+        let email = masked["customer"]["email"].as_str().unwrap();
+        assert_ne!(email, "alice@example.com");
+        assert!(email.contains('@'));
+        // Looks like PII, but is outside the rule's paths
+        assert_eq!(masked["metadata"]["trace_id"], "bob@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_masking_modes() {
+        let field = |name: &'static [u8]| FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: 0,
+            type_len: 0,
+            type_modifier: 0,
+            format_code: 0,
+        };
+        let description = RowDescription {
+            fields: vec![
+                field(b"ssn"),
+                field(b"contact"),
+                field(b"profile"),
+                field(b"emails"),
+            ],
+        };
+        let values: [&[u8]; 4] = [
+            b"123-45-6789",
+            b"alice@example.com",
+            br#"{"email":"bob@example.com"}"#,
+            b"{carol@example.com}",
+        ];
+
+        let mask_row = |mode: MaskingMode| {
+            let description = description.clone();
+            async move {
+                let config = AppConfig {
+                    rules: vec![MaskingRule {
+                        id: Default::default(),
+                        table: None,
+                        column: "ssn".parse().unwrap(),
+                        strategy: MaskStrategy::Redact,
+                        options: None,
+                        json_paths: vec![],
+                    }],
+                    masking_mode: mode,
+                    ..Default::default()
+                };
+                let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+                let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+                anonymizer.on_row_description(&description).await;
+                let row = DataRow {
+                    values: values.iter().map(|v| Some(BytesMut::from(*v))).collect(),
+                };
+                let row = anonymizer.on_data_row(row).await.unwrap();
+                row.values
+                    .into_iter()
+                    .map(|v| v.unwrap().to_vec())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Only the rule applies; JSON and arrays are not scanned either
+        let row = mask_row(MaskingMode::RulesOnly).await;
+        assert_eq!(row[0], b"[REDACTED]");
+        assert_eq!(&row[1..], &values[1..]);
+
+        // The rule is ignored, the scanner masks everything
+        let row = mask_row(MaskingMode::Heuristic).await;
+        assert_ne!(row[0], b"[REDACTED]");
+        assert_ne!(row[0], values[0]);
+        for (masked, original) in row.iter().zip(values).skip(1) {
+            assert_ne!(masked, original);
+        }
+
+        let row = mask_row(MaskingMode::Both).await;
+        assert_eq!(row[0], b"[REDACTED]");
+        for (masked, original) in row.iter().zip(values).skip(1) {
+            assert_ne!(masked, original);
+        }
+
+        // MySQL without any matching rule forwards rows as they are
+        let config = AppConfig {
+            masking_mode: MaskingMode::RulesOnly,
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        anonymizer
+            .on_column_definition(&ColumnDefinition {
+                sequence_id: 2,
+                catalog: bytes::Bytes::from_static(b"def"),
+                schema: bytes::Bytes::from_static(b"app"),
+                table: bytes::Bytes::from_static(b"users"),
+                org_table: bytes::Bytes::from_static(b"users"),
+                name: bytes::Bytes::from_static(b"contact"),
+                org_name: bytes::Bytes::from_static(b"contact"),
+                character_set: 45,
+                column_length: 255,
+                column_type: 0xfd,
+                flags: 0,
+                decimals: 0,
+                default_value: None,
+            })
+            .await;
+        let row = ResultRow {
+            sequence_id: 3,
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], b"alice@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_excluded_columns_pass_through() {
+        let rule = |table: Option<&str>, column: &str, strategy: &str| MaskingRule {
+            id: Default::default(),
+            table: table.map(|t| t.parse().unwrap()),
+            column: column.parse().unwrap(),
+            strategy: strategy.parse().unwrap(),
+            options: None,
+            json_paths: vec![],
+        };
+        let config = AppConfig {
+            rules: vec![
+                rule(None, "contact", "email"),
+                rule(None, "contact", "none"),
+                rule(None, "invoice_number", "none"),
+                rule(Some("inventory"), "server_ip", "none"),
+            ],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+
+        let field = |name: &'static [u8]| FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: 0,
+            type_len: 0,
+            type_modifier: 0,
+            format_code: 0,
+        };
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![
+                    field(b"contact"),
+                    field(b"invoice_number"),
+                    field(b"server_ip"),
+                    field(b"notes"),
+                ],
+            })
+            .await;
+        let row = DataRow {
+            values: vec![
+                Some(BytesMut::from(&b"alice@example.com"[..])),
+                Some(BytesMut::from(&b"4111111111111111"[..])),
+                Some(BytesMut::from(&b"10.0.0.12"[..])),
+                Some(BytesMut::from(&br#"{"email":"bob@example.com"}"#[..])),
+            ],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+
+        // The exclusion beats the email rule and the scanner
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], b"alice@example.com");
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"4111111111111111");
+        // A table-scoped exclusion needs the table to be known
+        assert_ne!(&row.values[2].as_ref().unwrap()[..], b"10.0.0.12");
+        assert!(!String::from_utf8_lossy(row.values[3].as_ref().unwrap()).contains("bob"));
+
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        for (i, name) in [&b"contact"[..], b"server_ip"].into_iter().enumerate() {
+            anonymizer
+                .on_column_definition(&ColumnDefinition {
+                    sequence_id: 2 + i as u8,
+                    catalog: bytes::Bytes::from_static(b"def"),
+                    schema: bytes::Bytes::from_static(b"ops"),
+                    table: bytes::Bytes::from_static(b"inventory"),
+                    org_table: bytes::Bytes::from_static(b"inventory"),
+                    name: bytes::Bytes::copy_from_slice(name),
+                    org_name: bytes::Bytes::copy_from_slice(name),
+                    character_set: 45,
+                    column_length: 255,
+                    column_type: 0xfd,
+                    flags: 0,
+                    decimals: 0,
+                    default_value: None,
+                })
+                .await;
+        }
+        let row = ResultRow {
+            sequence_id: 4,
+            values: vec![
+                Some(BytesMut::from(&b"alice@example.com"[..])),
+                Some(BytesMut::from(&b"10.0.0.12"[..])),
+            ],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], b"alice@example.com");
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"10.0.0.12");
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_masking() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let email = "test@example.com";
+
+        // Process same email twice
+        let mut row1 = DataRow {
+            values: vec![Some(BytesMut::from(email.as_bytes()))],
+        };
+        let mut row2 = DataRow {
+            values: vec![Some(BytesMut::from(email.as_bytes()))],
+        };
+
+        row1 = anonymizer.on_data_row(row1).await.unwrap();
+        row2 = anonymizer.on_data_row(row2).await.unwrap();
+
+        let val1 = std::str::from_utf8(row1.values[0].as_ref().unwrap()).unwrap();
+        let val2 = std::str::from_utf8(row2.values[0].as_ref().unwrap()).unwrap();
+
+        // Same input should produce same output (deterministic)
+        assert_eq!(val1, val2, "Same input should produce same masked output");
+        assert_ne!(val1, email, "Output should be different from input");
+    }
+
+    #[tokio::test]
+    async fn test_policy_overrides_global_masking() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - column: contact
+    strategy: redact
+rule_sets:
+  support:
+    - column: contact
+      strategy: partial
+policies:
+  - name: migrations
+    match: { user: migrator }
+    masking_enabled: false
+  - name: support
+    match: { user: support }
+    rule_set: support
+"#,
+        )
+        .unwrap();
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+
+        let mask = |policy: Option<&str>| {
+            let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+            anonymizer.set_policy(policy.map(String::from));
+            async move {
+                anonymizer
+                    .on_row_description(&RowDescription {
+                        fields: vec![FieldDescription {
+                            name: bytes::Bytes::from_static(b"contact"),
+                            table_oid: 0,
+                            column_index: 0,
+                            type_oid: 0,
+                            type_len: 0,
+                            type_modifier: 0,
+                            format_code: 0,
+                        }],
+                    })
+                    .await;
+                let row = DataRow {
+                    values: vec![Some(BytesMut::from(&b"555-0100"[..]))],
+                };
+                let row = anonymizer.on_data_row(row).await.unwrap();
+                row.values[0].as_ref().unwrap().to_vec()
+            }
+        };
+
+        assert_eq!(mask(None).await, b"[REDACTED]");
+        assert_eq!(mask(Some("migrations")).await, b"555-0100");
+        assert_eq!(mask(Some("support")).await, b"***-0100");
+
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        anonymizer.set_policy(Some("migrations".to_string()));
+        let row = ResultRow {
+            sequence_id: 1,
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], b"alice@example.com");
+    }
+
+    fn contact_description() -> RowDescription {
+        RowDescription {
+            fields: vec![FieldDescription {
+                name: bytes::Bytes::from_static(b"contact"),
+                table_oid: 0,
+                column_index: 0,
+                type_oid: 0,
+                type_len: 0,
+                type_modifier: 0,
+                format_code: 0,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_change_applies_from_next_result_set() {
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "contact".parse().unwrap(),
+                strategy: MaskStrategy::Redact,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        let row = || DataRow {
+            values: vec![Some(BytesMut::from(&b"555-0100"[..]))],
+        };
+        let masked = |row: DataRow| row.values[0].as_ref().unwrap().to_vec();
+
+        anonymizer.on_row_description(&contact_description()).await;
+        let first = anonymizer.on_data_row(row()).await.unwrap();
+        assert_eq!(masked(first), b"[REDACTED]");
+
+        // A result set is masked under the settings it started with
+        {
+            let mut config = state.config.write().await;
+            config.rules[0].strategy = MaskStrategy::Partial;
+            state.config_changed();
+        }
+        let second = anonymizer.on_data_row(row()).await.unwrap();
+        assert_eq!(masked(second), b"[REDACTED]");
+
+        // The change applies from the next result set
+        anonymizer.on_command_complete();
+        anonymizer.on_row_description(&contact_description()).await;
+        let third = anonymizer.on_data_row(row()).await.unwrap();
+        assert_eq!(masked(third), b"***-0100");
+
+        // Rows with no RowDescription of their own (an Execute without a
+        // Describe) still pick up the config of their result set
+        {
+            let mut config = state.config.write().await;
+            config.masking_enabled = false;
+            state.config_changed();
+        }
+        anonymizer.on_command_complete();
+        let fourth = anonymizer.on_data_row(row()).await.unwrap();
+        assert_eq!(masked(fourth), b"555-0100");
+    }
+
+    #[tokio::test]
+    async fn test_rows_are_masked_without_locking_config() {
+        const ROWS: usize = 100_000;
+
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "contact".parse().unwrap(),
+                strategy: MaskStrategy::Redact,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        anonymizer.on_row_description(&contact_description()).await;
+
+        // A writer parked on the config (an API call, a reload) would stall
+        // every row if rows still read it
+        let _writer = state.config.write().await;
+        let masked = tokio::time::timeout(std::time::Duration::from_secs(120), async {
+            for _ in 0..ROWS {
+                let row = DataRow {
+                    values: vec![Some(BytesMut::from(&b"555-0100"[..]))],
+                };
+                let row = anonymizer.on_data_row(row).await.unwrap();
+                assert_eq!(&row.values[0].as_ref().unwrap()[..], b"[REDACTED]");
+            }
+        })
+        .await;
+        assert!(masked.is_ok(), "rows waited on the config lock");
+    }
+
+    #[tokio::test]
+    async fn test_masking_can_be_disabled() {
+        let config = AppConfig {
+            masking_enabled: false, // Disabled
+            rules: vec![],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let email = "test@example.com";
+        let mut row = DataRow {
+            values: vec![Some(BytesMut::from(email.as_bytes()))],
+        };
+
+        row = anonymizer.on_data_row(row).await.unwrap();
+        let val = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+
+        // Should NOT be masked when disabled
+        assert_eq!(
+            val, email,
+            "Data should not be masked when masking is disabled"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_null_values_handled() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let mut row = DataRow {
+            values: vec![None, Some(BytesMut::from("data".as_bytes())), None],
+        };
+
+        row = anonymizer.on_data_row(row).await.unwrap();
 
         assert!(row.values[0].is_none(), "NULL should remain NULL");
         assert!(row.values[1].is_some(), "Non-NULL should remain Some");
         assert!(row.values[2].is_none(), "NULL should remain NULL");
     }
+
+    #[tokio::test]
+    async fn test_table_scoped_rule_uses_resolved_table() {
+        use crate::catalog::TableName;
+
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: Some("users".parse().unwrap()),
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer
+            .tables
+            .insert(100, TableName::new("public", "users"));
+        anonymizer
+            .tables
+            .insert(200, TableName::new("public", "audit"));
+
+        let email_field = |table_oid| FieldDescription {
+            name: bytes::Bytes::from_static(b"email"),
+            table_oid,
+            column_index: 1,
+            type_oid: type_oid::TEXT,
+            type_len: -1,
+            type_modifier: -1,
+            format_code: FORMAT_TEXT,
+        };
+        // A non-PII-looking value so only the explicit rule can mask it
+        let value = "not-an-email";
+
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![email_field(100)],
+            })
+            .await;
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from(value.as_bytes()))],
+            })
+            .await
+            .unwrap();
+        assert_ne!(&row.values[0].as_ref().unwrap()[..], value.as_bytes());
+
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![email_field(200)],
+            })
+            .await;
+        let row = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from(value.as_bytes()))],
+            })
+            .await
+            .unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], value.as_bytes());
+    }
+
+    fn binary_field(name: &'static [u8], type_oid: u32) -> FieldDescription {
+        FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid,
+            type_len: -1,
+            type_modifier: -1,
+            format_code: FORMAT_BINARY,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_binary_text_column_masked() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let desc = RowDescription {
+            fields: vec![
+                binary_field(b"contact", type_oid::TEXT),
+                binary_field(b"id", 23), // int4
+            ],
+        };
+        anonymizer.on_row_description(&desc).await;
+
+        let email = "test@example.com";
+        // int4 42 in network byte order; also happens to be valid UTF-8
+        let id = 42i32.to_be_bytes();
+        let mut row = DataRow {
+            values: vec![
+                Some(BytesMut::from(email.as_bytes())),
+                Some(BytesMut::from(&id[..])),
+            ],
+        };
+
+        row = anonymizer.on_data_row(row).await.unwrap();
+
+        let val0 = std::str::from_utf8(row.values[0].as_ref().unwrap()).unwrap();
+        assert_ne!(val0, email, "Binary text value should be masked");
+        assert!(val0.contains('@'), "Masked value should still be an email");
+        assert_eq!(
+            &row.values[1].as_ref().unwrap()[..],
+            &id[..],
+            "Binary int4 must be forwarded untouched"
+        );
+    }
+
+    /// Make `strategy` fail for the rest of the result set
+    async fn fail_strategy(
+        slot: &mut Option<MaskingSnapshot>,
+        state: &AppState,
+        strategy: MaskStrategy,
+    ) {
+        let snapshot = MaskingSnapshot::current(slot, state, None).await;
+        let mut ctx = MaskingContext::for_state(&*state.config.read().await, state);
+        ctx.failing_strategy = Some(strategy);
+        *slot = Some(MaskingSnapshot {
+            enabled: snapshot.enabled,
+            ctx: Arc::new(ctx),
+        });
+    }
+
+    #[tokio::test]
+    async fn test_masking_errors_fail_open_or_closed() {
+        let email = "alice@example.com";
+        let ssn = "123-45-6789";
+        let desc = RowDescription {
+            fields: vec![text_field(b"email"), text_field(b"notes")],
+        };
+        let row = || DataRow {
+            values: vec![Some(BytesMut::from(email)), Some(BytesMut::from(ssn))],
+        };
+
+        for (on_error, expected) in [
+            (OnMaskingError::FailOpen, email),
+            (OnMaskingError::FailClosed, FAIL_CLOSED_REPLACEMENT),
+        ] {
+            let mut config = AppConfig {
+                rules: vec![MaskingRule {
+                    id: Default::default(),
+                    table: None,
+                    column: "email".parse().unwrap(),
+                    strategy: MaskStrategy::Email,
+                    options: None,
+                    json_paths: vec![],
+                }],
+                ..Default::default()
+            };
+            config.masking.on_error = on_error;
+            let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+
+            let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+            anonymizer.on_row_description(&desc).await;
+            fail_strategy(&mut anonymizer.snapshot, &state, MaskStrategy::Email).await;
+            let masked = anonymizer.on_data_row(row()).await.unwrap();
+            assert_eq!(
+                &masked.values[0].as_ref().unwrap()[..],
+                expected.as_bytes(),
+                "{:?}",
+                on_error
+            );
+            // The rest of the row is still masked
+            assert_ne!(&masked.values[1].as_ref().unwrap()[..], ssn.as_bytes());
+
+            let logs = state.logs.read().await;
+            let error = logs
+                .iter()
+                .find(|log| log.event_type == "MaskingError")
+                .unwrap();
+            assert_eq!(error.details.as_ref().unwrap()["column_idx"], 0);
+            assert_eq!(error.details.as_ref().unwrap()["column_name"], "email");
+            assert_eq!(error.details.as_ref().unwrap()["error"], "email failed");
+            drop(logs);
+
+            let mut anonymizer = MySqlAnonymizer::new(state.clone(), 2);
+            anonymizer
+                .on_column_definition(&ColumnDefinition {
+                    sequence_id: 2,
+                    catalog: bytes::Bytes::from_static(b"def"),
+                    schema: bytes::Bytes::from_static(b"shop"),
+                    table: bytes::Bytes::from_static(b"users"),
+                    org_table: bytes::Bytes::from_static(b"users"),
+                    name: bytes::Bytes::from_static(b"email"),
+                    org_name: bytes::Bytes::from_static(b"email"),
+                    character_set: 45,
+                    column_length: 255,
+                    column_type: 0xfd,
+                    flags: 0,
+                    decimals: 0,
+                    default_value: None,
+                })
+                .await;
+            fail_strategy(&mut anonymizer.snapshot, &state, MaskStrategy::Email).await;
+            let masked = anonymizer
+                .on_result_row(ResultRow {
+                    sequence_id: 3,
+                    values: vec![Some(BytesMut::from(email))],
+                })
+                .await
+                .unwrap();
+            assert_eq!(&masked.values[0].as_ref().unwrap()[..], expected.as_bytes());
+            let logs = state.logs.read().await;
+            assert_eq!(logs[0].event_type, "MaskingError");
+            assert_eq!(logs[0].details.as_ref().unwrap()["column_name"], "email");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_non_text_columns_skip_heuristics() {
+        use crate::protocol::mysql::{MYSQL_TYPE_LONGLONG, MYSQL_TYPE_VAR_STRING};
+
+        const INT8: u32 = 20;
+        let card = "4111111111111111";
+        let typed_field = |name: &'static [u8], oid| FieldDescription {
+            type_oid: oid,
+            ..text_field(name)
+        };
+        let desc = RowDescription {
+            fields: vec![
+                typed_field(b"account_no", INT8),
+                typed_field(b"card", type_oid::TEXT),
+                typed_field(b"ruled_no", INT8),
+            ],
+        };
+        let row = || DataRow {
+            values: vec![Some(BytesMut::from(card)); 3],
+        };
+        let mut config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "ruled_no".parse().unwrap(),
+                strategy: MaskStrategy::Partial,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state.clone(), 1, "127.0.0.1".to_string(), 5432);
+        anonymizer.on_row_description(&desc).await;
+        let masked = anonymizer.on_data_row(row()).await.unwrap();
+        // Counted per column; the tables of columns aren't looked up without
+        // table-scoped rules
+        let stats = state.get_stats().await;
+        assert_eq!(stats.by_column.count(None, "card", "credit_card"), 1);
+        assert_eq!(stats.by_column.count(None, "ruled_no", "partial"), 1);
+        assert_eq!(stats.by_column.count(None, "account_no", "credit_card"), 0);
+        // A bigint that looks like a card number is not one
+        assert_eq!(&masked.values[0].as_ref().unwrap()[..], card.as_bytes());
+        assert_ne!(&masked.values[1].as_ref().unwrap()[..], card.as_bytes());
+        // Rules still apply whatever the type
+        assert_eq!(&masked.values[2].as_ref().unwrap()[..], b"************1111");
+
+        // Types can be made scannable
+        config.heuristic_masking.scannable_postgres_types.push(INT8);
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer.on_row_description(&desc).await;
+        let masked = anonymizer.on_data_row(row()).await.unwrap();
+        assert_ne!(&masked.values[0].as_ref().unwrap()[..], card.as_bytes());
+
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        for (name, column_type) in [
+            (&b"account_no"[..], MYSQL_TYPE_LONGLONG),
+            (b"card", MYSQL_TYPE_VAR_STRING),
+        ] {
+            anonymizer
+                .on_column_definition(&ColumnDefinition {
+                    sequence_id: 2,
+                    catalog: bytes::Bytes::from_static(b"def"),
+                    schema: bytes::Bytes::from_static(b"shop"),
+                    table: bytes::Bytes::from_static(b"accounts"),
+                    org_table: bytes::Bytes::from_static(b"accounts"),
+                    name: bytes::Bytes::copy_from_slice(name),
+                    org_name: bytes::Bytes::copy_from_slice(name),
+                    character_set: 63,
+                    column_length: 20,
+                    column_type,
+                    flags: 0,
+                    decimals: 0,
+                    default_value: None,
+                })
+                .await;
+        }
+        let row = anonymizer
+            .on_result_row(ResultRow {
+                sequence_id: 4,
+                values: vec![Some(BytesMut::from(card)); 2],
+            })
+            .await
+            .unwrap();
+        assert_eq!(&row.values[0].as_ref().unwrap()[..], card.as_bytes());
+        assert_ne!(&row.values[1].as_ref().unwrap()[..], card.as_bytes());
+    }
+
+    #[test]
+    fn test_bytea_hex_round_trip() {
+        assert_eq!(&decode_bytea_hex(b"\\x4869ff").unwrap()[..], b"Hi\xff");
+        assert_eq!(&decode_bytea_hex(b"\\x").unwrap()[..], b"");
+        assert_eq!(&encode_bytea_hex(b"Hi\xff")[..], b"\\x4869ff");
+        // Escape format and malformed hex aren't decoded
+        assert!(decode_bytea_hex(b"Hi").is_none());
+        assert!(decode_bytea_hex(b"\\x486").is_none());
+        assert!(decode_bytea_hex(b"\\x48zz").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bytea_values_are_masked_in_hex() {
+        let email = "alice@example.com";
+        let bytea_field = |name: &'static [u8], format_code| FieldDescription {
+            type_oid: type_oid::BYTEA,
+            format_code,
+            ..text_field(name)
+        };
+        let desc = RowDescription {
+            fields: vec![
+                bytea_field(b"contact", FORMAT_TEXT),
+                bytea_field(b"secret", FORMAT_TEXT),
+                bytea_field(b"legacy", FORMAT_TEXT),
+            ],
+        };
+        let row = || DataRow {
+            values: vec![
+                Some(encode_bytea_hex(email.as_bytes())),
+                Some(BytesMut::from(&b"\\xdeadbeef"[..])),
+                Some(BytesMut::from(email)),
+            ],
+        };
+        let mut config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "secret".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        anonymizer.on_row_description(&desc).await;
+        let masked = anonymizer.on_data_row(row()).await.unwrap();
+        let decoded = |idx: usize| {
+            let val = masked.values[idx].as_ref().unwrap();
+            String::from_utf8(decode_bytea_hex(val).unwrap().to_vec()).unwrap()
+        };
+        // Detected PII is replaced, and the client still gets valid hex
+        assert_ne!(decoded(0), email);
+        assert!(decoded(0).contains('@'));
+        // Rules apply to bytes that aren't UTF-8 too
+        assert!(decoded(1).contains('@'));
+        // Values in the escape format are left alone
+        assert_eq!(&masked.values[2].as_ref().unwrap()[..], email.as_bytes());
+
+        // Binary format values are the raw bytes
+        let desc = RowDescription {
+            fields: vec![bytea_field(b"contact", FORMAT_BINARY)],
+        };
+        anonymizer.on_row_description(&desc).await;
+        let masked = anonymizer
+            .on_data_row(DataRow {
+                values: vec![Some(BytesMut::from(email))],
+            })
+            .await
+            .unwrap();
+        let val = masked.values[0].as_ref().unwrap();
+        assert_ne!(&val[..], email.as_bytes());
+        assert!(std::str::from_utf8(val).unwrap().contains('@'));
+
+        // bytea_mode: skip leaves bytea alone, rules included
+        config.masking.bytea_mode = ByteaMode::Skip;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        let desc = RowDescription {
+            fields: vec![
+                bytea_field(b"contact", FORMAT_TEXT),
+                bytea_field(b"secret", FORMAT_TEXT),
+                bytea_field(b"legacy", FORMAT_TEXT),
+            ],
+        };
+        anonymizer.on_row_description(&desc).await;
+        let masked = anonymizer.on_data_row(row()).await.unwrap();
+        assert_eq!(masked.values, row().values);
+    }
+
+    #[tokio::test]
+    async fn test_binary_jsonb_keeps_version_header() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        let desc = RowDescription {
+            fields: vec![binary_field(b"doc", type_oid::JSONB)],
+        };
+        anonymizer.on_row_description(&desc).await;
+
+        let mut value = BytesMut::from(&[1u8][..]);
+        value.extend_from_slice(br#"{"email":"test@example.com"}"#);
+        let mut row = DataRow {
+            values: vec![Some(value)],
+        };
+
+        row = anonymizer.on_data_row(row).await.unwrap();
+        let val = row.values[0].as_ref().unwrap();
+
+        assert_eq!(val[0], 1, "jsonb version byte must be preserved");
+        let v: serde_json::Value = serde_json::from_slice(&val[1..]).unwrap();
+        assert_ne!(v["email"], "test@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_bind_result_formats_override_row_description() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+
+        // Describe(statement) reports text format before the Bind is known
+        let mut field = binary_field(b"id", 23);
+        field.format_code = FORMAT_TEXT;
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![field],
+            })
+            .await;
+
+        anonymizer.on_bind(&BindMessage {
+            portal: bytes::Bytes::new(),
+            statement: bytes::Bytes::new(),
+            param_formats: vec![],
+            params: vec![],
+            result_formats: vec![FORMAT_BINARY],
+        });
+        anonymizer.on_sync();
+        anonymizer.on_backend_message(b'2');
+
+        // A binary int4 whose bytes look like a masked-able IP if read as text
+        let raw = BytesMut::from(&b"1.1.1.1"[..]);
+        let mut row = DataRow {
+            values: vec![Some(raw.clone())],
+        };
+        row = anonymizer.on_data_row(row).await.unwrap();
+        assert_eq!(row.values[0].as_ref().unwrap(), &raw);
+
+        // After ReadyForQuery the portal's binary formats no longer apply
+        anonymizer.on_ready_for_query();
+        let mut row = DataRow {
+            values: vec![Some(raw.clone())],
+        };
+        row = anonymizer.on_data_row(row).await.unwrap();
+        assert_ne!(row.values[0].as_ref().unwrap(), &raw);
+    }
+
+    fn copy_message(message_type: u8, payload: &[u8]) -> RawMessage {
+        let mut frame = BytesMut::new();
+        frame.put_u8(message_type);
+        frame.put_u32(payload.len() as u32 + 4);
+        frame.put_slice(payload);
+        RawMessage {
+            message_type,
+            frame: frame.freeze(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_in_log_policy_forwards() {
+        let config = AppConfig {
+            copy_in_policy: CopyInPolicy::Log,
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut guard = CopyInGuard::new(state.clone(), 1);
+
+        guard.on_query("COPY users FROM STDIN WITH (FORMAT csv)");
+        guard
+            .on_copy_in_response(&copy_message(b'G', &[0, 0, 1, 0, 0]))
+            .await;
+
+        let verdict = guard
+            .on_copy_message(&copy_message(b'd', b"1,bob@example.com\n2,plain\n"))
+            .await;
+        assert_eq!(verdict, CopyVerdict::Forward);
+        let verdict = guard.on_copy_message(&copy_message(b'c', b"")).await;
+        assert_eq!(verdict, CopyVerdict::Forward);
+
+        let stats = state.stats.read().await;
+        assert_eq!(stats.copy_in.rows_inspected, 2);
+        assert_eq!(stats.copy_in.rows_flagged, 1);
+        let logs = state.logs.read().await;
+        assert_eq!(logs[0].event_type, "CopyPiiDetected");
+    }
+
+    #[tokio::test]
+    async fn test_copy_in_block_policy_aborts() {
+        let config = AppConfig {
+            copy_in_policy: CopyInPolicy::Block,
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut guard = CopyInGuard::new(state, 1);
+
+        guard.on_query("COPY users FROM STDIN");
+        guard
+            .on_copy_in_response(&copy_message(b'G', &[0, 0, 1, 0, 0]))
+            .await;
+
+        let verdict = guard
+            .on_copy_message(&copy_message(b'd', b"1\tplain\n"))
+            .await;
+        assert_eq!(verdict, CopyVerdict::Forward);
+
+        let verdict = guard
+            .on_copy_message(&copy_message(b'd', b"2\t123-45-6789\n"))
+            .await;
+        assert!(matches!(verdict, CopyVerdict::Abort(ref reason) if reason.contains("row 2")));
+
+        // The rest of the stream is swallowed, then the connection is back to normal
+        let verdict = guard
+            .on_copy_message(&copy_message(b'd', b"3\tplain\n"))
+            .await;
+        assert_eq!(verdict, CopyVerdict::Drop);
+        let verdict = guard.on_copy_message(&copy_message(b'c', b"")).await;
+        assert_eq!(verdict, CopyVerdict::Drop);
+        let verdict = guard
+            .on_copy_message(&copy_message(b'd', b"4\tplain\n"))
+            .await;
+        assert_eq!(verdict, CopyVerdict::Forward);
+    }
+
+    #[tokio::test]
+    async fn test_local_infile_block_policy_aborts_at_end_of_file() {
+        let config = AppConfig {
+            local_infile_policy: CopyInPolicy::Block,
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut guard = LocalInfileGuard::new(state.clone(), 1);
+
+        guard.on_query("LOAD DATA LOCAL INFILE 'u.csv' INTO TABLE u FIELDS TERMINATED BY ','");
+        guard.on_request().await;
+
+        assert_eq!(guard.on_data(b"1,plain\n2,ali").await, CopyVerdict::Forward);
+        assert_eq!(guard.on_data(b"ce@example.com\n").await, CopyVerdict::Drop);
+        assert_eq!(guard.on_data(b"3,plain\n").await, CopyVerdict::Drop);
+        let verdict = guard.on_data(b"").await;
+        assert!(matches!(verdict, CopyVerdict::Abort(ref reason) if reason.contains("row 2")));
+
+        let logs = state.logs.read().await;
+        assert_eq!(logs[0].event_type, "LocalInfileBlocked");
+        drop(logs);
+
+        // The next load starts clean
+        guard.on_request().await;
+        assert_eq!(guard.on_data(b"4,plain\n").await, CopyVerdict::Forward);
+        assert_eq!(guard.on_data(b"").await, CopyVerdict::Forward);
+    }
+
+    fn text_field(name: &'static [u8]) -> FieldDescription {
+        FieldDescription {
+            name: bytes::Bytes::from_static(name),
+            table_oid: 0,
+            column_index: 0,
+            type_oid: type_oid::TEXT,
+            type_len: -1,
+            type_modifier: -1,
+            format_code: FORMAT_TEXT,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mysql_binary_row_masks_string_values() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+
+        let email = "test@example.com";
+        let id = bytes::Bytes::copy_from_slice(&42u32.to_le_bytes());
+        let row = BinaryResultRow {
+            sequence_id: 5,
+            values: vec![
+                Some(BinaryValue::String(BytesMut::from(email.as_bytes()))),
+                Some(BinaryValue::Raw(id.clone())),
+                None,
+            ],
+        };
+
+        let row = anonymizer.on_binary_result_row(row).await.unwrap();
+
+        let Some(BinaryValue::String(masked)) = &row.values[0] else {
+            panic!("expected string value");
+        };
+        assert_ne!(&masked[..], email.as_bytes());
+        assert!(
+            masked.contains(&b'@'),
+            "Masked value should still be an email"
+        );
+        assert!(matches!(&row.values[1], Some(BinaryValue::Raw(v)) if *v == id));
+        assert!(row.values[2].is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mysql_null_rule_clears_text_and_binary_values() {
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Null,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state.clone(), 1);
+        let col = ColumnDefinition {
+            sequence_id: 2,
+            catalog: bytes::Bytes::from_static(b"def"),
+            schema: bytes::Bytes::from_static(b"shop"),
+            table: bytes::Bytes::from_static(b"users"),
+            org_table: bytes::Bytes::from_static(b"users"),
+            name: bytes::Bytes::from_static(b"email"),
+            org_name: bytes::Bytes::from_static(b"email"),
+            character_set: 45,
+            column_length: 255,
+            column_type: 0xfd,
+            flags: 0,
+            decimals: 0,
+            default_value: None,
+        };
+        anonymizer.on_column_definition(&col).await;
+
+        let row = ResultRow {
+            sequence_id: 3,
+            values: vec![Some(BytesMut::from(&b"alice@example.com"[..]))],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        assert!(row.values[0].is_none());
+
+        let row = BinaryResultRow {
+            sequence_id: 4,
+            values: vec![Some(BinaryValue::String(BytesMut::from(
+                &b"alice@example.com"[..],
+            )))],
+        };
+        let row = anonymizer.on_binary_result_row(row).await.unwrap();
+        assert!(row.values[0].is_none());
+
+        let stats = state.get_stats().await;
+        assert_eq!(
+            stats.by_column.count(Some("shop.users"), "email", "null"),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mysql_schema_qualified_rule_uses_session_database() {
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: Some("shop.users".parse().unwrap()),
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        let col = ColumnDefinition {
+            sequence_id: 2,
+            catalog: bytes::Bytes::from_static(b"def"),
+            schema: bytes::Bytes::new(),
+            table: bytes::Bytes::from_static(b"users"),
+            org_table: bytes::Bytes::from_static(b"users"),
+            name: bytes::Bytes::from_static(b"email"),
+            org_name: bytes::Bytes::from_static(b"email"),
+            character_set: 45,
+            column_length: 255,
+            column_type: 0xfd,
+            flags: 0,
+            decimals: 0,
+            default_value: None,
+        };
+
+        anonymizer.set_database(Some("crm".to_string()));
+        anonymizer.on_column_definition(&col).await;
+        assert_eq!(anonymizer.target_cols, vec![None]);
+
+        // COM_INIT_DB shop
+        anonymizer.reset_columns();
+        anonymizer.set_database(Some("shop".to_string()));
+        anonymizer.on_column_definition(&col).await;
+        assert_eq!(
+            anonymizer.target_cols,
+            vec![Some(ColumnRule {
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mysql_aliased_column_matches_original_name() {
+        let mut config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: Some("users".parse().unwrap()),
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        // SELECT u.email AS e FROM users u
+        let aliased = ColumnDefinition {
+            sequence_id: 2,
+            catalog: bytes::Bytes::from_static(b"def"),
+            schema: bytes::Bytes::from_static(b"shop"),
+            table: bytes::Bytes::from_static(b"u"),
+            org_table: bytes::Bytes::from_static(b"users"),
+            name: bytes::Bytes::from_static(b"e"),
+            org_name: bytes::Bytes::from_static(b"email"),
+            character_set: 45,
+            column_length: 255,
+            column_type: 0xfd,
+            flags: 0,
+            decimals: 0,
+            default_value: None,
+        };
+        // SELECT LOWER(email) AS email FROM users u
+        let computed = ColumnDefinition {
+            table: bytes::Bytes::from_static(b"users"),
+            org_table: bytes::Bytes::new(),
+            name: bytes::Bytes::from_static(b"email"),
+            org_name: bytes::Bytes::new(),
+            ..aliased.clone()
+        };
+
+        let state = AppState::new_for_test(config.clone(), "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        anonymizer.on_column_definition(&aliased).await;
+        let email = "alice@example.com";
+        let row = ResultRow {
+            sequence_id: 3,
+            values: vec![Some(BytesMut::from(email.as_bytes()))],
+        };
+        let row = anonymizer.on_result_row(row).await.unwrap();
+        let masked = row.values[0].as_ref().unwrap();
+        assert_ne!(&masked[..], email.as_bytes());
+        assert!(masked.contains(&b'@'));
+
+        anonymizer.reset_columns();
+        anonymizer.on_column_definition(&computed).await;
+        assert_eq!(
+            anonymizer.target_cols,
+            vec![Some(ColumnRule {
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            })]
+        );
+
+        // Strict matching ignores the alias of the computed column
+        config.mysql_column_match = ColumnMatch::Original;
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        anonymizer.on_column_definition(&aliased).await;
+        anonymizer.on_column_definition(&computed).await;
+        assert_eq!(
+            anonymizer.target_cols,
+            vec![
+                Some(ColumnRule {
+                    strategy: MaskStrategy::Email,
+                    options: None,
+                    json_paths: vec![],
+                }),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mysql_field_list_default_value_is_masked() {
+        let config = AppConfig {
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: Some("users".parse().unwrap()),
+                column: "email".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = MySqlAnonymizer::new(state, 1);
+        let email = "bob@example.com";
+        let col = ColumnDefinition {
+            sequence_id: 1,
+            catalog: bytes::Bytes::from_static(b"def"),
+            schema: bytes::Bytes::from_static(b"shop"),
+            table: bytes::Bytes::from_static(b"users"),
+            org_table: bytes::Bytes::from_static(b"users"),
+            name: bytes::Bytes::from_static(b"email"),
+            org_name: bytes::Bytes::from_static(b"email"),
+            character_set: 45,
+            column_length: 255,
+            column_type: 0xfd,
+            flags: 0,
+            decimals: 0,
+            default_value: Some(Some(BytesMut::from(email.as_bytes()))),
+        };
+
+        let col = anonymizer.on_field_list_column(col).await.unwrap();
+
+        let Some(Some(masked)) = &col.default_value else {
+            panic!("expected a default value");
+        };
+        assert_ne!(&masked[..], email.as_bytes());
+        assert!(masked.contains(&b'@'));
+        // Nothing is left over for a later result set
+        assert!(anonymizer.target_cols.is_empty());
+        assert!(anonymizer.column_names.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rule_columns_reset_between_result_sets() {
+        let config = AppConfig {
+            masking_enabled: true,
+            rules: vec![MaskingRule {
+                id: Default::default(),
+                table: None,
+                column: "secret".parse().unwrap(),
+                strategy: MaskStrategy::Email,
+                options: None,
+                json_paths: vec![],
+            }],
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut anonymizer = Anonymizer::new(state, 1, "127.0.0.1".to_string(), 5432);
+        let plain = BytesMut::from(&b"plain"[..]);
+
+        // SELECT id, note FROM a; SELECT note, secret FROM b;
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![text_field(b"id"), text_field(b"note")],
+            })
+            .await;
+        let row = DataRow {
+            values: vec![Some(plain.clone()), Some(plain.clone())],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        assert_eq!(row.values[1].as_ref().unwrap(), &plain);
+        anonymizer.on_command_complete();
+
+        anonymizer
+            .on_row_description(&RowDescription {
+                fields: vec![text_field(b"note"), text_field(b"secret")],
+            })
+            .await;
+        let row = DataRow {
+            values: vec![Some(plain.clone()), Some(plain.clone())],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        assert_eq!(row.values[0].as_ref().unwrap(), &plain);
+        assert_ne!(row.values[1].as_ref().unwrap(), &plain);
+        anonymizer.on_command_complete();
+
+        // A row arriving after CommandComplete is not matched against stale rules
+        let row = DataRow {
+            values: vec![Some(plain.clone()), Some(plain.clone())],
+        };
+        let row = anonymizer.on_data_row(row).await.unwrap();
+        assert_eq!(row.values[1].as_ref().unwrap(), &plain);
+    }
 }
@@ -1,82 +1,117 @@
 use anyhow::Result;
-use clap::{Parser, ValueEnum};
+use clap::Parser;
 use notify::{Config as NotifyConfig, Event, RecommendedWatcher, RecursiveMode, Watcher};
 use std::time::{Duration, Instant};
-use tokio::sync::Semaphore;
 use tokio_util::sync::CancellationToken;
 use tracing::{Instrument, info, info_span, warn};
 
 mod api;
 mod audit;
+mod auth_lockout;
+mod catalog;
 mod config;
+mod copy_in;
 mod db_scanner;
 mod interceptor;
+mod masking_cache;
 mod metrics;
 mod protocol;
+mod scan_history;
+mod scan_jobs;
+mod scan_schedule;
 mod scanner;
 mod state;
+mod stats_window;
 mod telemetry;
-
-use crate::config::AppConfig;
-use crate::interceptor::{Anonymizer, MySqlAnonymizer, MySqlPacketInterceptor, PacketInterceptor};
-use crate::protocol::mysql::{MySqlCodec, MySqlMessage};
-use crate::protocol::postgres::{PgMessage, PostgresCodec};
-use crate::state::{AppState, DbProtocol as StateDbProtocol, LogEntry};
-use bytes::BufMut;
+mod tls;
+mod vault;
+
+use crate::config::{
+    AppConfig, DbProtocol, ListenerConfig, MySqlCapability, ServerOverrides, UpstreamTlsMode,
+};
+use crate::interceptor::{
+    Anonymizer, CopyInGuard, CopyVerdict, LocalInfileGuard, MySqlAnonymizer,
+    MySqlPacketInterceptor, PacketInterceptor,
+};
+use crate::protocol::DEFAULT_MAX_MESSAGE_SIZE;
+use crate::protocol::mysql::{
+    CLIENT_SSL, COM_STMT_EXECUTE, COM_STMT_PREPARE, CR_CONN_HOST_ERROR, ER_UNKNOWN_ERROR,
+    ErrPacket, GenericPacket, MySqlCodec, MySqlMessage, SslRequest,
+};
+use crate::protocol::postgres::{
+    BindMessage, CancelRequest, PgMessage, PostgresCodec, RawMessage, RegularMessage,
+};
+use crate::state::{
+    AppState, BackendKey, ConnectionInfo, DbProtocol as StateDbProtocol, LogEntry, query_type,
+};
+use crate::tls::SharedTlsAcceptor;
+use bytes::{BufMut, BytesMut};
 use chrono::Utc;
-use futures::{SinkExt, StreamExt};
-use rustls_platform_verifier::Verifier;
-use std::fs::File;
-use std::io::BufReader;
+use futures::{FutureExt, SinkExt, StreamExt};
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::{Notify, mpsc, oneshot};
 use tokio_rustls::TlsAcceptor;
 use tokio_rustls::TlsConnector;
-use tokio_rustls::rustls::ClientConfig;
-use tokio_rustls::rustls::crypto::aws_lc_rs::default_provider;
-use tokio_rustls::rustls::pki_types::ServerName;
-use tokio_rustls::rustls::{ServerConfig, pki_types::CertificateDer, pki_types::PrivateKeyDer};
-use tokio_util::codec::Framed;
-
-#[derive(Debug, Clone, Copy, ValueEnum, Default)]
-pub enum DbProtocol {
-    #[default]
-    Postgres,
-    Mysql,
-}
+use tokio_util::codec::{Framed, FramedRead, FramedWrite};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Port to listen on
-    #[arg(short, long, default_value_t = 6543)]
-    port: u16,
+    /// Port to listen on [default: server.listen_port, or 6543]
+    #[arg(short, long)]
+    port: Option<u16>,
 
-    /// Upstream database host
-    #[arg(long, default_value = "127.0.0.1")]
-    upstream_host: String,
+    /// Address to listen on [default: server.listen_address, or 0.0.0.0]
+    #[arg(long)]
+    listen_address: Option<IpAddr>,
 
-    /// Upstream database port
-    #[arg(long, default_value_t = 5432)]
-    upstream_port: u16,
+    /// Upstream database host [default: server.upstream_host, or 127.0.0.1]
+    #[arg(long)]
+    upstream_host: Option<String>,
+
+    /// Upstream database port [default: server.upstream_port, or 5432]
+    #[arg(long)]
+    upstream_port: Option<u16>,
 
     /// Path to configuration file
     #[arg(long, default_value = "proxy.yaml")]
     config: String,
 
-    /// Management API port
-    #[arg(long, default_value_t = 3001)]
-    api_port: u16,
+    /// Management API port [default: server.api_port, or 3001]
+    #[arg(long)]
+    api_port: Option<u16>,
+
+    /// Management API address [default: server.api_bind, or all interfaces]
+    #[arg(long)]
+    api_bind: Option<IpAddr>,
+
+    /// Database protocol to proxy [default: server.protocol, or postgres]
+    #[arg(long, value_enum)]
+    protocol: Option<DbProtocol>,
 
-    /// Database protocol to proxy
-    #[arg(long, value_enum, default_value_t = DbProtocol::Postgres)]
-    protocol: DbProtocol,
+    /// Graceful shutdown timeout in seconds [default: server.shutdown_timeout, or 30]
+    #[arg(long)]
+    shutdown_timeout: Option<u64>,
+}
 
-    /// Graceful shutdown timeout in seconds
-    #[arg(long, default_value_t = 30)]
-    shutdown_timeout: u64,
+impl Args {
+    /// The flags that override the config file's `server` section
+    fn server_overrides(&self) -> ServerOverrides {
+        ServerOverrides {
+            listen_port: self.port,
+            listen_address: self.listen_address,
+            api_port: self.api_port,
+            api_bind: self.api_bind,
+            protocol: self.protocol,
+            upstream_host: self.upstream_host.clone(),
+            upstream_port: self.upstream_port,
+            shutdown_timeout: self.shutdown_timeout,
+        }
+    }
 }
 
 /// Waits for a shutdown signal (SIGTERM, SIGINT, or Ctrl+C)
@@ -104,13 +139,60 @@ async fn shutdown_signal() {
     }
 }
 
+/// Reloads on SIGHUP, as daemons do
+#[cfg(unix)]
+async fn run_sighup_handler(state: AppState, tls_acceptor: SharedTlsAcceptor) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration...");
+        reload_on_signal(&state, &tls_acceptor).await;
+    }
+}
+
+/// Read the config file again, then apply the audit log settings and reload
+/// the TLS certificates, so renewed certificates are picked up at the same
+/// paths. Audit entries go to the log file as it is at the time, so a file
+/// moved away by logrotate is recreated with the next entry.
+async fn reload_on_signal(state: &AppState, tls_acceptor: &SharedTlsAcceptor) {
+    let entry = match state.reload_config().await {
+        Ok(rules_count) => {
+            let config = state.config.read().await.clone();
+            state
+                .audit_logger
+                .update_config(state::audit_logger_config(&config))
+                .await;
+            match tls::acceptor(config.tls.as_ref()) {
+                Ok(acceptor) => {
+                    *tls_acceptor.write().unwrap_or_else(|e| e.into_inner()) = acceptor;
+                }
+                Err(e) => warn!(
+                    "Failed to reload TLS certificates, keeping the current ones: {:#}",
+                    e
+                ),
+            }
+            audit::AuditLogger::config_reload(rules_count, audit::ReloadTrigger::Signal)
+        }
+        Err(e) => {
+            warn!(
+                "Failed to reload configuration, keeping the running one: {}",
+                e
+            );
+            audit::AuditLogger::config_reload_failed(e, audit::ReloadTrigger::Signal)
+        }
+    };
+    state.audit_logger.log(entry).await;
+}
+
 /// Background task that periodically checks upstream database connectivity
-async fn run_health_check_task(
-    state: AppState,
-    upstream_host: String,
-    upstream_port: u16,
-    config: Option<crate::config::HealthCheckConfig>,
-) {
+async fn run_health_check_task(state: AppState, config: Option<crate::config::HealthCheckConfig>) {
     let config = config.unwrap_or_default();
     let interval = Duration::from_secs(config.interval_secs);
     let timeout = Duration::from_secs(config.timeout_secs);
@@ -121,6 +203,8 @@ async fn run_health_check_task(
     );
 
     loop {
+        // Probe the upstream as the config has it now, which a reload may change
+        let (upstream_host, upstream_port) = state.upstream().await;
         let start = Instant::now();
 
         // Try to connect to upstream
@@ -171,18 +255,20 @@ async fn run_health_check_task(
     }
 }
 
-/// Background task that watches the config file for changes and reloads
-async fn run_config_watcher(state: AppState, config_path: String) {
+/// Background task that watches the config file for changes and reloads it
+/// once it has stayed unchanged for `debounce`. The file's directory is
+/// watched rather than the file, so that files replaced by a rename (editors,
+/// our own saves, Kubernetes ConfigMap updates) are followed.
+async fn run_config_watcher(state: AppState, config_path: String, debounce: Duration) {
     use std::path::Path;
-    use std::sync::mpsc::channel;
 
     let path = Path::new(&config_path);
-    let parent = path.parent().unwrap_or(Path::new("."));
-
-    // Create a channel to receive events
-    let (tx, rx) = channel();
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
 
-    // Create a watcher with debounce
+    let (tx, mut rx) = mpsc::unbounded_channel();
     let mut watcher: RecommendedWatcher = match Watcher::new(
         move |res: Result<Event, notify::Error>| {
             if let Ok(event) = res {
@@ -201,7 +287,6 @@ async fn run_config_watcher(state: AppState, config_path: String) {
         }
     };
 
-    // Watch the config file's parent directory
     if let Err(e) = watcher.watch(parent, RecursiveMode::NonRecursive) {
         warn!(
             "Failed to watch config directory: {}. Hot reload disabled.",
@@ -212,47 +297,39 @@ async fn run_config_watcher(state: AppState, config_path: String) {
 
     info!("Config file watcher started for {}", config_path);
 
-    let filename = path
-        .file_name()
-        .and_then(|f| f.to_str())
-        .unwrap_or("proxy.yaml");
-    let mut last_reload = Instant::now();
-    let debounce_duration = Duration::from_secs(1);
-
-    loop {
-        // Check for events with a timeout
-        match rx.recv_timeout(Duration::from_secs(5)) {
-            Ok(event) => {
-                // Check if this event is for our config file
-                let is_config_file = event.paths.iter().any(|p| {
-                    p.file_name()
-                        .and_then(|f| f.to_str())
-                        .map(|f| f == filename)
-                        .unwrap_or(false)
-                });
-
-                if is_config_file && last_reload.elapsed() > debounce_duration {
-                    info!("Config file changed, reloading...");
-                    match state.reload_config().await {
-                        Ok(rules_count) => {
-                            info!("Configuration reloaded: {} rules", rules_count);
-                        }
-                        Err(e) => {
-                            warn!("Failed to reload configuration: {}", e);
-                        }
-                    }
-                    last_reload = Instant::now();
-                }
+    while let Some(event) = rx.recv().await {
+        if event.kind.is_access() {
+            continue;
+        }
+        // Wait for the writes to settle
+        loop {
+            match tokio::time::timeout(debounce, rx.recv()).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => return,
+                Err(_) => break,
             }
-            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-                // No events, continue watching
+        }
+        // Other files in the directory, and our own saves, change nothing
+        if !state.config_file_changed().await {
+            continue;
+        }
+
+        info!("Config file changed, reloading...");
+        let entry = match state.reload_config().await {
+            Ok(rules_count) => {
+                audit::AuditLogger::config_reload(rules_count, audit::ReloadTrigger::FileChange)
             }
-            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-                warn!("Config watcher channel disconnected, stopping watcher");
-                break;
+            Err(e) => {
+                warn!(
+                    "Failed to reload configuration, keeping the running one: {}",
+                    e
+                );
+                audit::AuditLogger::config_reload_failed(e, audit::ReloadTrigger::FileChange)
             }
-        }
+        };
+        state.audit_logger.log(entry).await;
     }
+    warn!("Config watcher channel disconnected, stopping watcher");
 }
 
 #[tokio::main]
@@ -276,43 +353,68 @@ async fn main() -> Result<()> {
     info!("Prometheus metrics initialized");
 
     // Load TLS config if enabled
-    let tls_acceptor = if let Some(tls_config) = &config.tls {
-        if tls_config.enabled {
+    match &config.tls {
+        Some(tls_config) if tls_config.enabled => {
             info!("TLS enabled. Loading certs from {}", tls_config.cert_path);
-            let certs = load_certs(&tls_config.cert_path)?;
-            let key = load_keys(&tls_config.key_path)?;
-            let config = ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(certs, key)?;
-            Some(TlsAcceptor::from(Arc::new(config)))
-        } else {
-            info!("TLS disabled in config.");
-            None
+            if tls_config.require_client_auth {
+                info!("Client certificates required (mutual TLS)");
+            }
         }
-    } else {
-        info!("TLS not configured.");
-        None
-    };
+        Some(_) => info!("TLS disabled in config."),
+        None => info!("TLS not configured."),
+    }
+    let tls_acceptor: SharedTlsAcceptor =
+        Arc::new(std::sync::RwLock::new(tls::acceptor(config.tls.as_ref())?));
+
+    // Validate upstream TLS files up front so misconfiguration fails at startup
+    if config.upstream_tls_enabled() {
+        tls::upstream_client_config(&config)?;
+        info!(
+            "Upstream TLS enabled (mode: {:?})",
+            config.upstream_tls_mode
+        );
+    }
+
+    // One listener per `listeners` entry, or a single one from `server` and
+    // the CLI flags overriding it
+    let server_overrides = args.server_overrides();
+    let server = config.server.with_overrides(&server_overrides);
+    let listeners = config.proxy_listeners(&server_overrides);
+    // Health checks and schema scans target the first listener's upstream,
+    // looked up again each time
+    let primary = listeners[0].clone();
 
     // Initialize shared state
-    let db_protocol = match args.protocol {
+    let db_protocol = match primary.protocol {
         DbProtocol::Postgres => StateDbProtocol::Postgres,
         DbProtocol::Mysql => StateDbProtocol::MySql,
     };
-    let state = AppState::new(
+    let mut state = AppState::new(
         config.clone(),
         args.config.clone(),
-        args.upstream_host.clone(),
-        args.upstream_port,
+        primary.upstream_host.clone(),
+        primary.upstream_port,
         db_protocol,
     )
-    .with_metrics(metrics_handle);
+    .with_metrics(metrics_handle)
+    .with_server_overrides(server_overrides)
+    .with_primary_listener(primary.port);
+
+    // Open the token vault before any connection can tokenize a value
+    if let Some(tokenization) = &config.tokenization {
+        state = state.with_token_vault(vault::TokenVault::open(tokenization).await?);
+    }
+    if let Some(schedule) = &config.scanner.schedule {
+        scan_schedule::scheduled_scan(schedule)?;
+    }
+    if let Some(path) = &config.scanner.history_file {
+        state = state.with_scan_history(scan_history::ScanHistory::open(path).await?);
+    }
 
     // Start Management API in a separate task
-    let api_port = args.api_port;
-    let api_state = state.clone();
+    let api_server = api::ApiServer::bind(state.clone()).await?;
     tokio::spawn(async move {
-        if let Err(e) = api::start_api_server(api_port, api_state).await {
+        if let Err(e) = api_server.serve().await {
             tracing::error!("API server error: {}", e);
         }
     });
@@ -325,82 +427,179 @@ async fn main() -> Result<()> {
         .unwrap_or(true);
 
     if health_check_enabled {
-        let health_state = state.clone();
-        let health_host = args.upstream_host.clone();
-        let health_port = args.upstream_port;
-        let health_config = config.health_check.clone();
-        tokio::spawn(async move {
-            run_health_check_task(health_state, health_host, health_port, health_config).await;
-        });
+        tokio::spawn(run_health_check_task(
+            state.clone(),
+            config.health_check.clone(),
+        ));
     }
 
     // Start config file watcher for hot reload
-    let watch_state = state.clone();
-    let config_path = args.config.clone();
-    tokio::spawn(async move {
-        run_config_watcher(watch_state, config_path).await;
-    });
+    if config.config_watch.enabled {
+        tokio::spawn(run_config_watcher(
+            state.clone(),
+            args.config.clone(),
+            Duration::from_millis(config.config_watch.debounce_ms),
+        ));
+    }
 
-    // Start stats history recorder (every 5 seconds)
-    let stats_state = state.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
-        loop {
-            interval.tick().await;
-            stats_state.record_history_snapshot().await;
-        }
-    });
+    #[cfg(unix)]
+    tokio::spawn(run_sighup_handler(state.clone(), tls_acceptor.clone()));
 
-    info!("Starting DB Proxy on port {}", args.port);
-    info!(
-        "Forwarding to upstream at {}:{}",
-        args.upstream_host, args.upstream_port
+    // Start stats history recorder
+    let stats_interval = config
+        .api
+        .as_ref()
+        .map(|a| a.stats_history_interval_secs)
+        .unwrap_or_else(crate::config::default_stats_history_interval);
+    tokio::spawn(
+        state
+            .clone()
+            .run_history_recorder(Duration::from_secs(stats_interval.max(1))),
     );
-    info!("Protocol: {:?}", args.protocol);
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
-    let protocol = args.protocol;
+    // Start the scan scheduler; it idles until `scanner.schedule` is set
+    tokio::spawn(scan_schedule::run_scan_scheduler(state.clone()));
 
     // Create cancellation token for graceful shutdown
     let cancel_token = CancellationToken::new();
-    let shutdown_timeout = args.shutdown_timeout;
+    let shutdown_timeout = server.shutdown_timeout;
 
-    // Connection limiting
-    let max_connections = config.limits.as_ref().and_then(|l| l.max_connections);
-    let connection_semaphore = max_connections.map(|max| {
+    // Connection and rate limits are read on every accept, so that
+    // `POST /config` and reloads apply to new connections
+    if let Some(max) = config.limits.as_ref().and_then(|l| l.max_connections) {
         info!("Connection limit set to {}", max);
-        Arc::new(Semaphore::new(max))
-    });
-
-    // Rate limiting state
-    let rate_limit = config
+    }
+    if let Some(rate) = config
         .limits
         .as_ref()
-        .and_then(|l| l.connections_per_second);
-    if let Some(rate) = rate_limit {
+        .and_then(|l| l.connections_per_second)
+    {
         info!("Rate limit set to {} connections/second", rate);
     }
-    let mut rate_limit_tokens: u32 = rate_limit.unwrap_or(0);
-    let mut last_refill = Instant::now();
 
-    // Accept connections until shutdown signal
+    // Bind every listener before accepting on any of them
+    let mut accept_loops = tokio::task::JoinSet::new();
+    for listener_config in listeners {
+        let name = listener_config.name();
+        let listener = tokio::net::TcpListener::bind(std::net::SocketAddr::new(
+            server.listen_address,
+            listener_config.port,
+        ))
+        .await?;
+        info!(
+            listener = %name,
+            "Starting DB Proxy on port {} ({:?}), forwarding to upstream at {}:{}",
+            listener_config.port,
+            listener_config.protocol,
+            listener_config.upstream_host,
+            listener_config.upstream_port
+        );
+        accept_loops.spawn(run_listener(
+            listener,
+            listener_config,
+            state.clone(),
+            tls_acceptor.clone(),
+            cancel_token.clone(),
+        ));
+    }
+
+    // Accept connections until shutdown signal or a listener fails
+    let mut listener_error = None;
+    tokio::select! {
+        _ = shutdown_signal() => {
+            info!("Shutdown signal received, stopping accept loops...");
+        }
+        Some(joined) = accept_loops.join_next() => {
+            let error = match joined {
+                Ok(Ok(())) => anyhow::anyhow!("Listener stopped unexpectedly"),
+                Ok(Err(e)) => e,
+                Err(e) => e.into(),
+            };
+            tracing::error!(error = %error, "Listener failed, shutting down");
+            listener_error = Some(error);
+        }
+    }
+
+    // Graceful shutdown: wait for active connections to drain
+    info!(
+        "Waiting for {} active connections to close (timeout: {}s)...",
+        state.active_connections.load(Ordering::Relaxed),
+        shutdown_timeout
+    );
+
+    // Signal all listeners and connections to shutdown
+    cancel_token.cancel();
+    while accept_loops.join_next().await.is_some() {}
+
+    // Wait for connections to drain with timeout
+    let drain_start = std::time::Instant::now();
+    let timeout_duration = std::time::Duration::from_secs(shutdown_timeout);
+
+    while state.active_connections.load(Ordering::Relaxed) > 0 {
+        if drain_start.elapsed() >= timeout_duration {
+            warn!(
+                "Shutdown timeout reached, {} connections still active",
+                state.active_connections.load(Ordering::Relaxed)
+            );
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    // Tokens handed out must stay resolvable after a restart
+    if let Some(vault) = &state.token_vault {
+        vault.flush().await;
+    }
+
+    info!("Shutdown complete.");
+    match listener_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Accept connections on one listener until shutdown, spawning a task per
+/// connection. The connection limit is shared by all listeners, the rate
+/// limit applies to each.
+async fn run_listener(
+    listener: tokio::net::TcpListener,
+    listener_config: ListenerConfig,
+    state: AppState,
+    tls_acceptor: SharedTlsAcceptor,
+    cancel_token: CancellationToken,
+) -> Result<()> {
+    let name: Arc<str> = listener_config.name().into();
+    let protocol = listener_config.protocol;
+    let mut rate_limit_tokens: u32 = 0;
+    let mut last_refill: Option<Instant> = None;
+
     loop {
         tokio::select! {
             // Wait for new connection
             accept_result = listener.accept() => {
                 let (client_socket, client_addr) = accept_result?;
+                let (max_connections, rate_limit) = {
+                    let config = state.config.read().await;
+                    let limits = config.limits.as_ref();
+                    (
+                        limits.and_then(|l| l.max_connections),
+                        limits.and_then(|l| l.connections_per_second),
+                    )
+                };
 
                 // Rate limiting check
                 if let Some(max_rate) = rate_limit {
                     // Refill tokens based on elapsed time
-                    let elapsed = last_refill.elapsed();
-                    if elapsed >= Duration::from_secs(1) {
+                    if last_refill.is_none_or(|at| at.elapsed() >= Duration::from_secs(1)) {
                         rate_limit_tokens = max_rate;
-                        last_refill = Instant::now();
+                        last_refill = Some(Instant::now());
                     }
+                    // The rate may have been lowered since the last refill
+                    rate_limit_tokens = rate_limit_tokens.min(max_rate);
 
                     if rate_limit_tokens == 0 {
-                        warn!("Rate limit exceeded, rejecting connection from {}", client_addr);
+                        warn!(listener = %name, "Rate limit exceeded, rejecting connection from {}", client_addr);
+                        metrics::record_connection_rejected(&name, "rate_limit");
                         drop(client_socket);
                         continue;
                     }
@@ -408,25 +607,34 @@ async fn main() -> Result<()> {
                 }
 
                 // Connection limit check
-                let permit = if let Some(ref sem) = connection_semaphore {
-                    match sem.clone().try_acquire_owned() {
-                        Ok(permit) => Some(permit),
-                        Err(_) => {
-                            warn!("Connection limit reached, rejecting connection from {}", client_addr);
-                            drop(client_socket);
-                            continue;
-                        }
-                    }
-                } else {
-                    None
+                let Some(permit) = state.try_admit_connection(max_connections) else {
+                    warn!(listener = %name, "Connection limit reached, rejecting connection from {}", client_addr);
+                    metrics::record_connection_rejected(&name, "max_connections");
+                    drop(client_socket);
+                    continue;
                 };
 
-                info!("Accepted connection from {}", client_addr);
+                info!(listener = %name, "Accepted connection from {}", client_addr);
 
-                let upstream_host = args.upstream_host.clone();
-                let upstream_port = args.upstream_port;
+                let name = name.clone();
+                // The upstream may have changed on a reload
+                let (upstream_host, upstream_port) = state
+                    .config
+                    .read()
+                    .await
+                    .upstream_for(&state.server_overrides, listener_config.port)
+                    .unwrap_or_else(|| {
+                        (
+                            listener_config.upstream_host.clone(),
+                            listener_config.upstream_port,
+                        )
+                    });
                 let state = state.clone();
-                let tls_acceptor = tls_acceptor.clone();
+                let tls_acceptor = tls_acceptor
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                let shutdown = cancel_token.clone();
 
                 tokio::spawn(async move {
                     // Hold the permit for the duration of the connection
@@ -434,15 +642,21 @@ async fn main() -> Result<()> {
 
                     let span = info_span!(
                         "connection",
+                        listener = %name,
                         client.addr = %client_addr,
                         upstream.host = %upstream_host,
                         upstream.port = %upstream_port,
-                        protocol = ?protocol
+                        protocol = ?protocol,
+                        client.cert_cn = tracing::field::Empty,
+                        db.user = tracing::field::Empty,
+                        db.name = tracing::field::Empty,
+                        db.application_name = tracing::field::Empty
                     );
 
                     async {
                         state.active_connections.fetch_add(1, Ordering::Relaxed);
                         state.record_connection().await;
+                        metrics::record_connection_opened(&name);
                         let result = match protocol {
                             DbProtocol::Postgres => {
                                 process_postgres_connection(
@@ -451,6 +665,7 @@ async fn main() -> Result<()> {
                                     upstream_port,
                                     state.clone(),
                                     tls_acceptor,
+                                    shutdown,
                                 )
                                 .await
                             }
@@ -460,10 +675,13 @@ async fn main() -> Result<()> {
                                     upstream_host,
                                     upstream_port,
                                     state.clone(),
+                                    tls_acceptor,
+                                    shutdown,
                                 )
                                 .await
                             }
                         };
+                        metrics::record_connection_closed(&name);
                         state.active_connections.fetch_sub(1, Ordering::Relaxed);
 
                         if let Err(e) = result {
@@ -475,41 +693,10 @@ async fn main() -> Result<()> {
                 });
             }
 
-            // Wait for shutdown signal
-            _ = shutdown_signal() => {
-                info!("Shutdown signal received, stopping accept loop...");
-                break;
-            }
-        }
-    }
-
-    // Graceful shutdown: wait for active connections to drain
-    info!(
-        "Waiting for {} active connections to close (timeout: {}s)...",
-        state.active_connections.load(Ordering::Relaxed),
-        shutdown_timeout
-    );
-
-    // Signal all connections to shutdown
-    cancel_token.cancel();
-
-    // Wait for connections to drain with timeout
-    let drain_start = std::time::Instant::now();
-    let timeout_duration = std::time::Duration::from_secs(shutdown_timeout);
-
-    while state.active_connections.load(Ordering::Relaxed) > 0 {
-        if drain_start.elapsed() >= timeout_duration {
-            warn!(
-                "Shutdown timeout reached, {} connections still active",
-                state.active_connections.load(Ordering::Relaxed)
-            );
-            break;
+            // Stop accepting once shutdown starts
+            _ = cancel_token.cancelled() => return Ok(()),
         }
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
-
-    info!("Shutdown complete.");
-    Ok(())
 }
 
 // ============================================================================
@@ -522,10 +709,21 @@ async fn process_postgres_connection(
     upstream_port: u16,
     state: AppState,
     tls_acceptor: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
 ) -> Result<()> {
-    let mut buffer = [0u8; 8];
-    let n = client_socket.peek(&mut buffer).await?;
-    if n >= 8 {
+    let client_addr = client_socket.peer_addr().ok().map(|addr| addr.ip());
+    // A client may send GSSENCRequest, then SSLRequest, then its Startup
+    // message on the same socket, trying each encryption method in turn.
+    loop {
+        let mut buffer = [0u8; 8];
+        let n = tokio::select! {
+            n = client_socket.peek(&mut buffer) => n?,
+            // Nothing to drain before the client has even started its session
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        if n < 8 {
+            break;
+        }
         let len = u32::from_be_bytes(
             buffer[0..4]
                 .try_into()
@@ -537,6 +735,21 @@ async fn process_postgres_connection(
                 .map_err(|_| anyhow::anyhow!("Invalid startup message code"))?,
         );
 
+        if len >= 16 && code == 80877102 {
+            // CancelRequest on its own connection (libpq's Ctrl+C)
+            return forward_cancel_request(client_socket, upstream_host, upstream_port, state)
+                .await;
+        }
+
+        if len == 8 && code == 80877104 {
+            // GSSENCRequest: GSSAPI encryption is not supported
+            let mut trash = [0u8; 8];
+            client_socket.read_exact(&mut trash).await?;
+            info!("Received GSSENCRequest, denying...");
+            client_socket.write_all(b"N").await?;
+            continue;
+        }
+
         if len == 8 && code == 80877103 {
             // It is an SSLRequest
             let mut trash = [0u8; 8];
@@ -547,30 +760,186 @@ async fn process_postgres_connection(
                 client_socket.write_all(b"S").await?;
 
                 let tls_stream = acceptor.accept(client_socket).await?;
-                return handle_postgres_protocol(tls_stream, upstream_host, upstream_port, state)
-                    .await;
+                let client_cn = tls_stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(tls::certificate_common_name);
+                if let Some(cn) = &client_cn {
+                    info!("Client presented certificate for {}", cn);
+                    tracing::Span::current().record("client.cert_cn", cn.as_str());
+                }
+                return handle_postgres_protocol(
+                    tls_stream,
+                    upstream_host,
+                    upstream_port,
+                    state,
+                    client_cn,
+                    client_addr,
+                    shutdown,
+                )
+                .await;
             } else {
                 info!("Received SSLRequest, denying (TLS not configured)...");
                 client_socket.write_all(b"N").await?;
+                continue;
             }
         }
+
+        break;
     }
 
-    handle_postgres_protocol(client_socket, upstream_host, upstream_port, state).await
+    handle_postgres_protocol(
+        client_socket,
+        upstream_host,
+        upstream_port,
+        state,
+        None,
+        client_addr,
+        shutdown,
+    )
+    .await
+}
+
+/// Forward a CancelRequest to upstream over a new connection. Postgres never
+/// replies to a cancel, so both sides are closed once it has been sent.
+async fn forward_cancel_request(
+    client_socket: tokio::net::TcpStream,
+    upstream_host: String,
+    upstream_port: u16,
+    state: AppState,
+) -> Result<()> {
+    let mut client_framed = Framed::new(client_socket, PostgresCodec::new());
+    let cancel = match client_framed.next().await {
+        Some(Ok(PgMessage::CancelRequest(cancel))) => cancel,
+        Some(Err(e)) => return Err(e),
+        _ => return Err(anyhow::anyhow!("Expected CancelRequest")),
+    };
+    log_cancel_request(&state, &cancel).await;
+
+    let upstream_socket = connect_upstream(&state, &upstream_host, upstream_port).await?;
+
+    let mut upstream_framed = Framed::new(upstream_socket, PostgresCodec::new_upstream());
+    upstream_framed
+        .send(PgMessage::CancelRequest(cancel))
+        .await?;
+    Ok(())
+}
+
+/// Record which proxied connection a CancelRequest is aimed at
+async fn log_cancel_request(state: &AppState, cancel: &CancelRequest) {
+    let target = state
+        .find_cancel_target(cancel.process_id, &cancel.secret_key)
+        .await;
+    let content = match target {
+        Some(connection_id) => format!(
+            "Cancel request for connection {:x} (backend PID {})",
+            connection_id, cancel.process_id
+        ),
+        None => format!(
+            "Cancel request for unknown backend PID {}",
+            cancel.process_id
+        ),
+    };
+    info!("{}", content);
+
+    let id = format!("{:x}", rand::random::<u128>());
+    state
+        .add_log(LogEntry {
+            id,
+            timestamp: Utc::now(),
+            connection_id: target.unwrap_or(0),
+            event_type: "CancelRequest".to_string(),
+            content,
+            details: None,
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        })
+        .await;
+}
+
+/// Delay before retrying a failed upstream connect; doubles per attempt
+const UPSTREAM_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Connect to the upstream, bounding each attempt by
+/// `limits.upstream_connect_timeout_secs` and retrying with a short backoff
+async fn connect_upstream(
+    state: &AppState,
+    upstream_host: &str,
+    upstream_port: u16,
+) -> Result<tokio::net::TcpStream> {
+    let (connect_timeout, retries) = {
+        let config = state.config.read().await;
+        let limits = config.limits.as_ref();
+        (
+            Duration::from_secs(
+                limits
+                    .map(|l| l.upstream_connect_timeout_secs)
+                    .unwrap_or(30),
+            ),
+            limits.map(|l| l.upstream_connect_retries).unwrap_or(2),
+        )
+    };
+
+    let address = format!("{}:{}", upstream_host, upstream_port);
+    let mut backoff = UPSTREAM_RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let error =
+            match tokio::time::timeout(connect_timeout, tokio::net::TcpStream::connect(&address))
+                .await
+            {
+                Ok(Ok(socket)) => return Ok(socket),
+                Ok(Err(e)) => anyhow::Error::from(e),
+                Err(_) => {
+                    metrics::record_upstream_timeout();
+                    anyhow::anyhow!("timed out after {:?}", connect_timeout)
+                }
+            };
+        if attempt >= retries {
+            return Err(error.context(format!(
+                "Upstream {} unavailable after {} attempt(s)",
+                address,
+                attempt + 1
+            )));
+        }
+        attempt += 1;
+        warn!(
+            "Upstream connect to {} failed ({}), retrying in {:?}",
+            address, error, backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
 }
 
-/// Creates a TLS ClientConfig that uses the OS native certificate verifier.
-pub fn create_upstream_tls_config() -> ClientConfig {
-    // Initialize the platform-specific verifier
-    let provider = Arc::new(default_provider());
-    let verifier = Arc::new(Verifier::new(provider).expect("Failed to create platform verifier"));
+/// Refuse a client whose session can't be proxied safely: wait for its
+/// startup packet, then answer with a FATAL ErrorResponse and close.
+async fn reject_postgres_client<S>(client_socket: S, message: &str) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut client_framed = Framed::new(client_socket, PostgresCodec::new());
+    loop {
+        match client_framed.next().await {
+            Some(Ok(PgMessage::SSLRequest)) => {
+                client_framed.get_mut().write_all(b"N").await?;
+            }
+            Some(Ok(_)) => break,
+            Some(Err(e)) => return Err(e),
+            None => return Err(anyhow::anyhow!("{}", message)),
+        }
+    }
 
-    ClientConfig::builder()
-        // .dangerous() is required because we are overriding the default
-        // WebPki verifier with a custom one (the platform verifier).
-        .dangerous()
-        .with_custom_certificate_verifier(verifier)
-        .with_no_client_auth()
+    client_framed
+        .send(PgMessage::Regular(RegularMessage::error_response(
+            "FATAL", "08001", message,
+        )))
+        .await?;
+    Err(anyhow::anyhow!("{}", message))
 }
 
 async fn handle_postgres_protocol<S>(
@@ -578,32 +947,25 @@ async fn handle_postgres_protocol<S>(
     upstream_host: String,
     upstream_port: u16,
     state: AppState,
+    client_cn: Option<String>,
+    client_addr: Option<IpAddr>,
+    shutdown: CancellationToken,
 ) -> Result<()>
 where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
-    // Get timeout configuration
-    let (connect_timeout, idle_timeout) = {
-        let config = state.config.read().await;
-        let limits = config.limits.as_ref();
-        (
-            Duration::from_secs(limits.map(|l| l.connect_timeout_secs).unwrap_or(30)),
-            Duration::from_secs(limits.map(|l| l.idle_timeout_secs).unwrap_or(300)),
-        )
+    let mut upstream_socket = match connect_upstream(&state, &upstream_host, upstream_port).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("{:#}", e);
+            return reject_postgres_client(client_socket, "IronVeil: upstream unavailable").await;
+        }
     };
 
-    // Create upstream connection with timeout
-    let mut upstream_socket = tokio::time::timeout(
-        connect_timeout,
-        tokio::net::TcpStream::connect(format!("{}:{}", upstream_host, upstream_port)),
-    )
-    .await
-    .map_err(|_| anyhow::anyhow!("Upstream connection timeout after {:?}", connect_timeout))??;
-
     // Check if upstream TLS is enabled
-    let upstream_tls_enabled = {
+    let (upstream_tls_enabled, tls_mode) = {
         let config = state.config.read().await;
-        config.upstream_tls
+        (config.upstream_tls_enabled(), config.upstream_tls_mode)
     };
 
     if upstream_tls_enabled {
@@ -626,144 +988,616 @@ where
             info!("Upstream accepted SSLRequest. Upgrading connection...");
 
             // 3. Upgrade to TLS
-            let client_config = Arc::new(create_upstream_tls_config());
+            let (client_config, domain) = {
+                let config = state.config.read().await;
+                (
+                    Arc::new(tls::upstream_client_config(&config)?),
+                    tls::upstream_server_name(&config, &upstream_host)?,
+                )
+            };
             let connector = TlsConnector::from(client_config);
 
-            let domain = ServerName::try_from(upstream_host.as_str())
-                .map_err(|_| anyhow::anyhow!("Invalid DNS name for upstream host"))?
-                .to_owned();
-
-            let upstream_tls_stream = connector.connect(domain, upstream_socket).await?;
+            let upstream_tls_stream = match connector.connect(domain, upstream_socket).await {
+                Ok(stream) => stream,
+                Err(e) if tls_mode != UpstreamTlsMode::Prefer => {
+                    return reject_postgres_client(
+                        client_socket,
+                        &format!("IronVeil: upstream TLS handshake failed: {}", e),
+                    )
+                    .await;
+                }
+                Err(e) => return Err(e.into()),
+            };
 
             // 4. Continue with TLS stream
             return handle_postgres_protocol_inner(
                 client_socket,
                 upstream_tls_stream,
+                (upstream_host, upstream_port),
                 state,
-                idle_timeout,
+                client_cn,
+                client_addr,
+                shutdown,
             )
             .await;
+        } else if tls_mode == UpstreamTlsMode::Prefer {
+            tracing::warn!("Upstream denied SSLRequest. Falling back to cleartext.");
         } else {
-            tracing::warn!(
-                "Upstream denied SSLRequest. Falling back to cleartext (or aborting if strict)."
+            tracing::error!(
+                "Upstream denied SSLRequest but upstream_tls_mode is {:?}. Rejecting client.",
+                tls_mode
             );
-            // For now, we fall back to cleartext as per standard behavior, but you might want to enforce it.
+            return reject_postgres_client(
+                client_socket,
+                "IronVeil: upstream server does not support TLS, which upstream_tls_mode requires",
+            )
+            .await;
         }
     }
 
     // Cleartext connection
-    handle_postgres_protocol_inner(client_socket, upstream_socket, state, idle_timeout).await
+    handle_postgres_protocol_inner(
+        client_socket,
+        upstream_socket,
+        (upstream_host, upstream_port),
+        state,
+        client_cn,
+        client_addr,
+        shutdown,
+    )
+    .await
 }
 
-async fn handle_postgres_protocol_inner<S, U>(
-    client_socket: S,
-    upstream_socket: U,
-    state: AppState,
-    idle_timeout: Duration,
-) -> Result<()>
-where
-    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
-    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
-{
-    let mut client_framed = Framed::new(client_socket, PostgresCodec::new());
-    let mut upstream_framed = Framed::new(upstream_socket, PostgresCodec::new_upstream());
+/// Capacity of the channels connecting a connection's two pump tasks
+const PUMP_CHANNEL_CAPACITY: usize = 64;
+
+/// Client messages the upstream -> client pump needs to interpret responses
+enum ClientEvent {
+    Database(String),
+    Policy(Option<String>),
+    Bind(BindMessage),
+    Sync,
+    /// The client sent an SSLRequest mid-stream; answer with 'N'
+    DenySsl,
+}
 
-    let connection_id = rand::random::<u64>() as usize;
-    let mut interceptor = Anonymizer::new(state.clone(), connection_id);
+/// Upstream messages the client -> upstream pump needs for COPY inspection
+enum UpstreamEvent {
+    CopyInResponse(RawMessage),
+    ReadyForQuery,
+}
 
-    loop {
-        tokio::select! {
-            // Client -> Upstream
-            msg = client_framed.next() => {
-                match msg {
-                    Some(Ok(msg)) => {
-                        match msg {
-                            PgMessage::SSLRequest => {
-                                info!("Received SSLRequest, denying...");
-                                // Deny SSL, force cleartext
-                                client_framed.get_mut().write_all(b"N").await?;
-                            }
-                            PgMessage::Query(ref q) => {
-                                let query_str = String::from_utf8_lossy(&q.query).to_string();
-                                let id = format!("{:x}", rand::random::<u128>());
-                                state.add_log(LogEntry {
-                                    id,
-                                    timestamp: Utc::now(),
-                                    connection_id,
-                                    event_type: "Query".to_string(),
-                                    content: query_str.clone(),
-                                    details: None,
-                                }).await;
-
-                                // Record query type stats
-                                let query_type = query_str
-                                    .split_whitespace()
-                                    .next()
-                                    .unwrap_or("OTHER")
-                                    .to_uppercase();
-                                state.record_query(&query_type).await;
-
-                                upstream_framed.send(msg).await?;
-                            }
-                            PgMessage::Parse(ref p) => {
-                                let query_str = String::from_utf8_lossy(&p.query).to_string();
-                                let id = format!("{:x}", rand::random::<u128>());
-                                state.add_log(LogEntry {
-                                    id,
-                                    timestamp: Utc::now(),
-                                    connection_id,
-                                    event_type: "Parse".to_string(),
-                                    content: query_str.clone(),
-                                    details: None,
-                                }).await;
-
-                                // Record query type stats for prepared statements
-                                let query_type = query_str
-                                    .split_whitespace()
-                                    .next()
-                                    .unwrap_or("OTHER")
-                                    .to_uppercase();
-                                state.record_query(&query_type).await;
-
-                                upstream_framed.send(msg).await?;
-                            }
-                            _ => {
-                                // Forward other messages (Startup, Query, etc.)
-                                upstream_framed.send(msg).await?;
-                            }
+/// When a connection last carried a message in either direction
+#[derive(Clone)]
+struct Activity {
+    started: Instant,
+    last_ms: Arc<AtomicU64>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+}
+
+/// Client requests (Query, Sync, ...) still awaiting their ReadyForQuery
+#[derive(Default)]
+struct InFlight {
+    requests: AtomicUsize,
+    drained: Notify,
+    /// When each outstanding request was sent, oldest first; `None` for
+    /// requests that don't run a query (startup, function calls)
+    sent_at: std::sync::Mutex<std::collections::VecDeque<Option<Instant>>>,
+}
+
+impl InFlight {
+    fn start(&self) {
+        self.push(None);
+    }
+
+    /// Start a request whose duration is recorded as a query
+    fn start_query(&self) {
+        self.push(Some(Instant::now()));
+    }
+
+    fn push(&self, sent_at: Option<Instant>) {
+        self.sent_at.lock().unwrap().push_back(sent_at);
+        self.requests.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Complete the oldest request, returning its duration if it was a query
+    fn finish(&self) -> Option<Duration> {
+        let previous = self
+            .requests
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        if previous == Ok(1) {
+            self.drained.notify_one();
+        }
+        let sent_at = self.sent_at.lock().unwrap().pop_front().flatten();
+        sent_at.map(|sent_at| sent_at.elapsed())
+    }
+
+    async fn wait_drained(&self) {
+        while self.requests.load(Ordering::Acquire) > 0 {
+            self.drained.notified().await;
+        }
+    }
+}
+
+/// Proxies a Postgres session with one task per direction, so a slow client
+/// doesn't stall reads from upstream and vice versa.
+async fn handle_postgres_protocol_inner<S, U>(
+    client_socket: S,
+    upstream_socket: U,
+    (upstream_host, upstream_port): (String, u16),
+    state: AppState,
+    client_cn: Option<String>,
+    client_addr: Option<IpAddr>,
+    shutdown: CancellationToken,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (max_message_size, shutdown_grace, idle_timeout) = {
+        let config = state.config.read().await;
+        let limits = config.limits.as_ref();
+        (
+            limits
+                .map(|l| l.max_message_size)
+                .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            Duration::from_secs(limits.map(|l| l.shutdown_grace_secs).unwrap_or(10)),
+            Duration::from_secs(limits.map(|l| l.idle_timeout_secs).unwrap_or(300)),
+        )
+    };
+    let (client_reader, client_writer) = tokio::io::split(client_socket);
+    let (upstream_reader, upstream_writer) = tokio::io::split(upstream_socket);
+
+    let connection_id = rand::random::<u64>() as usize;
+    let mut connection_info = ConnectionInfo::new(connection_id, StateDbProtocol::Postgres);
+    connection_info.client_cn = client_cn;
+    connection_info.client_addr = client_addr;
+    let kill_switch = state.register_connection(connection_info.clone()).await;
+
+    let (client_events_tx, client_events_rx) = mpsc::channel(PUMP_CHANNEL_CAPACITY);
+    let (upstream_events_tx, upstream_events_rx) = mpsc::channel(PUMP_CHANNEL_CAPACITY);
+    let (backend_pid_tx, mut backend_pid_rx) = oneshot::channel();
+    let activity = Activity::new();
+    let in_flight = Arc::new(InFlight::default());
+
+    let client_pump = ClientPump {
+        client: FramedRead::new(
+            client_reader,
+            PostgresCodec::new().with_max_message_size(max_message_size),
+        ),
+        upstream: FramedWrite::new(upstream_writer, PostgresCodec::new_upstream()),
+        copy_in: CopyInGuard::new(state.clone(), connection_id),
+        events: client_events_tx,
+        upstream_events: upstream_events_rx,
+        connection_info,
+        session_started: false,
+        state: state.clone(),
+        activity: activity.clone(),
+        in_flight: in_flight.clone(),
+        shutdown: shutdown.clone(),
+    };
+    let upstream_pump = UpstreamPump {
+        upstream: FramedRead::new(
+            upstream_reader,
+            PostgresCodec::new_upstream().with_max_message_size(max_message_size),
+        ),
+        client: FramedWrite::new(client_writer, PostgresCodec::new()),
+        interceptor: Anonymizer::new(state.clone(), connection_id, upstream_host, upstream_port),
+        client_events: client_events_rx,
+        events: upstream_events_tx,
+        copy_in_active: false,
+        backend_pid: Some(backend_pid_tx),
+        state: state.clone(),
+        connection_id,
+        activity: activity.clone(),
+        in_flight,
+        shutdown: shutdown.clone(),
+    };
+    let mut client_task = tokio::spawn(client_pump.run().in_current_span());
+    let mut upstream_task = tokio::spawn(upstream_pump.run().in_current_span());
+
+    // Stop as soon as either side closes, errors or the connection goes idle.
+    // On shutdown the client side stops first and the upstream closes once
+    // in-flight queries are done, bounded by the grace period.
+    let mut client_done = false;
+    let mut upstream_done = false;
+    let mut grace_deadline: Option<tokio::time::Instant> = None;
+    let joined = loop {
+        tokio::select! {
+            joined = &mut client_task, if !client_done => {
+                client_done = true;
+                if grace_deadline.is_none() || !matches!(joined, Ok(Ok(()))) {
+                    break joined;
+                }
+            }
+            joined = &mut upstream_task => {
+                upstream_done = true;
+                break joined;
+            }
+            // Killed through the API: no grace, both sockets close now
+            _ = kill_switch.cancelled() => {
+                info!("Connection killed, closing");
+                break Ok(Ok(()));
+            }
+            _ = shutdown.cancelled(), if grace_deadline.is_none() => {
+                info!("Shutting down, letting in-flight queries finish (grace period {:?})", shutdown_grace);
+                grace_deadline = Some(tokio::time::Instant::now() + shutdown_grace);
+            }
+            _ = tokio::time::sleep_until(grace_deadline.unwrap_or_else(tokio::time::Instant::now)), if grace_deadline.is_some() => {
+                warn!("Shutdown grace period expired, closing connection");
+                break Ok(Ok(()));
+            }
+            _ = tokio::time::sleep(idle_timeout.saturating_sub(activity.idle_for())), if grace_deadline.is_none() => {
+                if activity.idle_for() >= idle_timeout {
+                    record_idle_timeout(&state, connection_id, idle_timeout).await;
+                    break Ok(Ok(()));
+                }
+            }
+        }
+    };
+    if !client_done {
+        client_task.abort();
+        let _ = client_task.await;
+    }
+    if !upstream_done {
+        upstream_task.abort();
+        let _ = upstream_task.await;
+    }
+
+    if let Ok(pid) = backend_pid_rx.try_recv() {
+        state.unregister_backend_key(pid).await;
+    }
+    state.unregister_connection(connection_id).await;
+    joined.map_err(|e| anyhow::anyhow!("Connection task failed: {}", e))?
+}
+
+/// Client -> upstream half of a Postgres session
+struct ClientPump<C, U> {
+    client: FramedRead<C, PostgresCodec>,
+    upstream: FramedWrite<U, PostgresCodec>,
+    copy_in: CopyInGuard,
+    events: mpsc::Sender<ClientEvent>,
+    upstream_events: mpsc::Receiver<UpstreamEvent>,
+    connection_info: ConnectionInfo,
+    /// Whether the Startup message has been forwarded
+    session_started: bool,
+    state: AppState,
+    activity: Activity,
+    in_flight: Arc<InFlight>,
+    shutdown: CancellationToken,
+}
+
+impl<C, U> ClientPump<C, U>
+where
+    C: tokio::io::AsyncRead + Unpin,
+    U: tokio::io::AsyncWrite + Unpin,
+{
+    async fn run(mut self) -> Result<()> {
+        let connection_id = self.connection_info.connection_id;
+        loop {
+            // Upstream events precede the client's reaction to them
+            while let Ok(event) = self.upstream_events.try_recv() {
+                self.handle_event(event).await;
+            }
+            if self.shutdown.is_cancelled() {
+                return self.finish().await;
+            }
+            let msg = match self.client.next().now_or_never() {
+                Some(msg) => msg,
+                None => {
+                    // Nothing buffered: flush what was fed so far before waiting
+                    self.upstream.flush().await?;
+                    tokio::select! {
+                        biased;
+                        Some(event) = self.upstream_events.recv() => {
+                            self.handle_event(event).await;
+                            continue;
                         }
+                        _ = self.shutdown.cancelled() => return self.finish().await,
+                        msg = self.client.next() => msg,
                     }
-                    Some(Err(e)) => return Err(e),
-                    None => return Ok(()), // Client disconnected
+                }
+            };
+            let msg = match msg {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) => {
+                    let _ = self.upstream.flush().await;
+                    return Err(e);
+                }
+                None => {
+                    // Client disconnected
+                    let _ = self.upstream.flush().await;
+                    return Ok(());
+                }
+            };
+            self.activity.touch();
+
+            // Events are sent before the message is forwarded, so the upstream
+            // pump sees them before any response. A send only fails once that
+            // pump has stopped, and then this one is about to be stopped too.
+            match msg {
+                PgMessage::SSLRequest => {
+                    info!("Received SSLRequest, denying...");
+                    // Deny SSL, force cleartext
+                    let _ = self.events.send(ClientEvent::DenySsl).await;
+                }
+                PgMessage::Query(ref q) => {
+                    let query_str = String::from_utf8_lossy(&q.query).to_string();
+                    self.state
+                        .log_query(connection_id, "Query", &query_str)
+                        .await;
+
+                    // Record query type stats
+                    self.state.record_query(&query_type(&query_str)).await;
+                    self.state.record_connection_query(connection_id).await;
+                    self.copy_in.on_query(&query_str);
+
+                    self.in_flight.start_query();
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Parse(ref p) => {
+                    let query_str = String::from_utf8_lossy(&p.query).to_string();
+                    self.state
+                        .log_query(connection_id, "Parse", &query_str)
+                        .await;
+
+                    // Record query type stats for prepared statements
+                    self.state.record_query(&query_type(&query_str)).await;
+                    self.state.record_connection_query(connection_id).await;
+                    self.copy_in.on_query(&query_str);
+
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Startup(ref startup) => {
+                    // Postgres defaults the database to the user name
+                    let param = |name: &str| {
+                        startup
+                            .parameters
+                            .iter()
+                            .find(|(k, _)| k == name)
+                            .map(|(_, v)| v.clone())
+                    };
+                    let info = &mut self.connection_info;
+                    info.user = param("user");
+                    info.database = param("database").or_else(|| param("user"));
+                    info.application_name = param("application_name");
+                    info.policy = info.resolve_policy(&*self.state.config.read().await);
+                    record_session_span(info);
+                    self.state.register_connection(info.clone()).await;
+                    let _ = self
+                        .events
+                        .send(ClientEvent::Policy(info.policy.clone()))
+                        .await;
+                    if let Some(database) = info.database.clone() {
+                        let _ = self.events.send(ClientEvent::Database(database)).await;
+                    }
+                    self.session_started = true;
+                    self.in_flight.start();
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Bind(ref b) => {
+                    let _ = self.events.send(ClientEvent::Bind(b.clone())).await;
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Raw(ref r) if r.message_type == b'S' => {
+                    // Sync
+                    let _ = self.events.send(ClientEvent::Sync).await;
+                    self.in_flight.start_query();
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Raw(ref r) if r.message_type == b'F' => {
+                    // FunctionCall, answered with its own ReadyForQuery
+                    self.in_flight.start();
+                    self.upstream.feed(msg).await?;
+                }
+                PgMessage::Raw(ref r) if matches!(r.message_type, b'd' | b'c' | b'f') => {
+                    // CopyData / CopyDone / CopyFail
+                    match self.copy_in.on_copy_message(r).await {
+                        CopyVerdict::Forward => self.upstream.feed(msg).await?,
+                        CopyVerdict::Drop => {}
+                        CopyVerdict::Abort(reason) => {
+                            let mut payload = BytesMut::from(reason.as_bytes());
+                            payload.put_u8(0);
+                            self.upstream
+                                .feed(PgMessage::Regular(RegularMessage {
+                                    message_type: b'f',
+                                    payload,
+                                }))
+                                .await?;
+                        }
+                    }
+                }
+                _ => {
+                    // Forward other messages (Execute, Describe, Terminate, etc.)
+                    self.upstream.feed(msg).await?;
                 }
             }
-            // Upstream -> Client
-            msg = upstream_framed.next() => {
-                match msg {
-                    Some(Ok(msg)) => {
-                        let msg_to_send = match msg {
-                            PgMessage::RowDescription(ref rd) => {
-                                interceptor.on_row_description(rd).await;
-                                PgMessage::RowDescription(rd.clone())
-                            }
-                            PgMessage::DataRow(dr) => {
-                                let new_dr = interceptor.on_data_row(dr).await?;
-                                PgMessage::DataRow(new_dr)
-                            }
-                            _ => msg,
-                        };
-                        client_framed.send(msg_to_send).await?;
+        }
+    }
+
+    /// Graceful shutdown: stop reading from the client, let in-flight requests
+    /// complete, then end the upstream session
+    async fn finish(mut self) -> Result<()> {
+        self.upstream.flush().await?;
+        self.in_flight.wait_drained().await;
+        if self.session_started {
+            // Terminate
+            self.upstream
+                .feed(PgMessage::Regular(RegularMessage {
+                    message_type: b'X',
+                    payload: BytesMut::new(),
+                }))
+                .await?;
+        }
+        self.upstream.close().await
+    }
+
+    async fn handle_event(&mut self, event: UpstreamEvent) {
+        match event {
+            UpstreamEvent::CopyInResponse(r) => self.copy_in.on_copy_in_response(&r).await,
+            UpstreamEvent::ReadyForQuery => self.copy_in.on_ready_for_query(),
+        }
+    }
+}
+
+/// Upstream -> client half of a Postgres session; owns the interceptor
+struct UpstreamPump<U, C> {
+    upstream: FramedRead<U, PostgresCodec>,
+    client: FramedWrite<C, PostgresCodec>,
+    interceptor: Anonymizer,
+    client_events: mpsc::Receiver<ClientEvent>,
+    events: mpsc::Sender<UpstreamEvent>,
+    /// Whether the client pump is inspecting a COPY FROM STDIN
+    copy_in_active: bool,
+    /// Reports the backend PID announced via BackendKeyData
+    backend_pid: Option<oneshot::Sender<u32>>,
+    state: AppState,
+    connection_id: usize,
+    activity: Activity,
+    in_flight: Arc<InFlight>,
+    shutdown: CancellationToken,
+}
+
+impl<U, C> UpstreamPump<U, C>
+where
+    U: tokio::io::AsyncRead + Unpin,
+    C: tokio::io::AsyncWrite + Unpin,
+{
+    async fn run(mut self) -> Result<()> {
+        loop {
+            let msg = match self.upstream.next().now_or_never() {
+                Some(msg) => msg,
+                None => {
+                    // Nothing buffered: flush what was fed so far before waiting
+                    self.client.flush().await?;
+                    tokio::select! {
+                        biased;
+                        Some(event) = self.client_events.recv() => {
+                            self.handle_event(event).await?;
+                            continue;
+                        }
+                        msg = self.upstream.next() => msg,
                     }
-                    Some(Err(e)) => return Err(e),
-                    None => return Ok(()), // Upstream disconnected
                 }
+            };
+            let msg = match msg {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) => {
+                    let _ = self.client.flush().await;
+                    return Err(e);
+                }
+                None => {
+                    // Upstream disconnected
+                    if self.shutdown.is_cancelled() {
+                        let _ = self
+                            .client
+                            .feed(PgMessage::Regular(RegularMessage::error_response(
+                                "FATAL",
+                                "57P01",
+                                "terminating connection due to proxy shutdown",
+                            )))
+                            .await;
+                    }
+                    let _ = self.client.flush().await;
+                    return Ok(());
+                }
+            };
+            // The client pump reports a Bind or Sync before forwarding it, so
+            // the events of whatever this frame answers are queued by now
+            while let Ok(event) = self.client_events.try_recv() {
+                self.handle_event(event).await?;
             }
-            // Idle timeout
-            _ = tokio::time::sleep(idle_timeout) => {
-                info!("Connection idle timeout after {:?}", idle_timeout);
-                return Ok(());
+            self.activity.touch();
+
+            let msg_to_send = match msg {
+                PgMessage::RowDescription(ref rd) => {
+                    self.interceptor.on_row_description(rd).await;
+                    PgMessage::RowDescription(rd.clone())
+                }
+                PgMessage::DataRow(dr) => {
+                    let new_dr = self.interceptor.on_data_row(dr).await?;
+                    PgMessage::DataRow(new_dr)
+                }
+                PgMessage::Raw(ref r) => {
+                    if r.message_type == b'G' {
+                        // CopyInResponse: the client pump inspects the data that follows
+                        self.copy_in_active = true;
+                        let _ = self
+                            .events
+                            .send(UpstreamEvent::CopyInResponse(r.clone()))
+                            .await;
+                    }
+                    if r.message_type == b'K' && r.payload().len() >= 8 {
+                        // BackendKeyData: [Process ID (4 bytes)] [Secret Key...]
+                        let payload = r.payload();
+                        let pid =
+                            u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                        self.state
+                            .register_backend_key(
+                                pid,
+                                BackendKey {
+                                    secret_key: payload[4..].to_vec(),
+                                    connection_id: self.connection_id,
+                                },
+                            )
+                            .await;
+                        if let Some(tx) = self.backend_pid.take() {
+                            let _ = tx.send(pid);
+                        }
+                    }
+                    self.interceptor.on_backend_message(r.message_type);
+                    msg
+                }
+                PgMessage::CommandComplete(_) => {
+                    self.interceptor.on_command_complete();
+                    msg
+                }
+                PgMessage::ReadyForQuery(_) => {
+                    self.interceptor.on_ready_for_query();
+                    if let Some(duration) = self.in_flight.finish() {
+                        metrics::record_query_processed("postgres", duration.as_secs_f64());
+                    }
+                    if std::mem::take(&mut self.copy_in_active) {
+                        let _ = self.events.send(UpstreamEvent::ReadyForQuery).await;
+                    }
+                    msg
+                }
+                _ => msg,
+            };
+            self.client.feed(msg_to_send).await?;
+        }
+    }
+
+    async fn handle_event(&mut self, event: ClientEvent) -> Result<()> {
+        match event {
+            ClientEvent::Database(database) => self.interceptor.set_database(database),
+            ClientEvent::Policy(policy) => self.interceptor.set_policy(policy),
+            ClientEvent::Bind(b) => self.interceptor.on_bind(&b),
+            ClientEvent::Sync => self.interceptor.on_sync(),
+            ClientEvent::DenySsl => {
+                self.client.flush().await?;
+                self.client.get_mut().write_all(b"N").await?;
             }
         }
+        Ok(())
     }
 }
 
@@ -776,52 +1610,154 @@ async fn process_mysql_connection(
     upstream_host: String,
     upstream_port: u16,
     state: AppState,
+    tls_acceptor: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
 ) -> Result<()> {
-    // Get timeout configuration
-    let (connect_timeout, idle_timeout) = {
-        let config = state.config.read().await;
-        let limits = config.limits.as_ref();
-        (
-            Duration::from_secs(limits.map(|l| l.connect_timeout_secs).unwrap_or(30)),
-            Duration::from_secs(limits.map(|l| l.idle_timeout_secs).unwrap_or(300)),
-        )
+    let upstream_socket = match connect_upstream(&state, &upstream_host, upstream_port).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("{:#}", e);
+            let error = ErrPacket {
+                error_code: CR_CONN_HOST_ERROR,
+                ..mysql_error(0, "Can't connect to MySQL server (via ironveil)")
+            };
+            return reject_mysql_client(client_socket, error).await;
+        }
     };
 
-    // Connect to upstream MySQL server with timeout
-    let upstream_socket = tokio::time::timeout(
-        connect_timeout,
-        tokio::net::TcpStream::connect(format!("{}:{}", upstream_host, upstream_port)),
+    let client_addr = client_socket.peer_addr().ok().map(|addr| addr.ip());
+    handle_mysql_protocol(
+        client_socket,
+        upstream_socket,
+        upstream_host,
+        state,
+        tls_acceptor,
+        client_addr,
+        shutdown,
     )
     .await
-    .map_err(|_| anyhow::anyhow!("Upstream connection timeout after {:?}", connect_timeout))??;
+}
 
-    handle_mysql_protocol(client_socket, upstream_socket, state, idle_timeout).await
+/// Refuse a MySQL client with an ERR packet in place of the server handshake
+async fn reject_mysql_client<S>(client_socket: S, error: ErrPacket) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let message = error.error_message.clone();
+    let mut client_framed = Framed::new(client_socket, MySqlCodec::new_server());
+    client_framed.send(MySqlMessage::Err(error)).await?;
+    Err(anyhow::anyhow!("{}", message))
+}
+
+/// ERR packet for a failure originating in the proxy
+fn mysql_error(sequence_id: u8, message: &str) -> ErrPacket {
+    ErrPacket {
+        sequence_id,
+        error_code: ER_UNKNOWN_ERROR,
+        sql_state: *b"08S01",
+        error_message: message.to_string(),
+    }
 }
 
+/// Either leg of a MySQL session; boxed because TLS starts mid-handshake
+trait MySqlStream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> MySqlStream for T {}
+
+type MySqlFramed = Framed<Box<dyn MySqlStream>, MySqlCodec>;
+
 async fn handle_mysql_protocol<S, U>(
     client_socket: S,
     upstream_socket: U,
+    upstream_host: String,
     state: AppState,
-    idle_timeout: Duration,
+    tls_acceptor: Option<TlsAcceptor>,
+    client_addr: Option<IpAddr>,
+    shutdown: CancellationToken,
 ) -> Result<()>
 where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
     U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
-    let mut client_framed = Framed::new(client_socket, MySqlCodec::new_server());
-    let mut upstream_framed = Framed::new(upstream_socket, MySqlCodec::new_client());
+    let (max_message_size, idle_timeout) = {
+        let config = state.config.read().await;
+        let limits = config.limits.as_ref();
+        (
+            limits
+                .map(|l| l.max_message_size)
+                .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            Duration::from_secs(limits.map(|l| l.idle_timeout_secs).unwrap_or(300)),
+        )
+    };
+    let client_framed: MySqlFramed = Framed::new(
+        Box::new(client_socket),
+        MySqlCodec::new_server().with_max_message_size(max_message_size),
+    );
+    let upstream_framed: MySqlFramed = Framed::new(
+        Box::new(upstream_socket),
+        MySqlCodec::new_client().with_max_message_size(max_message_size),
+    );
 
     let connection_id = rand::random::<u64>() as usize;
     let mut interceptor = MySqlAnonymizer::new(state.clone(), connection_id);
 
+    let mut connection_info = ConnectionInfo::new(connection_id, StateDbProtocol::MySql);
+    connection_info.client_addr = client_addr;
+    let kill_switch = state.register_connection(connection_info.clone()).await;
+    let session = async {
+        let Some((mut client_framed, mut upstream_framed)) = mysql_handshake(
+            client_framed,
+            upstream_framed,
+            &mut connection_info,
+            &state,
+            tls_acceptor,
+            &upstream_host,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+        interceptor.set_database(connection_info.database.clone());
+        interceptor.set_policy(connection_info.policy.clone());
+        handle_mysql_session(
+            &mut client_framed,
+            &mut upstream_framed,
+            &mut interceptor,
+            &mut connection_info,
+            &state,
+            idle_timeout,
+            &shutdown,
+        )
+        .await
+    };
+    // Killed through the API: dropping the session closes both sockets
+    let result = tokio::select! {
+        result = session => result,
+        _ = kill_switch.cancelled() => {
+            info!("Connection killed, closing");
+            Ok(())
+        }
+    };
+    state.unregister_connection(connection_id).await;
+    result
+}
+
+/// Relay the MySQL connection phase: the server handshake, the client's
+/// response and the auth exchange, upgrading either leg to TLS when
+/// configured. Returns both legs once the client is authenticated, or
+/// `None` if the session ended during the handshake.
+async fn mysql_handshake(
+    mut client_framed: MySqlFramed,
+    mut upstream_framed: MySqlFramed,
+    connection_info: &mut ConnectionInfo,
+    state: &AppState,
+    tls_acceptor: Option<TlsAcceptor>,
+    upstream_host: &str,
+) -> Result<Option<(MySqlFramed, MySqlFramed)>> {
     // Phase 1: Forward handshake from upstream to client
-    let handshake = match upstream_framed.next().await {
+    let mut handshake = match upstream_framed.next().await {
         Some(Ok(MySqlMessage::Handshake(h))) => {
             info!(server_version = %h.server_version, "Received MySQL handshake from upstream");
-            // Forward the handshake to the client
-            client_framed
-                .send(MySqlMessage::Handshake(h.clone()))
-                .await?;
             h
         }
         Some(Ok(other)) => {
@@ -829,8 +1765,28 @@ where
             return Err(anyhow::anyhow!("Protocol error: expected handshake"));
         }
         Some(Err(e)) => return Err(e),
-        None => return Ok(()),
+        None => return Ok(None),
     };
+    let upstream_offers_tls = handshake.capability_flags & CLIENT_SSL != 0;
+
+    let strip_capabilities = state.config.read().await.mysql_strip_capabilities.clone();
+    let stripped = strip_mysql_capabilities(&mut handshake.capability_flags, &strip_capabilities);
+    if !stripped.is_empty() {
+        info!(
+            ?stripped,
+            "Stripped unsupported capabilities from the MySQL server handshake"
+        );
+    }
+
+    // Offer TLS to the client only when the proxy can terminate it
+    if tls_acceptor.is_some() {
+        handshake.capability_flags |= CLIENT_SSL;
+    } else {
+        handshake.capability_flags &= !CLIENT_SSL;
+    }
+    client_framed
+        .send(MySqlMessage::Handshake(handshake.clone()))
+        .await?;
 
     // Update codec capability flags
     client_framed
@@ -840,80 +1796,341 @@ where
         .codec_mut()
         .set_capability_flags(handshake.capability_flags);
 
-    // Phase 2: Forward client handshake response to upstream
-    match client_framed.next().await {
-        Some(Ok(MySqlMessage::HandshakeResponse(r))) => {
-            info!(username = %r.username, database = ?r.database, "Received client handshake response");
-            // Update capability flags based on what client actually supports
-            client_framed
-                .codec_mut()
-                .set_capability_flags(r.capability_flags);
-            upstream_framed
-                .codec_mut()
-                .set_capability_flags(r.capability_flags);
-            upstream_framed
-                .send(MySqlMessage::HandshakeResponse(r))
-                .await?;
-        }
-        Some(Ok(other)) => {
-            tracing::warn!("Expected handshake response, got {:?}", other);
-            return Err(anyhow::anyhow!(
-                "Protocol error: expected handshake response"
-            ));
+    // Phase 2: Read the client handshake response, switching to TLS first if asked
+    let mut response = match client_framed.next().await {
+        Some(Ok(MySqlMessage::SslRequest(_))) => {
+            let Some(acceptor) = tls_acceptor else {
+                return Err(anyhow::anyhow!(
+                    "Protocol error: SSLRequest without TLS configured"
+                ));
+            };
+            info!("Received MySQL SSLRequest, accepting...");
+            let (upgraded, client_cn) = accept_mysql_tls(client_framed, &acceptor).await?;
+            client_framed = upgraded;
+            if let Some(cn) = &client_cn {
+                info!("Client presented certificate for {}", cn);
+                tracing::Span::current().record("client.cert_cn", cn.as_str());
+            }
+            connection_info.client_cn = client_cn;
+            match client_framed.next().await {
+                Some(Ok(MySqlMessage::HandshakeResponse(r))) => r,
+                Some(Ok(other)) => return Err(unexpected_handshake_response(other)),
+                Some(Err(e)) => return Err(e),
+                None => return Ok(None),
+            }
         }
+        Some(Ok(MySqlMessage::HandshakeResponse(r))) => r,
+        Some(Ok(other)) => return Err(unexpected_handshake_response(other)),
         Some(Err(e)) => return Err(e),
-        None => return Ok(()),
+        None => return Ok(None),
+    };
+
+    info!(username = %response.username, database = ?response.database, "Received client handshake response");
+    let stripped = strip_mysql_capabilities(&mut response.capability_flags, &strip_capabilities);
+    if !stripped.is_empty() {
+        info!(
+            ?stripped,
+            "Stripped unsupported capabilities from the MySQL client response"
+        );
     }
+    connection_info.user = Some(response.username.clone());
+    connection_info.database = response.database.clone();
+    connection_info.policy = connection_info.resolve_policy(&*state.config.read().await);
+    record_session_span(connection_info);
+    state.register_connection(connection_info.clone()).await;
+    // Update capability flags based on what client actually supports
+    client_framed
+        .codec_mut()
+        .set_capability_flags(response.capability_flags);
+    upstream_framed
+        .codec_mut()
+        .set_capability_flags(response.capability_flags);
 
-    // Phase 3: Forward auth result
-    match upstream_framed.next().await {
-        Some(Ok(msg @ MySqlMessage::Ok(_))) => {
-            info!("MySQL authentication successful");
-            client_framed.send(msg).await?;
-        }
-        Some(Ok(MySqlMessage::Err(e))) => {
-            tracing::warn!(error_code = e.error_code, "MySQL authentication failed");
-            client_framed.send(MySqlMessage::Err(e)).await?;
-            return Ok(());
+    // Forward the response upstream, over TLS when configured
+    let client_sequence_id = response.sequence_id;
+    response.capability_flags &= !CLIENT_SSL;
+    response.sequence_id = 1;
+    let (upstream_tls_enabled, tls_mode) = {
+        let config = state.config.read().await;
+        (config.upstream_tls_enabled(), config.upstream_tls_mode)
+    };
+    if upstream_tls_enabled {
+        if upstream_offers_tls {
+            info!("Upstream offers TLS. Upgrading connection...");
+            upstream_framed
+                .send(MySqlMessage::SslRequest(SslRequest {
+                    capability_flags: response.capability_flags | CLIENT_SSL,
+                    max_packet_size: response.max_packet_size,
+                    character_set: response.character_set,
+                }))
+                .await?;
+            upstream_framed =
+                match connect_mysql_upstream_tls(upstream_framed, state, upstream_host).await {
+                    Ok(upgraded) => upgraded,
+                    Err(e) => {
+                        let message = format!("IronVeil: upstream TLS handshake failed: {}", e);
+                        client_framed
+                            .send(MySqlMessage::Err(mysql_error(
+                                client_sequence_id.wrapping_add(1),
+                                &message,
+                            )))
+                            .await?;
+                        return Err(anyhow::anyhow!("{}", message));
+                    }
+                };
+            response.capability_flags |= CLIENT_SSL;
+            response.sequence_id = 2;
+        } else if tls_mode == UpstreamTlsMode::Prefer {
+            tracing::warn!("Upstream does not offer TLS. Falling back to cleartext.");
+        } else {
+            tracing::error!(
+                "Upstream does not offer TLS but upstream_tls_mode is {:?}. Rejecting client.",
+                tls_mode
+            );
+            let message =
+                "IronVeil: upstream server does not support TLS, which upstream_tls_mode requires";
+            client_framed
+                .send(MySqlMessage::Err(mysql_error(
+                    client_sequence_id.wrapping_add(1),
+                    message,
+                )))
+                .await?;
+            return Err(anyhow::anyhow!("{}", message));
         }
-        Some(Ok(other)) => {
-            // Could be auth switch request or other auth packets - forward as-is
-            client_framed.send(other).await?;
+    }
+    // Sequence ids differ between the legs when only one of them uses TLS;
+    // each codec numbers the packets it sends on its own leg
+    upstream_framed
+        .send(MySqlMessage::HandshakeResponse(response))
+        .await?;
+
+    // Phase 3: Relay the auth exchange (auth switch, extra rounds) until OK or ERR
+    loop {
+        tokio::select! {
+            msg = upstream_framed.next() => match msg {
+                Some(Ok(MySqlMessage::Ok(ok))) => {
+                    info!("MySQL authentication successful");
+                    client_framed.send(MySqlMessage::Ok(ok)).await?;
+                    return Ok(Some((client_framed, upstream_framed)));
+                }
+                Some(Ok(MySqlMessage::Err(e))) => {
+                    tracing::warn!(error_code = e.error_code, "MySQL authentication failed");
+                    client_framed.send(MySqlMessage::Err(e)).await?;
+                    return Ok(None);
+                }
+                Some(Ok(MySqlMessage::AuthSwitchRequest(switch))) => {
+                    info!(plugin = %switch.plugin_name, "MySQL server requested an auth method switch");
+                    client_framed.send(MySqlMessage::AuthSwitchRequest(switch)).await?;
+                }
+                Some(Ok(MySqlMessage::AuthMoreData(more))) => {
+                    tracing::debug!(len = more.data.len(), "Relaying MySQL AuthMoreData");
+                    client_framed.send(MySqlMessage::AuthMoreData(more)).await?;
+                }
+                Some(Ok(MySqlMessage::Generic(packet))) => {
+                    client_framed.send(MySqlMessage::Generic(packet)).await?;
+                }
+                Some(Ok(other)) => {
+                    return Err(anyhow::anyhow!(
+                        "Protocol error: unexpected {:?} during authentication",
+                        other
+                    ));
+                }
+                Some(Err(e)) => return Err(e),
+                None => return Ok(None),
+            },
+            msg = client_framed.next() => match msg {
+                Some(Ok(MySqlMessage::Generic(packet))) => {
+                    upstream_framed.send(MySqlMessage::Generic(packet)).await?;
+                }
+                Some(Ok(other)) => {
+                    return Err(anyhow::anyhow!(
+                        "Protocol error: unexpected {:?} during authentication",
+                        other
+                    ));
+                }
+                Some(Err(e)) => return Err(e),
+                None => return Ok(None),
+            },
         }
-        Some(Err(e)) => return Err(e),
-        None => return Ok(()),
     }
+}
+
+/// Clear the given capabilities from handshake flags, returning those that were set
+fn strip_mysql_capabilities(flags: &mut u32, strip: &[MySqlCapability]) -> Vec<MySqlCapability> {
+    let stripped: Vec<_> = strip
+        .iter()
+        .copied()
+        .filter(|capability| *flags & capability.flag() != 0)
+        .collect();
+    for capability in &stripped {
+        *flags &= !capability.flag();
+    }
+    stripped
+}
+
+fn unexpected_handshake_response(message: MySqlMessage) -> anyhow::Error {
+    tracing::warn!("Expected handshake response, got {:?}", message);
+    anyhow::anyhow!("Protocol error: expected handshake response")
+}
+
+/// Upgrade the client leg to TLS after its SSLRequest, returning the
+/// common name of the client certificate, if any
+async fn accept_mysql_tls(
+    framed: MySqlFramed,
+    acceptor: &TlsAcceptor,
+) -> Result<(MySqlFramed, Option<String>)> {
+    let parts = framed.into_parts();
+    // Clients send their ClientHello without waiting, so it may already be buffered
+    let tls_stream = acceptor
+        .accept(tls::Rewind::new(parts.read_buf.freeze(), parts.io))
+        .await?;
+    let client_cn = tls_stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(tls::certificate_common_name);
+    Ok((Framed::new(Box::new(tls_stream), parts.codec), client_cn))
+}
 
-    // Phase 4: Command phase - bidirectional proxy with interception
+/// Upgrade the upstream leg to TLS after sending it an SSLRequest
+async fn connect_mysql_upstream_tls(
+    framed: MySqlFramed,
+    state: &AppState,
+    upstream_host: &str,
+) -> Result<MySqlFramed> {
+    let parts = framed.into_parts();
+    let (client_config, domain) = {
+        let config = state.config.read().await;
+        (
+            Arc::new(tls::upstream_client_config(&config)?),
+            tls::upstream_server_name(&config, upstream_host)?,
+        )
+    };
+    let tls_stream = TlsConnector::from(client_config)
+        .connect(domain, parts.io)
+        .await?;
+    Ok(Framed::new(Box::new(tls_stream), parts.codec))
+}
+
+async fn handle_mysql_session<S, U>(
+    client_framed: &mut Framed<S, MySqlCodec>,
+    upstream_framed: &mut Framed<U, MySqlCodec>,
+    interceptor: &mut MySqlAnonymizer,
+    connection_info: &mut ConnectionInfo,
+    state: &AppState,
+    idle_timeout: Duration,
+    shutdown: &CancellationToken,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let connection_id = connection_info.connection_id;
+    let shutdown_grace = {
+        let config = state.config.read().await;
+        Duration::from_secs(
+            config
+                .limits
+                .as_ref()
+                .map(|l| l.shutdown_grace_secs)
+                .unwrap_or(10),
+        )
+    };
+
+    // Phase 4: Command phase - bidirectional proxy with interception.
+    // On shutdown the client is no longer read from; the command in flight
+    // (if any) completes before the upstream session is closed with COM_QUIT.
+    let mut local_infile = LocalInfileGuard::new(state.clone(), connection_id);
+    // Session identity after a COM_CHANGE_USER or COM_INIT_DB, recorded once
+    // the server accepts it
+    let mut pending_identity: Option<ConnectionInfo> = None;
+    // When the query (or statement execution) in flight was sent
+    let mut query_sent_at: Option<Instant> = None;
+    let mut awaiting_response = false;
+    let mut grace_deadline: Option<tokio::time::Instant> = None;
     loop {
         tokio::select! {
             // Client -> Upstream
-            msg = client_framed.next() => {
+            msg = client_framed.next(), if grace_deadline.is_none() => {
                 match msg {
                     Some(Ok(msg)) => {
                         if let MySqlMessage::Query(q) = &msg {
                             let query_str = String::from_utf8_lossy(&q.query).to_string();
-                            let id = format!("{:x}", rand::random::<u128>());
-                            state.add_log(LogEntry {
-                                id,
-                                timestamp: Utc::now(),
-                                connection_id,
-                                event_type: "MySqlQuery".to_string(),
-                                content: query_str.clone(),
-                                details: None,
-                            }).await;
+                            state.log_query(connection_id, "MySqlQuery", &query_str).await;
 
                             // Record query type stats
-                            let query_type = query_str
-                                .split_whitespace()
-                                .next()
-                                .unwrap_or("OTHER")
-                                .to_uppercase();
-                            state.record_query(&query_type).await;
+                            state.record_query(&query_type(&query_str)).await;
+                            state.record_connection_query(connection_id).await;
+                            local_infile.on_query(&query_str);
+                            query_sent_at = Some(Instant::now());
 
                             // Reset interceptor for new result set
                             interceptor.reset_columns();
                         }
+                        match &msg {
+                            MySqlMessage::InitDb(init_db) => {
+                                let mut info = connection_info.clone();
+                                info.database = Some(init_db.database.clone());
+                                let content =
+                                    format!("Switching database to {}", init_db.database);
+                                record_mysql_identity_switch(state, "MySqlInitDb", content, &info)
+                                    .await;
+                                pending_identity = Some(info);
+                                interceptor.reset_columns();
+                            }
+                            MySqlMessage::ChangeUser(change_user) => {
+                                let mut info = connection_info.clone();
+                                info.user = Some(change_user.username.clone());
+                                info.database = Some(change_user.database.clone())
+                                    .filter(|database| !database.is_empty());
+                                let content =
+                                    format!("Changing user to {}", change_user.username);
+                                record_mysql_identity_switch(state, "MySqlChangeUser", content, &info)
+                                    .await;
+                                pending_identity = Some(info);
+                                interceptor.reset_columns();
+                            }
+                            _ => {}
+                        }
+                        if let MySqlMessage::LocalInfileData(data) = &msg {
+                            match local_infile.on_data(&data.payload).await {
+                                CopyVerdict::Forward => {}
+                                CopyVerdict::Drop => continue,
+                                CopyVerdict::Abort(reason) => {
+                                    // There is no way to cancel the transfer, so
+                                    // dropping the upstream aborts the statement
+                                    warn!("{}", reason);
+                                    client_framed
+                                        .send(MySqlMessage::Err(mysql_error(
+                                            data.sequence_id.wrapping_add(1),
+                                            &reason,
+                                        )))
+                                        .await?;
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        // Commands only; auth data during a COM_CHANGE_USER is Generic too
+                        if let MySqlMessage::Generic(g) = &msg
+                            && client_framed.codec().in_command_phase()
+                        {
+                            match g.payload.first() {
+                                // Recorded like a Postgres Parse
+                                Some(&COM_STMT_PREPARE) => {
+                                    let query_str = String::from_utf8_lossy(&g.payload[1..]);
+                                    state.record_query(&query_type(&query_str)).await;
+                                    state.record_connection_query(connection_id).await;
+                                }
+                                // Prepared statement results carry their own column definitions
+                                Some(&COM_STMT_EXECUTE) => {
+                                    interceptor.reset_columns();
+                                    query_sent_at = Some(Instant::now());
+                                }
+                                _ => {}
+                            }
+                        }
+                        awaiting_response = true;
                         upstream_framed.send(msg).await?;
                     }
                     Some(Err(e)) => return Err(e),
@@ -924,7 +2141,25 @@ where
             msg = upstream_framed.next() => {
                 match msg {
                     Some(Ok(msg)) => {
+                        // A multi-statement response holds several results,
+                        // each ended by its own OK/EOF
+                        let result_done = matches!(
+                            msg,
+                            MySqlMessage::Ok(_) | MySqlMessage::Err(_) | MySqlMessage::Eof(_)
+                        ) && !upstream_framed.codec().in_result_set();
+                        let response_done =
+                            result_done && upstream_framed.codec().in_command_phase();
+                        let accepted = matches!(msg, MySqlMessage::Ok(_));
                         let msg_to_send = match msg {
+                            MySqlMessage::ResultSetHeader(ref header) => {
+                                interceptor.on_result_set_header(header);
+                                msg
+                            }
+                            // Only COM_FIELD_LIST responses carry default values
+                            MySqlMessage::ColumnDefinition(col) if col.default_value.is_some() => {
+                                let new_col = interceptor.on_field_list_column(col).await?;
+                                MySqlMessage::ColumnDefinition(new_col)
+                            }
                             MySqlMessage::ColumnDefinition(ref col) => {
                                 interceptor.on_column_definition(col).await;
                                 msg
@@ -933,6 +2168,14 @@ where
                                 let new_row = interceptor.on_result_row(row).await?;
                                 MySqlMessage::ResultRow(new_row)
                             }
+                            MySqlMessage::BinaryResultRow(row) => {
+                                let new_row = interceptor.on_binary_result_row(row).await?;
+                                MySqlMessage::BinaryResultRow(new_row)
+                            }
+                            MySqlMessage::LocalInfileRequest(_) => {
+                                local_infile.on_request().await;
+                                msg
+                            }
                             MySqlMessage::Eof(_) => {
                                 // EOF after columns means we're about to get rows
                                 // EOF after rows means result set is done
@@ -941,31 +2184,1408 @@ where
                             _ => msg,
                         };
                         client_framed.send(msg_to_send).await?;
+                        if result_done {
+                            interceptor.reset_columns();
+                        }
+                        if response_done {
+                            awaiting_response = false;
+                            if let Some(sent_at) = query_sent_at.take() {
+                                metrics::record_query_processed(
+                                    "mysql",
+                                    sent_at.elapsed().as_secs_f64(),
+                                );
+                            }
+                            if let Some(info) = pending_identity.take()
+                                && accepted
+                            {
+                                *connection_info = info;
+                                connection_info.policy =
+                                    connection_info.resolve_policy(&*state.config.read().await);
+                                record_session_span(connection_info);
+                                state.register_connection(connection_info.clone()).await;
+                                interceptor.set_database(connection_info.database.clone());
+                                interceptor.set_policy(connection_info.policy.clone());
+                            }
+                            if grace_deadline.is_some() {
+                                return quit_mysql_upstream(upstream_framed).await;
+                            }
+                        }
                     }
                     Some(Err(e)) => return Err(e),
                     None => return Ok(()),
                 }
             }
+            _ = shutdown.cancelled(), if grace_deadline.is_none() => {
+                if !awaiting_response {
+                    return quit_mysql_upstream(upstream_framed).await;
+                }
+                info!("Shutting down, letting in-flight query finish (grace period {:?})", shutdown_grace);
+                grace_deadline = Some(tokio::time::Instant::now() + shutdown_grace);
+            }
+            _ = tokio::time::sleep_until(grace_deadline.unwrap_or_else(tokio::time::Instant::now)), if grace_deadline.is_some() => {
+                warn!("Shutdown grace period expired, closing MySQL connection");
+                return Ok(());
+            }
             // Idle timeout
-            _ = tokio::time::sleep(idle_timeout) => {
-                info!("MySQL connection idle timeout after {:?}", idle_timeout);
+            _ = tokio::time::sleep(idle_timeout), if grace_deadline.is_none() => {
+                record_idle_timeout(state, connection_id, idle_timeout).await;
                 return Ok(());
             }
         }
     }
 }
 
-fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
-    let certfile = File::open(path)?;
-    let mut reader = BufReader::new(certfile);
-    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
-    Ok(certs)
+/// End the upstream MySQL session with COM_QUIT
+async fn quit_mysql_upstream<U>(upstream_framed: &mut Framed<U, MySqlCodec>) -> Result<()>
+where
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    upstream_framed
+        .send(MySqlMessage::Generic(GenericPacket {
+            sequence_id: 0,
+            payload: BytesMut::from(&[0x01][..]),
+        }))
+        .await
+}
+
+/// Record a COM_CHANGE_USER or COM_INIT_DB sent by a MySQL client
+async fn record_mysql_identity_switch(
+    state: &AppState,
+    event_type: &str,
+    content: String,
+    info: &ConnectionInfo,
+) {
+    info!(user = ?info.user, database = ?info.database, "{}", content);
+    let id = format!("{:x}", rand::random::<u128>());
+    state
+        .add_log(LogEntry {
+            id,
+            timestamp: Utc::now(),
+            connection_id: info.connection_id,
+            event_type: event_type.to_string(),
+            content,
+            details: None,
+            client_cn: info.client_cn.clone(),
+            user: info.user.clone(),
+            database: info.database.clone(),
+            application_name: None,
+        })
+        .await;
+}
+
+/// Record a connection closed for inactivity
+async fn record_idle_timeout(state: &AppState, connection_id: usize, idle_timeout: Duration) {
+    let content = format!("Connection idle timeout after {:?}", idle_timeout);
+    info!("{}", content);
+    metrics::record_idle_timeout();
+
+    let id = format!("{:x}", rand::random::<u128>());
+    state
+        .add_log(LogEntry {
+            id,
+            timestamp: Utc::now(),
+            connection_id,
+            event_type: "IdleTimeout".to_string(),
+            content,
+            details: None,
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        })
+        .await;
+}
+
+/// Record a connection's session parameters on the current connection span
+fn record_session_span(info: &ConnectionInfo) {
+    let span = tracing::Span::current();
+    if let Some(user) = &info.user {
+        span.record("db.user", user.as_str());
+    }
+    if let Some(database) = &info.database {
+        span.record("db.name", database.as_str());
+    }
+    if let Some(application_name) = &info.application_name {
+        span.record("db.application_name", application_name.as_str());
+    }
 }
 
-fn load_keys(path: &str) -> Result<PrivateKeyDer<'static>> {
-    let keyfile = File::open(path)?;
-    let mut reader = BufReader::new(keyfile);
-    let key = rustls_pemfile::private_key(&mut reader)?
-        .ok_or_else(|| anyhow::anyhow!("No private key found"))?;
-    Ok(key)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LimitsConfig;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_gssenc_then_ssl_then_startup() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let _ = process_postgres_connection(
+                socket,
+                "127.0.0.1".to_string(),
+                upstream_port,
+                state,
+                None,
+                CancellationToken::new(),
+            )
+            .await;
+        });
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut response = [0u8; 1];
+
+        // 1. GSSENCRequest is denied
+        client
+            .write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x30])
+            .await
+            .unwrap();
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"N");
+
+        // 2. SSLRequest is denied (no TLS configured)
+        client
+            .write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f])
+            .await
+            .unwrap();
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"N");
+
+        // 3. Startup reaches upstream intact
+        let mut startup = BytesMut::new();
+        startup.put_u32(4 + 4 + 10);
+        startup.put_u32(196608);
+        startup.put_slice(b"user\0bob\0\0");
+        client.write_all(&startup).await.unwrap();
+
+        let (mut upstream_socket, _) = upstream.accept().await.unwrap();
+        let mut received = vec![0u8; startup.len()];
+        upstream_socket.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, startup.to_vec());
+    }
+
+    /// Fake upstream that refuses TLS, then returns whatever it receives next
+    async fn spawn_upstream_refusing_tls() -> (u16, tokio::task::JoinHandle<Vec<u8>>) {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut ssl_request = [0u8; 8];
+            socket.read_exact(&mut ssl_request).await.unwrap();
+            socket.write_all(b"N").await.unwrap();
+            let mut received = Vec::new();
+            let _ = tokio::time::timeout(
+                Duration::from_millis(500),
+                socket.read_to_end(&mut received),
+            )
+            .await;
+            received
+        });
+        (port, handle)
+    }
+
+    fn startup_message() -> BytesMut {
+        let mut startup = BytesMut::new();
+        startup.put_u32(4 + 4 + 10);
+        startup.put_u32(196608);
+        startup.put_slice(b"user\0bob\0\0");
+        startup
+    }
+
+    async fn connect_through_proxy(config: AppConfig, upstream_port: u16) -> TcpStream {
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        connect_through_proxy_with(state, upstream_port, CancellationToken::new()).await
+    }
+
+    async fn connect_through_proxy_with(
+        state: AppState,
+        upstream_port: u16,
+        shutdown: CancellationToken,
+    ) -> TcpStream {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let _ = handle_postgres_protocol(
+                socket,
+                "127.0.0.1".to_string(),
+                upstream_port,
+                state,
+                None,
+                None,
+                shutdown,
+            )
+            .await;
+        });
+        TcpStream::connect(proxy_addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upstream_tls_require_rejects_cleartext_upstream() {
+        let (upstream_port, upstream) = spawn_upstream_refusing_tls().await;
+        let config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::Require,
+            ..Default::default()
+        };
+        let mut client = connect_through_proxy(config, upstream_port).await;
+
+        client.write_all(&startup_message()).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+
+        assert_eq!(response[0], b'E');
+        let text = String::from_utf8_lossy(&response);
+        assert!(text.contains("08001"));
+        assert!(text.contains("does not support TLS"));
+        // Nothing was sent to upstream in cleartext
+        assert!(upstream.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_tls_prefer_falls_back_to_cleartext() {
+        let (upstream_port, upstream) = spawn_upstream_refusing_tls().await;
+        let config = AppConfig {
+            upstream_tls: true,
+            ..Default::default()
+        };
+        let mut client = connect_through_proxy(config, upstream_port).await;
+
+        let startup = startup_message();
+        client.write_all(&startup).await.unwrap();
+
+        assert_eq!(upstream.await.unwrap(), startup.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_both_directions_forwarded_until_idle_timeout() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 18];
+            socket.read_exact(&mut startup).await.unwrap();
+            socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            let mut query = [0u8; 14];
+            socket.read_exact(&mut query).await.unwrap();
+            assert_eq!(&query, b"Q\0\0\0\x0dSELECT 1\0");
+            socket
+                .write_all(b"C\0\0\0\x0dSELECT 1\0Z\0\0\0\x05I")
+                .await
+                .unwrap();
+            // Stay silent and keep the socket open until the proxy closes it
+            let mut rest = Vec::new();
+            let _ = socket.read_to_end(&mut rest).await;
+        });
+
+        let config = AppConfig {
+            limits: Some(LimitsConfig {
+                max_connections: None,
+                connections_per_second: None,
+                upstream_connect_timeout_secs: 5,
+                upstream_connect_retries: 0,
+                idle_timeout_secs: 1,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                shutdown_grace_secs: 10,
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let mut client =
+            connect_through_proxy_with(state.clone(), upstream_port, CancellationToken::new())
+                .await;
+        client.write_all(&startup_message()).await.unwrap();
+        let mut ready = [0u8; 6];
+        client.read_exact(&mut ready).await.unwrap();
+        assert_eq!(ready[0], b'Z');
+
+        client.write_all(b"Q\0\0\0\x0dSELECT 1\0").await.unwrap();
+        let mut response = [0u8; 20];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"C\0\0\0\x0dSELECT 1\0Z\0\0\0\x05I");
+
+        // No traffic in either direction: the proxy closes both sides
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+            .await
+            .expect("idle connection was not closed")
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), upstream)
+            .await
+            .expect("upstream connection was not closed")
+            .unwrap();
+
+        let logs = state.logs.read().await;
+        let idle = logs
+            .iter()
+            .find(|entry| entry.event_type == "IdleTimeout")
+            .expect("idle timeout was not logged");
+        assert_eq!(idle.user.as_deref(), Some("bob"));
+
+        let stats = state.get_stats().await;
+        assert_eq!(stats.queries.total_queries, 1);
+        assert_eq!(stats.queries.select_count, 1);
+    }
+
+    /// A local port with nothing listening on it
+    async fn closed_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    fn config_with_connect_retries(retries: u32) -> AppConfig {
+        AppConfig {
+            limits: Some(LimitsConfig {
+                max_connections: None,
+                connections_per_second: None,
+                upstream_connect_timeout_secs: 1,
+                upstream_connect_retries: retries,
+                idle_timeout_secs: 300,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                shutdown_grace_secs: 10,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_postgres_upstream_unavailable_sends_error_response() {
+        let upstream_port = closed_port().await;
+        let mut client = connect_through_proxy(config_with_connect_retries(1), upstream_port).await;
+        client.write_all(&startup_message()).await.unwrap();
+
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+            .await
+            .expect("client connection was not closed")
+            .unwrap();
+        assert_eq!(response[0], b'E');
+        assert!(response.windows(5).any(|w| w == b"08001"));
+        assert!(
+            response
+                .windows(b"upstream unavailable".len())
+                .any(|w| w == b"upstream unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mysql_upstream_unavailable_sends_err_packet() {
+        let upstream_port = closed_port().await;
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let state =
+            AppState::new_for_test(config_with_connect_retries(0), "proxy.yaml".to_string());
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let _ = process_mysql_connection(
+                socket,
+                "127.0.0.1".to_string(),
+                upstream_port,
+                state,
+                None,
+                CancellationToken::new(),
+            )
+            .await;
+        });
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+            .await
+            .expect("client connection was not closed")
+            .unwrap();
+        // Header with sequence id 0, then 0xff and error code 2003
+        assert_eq!(response[3], 0);
+        assert_eq!(&response[4..7], &[0xff, 0xd3, 0x07]);
+        assert_eq!(
+            &response[7..],
+            b"Can't connect to MySQL server (via ironveil)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_changes_apply_to_new_connections() {
+        // Upstream answering every startup with ReadyForQuery
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = upstream.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut startup = [0u8; 18];
+                    socket.read_exact(&mut startup).await.unwrap();
+                    socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+                    let mut rest = Vec::new();
+                    let _ = socket.read_to_end(&mut rest).await;
+                });
+            }
+        });
+
+        let mut config = config_with_connect_retries(0);
+        config.limits.as_mut().unwrap().max_connections = Some(1);
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_listener(
+            listener,
+            ListenerConfig {
+                name: None,
+                port: addr.port(),
+                protocol: DbProtocol::Postgres,
+                upstream_host: "127.0.0.1".to_string(),
+                upstream_port,
+            },
+            state.clone(),
+            Default::default(),
+            shutdown.clone(),
+        ));
+        // Whether the proxy takes a session, or closes the socket at once
+        let admitted = || async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&startup_message()).await.unwrap();
+            let mut ready = [0u8; 6];
+            let admitted = client.read_exact(&mut ready).await.is_ok();
+            (admitted, client)
+        };
+
+        let (first, _first) = admitted().await;
+        assert!(first);
+        let (second, _) = admitted().await;
+        assert!(!second);
+
+        state
+            .config
+            .write()
+            .await
+            .limits
+            .as_mut()
+            .unwrap()
+            .max_connections = Some(2);
+        let (third, _third) = admitted().await;
+        assert!(third);
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_upstream_changes_apply_to_new_connections() {
+        // Two upstreams answering startups, each reporting its connections
+        let (accepted_tx, mut accepted) = mpsc::unbounded_channel();
+        let mut upstream_ports = Vec::new();
+        for id in 0..2 {
+            let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            upstream_ports.push(upstream.local_addr().unwrap().port());
+            let accepted_tx = accepted_tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (mut socket, _) = upstream.accept().await.unwrap();
+                    accepted_tx.send(id).unwrap();
+                    tokio::spawn(async move {
+                        let mut startup = [0u8; 18];
+                        socket.read_exact(&mut startup).await.unwrap();
+                        socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+                        let mut rest = Vec::new();
+                        let _ = socket.read_to_end(&mut rest).await;
+                    });
+                }
+            });
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = config_with_connect_retries(0);
+        config.server.listen_port = addr.port();
+        config.server.upstream_port = upstream_ports[0];
+        // The CLI host wins over the file's
+        config.server.upstream_host = "db.invalid".to_string();
+        let overrides = ServerOverrides {
+            upstream_host: Some("127.0.0.1".to_string()),
+            ..Default::default()
+        };
+        let listener_config = config.proxy_listeners(&overrides).remove(0);
+        let state = AppState::new_for_test(config, "proxy.yaml".to_string())
+            .with_server_overrides(overrides);
+        let shutdown = CancellationToken::new();
+        tokio::spawn(run_listener(
+            listener,
+            listener_config,
+            state.clone(),
+            Default::default(),
+            shutdown.clone(),
+        ));
+        let connect = || async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&startup_message()).await.unwrap();
+            let mut ready = [0u8; 6];
+            client.read_exact(&mut ready).await.unwrap();
+            client
+        };
+
+        let _first = connect().await;
+        assert_eq!(accepted.recv().await, Some(0));
+
+        // As a reload would
+        state.config.write().await.server.upstream_port = upstream_ports[1];
+        let _second = connect().await;
+        assert_eq!(accepted.recv().await, Some(1));
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_listeners_share_state_and_stop_on_shutdown() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pg_upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 18];
+            socket.read_exact(&mut startup).await.unwrap();
+            socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = socket.read_to_end(&mut rest).await;
+        });
+        let mysql_upstream_port = closed_port().await;
+
+        let state =
+            AppState::new_for_test(config_with_connect_retries(0), "proxy.yaml".to_string());
+        let shutdown = CancellationToken::new();
+        let mut addrs = Vec::new();
+        let mut accept_loops = Vec::new();
+        for (protocol, upstream_port) in [
+            (DbProtocol::Postgres, pg_upstream_port),
+            (DbProtocol::Mysql, mysql_upstream_port),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            addrs.push(listener.local_addr().unwrap());
+            accept_loops.push(tokio::spawn(run_listener(
+                listener,
+                ListenerConfig {
+                    name: None,
+                    port,
+                    protocol,
+                    upstream_host: "127.0.0.1".to_string(),
+                    upstream_port,
+                },
+                state.clone(),
+                Default::default(),
+                shutdown.clone(),
+            )));
+        }
+
+        // Postgres listener proxies to its upstream
+        let mut pg_client = TcpStream::connect(addrs[0]).await.unwrap();
+        pg_client.write_all(&startup_message()).await.unwrap();
+        let mut ready = [0u8; 6];
+        pg_client.read_exact(&mut ready).await.unwrap();
+        assert_eq!(ready[0], b'Z');
+
+        // MySQL listener answers with a MySQL ERR packet for its unreachable upstream
+        let mut mysql_client = TcpStream::connect(addrs[1]).await.unwrap();
+        let mut response = Vec::new();
+        mysql_client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response[4], 0xff);
+
+        assert_eq!(state.get_stats().await.total_connections, 2);
+
+        // Shutdown stops both accept loops and closes the open session
+        shutdown.cancel();
+        for accept_loop in accept_loops {
+            tokio::time::timeout(Duration::from_secs(5), accept_loop)
+                .await
+                .expect("listener did not stop")
+                .unwrap()
+                .unwrap();
+        }
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), pg_client.read_to_end(&mut rest))
+            .await
+            .expect("client connection was not closed")
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while state.active_connections.load(Ordering::Relaxed) > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connections did not drain");
+    }
+
+    fn tls_fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn fixture_tls_acceptor() -> TlsAcceptor {
+        let tls_config = crate::config::TlsConfig {
+            enabled: true,
+            cert_path: tls_fixture("server.crt"),
+            key_path: tls_fixture("server.key"),
+            client_ca_path: None,
+            require_client_auth: false,
+        };
+        TlsAcceptor::from(Arc::new(tls::server_config(&tls_config).unwrap()))
+    }
+
+    /// Config trusting the fixture CA for `db.internal.example.com`
+    fn fixture_upstream_tls_config() -> AppConfig {
+        AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::VerifyFull,
+            upstream_tls_ca_path: Some(tls_fixture("ca.crt")),
+            upstream_tls_server_name: Some("db.internal.example.com".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn mysql_handshake_packet() -> crate::protocol::mysql::HandshakeV10 {
+        use crate::protocol::mysql::{
+            CLIENT_PLUGIN_AUTH, CLIENT_PROTOCOL_41, CLIENT_SECURE_CONNECTION,
+        };
+        crate::protocol::mysql::HandshakeV10 {
+            protocol_version: 10,
+            server_version: "8.0.36".to_string(),
+            connection_id: 7,
+            auth_plugin_data_part1: [1; 8],
+            capability_flags: CLIENT_PROTOCOL_41
+                | CLIENT_SECURE_CONNECTION
+                | CLIENT_PLUGIN_AUTH
+                | CLIENT_SSL,
+            character_set: 45,
+            status_flags: 2,
+            auth_plugin_data_part2: vec![2; 12],
+            auth_plugin_name: "mysql_native_password".to_string(),
+        }
+    }
+
+    fn mysql_ok_packet(sequence_id: u8) -> MySqlMessage {
+        MySqlMessage::Ok(crate::protocol::mysql::OkPacket {
+            sequence_id,
+            affected_rows: 0,
+            last_insert_id: 0,
+            status_flags: 2,
+            warnings: 0,
+            info: bytes::Bytes::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mysql_tls_on_both_legs() {
+        // Upstream offering TLS, which sees the handshake response over TLS
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (socket, _) = upstream.accept().await.unwrap();
+            let mut framed = Framed::new(socket, MySqlCodec::new_server());
+            let handshake = mysql_handshake_packet();
+            framed
+                .codec_mut()
+                .set_capability_flags(handshake.capability_flags);
+            framed
+                .send(MySqlMessage::Handshake(handshake))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::SslRequest(_))) = framed.next().await else {
+                panic!("expected SSLRequest");
+            };
+            let parts = framed.into_parts();
+            let tls_stream = fixture_tls_acceptor()
+                .accept(tls::Rewind::new(parts.read_buf.freeze(), parts.io))
+                .await
+                .unwrap();
+            let mut framed = Framed::new(tls_stream, parts.codec);
+            let Some(Ok(MySqlMessage::HandshakeResponse(response))) = framed.next().await else {
+                panic!("expected handshake response");
+            };
+            framed.send(mysql_ok_packet(3)).await.unwrap();
+            response
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let state = AppState::new_for_test(fixture_upstream_tls_config(), "proxy.yaml".to_string());
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let _ = process_mysql_connection(
+                socket,
+                "127.0.0.1".to_string(),
+                upstream_port,
+                state,
+                Some(fixture_tls_acceptor()),
+                CancellationToken::new(),
+            )
+            .await;
+        });
+
+        // Client switches to TLS as `mysql --ssl-mode=REQUIRED` does
+        let socket = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut client = Framed::new(socket, MySqlCodec::new_client());
+        let Some(Ok(MySqlMessage::Handshake(handshake))) = client.next().await else {
+            panic!("expected handshake");
+        };
+        assert_ne!(handshake.capability_flags & CLIENT_SSL, 0);
+        let capability_flags = handshake.capability_flags;
+        client.codec_mut().set_capability_flags(capability_flags);
+        client
+            .send(MySqlMessage::SslRequest(SslRequest {
+                capability_flags,
+                max_packet_size: 1 << 24,
+                character_set: 45,
+            }))
+            .await
+            .unwrap();
+        let parts = client.into_parts();
+        let client_tls = fixture_upstream_tls_config();
+        let tls_stream =
+            TlsConnector::from(Arc::new(tls::upstream_client_config(&client_tls).unwrap()))
+                .connect(
+                    tls::upstream_server_name(&client_tls, "127.0.0.1").unwrap(),
+                    parts.io,
+                )
+                .await
+                .unwrap();
+        let mut client = Framed::new(tls_stream, parts.codec);
+        client
+            .send(MySqlMessage::HandshakeResponse(
+                crate::protocol::mysql::HandshakeResponse {
+                    sequence_id: 2,
+                    capability_flags,
+                    max_packet_size: 1 << 24,
+                    character_set: 45,
+                    username: "alice".to_string(),
+                    auth_response: vec![],
+                    database: Some("shop".to_string()),
+                    auth_plugin_name: Some("mysql_native_password".to_string()),
+                },
+            ))
+            .await
+            .unwrap();
+
+        let Some(Ok(MySqlMessage::Ok(ok))) = client.next().await else {
+            panic!("expected OK");
+        };
+        assert_eq!(ok.sequence_id, 3);
+
+        let forwarded = upstream.await.unwrap();
+        assert_eq!(forwarded.sequence_id, 2);
+        assert_eq!(forwarded.username, "alice");
+        assert_ne!(forwarded.capability_flags & CLIENT_SSL, 0);
+    }
+
+    #[tokio::test]
+    async fn test_mysql_tls_not_offered_without_acceptor() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = upstream.accept().await.unwrap();
+            let mut framed = Framed::new(socket, MySqlCodec::new_server());
+            framed
+                .send(MySqlMessage::Handshake(mysql_handshake_packet()))
+                .await
+                .unwrap();
+            let _ = framed.next().await;
+        });
+
+        let mut client = connect_mysql_through_proxy(upstream_port).await;
+        let Some(Ok(MySqlMessage::Handshake(handshake))) = client.next().await else {
+            panic!("expected handshake");
+        };
+        assert_eq!(handshake.capability_flags & CLIENT_SSL, 0);
+    }
+
+    fn mysql_handshake_response(capability_flags: u32) -> MySqlMessage {
+        MySqlMessage::HandshakeResponse(crate::protocol::mysql::HandshakeResponse {
+            sequence_id: 1,
+            capability_flags,
+            max_packet_size: 1 << 24,
+            character_set: 45,
+            username: "alice".to_string(),
+            auth_response: vec![7; 32],
+            database: Some("shop".to_string()),
+            auth_plugin_name: Some("mysql_native_password".to_string()),
+        })
+    }
+
+    fn mysql_auth_data(sequence_id: u8, data: &[u8]) -> MySqlMessage {
+        MySqlMessage::Generic(GenericPacket {
+            sequence_id,
+            payload: BytesMut::from(data),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mysql_multi_round_auth_is_relayed() {
+        use crate::protocol::mysql::{AuthMoreData, AuthSwitchRequest, QueryPacket};
+
+        // Upstream switching to caching_sha2_password and asking for full auth
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (socket, _) = upstream.accept().await.unwrap();
+            let mut framed = Framed::new(socket, MySqlCodec::new_server());
+            framed
+                .send(MySqlMessage::Handshake(mysql_handshake_packet()))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::HandshakeResponse(_))) = framed.next().await else {
+                panic!("expected handshake response");
+            };
+            framed
+                .send(MySqlMessage::AuthSwitchRequest(AuthSwitchRequest {
+                    sequence_id: 2,
+                    plugin_name: "caching_sha2_password".to_string(),
+                    plugin_data: bytes::Bytes::from_static(&[3; 21]),
+                }))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::Generic(scramble))) = framed.next().await else {
+                panic!("expected auth switch response");
+            };
+            assert_eq!(scramble.sequence_id, 3);
+            framed
+                .send(MySqlMessage::AuthMoreData(AuthMoreData {
+                    sequence_id: 4,
+                    data: bytes::Bytes::from_static(&[0x04]), // perform full authentication
+                }))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::Generic(password))) = framed.next().await else {
+                panic!("expected password");
+            };
+            assert_eq!(&password.payload[..], b"secret\0");
+            framed.send(mysql_ok_packet(6)).await.unwrap();
+            let Some(Ok(MySqlMessage::Query(query))) = framed.next().await else {
+                panic!("expected query after authentication");
+            };
+            query.query
+        });
+
+        let mut client = connect_mysql_through_proxy(upstream_port).await;
+        let Some(Ok(MySqlMessage::Handshake(handshake))) = client.next().await else {
+            panic!("expected handshake");
+        };
+        client
+            .codec_mut()
+            .set_capability_flags(handshake.capability_flags);
+        client
+            .send(mysql_handshake_response(handshake.capability_flags))
+            .await
+            .unwrap();
+
+        let Some(Ok(MySqlMessage::AuthSwitchRequest(switch))) = client.next().await else {
+            panic!("expected auth switch request");
+        };
+        assert_eq!(switch.plugin_name, "caching_sha2_password");
+        assert_eq!(switch.sequence_id, 2);
+        client.send(mysql_auth_data(3, &[9; 32])).await.unwrap();
+
+        let Some(Ok(MySqlMessage::AuthMoreData(more))) = client.next().await else {
+            panic!("expected AuthMoreData");
+        };
+        assert_eq!(&more.data[..], &[0x04]);
+        client.send(mysql_auth_data(5, b"secret\0")).await.unwrap();
+
+        let Some(Ok(MySqlMessage::Ok(ok))) = client.next().await else {
+            panic!("expected OK");
+        };
+        assert_eq!(ok.sequence_id, 6);
+
+        client
+            .send(MySqlMessage::Query(QueryPacket {
+                sequence_id: 0,
+                query: bytes::Bytes::from_static(b"SELECT 1"),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(&upstream.await.unwrap()[..], b"SELECT 1");
+    }
+
+    #[tokio::test]
+    async fn test_mysql_compression_is_stripped_from_handshake() {
+        use crate::protocol::mysql::{CLIENT_COMPRESS, QueryPacket};
+
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (socket, _) = upstream.accept().await.unwrap();
+            let mut framed = Framed::new(socket, MySqlCodec::new_server());
+            let mut handshake = mysql_handshake_packet();
+            handshake.capability_flags |= CLIENT_COMPRESS;
+            framed
+                .send(MySqlMessage::Handshake(handshake))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::HandshakeResponse(response))) = framed.next().await else {
+                panic!("expected handshake response");
+            };
+            framed.send(mysql_ok_packet(2)).await.unwrap();
+            // Uncompressed command phase
+            let Some(Ok(MySqlMessage::Query(query))) = framed.next().await else {
+                panic!("expected uncompressed query");
+            };
+            framed.send(mysql_ok_packet(1)).await.unwrap();
+            (response.capability_flags, query.query)
+        });
+
+        let mut client = connect_mysql_through_proxy(upstream_port).await;
+        let Some(Ok(MySqlMessage::Handshake(handshake))) = client.next().await else {
+            panic!("expected handshake");
+        };
+        assert_eq!(handshake.capability_flags & CLIENT_COMPRESS, 0);
+
+        // A client asking for compression anyway
+        let capability_flags = handshake.capability_flags | CLIENT_COMPRESS;
+        client.codec_mut().set_capability_flags(capability_flags);
+        client
+            .send(mysql_handshake_response(capability_flags))
+            .await
+            .unwrap();
+        let Some(Ok(MySqlMessage::Ok(_))) = client.next().await else {
+            panic!("expected OK");
+        };
+
+        client
+            .send(MySqlMessage::Query(QueryPacket {
+                sequence_id: 0,
+                query: bytes::Bytes::from_static(b"SELECT 1"),
+            }))
+            .await
+            .unwrap();
+        let Some(Ok(MySqlMessage::Ok(_))) = client.next().await else {
+            panic!("expected query result");
+        };
+
+        let (upstream_flags, query) = upstream.await.unwrap();
+        assert_eq!(upstream_flags & CLIENT_COMPRESS, 0);
+        assert_eq!(&query[..], b"SELECT 1");
+    }
+
+    async fn connect_mysql_through_proxy(upstream_port: u16) -> Framed<TcpStream, MySqlCodec> {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        connect_mysql_through_proxy_with(state, upstream_port).await
+    }
+
+    async fn connect_mysql_through_proxy_with(
+        state: AppState,
+        upstream_port: u16,
+    ) -> Framed<TcpStream, MySqlCodec> {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let _ = process_mysql_connection(
+                socket,
+                "127.0.0.1".to_string(),
+                upstream_port,
+                state,
+                None,
+                CancellationToken::new(),
+            )
+            .await;
+        });
+        let socket = TcpStream::connect(proxy_addr).await.unwrap();
+        Framed::new(socket, MySqlCodec::new_client())
+    }
+
+    #[tokio::test]
+    async fn test_mysql_queries_recorded_in_stats() {
+        use crate::protocol::mysql::QueryPacket;
+
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = upstream.accept().await.unwrap();
+            let mut framed = Framed::new(socket, MySqlCodec::new_server());
+            framed
+                .send(MySqlMessage::Handshake(mysql_handshake_packet()))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::HandshakeResponse(_))) = framed.next().await else {
+                panic!("expected handshake response");
+            };
+            framed.send(mysql_ok_packet(2)).await.unwrap();
+            while let Some(Ok(MySqlMessage::Query(_))) = framed.next().await {
+                framed.send(mysql_ok_packet(1)).await.unwrap();
+            }
+        });
+
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut client = connect_mysql_through_proxy_with(state.clone(), upstream_port).await;
+        let Some(Ok(MySqlMessage::Handshake(handshake))) = client.next().await else {
+            panic!("expected handshake");
+        };
+        client
+            .codec_mut()
+            .set_capability_flags(handshake.capability_flags);
+        client
+            .send(mysql_handshake_response(handshake.capability_flags))
+            .await
+            .unwrap();
+        let Some(Ok(MySqlMessage::Ok(_))) = client.next().await else {
+            panic!("expected OK");
+        };
+
+        for query in ["/* app */ INSERT INTO t VALUES (1)", "select * from t"] {
+            client
+                .send(MySqlMessage::Query(QueryPacket {
+                    sequence_id: 0,
+                    query: bytes::Bytes::from(query),
+                }))
+                .await
+                .unwrap();
+            let Some(Ok(MySqlMessage::Ok(_))) = client.next().await else {
+                panic!("expected query result");
+            };
+        }
+
+        let stats = state.get_stats().await;
+        assert_eq!(stats.queries.total_queries, 2);
+        assert_eq!(stats.queries.insert_count, 1);
+        assert_eq!(stats.queries.select_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_lets_in_flight_query_finish() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let (query_received_tx, query_received) = oneshot::channel();
+        let (respond_tx, respond) = oneshot::channel::<()>();
+        let upstream = tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 18];
+            socket.read_exact(&mut startup).await.unwrap();
+            socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            let mut query = [0u8; 14];
+            socket.read_exact(&mut query).await.unwrap();
+            query_received_tx.send(()).unwrap();
+            // The result is produced only after shutdown has started
+            respond.await.unwrap();
+            socket
+                .write_all(b"C\0\0\0\x0dSELECT 1\0Z\0\0\0\x05I")
+                .await
+                .unwrap();
+            let mut rest = Vec::new();
+            socket.read_to_end(&mut rest).await.unwrap();
+            rest
+        });
+
+        let shutdown = CancellationToken::new();
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut client = connect_through_proxy_with(state, upstream_port, shutdown.clone()).await;
+        client.write_all(&startup_message()).await.unwrap();
+        let mut ready = [0u8; 6];
+        client.read_exact(&mut ready).await.unwrap();
+        client.write_all(b"Q\0\0\0\x0dSELECT 1\0").await.unwrap();
+        query_received.await.unwrap();
+
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        respond_tx.send(()).unwrap();
+
+        // The in-flight result is delivered, then the session is terminated
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+            .await
+            .expect("client connection was not closed")
+            .unwrap();
+        assert!(rest.starts_with(b"C\0\0\0\x0dSELECT 1\0Z\0\0\0\x05I"));
+        assert_eq!(rest[20], b'E');
+        assert!(rest.windows(5).any(|w| w == b"57P01"));
+
+        let upstream_rest = tokio::time::timeout(Duration::from_secs(5), upstream)
+            .await
+            .expect("upstream connection was not closed")
+            .unwrap();
+        assert_eq!(upstream_rest, b"X\0\0\0\x04");
+    }
+
+    #[tokio::test]
+    async fn test_kill_closes_both_sockets() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let (query_received_tx, query_received) = oneshot::channel();
+        let upstream = tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 18];
+            socket.read_exact(&mut startup).await.unwrap();
+            socket.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            let mut query = [0u8; 14];
+            socket.read_exact(&mut query).await.unwrap();
+            query_received_tx.send(()).unwrap();
+            // Never answers; only the kill ends the session
+            let mut rest = Vec::new();
+            socket.read_to_end(&mut rest).await.unwrap();
+        });
+
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut client =
+            connect_through_proxy_with(state.clone(), upstream_port, CancellationToken::new())
+                .await;
+        client.write_all(&startup_message()).await.unwrap();
+        let mut ready = [0u8; 6];
+        client.read_exact(&mut ready).await.unwrap();
+        client.write_all(b"Q\0\0\0\x0dSELECT 1\0").await.unwrap();
+        query_received.await.unwrap();
+
+        let connections = state.list_connections().await;
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].queries, 1);
+        let connection_id = connections[0].info.connection_id;
+        assert!(state.kill_connection(connection_id).await.is_some());
+
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), client.read_to_end(&mut rest))
+            .await
+            .expect("client connection was not closed")
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), upstream)
+            .await
+            .expect("upstream connection was not closed")
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(state.list_connections().await.is_empty());
+    }
+
+    /// A large result set passes through the proxy whole and in bounded time.
+    /// Run with `cargo test --release -- --ignored bench_large_result_set`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_large_result_set() {
+        const ROWS: usize = 500_000;
+
+        let mut result_set = BytesMut::new();
+        let columns: [&[u8]; 2] = [b"id", b"note"];
+        result_set.put_u8(b'T');
+        result_set.put_u32(4 + 2 + columns.iter().map(|c| c.len() + 1 + 18).sum::<usize>() as u32);
+        result_set.put_u16(columns.len() as u16);
+        for column in columns {
+            result_set.put_slice(column);
+            result_set.put_u8(0);
+            result_set.put_u32(0); // table OID
+            result_set.put_u16(0); // column index
+            result_set.put_u32(25); // text
+            result_set.put_i16(-1);
+            result_set.put_i32(-1);
+            result_set.put_i16(0);
+        }
+        let note = b"the quick brown fox jumps over the lazy dog, again and again";
+        for i in 0..ROWS {
+            let id = i.to_string();
+            result_set.put_u8(b'D');
+            result_set.put_u32((4 + 2 + 4 + id.len() + 4 + note.len()) as u32);
+            result_set.put_u16(2);
+            result_set.put_u32(id.len() as u32);
+            result_set.put_slice(id.as_bytes());
+            result_set.put_u32(note.len() as u32);
+            result_set.put_slice(note);
+        }
+        let tag = format!("SELECT {}\0", ROWS);
+        result_set.put_u8(b'C');
+        result_set.put_u32((4 + tag.len()) as u32);
+        result_set.put_slice(tag.as_bytes());
+        result_set.put_slice(&[b'Z', 0, 0, 0, 5, b'I']);
+        let result_set = result_set.freeze();
+        let expected = result_set.len();
+
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 18];
+            socket.read_exact(&mut startup).await.unwrap();
+            socket.write_all(&result_set).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = socket.read_to_end(&mut rest).await;
+        });
+
+        let mut client = connect_through_proxy(AppConfig::default(), upstream_port).await;
+        let started = std::time::Instant::now();
+        client.write_all(&startup_message()).await.unwrap();
+        let mut received = 0;
+        let mut buf = vec![0u8; 64 * 1024];
+        while received < expected {
+            let n = client.read(&mut buf).await.unwrap();
+            assert!(
+                n > 0,
+                "proxy closed after {} of {} bytes",
+                received,
+                expected
+            );
+            received += n;
+        }
+        assert_eq!(received, expected);
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(60),
+            "{} rows took {:?}",
+            ROWS,
+            elapsed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_watcher_reloads_changed_file() {
+        use crate::audit::{AuditEventType, AuditFilter, AuditOutcome};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "rules: []\n").unwrap();
+        let state = AppState::new_for_test(AppConfig::load(&path).unwrap(), path.clone());
+        tokio::spawn(run_config_watcher(
+            state.clone(),
+            path.clone(),
+            Duration::from_millis(50),
+        ));
+        // Let the watcher start before changing the file
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let wait_for = |check: fn(&crate::state::ConfigReloadStatus) -> bool| {
+            let state = state.clone();
+            async move {
+                for _ in 0..100 {
+                    if check(&*state.config_reload_status.read().await) {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                panic!("config was not reloaded");
+            }
+        };
+
+        std::fs::write(&path, "rules:\n  - column: email\n    strategy: email\n").unwrap();
+        wait_for(|status| status.last_reload.is_some()).await;
+        assert_eq!(state.config.read().await.rules.len(), 1);
+
+        // A malformed file keeps the running config and reports why
+        std::fs::write(&path, "rules: [\n").unwrap();
+        wait_for(|status| status.last_error.is_some()).await;
+        assert_eq!(state.config.read().await.rules.len(), 1);
+
+        let (_, entries) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        let reloads: Vec<_> = entries
+            .iter()
+            .filter(|e| e.event_type == AuditEventType::ConfigReload)
+            .collect();
+        assert_eq!(reloads.len(), 2);
+        assert!(
+            reloads
+                .iter()
+                .any(|e| e.outcome == AuditOutcome::Failure && e.error.is_some())
+        );
+        assert!(
+            reloads
+                .iter()
+                .all(|e| e.details.as_ref().unwrap()["trigger"] == "file_change")
+        );
+
+        // Saving the running config is not taken for a change
+        std::fs::write(&path, "rules: []\n").unwrap();
+        wait_for(|status| status.last_error.is_none()).await;
+        state.save_config().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let (total, _) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        assert_eq!(total, 3);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_signal() {
+        use crate::audit::{AuditEventType, AuditFilter};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "rules: []\n").unwrap();
+        let state = AppState::new_for_test(AppConfig::load(&path).unwrap(), path.clone());
+        let tls_acceptor = SharedTlsAcceptor::default();
+
+        // TLS turned on, and the audit log moved to a file
+        let audit_log = dir.path().join("audit.log");
+        std::fs::write(
+            &path,
+            format!(
+                "rules: []\ntls:\n  enabled: true\n  cert_path: {}\n  key_path: {}\naudit:\n  log_file: {}\n",
+                tls_fixture("server.crt"),
+                tls_fixture("server.key"),
+                audit_log.display()
+            ),
+        )
+        .unwrap();
+        reload_on_signal(&state, &tls_acceptor).await;
+        assert!(tls_acceptor.read().unwrap().is_some());
+        let logged = std::fs::read_to_string(&audit_log).unwrap();
+        assert!(logged.contains(r#""trigger":"signal""#), "{}", logged);
+
+        // After logrotate moves the file, the next entry starts a new one
+        std::fs::rename(&audit_log, dir.path().join("audit.log.1")).unwrap();
+        std::fs::write(&path, "rules: [\n").unwrap();
+        reload_on_signal(&state, &tls_acceptor).await;
+        let logged = std::fs::read_to_string(&audit_log).unwrap();
+        assert!(logged.contains(r#""outcome":"failure""#), "{}", logged);
+        // The running config, and TLS, are kept
+        assert!(state.config.read().await.tls.is_some());
+        assert!(tls_acceptor.read().unwrap().is_some());
+
+        let (_, entries) = state
+            .audit_logger
+            .query(&AuditFilter::default(), 0, 100)
+            .await;
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|e| e.event_type == AuditEventType::ConfigReload)
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_check_follows_reloaded_upstream() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        let path = path.to_str().unwrap().to_string();
+        let write_config = |upstream_port: u16| {
+            std::fs::write(
+                &path,
+                format!(
+                    "rules: []\nserver:\n  listen_port: 7654\n  upstream_port: {}\nhealth_check:\n  interval_secs: 1\n",
+                    upstream_port
+                ),
+            )
+            .unwrap();
+        };
+        write_config(closed_port().await);
+        let config = AppConfig::load(&path).unwrap();
+        let state =
+            AppState::new_for_test(config.clone(), path.clone()).with_primary_listener(7654);
+        tokio::spawn(run_health_check_task(state.clone(), config.health_check));
+        // A first probe of the old upstream fails
+        while state.health_status.read().await.last_check.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(state.health_status.read().await.last_error.is_some());
+
+        write_config(upstream_port);
+        state.reload_config().await.unwrap();
+        assert_eq!(
+            state.upstream().await,
+            ("127.0.0.1".to_string(), upstream_port)
+        );
+        // The next probe goes to the new upstream
+        tokio::time::timeout(Duration::from_secs(5), upstream.accept())
+            .await
+            .expect("health check did not probe the reloaded upstream")
+            .unwrap();
+    }
 }
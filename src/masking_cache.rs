@@ -0,0 +1,179 @@
+//! Shared cache of generated fakes.
+//!
+//! Seeding already makes masking deterministic, but the cache makes it a
+//! guarantee: once a value has been masked under a strategy, every
+//! connection, Postgres or MySQL, gets the same replacement until the entry
+//! is evicted. The cache is a bounded LRU keyed by strategy, seed key and
+//! original value.
+
+use crate::config::MaskStrategy;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    strategy: MaskStrategy,
+    seed_key: [u8; 16],
+    value: Vec<u8>,
+}
+
+/// Entries plus their last use, oldest first in `order`
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<CacheKey, (String, u64)>,
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &CacheKey) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (masked, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        *last_used = tick;
+        self.order.insert(tick, key.clone());
+        Some(masked.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, masked: String, capacity: usize) {
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.order.remove(&last_used);
+        }
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (masked, self.tick));
+    }
+}
+
+pub struct MaskingCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Cache figures reported by `/stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct MaskingCacheStats {
+    pub capacity: usize,
+    pub size: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+impl MaskingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached replacement for a value, or the one `mask` produces, which
+    /// is then cached. `mask` runs without the lock held.
+    pub fn get_or_insert_with(
+        &self,
+        strategy: MaskStrategy,
+        seed_key: &[u8; 16],
+        value: &[u8],
+        mask: impl FnOnce() -> String,
+    ) -> String {
+        let key = CacheKey {
+            strategy,
+            seed_key: *seed_key,
+            value: value.to_vec(),
+        };
+        if let Some(masked) = self.lru.lock().expect("masking cache poisoned").touch(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return masked;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let masked = mask();
+        self.lru
+            .lock()
+            .expect("masking cache poisoned")
+            .insert(key, masked.clone(), self.capacity);
+        masked
+    }
+
+    pub fn stats(&self) -> MaskingCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        MaskingCacheStats {
+            capacity: self.capacity,
+            size: self
+                .lru
+                .lock()
+                .expect("masking cache poisoned")
+                .entries
+                .len(),
+            hits,
+            misses,
+            hit_rate: if lookups > 0 {
+                hits as f64 / lookups as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_hits_and_evicts_least_recently_used() {
+        let cache = MaskingCache::new(2);
+        let key = [0u8; 16];
+        let mask = |masked: &str| {
+            let masked = masked.to_string();
+            move || masked
+        };
+
+        assert_eq!(
+            cache.get_or_insert_with(MaskStrategy::Email, &key, b"a", mask("x")),
+            "x"
+        );
+        // Cached, so the new replacement is ignored
+        assert_eq!(
+            cache.get_or_insert_with(MaskStrategy::Email, &key, b"a", mask("y")),
+            "x"
+        );
+        // Same value, another strategy or salt: separate entries
+        assert_eq!(
+            cache.get_or_insert_with(MaskStrategy::Phone, &key, b"a", mask("p")),
+            "p"
+        );
+        assert_eq!(
+            cache.get_or_insert_with(MaskStrategy::Email, &[1; 16], b"a", mask("s")),
+            "s"
+        );
+
+        // Capacity 2: "email"/a was used before "phone"/a, so it went first
+        assert_eq!(
+            cache.get_or_insert_with(MaskStrategy::Email, &key, b"a", mask("z")),
+            "z"
+        );
+
+        let stats = cache.stats();
+        assert_eq!(stats.size, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 4);
+        assert!((stats.hit_rate - 0.2).abs() < f64::EPSILON);
+    }
+}
@@ -5,6 +5,8 @@
 //! - Query processing metrics (count, latency)
 //! - Masking operations (fields masked, errors)
 //! - Upstream health check latency
+//! - Protocol errors (oversized or malformed messages)
+//! - PII findings of the latest database scan
 
 use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
@@ -18,27 +20,28 @@ pub fn init_metrics() -> PrometheusHandle {
         .expect("Failed to install Prometheus recorder")
 }
 
-/// Record a new connection
-#[allow(dead_code)]
-pub fn record_connection_opened() {
-    counter!("ironveil_connections_total").increment(1);
-    gauge!("ironveil_connections_active").increment(1.0);
+/// Record a new connection on a listener
+pub fn record_connection_opened(listener: &str) {
+    counter!("ironveil_connections_total", "listener" => listener.to_string()).increment(1);
+    gauge!("ironveil_connections_active", "listener" => listener.to_string()).increment(1.0);
 }
 
 /// Record connection closed
-#[allow(dead_code)]
-pub fn record_connection_closed() {
-    gauge!("ironveil_connections_active").decrement(1.0);
+pub fn record_connection_closed(listener: &str) {
+    gauge!("ironveil_connections_active", "listener" => listener.to_string()).decrement(1.0);
 }
 
 /// Record a connection rejected (rate limit or max connections)
-#[allow(dead_code)]
-pub fn record_connection_rejected(reason: &str) {
-    counter!("ironveil_connections_rejected_total", "reason" => reason.to_string()).increment(1);
+pub fn record_connection_rejected(listener: &str, reason: &str) {
+    counter!(
+        "ironveil_connections_rejected_total",
+        "listener" => listener.to_string(),
+        "reason" => reason.to_string()
+    )
+    .increment(1);
 }
 
-/// Record query processed
-#[allow(dead_code)]
+/// Record a query answered by the upstream, with its round-trip time
 pub fn record_query_processed(protocol: &str, duration_secs: f64) {
     counter!("ironveil_queries_total", "protocol" => protocol.to_string()).increment(1);
     histogram!("ironveil_query_duration_seconds", "protocol" => protocol.to_string())
@@ -51,8 +54,18 @@ pub fn record_fields_masked(count: u64) {
     counter!("ironveil_fields_masked_total").increment(count);
 }
 
-/// Record masking error
-#[allow(dead_code)]
+/// Record a value masked in a column (table is empty when unknown)
+pub fn record_column_masked(table: &str, column: &str, strategy: &str) {
+    counter!(
+        "ironveil_column_masked_total",
+        "table" => table.to_string(),
+        "column" => column.to_string(),
+        "strategy" => strategy.to_string()
+    )
+    .increment(1);
+}
+
+/// Record a value that failed to be masked
 pub fn record_masking_error() {
     counter!("ironveil_masking_errors_total").increment(1);
 }
@@ -71,17 +84,30 @@ pub fn record_health_check(healthy: bool, latency_ms: Option<u64>) {
 }
 
 /// Record upstream connection timeout
-#[allow(dead_code)]
 pub fn record_upstream_timeout() {
     counter!("ironveil_upstream_timeouts_total").increment(1);
 }
 
 /// Record idle connection timeout
-#[allow(dead_code)]
 pub fn record_idle_timeout() {
     counter!("ironveil_idle_timeouts_total").increment(1);
 }
 
+/// Record a malformed or oversized protocol message that closed a connection
+pub fn record_protocol_error(protocol: &str) {
+    counter!("ironveil_protocol_errors_total", "protocol" => protocol.to_string()).increment(1);
+}
+
+/// Record the number of PII findings of the latest scan of a schema
+pub fn record_scan_findings(database: &str, schema: &str, findings: usize) {
+    gauge!(
+        "ironveil_scan_findings",
+        "database" => database.to_string(),
+        "schema" => schema.to_string()
+    )
+    .set(findings as f64);
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,2 +1,5 @@
 pub mod mysql;
 pub mod postgres;
+
+/// Default upper bound on a single protocol message (64 MB)
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
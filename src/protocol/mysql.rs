@@ -3,8 +3,10 @@
 //! This module implements the MySQL client/server protocol for proxying MySQL connections.
 //! Reference: https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_basics.html
 
+use super::DEFAULT_MAX_MESSAGE_SIZE;
 use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
 use tokio_util::codec::{Decoder, Encoder};
 
 /// MySQL packet types and messages
@@ -14,20 +16,39 @@ pub enum MySqlMessage {
     Handshake(HandshakeV10),
     /// Client response to handshake
     HandshakeResponse(HandshakeResponse),
+    /// Truncated handshake response asking to switch to TLS
+    SslRequest(SslRequest),
+    /// Server asks the client to authenticate with another plugin
+    AuthSwitchRequest(AuthSwitchRequest),
+    /// Extra data from the server's auth plugin (e.g. caching_sha2_password)
+    AuthMoreData(AuthMoreData),
     /// Generic packet (passthrough)
     Generic(GenericPacket),
     /// COM_QUERY command
     Query(QueryPacket),
+    /// COM_INIT_DB command, switching the default database
+    InitDb(InitDbPacket),
+    /// COM_CHANGE_USER command, re-authenticating the connection
+    ChangeUser(ChangeUserPacket),
+    /// Column count that starts a result set
+    ResultSetHeader(ResultSetHeader),
     /// Column definition (in result set)
     ColumnDefinition(ColumnDefinition),
     /// Result set row (text protocol)
     ResultRow(ResultRow),
+    /// Result set row (binary protocol, prepared statements)
+    BinaryResultRow(BinaryResultRow),
     /// OK packet
     Ok(OkPacket),
     /// ERR packet
     Err(ErrPacket),
     /// EOF packet (deprecated in 4.1+ but still used)
     Eof(EofPacket),
+    /// Server asks for a client file during `LOAD DATA LOCAL INFILE` (0xfb)
+    LocalInfileRequest(LocalInfileRequest),
+    /// Chunk of the file sent in reply to a local infile request; an empty
+    /// payload ends the transfer
+    LocalInfileData(GenericPacket),
 }
 
 /// MySQL Handshake V10 packet (server -> client)
@@ -47,6 +68,8 @@ pub struct HandshakeV10 {
 /// Client handshake response
 #[derive(Debug, Clone)]
 pub struct HandshakeResponse {
+    /// 1 in cleartext, 2 when sent after an SSLRequest
+    pub sequence_id: u8,
     pub capability_flags: u32,
     pub max_packet_size: u32,
     pub character_set: u8,
@@ -56,6 +79,37 @@ pub struct HandshakeResponse {
     pub auth_plugin_name: Option<String>,
 }
 
+/// SSLRequest: the first 32 bytes of a handshake response, with `CLIENT_SSL` set
+#[derive(Debug, Clone)]
+pub struct SslRequest {
+    pub capability_flags: u32,
+    pub max_packet_size: u32,
+    pub character_set: u8,
+}
+
+/// AuthSwitchRequest packet (0xfe during authentication)
+#[derive(Debug, Clone)]
+pub struct AuthSwitchRequest {
+    pub sequence_id: u8,
+    pub plugin_name: String,
+    /// Plugin-specific data, usually a new scramble
+    pub plugin_data: Bytes,
+}
+
+/// AuthMoreData packet (0x01 during authentication)
+#[derive(Debug, Clone)]
+pub struct AuthMoreData {
+    pub sequence_id: u8,
+    pub data: Bytes,
+}
+
+/// Local infile request (0xfb in reply to `LOAD DATA LOCAL INFILE`)
+#[derive(Debug, Clone)]
+pub struct LocalInfileRequest {
+    pub sequence_id: u8,
+    pub filename: Bytes,
+}
+
 /// Generic packet for passthrough
 #[derive(Debug, Clone)]
 pub struct GenericPacket {
@@ -70,6 +124,35 @@ pub struct QueryPacket {
     pub query: Bytes,
 }
 
+/// COM_INIT_DB packet
+#[derive(Debug, Clone)]
+pub struct InitDbPacket {
+    pub sequence_id: u8,
+    pub database: String,
+}
+
+/// COM_CHANGE_USER packet
+#[derive(Debug, Clone)]
+pub struct ChangeUserPacket {
+    pub sequence_id: u8,
+    pub username: String,
+    pub auth_response: Bytes,
+    /// Empty when the new session has no default database
+    pub database: String,
+    /// Character set, auth plugin name and connection attributes, passed through as-is
+    pub trailer: Bytes,
+}
+
+/// Result set header: the number of columns that follow
+#[derive(Debug, Clone)]
+pub struct ResultSetHeader {
+    pub sequence_id: u8,
+    pub column_count: u64,
+    /// Whether column definitions are sent; only present with
+    /// `CLIENT_OPTIONAL_RESULTSET_METADATA`
+    pub metadata_follows: Option<bool>,
+}
+
 /// Column definition packet (part of result set)
 #[derive(Debug, Clone)]
 pub struct ColumnDefinition {
@@ -85,6 +168,9 @@ pub struct ColumnDefinition {
     pub column_type: u8,
     pub flags: u16,
     pub decimals: u8,
+    /// Default value, only sent in reply to COM_FIELD_LIST; the inner
+    /// `None` is a NULL default
+    pub default_value: Option<Option<BytesMut>>,
 }
 
 /// Result row packet (text protocol)
@@ -94,6 +180,22 @@ pub struct ResultRow {
     pub values: Vec<Option<BytesMut>>,
 }
 
+/// Result row packet (binary protocol)
+#[derive(Debug, Clone)]
+pub struct BinaryResultRow {
+    pub sequence_id: u8,
+    pub values: Vec<Option<BinaryValue>>,
+}
+
+/// A non-NULL value in a binary result row
+#[derive(Debug, Clone)]
+pub enum BinaryValue {
+    /// String-typed value (VARCHAR, TEXT, BLOB, JSON, ...), without its length prefix
+    String(BytesMut),
+    /// Any other value, kept exactly as encoded
+    Raw(Bytes),
+}
+
 /// OK packet
 #[derive(Debug, Clone)]
 pub struct OkPacket {
@@ -125,24 +227,102 @@ pub struct EofPacket {
 // Capability flags
 #[allow(dead_code)]
 pub const CLIENT_LONG_PASSWORD: u32 = 1;
+pub const CLIENT_COMPRESS: u32 = 1 << 5;
+pub const CLIENT_LOCAL_FILES: u32 = 1 << 7;
 pub const CLIENT_PROTOCOL_41: u32 = 1 << 9;
+pub const CLIENT_SSL: u32 = 1 << 11;
 pub const CLIENT_SECURE_CONNECTION: u32 = 1 << 15;
+pub const CLIENT_MULTI_STATEMENTS: u32 = 1 << 16;
+pub const CLIENT_MULTI_RESULTS: u32 = 1 << 17;
+pub const CLIENT_PS_MULTI_RESULTS: u32 = 1 << 18;
 pub const CLIENT_PLUGIN_AUTH: u32 = 1 << 19;
+pub const CLIENT_CONNECT_ATTRS: u32 = 1 << 20;
+pub const CLIENT_SESSION_TRACK: u32 = 1 << 23;
 pub const CLIENT_DEPRECATE_EOF: u32 = 1 << 24;
+pub const CLIENT_OPTIONAL_RESULTSET_METADATA: u32 = 1 << 25;
+pub const CLIENT_ZSTD_COMPRESSION_ALGORITHM: u32 = 1 << 26;
+pub const CLIENT_QUERY_ATTRIBUTES: u32 = 1 << 27;
+
+/// Largest payload a single packet can carry; longer payloads are split
+const MAX_PACKET_PAYLOAD: usize = 0xff_ffff;
+
+// Server status flags
+/// Another result follows the one this OK/EOF packet ends
+pub const SERVER_MORE_RESULTS_EXISTS: u16 = 0x0008;
+
+// Command bytes
+pub const COM_INIT_DB: u8 = 0x02;
+pub const COM_QUERY: u8 = 0x03;
+pub const COM_FIELD_LIST: u8 = 0x04;
+pub const COM_PROCESS_INFO: u8 = 0x0a;
+pub const COM_CHANGE_USER: u8 = 0x11;
+pub const COM_STMT_PREPARE: u8 = 0x16;
+pub const COM_STMT_EXECUTE: u8 = 0x17;
+pub const COM_STMT_CLOSE: u8 = 0x19;
+pub const COM_STMT_FETCH: u8 = 0x1c;
+
+// Column types
+pub const MYSQL_TYPE_TINY: u8 = 0x01;
+pub const MYSQL_TYPE_SHORT: u8 = 0x02;
+pub const MYSQL_TYPE_LONG: u8 = 0x03;
+pub const MYSQL_TYPE_FLOAT: u8 = 0x04;
+pub const MYSQL_TYPE_DOUBLE: u8 = 0x05;
+pub const MYSQL_TYPE_NULL: u8 = 0x06;
+pub const MYSQL_TYPE_TIMESTAMP: u8 = 0x07;
+pub const MYSQL_TYPE_LONGLONG: u8 = 0x08;
+pub const MYSQL_TYPE_INT24: u8 = 0x09;
+pub const MYSQL_TYPE_DATE: u8 = 0x0a;
+pub const MYSQL_TYPE_TIME: u8 = 0x0b;
+pub const MYSQL_TYPE_DATETIME: u8 = 0x0c;
+pub const MYSQL_TYPE_YEAR: u8 = 0x0d;
+pub const MYSQL_TYPE_NEWDATE: u8 = 0x0e;
+pub const MYSQL_TYPE_VARCHAR: u8 = 0x0f;
+pub const MYSQL_TYPE_JSON: u8 = 0xf5;
+pub const MYSQL_TYPE_ENUM: u8 = 0xf7;
+pub const MYSQL_TYPE_SET: u8 = 0xf8;
+pub const MYSQL_TYPE_TINY_BLOB: u8 = 0xf9;
+pub const MYSQL_TYPE_MEDIUM_BLOB: u8 = 0xfa;
+pub const MYSQL_TYPE_LONG_BLOB: u8 = 0xfb;
+pub const MYSQL_TYPE_BLOB: u8 = 0xfc;
+pub const MYSQL_TYPE_VAR_STRING: u8 = 0xfd;
+pub const MYSQL_TYPE_STRING: u8 = 0xfe;
+
+// Server error codes
+/// Generic server error, used for failures originating in the proxy
+pub const ER_UNKNOWN_ERROR: u16 = 1105;
+/// Client-side "can't connect" error, reported when the upstream is unreachable
+pub const CR_CONN_HOST_ERROR: u16 = 2003;
 
 /// State machine for MySQL codec
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MySqlState {
     /// Waiting for server handshake
     WaitingHandshake,
-    /// Waiting for client handshake response
+    /// Waiting for client handshake response (server side) or the auth
+    /// result (client side)
     WaitingHandshakeResponse,
+    /// Handshake response or COM_CHANGE_USER received, auth exchange in
+    /// progress (server side)
+    Authenticating,
     /// Normal command phase
     Command,
+    /// Client is streaming a file for `LOAD DATA LOCAL INFILE`, until an
+    /// empty packet
+    LocalInfile,
     /// Reading column definitions in result set
     ReadingColumns { remaining: usize },
     /// Reading rows in result set
     ReadingRows,
+    /// Reading the column definitions sent in reply to COM_FIELD_LIST,
+    /// until EOF
+    ReadingFieldList,
+    /// Reading the parameter and column definitions that follow a
+    /// COM_STMT_PREPARE OK; counts include the closing EOF packets
+    ReadingStatementDefinitions {
+        statement_id: u32,
+        params_remaining: usize,
+        columns_remaining: usize,
+    },
 }
 
 /// MySQL codec for framing and parsing packets
@@ -151,16 +331,45 @@ pub struct MySqlCodec {
     capability_flags: u32,
     is_client_side: bool,
     column_count: usize,
+    max_message_size: usize,
+    /// Column types of the current result set, needed to parse binary rows
+    column_types: Vec<u8>,
+    /// Whether the current result set uses the binary row format
+    binary_rows: bool,
+    /// A COM_STMT_PREPARE was sent and its response is pending
+    prepare_pending: bool,
+    /// Result column types of each prepared statement, by statement id
+    statements: HashMap<u32, Vec<u8>>,
+    /// The last result ended with SERVER_MORE_RESULTS_EXISTS, so the
+    /// response continues with another result
+    more_results: bool,
+    /// The command in flight can be answered with a result set
+    expects_result_set: bool,
+    /// Sequence id of the next packet on this connection, in either direction
+    next_sequence_id: u8,
+    /// Number outgoing packets from `next_sequence_id` rather than keeping
+    /// the ids they carry
+    renumber_sequence_ids: bool,
 }
 
 impl MySqlCodec {
     /// Create codec for client-facing connection (proxy as server)
     pub fn new_server() -> Self {
         Self {
-            state: MySqlState::WaitingHandshake,
+            // The server speaks first, so the first packet read is the client's response
+            state: MySqlState::WaitingHandshakeResponse,
             capability_flags: 0,
             is_client_side: false,
             column_count: 0,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            column_types: Vec::new(),
+            binary_rows: false,
+            prepare_pending: false,
+            statements: HashMap::new(),
+            more_results: false,
+            expects_result_set: false,
+            next_sequence_id: 0,
+            renumber_sequence_ids: true,
         }
     }
 
@@ -171,9 +380,45 @@ impl MySqlCodec {
             capability_flags: 0,
             is_client_side: true,
             column_count: 0,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            column_types: Vec::new(),
+            binary_rows: false,
+            prepare_pending: false,
+            statements: HashMap::new(),
+            more_results: false,
+            expects_result_set: false,
+            next_sequence_id: 0,
+            renumber_sequence_ids: true,
         }
     }
 
+    /// Set the largest packet payload this codec will accept
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Keep the sequence ids of encoded packets as they are instead of
+    /// renumbering them, for a leg relayed without injecting or dropping packets
+    #[allow(dead_code)]
+    pub fn with_sequence_passthrough(mut self) -> Self {
+        self.renumber_sequence_ids = false;
+        self
+    }
+
+    /// Whether the last response has been read in full and the next packet starts a new one
+    pub fn in_command_phase(&self) -> bool {
+        self.state == MySqlState::Command && !self.more_results
+    }
+
+    /// Whether a result set's column definitions or rows are being read
+    pub fn in_result_set(&self) -> bool {
+        matches!(
+            self.state,
+            MySqlState::ReadingColumns { .. } | MySqlState::ReadingRows
+        )
+    }
+
     /// Update capability flags after handshake
     pub fn set_capability_flags(&mut self, flags: u32) {
         self.capability_flags = flags;
@@ -182,6 +427,66 @@ impl MySqlCodec {
     fn uses_deprecate_eof(&self) -> bool {
         self.capability_flags & CLIENT_DEPRECATE_EOF != 0
     }
+
+    /// Track a command sent upstream so its response can be parsed
+    fn on_command(&mut self, command: &[u8]) {
+        // Responses are strictly one per command, so any previous one is complete
+        self.state = MySqlState::Command;
+        self.prepare_pending = false;
+        self.more_results = false;
+        self.expects_result_set = matches!(
+            command.first().copied(),
+            Some(COM_QUERY | COM_STMT_EXECUTE | COM_PROCESS_INFO)
+        );
+        match command.first().copied() {
+            Some(COM_STMT_PREPARE) => {
+                self.prepare_pending = true;
+            }
+            Some(COM_FIELD_LIST) => {
+                // Column definitions with default values, without a header
+                self.state = MySqlState::ReadingFieldList;
+            }
+            Some(COM_CHANGE_USER) => {
+                // Answered like a handshake response: auth exchange, then OK or ERR
+                self.state = MySqlState::WaitingHandshakeResponse;
+            }
+            Some(COM_STMT_EXECUTE) => {
+                self.binary_rows = true;
+                // Normally replaced by the column definitions in the response
+                if let Some(types) =
+                    read_statement_id(command).and_then(|id| self.statements.get(&id))
+                {
+                    self.column_types = types.clone();
+                }
+            }
+            Some(COM_STMT_FETCH) => {
+                // Rows from an open cursor, without column definitions
+                if let Some(types) =
+                    read_statement_id(command).and_then(|id| self.statements.get(&id))
+                {
+                    self.column_types = types.clone();
+                    self.column_count = types.len();
+                    self.binary_rows = true;
+                    self.state = MySqlState::ReadingRows;
+                }
+            }
+            Some(COM_STMT_CLOSE) => {
+                if let Some(id) = read_statement_id(command) {
+                    self.statements.remove(&id);
+                }
+            }
+            _ => {
+                self.binary_rows = false;
+            }
+        }
+    }
+}
+
+/// Statement id of a COM_STMT_* command
+fn read_statement_id(command: &[u8]) -> Option<u32> {
+    command
+        .get(1..5)
+        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
 }
 
 impl Decoder for MySqlCodec {
@@ -194,18 +499,62 @@ impl Decoder for MySqlCodec {
             return Ok(None);
         }
 
-        // Read packet length (little-endian 3 bytes)
-        let payload_len = (src[0] as usize) | ((src[1] as usize) << 8) | ((src[2] as usize) << 16);
         let sequence_id = src[3];
 
-        let total_len = 4 + payload_len;
-        if src.len() < total_len {
-            src.reserve(total_len - src.len());
-            return Ok(None);
+        // Payloads of 16 MB or more are split over consecutive packets, the
+        // last of which is shorter than the maximum
+        let mut payload_len = 0;
+        let mut chunks = 0;
+        let mut offset = 0;
+        loop {
+            if src.len() < offset + 4 {
+                return Ok(None);
+            }
+            // Read packet length (little-endian 3 bytes)
+            let chunk_len = (src[offset] as usize)
+                | ((src[offset + 1] as usize) << 8)
+                | ((src[offset + 2] as usize) << 16);
+            payload_len += chunk_len;
+            chunks += 1;
+
+            if payload_len > self.max_message_size {
+                crate::metrics::record_protocol_error("mysql");
+                return Err(anyhow::anyhow!(
+                    "MySQL packet length {} exceeds maximum {}",
+                    payload_len,
+                    self.max_message_size
+                ));
+            }
+
+            offset += 4 + chunk_len;
+            if src.len() < offset {
+                src.reserve(offset - src.len());
+                return Ok(None);
+            }
+            if chunk_len < MAX_PACKET_PAYLOAD {
+                break;
+            }
         }
 
-        let mut packet = src.split_to(total_len);
-        packet.advance(4); // Skip header
+        let mut packet = if chunks == 1 {
+            let mut packet = src.split_to(offset);
+            packet.advance(4); // Skip header
+            packet
+        } else {
+            let mut packet = BytesMut::with_capacity(payload_len);
+            let mut frames = src.split_to(offset);
+            while !frames.is_empty() {
+                let chunk_len = (frames[0] as usize)
+                    | ((frames[1] as usize) << 8)
+                    | ((frames[2] as usize) << 16);
+                frames.advance(4);
+                packet.extend_from_slice(&frames.split_to(chunk_len));
+            }
+            packet
+        };
+
+        // A split payload uses one sequence id per packet
+        self.next_sequence_id = sequence_id.wrapping_add(chunks as u8);
 
         // Dispatch based on state and packet type
         match self.state {
@@ -226,9 +575,17 @@ impl Decoder for MySqlCodec {
             MySqlState::WaitingHandshakeResponse => {
                 if !self.is_client_side {
                     // We're the server, expecting client response
-                    let response = parse_handshake_response(&mut packet, self.capability_flags)?;
+                    if packet.len() == SSL_REQUEST_LEN
+                        && (&packet[..]).get_u32_le() & CLIENT_SSL != 0
+                    {
+                        // The full response follows once TLS is up
+                        return Ok(Some(MySqlMessage::SslRequest(parse_ssl_request(
+                            &mut packet,
+                        ))));
+                    }
+                    let response = parse_handshake_response(&mut packet, sequence_id)?;
                     self.capability_flags = response.capability_flags;
-                    self.state = MySqlState::Command;
+                    self.state = MySqlState::Authenticating;
                     Ok(Some(MySqlMessage::HandshakeResponse(response)))
                 } else {
                     // We're the client, expecting OK/ERR after sending our response
@@ -243,19 +600,47 @@ impl Decoder for MySqlCodec {
                         0xff => {
                             let err =
                                 parse_err_packet(&mut packet, sequence_id, self.capability_flags)?;
+                            // Only a failed COM_CHANGE_USER leaves the connection open
+                            self.state = MySqlState::Command;
                             Ok(Some(MySqlMessage::Err(err)))
                         }
-                        _ => {
-                            self.state = MySqlState::Command;
-                            Ok(Some(MySqlMessage::Generic(GenericPacket {
+                        // The exchange continues until OK or ERR
+                        0xfe => {
+                            let switch = parse_auth_switch_request(&mut packet, sequence_id)?;
+                            Ok(Some(MySqlMessage::AuthSwitchRequest(switch)))
+                        }
+                        0x01 => {
+                            packet.advance(1);
+                            Ok(Some(MySqlMessage::AuthMoreData(AuthMoreData {
                                 sequence_id,
-                                payload: packet,
+                                data: packet.freeze(),
                             })))
                         }
+                        _ => Ok(Some(MySqlMessage::Generic(GenericPacket {
+                            sequence_id,
+                            payload: packet,
+                        }))),
                     }
                 }
             }
-            MySqlState::Command => {
+            MySqlState::Authenticating => {
+                // Auth switch responses and other auth data from the client
+                Ok(Some(MySqlMessage::Generic(GenericPacket {
+                    sequence_id,
+                    payload: packet,
+                })))
+            }
+            MySqlState::LocalInfile if !self.is_client_side => {
+                if packet.is_empty() {
+                    // The server replies to the end of the file with OK or ERR
+                    self.state = MySqlState::Command;
+                }
+                Ok(Some(MySqlMessage::LocalInfileData(GenericPacket {
+                    sequence_id,
+                    payload: packet,
+                })))
+            }
+            MySqlState::Command | MySqlState::LocalInfile => {
                 if packet.is_empty() {
                     return Ok(Some(MySqlMessage::Generic(GenericPacket {
                         sequence_id,
@@ -266,7 +651,7 @@ impl Decoder for MySqlCodec {
                 let first_byte = packet[0];
 
                 // Check for COM_QUERY from client
-                if !self.is_client_side && first_byte == 0x03 {
+                if !self.is_client_side && first_byte == COM_QUERY {
                     packet.advance(1);
                     let query = packet.freeze();
                     return Ok(Some(MySqlMessage::Query(QueryPacket {
@@ -275,19 +660,80 @@ impl Decoder for MySqlCodec {
                     })));
                 }
 
-                // Check for result set header (column count) from server
+                if !self.is_client_side && first_byte == COM_INIT_DB {
+                    packet.advance(1);
+                    return Ok(Some(MySqlMessage::InitDb(InitDbPacket {
+                        sequence_id,
+                        database: String::from_utf8_lossy(&packet).into_owned(),
+                    })));
+                }
+
+                if !self.is_client_side && first_byte == COM_CHANGE_USER {
+                    let change_user =
+                        parse_change_user(&mut packet, sequence_id, self.capability_flags)?;
+                    self.state = MySqlState::Authenticating;
+                    return Ok(Some(MySqlMessage::ChangeUser(change_user)));
+                }
+
+                // LOAD DATA LOCAL INFILE: the client sends the file next
+                if self.is_client_side && first_byte == 0xfb {
+                    packet.advance(1);
+                    self.state = MySqlState::LocalInfile;
+                    return Ok(Some(MySqlMessage::LocalInfileRequest(LocalInfileRequest {
+                        sequence_id,
+                        filename: packet.freeze(),
+                    })));
+                }
+
+                // Result set header (column count) from server
                 if self.is_client_side
+                    && self.expects_result_set
                     && first_byte != 0x00
                     && first_byte != 0xff
-                    && first_byte != 0xfe
+                    && !(first_byte == 0xfe && packet.len() < 9)
                 {
-                    // Could be column count (length-encoded int)
-                    let (col_count, _) = read_lenenc_int(&packet)?;
-                    if col_count > 0 && col_count < 1000 {
-                        self.column_count = col_count as usize;
-                        self.state = MySqlState::ReadingColumns {
-                            remaining: col_count as usize,
+                    let header =
+                        parse_result_set_header(&packet, sequence_id, self.capability_flags)?;
+                    let column_count = usize::try_from(header.column_count)?;
+                    self.column_count = column_count;
+                    // Without metadata, binary rows keep the prepared statement's types
+                    let columns = if header.metadata_follows == Some(false) {
+                        0
+                    } else {
+                        self.column_types.clear();
+                        column_count
+                    };
+                    self.state = if columns == 0 && self.uses_deprecate_eof() {
+                        MySqlState::ReadingRows
+                    } else {
+                        MySqlState::ReadingColumns { remaining: columns }
+                    };
+                    return Ok(Some(MySqlMessage::ResultSetHeader(header)));
+                }
+
+                // COM_STMT_PREPARE OK, followed by parameter and column definitions
+                if self.is_client_side && self.prepare_pending && first_byte == 0x00 {
+                    self.prepare_pending = false;
+                    if packet.len() >= 12 {
+                        let statement_id = read_statement_id(&packet).unwrap_or_default();
+                        let num_columns = (&packet[5..7]).get_u16_le() as usize;
+                        let num_params = (&packet[7..9]).get_u16_le() as usize;
+                        let deprecate_eof = self.uses_deprecate_eof();
+                        let with_eof = |count: usize| {
+                            if count > 0 && !deprecate_eof {
+                                count + 1
+                            } else {
+                                count
+                            }
                         };
+                        self.statements.insert(statement_id, Vec::new());
+                        if num_columns > 0 || num_params > 0 {
+                            self.state = MySqlState::ReadingStatementDefinitions {
+                                statement_id,
+                                params_remaining: with_eof(num_params),
+                                columns_remaining: with_eof(num_columns),
+                            };
+                        }
                         return Ok(Some(MySqlMessage::Generic(GenericPacket {
                             sequence_id,
                             payload: packet,
@@ -298,18 +744,23 @@ impl Decoder for MySqlCodec {
                 // OK packet
                 if first_byte == 0x00 {
                     let ok = parse_ok_packet(&mut packet, sequence_id, self.capability_flags)?;
+                    self.state = MySqlState::Command;
+                    self.more_results = ok.status_flags & SERVER_MORE_RESULTS_EXISTS != 0;
                     return Ok(Some(MySqlMessage::Ok(ok)));
                 }
 
                 // ERR packet
                 if first_byte == 0xff {
                     let err = parse_err_packet(&mut packet, sequence_id, self.capability_flags)?;
+                    self.state = MySqlState::Command;
+                    self.more_results = false;
                     return Ok(Some(MySqlMessage::Err(err)));
                 }
 
                 // EOF packet (0xfe with payload < 9 bytes)
                 if first_byte == 0xfe && packet.len() < 9 {
                     let eof = parse_eof_packet(&mut packet, sequence_id)?;
+                    self.more_results = eof.status_flags & SERVER_MORE_RESULTS_EXISTS != 0;
                     return Ok(Some(MySqlMessage::Eof(eof)));
                 }
 
@@ -318,6 +769,32 @@ impl Decoder for MySqlCodec {
                     payload: packet,
                 })))
             }
+            MySqlState::ReadingFieldList => {
+                let first_byte = packet[0];
+
+                if first_byte == 0xff {
+                    let err = parse_err_packet(&mut packet, sequence_id, self.capability_flags)?;
+                    self.state = MySqlState::Command;
+                    return Ok(Some(MySqlMessage::Err(err)));
+                }
+
+                // Column definitions start with the catalog, never 0xfe; the
+                // end is an EOF, or an OK with a 0xfe header under CLIENT_DEPRECATE_EOF
+                if first_byte == 0xfe {
+                    self.state = MySqlState::Command;
+                    if packet.len() < 9 {
+                        let eof = parse_eof_packet(&mut packet, sequence_id)?;
+                        return Ok(Some(MySqlMessage::Eof(eof)));
+                    }
+                    return Ok(Some(MySqlMessage::Generic(GenericPacket {
+                        sequence_id,
+                        payload: packet,
+                    })));
+                }
+
+                let col_def = parse_column_definition(&mut packet, sequence_id)?;
+                Ok(Some(MySqlMessage::ColumnDefinition(col_def)))
+            }
             MySqlState::ReadingColumns { remaining } => {
                 let first_byte = packet[0];
 
@@ -330,6 +807,7 @@ impl Decoder for MySqlCodec {
 
                 // Parse column definition
                 let col_def = parse_column_definition(&mut packet, sequence_id)?;
+                self.column_types.push(col_def.column_type);
                 let new_remaining = remaining.saturating_sub(1);
 
                 if new_remaining == 0 {
@@ -349,17 +827,19 @@ impl Decoder for MySqlCodec {
             MySqlState::ReadingRows => {
                 let first_byte = packet[0];
 
-                // EOF packet marks end of rows
+                // EOF packet marks end of rows; another result set may follow
                 if first_byte == 0xfe && packet.len() < 9 {
                     let eof = parse_eof_packet(&mut packet, sequence_id)?;
                     self.state = MySqlState::Command;
+                    self.more_results = eof.status_flags & SERVER_MORE_RESULTS_EXISTS != 0;
                     return Ok(Some(MySqlMessage::Eof(eof)));
                 }
 
-                // OK packet (with CLIENT_DEPRECATE_EOF)
-                if first_byte == 0x00 && self.uses_deprecate_eof() {
+                // OK packet (with CLIENT_DEPRECATE_EOF); binary rows also start with 0x00
+                if first_byte == 0x00 && self.uses_deprecate_eof() && !self.binary_rows {
                     let ok = parse_ok_packet(&mut packet, sequence_id, self.capability_flags)?;
                     self.state = MySqlState::Command;
+                    self.more_results = ok.status_flags & SERVER_MORE_RESULTS_EXISTS != 0;
                     return Ok(Some(MySqlMessage::Ok(ok)));
                 }
 
@@ -367,13 +847,52 @@ impl Decoder for MySqlCodec {
                 if first_byte == 0xff {
                     let err = parse_err_packet(&mut packet, sequence_id, self.capability_flags)?;
                     self.state = MySqlState::Command;
+                    self.more_results = false;
                     return Ok(Some(MySqlMessage::Err(err)));
                 }
 
+                if self.binary_rows {
+                    let row =
+                        parse_binary_result_row(&mut packet, sequence_id, &self.column_types)?;
+                    return Ok(Some(MySqlMessage::BinaryResultRow(row)));
+                }
+
                 // Parse result row
                 let row = parse_result_row(&mut packet, sequence_id, self.column_count)?;
                 Ok(Some(MySqlMessage::ResultRow(row)))
             }
+            MySqlState::ReadingStatementDefinitions {
+                statement_id,
+                mut params_remaining,
+                mut columns_remaining,
+            } => {
+                if params_remaining > 0 {
+                    params_remaining -= 1;
+                } else {
+                    // Column definitions start with the catalog, never 0xfe
+                    if packet.first() != Some(&0xfe) {
+                        let col_def = parse_column_definition(&mut packet.clone(), sequence_id)?;
+                        self.statements
+                            .entry(statement_id)
+                            .or_default()
+                            .push(col_def.column_type);
+                    }
+                    columns_remaining = columns_remaining.saturating_sub(1);
+                }
+                self.state = if params_remaining == 0 && columns_remaining == 0 {
+                    MySqlState::Command
+                } else {
+                    MySqlState::ReadingStatementDefinitions {
+                        statement_id,
+                        params_remaining,
+                        columns_remaining,
+                    }
+                };
+                Ok(Some(MySqlMessage::Generic(GenericPacket {
+                    sequence_id,
+                    payload: packet,
+                })))
+            }
         }
     }
 }
@@ -382,21 +901,90 @@ impl Encoder<MySqlMessage> for MySqlCodec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: MySqlMessage, dst: &mut BytesMut) -> Result<()> {
+        // The OK or ERR that ends authentication starts the command phase
+        if self.state == MySqlState::Authenticating
+            && matches!(item, MySqlMessage::Ok(_) | MySqlMessage::Err(_))
+        {
+            self.state = MySqlState::Command;
+        }
+        // File data follows a local infile request, up to an empty packet
+        match &item {
+            MySqlMessage::LocalInfileRequest(_) => self.state = MySqlState::LocalInfile,
+            MySqlMessage::LocalInfileData(d) if d.payload.is_empty() => {
+                self.state = MySqlState::Command;
+            }
+            _ => {}
+        }
+        let mut is_command = false;
+        if self.is_client_side
+            && !matches!(
+                self.state,
+                MySqlState::WaitingHandshake
+                    | MySqlState::WaitingHandshakeResponse
+                    | MySqlState::Authenticating
+                    | MySqlState::LocalInfile
+            )
+        {
+            is_command = true;
+            match &item {
+                MySqlMessage::Query(_) => self.on_command(&[COM_QUERY]),
+                MySqlMessage::InitDb(_) => self.on_command(&[COM_INIT_DB]),
+                MySqlMessage::ChangeUser(_) => self.on_command(&[COM_CHANGE_USER]),
+                MySqlMessage::Generic(g) => self.on_command(&g.payload),
+                _ => is_command = false,
+            }
+        }
+
+        let start = dst.len();
         match item {
             MySqlMessage::Handshake(h) => encode_handshake_v10(&h, dst),
             MySqlMessage::HandshakeResponse(r) => encode_handshake_response(&r, dst),
+            MySqlMessage::SslRequest(r) => encode_ssl_request(&r, dst),
+            MySqlMessage::AuthSwitchRequest(a) => encode_auth_switch_request(&a, dst),
+            MySqlMessage::AuthMoreData(a) => encode_auth_more_data(&a, dst),
             MySqlMessage::Generic(g) => encode_generic(&g, dst),
             MySqlMessage::Query(q) => encode_query(&q, dst),
+            MySqlMessage::InitDb(d) => encode_init_db(&d, dst),
+            MySqlMessage::ChangeUser(c) => encode_change_user(&c, dst, self.capability_flags),
+            MySqlMessage::ResultSetHeader(h) => encode_result_set_header(&h, dst),
             MySqlMessage::ColumnDefinition(c) => encode_column_definition(&c, dst),
             MySqlMessage::ResultRow(r) => encode_result_row(&r, dst),
+            MySqlMessage::BinaryResultRow(r) => encode_binary_result_row(&r, dst),
             MySqlMessage::Ok(o) => encode_ok(&o, dst, self.capability_flags),
             MySqlMessage::Err(e) => encode_err(&e, dst, self.capability_flags),
             MySqlMessage::Eof(e) => encode_eof(&e, dst),
+            MySqlMessage::LocalInfileRequest(r) => encode_local_infile_request(&r, dst),
+            MySqlMessage::LocalInfileData(d) => encode_generic(&d, dst),
         }
+
+        // Every command starts a new sequence
+        let first = if is_command { 0 } else { self.next_sequence_id };
+        self.next_sequence_id = number_packets(
+            &mut dst[start..],
+            self.renumber_sequence_ids.then_some(first),
+        );
         Ok(())
     }
 }
 
+/// Give consecutive sequence ids, starting at `first`, to the packets in
+/// `buf` (or keep theirs when `None`). Returns the id of the next packet.
+fn number_packets(buf: &mut [u8], first: Option<u8>) -> u8 {
+    let mut next = first.unwrap_or_default();
+    let mut offset = 0;
+    while offset + 4 <= buf.len() {
+        let len = (buf[offset] as usize)
+            | ((buf[offset + 1] as usize) << 8)
+            | ((buf[offset + 2] as usize) << 16);
+        if first.is_some() {
+            buf[offset + 3] = next;
+        }
+        next = buf[offset + 3].wrapping_add(1);
+        offset += 4 + len;
+    }
+    next
+}
+
 // ============================================================================
 // Parsing helpers
 // ============================================================================
@@ -518,7 +1106,21 @@ fn parse_handshake_v10(buf: &mut BytesMut) -> Result<HandshakeV10> {
     })
 }
 
-fn parse_handshake_response(buf: &mut BytesMut, _server_caps: u32) -> Result<HandshakeResponse> {
+/// Payload length of an SSLRequest packet
+const SSL_REQUEST_LEN: usize = 32;
+
+fn parse_ssl_request(buf: &mut BytesMut) -> SslRequest {
+    let capability_flags = buf.get_u32_le();
+    let max_packet_size = buf.get_u32_le();
+    let character_set = buf.get_u8();
+    SslRequest {
+        capability_flags,
+        max_packet_size,
+        character_set,
+    }
+}
+
+fn parse_handshake_response(buf: &mut BytesMut, sequence_id: u8) -> Result<HandshakeResponse> {
     let capability_flags = buf.get_u32_le();
     let max_packet_size = buf.get_u32_le();
     let character_set = buf.get_u8();
@@ -551,6 +1153,7 @@ fn parse_handshake_response(buf: &mut BytesMut, _server_caps: u32) -> Result<Han
     };
 
     Ok(HandshakeResponse {
+        sequence_id,
         capability_flags,
         max_packet_size,
         character_set,
@@ -561,6 +1164,56 @@ fn parse_handshake_response(buf: &mut BytesMut, _server_caps: u32) -> Result<Han
     })
 }
 
+fn parse_change_user(
+    buf: &mut BytesMut,
+    sequence_id: u8,
+    capability_flags: u32,
+) -> Result<ChangeUserPacket> {
+    buf.advance(1); // COM_CHANGE_USER
+    let username = read_null_terminated_string(buf)?;
+
+    let auth_response = if capability_flags & CLIENT_SECURE_CONNECTION != 0 {
+        if !buf.has_remaining() {
+            return Err(anyhow::anyhow!("COM_CHANGE_USER missing auth response"));
+        }
+        let len = buf.get_u8() as usize;
+        if buf.len() < len {
+            return Err(anyhow::anyhow!("COM_CHANGE_USER auth response truncated"));
+        }
+        buf.split_to(len).freeze()
+    } else {
+        let pos = buf
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| anyhow::anyhow!("Missing null terminator"))?;
+        let data = buf.split_to(pos).freeze();
+        buf.advance(1);
+        data
+    };
+
+    let database = read_null_terminated_string(buf)?;
+
+    Ok(ChangeUserPacket {
+        sequence_id,
+        username,
+        auth_response,
+        database,
+        trailer: buf.split().freeze(),
+    })
+}
+
+fn parse_auth_switch_request(buf: &mut BytesMut, sequence_id: u8) -> Result<AuthSwitchRequest> {
+    buf.advance(1); // header 0xfe
+    let plugin_name = read_null_terminated_string(buf)?;
+    let plugin_data = buf.split().freeze();
+
+    Ok(AuthSwitchRequest {
+        sequence_id,
+        plugin_name,
+        plugin_data,
+    })
+}
+
 fn parse_ok_packet(buf: &mut BytesMut, sequence_id: u8, capability_flags: u32) -> Result<OkPacket> {
     buf.advance(1); // header 0x00
     let affected_rows = read_lenenc_int_from_buf(buf)?;
@@ -623,6 +1276,30 @@ fn parse_eof_packet(buf: &mut BytesMut, sequence_id: u8) -> Result<EofPacket> {
     })
 }
 
+fn parse_result_set_header(
+    buf: &[u8],
+    sequence_id: u8,
+    capability_flags: u32,
+) -> Result<ResultSetHeader> {
+    let (column_count, len) = read_lenenc_int(buf)?;
+    if column_count == 0 {
+        return Err(anyhow::anyhow!("Result set header with no columns"));
+    }
+    let metadata_follows = if capability_flags & CLIENT_OPTIONAL_RESULTSET_METADATA != 0 {
+        let flag = buf
+            .get(len)
+            .ok_or_else(|| anyhow::anyhow!("Result set header missing metadata flag"))?;
+        Some(*flag != 0)
+    } else {
+        None
+    };
+    Ok(ResultSetHeader {
+        sequence_id,
+        column_count,
+        metadata_follows,
+    })
+}
+
 fn parse_column_definition(buf: &mut BytesMut, sequence_id: u8) -> Result<ColumnDefinition> {
     let catalog = read_lenenc_string(buf)?;
     let schema = read_lenenc_string(buf)?;
@@ -637,6 +1314,12 @@ fn parse_column_definition(buf: &mut BytesMut, sequence_id: u8) -> Result<Column
     let flags = buf.get_u16_le();
     let decimals = buf.get_u8();
     buf.advance(2); // filler
+    // Only present in a COM_FIELD_LIST response
+    let default_value = match buf.first() {
+        None => None,
+        Some(0xfb) => Some(None),
+        Some(_) => Some(Some(BytesMut::from(&read_lenenc_string(buf)?[..]))),
+    };
 
     Ok(ColumnDefinition {
         sequence_id,
@@ -651,6 +1334,7 @@ fn parse_column_definition(buf: &mut BytesMut, sequence_id: u8) -> Result<Column
         column_type,
         flags,
         decimals,
+        default_value,
     })
 }
 
@@ -683,6 +1367,90 @@ fn parse_result_row(buf: &mut BytesMut, sequence_id: u8, column_count: usize) ->
     })
 }
 
+/// Size of a fixed-length binary value, or `None` for length-prefixed types
+fn binary_value_len(column_type: u8, buf: &[u8]) -> Option<usize> {
+    match column_type {
+        MYSQL_TYPE_NULL => Some(0),
+        MYSQL_TYPE_TINY => Some(1),
+        MYSQL_TYPE_SHORT | MYSQL_TYPE_YEAR => Some(2),
+        MYSQL_TYPE_LONG | MYSQL_TYPE_INT24 | MYSQL_TYPE_FLOAT => Some(4),
+        MYSQL_TYPE_LONGLONG | MYSQL_TYPE_DOUBLE => Some(8),
+        // Temporal values carry a one-byte length
+        MYSQL_TYPE_DATE | MYSQL_TYPE_DATETIME | MYSQL_TYPE_TIMESTAMP | MYSQL_TYPE_TIME => {
+            buf.first().map(|&len| 1 + len as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a binary value of this type holds character or byte data worth masking
+fn is_string_type(column_type: u8) -> bool {
+    matches!(
+        column_type,
+        MYSQL_TYPE_VARCHAR
+            | MYSQL_TYPE_VAR_STRING
+            | MYSQL_TYPE_STRING
+            | MYSQL_TYPE_ENUM
+            | MYSQL_TYPE_SET
+            | MYSQL_TYPE_TINY_BLOB
+            | MYSQL_TYPE_MEDIUM_BLOB
+            | MYSQL_TYPE_LONG_BLOB
+            | MYSQL_TYPE_BLOB
+            | MYSQL_TYPE_JSON
+    )
+}
+
+fn parse_binary_result_row(
+    buf: &mut BytesMut,
+    sequence_id: u8,
+    column_types: &[u8],
+) -> Result<BinaryResultRow> {
+    buf.advance(1); // header 0x00
+    // NULL bitmap, offset by two bits
+    let bitmap_len = (column_types.len() + 7 + 2) / 8;
+    if buf.len() < bitmap_len {
+        anyhow::bail!("Not enough bytes for binary row NULL bitmap");
+    }
+    let null_bitmap = buf.split_to(bitmap_len);
+
+    let mut values = Vec::with_capacity(column_types.len());
+    for (i, &column_type) in column_types.iter().enumerate() {
+        let bit = i + 2;
+        if null_bitmap[bit / 8] & (1 << (bit % 8)) != 0 {
+            values.push(None);
+            continue;
+        }
+
+        let value = match binary_value_len(column_type, buf) {
+            Some(len) => {
+                if buf.len() < len {
+                    anyhow::bail!("Not enough bytes for binary value of type {}", column_type);
+                }
+                BinaryValue::Raw(buf.split_to(len).freeze())
+            }
+            None => {
+                let (len, prefix_len) = read_lenenc_int(buf)?;
+                let total_len = prefix_len + len as usize;
+                if buf.len() < total_len {
+                    anyhow::bail!("Not enough bytes for binary value of type {}", column_type);
+                }
+                if is_string_type(column_type) {
+                    buf.advance(prefix_len);
+                    BinaryValue::String(buf.split_to(len as usize))
+                } else {
+                    BinaryValue::Raw(buf.split_to(total_len).freeze())
+                }
+            }
+        };
+        values.push(Some(value));
+    }
+
+    Ok(BinaryResultRow {
+        sequence_id,
+        values,
+    })
+}
+
 // ============================================================================
 // Encoding helpers
 // ============================================================================
@@ -694,6 +1462,24 @@ fn write_packet_header(dst: &mut BytesMut, payload_len: usize, sequence_id: u8)
     dst.put_u8(sequence_id);
 }
 
+/// Write a payload as one packet, or split into consecutive packets with
+/// increasing sequence ids when it doesn't fit in one
+fn write_packet(dst: &mut BytesMut, payload: &[u8], sequence_id: u8) {
+    let mut rest = payload;
+    let mut sequence_id = sequence_id;
+    loop {
+        let len = rest.len().min(MAX_PACKET_PAYLOAD);
+        write_packet_header(dst, len, sequence_id);
+        dst.put_slice(&rest[..len]);
+        // A full-size packet is always followed by another, possibly empty, one
+        if len < MAX_PACKET_PAYLOAD {
+            break;
+        }
+        rest = &rest[len..];
+        sequence_id = sequence_id.wrapping_add(1);
+    }
+}
+
 fn write_lenenc_int(dst: &mut BytesMut, val: u64) {
     if val < 251 {
         dst.put_u8(val as u8);
@@ -737,8 +1523,7 @@ fn encode_handshake_v10(h: &HandshakeV10, dst: &mut BytesMut) {
         payload.put_u8(0);
     }
 
-    write_packet_header(dst, payload.len(), 0);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, 0);
 }
 
 fn encode_handshake_response(r: &HandshakeResponse, dst: &mut BytesMut) {
@@ -768,20 +1553,89 @@ fn encode_handshake_response(r: &HandshakeResponse, dst: &mut BytesMut) {
         payload.put_u8(0);
     }
 
-    write_packet_header(dst, payload.len(), 1);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, r.sequence_id);
+}
+
+fn encode_ssl_request(r: &SslRequest, dst: &mut BytesMut) {
+    write_packet_header(dst, SSL_REQUEST_LEN, 1);
+    dst.put_u32_le(r.capability_flags);
+    dst.put_u32_le(r.max_packet_size);
+    dst.put_u8(r.character_set);
+    dst.put_slice(&[0u8; 23]); // reserved
+}
+
+fn encode_auth_switch_request(a: &AuthSwitchRequest, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(0xfe);
+    payload.put_slice(a.plugin_name.as_bytes());
+    payload.put_u8(0);
+    payload.put_slice(&a.plugin_data);
+
+    write_packet(dst, &payload, a.sequence_id);
+}
+
+fn encode_auth_more_data(a: &AuthMoreData, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(0x01);
+    payload.put_slice(&a.data);
+
+    write_packet(dst, &payload, a.sequence_id);
+}
+
+fn encode_local_infile_request(r: &LocalInfileRequest, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(0xfb);
+    payload.put_slice(&r.filename);
+
+    write_packet(dst, &payload, r.sequence_id);
 }
 
 fn encode_generic(g: &GenericPacket, dst: &mut BytesMut) {
-    write_packet_header(dst, g.payload.len(), g.sequence_id);
-    dst.put_slice(&g.payload);
+    write_packet(dst, &g.payload, g.sequence_id);
 }
 
 fn encode_query(q: &QueryPacket, dst: &mut BytesMut) {
-    let payload_len = 1 + q.query.len();
-    write_packet_header(dst, payload_len, q.sequence_id);
-    dst.put_u8(0x03); // COM_QUERY
-    dst.put_slice(&q.query);
+    let mut payload = BytesMut::with_capacity(1 + q.query.len());
+    payload.put_u8(COM_QUERY);
+    payload.put_slice(&q.query);
+    write_packet(dst, &payload, q.sequence_id);
+}
+
+fn encode_init_db(d: &InitDbPacket, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(COM_INIT_DB);
+    payload.put_slice(d.database.as_bytes());
+
+    write_packet(dst, &payload, d.sequence_id);
+}
+
+fn encode_change_user(c: &ChangeUserPacket, dst: &mut BytesMut, capability_flags: u32) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(COM_CHANGE_USER);
+    payload.put_slice(c.username.as_bytes());
+    payload.put_u8(0);
+    if capability_flags & CLIENT_SECURE_CONNECTION != 0 {
+        payload.put_u8(c.auth_response.len() as u8);
+        payload.put_slice(&c.auth_response);
+    } else {
+        payload.put_slice(&c.auth_response);
+        payload.put_u8(0);
+    }
+    payload.put_slice(c.database.as_bytes());
+    payload.put_u8(0);
+    payload.put_slice(&c.trailer);
+
+    write_packet(dst, &payload, c.sequence_id);
+}
+
+fn encode_result_set_header(h: &ResultSetHeader, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    write_lenenc_int(&mut payload, h.column_count);
+    if let Some(metadata_follows) = h.metadata_follows {
+        payload.put_u8(metadata_follows as u8);
+    }
+
+    write_packet(dst, &payload, h.sequence_id);
 }
 
 fn encode_column_definition(c: &ColumnDefinition, dst: &mut BytesMut) {
@@ -799,9 +1653,13 @@ fn encode_column_definition(c: &ColumnDefinition, dst: &mut BytesMut) {
     payload.put_u16_le(c.flags);
     payload.put_u8(c.decimals);
     payload.put_u16(0); // filler
+    match &c.default_value {
+        Some(Some(v)) => write_lenenc_string(&mut payload, v),
+        Some(None) => payload.put_u8(0xfb), // NULL
+        None => {}
+    }
 
-    write_packet_header(dst, payload.len(), c.sequence_id);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, c.sequence_id);
 }
 
 fn encode_result_row(r: &ResultRow, dst: &mut BytesMut) {
@@ -813,8 +1671,30 @@ fn encode_result_row(r: &ResultRow, dst: &mut BytesMut) {
         }
     }
 
-    write_packet_header(dst, payload.len(), r.sequence_id);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, r.sequence_id);
+}
+
+fn encode_binary_result_row(r: &BinaryResultRow, dst: &mut BytesMut) {
+    let mut payload = BytesMut::new();
+    payload.put_u8(0x00);
+
+    let mut null_bitmap = vec![0u8; (r.values.len() + 7 + 2) / 8];
+    for (i, val) in r.values.iter().enumerate() {
+        if val.is_none() {
+            let bit = i + 2;
+            null_bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    payload.put_slice(&null_bitmap);
+
+    for val in r.values.iter().flatten() {
+        match val {
+            BinaryValue::String(v) => write_lenenc_string(&mut payload, v),
+            BinaryValue::Raw(v) => payload.put_slice(v),
+        }
+    }
+
+    write_packet(dst, &payload, r.sequence_id);
 }
 
 fn encode_ok(o: &OkPacket, dst: &mut BytesMut, capability_flags: u32) {
@@ -830,8 +1710,7 @@ fn encode_ok(o: &OkPacket, dst: &mut BytesMut, capability_flags: u32) {
 
     payload.put_slice(&o.info);
 
-    write_packet_header(dst, payload.len(), o.sequence_id);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, o.sequence_id);
 }
 
 fn encode_err(e: &ErrPacket, dst: &mut BytesMut, capability_flags: u32) {
@@ -846,8 +1725,7 @@ fn encode_err(e: &ErrPacket, dst: &mut BytesMut, capability_flags: u32) {
 
     payload.put_slice(e.error_message.as_bytes());
 
-    write_packet_header(dst, payload.len(), e.sequence_id);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, e.sequence_id);
 }
 
 fn encode_eof(e: &EofPacket, dst: &mut BytesMut) {
@@ -856,14 +1734,656 @@ fn encode_eof(e: &EofPacket, dst: &mut BytesMut) {
     payload.put_u16_le(e.warnings);
     payload.put_u16_le(e.status_flags);
 
-    write_packet_header(dst, payload.len(), e.sequence_id);
-    dst.put_slice(&payload);
+    write_packet(dst, &payload, e.sequence_id);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_oversized_packet_rejected() {
+        let mut codec = MySqlCodec::new_server().with_max_message_size(1024);
+        // Header claiming a 16 MB payload
+        let mut buf = BytesMut::from(&[0xff, 0xff, 0xff, 0x00][..]);
+
+        assert!(codec.decode(&mut buf).is_err());
+        assert!(buf.capacity() < 1024);
+    }
+
+    /// Upstream codec past authentication, as the proxy uses it
+    fn command_phase_client_codec() -> MySqlCodec {
+        let mut codec = MySqlCodec::new_client();
+        codec.set_capability_flags(CLIENT_PROTOCOL_41);
+        codec.state = MySqlState::Command;
+        codec
+    }
+
+    fn packet(sequence_id: u8, payload: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_packet_header(&mut buf, payload.len(), sequence_id);
+        buf.put_slice(payload);
+        buf
+    }
+
+    fn column_definition_packet(sequence_id: u8, name: &str, column_type: u8) -> BytesMut {
+        let mut buf = BytesMut::new();
+        encode_column_definition(
+            &ColumnDefinition {
+                sequence_id,
+                catalog: Bytes::from_static(b"def"),
+                schema: Bytes::from_static(b"shop"),
+                table: Bytes::from_static(b"users"),
+                org_table: Bytes::from_static(b"users"),
+                name: Bytes::copy_from_slice(name.as_bytes()),
+                org_name: Bytes::copy_from_slice(name.as_bytes()),
+                character_set: 45,
+                column_length: 255,
+                column_type,
+                flags: 0,
+                decimals: 0,
+                default_value: None,
+            },
+            &mut buf,
+        );
+        buf
+    }
+
+    fn eof_packet(sequence_id: u8) -> BytesMut {
+        packet(sequence_id, &[0xfe, 0, 0, 2, 0])
+    }
+
+    fn send_command(codec: &mut MySqlCodec, command: &[u8]) {
+        let mut out = BytesMut::new();
+        codec
+            .encode(
+                MySqlMessage::Generic(GenericPacket {
+                    sequence_id: 0,
+                    payload: BytesMut::from(command),
+                }),
+                &mut out,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_prepare_response_records_column_types() {
+        let mut codec = command_phase_client_codec();
+        send_command(&mut codec, b"\x16SELECT email FROM users WHERE id = ?");
+
+        // Statement 7, one column, one parameter
+        let mut buf = packet(1, &[0x00, 7, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0]);
+        buf.extend_from_slice(&column_definition_packet(2, "?", MYSQL_TYPE_LONGLONG));
+        buf.extend_from_slice(&eof_packet(3));
+        buf.extend_from_slice(&column_definition_packet(4, "email", MYSQL_TYPE_VAR_STRING));
+        buf.extend_from_slice(&eof_packet(5));
+
+        for _ in 0..5 {
+            let msg = codec.decode(&mut buf).unwrap().unwrap();
+            assert!(matches!(msg, MySqlMessage::Generic(_)), "got {:?}", msg);
+        }
+        assert!(buf.is_empty());
+        assert!(codec.in_command_phase());
+        assert_eq!(codec.statements[&7], vec![MYSQL_TYPE_VAR_STRING]);
+    }
+
+    #[test]
+    fn test_binary_result_row_roundtrip() {
+        let mut codec = command_phase_client_codec().with_sequence_passthrough();
+        send_command(&mut codec, &[COM_STMT_EXECUTE, 7, 0, 0, 0, 0, 1, 0, 0, 0]);
+
+        let mut buf = packet(1, &[2]);
+        buf.extend_from_slice(&column_definition_packet(2, "email", MYSQL_TYPE_VAR_STRING));
+        buf.extend_from_slice(&column_definition_packet(3, "id", MYSQL_TYPE_LONG));
+        buf.extend_from_slice(&eof_packet(4));
+        for _ in 0..4 {
+            codec.decode(&mut buf).unwrap().unwrap();
+        }
+
+        // email = 'test@example.com', id = 42
+        let mut payload = vec![0x00, 0x00, 16];
+        payload.extend_from_slice(b"test@example.com");
+        payload.extend_from_slice(&42u32.to_le_bytes());
+        let row_packet = packet(5, &payload);
+        // email = NULL, id = 43: bit 2 of the bitmap marks the first column NULL
+        let mut payload = vec![0x00, 0b100];
+        payload.extend_from_slice(&43u32.to_le_bytes());
+        let null_row_packet = packet(6, &payload);
+
+        let mut buf = row_packet.clone();
+        let Some(MySqlMessage::BinaryResultRow(row)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected binary row");
+        };
+        let Some(BinaryValue::String(email)) = &row.values[0] else {
+            panic!("expected string value");
+        };
+        assert_eq!(&email[..], b"test@example.com");
+        assert!(
+            matches!(&row.values[1], Some(BinaryValue::Raw(v)) if v[..] == 42u32.to_le_bytes())
+        );
+        let mut out = BytesMut::new();
+        codec
+            .encode(MySqlMessage::BinaryResultRow(row), &mut out)
+            .unwrap();
+        assert_eq!(out, row_packet);
+
+        let mut buf = null_row_packet.clone();
+        let Some(MySqlMessage::BinaryResultRow(row)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected binary row");
+        };
+        assert!(row.values[0].is_none());
+        let mut out = BytesMut::new();
+        codec
+            .encode(MySqlMessage::BinaryResultRow(row), &mut out)
+            .unwrap();
+        assert_eq!(out, null_row_packet);
+
+        let mut buf = eof_packet(7);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Eof(_))
+        ));
+        assert!(codec.in_command_phase());
+    }
+
+    fn text_row_packet(sequence_id: u8, values: &[&[u8]]) -> BytesMut {
+        let mut payload = BytesMut::new();
+        for value in values {
+            write_lenenc_string(&mut payload, value);
+        }
+        packet(sequence_id, &payload)
+    }
+
+    #[test]
+    fn test_consecutive_result_sets() {
+        let mut codec = command_phase_client_codec();
+        let mut out = BytesMut::new();
+        codec
+            .encode(
+                MySqlMessage::Query(QueryPacket {
+                    sequence_id: 0,
+                    query: Bytes::from_static(b"SELECT 1; SELECT id, email FROM users"),
+                }),
+                &mut out,
+            )
+            .unwrap();
+
+        // First result set, ending with SERVER_MORE_RESULTS_EXISTS
+        let mut buf = packet(1, &[1]);
+        buf.extend_from_slice(&column_definition_packet(2, "1", MYSQL_TYPE_LONGLONG));
+        buf.extend_from_slice(&eof_packet(3));
+        buf.extend_from_slice(&text_row_packet(4, &[b"1"]));
+        buf.extend_from_slice(&packet(5, &[0xfe, 0, 0, 0x0a, 0]));
+        // Second result set with a different column count
+        buf.extend_from_slice(&packet(6, &[2]));
+        buf.extend_from_slice(&column_definition_packet(7, "id", MYSQL_TYPE_LONG));
+        buf.extend_from_slice(&column_definition_packet(8, "email", MYSQL_TYPE_VAR_STRING));
+        buf.extend_from_slice(&eof_packet(9));
+        buf.extend_from_slice(&text_row_packet(10, &[b"7", b"test@example.com"]));
+        buf.extend_from_slice(&eof_packet(11));
+
+        let mut next = || codec.decode(&mut buf).unwrap().unwrap();
+        assert!(matches!(next(), MySqlMessage::ResultSetHeader(h) if h.column_count == 1));
+        assert!(matches!(next(), MySqlMessage::ColumnDefinition(_)));
+        assert!(matches!(next(), MySqlMessage::Eof(_)));
+        assert!(matches!(next(), MySqlMessage::ResultRow(r) if r.values.len() == 1));
+        assert!(
+            matches!(next(), MySqlMessage::Eof(e) if e.status_flags & SERVER_MORE_RESULTS_EXISTS != 0)
+        );
+        assert!(!codec.in_command_phase());
+        assert!(!codec.in_result_set());
+
+        let mut next = || codec.decode(&mut buf).unwrap().unwrap();
+        assert!(matches!(next(), MySqlMessage::ResultSetHeader(h) if h.column_count == 2));
+        assert!(matches!(next(), MySqlMessage::ColumnDefinition(_)));
+        assert!(matches!(next(), MySqlMessage::ColumnDefinition(_)));
+        assert!(matches!(next(), MySqlMessage::Eof(_)));
+        let MySqlMessage::ResultRow(row) = next() else {
+            panic!("expected result row");
+        };
+        assert_eq!(&row.values[1].as_ref().unwrap()[..], b"test@example.com");
+        assert!(matches!(next(), MySqlMessage::Eof(_)));
+        assert!(codec.in_command_phase());
+    }
+
+    #[test]
+    fn test_ok_with_more_results_keeps_response_open() {
+        let mut codec = command_phase_client_codec();
+        send_command(&mut codec, b"\x03INSERT INTO t VALUES (1); SELECT 1");
+
+        let mut buf = packet(1, &[0x00, 1, 0, 0x08, 0, 0, 0]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Ok(_))
+        ));
+        assert!(!codec.in_command_phase());
+
+        let mut buf = packet(2, &[1]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::ResultSetHeader(_))
+        ));
+        assert!(codec.in_result_set());
+    }
+
+    #[test]
+    fn test_wide_result_set_header() {
+        let mut codec = command_phase_client_codec().with_sequence_passthrough();
+        send_command(&mut codec, b"\x03SELECT * FROM wide");
+
+        // 1500 columns, as a 2-byte length-encoded integer
+        let mut buf = packet(1, &[0xfc, 0xdc, 0x05]);
+        let Some(MySqlMessage::ResultSetHeader(header)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected result set header");
+        };
+        assert_eq!(header.column_count, 1500);
+        assert_eq!(header.metadata_follows, None);
+        assert_eq!(codec.state, MySqlState::ReadingColumns { remaining: 1500 });
+
+        let mut out = BytesMut::new();
+        codec
+            .encode(MySqlMessage::ResultSetHeader(header), &mut out)
+            .unwrap();
+        assert_eq!(out, packet(1, &[0xfc, 0xdc, 0x05]));
+    }
+
+    #[test]
+    fn test_non_result_set_response_is_not_a_header() {
+        // COM_STATISTICS answers with a bare string
+        let mut codec = command_phase_client_codec();
+        send_command(&mut codec, &[0x09]);
+        let mut buf = packet(1, b"Uptime: 42  Threads: 1");
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Generic(_))
+        ));
+        assert!(codec.in_command_phase());
+    }
+
+    #[test]
+    fn test_result_set_without_metadata_goes_to_rows() {
+        let mut codec = command_phase_client_codec();
+        codec.set_capability_flags(
+            CLIENT_PROTOCOL_41 | CLIENT_DEPRECATE_EOF | CLIENT_OPTIONAL_RESULTSET_METADATA,
+        );
+        send_command(&mut codec, b"\x03SELECT 1");
+
+        let mut buf = packet(1, &[1, 0]);
+        let Some(MySqlMessage::ResultSetHeader(header)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected result set header");
+        };
+        assert_eq!(header.metadata_follows, Some(false));
+        let mut buf = text_row_packet(2, &[b"1"]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::ResultRow(_))
+        ));
+    }
+
+    #[test]
+    fn test_field_list_response_carries_default_values() {
+        let mut codec = command_phase_client_codec();
+        send_command(&mut codec, b"\x04users\x00");
+
+        let column = |sequence_id, name: &'static [u8], default_value| ColumnDefinition {
+            sequence_id,
+            catalog: Bytes::from_static(b"def"),
+            schema: Bytes::from_static(b"shop"),
+            table: Bytes::from_static(b"users"),
+            org_table: Bytes::from_static(b"users"),
+            name: Bytes::from_static(name),
+            org_name: Bytes::from_static(name),
+            character_set: 45,
+            column_length: 255,
+            column_type: 0xfd,
+            flags: 0,
+            decimals: 0,
+            default_value,
+        };
+        let mut buf = BytesMut::new();
+        encode_column_definition(
+            &column(
+                1,
+                b"email",
+                Some(Some(BytesMut::from(&b"bob@example.com"[..]))),
+            ),
+            &mut buf,
+        );
+        encode_column_definition(&column(2, b"phone", Some(None)), &mut buf);
+        buf.extend_from_slice(&eof_packet(3));
+        let wire = buf.clone();
+
+        let mut out = BytesMut::new();
+        let mut decoded = Vec::new();
+        while let Some(msg) = codec.decode(&mut buf).unwrap() {
+            decoded.push(msg);
+        }
+        assert_eq!(decoded.len(), 3);
+        let MySqlMessage::ColumnDefinition(email) = &decoded[0] else {
+            panic!("expected column definition");
+        };
+        assert_eq!(
+            email.default_value.as_ref().unwrap().as_deref(),
+            Some(&b"bob@example.com"[..])
+        );
+        let MySqlMessage::ColumnDefinition(phone) = &decoded[1] else {
+            panic!("expected column definition");
+        };
+        assert!(matches!(phone.default_value, Some(None)));
+        assert!(matches!(decoded[2], MySqlMessage::Eof(_)));
+        assert!(codec.in_command_phase());
+
+        let mut server = MySqlCodec::new_server().with_sequence_passthrough();
+        server.state = MySqlState::Command;
+        for msg in decoded {
+            server.encode(msg, &mut out).unwrap();
+        }
+        assert_eq!(out, wire);
+    }
+
+    #[test]
+    fn test_local_infile_roundtrip() {
+        // Upstream side: the server asks for the file
+        let mut upstream = command_phase_client_codec();
+        send_command(
+            &mut upstream,
+            b"\x03LOAD DATA LOCAL INFILE 'u.txt' INTO TABLE u",
+        );
+        let mut buf = packet(1, b"\xfbu.txt");
+        let Some(MySqlMessage::LocalInfileRequest(request)) = upstream.decode(&mut buf).unwrap()
+        else {
+            panic!("expected local infile request");
+        };
+        assert_eq!(&request.filename[..], b"u.txt");
+        assert!(!upstream.in_command_phase());
+
+        // Client side: file contents are data even when they look like commands
+        let mut client = MySqlCodec::new_server();
+        client.state = MySqlState::Command;
+        let mut out = BytesMut::new();
+        client
+            .encode(MySqlMessage::LocalInfileRequest(request), &mut out)
+            .unwrap();
+        assert_eq!(&out[4..], b"\xfbu.txt");
+
+        let mut buf = packet(2, b"\x03\tbob@example.com\n");
+        buf.extend_from_slice(&packet(3, b""));
+        let Some(MySqlMessage::LocalInfileData(data)) = client.decode(&mut buf).unwrap() else {
+            panic!("expected file data");
+        };
+        assert_eq!(&data.payload[..], b"\x03\tbob@example.com\n");
+        let Some(MySqlMessage::LocalInfileData(end)) = client.decode(&mut buf).unwrap() else {
+            panic!("expected end of file");
+        };
+        assert!(end.payload.is_empty());
+        assert!(client.in_command_phase());
+
+        // The data is not mistaken for commands on the way upstream
+        let mut out = BytesMut::new();
+        upstream
+            .encode(MySqlMessage::LocalInfileData(data), &mut out)
+            .unwrap();
+        assert!(!upstream.in_command_phase());
+        upstream
+            .encode(MySqlMessage::LocalInfileData(end), &mut out)
+            .unwrap();
+        let mut expected = packet(2, b"\x03\tbob@example.com\n");
+        expected.extend_from_slice(&packet(3, b""));
+        assert_eq!(out, expected);
+
+        let mut buf = packet(4, &[0x00, 1, 0, 2, 0, 0, 0]);
+        assert!(matches!(
+            upstream.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Ok(_))
+        ));
+        assert!(upstream.in_command_phase());
+    }
+
+    #[test]
+    fn test_split_packet_result_row_roundtrip() {
+        let mut codec = command_phase_client_codec()
+            .with_max_message_size(32 * 1024 * 1024)
+            .with_sequence_passthrough();
+        codec.state = MySqlState::ReadingRows;
+        codec.column_count = 2;
+
+        // 17 MB value, more than one packet can carry
+        let value: BytesMut = (0..17 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let row = ResultRow {
+            sequence_id: 4,
+            values: vec![Some(value.clone()), Some(BytesMut::from(&b"tail"[..]))],
+        };
+        let mut encoded = BytesMut::new();
+        codec
+            .encode(MySqlMessage::ResultRow(row), &mut encoded)
+            .unwrap();
+
+        // Split into a full packet and a short one with the next sequence id
+        assert_eq!(&encoded[..4], &[0xff, 0xff, 0xff, 4]);
+        assert_eq!(encoded[4 + MAX_PACKET_PAYLOAD + 3], 5);
+
+        // Incomplete input is not decoded
+        let mut partial = encoded.clone();
+        partial.truncate(encoded.len() - 1);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+
+        let mut buf = encoded.clone();
+        let Some(MySqlMessage::ResultRow(decoded)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected result row");
+        };
+        assert!(buf.is_empty());
+        assert_eq!(decoded.sequence_id, 4);
+        assert_eq!(decoded.values[0].as_ref().unwrap(), &value);
+        assert_eq!(&decoded.values[1].as_ref().unwrap()[..], b"tail");
+
+        let mut reencoded = BytesMut::new();
+        codec
+            .encode(MySqlMessage::ResultRow(decoded), &mut reencoded)
+            .unwrap();
+        assert_eq!(reencoded, encoded);
+    }
+
+    #[test]
+    fn test_payload_of_exactly_max_size_ends_with_empty_packet() {
+        let payload = vec![0u8; MAX_PACKET_PAYLOAD];
+        let mut buf = BytesMut::new();
+        write_packet(&mut buf, &payload, 0);
+
+        assert_eq!(buf.len(), 4 + MAX_PACKET_PAYLOAD + 4);
+        assert_eq!(&buf[4 + MAX_PACKET_PAYLOAD..], &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_auth_packets_keep_codec_authenticating() {
+        let mut codec = MySqlCodec::new_client().with_sequence_passthrough();
+        codec.set_capability_flags(CLIENT_PROTOCOL_41);
+        codec.state = MySqlState::WaitingHandshakeResponse;
+
+        let mut switch_payload = vec![0xfe];
+        switch_payload.extend_from_slice(b"caching_sha2_password\0");
+        switch_payload.extend_from_slice(&[5; 20]);
+        switch_payload.push(0);
+        let switch_packet = packet(2, &switch_payload);
+        let more_data_packet = packet(4, &[0x01, 0x03]); // fast auth success
+
+        let mut buf = switch_packet.clone();
+        let Some(MySqlMessage::AuthSwitchRequest(switch)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected AuthSwitchRequest");
+        };
+        assert_eq!(switch.plugin_name, "caching_sha2_password");
+        assert_eq!(switch.plugin_data.len(), 21);
+        let mut out = BytesMut::new();
+        codec
+            .encode(MySqlMessage::AuthSwitchRequest(switch), &mut out)
+            .unwrap();
+        assert_eq!(out, switch_packet);
+
+        let mut buf = more_data_packet.clone();
+        let Some(MySqlMessage::AuthMoreData(more)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected AuthMoreData");
+        };
+        assert_eq!(&more.data[..], &[0x03]);
+        let mut out = BytesMut::new();
+        codec
+            .encode(MySqlMessage::AuthMoreData(more), &mut out)
+            .unwrap();
+        assert_eq!(out, more_data_packet);
+        assert!(!codec.in_command_phase());
+
+        let mut buf = packet(5, &[0x00, 0, 0, 2, 0, 0, 0]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Ok(_))
+        ));
+        assert!(codec.in_command_phase());
+    }
+
+    #[test]
+    fn test_change_user_reenters_authentication() {
+        let flags = CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH;
+        let mut payload = vec![COM_CHANGE_USER];
+        payload.extend_from_slice(b"bob\0");
+        payload.extend_from_slice(&[3, 1, 2, 3]);
+        payload.extend_from_slice(b"shop\0");
+        payload.extend_from_slice(&[45, 0]);
+        payload.extend_from_slice(b"mysql_native_password\0");
+
+        // Client-facing side: the switch request is answered with auth data that
+        // may start with 0x03 and must not be taken for COM_QUERY
+        let mut server = MySqlCodec::new_server();
+        server.set_capability_flags(flags);
+        server.state = MySqlState::Command;
+        let mut buf = packet(0, &payload);
+        let Some(MySqlMessage::ChangeUser(change_user)) = server.decode(&mut buf).unwrap() else {
+            panic!("expected COM_CHANGE_USER");
+        };
+        assert_eq!(change_user.username, "bob");
+        assert_eq!(&change_user.auth_response[..], &[1, 2, 3]);
+        assert_eq!(change_user.database, "shop");
+        let mut buf = packet(3, &[0x03, 0xaa]);
+        assert!(matches!(
+            server.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Generic(_))
+        ));
+
+        // Upstream side: the packet is re-encoded as received
+        let mut upstream = command_phase_client_codec();
+        upstream.set_capability_flags(flags);
+        let mut out = BytesMut::new();
+        upstream
+            .encode(MySqlMessage::ChangeUser(change_user), &mut out)
+            .unwrap();
+        assert_eq!(out, packet(0, &payload));
+
+        let mut buf = packet(1, b"\xfemysql_native_password\0abcdefgh\0");
+        assert!(matches!(
+            upstream.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::AuthSwitchRequest(_))
+        ));
+        assert!(!upstream.in_command_phase());
+        let mut buf = packet(4, &[0x00, 0, 0, 2, 0, 0, 0]);
+        assert!(matches!(
+            upstream.decode(&mut buf).unwrap(),
+            Some(MySqlMessage::Ok(_))
+        ));
+        assert!(upstream.in_command_phase());
+
+        let mut out = BytesMut::new();
+        server
+            .encode(
+                MySqlMessage::Ok(OkPacket {
+                    sequence_id: 4,
+                    affected_rows: 0,
+                    last_insert_id: 0,
+                    status_flags: 2,
+                    warnings: 0,
+                    info: Bytes::new(),
+                }),
+                &mut out,
+            )
+            .unwrap();
+        assert!(server.in_command_phase());
+    }
+
+    #[test]
+    fn test_injected_and_dropped_packets_keep_sequence_contiguous() {
+        let mut client = MySqlCodec::new_server();
+        client.set_capability_flags(CLIENT_PROTOCOL_41);
+        client.state = MySqlState::Command;
+        let sequence_ids = |client: &mut MySqlCodec, messages: Vec<MySqlMessage>| {
+            let mut out = BytesMut::new();
+            for msg in messages {
+                client.encode(msg, &mut out).unwrap();
+            }
+            let mut ids = Vec::new();
+            while !out.is_empty() {
+                let len = (out[0] as usize) | ((out[1] as usize) << 8) | ((out[2] as usize) << 16);
+                ids.push(out[3]);
+                out.advance(4 + len);
+            }
+            ids
+        };
+
+        // A synthetic OK in answer to a command follows the command's id
+        let mut buf = packet(0, b"\x03SET @a = 1");
+        client.decode(&mut buf).unwrap();
+        let ok = OkPacket {
+            sequence_id: 0,
+            affected_rows: 0,
+            last_insert_id: 0,
+            status_flags: 2,
+            warnings: 0,
+            info: Bytes::new(),
+        };
+        assert_eq!(
+            sequence_ids(&mut client, vec![MySqlMessage::Ok(ok)]),
+            vec![1]
+        );
+
+        // A row dropped from a relayed result set leaves no gap
+        let mut buf = packet(0, b"\x03SELECT a FROM t");
+        client.decode(&mut buf).unwrap();
+        let row = |sequence_id| {
+            MySqlMessage::ResultRow(ResultRow {
+                sequence_id,
+                values: vec![Some(BytesMut::from(&b"x"[..]))],
+            })
+        };
+        let eof = |sequence_id| {
+            MySqlMessage::Eof(EofPacket {
+                sequence_id,
+                warnings: 0,
+                status_flags: 2,
+            })
+        };
+        let relayed = vec![
+            MySqlMessage::ResultSetHeader(ResultSetHeader {
+                sequence_id: 1,
+                column_count: 1,
+                metadata_follows: None,
+            }),
+            eof(3),
+            row(4),
+            // row 5 dropped
+            row(6),
+            eof(7),
+        ];
+        assert_eq!(sequence_ids(&mut client, relayed), vec![1, 2, 3, 4, 5]);
+
+        // Commands sent upstream always start at 0
+        let mut upstream = command_phase_client_codec();
+        let query = |sequence_id| {
+            MySqlMessage::Query(QueryPacket {
+                sequence_id,
+                query: Bytes::from_static(b"SELECT 1"),
+            })
+        };
+        assert_eq!(sequence_ids(&mut upstream, vec![query(5)]), vec![0]);
+        let mut buf = packet(1, &[0x00, 0, 0, 2, 0, 0, 0]);
+        upstream.decode(&mut buf).unwrap();
+        assert_eq!(sequence_ids(&mut upstream, vec![query(2)]), vec![0]);
+    }
+
     #[test]
     fn test_read_lenenc_int_1byte() {
         let buf = [0x0a];
@@ -1,3 +1,4 @@
+use super::DEFAULT_MAX_MESSAGE_SIZE;
 use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
@@ -8,9 +9,44 @@ pub enum PgMessage {
     Regular(RegularMessage),
     RowDescription(RowDescription),
     DataRow(DataRow),
+    CommandComplete(CommandComplete),
+    ReadyForQuery(ReadyForQuery),
     Query(QueryMessage),
     Parse(ParseMessage),
+    Bind(BindMessage),
+    /// A message the proxy doesn't rewrite, forwarded as its original frame
+    Raw(RawMessage),
     SSLRequest,
+    CancelRequest(CancelRequest),
+}
+
+/// Format code for text-encoded values
+pub const FORMAT_TEXT: i16 = 0;
+/// Format code for binary-encoded values
+pub const FORMAT_BINARY: i16 = 1;
+
+/// Well-known type OIDs from `pg_type`
+pub mod type_oid {
+    pub const BYTEA: u32 = 17;
+    pub const CHAR: u32 = 18;
+    pub const NAME: u32 = 19;
+    pub const TEXT: u32 = 25;
+    pub const JSON: u32 = 114;
+    pub const XML: u32 = 142;
+    pub const JSON_ARRAY: u32 = 199;
+    pub const UNKNOWN: u32 = 705;
+    pub const INET: u32 = 869;
+    pub const TEXT_ARRAY: u32 = 1009;
+    pub const BPCHAR_ARRAY: u32 = 1014;
+    pub const VARCHAR_ARRAY: u32 = 1015;
+    pub const BPCHAR: u32 = 1042;
+    pub const VARCHAR: u32 = 1043;
+    pub const DATE: u32 = 1082;
+    pub const RECORD: u32 = 2249;
+    pub const JSONB: u32 = 3802;
+    pub const JSONB_ARRAY: u32 = 3807;
+    /// OIDs from here on are assigned to objects created in the database
+    pub const FIRST_NORMAL_OBJECT_ID: u32 = 16384;
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +55,14 @@ pub struct StartupMessage {
     pub parameters: Vec<(String, String)>,
 }
 
+/// Sent on a fresh connection to cancel the query running on another backend
+#[derive(Debug, Clone)]
+pub struct CancelRequest {
+    pub process_id: u32,
+    /// 4 bytes before protocol 3.2, up to 256 bytes since
+    pub secret_key: Bytes,
+}
+
 #[derive(Debug, Clone)]
 pub struct QueryMessage {
     pub query: Bytes,
@@ -31,12 +75,60 @@ pub struct ParseMessage {
     pub param_types: Vec<u32>,
 }
 
+#[derive(Debug, Clone)]
+pub struct BindMessage {
+    pub portal: Bytes,
+    pub statement: Bytes,
+    pub param_formats: Vec<i16>,
+    pub params: Vec<Option<Bytes>>,
+    /// Requested result column formats: empty means all text, a single entry
+    /// applies to every column, otherwise one entry per column.
+    pub result_formats: Vec<i16>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RegularMessage {
     pub message_type: u8,
     pub payload: BytesMut,
 }
 
+/// An undecoded message. `frame` is the complete wire frame (type, length and
+/// payload) sliced from the read buffer, so forwarding it needs no re-encoding.
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    pub message_type: u8,
+    pub frame: Bytes,
+}
+
+impl RawMessage {
+    /// The message body, after the type byte and length
+    pub fn payload(&self) -> &[u8] {
+        &self.frame[5..]
+    }
+}
+
+impl RegularMessage {
+    /// Build an ErrorResponse with the given severity, SQLSTATE code and message
+    pub fn error_response(severity: &str, code: &str, message: &str) -> Self {
+        let mut payload = BytesMut::new();
+        for (field, value) in [
+            (b'S', severity),
+            (b'V', severity),
+            (b'C', code),
+            (b'M', message),
+        ] {
+            payload.put_u8(field);
+            payload.put_slice(value.as_bytes());
+            payload.put_u8(0);
+        }
+        payload.put_u8(0);
+        Self {
+            message_type: b'E',
+            payload,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RowDescription {
     pub fields: Vec<FieldDescription>,
@@ -58,19 +150,75 @@ pub struct DataRow {
     pub values: Vec<Option<BytesMut>>,
 }
 
+#[derive(Debug, Clone)]
+pub struct CommandComplete {
+    /// Command tag, e.g. `SELECT 5`
+    pub tag: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadyForQuery {
+    /// Transaction status: `I` (idle), `T` (in transaction) or `E` (failed transaction)
+    pub status: u8,
+}
+
 pub struct PostgresCodec {
     // State to track if we are expecting a startup message (first message)
     // or regular messages.
     is_startup: bool,
+    // Frontend (client) and backend (server) messages share type bytes with
+    // different meanings (e.g. 'D' is Describe vs DataRow), so the codec must
+    // know which side of the conversation it is decoding.
+    is_frontend: bool,
+    // Frames claiming a larger length are rejected before any buffer is reserved
+    max_message_size: usize,
 }
 
 impl PostgresCodec {
+    /// Create codec for the client-facing side (decodes frontend messages)
     pub fn new() -> Self {
-        Self { is_startup: true }
+        Self {
+            is_startup: true,
+            is_frontend: true,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
     }
 
+    /// Create codec for the upstream side (decodes backend messages)
     pub fn new_upstream() -> Self {
-        Self { is_startup: false }
+        Self {
+            is_startup: false,
+            is_frontend: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the largest message length this codec will accept
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Reject lengths that are malformed or over the configured limit
+    fn check_length(&self, length: usize, min: usize) -> Result<()> {
+        if length < min || length > self.max_message_size {
+            crate::metrics::record_protocol_error("postgres");
+            return Err(anyhow::anyhow!(
+                "Invalid Postgres message length {} (max {})",
+                length,
+                self.max_message_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether messages of this type are decoded; everything else is passed through raw
+    fn is_decoded(&self, message_type: u8) -> bool {
+        if self.is_frontend {
+            matches!(message_type, b'Q' | b'P' | b'B')
+        } else {
+            matches!(message_type, b'T' | b'D' | b'C' | b'Z')
+        }
     }
 }
 
@@ -91,6 +239,7 @@ impl Decoder for PostgresCodec {
         if self.is_startup {
             // Startup packet: [Length (4 bytes)] [Protocol Version (4 bytes)] [Params...]
             // OR SSLRequest: [Length (4 bytes)] [1234 in high 16 bits] [5679 in low 16 bits]
+            self.check_length(length, 8)?;
 
             if src.len() < length {
                 src.reserve(length - src.len());
@@ -109,6 +258,18 @@ impl Decoder for PostgresCodec {
                 return Ok(Some(PgMessage::SSLRequest));
             }
 
+            if protocol_version == 80877102 {
+                // CancelRequest (1234.5678): [Process ID (4 bytes)] [Secret Key...]
+                if data.remaining() < 4 {
+                    return Err(anyhow::anyhow!("CancelRequest too short"));
+                }
+                let process_id = data.get_u32();
+                return Ok(Some(PgMessage::CancelRequest(CancelRequest {
+                    process_id,
+                    secret_key: data.freeze(),
+                })));
+            }
+
             // Parse Startup Message
             let mut parameters = Vec::new();
             while data.has_remaining() {
@@ -143,6 +304,7 @@ impl Decoder for PostgresCodec {
             let mut length_bytes = [0u8; 4];
             length_bytes.copy_from_slice(&src[1..5]);
             let length = u32::from_be_bytes(length_bytes) as usize;
+            self.check_length(length, 4)?;
 
             // Total frame size = 1 (type) + length
             let frame_len = 1 + length;
@@ -153,10 +315,16 @@ impl Decoder for PostgresCodec {
             }
 
             let mut data = src.split_to(frame_len);
+            if !self.is_decoded(message_type) {
+                return Ok(Some(PgMessage::Raw(RawMessage {
+                    message_type,
+                    frame: data.freeze(),
+                })));
+            }
             data.advance(5); // Skip Type (1) + Length (4)
 
-            match message_type {
-                b'T' => {
+            match (self.is_frontend, message_type) {
+                (false, b'T') => {
                     // RowDescription
                     let num_fields = data.get_u16();
                     let mut fields = Vec::with_capacity(num_fields as usize);
@@ -181,7 +349,7 @@ impl Decoder for PostgresCodec {
                     }
                     Ok(Some(PgMessage::RowDescription(RowDescription { fields })))
                 }
-                b'D' => {
+                (false, b'D') => {
                     // DataRow
                     let num_cols = data.get_u16();
                     let mut values = Vec::with_capacity(num_cols as usize);
@@ -196,11 +364,22 @@ impl Decoder for PostgresCodec {
                     }
                     Ok(Some(PgMessage::DataRow(DataRow { values })))
                 }
-                b'Q' => {
+                (false, b'C') => {
+                    let tag = read_cstring_bytes(&mut data)?;
+                    Ok(Some(PgMessage::CommandComplete(CommandComplete { tag })))
+                }
+                (false, b'Z') => {
+                    if !data.has_remaining() {
+                        return Err(anyhow::anyhow!("ReadyForQuery missing status"));
+                    }
+                    let status = data.get_u8();
+                    Ok(Some(PgMessage::ReadyForQuery(ReadyForQuery { status })))
+                }
+                (true, b'Q') => {
                     let query = read_cstring_bytes(&mut data)?;
                     Ok(Some(PgMessage::Query(QueryMessage { query })))
                 }
-                b'P' => {
+                (true, b'P') => {
                     let statement = read_cstring_bytes(&mut data)?;
                     let query = read_cstring_bytes(&mut data)?;
                     let num_params = data.get_u16();
@@ -214,10 +393,48 @@ impl Decoder for PostgresCodec {
                         param_types,
                     })))
                 }
-                _ => Ok(Some(PgMessage::Regular(RegularMessage {
-                    message_type,
-                    payload: data,
-                }))),
+                (true, b'B') => {
+                    // Sent by the client, so every count and length is checked
+                    let portal = read_cstring_bytes(&mut data)?;
+                    let statement = read_cstring_bytes(&mut data)?;
+                    ensure_remaining(&data, 2, "Bind")?;
+                    let num_param_formats = data.get_u16();
+                    ensure_remaining(&data, 2 * num_param_formats as usize, "Bind")?;
+                    let mut param_formats = Vec::with_capacity(num_param_formats as usize);
+                    for _ in 0..num_param_formats {
+                        param_formats.push(data.get_i16());
+                    }
+                    ensure_remaining(&data, 2, "Bind")?;
+                    let num_params = data.get_u16();
+                    let mut params = Vec::with_capacity(num_params as usize);
+                    for _ in 0..num_params {
+                        ensure_remaining(&data, 4, "Bind")?;
+                        let len = data.get_i32();
+                        if len == -1 {
+                            params.push(None);
+                        } else if len < 0 {
+                            return Err(anyhow::anyhow!("Invalid Bind parameter length {}", len));
+                        } else {
+                            ensure_remaining(&data, len as usize, "Bind")?;
+                            params.push(Some(data.split_to(len as usize).freeze()));
+                        }
+                    }
+                    ensure_remaining(&data, 2, "Bind")?;
+                    let num_result_formats = data.get_u16();
+                    ensure_remaining(&data, 2 * num_result_formats as usize, "Bind")?;
+                    let mut result_formats = Vec::with_capacity(num_result_formats as usize);
+                    for _ in 0..num_result_formats {
+                        result_formats.push(data.get_i16());
+                    }
+                    Ok(Some(PgMessage::Bind(BindMessage {
+                        portal,
+                        statement,
+                        param_formats,
+                        params,
+                        result_formats,
+                    })))
+                }
+                _ => unreachable!("is_decoded covers every decoded message type"),
             }
         }
     }
@@ -252,6 +469,12 @@ impl Encoder<PgMessage> for PostgresCodec {
                 dst.put_u32(8);
                 dst.put_u32(80877103);
             }
+            PgMessage::CancelRequest(msg) => {
+                dst.put_u32((12 + msg.secret_key.len()) as u32);
+                dst.put_u32(80877102);
+                dst.put_u32(msg.process_id);
+                dst.put_slice(&msg.secret_key);
+            }
             PgMessage::RowDescription(msg) => {
                 dst.put_u8(b'T');
 
@@ -300,6 +523,17 @@ impl Encoder<PgMessage> for PostgresCodec {
                     }
                 }
             }
+            PgMessage::CommandComplete(msg) => {
+                dst.put_u8(b'C');
+                dst.put_u32((4 + msg.tag.len() + 1) as u32);
+                dst.put_slice(&msg.tag);
+                dst.put_u8(0);
+            }
+            PgMessage::ReadyForQuery(msg) => {
+                dst.put_u8(b'Z');
+                dst.put_u32(5);
+                dst.put_u8(msg.status);
+            }
             PgMessage::Query(msg) => {
                 dst.put_u8(b'Q');
                 let len = 4 + msg.query.len() + 1;
@@ -326,11 +560,47 @@ impl Encoder<PgMessage> for PostgresCodec {
                     dst.put_u32(*param);
                 }
             }
+            PgMessage::Bind(msg) => {
+                dst.put_u8(b'B');
+                let mut len = 4 + msg.portal.len() + 1 + msg.statement.len() + 1;
+                len += 2 + msg.param_formats.len() * 2;
+                len += 2;
+                for param in &msg.params {
+                    len += 4 + param.as_ref().map_or(0, |p| p.len());
+                }
+                len += 2 + msg.result_formats.len() * 2;
+
+                dst.put_u32(len as u32);
+                dst.put_slice(&msg.portal);
+                dst.put_u8(0);
+                dst.put_slice(&msg.statement);
+                dst.put_u8(0);
+                dst.put_u16(msg.param_formats.len() as u16);
+                for format in &msg.param_formats {
+                    dst.put_i16(*format);
+                }
+                dst.put_u16(msg.params.len() as u16);
+                for param in &msg.params {
+                    if let Some(p) = param {
+                        dst.put_i32(p.len() as i32);
+                        dst.put_slice(p);
+                    } else {
+                        dst.put_i32(-1);
+                    }
+                }
+                dst.put_u16(msg.result_formats.len() as u16);
+                for format in &msg.result_formats {
+                    dst.put_i16(*format);
+                }
+            }
             PgMessage::Regular(msg) => {
                 dst.put_u8(msg.message_type);
                 dst.put_u32((msg.payload.len() + 4) as u32);
                 dst.put_slice(&msg.payload);
             }
+            PgMessage::Raw(msg) => {
+                dst.put_slice(&msg.frame);
+            }
         }
         Ok(())
     }
@@ -347,6 +617,14 @@ fn read_cstring_bytes(buf: &mut BytesMut) -> Result<Bytes> {
     Ok(bytes)
 }
 
+/// Fail unless `buf` holds at least `len` more bytes of `message`
+fn ensure_remaining(buf: &BytesMut, len: usize, message: &str) -> Result<()> {
+    if buf.remaining() < len {
+        return Err(anyhow::anyhow!("Truncated {} message", message));
+    }
+    Ok(())
+}
+
 /// Read a null-terminated C-string as a String (for startup parameters)
 fn read_cstring(buf: &mut BytesMut) -> Result<String> {
     let bytes = read_cstring_bytes(buf)?;
@@ -412,8 +690,7 @@ mod tests {
 
     #[test]
     fn test_decode_row_description() {
-        let mut codec = PostgresCodec::new();
-        codec.is_startup = false;
+        let mut codec = PostgresCodec::new_upstream();
         let mut buf = BytesMut::new();
 
         // 'T' (RowDescription)
@@ -449,8 +726,7 @@ mod tests {
 
     #[test]
     fn test_decode_data_row() {
-        let mut codec = PostgresCodec::new();
-        codec.is_startup = false;
+        let mut codec = PostgresCodec::new_upstream();
         let mut buf = BytesMut::new();
 
         // 'D' (DataRow)
@@ -530,6 +806,256 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decode_bind_message() {
+        let mut codec = PostgresCodec::new();
+        codec.is_startup = false;
+        let mut buf = BytesMut::new();
+
+        let original = BindMessage {
+            portal: Bytes::from_static(b""),
+            statement: Bytes::from_static(b"stmt1"),
+            param_formats: vec![FORMAT_TEXT],
+            params: vec![Some(Bytes::from_static(b"42")), None],
+            result_formats: vec![FORMAT_BINARY],
+        };
+
+        codec
+            .encode(PgMessage::Bind(original.clone()), &mut buf)
+            .unwrap();
+        let result = codec.decode(&mut buf).unwrap().unwrap();
+
+        if let PgMessage::Bind(msg) = result {
+            assert_eq!(msg.statement, original.statement);
+            assert_eq!(msg.param_formats, vec![FORMAT_TEXT]);
+            assert_eq!(msg.params.len(), 2);
+            assert_eq!(msg.params[0], Some(Bytes::from_static(b"42")));
+            assert!(msg.params[1].is_none());
+            assert_eq!(msg.result_formats, vec![FORMAT_BINARY]);
+        } else {
+            panic!("Expected Bind message");
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_bind_rejected() {
+        let bind = |body: &[u8]| {
+            let mut buf = BytesMut::new();
+            buf.put_u8(b'B');
+            buf.put_u32(4 + body.len() as u32);
+            buf.put_slice(body);
+            buf
+        };
+        let decode = |mut buf: BytesMut| {
+            let mut codec = PostgresCodec::new();
+            codec.is_startup = false;
+            codec.decode(&mut buf).map(|_| ())
+        };
+
+        // Ends inside the parameter format count, the formats and a value
+        assert!(decode(bind(b"\0stmt\0\0")).is_err());
+        assert!(decode(bind(b"\0stmt\0\0\x05\0\0")).is_err());
+        let mut body = b"\0stmt\0\0\0\0\x01".to_vec();
+        body.extend_from_slice(&100i32.to_be_bytes());
+        body.extend_from_slice(b"42");
+        assert!(decode(bind(&body)).is_err());
+        // No result format count
+        let mut body = b"\0stmt\0\0\0\0\x01".to_vec();
+        body.extend_from_slice(&2i32.to_be_bytes());
+        body.extend_from_slice(b"42");
+        assert!(decode(bind(&body)).is_err());
+        body.extend_from_slice(&[0, 0]);
+        assert!(decode(bind(&body)).is_ok());
+    }
+
+    #[test]
+    fn test_bind_with_negative_length_rejected() {
+        let mut codec = PostgresCodec::new();
+        codec.is_startup = false;
+        let mut body = b"\0stmt\0\0\0\0\x01".to_vec();
+        body.extend_from_slice(&(-2i32).to_be_bytes());
+        body.extend_from_slice(&[0, 0]);
+        let mut buf = BytesMut::new();
+        buf.put_u8(b'B');
+        buf.put_u32(4 + body.len() as u32);
+        buf.put_slice(&body);
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid Bind parameter length -2"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_frontend_describe_is_not_data_row() {
+        // 'D' from the client is Describe, not DataRow
+        let mut codec = PostgresCodec::new();
+        codec.is_startup = false;
+        let mut buf = BytesMut::new();
+
+        buf.put_u8(b'D');
+        buf.put_u32(4 + 2);
+        buf.put_u8(b'P');
+        buf.put_u8(0);
+
+        let result = codec.decode(&mut buf).unwrap().unwrap();
+
+        if let PgMessage::Raw(msg) = result {
+            assert_eq!(msg.message_type, b'D');
+            assert_eq!(msg.payload(), b"P\0");
+        } else {
+            panic!("Expected Raw message");
+        }
+    }
+
+    #[test]
+    fn test_command_complete_and_ready_for_query_roundtrip() {
+        let mut codec = PostgresCodec::new_upstream();
+        let mut buf = BytesMut::new();
+        buf.put_u8(b'C');
+        buf.put_u32(4 + 9);
+        buf.put_slice(b"SELECT 2\0");
+        buf.put_u8(b'Z');
+        buf.put_u32(5);
+        buf.put_u8(b'I');
+        let original = buf.clone();
+
+        let complete = codec.decode(&mut buf).unwrap().unwrap();
+        let ready = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(matches!(complete, PgMessage::CommandComplete(ref c) if &c.tag[..] == b"SELECT 2"));
+        assert!(matches!(ready, PgMessage::ReadyForQuery(ref r) if r.status == b'I'));
+
+        let mut out = BytesMut::new();
+        codec.encode(complete, &mut out).unwrap();
+        codec.encode(ready, &mut out).unwrap();
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_error_response_fields() {
+        let msg = RegularMessage::error_response("FATAL", "08001", "no TLS");
+        assert_eq!(msg.message_type, b'E');
+        assert_eq!(&msg.payload[..], b"SFATAL\0VFATAL\0C08001\0Mno TLS\0\0");
+    }
+
+    #[test]
+    fn test_decode_cancel_request() {
+        let mut codec = PostgresCodec::new();
+        let mut buf = BytesMut::new();
+
+        // CancelRequest: Length (16) + Code (80877102) + PID + Secret
+        buf.put_u32(16);
+        buf.put_u32(80877102);
+        buf.put_u32(4242);
+        buf.put_u32(0xdeadbeef);
+        let original = buf.clone();
+
+        let result = codec.decode(&mut buf).unwrap().unwrap();
+        let PgMessage::CancelRequest(cancel) = result else {
+            panic!("Expected CancelRequest");
+        };
+        assert_eq!(cancel.process_id, 4242);
+        assert_eq!(&cancel.secret_key[..], &0xdeadbeef_u32.to_be_bytes());
+
+        let mut out = BytesMut::new();
+        codec
+            .encode(PgMessage::CancelRequest(cancel), &mut out)
+            .unwrap();
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_large_copy_data_passes_through_raw() {
+        let payload = vec![b'x'; 10 * 1024 * 1024];
+        let mut buf = BytesMut::new();
+        buf.put_u8(b'd');
+        buf.put_u32((payload.len() + 4) as u32);
+        buf.put_slice(&payload);
+        let original = buf.clone();
+        let original_ptr = buf.as_ptr();
+
+        let mut codec = PostgresCodec::new();
+        codec.is_startup = false;
+        let result = codec.decode(&mut buf).unwrap().unwrap();
+
+        let PgMessage::Raw(msg) = result else {
+            panic!("Expected Raw message");
+        };
+        assert_eq!(msg.message_type, b'd');
+        // The frame is a view into the read buffer, not a re-serialized copy
+        assert_eq!(msg.frame.as_ptr(), original_ptr);
+        assert_eq!(msg.payload().len(), payload.len());
+
+        let mut out = BytesMut::new();
+        codec.encode(PgMessage::Raw(msg), &mut out).unwrap();
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_upstream_raw_messages_do_not_disturb_data_rows() {
+        let mut codec = PostgresCodec::new_upstream();
+        let mut buf = BytesMut::new();
+
+        // CopyOutResponse-style data followed by a DataRow
+        buf.put_u8(b'd');
+        buf.put_u32(4 + 3);
+        buf.put_slice(b"abc");
+        buf.put_u8(b'D');
+        buf.put_u32(4 + 2 + 4 + 2);
+        buf.put_u16(1);
+        buf.put_i32(2);
+        buf.put_slice(b"hi");
+
+        let first = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(matches!(first, PgMessage::Raw(ref m) if m.payload() == b"abc"));
+
+        let second = codec.decode(&mut buf).unwrap().unwrap();
+        let PgMessage::DataRow(row) = second else {
+            panic!("Expected DataRow message");
+        };
+        assert_eq!(row.values[0].as_deref(), Some(&b"hi"[..]));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_oversized_message_rejected() {
+        let mut codec = PostgresCodec::new_upstream().with_max_message_size(1024);
+        let mut buf = BytesMut::new();
+
+        // DataRow claiming to be ~2 GB
+        buf.put_u8(b'D');
+        buf.put_u32(0x7fff_fff0);
+        buf.put_u16(1);
+
+        let result = codec.decode(&mut buf);
+        assert!(result.is_err());
+        // Nothing was reserved for the claimed length
+        assert!(buf.capacity() < 1024);
+    }
+
+    #[test]
+    fn test_oversized_startup_rejected() {
+        let mut codec = PostgresCodec::new();
+        let mut buf = BytesMut::new();
+        buf.put_u32(u32::MAX);
+        buf.put_u32(196608);
+
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_undersized_length_rejected() {
+        let mut codec = PostgresCodec::new_upstream();
+        let mut buf = BytesMut::new();
+        buf.put_u8(b'Z');
+        buf.put_u32(2);
+
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
     #[test]
     fn test_decode_incomplete_message() {
         let mut codec = PostgresCodec::new();
@@ -550,8 +1076,7 @@ mod tests {
 
     #[test]
     fn test_decode_data_row_with_null() {
-        let mut codec = PostgresCodec::new();
-        codec.is_startup = false;
+        let mut codec = PostgresCodec::new_upstream();
         let mut buf = BytesMut::new();
 
         // DataRow with 2 cols: NULL and "data"
@@ -605,8 +1130,7 @@ mod tests {
     fn test_zero_copy_field_name() {
         // This test demonstrates zero-copy parsing for RowDescription field names.
         // The decoded field name should share the same underlying buffer as the input.
-        let mut codec = PostgresCodec::new();
-        codec.is_startup = false;
+        let mut codec = PostgresCodec::new_upstream();
         let mut buf = BytesMut::new();
 
         let field_name = b"customer_email";
@@ -642,8 +1166,7 @@ mod tests {
     #[test]
     fn test_zero_copy_data_row() {
         // DataRow values are already BytesMut/Bytes - this test verifies they remain zero-copy
-        let mut codec = PostgresCodec::new();
-        codec.is_startup = false;
+        let mut codec = PostgresCodec::new_upstream();
         let mut buf = BytesMut::new();
 
         let data = b"sensitive_value_12345";
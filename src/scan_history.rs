@@ -0,0 +1,296 @@
+//! History of completed database scans.
+//!
+//! The latest results are kept for `GET /scan/history`, each compared with
+//! the previous scan of the same database and schema so that findings fixed
+//! since then show up as resolved. With `scanner.history_file` every result
+//! is also appended to a JSON lines file, and the latest ones are loaded from
+//! it at startup.
+
+use crate::db_scanner::{PiiFinding, ScanResult};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Completed scans kept in memory
+pub const SCAN_HISTORY_LEN: usize = 50;
+
+/// A completed scan and how its findings changed since the previous one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanRecord {
+    /// ID of the scan job
+    pub id: String,
+    pub completed_at: DateTime<Utc>,
+    /// The scan of the same database and schema this one is compared with
+    pub previous_id: Option<String>,
+    /// Findings the previous scan didn't report; all of them for a first scan
+    pub new_findings: Vec<PiiFinding>,
+    /// Findings of the previous scan this one no longer reports
+    pub resolved_findings: Vec<PiiFinding>,
+    pub result: ScanResult,
+}
+
+/// What `GET /scan/history` lists for a scan
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanSummary {
+    pub id: String,
+    pub completed_at: DateTime<Utc>,
+    pub database: String,
+    pub schema: String,
+    pub tables_scanned: usize,
+    pub findings: usize,
+    pub new_findings: usize,
+    pub resolved_findings: usize,
+}
+
+impl From<&ScanRecord> for ScanSummary {
+    fn from(record: &ScanRecord) -> Self {
+        Self {
+            id: record.id.clone(),
+            completed_at: record.completed_at,
+            database: record.result.database.clone(),
+            schema: record.result.schema.clone(),
+            tables_scanned: record.result.tables_scanned,
+            findings: record.result.findings.len(),
+            new_findings: record.new_findings.len(),
+            resolved_findings: record.resolved_findings.len(),
+        }
+    }
+}
+
+/// Completed scans, oldest first
+#[derive(Default)]
+pub struct ScanHistory {
+    records: Mutex<VecDeque<ScanRecord>>,
+    file: Option<PathBuf>,
+}
+
+impl ScanHistory {
+    /// Load the latest scans from a JSON lines file, which is created on the
+    /// first scan if it doesn't exist
+    pub async fn open(path: &str) -> Result<Self> {
+        let mut records = VecDeque::new();
+        match tokio::fs::read_to_string(path).await {
+            Ok(contents) => {
+                for (n, line) in contents.lines().enumerate() {
+                    match serde_json::from_str::<ScanRecord>(line) {
+                        Ok(record) => records.push_back(record),
+                        Err(e) => warn!("Skipping line {} of scan history {}: {}", n + 1, path, e),
+                    }
+                    if records.len() > SCAN_HISTORY_LEN {
+                        records.pop_front();
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read scan history {}", path));
+            }
+        }
+        Ok(Self {
+            records: Mutex::new(records),
+            file: Some(PathBuf::from(path)),
+        })
+    }
+
+    /// Record a completed scan, comparing it with the previous scan of the
+    /// same database and schema
+    pub async fn record(&self, id: &str, result: ScanResult) -> ScanRecord {
+        let mut records = self.records.lock().await;
+        let previous = records.iter().rev().find(|record| {
+            record.result.database == result.database && record.result.schema == result.schema
+        });
+        let (new_findings, resolved_findings) = match previous {
+            Some(previous) => (
+                missing_from(&result.findings, &previous.result.findings),
+                missing_from(&previous.result.findings, &result.findings),
+            ),
+            None => (result.findings.clone(), Vec::new()),
+        };
+        let record = ScanRecord {
+            id: id.to_string(),
+            completed_at: Utc::now(),
+            previous_id: previous.map(|record| record.id.clone()),
+            new_findings,
+            resolved_findings,
+            result,
+        };
+
+        records.push_back(record.clone());
+        if records.len() > SCAN_HISTORY_LEN {
+            records.pop_front();
+        }
+        // Appended under the lock, so that lines of concurrent scans don't interleave
+        if let Some(path) = &self.file
+            && let Err(e) = append_line(path, &record).await
+        {
+            warn!("Failed to write scan history {}: {:#}", path.display(), e);
+        }
+        record
+    }
+
+    /// A page of the history, newest first, and the number of scans in it
+    pub async fn list(&self, offset: usize, limit: usize) -> (usize, Vec<ScanSummary>) {
+        let records = self.records.lock().await;
+        let page = records
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .map(ScanSummary::from)
+            .collect();
+        (records.len(), page)
+    }
+
+    pub async fn get(&self, id: &str) -> Option<ScanRecord> {
+        self.records
+            .lock()
+            .await
+            .iter()
+            .find(|record| record.id == id)
+            .cloned()
+    }
+}
+
+/// The findings of `findings` for a column, JSON path and type that `other`
+/// doesn't report
+fn missing_from(findings: &[PiiFinding], other: &[PiiFinding]) -> Vec<PiiFinding> {
+    let key = |f: &PiiFinding| (f.table.clone(), f.column.clone(), f.json_path.clone());
+    findings
+        .iter()
+        .filter(|f| {
+            !other
+                .iter()
+                .any(|o| key(o) == key(f) && o.pii_type == f.pii_type)
+        })
+        .cloned()
+        .collect()
+}
+
+async fn append_line(path: &PathBuf, record: &ScanRecord) -> Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finding(table: &str, column: &str, pii_type: &str) -> PiiFinding {
+        PiiFinding {
+            table: table.to_string(),
+            column: column.to_string(),
+            pii_type: pii_type.to_string(),
+            confidence: 1.0,
+            sample: None,
+            row_count: 10,
+            match_count: 10,
+            data_type: "text".to_string(),
+            json_path: None,
+            suggested_strategy: "redact".to_string(),
+        }
+    }
+
+    fn result(database: &str, findings: Vec<PiiFinding>) -> ScanResult {
+        ScanResult {
+            status: "completed".to_string(),
+            tables_scanned: 2,
+            columns_scanned: 5,
+            findings,
+            schema: "public".to_string(),
+            database: database.to_string(),
+            scan_duration_ms: 5,
+            filters: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_history_diff() {
+        let history = ScanHistory::default();
+        let first = history
+            .record(
+                "a",
+                result(
+                    "shop",
+                    vec![
+                        finding("users", "email", "Email"),
+                        finding("users", "ssn", "Ssn"),
+                    ],
+                ),
+            )
+            .await;
+        assert_eq!(first.previous_id, None);
+        assert_eq!(first.new_findings.len(), 2);
+
+        // Another database doesn't count as the previous scan
+        history.record("b", result("crm", vec![])).await;
+
+        let second = history
+            .record(
+                "c",
+                result(
+                    "shop",
+                    vec![
+                        finding("users", "email", "Email"),
+                        finding("orders", "note", "Phone"),
+                    ],
+                ),
+            )
+            .await;
+        assert_eq!(second.previous_id.as_deref(), Some("a"));
+        assert_eq!(second.new_findings[0].column, "note");
+        assert_eq!(second.new_findings.len(), 1);
+        assert_eq!(second.resolved_findings[0].column, "ssn");
+        assert_eq!(second.resolved_findings.len(), 1);
+
+        let (total, page) = history.list(0, 2).await;
+        assert_eq!(total, 3);
+        let ids: Vec<&str> = page.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["c", "b"]);
+        assert_eq!(page[0].resolved_findings, 1);
+        let (_, page) = history.list(2, 2).await;
+        assert_eq!(page[0].id, "a");
+        assert!(history.get("b").await.is_some());
+        assert!(history.get("nope").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scan_history_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scans.jsonl");
+        let path = path.to_str().unwrap();
+
+        let history = ScanHistory::open(path).await.unwrap();
+        for i in 0..SCAN_HISTORY_LEN + 2 {
+            history.record(&i.to_string(), result("shop", vec![])).await;
+        }
+        history
+            .record(
+                "last",
+                result("shop", vec![finding("users", "email", "Email")]),
+            )
+            .await;
+
+        // Reopened, only the latest are kept, and they still diff
+        let history = ScanHistory::open(path).await.unwrap();
+        let (total, page) = history.list(0, 1).await;
+        assert_eq!(total, SCAN_HISTORY_LEN);
+        assert_eq!(page[0].id, "last");
+        assert_eq!(page[0].new_findings, 1);
+        assert!(history.get("0").await.is_none());
+        let record = history.record("next", result("shop", vec![])).await;
+        assert_eq!(record.previous_id.as_deref(), Some("last"));
+        assert_eq!(record.resolved_findings.len(), 1);
+    }
+}
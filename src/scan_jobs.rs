@@ -0,0 +1,295 @@
+//! Database scans running in the background.
+//!
+//! A scan of a large database outlasts any HTTP timeout, so `POST /scan`
+//! only starts a job. The job's progress is kept here for the status
+//! endpoint, along with a bounded history of finished jobs and their results.
+
+use crate::db_scanner::{PiiFinding, ScanResult};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+
+/// Finished jobs kept for their status and results
+pub const SCAN_JOB_HISTORY: usize = 20;
+
+/// Progress of a running scan, updated by `DbScanner` table by table
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    tables_total: AtomicUsize,
+    tables_done: AtomicUsize,
+    findings: Mutex<Vec<PiiFinding>>,
+}
+
+impl ScanProgress {
+    pub fn set_tables_total(&self, tables: usize) {
+        self.tables_total.store(tables, Ordering::Relaxed);
+    }
+
+    /// Record a scanned table and what was found in it
+    pub fn table_done(&self, findings: &[PiiFinding]) {
+        self.findings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(findings);
+        self.tables_done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanJobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+struct ScanJob {
+    id: String,
+    database: String,
+    status: ScanJobStatus,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+    progress: Arc<ScanProgress>,
+    result: Option<ScanResult>,
+    abort: Option<AbortHandle>,
+}
+
+/// What `GET /scan/{job_id}` reports
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanJobInfo {
+    pub job_id: String,
+    pub status: ScanJobStatus,
+    pub database: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub tables_total: usize,
+    pub tables_done: usize,
+    /// Findings in the tables scanned so far
+    pub findings: Vec<PiiFinding>,
+    pub error: Option<String>,
+}
+
+impl From<&ScanJob> for ScanJobInfo {
+    fn from(job: &ScanJob) -> Self {
+        Self {
+            job_id: job.id.clone(),
+            status: job.status,
+            database: job.database.clone(),
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            tables_total: job.progress.tables_total.load(Ordering::Relaxed),
+            tables_done: job.progress.tables_done.load(Ordering::Relaxed),
+            findings: job
+                .progress
+                .findings
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            error: job.error.clone(),
+        }
+    }
+}
+
+/// Running jobs and the latest finished ones, oldest first
+#[derive(Default)]
+pub struct ScanJobs {
+    jobs: Mutex<VecDeque<ScanJob>>,
+}
+
+impl ScanJobs {
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<ScanJob>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a running scan of `database`. Returns the job ID and the
+    /// progress for the scanner to update.
+    pub fn start(&self, database: &str) -> (String, Arc<ScanProgress>) {
+        let progress = Arc::new(ScanProgress::default());
+        let id = uuid::Uuid::new_v4().to_string();
+        self.lock().push_back(ScanJob {
+            id: id.clone(),
+            database: database.to_string(),
+            status: ScanJobStatus::Running,
+            started_at: Utc::now(),
+            finished_at: None,
+            error: None,
+            progress: progress.clone(),
+            result: None,
+            abort: None,
+        });
+        (id, progress)
+    }
+
+    /// Remember how to cancel the task running a job
+    pub fn set_abort(&self, id: &str, abort: AbortHandle) {
+        if let Some(job) = self
+            .lock()
+            .iter_mut()
+            .find(|job| job.id == id && job.status == ScanJobStatus::Running)
+        {
+            job.abort = Some(abort);
+        }
+    }
+
+    /// Record the outcome of a job. Returns false if it was cancelled first.
+    pub fn finish(&self, id: &str, outcome: Result<ScanResult, String>) -> bool {
+        let mut jobs = self.lock();
+        let Some(job) = jobs
+            .iter_mut()
+            .find(|job| job.id == id && job.status == ScanJobStatus::Running)
+        else {
+            return false;
+        };
+        job.finished_at = Some(Utc::now());
+        job.abort = None;
+        match outcome {
+            Ok(result) => {
+                job.status = ScanJobStatus::Completed;
+                job.result = Some(result);
+            }
+            Err(error) => {
+                job.status = ScanJobStatus::Failed;
+                job.error = Some(error);
+            }
+        }
+        prune(&mut jobs);
+        true
+    }
+
+    /// Stop a running job. Returns the job's status afterwards, or `None`
+    /// for an unknown job.
+    pub fn cancel(&self, id: &str) -> Option<ScanJobStatus> {
+        let mut jobs = self.lock();
+        let job = jobs.iter_mut().find(|job| job.id == id)?;
+        if job.status != ScanJobStatus::Running {
+            return Some(job.status);
+        }
+        if let Some(abort) = job.abort.take() {
+            abort.abort();
+        }
+        job.status = ScanJobStatus::Cancelled;
+        job.finished_at = Some(Utc::now());
+        prune(&mut jobs);
+        Some(ScanJobStatus::Cancelled)
+    }
+
+    pub fn info(&self, id: &str) -> Option<ScanJobInfo> {
+        self.lock()
+            .iter()
+            .find(|job| job.id == id)
+            .map(ScanJobInfo::from)
+    }
+
+    /// The status of a job, with its result once it has completed
+    pub fn result(&self, id: &str) -> Option<(ScanJobStatus, Option<ScanResult>)> {
+        self.lock()
+            .iter()
+            .find(|job| job.id == id)
+            .map(|job| (job.status, job.result.clone()))
+    }
+}
+
+/// Drop the oldest finished jobs beyond `SCAN_JOB_HISTORY`
+fn prune(jobs: &mut VecDeque<ScanJob>) {
+    let mut finished = jobs
+        .iter()
+        .filter(|job| job.status != ScanJobStatus::Running)
+        .count();
+    jobs.retain(|job| {
+        if finished > SCAN_JOB_HISTORY && job.status != ScanJobStatus::Running {
+            finished -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> ScanResult {
+        ScanResult {
+            status: "completed".to_string(),
+            tables_scanned: 1,
+            columns_scanned: 2,
+            findings: Vec::new(),
+            schema: "public".to_string(),
+            database: "shop".to_string(),
+            scan_duration_ms: 5,
+            filters: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_scan_job_lifecycle() {
+        let jobs = ScanJobs::default();
+        let (id, progress) = jobs.start("shop");
+        progress.set_tables_total(2);
+        progress.table_done(&[]);
+
+        let info = jobs.info(&id).unwrap();
+        assert_eq!(info.status, ScanJobStatus::Running);
+        assert_eq!((info.tables_done, info.tables_total), (1, 2));
+        assert!(info.finished_at.is_none());
+        assert!(matches!(
+            jobs.result(&id),
+            Some((ScanJobStatus::Running, None))
+        ));
+
+        assert!(jobs.finish(&id, Ok(result())));
+        let (status, scan) = jobs.result(&id).unwrap();
+        assert_eq!(status, ScanJobStatus::Completed);
+        assert_eq!(scan.unwrap().columns_scanned, 2);
+        // Finished jobs can't be cancelled
+        assert_eq!(jobs.cancel(&id), Some(ScanJobStatus::Completed));
+
+        let (id, _) = jobs.start("shop");
+        assert!(jobs.finish(&id, Err("connection refused".to_string())));
+        let info = jobs.info(&id).unwrap();
+        assert_eq!(info.status, ScanJobStatus::Failed);
+        assert_eq!(info.error.as_deref(), Some("connection refused"));
+
+        assert!(jobs.info("unknown").is_none());
+        assert_eq!(jobs.cancel("unknown"), None);
+    }
+
+    #[tokio::test]
+    async fn test_scan_job_cancel() {
+        let jobs = ScanJobs::default();
+        let (id, _) = jobs.start("shop");
+        let task = tokio::spawn(std::future::pending::<()>());
+        jobs.set_abort(&id, task.abort_handle());
+
+        assert_eq!(jobs.cancel(&id), Some(ScanJobStatus::Cancelled));
+        assert!(task.await.unwrap_err().is_cancelled());
+        // A scan that finishes regardless doesn't overwrite the cancellation
+        assert!(!jobs.finish(&id, Ok(result())));
+        assert_eq!(jobs.info(&id).unwrap().status, ScanJobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_scan_job_history_is_bounded() {
+        let jobs = ScanJobs::default();
+        let (running, _) = jobs.start("shop");
+        let ids: Vec<String> = (0..SCAN_JOB_HISTORY + 5)
+            .map(|_| {
+                let (id, _) = jobs.start("shop");
+                jobs.finish(&id, Ok(result()));
+                id
+            })
+            .collect();
+
+        assert!(jobs.info(&ids[0]).is_none());
+        assert!(jobs.info(&ids[4]).is_none());
+        assert!(jobs.info(&ids[5]).is_some());
+        // Running jobs are never dropped
+        assert!(jobs.info(&running).is_some());
+        assert_eq!(jobs.lock().len(), SCAN_JOB_HISTORY + 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Database scans run on a schedule.
+//!
+//! `scanner.schedule` runs a scan every `interval_secs` or on a cron
+//! expression, so that new PII columns are noticed without anyone asking.
+//! Scheduled runs are ordinary scan jobs: they can be followed and cancelled
+//! through `/scan/{job_id}`, and land in the scan history, which logs a
+//! warning when a run finds PII the previous one didn't.
+
+use crate::api::start_scan_job;
+use crate::config::ScanScheduleConfig;
+use crate::db_scanner::ScanConfig;
+use crate::scan_jobs::ScanJobStatus;
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often the scheduler checks for config changes while waiting
+const RESCHEDULE_POLL: Duration = Duration::from_secs(1);
+
+/// The scan a schedule runs, checked at startup and on reload
+pub fn scheduled_scan(schedule: &ScanScheduleConfig) -> Result<ScanConfig> {
+    let scan = serde_json::Value::Object(schedule.scan.clone());
+    let scan: ScanConfig = serde_json::from_value(scan).context("Invalid scanner.schedule.scan")?;
+    scan.filters.validate()?;
+    Ok(scan)
+}
+
+/// Starts the scheduled scan, keeping track of the previous run
+struct ScanScheduler {
+    state: AppState,
+    /// Last time a run was due, or when the scheduler started; a run counts
+    /// even when it was skipped
+    last_run: DateTime<Utc>,
+    /// Job of the latest run
+    job_id: Option<String>,
+}
+
+impl ScanScheduler {
+    fn new(state: AppState) -> Self {
+        Self {
+            state,
+            last_run: Utc::now(),
+            job_id: None,
+        }
+    }
+
+    /// Start a run, unless the previous one is still going. Returns the job ID.
+    async fn run(&mut self, scan: &ScanConfig) -> Option<String> {
+        if let Some(job_id) = &self.job_id
+            && self
+                .state
+                .scan_jobs
+                .info(job_id)
+                .is_some_and(|job| job.status == ScanJobStatus::Running)
+        {
+            warn!(
+                "Skipping scheduled scan of {}: scan {} is still running",
+                scan.database, job_id
+            );
+            return None;
+        }
+        match start_scan_job(&self.state, scan.clone(), true).await {
+            Ok(job_id) => {
+                info!("Started scheduled scan {} of {}", job_id, scan.database);
+                self.job_id = Some(job_id.clone());
+                Some(job_id)
+            }
+            Err(e) => {
+                warn!("Failed to start scheduled scan of {}: {}", scan.database, e);
+                None
+            }
+        }
+    }
+}
+
+/// Run `scanner.schedule` for as long as the proxy runs. The schedule is
+/// re-read whenever the config changes, so a reload adds, moves or removes
+/// runs without a restart.
+pub async fn run_scan_scheduler(state: AppState) {
+    let mut scheduler = ScanScheduler::new(state.clone());
+    loop {
+        let generation = state.config_generation();
+        let schedule = state.config.read().await.scanner.schedule.clone();
+        let next = schedule
+            .as_ref()
+            .and_then(|s| s.next_run(scheduler.last_run, Utc::now()));
+
+        // Wait for the next run, or for a config change to reschedule
+        let due = loop {
+            if state.config_generation() != generation {
+                break false;
+            }
+            let now = Utc::now();
+            let wait = match next {
+                Some(next) if next <= now => break true,
+                Some(next) => (next - now).to_std().unwrap_or_default(),
+                None => RESCHEDULE_POLL,
+            };
+            tokio::time::sleep(wait.min(RESCHEDULE_POLL)).await;
+        };
+        if let (true, Some(schedule)) = (due, &schedule) {
+            scheduler.last_run = Utc::now();
+            match scheduled_scan(schedule) {
+                Ok(scan) => {
+                    scheduler.run(&scan).await;
+                }
+                Err(e) => warn!("Skipping scheduled scan: {:#}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::state::DbProtocol;
+
+    #[tokio::test]
+    async fn test_scheduled_runs_do_not_overlap() {
+        // An upstream that accepts connections but never answers keeps the
+        // scan running
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let state = AppState::new(
+            AppConfig::default(),
+            "proxy.yaml".to_string(),
+            "127.0.0.1".to_string(),
+            port,
+            DbProtocol::Postgres,
+        );
+        let scan: ScanConfig = serde_json::from_value(serde_json::json!({
+            "username": "scanner",
+            "database": "shop"
+        }))
+        .unwrap();
+
+        let mut scheduler = ScanScheduler::new(state.clone());
+        let job_id = scheduler.run(&scan).await.unwrap();
+        assert_eq!(
+            state.scan_jobs.info(&job_id).unwrap().status,
+            ScanJobStatus::Running
+        );
+        assert_eq!(scheduler.run(&scan).await, None);
+
+        state.scan_jobs.cancel(&job_id);
+        let next = scheduler.run(&scan).await.unwrap();
+        assert_ne!(next, job_id);
+        state.scan_jobs.cancel(&next);
+    }
+}
@@ -1,4 +1,8 @@
-use regex::Regex;
+use crate::config::{CustomPattern, MaskStrategy, PatternValidation, ScannerConfig};
+use regex::{Regex, RegexSet};
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum PiiType {
@@ -9,16 +13,126 @@ pub enum PiiType {
     IpAddress,
     DateOfBirth,
     Passport,
+    /// International Bank Account Number, mod-97 checked
+    Iban,
+    /// UK National Insurance number
+    UkNino,
+    /// German tax identification number (Steuer-ID), check digit verified
+    DeTaxId,
+    /// Matched by the `scanner.custom_patterns` entry of this name
+    Custom(String),
+}
+
+impl std::fmt::Display for PiiType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PiiType::Custom(name) => f.write_str(name),
+            builtin => write!(f, "{:?}", builtin),
+        }
+    }
+}
+
+// Patterns of the PII found inside longer text as well as in whole values
+const EMAIL_PATTERN: &str = r"(?i)[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}";
+// 13-19 digits, optional dashes/spaces between groups, such as 4-4-4-4 or
+// Amex's 4-6-5
+const CREDIT_CARD_PATTERN: &str = r"\d(?:[-\s]?\d){12,18}";
+// US SSN: XXX-XX-XXXX format
+const SSN_PATTERN: &str = r"\d{3}-\d{2}-\d{4}";
+// Phone: Must have at least 10 digits total and include formatting
+// Matches: +1-555-123-4567, (555) 123-4567, 555-123-4567, +44 20 7946 0958
+const PHONE_PATTERN: &str = r"(?:\+\d{1,3}[-.\s])?\(?(\d{3})\)?[-.\s]?\d{3}[-.\s]?\d{4}";
+// IPv4 address
+const IP_PATTERN: &str =
+    r"(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)";
+// IBAN: country code, check digits and 11-30 alphanumerics, optionally in
+// groups of four
+const IBAN_PATTERN: &str = r"[A-Z]{2}\d{2}(?: ?[A-Z0-9]){11,30}";
+// UK NINO: two prefix letters (D, F, I, Q, U and V never used, O not
+// second), six digits and a suffix of A-D
+const NINO_PATTERN: &str = r"[A-CEGHJ-PR-TW-Z][A-CEGHJ-NPR-TW-Z] ?\d{2} ?\d{2} ?\d{2} ?[A-D]";
+// Steuer-ID: 11 digits, written plain or as 2-3-3-3
+const TAX_ID_PATTERN: &str = r"[1-9]\d(?: ?\d{3}){3}";
+// Date of birth: YYYY-MM-DD, MM/DD/YYYY, DD/MM/YYYY, DD-MM-YYYY
+const DOB_PATTERN: &str = r"\d{4}[-/]\d{2}[-/]\d{2}|\d{2}[-/]\d{2}[-/]\d{4}";
+// Passport: Basic pattern for common formats (alphanumeric, 6-9 chars)
+const PASSPORT_PATTERN: &str = r"[A-Z]{1,2}\d{6,8}";
+
+/// Types `scan` looks for in whole values. Ties in confidence go to the
+/// earlier type.
+const VALUE_PATTERNS: [(PiiType, &str); 10] = [
+    (PiiType::Email, EMAIL_PATTERN),
+    (PiiType::CreditCard, CREDIT_CARD_PATTERN),
+    (PiiType::Ssn, SSN_PATTERN),
+    (PiiType::IpAddress, IP_PATTERN),
+    (PiiType::DateOfBirth, DOB_PATTERN),
+    (PiiType::Phone, PHONE_PATTERN),
+    (PiiType::Iban, IBAN_PATTERN),
+    (PiiType::UkNino, NINO_PATTERN),
+    (PiiType::DeTaxId, TAX_ID_PATTERN),
+    (PiiType::Passport, PASSPORT_PATTERN),
+];
+
+/// Types `scan_all` looks for, in order of precedence. Dates and passport
+/// numbers are too common in prose to be picked out of it.
+const SUBSTRING_PATTERNS: [(PiiType, &str); 8] = [
+    (PiiType::Email, EMAIL_PATTERN),
+    (PiiType::CreditCard, CREDIT_CARD_PATTERN),
+    (PiiType::Ssn, SSN_PATTERN),
+    (PiiType::IpAddress, IP_PATTERN),
+    (PiiType::Iban, IBAN_PATTERN),
+    (PiiType::UkNino, NINO_PATTERN),
+    (PiiType::DeTaxId, TAX_ID_PATTERN),
+    (PiiType::Phone, PHONE_PATTERN),
+];
+
+/// A pattern that must match the whole value
+fn anchored(pattern: &str) -> String {
+    format!("^(?:{})$", pattern)
+}
+
+/// A `scanner.custom_patterns` entry, compiled
+struct CustomMatcher {
+    pii_type: PiiType,
+    /// The pattern as configured
+    regex: Regex,
+    strategy: MaskStrategy,
+    validate: PatternValidation,
+}
+
+impl CustomMatcher {
+    fn new(pattern: &CustomPattern) -> Self {
+        Self {
+            pii_type: PiiType::Custom(pattern.name.clone()),
+            regex: pattern.regex.0.clone(),
+            strategy: pattern.strategy,
+            validate: pattern.validate,
+        }
+    }
+
+    fn valid(&self, text: &str) -> bool {
+        match self.validate {
+            PatternValidation::None => true,
+            PatternValidation::Luhn => {
+                let digits = digits(text);
+                !digits.is_empty() && luhn_valid(&digits)
+            }
+        }
+    }
 }
 
 pub struct PiiScanner {
-    email_regex: Regex,
-    cc_regex: Regex,
-    ssn_regex: Regex,
-    phone_regex: Regex,
-    ip_regex: Regex,
-    dob_regex: Regex,
-    passport_regex: Regex,
+    /// Anchored custom patterns followed by `VALUE_PATTERNS`, matched in a
+    /// single pass
+    value_set: RegexSet,
+    /// The type of each pattern in `value_set`
+    value_types: Vec<PiiType>,
+    /// Unanchored `SUBSTRING_PATTERNS`, compiled on the first `scan_all`
+    substring_regexes: OnceLock<Vec<(PiiType, Regex)>>,
+    /// `scanner.custom_patterns`
+    custom: Vec<CustomMatcher>,
+    /// Only report card numbers that pass the Luhn check
+    strict_credit_card: bool,
 }
 
 impl Default for PiiScanner {
@@ -29,57 +143,304 @@ impl Default for PiiScanner {
 
 impl PiiScanner {
     pub fn new() -> Self {
+        Self::with_custom(Vec::new(), true)
+    }
+
+    /// Scanner with the settings and custom patterns of the `scanner` config
+    pub fn from_config(config: &ScannerConfig) -> Self {
+        let custom = config
+            .custom_patterns
+            .iter()
+            .map(CustomMatcher::new)
+            .collect();
+        Self::with_custom(custom, config.strict_credit_card)
+    }
+
+    fn with_custom(custom: Vec<CustomMatcher>, strict_credit_card: bool) -> Self {
+        let custom_patterns = custom
+            .iter()
+            .map(|matcher| (matcher.pii_type.clone(), matcher.regex.as_str()));
+        let (value_types, value_patterns): (Vec<PiiType>, Vec<String>) = custom_patterns
+            .chain(VALUE_PATTERNS)
+            .map(|(pii_type, pattern)| (pii_type, anchored(pattern)))
+            .unzip();
         Self {
-            // Simple email regex
-            email_regex: Regex::new(r"(?i)^[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}$").unwrap(),
-            // Credit Card regex (13-19 digits, optional dashes/spaces)
-            cc_regex: Regex::new(r"^(?:\d{4}[-\s]?){3}\d{4}$").unwrap(),
-            // US SSN: XXX-XX-XXXX format
-            ssn_regex: Regex::new(r"^\d{3}-\d{2}-\d{4}$").unwrap(),
-            // Phone: Must have at least 10 digits total and include formatting
-            // Matches: +1-555-123-4567, (555) 123-4567, 555-123-4567, +44 20 7946 0958
-            phone_regex: Regex::new(r"^(?:\+\d{1,3}[-.\s])?\(?(\d{3})\)?[-.\s]?\d{3}[-.\s]?\d{4}$").unwrap(),
-            // IPv4 address
-            ip_regex: Regex::new(r"^(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)$").unwrap(),
-            // Date of birth: YYYY-MM-DD, MM/DD/YYYY, DD/MM/YYYY, DD-MM-YYYY
-            dob_regex: Regex::new(r"^(?:\d{4}[-/]\d{2}[-/]\d{2}|\d{2}[-/]\d{2}[-/]\d{4})$").unwrap(),
-            // Passport: Basic pattern for common formats (alphanumeric, 6-9 chars)
-            passport_regex: Regex::new(r"^[A-Z]{1,2}\d{6,8}$").unwrap(),
+            // Custom patterns compiled when the config was loaded, so this
+            // can't fail
+            value_set: RegexSet::new(value_patterns).unwrap(),
+            value_types,
+            substring_regexes: OnceLock::new(),
+            custom,
+            strict_credit_card,
         }
     }
 
-    pub fn scan(&self, text: &str) -> Option<PiiType> {
-        // Check patterns in order of specificity
-        if self.email_regex.is_match(text) {
-            return Some(PiiType::Email);
-        }
-        if self.cc_regex.is_match(text) {
-            return Some(PiiType::CreditCard);
+    /// Strategy of a custom PII type
+    pub fn custom_strategy(&self, pii_type: &PiiType) -> Option<MaskStrategy> {
+        self.custom
+            .iter()
+            .find(|matcher| &matcher.pii_type == pii_type)
+            .map(|matcher| matcher.strategy)
+    }
+
+    /// Checksums and reserved ranges the patterns can't express
+    fn valid(&self, pii_type: &PiiType, text: &str) -> bool {
+        match pii_type {
+            PiiType::CreditCard => !self.strict_credit_card || luhn_valid(&digits(text)),
+            PiiType::Iban => iban_valid(text),
+            PiiType::UkNino => nino_prefix_allocated(text),
+            PiiType::DeTaxId => tax_id_valid(text),
+            PiiType::Custom(_) => self
+                .custom
+                .iter()
+                .find(|matcher| &matcher.pii_type == pii_type)
+                .is_some_and(|matcher| matcher.valid(text)),
+            _ => true,
         }
-        if self.ssn_regex.is_match(text) {
-            return Some(PiiType::Ssn);
+    }
+
+    /// The most likely type of PII `text` is, if any
+    pub fn scan(&self, text: &str) -> Option<PiiType> {
+        self.scan_ranked(text)
+            .into_iter()
+            .next()
+            .map(|(pii_type, _)| pii_type)
+    }
+
+    /// Every type of PII `text` could be, with the confidence of each, most
+    /// likely first
+    pub fn scan_ranked(&self, text: &str) -> Vec<(PiiType, f32)> {
+        let mut hits: Vec<(PiiType, f32)> = self
+            .value_set
+            .matches(text)
+            .into_iter()
+            .map(|i| &self.value_types[i])
+            .filter(|pii_type| self.valid(pii_type, text))
+            .map(|pii_type| (pii_type.clone(), self.confidence(pii_type)))
+            .collect();
+        // Stable, so ties keep the order of the patterns
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits
+    }
+
+    /// How sure a match of `pii_type` is to really be that
+    fn confidence(&self, pii_type: &PiiType) -> f32 {
+        match pii_type {
+            // Organisation-specific identifiers are the most specific of all
+            PiiType::Custom(_) => 1.0,
+            PiiType::Iban => 0.99,
+            PiiType::Email => 0.95,
+            PiiType::CreditCard if self.strict_credit_card => 0.95,
+            PiiType::CreditCard => 0.6,
+            PiiType::UkNino | PiiType::DeTaxId => 0.9,
+            PiiType::Ssn | PiiType::IpAddress => 0.85,
+            PiiType::DateOfBirth => 0.6,
+            PiiType::Phone => 0.55,
+            PiiType::Passport => 0.4,
         }
-        if self.ip_regex.is_match(text) {
-            return Some(PiiType::IpAddress);
+    }
+
+    /// Find the PII inside longer text, such as an email in a free-text note.
+    /// Returns the byte ranges of non-overlapping hits, in order. A hit must
+    /// not run into letters, digits or underscores on either side.
+    pub fn scan_all(&self, text: &str) -> Vec<(Range<usize>, PiiType)> {
+        let regexes = self.substring_regexes.get_or_init(|| {
+            SUBSTRING_PATTERNS
+                .iter()
+                .map(|(pii_type, pattern)| (pii_type.clone(), Regex::new(pattern).unwrap()))
+                .collect()
+        });
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+        let custom = self
+            .custom
+            .iter()
+            .map(|matcher| (&matcher.pii_type, &matcher.regex));
+        let builtin = regexes.iter().map(|(pii_type, regex)| (pii_type, regex));
+
+        let mut found: Vec<(Range<usize>, PiiType)> = Vec::new();
+        for (pii_type, regex) in custom.chain(builtin) {
+            for hit in regex.find_iter(text) {
+                let range = hit.range();
+                // Custom patterns may match empty strings
+                if range.is_empty() {
+                    continue;
+                }
+                let bounded = !text[..range.start].chars().next_back().is_some_and(is_word)
+                    && !text[range.end..].chars().next().is_some_and(is_word);
+                let overlaps = found
+                    .iter()
+                    .any(|(other, _)| other.start < range.end && range.start < other.end);
+                if bounded && !overlaps && self.valid(pii_type, hit.as_str()) {
+                    found.push((range, pii_type.clone()));
+                }
+            }
         }
-        // Check date before phone to avoid false positives
-        if self.dob_regex.is_match(text) {
-            return Some(PiiType::DateOfBirth);
+        found.sort_by_key(|(range, _)| range.start);
+        found
+    }
+
+    /// Replace the quoted literals of a SQL statement that look like PII, and
+    /// bare numbers that look like card numbers, with `***`
+    pub fn redact_sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        let bytes = sql.as_bytes();
+        let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$';
+        let mut redacted: Vec<Range<usize>> = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                quote @ (b'\'' | b'"') => {
+                    let start = i + 1;
+                    let mut end = start;
+                    while end < bytes.len() {
+                        match bytes[end] {
+                            b'\\' => end += 2,
+                            b if b == quote && bytes.get(end + 1) == Some(&quote) => end += 2,
+                            b if b == quote => break,
+                            _ => end += 1,
+                        }
+                    }
+                    // An unterminated literal runs to the end of the statement
+                    let end = end.min(bytes.len());
+                    if self.scan(&sql[start..end]).is_some() {
+                        redacted.push(start..end);
+                    }
+                    i = end + 1;
+                }
+                b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                    i = sql[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                }
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    i = sql[i + 2..].find("*/").map_or(bytes.len(), |n| i + n + 4);
+                }
+                b'0'..=b'9' if i == 0 || !is_word(bytes[i - 1]) => {
+                    let start = i;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    // Other numbers are more likely ids or amounts than PII
+                    if (i == bytes.len() || !is_word(bytes[i]))
+                        && self.scan(&sql[start..i]) == Some(PiiType::CreditCard)
+                    {
+                        redacted.push(start..i);
+                    }
+                }
+                _ => i += 1,
+            }
         }
-        if self.phone_regex.is_match(text) {
-            return Some(PiiType::Phone);
+
+        if redacted.is_empty() {
+            return Cow::Borrowed(sql);
         }
-        if self.passport_regex.is_match(text) {
-            return Some(PiiType::Passport);
+        let mut out = String::with_capacity(sql.len());
+        let mut copied = 0;
+        for range in redacted {
+            out.push_str(&sql[copied..range.start]);
+            out.push_str("***");
+            copied = range.end;
         }
-        None
+        out.push_str(&sql[copied..]);
+        Cow::Owned(out)
+    }
+}
+
+/// Digits of a value, ignoring everything else
+fn digits(text: &str) -> Vec<u8> {
+    text.bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|b| b - b'0')
+        .collect()
+}
+
+/// Whether a digit string passes the Luhn check
+pub fn luhn_valid(digits: &[u8]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            let d = u32::from(*d);
+            if i % 2 == 1 {
+                if d * 2 > 9 { d * 2 - 9 } else { d * 2 }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Remainder of an IBAN, rearranged with letters as numbers, modulo 97
+pub fn iban_remainder(iban: &str) -> u32 {
+    let chars: Vec<char> = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    let (head, tail) = chars.split_at(4.min(chars.len()));
+    tail.iter()
+        .chain(head)
+        .fold(0, |acc, c| match c.to_digit(36) {
+            // Letters count as two digits (A = 10 ... Z = 35)
+            Some(n) if n >= 10 => (acc * 100 + n) % 97,
+            Some(n) => (acc * 10 + n) % 97,
+            None => acc,
+        })
+}
+
+/// ISO 13616 check: the rearranged IBAN is 1 modulo 97
+fn iban_valid(iban: &str) -> bool {
+    iban_remainder(iban) == 1
+}
+
+/// Prefixes that pass the letter rules but are never allocated
+fn nino_prefix_allocated(nino: &str) -> bool {
+    !matches!(&nino[..2], "BG" | "GB" | "KN" | "NK" | "NT" | "TN" | "ZZ")
+}
+
+/// ISO 7064 MOD 11,10 check digit over the first ten digits of a Steuer-ID
+pub fn tax_id_check_digit(digits: &[u8]) -> u8 {
+    let mut product = 10;
+    for &d in digits {
+        let sum = match (d as u32 + product) % 10 {
+            0 => 10,
+            sum => sum,
+        };
+        product = sum * 2 % 11;
     }
+    ((11 - product) % 10) as u8
+}
+
+/// A Steuer-ID repeats exactly one of its first ten digits, two or three
+/// times, and ends with its check digit
+fn tax_id_valid(text: &str) -> bool {
+    let digits = digits(text);
+    let mut counts = [0u8; 10];
+    for &d in &digits[..10] {
+        counts[d as usize] += 1;
+    }
+    let repeated = counts.iter().filter(|&&n| n > 1).count();
+    repeated == 1
+        && counts.iter().all(|&n| n <= 3)
+        && tax_id_check_digit(&digits[..10]) == digits[10]
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// `scan_ranked`, trying each pattern on its own
+    fn scan_sequential(scanner: &PiiScanner, text: &str) -> Vec<(PiiType, f32)> {
+        let mut hits: Vec<(PiiType, f32)> = scanner
+            .value_set
+            .patterns()
+            .iter()
+            .zip(&scanner.value_types)
+            .filter(|(pattern, pii_type)| {
+                Regex::new(pattern).unwrap().is_match(text) && scanner.valid(pii_type, text)
+            })
+            .map(|(_, pii_type)| (pii_type.clone(), scanner.confidence(pii_type)))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits
+    }
+
     #[test]
     fn test_email_detection() {
         let scanner = PiiScanner::new();
@@ -101,21 +462,157 @@ mod tests {
     fn test_credit_card_detection() {
         let scanner = PiiScanner::new();
 
-        // Valid credit cards
+        // Luhn-valid card numbers, with or without separators
+        for card in [
+            "4111-1111-1111-1111",
+            "4111 1111 1111 1111",
+            "4111111111111111",
+            "378282246310005",     // Amex, 15 digits
+            "3782 822463 10005",   // Amex grouping
+            "4222222222222",       // 13-digit Visa
+            "6759649826438453",    // Maestro
+            "6799990100000000019", // 19-digit Maestro
+        ] {
+            assert_eq!(scanner.scan(card), Some(PiiType::CreditCard), "{}", card);
+        }
+
+        // Order numbers and tracking codes that fail the Luhn check
+        assert_eq!(scanner.scan("1234-5678-9012-3456"), None);
+        assert_eq!(scanner.scan("1234567890123456"), None);
+        assert_eq!(scanner.scan("4111111111111112"), None);
+
+        // Invalid credit cards
+        assert_eq!(scanner.scan("1234-5678-9012"), None);
+        assert_eq!(scanner.scan("not a credit card"), None);
+        assert_eq!(scanner.scan("12345678901234567890"), None); // Too long
+        assert_eq!(scanner.scan("4111--1111-1111-1111"), None);
+    }
+
+    #[test]
+    fn test_custom_patterns() {
+        let config: ScannerConfig = serde_yaml::from_str(
+            r#"
+custom_patterns:
+  - { name: employee_id, regex: "EMP-\\d{6}", strategy: hash }
+  - { name: loyalty_card, regex: "\\d{16}", strategy: redact, validate: luhn }
+"#,
+        )
+        .unwrap();
+        let scanner = PiiScanner::from_config(&config);
+        let employee = PiiType::Custom("employee_id".to_string());
+        let loyalty = PiiType::Custom("loyalty_card".to_string());
+
+        assert_eq!(scanner.scan("EMP-123456"), Some(employee.clone()));
+        assert_eq!(scanner.scan("EMP-123456 "), None);
+        assert_eq!(scanner.scan("4111111111111111"), Some(loyalty.clone()));
+        // Fails the Luhn check, so falls through to the builtin patterns
+        assert_eq!(scanner.scan("4111111111111112"), None);
+        assert_eq!(scanner.scan("user@example.com"), Some(PiiType::Email));
+
         assert_eq!(
-            scanner.scan("1234-5678-9012-3456"),
-            Some(PiiType::CreditCard)
+            scanner.scan_all("badge EMP-654321, mail bob@example.com"),
+            vec![(6..16, employee.clone()), (23..38, PiiType::Email)]
         );
+        assert_eq!(scanner.custom_strategy(&employee), Some(MaskStrategy::Hash));
         assert_eq!(
-            scanner.scan("1234 5678 9012 3456"),
-            Some(PiiType::CreditCard)
+            scanner.custom_strategy(&loyalty),
+            Some(MaskStrategy::Redact)
         );
-        assert_eq!(scanner.scan("1234567890123456"), Some(PiiType::CreditCard));
+        assert_eq!(scanner.custom_strategy(&PiiType::Email), None);
+        assert_eq!(employee.to_string(), "employee_id");
+        assert_eq!(PiiType::CreditCard.to_string(), "CreditCard");
+    }
 
-        // Invalid credit cards
+    #[test]
+    fn test_scan_ranked() {
+        let scanner = PiiScanner::new();
+        assert_eq!(
+            scanner.scan_ranked("user@example.com"),
+            vec![(PiiType::Email, 0.95)]
+        );
+        assert!(scanner.scan_ranked("hello").is_empty());
+
+        let config: ScannerConfig = serde_yaml::from_str(
+            "custom_patterns:\n  - { name: order_ref, regex: \"\\\\d{16}\", strategy: hash }",
+        )
+        .unwrap();
+        let scanner = PiiScanner::from_config(&config);
+        let order_ref = PiiType::Custom("order_ref".to_string());
+        assert_eq!(
+            scanner.scan_ranked("4111111111111111"),
+            vec![(order_ref.clone(), 1.0), (PiiType::CreditCard, 0.95)]
+        );
+        assert_eq!(scanner.scan("4111111111111111"), Some(order_ref));
+        // A failed checksum drops the card interpretation only
+        assert_eq!(scanner.scan_ranked("4111111111111112").len(), 1);
+    }
+
+    #[test]
+    fn test_single_pass_matches_sequential() {
+        let corpus = [
+            "user@example.com",
+            "4111 1111 1111 1111",
+            "4111-1111-1111-1112",
+            "378282246310005",
+            "123-45-6789",
+            "+1-555-123-4567",
+            "(555) 123-4567",
+            "192.168.1.1",
+            "256.1.1.1",
+            "1990-01-15",
+            "01/15/1990",
+            "AB1234567",
+            "GB63 2938 4710 5582 9301 64",
+            "DE89370400440532013000",
+            "AB 12 34 56 C",
+            "86095742719",
+            "EMP-123456",
+            "5555555555554444",
+            "hello world",
+            "",
+            "12345",
+        ];
+        let config: ScannerConfig = serde_yaml::from_str(
+            r#"
+custom_patterns:
+  - { name: employee_id, regex: "EMP-\\d{6}", strategy: hash }
+  - { name: order_ref, regex: "\\d{16}", strategy: redact, validate: luhn }
+"#,
+        )
+        .unwrap();
+        let lenient = ScannerConfig {
+            strict_credit_card: false,
+            ..Default::default()
+        };
+        for scanner in [
+            PiiScanner::new(),
+            PiiScanner::from_config(&config),
+            PiiScanner::from_config(&lenient),
+        ] {
+            for value in corpus {
+                assert_eq!(
+                    scanner.scan_ranked(value),
+                    scan_sequential(&scanner, value),
+                    "{}",
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_lenient_credit_card_detection() {
+        let scanner = PiiScanner::from_config(&ScannerConfig {
+            strict_credit_card: false,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            scanner.scan("1234-5678-9012-3456"),
+            Some(PiiType::CreditCard)
+        );
+        assert_eq!(scanner.scan("1234567890123"), Some(PiiType::CreditCard));
         assert_eq!(scanner.scan("1234-5678-9012"), None);
-        assert_eq!(scanner.scan("not a credit card"), None);
-        assert_eq!(scanner.scan("12345678901234567890"), None); // Too long
     }
 
     #[test]
@@ -192,6 +689,65 @@ mod tests {
         assert_eq!(scanner.scan("12345678"), None); // no letter prefix
     }
 
+    #[test]
+    fn test_iban_detection() {
+        let scanner = PiiScanner::new();
+
+        // Valid IBANs from several countries, compact and grouped
+        for iban in [
+            "DE89370400440532013000",
+            "DE89 3704 0044 0532 0130 00",
+            "GB82WEST12345698765432",
+            "GB82 WEST 1234 5698 7654 32",
+            "FR1420041010050500013M02606",
+            "NL91ABNA0417164300",
+            "ES9121000418450200051332",
+        ] {
+            assert_eq!(scanner.scan(iban), Some(PiiType::Iban), "{}", iban);
+        }
+
+        // Checksum failures and malformed values
+        assert_eq!(scanner.scan("DE89370400440532013001"), None);
+        assert_eq!(scanner.scan("GB82WEST12345698765433"), None);
+        assert_eq!(scanner.scan("NL91ABNA041716430"), None); // Digit dropped
+        assert_eq!(scanner.scan("de89370400440532013000"), None); // lowercase
+        assert_eq!(scanner.scan("DE89"), None);
+    }
+
+    #[test]
+    fn test_uk_nino_detection() {
+        let scanner = PiiScanner::new();
+
+        assert_eq!(scanner.scan("AB123456C"), Some(PiiType::UkNino));
+        assert_eq!(scanner.scan("AB 12 34 56 C"), Some(PiiType::UkNino));
+        assert_eq!(scanner.scan("JG103759A"), Some(PiiType::UkNino));
+
+        assert_eq!(scanner.scan("BG123456C"), None); // Unallocated prefix
+        assert_eq!(scanner.scan("DA123456C"), None); // D is never used
+        assert_eq!(scanner.scan("AO123456C"), None); // O is never second
+        assert_eq!(scanner.scan("AB123456E"), None); // Suffix beyond D
+        assert_eq!(scanner.scan("AB12345C"), None);
+    }
+
+    #[test]
+    fn test_de_tax_id_detection() {
+        let scanner = PiiScanner::new();
+
+        for tax_id in [
+            "86095742719",
+            "47036892816",
+            "65929970489", // A digit repeated three times
+            "86 095 742 719",
+        ] {
+            assert_eq!(scanner.scan(tax_id), Some(PiiType::DeTaxId), "{}", tax_id);
+        }
+
+        assert_eq!(scanner.scan("86095742718"), None); // Wrong check digit
+        assert_eq!(scanner.scan("12345678903"), None); // No repeated digit
+        assert_eq!(scanner.scan("06095742719"), None); // Leading zero
+        assert_eq!(scanner.scan("860957427"), None);
+    }
+
     #[test]
     fn test_non_pii_data() {
         let scanner = PiiScanner::new();
@@ -203,6 +759,74 @@ mod tests {
         assert_eq!(scanner.scan("12345"), None);
     }
 
+    #[test]
+    fn test_scan_all_finds_embedded_pii() {
+        let scanner = PiiScanner::new();
+
+        let text = "Call john@acme.com or (555) 123-4567, card 4111 1111 1111 1111.";
+        let hits: Vec<(&str, PiiType)> = scanner
+            .scan_all(text)
+            .into_iter()
+            .map(|(range, pii_type)| (&text[range], pii_type))
+            .collect();
+        assert_eq!(
+            hits,
+            vec![
+                ("john@acme.com", PiiType::Email),
+                ("(555) 123-4567", PiiType::Phone),
+                ("4111 1111 1111 1111", PiiType::CreditCard),
+            ]
+        );
+
+        // Hits can't run into surrounding words or digits
+        assert!(
+            scanner
+                .scan_all("ref 123-45-67890 or x123-45-6789")
+                .is_empty()
+        );
+        assert!(scanner.scan_all("order 41111111111111112").is_empty());
+        // Checksums apply as for whole values
+        assert!(scanner.scan_all("tracking 1234 5678 9012 3456").is_empty());
+        assert_eq!(
+            scanner.scan_all("IBAN DE89370400440532013000, ssn 123-45-6789"),
+            vec![(5..27, PiiType::Iban), (33..44, PiiType::Ssn)]
+        );
+        // Dates are only recognised as whole values
+        assert!(scanner.scan_all("born 1990-01-15").is_empty());
+        assert!(scanner.scan_all("").is_empty());
+    }
+
+    #[test]
+    fn test_redact_sql_literals() {
+        let scanner = PiiScanner::new();
+        let redact = |sql| scanner.redact_sql(sql).into_owned();
+
+        assert_eq!(
+            redact("INSERT INTO users (email, name) VALUES ('real@person.com', 'Real Person')"),
+            "INSERT INTO users (email, name) VALUES ('***', 'Real Person')"
+        );
+        // Escaped quotes stay inside their literal
+        assert_eq!(
+            redact("SELECT 'O''Brien', 'it\\'s', \"123-45-6789\""),
+            "SELECT 'O''Brien', 'it\\'s', \"***\""
+        );
+        // Bare card numbers, but not ids or identifiers ending in digits
+        assert_eq!(
+            redact("UPDATE t1 SET card = 4111111111111111 WHERE id = 5551234567"),
+            "UPDATE t1 SET card = *** WHERE id = 5551234567"
+        );
+        // Quotes in comments don't start literals
+        assert_eq!(
+            redact("SELECT 1 -- don't\nFROM t WHERE ip = '10.0.0.1' /* it's */"),
+            "SELECT 1 -- don't\nFROM t WHERE ip = '***' /* it's */"
+        );
+        assert_eq!(redact("SELECT 'unterminated@example.com"), "SELECT '***");
+        assert!(matches!(
+            scanner.redact_sql("SELECT * FROM users"),
+            Cow::Borrowed(_)
+        ));
+    }
+
     #[test]
     fn test_default_trait() {
         let scanner = PiiScanner::default();
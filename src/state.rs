@@ -1,14 +1,31 @@
 use crate::audit::AuditLogger;
-use crate::config::AppConfig;
+use crate::auth_lockout::AuthLockout;
+use crate::config::{AppConfig, ServerOverrides};
+use crate::masking_cache::MaskingCache;
+use crate::scan_history::ScanHistory;
+use crate::scan_jobs::ScanJobs;
+use crate::scanner::PiiScanner;
+use crate::stats_window::StatsWindow;
+use crate::vault::TokenVault;
 use chrono::{DateTime, Utc};
 use metrics_exporter_prometheus::PrometheusHandle;
-use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
 };
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock, broadcast};
+use tokio_util::sync::CancellationToken;
+
+/// Log entries kept for `/logs`
+pub const LOG_BUFFER_LEN: usize = 100;
+
+/// Log entries a `/logs/stream` subscriber may fall behind by before it
+/// skips ahead
+pub const LOG_STREAM_CAPACITY: usize = 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
@@ -18,6 +35,76 @@ pub struct LogEntry {
     pub event_type: String,
     pub content: String,
     pub details: Option<serde_json::Value>,
+    /// CN of the client certificate presented over mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cn: Option<String>,
+    /// Database user of the connection, filled in from its startup parameters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+}
+
+/// Session details of a live proxied connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub connection_id: usize,
+    pub protocol: DbProtocol,
+    pub connected_at: DateTime<Utc>,
+    pub user: Option<String>,
+    pub database: Option<String>,
+    pub application_name: Option<String>,
+    pub client_cn: Option<String>,
+    pub client_addr: Option<IpAddr>,
+    /// Masking policy the connection resolved to, if any matched
+    pub policy: Option<String>,
+}
+
+/// A connection in the registry: its session, what it has done so far and
+/// the switch that closes it
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveConnection {
+    #[serde(flatten)]
+    pub info: ConnectionInfo,
+    pub queries: u64,
+    pub masked_fields: u64,
+    /// Cancelled to have the connection's handler close both sockets
+    #[serde(skip)]
+    kill_switch: CancellationToken,
+}
+
+impl ConnectionInfo {
+    pub fn new(connection_id: usize, protocol: DbProtocol) -> Self {
+        Self {
+            connection_id,
+            protocol,
+            connected_at: Utc::now(),
+            user: None,
+            database: None,
+            application_name: None,
+            client_cn: None,
+            client_addr: None,
+            policy: None,
+        }
+    }
+
+    /// Name of the first masking policy matching this connection
+    pub fn resolve_policy(&self, config: &AppConfig) -> Option<String> {
+        config
+            .policies
+            .iter()
+            .find(|policy| {
+                policy.criteria.matches(
+                    self.user.as_deref(),
+                    self.database.as_deref(),
+                    self.application_name.as_deref(),
+                    self.client_addr,
+                )
+            })
+            .map(|policy| policy.name.clone())
+    }
 }
 
 /// Upstream health status information
@@ -25,17 +112,31 @@ pub struct LogEntry {
 pub struct HealthStatus {
     pub healthy: bool,
     pub last_check: Option<DateTime<Utc>>,
+    /// When the upstream last answered a probe; `None` until it first does
+    pub last_success: Option<DateTime<Utc>>,
     pub last_error: Option<String>,
     pub consecutive_failures: u32,
     pub consecutive_successes: u32,
     pub latency_ms: Option<u64>,
 }
 
+/// Outcome of the latest config reloads, shown by `/health`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigReloadStatus {
+    /// When the config was last reloaded successfully
+    pub last_reload: Option<DateTime<Utc>>,
+    /// Why the latest reload failed, until one succeeds again. The config
+    /// loaded before stays active meanwhile.
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
 impl Default for HealthStatus {
     fn default() -> Self {
         Self {
             healthy: true, // Assume healthy until proven otherwise
             last_check: None,
+            last_success: None,
             last_error: None,
             consecutive_failures: 0,
             consecutive_successes: 0,
@@ -56,30 +157,56 @@ pub enum DbProtocol {
 pub struct MaskingStats {
     pub email: u64,
     pub phone: u64,
+    /// Cities, street addresses and ZIP codes
     pub address: u64,
+    /// First, last and full names
+    pub name: u64,
+    pub company: u64,
     pub credit_card: u64,
     pub ssn: u64,
     pub ip: u64,
     pub dob: u64,
     pub passport: u64,
+    pub iban: u64,
+    /// UK National Insurance numbers
+    pub nino: u64,
+    /// German tax IDs
+    pub tax_id: u64,
     pub hash: u64,
     pub json: u64,
+    /// Values replaced by NULL
+    pub null: u64,
+    /// Values replaced by fixed redaction text
+    pub redact: u64,
+    /// Values replaced by vault tokens
+    pub tokenize: u64,
+    /// Numbers perturbed by `numeric_noise`
+    pub numeric_noise: u64,
     pub other: u64,
 }
 
 impl MaskingStats {
     pub fn increment(&mut self, strategy: &str) {
         match strategy {
-            "email" => self.email += 1,
-            "phone" => self.phone += 1,
-            "address" => self.address += 1,
-            "credit_card" => self.credit_card += 1,
+            "email" | "email_preserve_domain" => self.email += 1,
+            "phone" | "phone_preserve" => self.phone += 1,
+            "address" | "street_address" | "zip" => self.address += 1,
+            "first_name" | "last_name" | "full_name" => self.name += 1,
+            "company" => self.company += 1,
+            "credit_card" | "credit_card_preserve" => self.credit_card += 1,
             "ssn" => self.ssn += 1,
             "ip" => self.ip += 1,
-            "dob" => self.dob += 1,
+            "dob" | "date_shift" => self.dob += 1,
             "passport" => self.passport += 1,
+            "iban" => self.iban += 1,
+            "nino" => self.nino += 1,
+            "tax_id" => self.tax_id += 1,
             "hash" => self.hash += 1,
             "json" => self.json += 1,
+            "null" => self.null += 1,
+            "redact" => self.redact += 1,
+            "tokenize" => self.tokenize += 1,
+            "numeric_noise" => self.numeric_noise += 1,
             _ => self.other += 1,
         }
     }
@@ -88,15 +215,136 @@ impl MaskingStats {
         self.email
             + self.phone
             + self.address
+            + self.name
+            + self.company
             + self.credit_card
             + self.ssn
             + self.ip
             + self.dob
             + self.passport
+            + self.iban
+            + self.nino
+            + self.tax_id
             + self.hash
             + self.json
+            + self.null
+            + self.redact
+            + self.tokenize
+            + self.numeric_noise
             + self.other
     }
+
+    /// Add the counts of `other` to these
+    pub fn add(&mut self, other: &MaskingStats) {
+        self.email += other.email;
+        self.phone += other.phone;
+        self.address += other.address;
+        self.name += other.name;
+        self.company += other.company;
+        self.credit_card += other.credit_card;
+        self.ssn += other.ssn;
+        self.ip += other.ip;
+        self.dob += other.dob;
+        self.passport += other.passport;
+        self.iban += other.iban;
+        self.nino += other.nino;
+        self.tax_id += other.tax_id;
+        self.hash += other.hash;
+        self.json += other.json;
+        self.null += other.null;
+        self.redact += other.redact;
+        self.tokenize += other.tokenize;
+        self.numeric_noise += other.numeric_noise;
+        self.other += other.other;
+    }
+}
+
+/// Most (table, column, strategy) combinations counted by
+/// `ColumnMaskingStats`; values masked under any other are counted in `other`
+pub const MAX_TRACKED_COLUMNS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ColumnKey {
+    table: Option<String>,
+    column: String,
+    strategy: String,
+}
+
+/// Masked values per table, column and strategy
+#[derive(Debug, Clone, Default)]
+pub struct ColumnMaskingStats {
+    counts: HashMap<ColumnKey, u64>,
+    /// Values masked once `MAX_TRACKED_COLUMNS` combinations were tracked
+    pub other: u64,
+}
+
+impl ColumnMaskingStats {
+    /// Count a masked value. Returns false when it went to `other`.
+    pub fn increment(&mut self, table: Option<&str>, column: &str, strategy: &str) -> bool {
+        let key = ColumnKey {
+            table: table.map(str::to_string),
+            column: column.to_string(),
+            strategy: strategy.to_string(),
+        };
+        if let Some(count) = self.counts.get_mut(&key) {
+            *count += 1;
+        } else if self.counts.len() < MAX_TRACKED_COLUMNS {
+            self.counts.insert(key, 1);
+        } else {
+            self.other += 1;
+            return false;
+        }
+        true
+    }
+
+    #[cfg(test)]
+    pub fn count(&self, table: Option<&str>, column: &str, strategy: &str) -> u64 {
+        let key = ColumnKey {
+            table: table.map(str::to_string),
+            column: column.to_string(),
+            strategy: strategy.to_string(),
+        };
+        self.counts.get(&key).copied().unwrap_or_default()
+    }
+}
+
+/// Serialized as a list, most masked first
+impl Serialize for ColumnMaskingStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Column<'a> {
+            table: Option<&'a str>,
+            column: &'a str,
+            strategy: &'a str,
+            count: u64,
+        }
+        #[derive(Serialize)]
+        struct ByColumn<'a> {
+            columns: Vec<Column<'a>>,
+            other: u64,
+        }
+
+        let mut columns: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(key, count)| Column {
+                table: key.table.as_deref(),
+                column: &key.column,
+                strategy: &key.strategy,
+                count: *count,
+            })
+            .collect();
+        columns.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| (a.table, a.column, a.strategy).cmp(&(b.table, b.column, b.strategy)))
+        });
+        ByColumn {
+            columns,
+            other: self.other,
+        }
+        .serialize(serializer)
+    }
 }
 
 /// Query statistics
@@ -121,6 +369,48 @@ impl QueryStats {
             _ => self.other_count += 1,
         }
     }
+
+    /// Add the counts of `other` to these
+    pub fn add(&mut self, other: &QueryStats) {
+        self.total_queries += other.total_queries;
+        self.select_count += other.select_count;
+        self.insert_count += other.insert_count;
+        self.update_count += other.update_count;
+        self.delete_count += other.delete_count;
+        self.other_count += other.other_count;
+    }
+}
+
+/// Classify a query by its first keyword, upper-cased, skipping leading
+/// comments and parentheses. Returns "OTHER" for a query without one.
+pub fn query_type(query: &str) -> String {
+    let mut rest = query;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '(');
+        if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, after)| after);
+        } else if rest.starts_with("--") || rest.starts_with('#') {
+            rest = rest.split_once('\n').map_or("", |(_, after)| after);
+        } else {
+            break;
+        }
+    }
+    let keyword: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    if keyword.is_empty() {
+        "OTHER".to_string()
+    } else {
+        keyword.to_ascii_uppercase()
+    }
+}
+
+/// Statistics for `COPY ... FROM STDIN` and `LOAD DATA LOCAL INFILE` inspection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CopyInStats {
+    pub rows_inspected: u64,
+    pub rows_flagged: u64,
 }
 
 /// Connection history data point
@@ -136,23 +426,109 @@ pub struct ConnectionDataPoint {
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AppStats {
     pub masking: MaskingStats,
+    /// Bounded; cleared with `POST /stats/columns/reset`
+    #[serde(skip_deserializing)]
+    pub by_column: ColumnMaskingStats,
     pub queries: QueryStats,
     pub total_connections: u64,
+    pub copy_in: CopyInStats,
+}
+
+/// Settings for the `AuditLogger` from the `audit` section of the config
+pub fn audit_logger_config(config: &AppConfig) -> crate::audit::AuditConfig {
+    let Some(cfg) = config.audit.as_ref() else {
+        return crate::audit::AuditConfig::default();
+    };
+    crate::audit::AuditConfig {
+        enabled: cfg.enabled,
+        log_to_stdout: cfg.log_to_stdout,
+        log_file: cfg.log_file.clone(),
+        rotation_enabled: cfg.rotation_enabled,
+        max_file_size_bytes: cfg.max_file_size_bytes,
+        max_rotated_files: cfg.max_rotated_files,
+        events: cfg
+            .events
+            .iter()
+            .map(|e| match e {
+                crate::config::AuditEventType::AuthAttempt => {
+                    crate::audit::AuditEventType::AuthAttempt
+                }
+                crate::config::AuditEventType::ConfigChange => {
+                    crate::audit::AuditEventType::ConfigChange
+                }
+                crate::config::AuditEventType::RuleAdded => crate::audit::AuditEventType::RuleAdded,
+                crate::config::AuditEventType::RuleUpdated => {
+                    crate::audit::AuditEventType::RuleUpdated
+                }
+                crate::config::AuditEventType::RuleDeleted => {
+                    crate::audit::AuditEventType::RuleDeleted
+                }
+                crate::config::AuditEventType::RulesImported => {
+                    crate::audit::AuditEventType::RulesImported
+                }
+                crate::config::AuditEventType::ConfigReload => {
+                    crate::audit::AuditEventType::ConfigReload
+                }
+                crate::config::AuditEventType::DatabaseScan => {
+                    crate::audit::AuditEventType::DatabaseScan
+                }
+                crate::config::AuditEventType::SchemaQuery => {
+                    crate::audit::AuditEventType::SchemaQuery
+                }
+                crate::config::AuditEventType::ApiAccess => crate::audit::AuditEventType::ApiAccess,
+                crate::config::AuditEventType::Detokenize => {
+                    crate::audit::AuditEventType::Detokenize
+                }
+                crate::config::AuditEventType::ScanHistory => {
+                    crate::audit::AuditEventType::ScanHistory
+                }
+                crate::config::AuditEventType::AuditExport => {
+                    crate::audit::AuditEventType::AuditExport
+                }
+                crate::config::AuditEventType::ApiKeyCreated => {
+                    crate::audit::AuditEventType::ApiKeyCreated
+                }
+                crate::config::AuditEventType::ApiKeyRevoked => {
+                    crate::audit::AuditEventType::ApiKeyRevoked
+                }
+                crate::config::AuditEventType::ConnectionKilled => {
+                    crate::audit::AuditEventType::ConnectionKilled
+                }
+                crate::config::AuditEventType::StatsReset => {
+                    crate::audit::AuditEventType::StatsReset
+                }
+            })
+            .collect(),
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<RwLock<AppConfig>>,
+    /// Bumped on every config change, so interceptors know when the settings
+    /// they took at the start of a result set are stale
+    pub config_generation: Arc<AtomicU64>,
     pub config_path: Arc<String>,
+    /// Held while the config is written out, so saves land in the order the
+    /// changes were made
+    save_lock: Arc<Mutex<()>>,
     pub active_connections: Arc<AtomicUsize>,
+    /// Connections the listeners have admitted under `limits.max_connections`
+    admitted_connections: Arc<AtomicUsize>,
     pub logs: Arc<RwLock<VecDeque<LogEntry>>>,
+    /// Every log entry as it is added, for `/logs/stream`. Sending never
+    /// waits, so slow subscribers lag instead of holding up connections.
+    pub log_events: broadcast::Sender<LogEntry>,
     pub upstream_healthy: Arc<AtomicBool>,
     pub health_status: Arc<RwLock<HealthStatus>>,
+    pub config_reload_status: Arc<RwLock<ConfigReloadStatus>>,
     pub metrics_handle: Option<Arc<PrometheusHandle>>,
-    /// Upstream database host for scanning
+    /// Upstream database host at startup; see `upstream()` for the current one
     pub upstream_host: Arc<String>,
-    /// Upstream database port for scanning
+    /// Upstream database port at startup
     pub upstream_port: u16,
+    /// Port of the listener whose upstream health checks and scans target
+    primary_listen_port: Option<u16>,
     /// Database protocol (Postgres or MySQL)
     pub db_protocol: DbProtocol,
     /// Audit logger for security events
@@ -161,6 +537,44 @@ pub struct AppState {
     pub stats: Arc<RwLock<AppStats>>,
     /// Connection history for charts (last 60 data points)
     pub connection_history: Arc<RwLock<VecDeque<ConnectionDataPoint>>>,
+    /// Per-minute counts of the last day, for `/stats?window=`
+    pub stats_window: Arc<RwLock<StatsWindow>>,
+    /// Postgres BackendKeyData of live connections, keyed by backend PID
+    pub backend_keys: Arc<RwLock<HashMap<u32, BackendKey>>>,
+    /// Live connections, keyed by connection ID
+    pub connections: Arc<RwLock<HashMap<usize, LiveConnection>>>,
+    /// Token vault for the `tokenize` strategy, when tokenization is configured
+    pub token_vault: Option<Arc<TokenVault>>,
+    /// Generated fakes shared by all connections, when a capacity is configured
+    pub masking_cache: Option<Arc<MaskingCache>>,
+    /// Database scans started through the API
+    pub scan_jobs: Arc<ScanJobs>,
+    /// Results of completed scans
+    pub scan_history: Arc<ScanHistory>,
+    /// `server` settings given on the command line, applied over the config
+    /// file's on every reload
+    pub server_overrides: Arc<ServerOverrides>,
+    /// Failed management API authentication attempts by client IP
+    pub auth_lockout: Arc<AuthLockout>,
+    /// PII scanner built from `scanner`, rebuilt when the config is reloaded
+    scanner: Arc<std::sync::RwLock<Arc<PiiScanner>>>,
+}
+
+/// A connection's slot under `limits.max_connections`, released on drop
+#[derive(Debug)]
+pub struct ConnectionPermit(Arc<AtomicUsize>);
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The key a Postgres backend hands out for cancelling its queries
+#[derive(Debug, Clone)]
+pub struct BackendKey {
+    pub secret_key: Vec<u8>,
+    pub connection_id: usize,
 }
 
 impl AppState {
@@ -171,69 +585,44 @@ impl AppState {
         upstream_port: u16,
         db_protocol: DbProtocol,
     ) -> Self {
-        // Create audit logger from config
-        let audit_logger = config
-            .audit
-            .as_ref()
-            .map(|cfg| {
-                AuditLogger::new(crate::audit::AuditConfig {
-                    enabled: cfg.enabled,
-                    log_to_stdout: cfg.log_to_stdout,
-                    log_file: cfg.log_file.clone(),
-                    rotation_enabled: cfg.rotation_enabled,
-                    max_file_size_bytes: cfg.max_file_size_bytes,
-                    max_rotated_files: cfg.max_rotated_files,
-                    events: cfg
-                        .events
-                        .iter()
-                        .map(|e| match e {
-                            crate::config::AuditEventType::AuthAttempt => {
-                                crate::audit::AuditEventType::AuthAttempt
-                            }
-                            crate::config::AuditEventType::ConfigChange => {
-                                crate::audit::AuditEventType::ConfigChange
-                            }
-                            crate::config::AuditEventType::RuleAdded => {
-                                crate::audit::AuditEventType::RuleAdded
-                            }
-                            crate::config::AuditEventType::RuleDeleted => {
-                                crate::audit::AuditEventType::RuleDeleted
-                            }
-                            crate::config::AuditEventType::RulesImported => {
-                                crate::audit::AuditEventType::RulesImported
-                            }
-                            crate::config::AuditEventType::ConfigReload => {
-                                crate::audit::AuditEventType::ConfigReload
-                            }
-                            crate::config::AuditEventType::DatabaseScan => {
-                                crate::audit::AuditEventType::DatabaseScan
-                            }
-                            crate::config::AuditEventType::SchemaQuery => {
-                                crate::audit::AuditEventType::SchemaQuery
-                            }
-                            crate::config::AuditEventType::ApiAccess => {
-                                crate::audit::AuditEventType::ApiAccess
-                            }
-                        })
-                        .collect(),
-                })
-            })
-            .unwrap_or_else(|| AuditLogger::new(crate::audit::AuditConfig::default()));
+        let audit_logger = AuditLogger::new(audit_logger_config(&config));
+
+        let masking_cache = config
+            .masking
+            .cache_capacity
+            .map(|capacity| Arc::new(MaskingCache::new(capacity)));
+        let scanner = PiiScanner::from_config(&config.scanner);
 
         Self {
             config: Arc::new(RwLock::new(config)),
+            config_generation: Arc::new(AtomicU64::new(0)),
             config_path: Arc::new(config_path),
+            save_lock: Arc::new(Mutex::new(())),
             active_connections: Arc::new(AtomicUsize::new(0)),
-            logs: Arc::new(RwLock::new(VecDeque::with_capacity(100))),
+            admitted_connections: Arc::new(AtomicUsize::new(0)),
+            logs: Arc::new(RwLock::new(VecDeque::with_capacity(LOG_BUFFER_LEN))),
+            log_events: broadcast::channel(LOG_STREAM_CAPACITY).0,
             upstream_healthy: Arc::new(AtomicBool::new(true)),
             health_status: Arc::new(RwLock::new(HealthStatus::default())),
+            config_reload_status: Arc::new(RwLock::new(ConfigReloadStatus::default())),
             metrics_handle: None,
             upstream_host: Arc::new(upstream_host),
             upstream_port,
+            primary_listen_port: None,
             db_protocol,
             audit_logger: Arc::new(audit_logger),
             stats: Arc::new(RwLock::new(AppStats::default())),
             connection_history: Arc::new(RwLock::new(VecDeque::with_capacity(60))),
+            stats_window: Arc::new(RwLock::new(StatsWindow::default())),
+            backend_keys: Arc::new(RwLock::new(HashMap::new())),
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            token_vault: None,
+            masking_cache,
+            scan_jobs: Arc::new(ScanJobs::default()),
+            auth_lockout: Arc::new(AuthLockout::default()),
+            scan_history: Arc::new(ScanHistory::default()),
+            server_overrides: Arc::new(ServerOverrides::default()),
+            scanner: Arc::new(std::sync::RwLock::new(Arc::new(scanner))),
         }
     }
 
@@ -254,22 +643,110 @@ impl AppState {
         self
     }
 
-    /// Save current config to the config file
-    pub async fn save_config(&self) -> Result<(), std::io::Error> {
-        let config = self.config.read().await;
-        let yaml = serde_yaml::to_string(&*config)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        std::fs::write(&*self.config_path, yaml)
+    pub fn with_token_vault(mut self, vault: TokenVault) -> Self {
+        self.token_vault = Some(Arc::new(vault));
+        self
+    }
+
+    pub fn with_scan_history(mut self, history: ScanHistory) -> Self {
+        self.scan_history = Arc::new(history);
+        self
+    }
+
+    pub fn with_server_overrides(mut self, overrides: ServerOverrides) -> Self {
+        self.server_overrides = Arc::new(overrides);
+        self
+    }
+
+    /// Health checks and scans follow the upstream of the listener on `port`
+    /// as the config has it, so a reload moving the upstream moves them too
+    pub fn with_primary_listener(mut self, port: u16) -> Self {
+        self.primary_listen_port = Some(port);
+        self
+    }
+
+    /// The upstream health checks and scans target
+    pub async fn upstream(&self) -> (String, u16) {
+        let current = match self.primary_listen_port {
+            Some(port) => self
+                .config
+                .read()
+                .await
+                .upstream_for(&self.server_overrides, port),
+            None => None,
+        };
+        current.unwrap_or_else(|| (self.upstream_host.to_string(), self.upstream_port))
+    }
+
+    /// Mark the config as changed; call while still holding the write lock
+    pub fn config_changed(&self) {
+        self.config_generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn config_generation(&self) -> u64 {
+        self.config_generation.load(Ordering::Acquire)
+    }
+
+    /// The PII scanner for the current `scanner` config
+    pub fn scanner(&self) -> Arc<PiiScanner> {
+        self.scanner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Save current config to the config file, see `AppConfig::save`
+    pub async fn save_config(&self) -> anyhow::Result<()> {
+        let _saving = self.save_lock.lock().await;
+        let config = self.config.read().await.clone();
+        let path = self.config_path.clone();
+        tokio::task::spawn_blocking(move || config.save(&path)).await?
     }
 
-    pub async fn add_log(&self, entry: LogEntry) {
+    /// Record a log entry, attributing it to its connection's session
+    pub async fn add_log(&self, mut entry: LogEntry) {
+        if let Some(LiveConnection { info, .. }) =
+            self.connections.read().await.get(&entry.connection_id)
+        {
+            entry.client_cn = entry.client_cn.or_else(|| info.client_cn.clone());
+            entry.user = entry.user.or_else(|| info.user.clone());
+            entry.database = entry.database.or_else(|| info.database.clone());
+            entry.application_name = entry
+                .application_name
+                .or_else(|| info.application_name.clone());
+        }
+        // No subscribers is not an error
+        let _ = self.log_events.send(entry.clone());
         let mut logs = self.logs.write().await;
-        if logs.len() >= 100 {
+        if logs.len() >= LOG_BUFFER_LEN {
             logs.pop_back();
         }
         logs.push_front(entry);
     }
 
+    /// Record a statement sent by a client, with the literals that look like
+    /// PII redacted unless `logs.redact_queries` is off
+    pub async fn log_query(&self, connection_id: usize, event_type: &str, query: &str) {
+        let content = if self.config.read().await.logs.redact_queries {
+            self.scanner().redact_sql(query).into_owned()
+        } else {
+            query.to_string()
+        };
+        self.add_log(LogEntry {
+            id: format!("{:x}", rand::random::<u128>()),
+            timestamp: Utc::now(),
+            connection_id,
+            event_type: event_type.to_string(),
+            content,
+            details: None,
+            client_cn: None,
+            user: None,
+            database: None,
+            application_name: None,
+        })
+        .await;
+    }
+
     /// Check if upstream is healthy (fast atomic check)
     #[allow(dead_code)]
     pub fn is_upstream_healthy(&self) -> bool {
@@ -289,6 +766,7 @@ impl AppState {
         status.latency_ms = latency_ms;
 
         if healthy {
+            status.last_success = status.last_check;
             status.consecutive_successes += 1;
             status.consecutive_failures = 0;
             status.last_error = None;
@@ -318,18 +796,63 @@ impl AppState {
     /// Reload configuration from disk
     /// Returns the number of rules in the new config, or an error
     pub async fn reload_config(&self) -> Result<usize, String> {
+        let result = self.load_config_file().await;
+        let mut status = self.config_reload_status.write().await;
+        match &result {
+            Ok(_) => {
+                status.last_reload = Some(Utc::now());
+                status.last_error = None;
+                status.last_error_at = None;
+            }
+            Err(e) => {
+                status.last_error = Some(e.clone());
+                status.last_error_at = Some(Utc::now());
+            }
+        }
+        result
+    }
+
+    /// Whether the config file differs from what saving the running config
+    /// would write, so the file watcher does not reload after our own saves
+    pub async fn config_file_changed(&self) -> bool {
+        let Ok(content) = tokio::fs::read_to_string(&*self.config_path).await else {
+            return true;
+        };
+        let config = self.config.read().await;
+        config.to_yaml().map_or(true, |saved| saved != content)
+    }
+
+    /// Load the config file and make it the running config
+    async fn load_config_file(&self) -> Result<usize, String> {
         let path = self.config_path.as_ref();
 
         // Load new config from file
         let new_config = AppConfig::load(path)
             .map_err(|e| format!("Failed to load config from {}: {}", path, e))?;
+        if let Some(schedule) = &new_config.scanner.schedule {
+            crate::scan_schedule::scheduled_scan(schedule)
+                .map_err(|e| format!("Failed to load config from {}: {:#}", path, e))?;
+        }
 
         let rules_count = new_config.rules.len();
 
         // Update the config
         {
             let mut config = self.config.write().await;
+            let restart_required = config
+                .server
+                .with_overrides(&self.server_overrides)
+                .restart_required(&new_config.server.with_overrides(&self.server_overrides));
+            if !restart_required.is_empty() {
+                tracing::warn!(
+                    "Changes to server.{} take effect on restart",
+                    restart_required.join(", server.")
+                );
+            }
+            let scanner = PiiScanner::from_config(&new_config.scanner);
+            *self.scanner.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(scanner);
             *config = new_config;
+            self.config_changed();
         }
 
         tracing::info!(
@@ -340,22 +863,160 @@ impl AppState {
         Ok(rules_count)
     }
 
-    /// Record a masking operation by strategy
-    pub async fn record_masking(&self, strategy: &str) {
+    /// Record values masked as `(table, column, strategy)`, taking each
+    /// stats lock once however many values there are
+    pub async fn record_maskings(&self, masked: &[(Option<&str>, &str, &str)]) {
+        if masked.is_empty() {
+            return;
+        }
+        let mut stats = self.stats.write().await;
+        for &(table, column, strategy) in masked {
+            stats.masking.increment(strategy);
+            if stats.by_column.increment(table, column, strategy) {
+                crate::metrics::record_column_masked(table.unwrap_or_default(), column, strategy);
+            } else {
+                crate::metrics::record_column_masked("other", "other", strategy);
+            }
+        }
+        drop(stats);
+        let mut window = self.stats_window.write().await;
+        let bucket = window.bucket(Utc::now());
+        for &(_, _, strategy) in masked {
+            bucket.masking.increment(strategy);
+        }
+    }
+
+    /// Forget the per-column masking counts. Returns how many were dropped.
+    pub async fn reset_column_stats(&self) -> usize {
         let mut stats = self.stats.write().await;
-        stats.masking.increment(strategy);
+        std::mem::take(&mut stats.by_column).counts.len()
     }
 
     /// Record a query by type (SELECT, INSERT, UPDATE, DELETE, etc.)
     pub async fn record_query(&self, query_type: &str) {
+        self.stats.write().await.queries.record_query(query_type);
+        self.stats_window
+            .write()
+            .await
+            .bucket(Utc::now())
+            .queries
+            .record_query(query_type);
+    }
+
+    /// Record rows inspected (and flagged as containing PII) during COPY FROM STDIN
+    pub async fn record_copy_in(&self, rows_inspected: u64, rows_flagged: u64) {
         let mut stats = self.stats.write().await;
-        stats.queries.record_query(query_type);
+        stats.copy_in.rows_inspected += rows_inspected;
+        stats.copy_in.rows_flagged += rows_flagged;
+        drop(stats);
+        let mut window = self.stats_window.write().await;
+        let bucket = window.bucket(Utc::now());
+        bucket.copy_in.rows_inspected += rows_inspected;
+        bucket.copy_in.rows_flagged += rows_flagged;
+    }
+
+    /// Remember which proxied connection a backend PID belongs to
+    pub async fn register_backend_key(&self, process_id: u32, key: BackendKey) {
+        self.backend_keys.write().await.insert(process_id, key);
+    }
+
+    /// Forget a backend PID once its connection has closed
+    pub async fn unregister_backend_key(&self, process_id: u32) {
+        self.backend_keys.write().await.remove(&process_id);
+    }
+
+    /// Find the proxied connection a CancelRequest targets, if the key matches
+    pub async fn find_cancel_target(&self, process_id: u32, secret_key: &[u8]) -> Option<usize> {
+        self.backend_keys
+            .read()
+            .await
+            .get(&process_id)
+            .filter(|key| key.secret_key == secret_key)
+            .map(|key| key.connection_id)
+    }
+
+    /// Record (or update) the session details of a connection, returning the
+    /// token that is cancelled when the connection is killed
+    pub async fn register_connection(&self, info: ConnectionInfo) -> CancellationToken {
+        let mut connections = self.connections.write().await;
+        let connection = connections
+            .entry(info.connection_id)
+            .and_modify(|connection| connection.info = info.clone())
+            .or_insert_with(|| LiveConnection {
+                info,
+                queries: 0,
+                masked_fields: 0,
+                kill_switch: CancellationToken::new(),
+            });
+        connection.kill_switch.clone()
+    }
+
+    /// Count a query sent on a connection
+    pub async fn record_connection_query(&self, connection_id: usize) {
+        if let Some(connection) = self.connections.write().await.get_mut(&connection_id) {
+            connection.queries += 1;
+        }
+    }
+
+    /// Count values masked on a connection
+    pub async fn record_connection_masked(&self, connection_id: usize, count: u64) {
+        if let Some(connection) = self.connections.write().await.get_mut(&connection_id) {
+            connection.masked_fields += count;
+        }
+    }
+
+    /// Signal a connection to close. Returns its details, or `None` if no
+    /// such connection is live. The handler removes it from the registry
+    /// once both sockets are closed.
+    pub async fn kill_connection(&self, connection_id: usize) -> Option<LiveConnection> {
+        let connections = self.connections.read().await;
+        let connection = connections.get(&connection_id)?;
+        connection.kill_switch.cancel();
+        Some(connection.clone())
+    }
+
+    /// Forget a connection once it has closed
+    pub async fn unregister_connection(&self, connection_id: usize) {
+        self.connections.write().await.remove(&connection_id);
+    }
+
+    /// Live connections, oldest first
+    pub async fn list_connections(&self) -> Vec<LiveConnection> {
+        let mut connections: Vec<_> = self.connections.read().await.values().cloned().collect();
+        connections.sort_by_key(|connection| connection.info.connected_at);
+        connections
+    }
+
+    /// Admit a new connection unless `max` are already open. The limit is
+    /// passed in on every call, so that lowering it refuses new connections
+    /// while those already open carry on.
+    pub fn try_admit_connection(&self, max: Option<usize>) -> Option<ConnectionPermit> {
+        let max = max.unwrap_or(usize::MAX);
+        self.admitted_connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(ConnectionPermit(self.admitted_connections.clone()))
     }
 
     /// Increment connection count
     pub async fn record_connection(&self) {
-        let mut stats = self.stats.write().await;
-        stats.total_connections += 1;
+        self.stats.write().await.total_connections += 1;
+        self.stats_window
+            .write()
+            .await
+            .bucket(Utc::now())
+            .connections += 1;
+    }
+
+    /// Zero every counter, the per-minute window and the connection history.
+    /// Returns the counters as they were.
+    pub async fn reset_stats(&self) -> AppStats {
+        let previous = std::mem::take(&mut *self.stats.write().await);
+        self.stats_window.write().await.clear();
+        self.connection_history.write().await.clear();
+        previous
     }
 
     /// Record a connection history data point (call periodically)
@@ -378,11 +1039,26 @@ impl AppState {
         history.push_front(point);
     }
 
+    /// Record a history snapshot every `period`, starting one period from now
+    pub async fn run_history_recorder(self, period: Duration) {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.record_history_snapshot().await;
+        }
+    }
+
     /// Get current statistics
     pub async fn get_stats(&self) -> AppStats {
         self.stats.read().await.clone()
     }
 
+    /// Counts of the last `minutes` minutes
+    pub async fn get_windowed_stats(&self, minutes: i64) -> crate::stats_window::StatsBucket {
+        self.stats_window.read().await.sum(minutes, Utc::now())
+    }
+
     /// Get connection history for charts
     pub async fn get_connection_history(&self) -> Vec<ConnectionDataPoint> {
         self.connection_history
@@ -428,6 +1104,9 @@ mod tests {
         stats.increment("ip");
         stats.increment("dob");
         stats.increment("passport");
+        stats.increment("iban");
+        stats.increment("nino");
+        stats.increment("tax_id");
         stats.increment("hash");
         stats.increment("json");
         stats.increment("other");
@@ -440,10 +1119,32 @@ mod tests {
         assert_eq!(stats.ip, 1);
         assert_eq!(stats.dob, 1);
         assert_eq!(stats.passport, 1);
+        assert_eq!(stats.iban, 1);
+        assert_eq!(stats.nino, 1);
+        assert_eq!(stats.tax_id, 1);
         assert_eq!(stats.hash, 1);
         assert_eq!(stats.json, 1);
         assert_eq!(stats.other, 1);
-        assert_eq!(stats.total(), 11);
+        assert_eq!(stats.total(), 14);
+    }
+
+    #[test]
+    fn test_masking_stats_name_and_address_grouping() {
+        let mut stats = MaskingStats::default();
+
+        for strategy in ["first_name", "last_name", "full_name"] {
+            stats.increment(strategy);
+        }
+        for strategy in ["address", "street_address", "zip"] {
+            stats.increment(strategy);
+        }
+        stats.increment("company");
+
+        assert_eq!(stats.name, 3);
+        assert_eq!(stats.address, 3);
+        assert_eq!(stats.company, 1);
+        assert_eq!(stats.other, 0);
+        assert_eq!(stats.total(), 7);
     }
 
     #[test]
@@ -465,29 +1166,75 @@ mod tests {
         assert_eq!(stats.other_count, 1);
     }
 
+    #[test]
+    fn test_query_type() {
+        assert_eq!(query_type("select 1"), "SELECT");
+        assert_eq!(
+            query_type("  /* app=web */ INSERT INTO t VALUES (1)"),
+            "INSERT"
+        );
+        assert_eq!(query_type("-- refresh\nupdate t set a = 1"), "UPDATE");
+        assert_eq!(query_type("(SELECT 1) UNION (SELECT 2)"), "SELECT");
+        assert_eq!(query_type("DELETE;"), "DELETE");
+        assert_eq!(query_type("/* unterminated"), "OTHER");
+        assert_eq!(query_type(""), "OTHER");
+    }
+
     #[tokio::test]
     async fn test_app_state_record_masking() {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
-        state.record_masking("email").await;
-        state.record_masking("email").await;
-        state.record_masking("phone").await;
+        state
+            .record_maskings(&[
+                (Some("public.users"), "email", "email"),
+                (Some("public.users"), "email", "email"),
+                (None, "contact", "phone"),
+            ])
+            .await;
 
         let stats = state.get_stats().await;
         assert_eq!(stats.masking.email, 2);
         assert_eq!(stats.masking.phone, 1);
         assert_eq!(stats.masking.total(), 3);
+        assert_eq!(
+            stats
+                .by_column
+                .count(Some("public.users"), "email", "email"),
+            2
+        );
+        assert_eq!(stats.by_column.count(None, "contact", "phone"), 1);
+
+        let by_column = serde_json::to_value(&stats.by_column).unwrap();
+        assert_eq!(by_column["columns"][0]["table"], "public.users");
+        assert_eq!(by_column["columns"][0]["count"], 2);
+        assert!(by_column["columns"][1]["table"].is_null());
+        assert_eq!(by_column["other"], 0);
+
+        assert_eq!(state.reset_column_stats().await, 2);
+        let stats = state.get_stats().await;
+        assert_eq!(stats.by_column.count(None, "contact", "phone"), 0);
+        // The per-strategy totals are kept
+        assert_eq!(stats.masking.total(), 3);
+    }
+
+    #[test]
+    fn test_column_stats_overflow_into_other() {
+        let mut stats = ColumnMaskingStats::default();
+        for i in 0..MAX_TRACKED_COLUMNS {
+            assert!(stats.increment(Some("t"), &format!("c{}", i), "redact"));
+        }
+        // Tracked columns keep counting, new ones go to `other`
+        assert!(stats.increment(Some("t"), "c0", "redact"));
+        assert!(!stats.increment(Some("t"), "new", "redact"));
+        assert!(!stats.increment(Some("t"), "c0", "hash"));
+        assert_eq!(stats.count(Some("t"), "c0", "redact"), 2);
+        assert_eq!(stats.count(Some("t"), "new", "redact"), 0);
+        assert_eq!(stats.other, 2);
     }
 
     #[tokio::test]
@@ -495,13 +1242,7 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
@@ -515,18 +1256,34 @@ mod tests {
         assert_eq!(stats.queries.insert_count, 1);
     }
 
+    #[tokio::test]
+    async fn test_app_state_reload_rebuilds_scanner() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"rules: []\n").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let state = AppState::new_for_test(AppConfig::load(&path).unwrap(), path.clone());
+        assert_eq!(state.scanner().scan("EMP-123456"), None);
+
+        std::fs::write(
+            &path,
+            "rules: []\nscanner:\n  custom_patterns:\n    - { name: employee_id, regex: \"EMP-\\\\d{6}\", strategy: hash }\n",
+        )
+        .unwrap();
+        let generation = state.config_generation();
+        state.reload_config().await.unwrap();
+        assert!(state.config_generation() > generation);
+        assert_eq!(
+            state.scanner().scan("EMP-123456"),
+            Some(crate::scanner::PiiType::Custom("employee_id".to_string()))
+        );
+    }
+
     #[tokio::test]
     async fn test_app_state_record_connection() {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
@@ -543,19 +1300,13 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
         // Record some stats
         state.record_query("SELECT").await;
-        state.record_masking("email").await;
+        state.record_maskings(&[(None, "email", "email")]).await;
 
         // Take a snapshot
         state.record_history_snapshot().await;
@@ -571,13 +1322,7 @@ mod tests {
         let config = AppConfig {
             masking_enabled: true,
             rules: vec![],
-            tls: None,
-            upstream_tls: false,
-            telemetry: None,
-            api: None,
-            limits: None,
-            health_check: None,
-            audit: None,
+            ..Default::default()
         };
         let state = AppState::new_for_test(config, "proxy.yaml".to_string());
 
@@ -589,4 +1334,151 @@ mod tests {
         let history = state.get_connection_history().await;
         assert_eq!(history.len(), 60, "History should be capped at 60 entries");
     }
+
+    #[tokio::test]
+    async fn test_find_cancel_target() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state
+            .register_backend_key(
+                4242,
+                BackendKey {
+                    secret_key: vec![1, 2, 3, 4],
+                    connection_id: 7,
+                },
+            )
+            .await;
+
+        assert_eq!(state.find_cancel_target(4242, &[1, 2, 3, 4]).await, Some(7));
+        assert_eq!(state.find_cancel_target(4242, &[9, 9, 9, 9]).await, None);
+
+        state.unregister_backend_key(4242).await;
+        assert_eq!(state.find_cancel_target(4242, &[1, 2, 3, 4]).await, None);
+    }
+
+    #[test]
+    fn test_connection_resolves_first_matching_policy() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+rules: []
+policies:
+  - name: migrations
+    match: { user: migrator }
+    masking_enabled: false
+  - name: office
+    match: { client_cidr: "192.168.0.0/16" }
+  - name: default
+"#,
+        )
+        .unwrap();
+
+        let mut info = ConnectionInfo::new(1, DbProtocol::Postgres);
+        info.client_addr = Some("192.168.1.20".parse().unwrap());
+        assert_eq!(info.resolve_policy(&config).as_deref(), Some("office"));
+
+        info.user = Some("migrator".to_string());
+        assert_eq!(info.resolve_policy(&config).as_deref(), Some("migrations"));
+
+        info.user = None;
+        info.client_addr = None;
+        assert_eq!(info.resolve_policy(&config).as_deref(), Some("default"));
+
+        assert_eq!(info.resolve_policy(&AppConfig::default()), None);
+    }
+
+    #[tokio::test]
+    async fn test_logs_are_attributed_to_connection() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let mut info = ConnectionInfo::new(11, DbProtocol::Postgres);
+        info.user = Some("alice".to_string());
+        info.database = Some("shop".to_string());
+        state.register_connection(info).await;
+
+        for connection_id in [11, 12] {
+            state
+                .add_log(LogEntry {
+                    id: connection_id.to_string(),
+                    timestamp: Utc::now(),
+                    connection_id,
+                    event_type: "Query".to_string(),
+                    content: "SELECT 1".to_string(),
+                    details: None,
+                    client_cn: None,
+                    user: None,
+                    database: None,
+                    application_name: None,
+                })
+                .await;
+        }
+
+        let logs = state.logs.read().await;
+        assert_eq!(logs[1].user.as_deref(), Some("alice"));
+        assert_eq!(logs[1].database.as_deref(), Some("shop"));
+        assert_eq!(logs[0].user, None);
+        drop(logs);
+
+        assert_eq!(state.list_connections().await.len(), 1);
+        state.unregister_connection(11).await;
+        assert!(state.list_connections().await.is_empty());
+    }
+
+    #[test]
+    fn test_connection_admission_follows_limit() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let first = state.try_admit_connection(Some(2)).unwrap();
+        let second = state.try_admit_connection(Some(2)).unwrap();
+        assert!(state.try_admit_connection(Some(2)).is_none());
+
+        // Raising the limit admits more at once; lowering it keeps those open
+        let third = state.try_admit_connection(Some(3)).unwrap();
+        assert!(state.try_admit_connection(Some(1)).is_none());
+        drop((first, second));
+        assert!(state.try_admit_connection(Some(1)).is_none());
+        drop(third);
+        assert!(state.try_admit_connection(Some(1)).is_some());
+        assert!(state.try_admit_connection(None).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_connection_registry() {
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        let kill_switch = state
+            .register_connection(ConnectionInfo::new(5, DbProtocol::MySql))
+            .await;
+        state.record_connection_query(5).await;
+        state.record_connection_masked(5, 1).await;
+        state.record_connection_masked(5, 1).await;
+        state.record_connection_query(6).await;
+
+        // Updating the session keeps the counters and the kill switch
+        let mut info = ConnectionInfo::new(5, DbProtocol::MySql);
+        info.user = Some("bob".to_string());
+        state.register_connection(info).await;
+        let connections = state.list_connections().await;
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].info.user.as_deref(), Some("bob"));
+        assert_eq!(connections[0].queries, 1);
+        assert_eq!(connections[0].masked_fields, 2);
+
+        assert!(state.kill_connection(6).await.is_none());
+        assert!(!kill_switch.is_cancelled());
+        assert!(state.kill_connection(5).await.is_some());
+        assert!(kill_switch.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_logged_queries_are_redacted() {
+        let query = "INSERT INTO users (email) VALUES ('real@person.com')";
+        let state = AppState::new_for_test(AppConfig::default(), "proxy.yaml".to_string());
+        state.log_query(1, "Query", query).await;
+        assert_eq!(
+            state.logs.read().await[0].content,
+            "INSERT INTO users (email) VALUES ('***')"
+        );
+
+        state.config.write().await.logs.redact_queries = false;
+        state.log_query(1, "MySqlQuery", query).await;
+        let logs = state.logs.read().await;
+        assert_eq!(logs[0].event_type, "MySqlQuery");
+        assert_eq!(logs[0].content, query);
+    }
 }
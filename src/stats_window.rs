@@ -0,0 +1,164 @@
+//! Per-minute statistics for the last day.
+//!
+//! The counters in `AppStats` grow from process start, which says little
+//! about what happened during a given batch run. Alongside them every event
+//! is also counted in the bucket for its minute, and `GET /stats?window=`
+//! sums the buckets of the last minutes or hours.
+
+use crate::state::{CopyInStats, MaskingStats, QueryStats};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// How far back buckets are kept
+pub const MAX_WINDOW_MINUTES: i64 = 24 * 60;
+
+/// Counts for one minute, or summed over a window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsBucket {
+    /// Start of the (first) minute counted
+    pub minute: DateTime<Utc>,
+    pub masking: MaskingStats,
+    pub queries: QueryStats,
+    pub connections: u64,
+    pub copy_in: CopyInStats,
+}
+
+impl StatsBucket {
+    fn add(&mut self, other: &StatsBucket) {
+        self.masking.add(&other.masking);
+        self.queries.add(&other.queries);
+        self.connections += other.connections;
+        self.copy_in.rows_inspected += other.copy_in.rows_inspected;
+        self.copy_in.rows_flagged += other.copy_in.rows_flagged;
+    }
+}
+
+/// Ring of per-minute buckets, newest first. Minutes without events have no
+/// bucket.
+#[derive(Debug, Default)]
+pub struct StatsWindow {
+    buckets: VecDeque<StatsBucket>,
+}
+
+fn start_of_minute(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(Duration::minutes(1)).unwrap_or(time)
+}
+
+impl StatsWindow {
+    /// The bucket to count an event at `now` in, starting a new one (and
+    /// dropping those older than a day) when the minute has changed
+    pub fn bucket(&mut self, now: DateTime<Utc>) -> &mut StatsBucket {
+        let minute = start_of_minute(now);
+        // Should the clock go back, events keep going to the newest bucket
+        if self
+            .buckets
+            .front()
+            .is_none_or(|bucket| bucket.minute < minute)
+        {
+            self.buckets.push_front(StatsBucket {
+                minute,
+                ..Default::default()
+            });
+            let oldest = minute - Duration::minutes(MAX_WINDOW_MINUTES);
+            while self
+                .buckets
+                .back()
+                .is_some_and(|bucket| bucket.minute <= oldest)
+            {
+                self.buckets.pop_back();
+            }
+        }
+        self.buckets.front_mut().expect("bucket was just ensured")
+    }
+
+    /// Sum of the last `minutes` minutes up to `now`, the current one included
+    pub fn sum(&self, minutes: i64, now: DateTime<Utc>) -> StatsBucket {
+        let since = start_of_minute(now) - Duration::minutes(minutes - 1);
+        let mut total = StatsBucket {
+            minute: since,
+            ..Default::default()
+        };
+        for bucket in self
+            .buckets
+            .iter()
+            .take_while(|bucket| bucket.minute >= since)
+        {
+            total.add(bucket);
+        }
+        total
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 1, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bucket_rolls_over_each_minute() {
+        let mut window = StatsWindow::default();
+        window.bucket(at(10, 0, 5)).queries.record_query("SELECT");
+        window.bucket(at(10, 0, 59)).queries.record_query("SELECT");
+        window.bucket(at(10, 1, 0)).queries.record_query("INSERT");
+        // Nothing happens for a few minutes
+        window.bucket(at(10, 5, 30)).masking.increment("email");
+
+        assert_eq!(window.buckets.len(), 3);
+        assert_eq!(window.buckets[0].minute, at(10, 5, 0));
+        assert_eq!(window.buckets[2].queries.total_queries, 2);
+
+        // A clock going back counts in the newest bucket
+        window.bucket(at(10, 4, 0)).connections += 1;
+        assert_eq!(window.buckets.len(), 3);
+        assert_eq!(window.buckets[0].connections, 1);
+    }
+
+    #[test]
+    fn test_sum_covers_the_last_minutes() {
+        let mut window = StatsWindow::default();
+        window.bucket(at(10, 0, 0)).queries.record_query("SELECT");
+        window.bucket(at(10, 1, 0)).queries.record_query("UPDATE");
+        window.bucket(at(10, 2, 0)).masking.increment("email");
+        window.bucket(at(10, 2, 10)).copy_in.rows_inspected += 3;
+
+        let now = at(10, 2, 30);
+        let last_minute = window.sum(1, now);
+        assert_eq!(last_minute.minute, at(10, 2, 0));
+        assert_eq!(last_minute.masking.email, 1);
+        assert_eq!(last_minute.copy_in.rows_inspected, 3);
+        assert_eq!(last_minute.queries.total_queries, 0);
+
+        let two_minutes = window.sum(2, now);
+        assert_eq!(two_minutes.queries.update_count, 1);
+        assert_eq!(two_minutes.queries.total_queries, 1);
+        assert_eq!(window.sum(60, now).queries.total_queries, 2);
+    }
+
+    #[test]
+    fn test_buckets_older_than_a_day_are_dropped() {
+        let mut window = StatsWindow::default();
+        window.bucket(at(0, 0, 0)).connections += 1;
+        window.bucket(at(12, 0, 0)).connections += 1;
+        window.bucket(at(23, 59, 0)).connections += 1;
+        assert_eq!(window.buckets.len(), 3);
+
+        // A day after the first bucket it falls out of the window
+        let next_day = at(0, 0, 0) + Duration::days(1);
+        window.bucket(next_day).connections += 1;
+        assert_eq!(window.buckets.len(), 3);
+        assert_eq!(window.sum(MAX_WINDOW_MINUTES, next_day).connections, 3);
+
+        window.clear();
+        assert_eq!(window.sum(MAX_WINDOW_MINUTES, next_day).connections, 0);
+    }
+}
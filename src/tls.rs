@@ -0,0 +1,706 @@
+//! TLS helpers.
+//!
+//! Loads PEM certificates and keys, builds the rustls `ServerConfig` clients
+//! connect to and the `ClientConfig` used for upstream connections, and adapts
+//! the latter to `tokio-postgres` so side connections (database scanner) use
+//! the same trust settings as the proxied traffic.
+
+use crate::config::{AppConfig, TlsConfig, UpstreamTlsMode};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use rustls_platform_verifier::Verifier;
+use simple_asn1::{ASN1Block, oid};
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::{WantsClientCert, WebPkiServerVerifier};
+use tokio_rustls::rustls::crypto::aws_lc_rs::default_provider;
+use tokio_rustls::rustls::crypto::{
+    CryptoProvider, verify_tls12_signature, verify_tls13_signature,
+};
+use tokio_rustls::rustls::pki_types::{
+    CertificateDer, InvalidDnsNameError, PrivateKeyDer, ServerName, UnixTime,
+};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, ConfigBuilder, DigitallySignedStruct, Error as TlsError,
+    RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certfile = File::open(path)?;
+    let mut reader = BufReader::new(certfile);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    Ok(certs)
+}
+
+pub fn load_keys(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let keyfile = File::open(path)?;
+    let mut reader = BufReader::new(keyfile);
+    let key = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow::anyhow!("No private key found"))?;
+    Ok(key)
+}
+
+fn load_roots(path: &str, what: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let certs = load_certs(path).with_context(|| format!("Failed to load {} {}", what, path))?;
+    for cert in certs {
+        roots.add(cert)?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificates found in {} {}", what, path);
+    }
+    Ok(roots)
+}
+
+/// Creates the TLS ServerConfig clients connect to.
+///
+/// With `client_ca_path` set, client certificates signed by that CA are
+/// verified; `require_client_auth` additionally rejects clients that present
+/// none, failing the handshake.
+pub fn server_config(tls_config: &TlsConfig) -> Result<ServerConfig> {
+    let provider = Arc::new(default_provider());
+    let certs = load_certs(&tls_config.cert_path)?;
+    let key = load_keys(&tls_config.key_path)?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match (&tls_config.client_ca_path, tls_config.require_client_auth) {
+        (Some(ca_path), require) => {
+            let roots = load_roots(ca_path, "client CA")?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if require {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        (None, true) => anyhow::bail!("tls.require_client_auth requires tls.client_ca_path"),
+        (None, false) => builder.with_no_client_auth(),
+    };
+
+    Ok(builder.with_single_cert(certs, key)?)
+}
+
+/// The acceptor for client connections, shared by the listeners. Replaced
+/// when the certificates are reloaded on SIGHUP; connections keep the one
+/// they were accepted with.
+pub type SharedTlsAcceptor = Arc<RwLock<Option<TlsAcceptor>>>;
+
+/// The acceptor for the `tls` section, or `None` when TLS is not enabled
+pub fn acceptor(tls_config: Option<&TlsConfig>) -> Result<Option<TlsAcceptor>> {
+    match tls_config {
+        Some(tls_config) if tls_config.enabled => Ok(Some(TlsAcceptor::from(Arc::new(
+            server_config(tls_config)?,
+        )))),
+        _ => Ok(None),
+    }
+}
+
+/// Common name (CN) from the subject of a DER-encoded certificate
+pub fn certificate_common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let blocks = simple_asn1::from_der(cert.as_ref()).ok()?;
+    let Some(ASN1Block::Sequence(_, certificate)) = blocks.first() else {
+        return None;
+    };
+    let Some(ASN1Block::Sequence(_, tbs)) = certificate.first() else {
+        return None;
+    };
+    // version [0] is optional; subject follows serial, signature, issuer and validity
+    let offset = usize::from(matches!(tbs.first(), Some(ASN1Block::Explicit(..))));
+    let Some(ASN1Block::Sequence(_, subject)) = tbs.get(offset + 4) else {
+        return None;
+    };
+
+    let common_name = oid!(2, 5, 4, 3);
+    subject.iter().rev().find_map(|rdn| {
+        let ASN1Block::Set(_, attributes) = rdn else {
+            return None;
+        };
+        attributes.iter().find_map(|attribute| {
+            let ASN1Block::Sequence(_, pair) = attribute else {
+                return None;
+            };
+            match pair.as_slice() {
+                [
+                    ASN1Block::ObjectIdentifier(_, id),
+                    ASN1Block::UTF8String(_, s)
+                    | ASN1Block::PrintableString(_, s)
+                    | ASN1Block::TeletexString(_, s)
+                    | ASN1Block::IA5String(_, s)
+                    | ASN1Block::UniversalString(_, s)
+                    | ASN1Block::BMPString(_, s),
+                ] if *id == common_name => Some(s.clone()),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// Creates the TLS ClientConfig for upstream connections.
+///
+/// `require` only encrypts, unless `upstream_tls_ca_path` is set: then, like
+/// libpq, it verifies the chain against it but not the hostname. The other
+/// modes verify the chain and hostname, against `upstream_tls_ca_path` when
+/// set and the OS native verifier otherwise.
+/// A client certificate is presented when `upstream_tls_client_cert_path` and
+/// `upstream_tls_client_key_path` are set.
+pub fn upstream_client_config(config: &AppConfig) -> Result<ClientConfig> {
+    let builder = client_config_builder(
+        config.upstream_tls_mode != UpstreamTlsMode::Require,
+        config.upstream_tls_ca_path.as_deref(),
+    )?;
+
+    let client_config = match (
+        &config.upstream_tls_client_cert_path,
+        &config.upstream_tls_client_key_path,
+    ) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = load_certs(cert_path).with_context(|| {
+                format!("Failed to load upstream TLS client cert {}", cert_path)
+            })?;
+            let key = load_keys(key_path)
+                .with_context(|| format!("Failed to load upstream TLS client key {}", key_path))?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => anyhow::bail!(
+            "upstream_tls_client_cert_path and upstream_tls_client_key_path must be set together"
+        ),
+    };
+
+    Ok(client_config)
+}
+
+/// A `ClientConfig` builder checking the server certificate when `verify` is
+/// set, against `ca_path` when given and the OS native verifier otherwise.
+/// Without `verify`, a `ca_path` still has the chain checked against it.
+fn client_config_builder(
+    verify: bool,
+    ca_path: Option<&str>,
+) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>> {
+    let provider = Arc::new(default_provider());
+
+    let verifier: Arc<dyn ServerCertVerifier> = match (verify, ca_path) {
+        (false, None) => Arc::new(NoCertificateVerification(provider.clone())),
+        (false, Some(ca_path)) => {
+            let roots = load_roots(ca_path, "upstream TLS CA")?;
+            Arc::new(ChainVerification(
+                WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()?,
+            ))
+        }
+        (true, Some(ca_path)) => {
+            let roots = load_roots(ca_path, "upstream TLS CA")?;
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()?
+        }
+        // Initialize the platform-specific verifier
+        (true, None) => Arc::new(Verifier::new(provider.clone())?),
+    };
+
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        // .dangerous() is required because we are overriding the default
+        // WebPki verifier with a custom one.
+        .dangerous()
+        .with_custom_certificate_verifier(verifier))
+}
+
+/// The name to present via SNI and verify the upstream certificate against
+pub fn upstream_server_name(
+    config: &AppConfig,
+    upstream_host: &str,
+) -> Result<ServerName<'static>> {
+    let name = config
+        .upstream_tls_server_name
+        .as_deref()
+        .unwrap_or(upstream_host);
+    ServerName::try_from(name.to_string())
+        .map_err(|_| anyhow::anyhow!("Invalid upstream TLS server name: {}", name))
+}
+
+/// Build a `tokio-postgres` TLS connector and SSL mode matching the upstream TLS settings
+pub fn postgres_connector(config: &AppConfig) -> Result<(MakeRustlsConnect, SslMode)> {
+    let ssl_mode = if !config.upstream_tls_enabled() {
+        SslMode::Disable
+    } else if config.upstream_tls_mode == UpstreamTlsMode::Prefer {
+        SslMode::Prefer
+    } else {
+        SslMode::Require
+    };
+    let connector = MakeRustlsConnect {
+        config: Arc::new(upstream_client_config(config)?),
+        server_name: config.upstream_tls_server_name.clone(),
+    };
+    Ok((connector, ssl_mode))
+}
+
+/// Build a `tokio-postgres` TLS connector for a database scan that sets its
+/// own `sslmode`. Like libpq, `verify` checks the certificate and hostname,
+/// and otherwise a `ca_path` has the chain checked against it. No client
+/// certificate is presented.
+pub fn scan_connector(
+    verify: bool,
+    ca_path: Option<&str>,
+    server_name: Option<String>,
+) -> Result<MakeRustlsConnect> {
+    let config = client_config_builder(verify, ca_path)?.with_no_client_auth();
+    Ok(MakeRustlsConnect {
+        config: Arc::new(config),
+        server_name,
+    })
+}
+
+/// Accepts any upstream certificate while still checking handshake signatures,
+/// matching libpq's `sslmode=require`.
+#[derive(Debug)]
+struct NoCertificateVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, TlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Checks the upstream certificate chain but not that it names the host,
+/// matching libpq's `sslmode=require` with a root certificate (`verify-ca`)
+#[derive(Debug)]
+struct ChainVerification(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for ChainVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, TlsError> {
+        // The name is checked once the chain is known to be good
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(TlsError::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// `tokio-postgres` TLS connector backed by rustls
+#[derive(Clone)]
+pub struct MakeRustlsConnect {
+    config: Arc<ClientConfig>,
+    /// Overrides the host `tokio-postgres` connects to for SNI and verification
+    server_name: Option<String>,
+}
+
+impl<S> MakeTlsConnect<S> for MakeRustlsConnect
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = RustlsStream<S>;
+    type TlsConnect = RustlsConnect;
+    type Error = InvalidDnsNameError;
+
+    fn make_tls_connect(&mut self, domain: &str) -> Result<RustlsConnect, InvalidDnsNameError> {
+        Ok(RustlsConnect {
+            connector: TlsConnector::from(self.config.clone()),
+            server_name: ServerName::try_from(
+                self.server_name.as_deref().unwrap_or(domain).to_string(),
+            )?,
+        })
+    }
+}
+
+pub struct RustlsConnect {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl<S> TlsConnect<S> for RustlsConnect
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = RustlsStream<S>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<RustlsStream<S>>> + Send>>;
+
+    fn connect(self, stream: S) -> Self::Future {
+        Box::pin(async move {
+            let stream = self.connector.connect(self.server_name, stream).await?;
+            Ok(RustlsStream(stream))
+        })
+    }
+}
+
+pub struct RustlsStream<S>(tokio_rustls::client::TlsStream<S>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for RustlsStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for RustlsStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream for RustlsStream<S> {
+    fn channel_binding(&self) -> ChannelBinding {
+        ChannelBinding::none()
+    }
+}
+
+/// Stream that first yields bytes already read from `inner`, so a TLS
+/// handshake can start on a socket whose ClientHello was buffered along with
+/// the preceding plaintext request
+pub struct Rewind<S> {
+    prefix: Bytes,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub fn new(prefix: Bytes, inner: S) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.prefix.is_empty() {
+            let n = self.prefix.len().min(buf.remaining());
+            let chunk = self.prefix.split_to(n);
+            buf.put_slice(&chunk);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// TLS server presenting a certificate for `db.internal.example.com` only
+    async fn spawn_tls_server() -> std::net::SocketAddr {
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                load_certs(&fixture("server.crt")).unwrap(),
+                load_keys(&fixture("server.key")).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let _ = acceptor.accept(socket).await;
+            }
+        });
+        addr
+    }
+
+    async fn handshake(config: &AppConfig, addr: std::net::SocketAddr) -> Result<()> {
+        let connector = TlsConnector::from(Arc::new(upstream_client_config(config)?));
+        let server_name = upstream_server_name(config, &addr.ip().to_string())?;
+        let socket = TcpStream::connect(addr).await?;
+        connector.connect(server_name, socket).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_name_override_verifies_against_dns_name() {
+        let addr = spawn_tls_server().await;
+        let mut config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::VerifyFull,
+            upstream_tls_ca_path: Some(fixture("ca.crt")),
+            ..Default::default()
+        };
+
+        // Connecting by IP fails: the certificate only names db.internal.example.com
+        assert!(handshake(&config, addr).await.is_err());
+
+        config.upstream_tls_server_name = Some("db.internal.example.com".to_string());
+        handshake(&config, addr).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_require_mode_verifies_chain_against_ca() {
+        let addr = spawn_tls_server().await;
+        let mut config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::Require,
+            upstream_tls_ca_path: Some(fixture("ca.crt")),
+            ..Default::default()
+        };
+
+        // Signed by the CA: accepted, though it doesn't name the IP
+        handshake(&config, addr).await.unwrap();
+
+        // Not signed by the configured CA
+        config.upstream_tls_ca_path = Some(fixture("client.crt"));
+        let err = handshake(&config, addr).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("UnknownIssuer"), "{:#}", err);
+
+        // Without a CA, require only encrypts
+        config.upstream_tls_ca_path = None;
+        handshake(&config, addr).await.unwrap();
+    }
+
+    fn mutual_tls_server_config(require_client_auth: bool) -> TlsConfig {
+        TlsConfig {
+            enabled: true,
+            cert_path: fixture("server.crt"),
+            key_path: fixture("server.key"),
+            client_ca_path: Some(fixture("ca.crt")),
+            require_client_auth,
+        }
+    }
+
+    /// Accept one connection and report the CN of the client certificate
+    async fn accept_one(
+        tls_config: TlsConfig,
+    ) -> (
+        std::net::SocketAddr,
+        tokio::task::JoinHandle<Result<Option<String>>>,
+    ) {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&tls_config).unwrap()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let stream = acceptor.accept(socket).await?;
+            Ok(stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(certificate_common_name))
+        });
+        (addr, handle)
+    }
+
+    fn client_config(with_cert: bool) -> AppConfig {
+        let mut config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::VerifyFull,
+            upstream_tls_ca_path: Some(fixture("ca.crt")),
+            upstream_tls_server_name: Some("db.internal.example.com".to_string()),
+            ..Default::default()
+        };
+        if with_cert {
+            config.upstream_tls_client_cert_path = Some(fixture("client.crt"));
+            config.upstream_tls_client_key_path = Some(fixture("client.key"));
+        }
+        config
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_cn_is_extracted() {
+        let (addr, server) = accept_one(mutual_tls_server_config(true)).await;
+        handshake(&client_config(true), addr).await.unwrap();
+        let cn = server.await.unwrap().unwrap();
+        assert_eq!(cn.as_deref(), Some("workload-a"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_client_certificate_is_rejected() {
+        let (addr, server) = accept_one(mutual_tls_server_config(true)).await;
+        // With TLS 1.3 the client may finish before the server rejects it
+        let _ = handshake(&client_config(false), addr).await;
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_optional_client_auth_allows_anonymous_clients() {
+        let (addr, server) = accept_one(mutual_tls_server_config(false)).await;
+        handshake(&client_config(false), addr).await.unwrap();
+        assert_eq!(server.await.unwrap().unwrap(), None);
+    }
+
+    #[test]
+    fn test_require_client_auth_without_ca_is_an_error() {
+        let mut tls_config = mutual_tls_server_config(true);
+        tls_config.client_ca_path = None;
+        assert!(server_config(&tls_config).is_err());
+    }
+
+    #[test]
+    fn test_certificate_common_name_of_server_cert() {
+        let certs = load_certs(&fixture("server.crt")).unwrap();
+        assert_eq!(
+            certificate_common_name(&certs[0]).as_deref(),
+            Some("db.internal.example.com")
+        );
+    }
+
+    #[test]
+    fn test_require_mode_builds_without_files() {
+        let config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::Require,
+            ..Default::default()
+        };
+        assert!(upstream_client_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_missing_ca_file_is_an_error() {
+        let config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::VerifyFull,
+            upstream_tls_ca_path: Some("/nonexistent/ca.pem".to_string()),
+            ..Default::default()
+        };
+        let err = upstream_client_config(&config).unwrap_err();
+        assert!(format!("{:#}", err).contains("/nonexistent/ca.pem"));
+    }
+
+    #[test]
+    fn test_client_cert_without_key_is_an_error() {
+        let config = AppConfig {
+            upstream_tls: true,
+            upstream_tls_mode: UpstreamTlsMode::Require,
+            upstream_tls_client_cert_path: Some("client.crt".to_string()),
+            ..Default::default()
+        };
+        assert!(upstream_client_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_postgres_connector_ssl_mode() {
+        let (_, ssl_mode) = postgres_connector(&AppConfig::default()).unwrap();
+        assert_eq!(ssl_mode, SslMode::Disable);
+
+        let config = AppConfig {
+            upstream_tls_mode: UpstreamTlsMode::Require,
+            ..Default::default()
+        };
+        let (_, ssl_mode) = postgres_connector(&config).unwrap();
+        assert_eq!(ssl_mode, SslMode::Require);
+    }
+}
@@ -0,0 +1,294 @@
+//! Token vault for the reversible `tokenize` masking strategy.
+//!
+//! Tokens are derived from the value and the vault key, so a value always maps
+//! to the same token. The token -> value mapping is kept in memory and
+//! appended to an encrypted file by a background task, so that tokenizing a
+//! value never waits on disk. Each line of the file is one AES-256-GCM sealed
+//! record, base64 encoded with its nonce in front.
+
+use crate::config::TokenizationConfig;
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+/// Prefix that marks a value as a vault token
+pub const TOKEN_PREFIX: &str = "tok_";
+
+/// Hex characters of the digest kept in a token
+const TOKEN_HEX_LEN: usize = 32;
+
+/// Token for a value under the given key
+pub fn token_for(key: &[u8], value: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(value);
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}{}", TOKEN_PREFIX, &hex[..TOKEN_HEX_LEN])
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    token: String,
+    value: String,
+}
+
+enum WriterMessage {
+    Record(Record),
+    /// Answered once everything sent before it is on disk
+    Flush(oneshot::Sender<()>),
+}
+
+pub struct TokenVault {
+    /// Key tokens are derived with, separate from the encryption key
+    token_key: [u8; 32],
+    tokens: RwLock<HashMap<String, String>>,
+    writer: mpsc::UnboundedSender<WriterMessage>,
+}
+
+impl TokenVault {
+    /// Open the vault file, loading the tokens already in it, and start the
+    /// background writer. The key comes from `key`, or else the `key_env`
+    /// environment variable.
+    pub async fn open(config: &TokenizationConfig) -> Result<Self> {
+        let secret = match &config.key {
+            Some(key) => key.clone(),
+            None => std::env::var(&config.key_env).with_context(|| {
+                format!(
+                    "Token vault key not configured: set tokenization.key or {}",
+                    config.key_env
+                )
+            })?,
+        };
+        let (cipher_key, token_key) = derive_keys(&secret);
+
+        let mut tokens = HashMap::new();
+        match tokio::fs::read_to_string(&config.vault_path).await {
+            Ok(contents) => {
+                let key = cipher(&cipher_key)?;
+                for (n, line) in contents.lines().enumerate() {
+                    let record = open_record(&key, line).with_context(|| {
+                        format!("Unreadable token vault record on line {}", n + 1)
+                    })?;
+                    tokens.insert(record.token, record.value);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("Failed to read token vault"),
+        }
+        info!(
+            "Token vault {} opened with {} tokens",
+            config.vault_path,
+            tokens.len()
+        );
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.vault_path)
+            .await
+            .context("Failed to open token vault for writing")?;
+        let (writer, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_records(file, cipher(&cipher_key)?, rx));
+
+        Ok(Self {
+            token_key,
+            tokens: RwLock::new(tokens),
+            writer,
+        })
+    }
+
+    /// Replace a value with its token, recording the mapping if it is new
+    pub fn tokenize(&self, value: &[u8]) -> String {
+        let token = token_for(&self.token_key, value);
+        if self
+            .tokens
+            .read()
+            .expect("token map poisoned")
+            .contains_key(&token)
+        {
+            return token;
+        }
+
+        let value = String::from_utf8_lossy(value).into_owned();
+        let inserted = self
+            .tokens
+            .write()
+            .expect("token map poisoned")
+            .insert(token.clone(), value.clone())
+            .is_none();
+        if inserted {
+            let record = Record {
+                token: token.clone(),
+                value,
+            };
+            if self.writer.send(WriterMessage::Record(record)).is_err() {
+                error!(
+                    "Token vault writer stopped, token {} is not persisted",
+                    token
+                );
+            }
+        }
+        token
+    }
+
+    /// The original value of a token
+    pub fn detokenize(&self, token: &str) -> Option<String> {
+        self.tokens
+            .read()
+            .expect("token map poisoned")
+            .get(token)
+            .cloned()
+    }
+
+    /// Wait until every mapping recorded so far is written to the file
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.writer.send(WriterMessage::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+/// Derive the encryption key and the token key from the configured secret
+fn derive_keys(secret: &str) -> ([u8; 32], [u8; 32]) {
+    let derive = |purpose: &[u8]| -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(purpose);
+        hasher.update(secret.as_bytes());
+        hasher.finalize().into()
+    };
+    (
+        derive(b"ironveil-vault-cipher"),
+        derive(b"ironveil-vault-token"),
+    )
+}
+
+fn cipher(key: &[u8; 32]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| anyhow!("Invalid vault key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn seal_record(key: &LessSafeKey, rng: &SystemRandom, record: &Record) -> Result<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to generate nonce"))?;
+    let mut sealed = serde_json::to_vec(record)?;
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut sealed,
+    )
+    .map_err(|_| anyhow!("Failed to encrypt token record"))?;
+
+    let mut line = nonce.to_vec();
+    line.extend_from_slice(&sealed);
+    Ok(BASE64.encode(line))
+}
+
+fn open_record(key: &LessSafeKey, line: &str) -> Result<Record> {
+    let mut sealed = BASE64.decode(line.trim())?;
+    if sealed.len() < NONCE_LEN {
+        anyhow::bail!("Record too short");
+    }
+    let mut body = sealed.split_off(NONCE_LEN);
+    let nonce =
+        Nonce::try_assume_unique_for_key(&sealed).map_err(|_| anyhow!("Invalid record nonce"))?;
+    let plain = key
+        .open_in_place(nonce, Aad::empty(), &mut body)
+        .map_err(|_| anyhow!("Record does not decrypt with this key"))?;
+    Ok(serde_json::from_slice(plain)?)
+}
+
+async fn write_records(
+    mut file: tokio::fs::File,
+    key: LessSafeKey,
+    mut rx: mpsc::UnboundedReceiver<WriterMessage>,
+) {
+    let rng = SystemRandom::new();
+    while let Some(message) = rx.recv().await {
+        match message {
+            WriterMessage::Record(record) => {
+                let result = match seal_record(&key, &rng, &record) {
+                    Ok(line) => file
+                        .write_all(format!("{}\n", line).as_bytes())
+                        .await
+                        .map_err(anyhow::Error::from),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    error!("Failed to persist token {}: {}", record.token, e);
+                }
+            }
+            WriterMessage::Flush(done) => {
+                if let Err(e) = file.flush().await {
+                    error!("Failed to flush token vault: {}", e);
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &tempfile::TempDir, key: &str) -> TokenizationConfig {
+        TokenizationConfig {
+            vault_path: dir.path().join("vault.log").to_string_lossy().into_owned(),
+            key: Some(key.to_string()),
+            key_env: "IRONVEIL_TEST_UNSET_VAULT_KEY".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vault_roundtrip_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = TokenVault::open(&config(&dir, "secret")).await.unwrap();
+
+        let token = vault.tokenize(b"alice@example.com");
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token, vault.tokenize(b"alice@example.com"));
+        assert_ne!(token, vault.tokenize(b"bob@example.com"));
+        assert_eq!(
+            vault.detokenize(&token).as_deref(),
+            Some("alice@example.com")
+        );
+        vault.flush().await;
+
+        // Two new tokens, one line each, none in the clear
+        let contents = std::fs::read_to_string(dir.path().join("vault.log")).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(!contents.contains("alice"));
+
+        let reopened = TokenVault::open(&config(&dir, "secret")).await.unwrap();
+        assert_eq!(
+            reopened.detokenize(&token).as_deref(),
+            Some("alice@example.com")
+        );
+        assert_eq!(reopened.tokenize(b"alice@example.com"), token);
+    }
+
+    #[tokio::test]
+    async fn test_vault_rejects_wrong_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = TokenVault::open(&config(&dir, "secret")).await.unwrap();
+        vault.tokenize(b"alice@example.com");
+        vault.flush().await;
+
+        assert!(TokenVault::open(&config(&dir, "other")).await.is_err());
+    }
+}
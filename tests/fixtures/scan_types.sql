@@ -0,0 +1,24 @@
+-- Columns of the types the scanner samples besides text, for
+-- scan_types_tests in integration_test.rs
+DROP TABLE IF EXISTS scan_types;
+DROP TYPE IF EXISTS scan_contact;
+
+CREATE TYPE scan_contact AS ENUM ('ops@example.com', 'sales@example.com');
+
+CREATE TABLE scan_types (
+    id serial PRIMARY KEY,
+    external_id uuid NOT NULL,
+    born date,
+    seen_at timestamp,
+    updated_at timestamptz,
+    reference numeric(20),
+    client inet,
+    contact scan_contact
+);
+
+INSERT INTO scan_types (external_id, born, seen_at, updated_at, reference, client, contact) VALUES
+    ('6f1c2e3a-4b5d-4e6f-8a7b-9c0d1e2f3a4b', '1990-01-15', '2024-03-01 12:00:00', '2024-03-01 12:00:00+00', 4111111111111111, '192.168.1.10', 'ops@example.com'),
+    ('0b9a8c7d-6e5f-4a3b-9c2d-1e0f9a8b7c6d', '1985-07-30', '2024-03-02 08:30:00', '2024-03-02 08:30:00+00', 5555555555554444, '10.0.0.7', 'sales@example.com'),
+    ('d4c3b2a1-0f9e-4d8c-b7a6-5f4e3d2c1b0a', '2001-12-02', '2024-03-03 17:45:00', '2024-03-03 17:45:00+00', 4012888888881881, '172.16.5.4', 'ops@example.com');
+
+COMMENT ON COLUMN scan_types.seen_at IS 'Sensitive: last seen in store';
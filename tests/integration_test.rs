@@ -5,6 +5,15 @@
 //! docker-compose up -d postgres
 //! cargo test --test integration_test
 //! ```
+//!
+//! The scanner's TLS test also needs the TLS-only Postgres and its proxy:
+//! ```bash
+//! ./scripts/generate_certs.sh
+//! docker-compose up -d proxy-tls
+//! ```
+//!
+//! The scanner's column type test creates `tests/fixtures/scan_types.sql` in
+//! the `postgres` service, reaching it directly on port 5432.
 
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -324,6 +333,44 @@ mod postgres_tests {
         }
     }
 
+    /// Test CancelRequest is forwarded and the connection closed without a reply
+    #[tokio::test]
+    async fn test_postgres_cancel_request() {
+        if !is_proxy_running().await {
+            eprintln!("Skipping test: Proxy not running on port {}", PROXY_PORT);
+            return;
+        }
+
+        let mut stream = match timeout(
+            CONNECTION_TIMEOUT,
+            TcpStream::connect(format!("{}:{}", PROXY_HOST, PROXY_PORT)),
+        )
+        .await
+        {
+            Ok(Ok(s)) => s,
+            _ => return,
+        };
+
+        // CancelRequest (16 bytes: length 16 + code 80877102 + PID + secret)
+        let cancel_request = [
+            0x00, 0x00, 0x00, 0x10, // Length: 16
+            0x04, 0xd2, 0x16, 0x2e, // Cancel request code: 80877102
+            0x00, 0x00, 0x10, 0x92, // Backend PID: 4242
+            0xde, 0xad, 0xbe, 0xef, // Secret key
+        ];
+
+        if let Err(e) = stream.write_all(&cancel_request).await {
+            eprintln!("Failed to send CancelRequest: {}", e);
+            return;
+        }
+
+        // The server never answers a cancel; the proxy should just close
+        let mut buf = [0u8; 1];
+        if let Ok(Ok(n)) = timeout(CONNECTION_TIMEOUT, stream.read(&mut buf)).await {
+            assert_eq!(n, 0, "CancelRequest should not get a response");
+        }
+    }
+
     /// Test connection rejection when upstream is unavailable
     #[tokio::test]
     async fn test_postgres_upstream_unavailable() {
@@ -424,6 +471,200 @@ mod mysql_tests {
     }
 }
 
+mod scan_tls_tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    /// API of the `proxy-tls` compose service, in front of a Postgres that
+    /// refuses cleartext connections
+    const TLS_API_PORT: u16 = 3002;
+
+    async fn is_tls_api_running() -> bool {
+        matches!(
+            timeout(
+                CONNECTION_TIMEOUT,
+                TcpStream::connect(format!("{}:{}", PROXY_HOST, TLS_API_PORT)),
+            )
+            .await,
+            Ok(Ok(_))
+        )
+    }
+
+    /// Run a scan to completion and return its final status
+    async fn run_scan(client: &reqwest::Client, tls: Value) -> Value {
+        let mut request = json!({
+            "username": "postgres",
+            "password": "password",
+            "database": "postgres"
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(tls.as_object().unwrap().clone());
+
+        let base = format!("http://{}:{}", PROXY_HOST, TLS_API_PORT);
+        let resp = client
+            .post(format!("{}/scan", base))
+            .header("X-API-Key", "test-api-key-12345")
+            .json(&request)
+            .timeout(CONNECTION_TIMEOUT)
+            .send()
+            .await
+            .expect("Failed to start scan");
+        assert_eq!(resp.status().as_u16(), 202);
+        let job: Value = resp.json().await.expect("Failed to parse JSON");
+        let job_id = job["job_id"].as_str().expect("job_id").to_string();
+
+        for _ in 0..60 {
+            let info: Value = client
+                .get(format!("{}/scan/{}", base, job_id))
+                .header("X-API-Key", "test-api-key-12345")
+                .timeout(CONNECTION_TIMEOUT)
+                .send()
+                .await
+                .expect("Failed to get scan status")
+                .json()
+                .await
+                .expect("Failed to parse JSON");
+            if info["status"] != "running" {
+                return info;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        panic!("scan {} did not finish", job_id);
+    }
+
+    /// Test scanning a TLS-only Postgres (`docker compose up -d proxy-tls`)
+    #[tokio::test]
+    async fn test_scan_tls_only_postgres() {
+        if !is_tls_api_running().await {
+            eprintln!("Skipping test: API not running on port {}", TLS_API_PORT);
+            return;
+        }
+        let client = reqwest::Client::new();
+
+        // The default prefers TLS, as libpq does
+        let info = run_scan(&client, json!({})).await;
+        assert_eq!(info["status"], "completed", "{}", info);
+        let info = run_scan(&client, json!({"sslmode": "require"})).await;
+        assert_eq!(info["status"], "completed", "{}", info);
+
+        // pg_hba.conf only has hostssl entries
+        let info = run_scan(&client, json!({"sslmode": "disable"})).await;
+        assert_eq!(info["status"], "failed", "{}", info);
+
+        // The compose CA isn't a system root
+        let info = run_scan(&client, json!({"sslmode": "verify-full"})).await;
+        assert_eq!(info["status"], "failed", "{}", info);
+        let error = info["error"].as_str().unwrap_or_default();
+        assert!(error.contains("invalid peer certificate"), "{}", error);
+    }
+}
+
+mod scan_types_tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    /// The `postgres` compose service, which the proxy on `API_PORT` fronts
+    const POSTGRES_PORT: u16 = 5432;
+
+    /// Test that uuid, date, timestamp, numeric, inet and enum columns are
+    /// sampled and scanned, and that column comments are read
+    /// (`docker-compose up -d proxy`)
+    #[tokio::test]
+    async fn test_scan_postgres_column_types() {
+        if !is_api_running().await {
+            eprintln!("Skipping test: API not running on port {}", API_PORT);
+            return;
+        }
+        let Ok((db, connection)) = tokio_postgres::connect(
+            &format!(
+                "host={} port={} user=postgres password=password dbname=postgres",
+                PROXY_HOST, POSTGRES_PORT
+            ),
+            tokio_postgres::NoTls,
+        )
+        .await
+        else {
+            eprintln!(
+                "Skipping test: Postgres not reachable on port {}",
+                POSTGRES_PORT
+            );
+            return;
+        };
+        tokio::spawn(connection);
+        db.batch_execute(include_str!("fixtures/scan_types.sql"))
+            .await
+            .expect("Failed to create fixture table");
+
+        let client = reqwest::Client::new();
+        let base = format!("http://{}:{}", PROXY_HOST, API_PORT);
+        let job: Value = client
+            .post(format!("{}/scan", base))
+            .json(&json!({
+                "username": "postgres",
+                "password": "password",
+                "database": "postgres",
+                "include_tables": ["scan_types"]
+            }))
+            .timeout(CONNECTION_TIMEOUT)
+            .send()
+            .await
+            .expect("Failed to start scan")
+            .json()
+            .await
+            .expect("Failed to parse JSON");
+        let job_id = job["job_id"].as_str().expect("job_id").to_string();
+
+        let mut info = Value::Null;
+        for _ in 0..60 {
+            info = client
+                .get(format!("{}/scan/{}", base, job_id))
+                .timeout(CONNECTION_TIMEOUT)
+                .send()
+                .await
+                .expect("Failed to get scan status")
+                .json()
+                .await
+                .expect("Failed to parse JSON");
+            if info["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        assert_eq!(info["status"], "completed", "{}", info);
+
+        let found: Vec<(String, String)> = info["findings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                (
+                    f["column"].as_str().unwrap().to_string(),
+                    f["pii_type"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        for expected in [
+            ("born", "DateOfBirth"),
+            ("reference", "CreditCard"),
+            ("client", "IpAddress"),
+            ("contact", "Email"),
+            // Only its comment marks it
+            ("seen_at", "Documented"),
+        ] {
+            assert!(
+                found
+                    .iter()
+                    .any(|(c, t)| (c.as_str(), t.as_str()) == expected),
+                "{:?} not in {:?}",
+                expected,
+                found
+            );
+        }
+    }
+}
+
 mod masking_tests {
     /// Test that email patterns are detected correctly
     #[test]
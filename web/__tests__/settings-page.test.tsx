@@ -95,7 +95,7 @@ describe("SettingsPage", () => {
     await user.click(button)
 
     const fetchMock = global.fetch as jest.Mock
-    expect(fetchMock).toHaveBeenCalledWith("http://localhost:3001/rules")
+    expect(fetchMock).toHaveBeenCalledWith("http://localhost:3001/config/full")
     expect(window.URL.createObjectURL).toHaveBeenCalled()
   })
 })
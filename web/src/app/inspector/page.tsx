@@ -20,6 +20,8 @@ interface LogDetail {
   masked: string
 }
 
+const MAX_LOGS = 500
+
 export default function InspectorPage() {
   const [logs, setLogs] = useState<LogEntry[]>([])
   const [selectedLog, setSelectedLog] = useState<string | null>(null)
@@ -36,8 +38,13 @@ export default function InspectorPage() {
     }
 
     fetchLogs()
-    const interval = setInterval(fetchLogs, 2000)
-    return () => clearInterval(interval)
+    // New entries are pushed as they happen, so bursts aren't missed
+    const stream = new EventSource("http://localhost:3001/logs/stream")
+    stream.addEventListener("log", (event) => {
+      const entry: LogEntry = JSON.parse((event as MessageEvent).data)
+      setLogs(prev => [entry, ...prev.filter(log => log.id !== entry.id)].slice(0, MAX_LOGS))
+    })
+    return () => stream.close()
   }, [])
 
   return (
@@ -65,7 +65,7 @@ export default function SettingsPage() {
 
   const handleExport = async () => {
     try {
-      const res = await fetch(`${API_BASE}/rules`)
+      const res = await fetch(`${API_BASE}/config/full`)
       const data = await res.json()
       
       const blob = new Blob([JSON.stringify(data, null, 2)], { type: "application/json" })